@@ -0,0 +1,88 @@
+//! Blob store throughput under concurrent readers and writers.
+//!
+//! Compares the store shared as is with the same store behind one outer
+//! `Mutex`, which is how volumes served it before reads and writes stopped
+//! serializing. Run with `cargo bench --bench blob_store`.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use minikv::common::WalSyncPolicy;
+use minikv::volume::blob::BlobStore;
+use std::sync::{Arc, Mutex};
+use tempfile::TempDir;
+
+const KEYS: usize = 1_000;
+const OPS_PER_THREAD: usize = 500;
+const VALUE: &[u8] = &[0x5a; 4096];
+
+fn open_store(dir: &TempDir) -> BlobStore {
+    let store = BlobStore::open(
+        &dir.path().join("data"),
+        &dir.path().join("wal"),
+        WalSyncPolicy::Never,
+    )
+    .unwrap();
+    for i in 0..KEYS {
+        store.put(&format!("key_{}", i), VALUE).unwrap();
+    }
+    store
+}
+
+/// One write every `write_every` operations, reads otherwise
+fn run(threads: usize, write_every: usize, op: impl Fn(bool, &str) + Send + Sync) {
+    std::thread::scope(|scope| {
+        for t in 0..threads {
+            let op = &op;
+            scope.spawn(move || {
+                for i in 0..OPS_PER_THREAD {
+                    let key = format!("key_{}", (t * OPS_PER_THREAD + i * 7) % KEYS);
+                    op(i % write_every == 0, &key);
+                }
+            });
+        }
+    });
+}
+
+fn bench_mixed(c: &mut Criterion) {
+    let mut group = c.benchmark_group("blob_store_90r_10w");
+    group.sample_size(10);
+    for threads in [1, 4, 8] {
+        group.throughput(Throughput::Elements((threads * OPS_PER_THREAD) as u64));
+
+        let dir = TempDir::new().unwrap();
+        let store = Arc::new(open_store(&dir));
+        group.bench_with_input(BenchmarkId::new("shared", threads), &threads, |b, &n| {
+            b.iter(|| {
+                run(n, 10, |write, key| {
+                    if write {
+                        store.put(key, VALUE).unwrap();
+                    } else {
+                        store.get(key).unwrap();
+                    }
+                })
+            })
+        });
+
+        let dir = TempDir::new().unwrap();
+        let locked = Arc::new(Mutex::new(open_store(&dir)));
+        group.bench_with_input(
+            BenchmarkId::new("global_mutex", threads),
+            &threads,
+            |b, &n| {
+                b.iter(|| {
+                    run(n, 10, |write, key| {
+                        let store = locked.lock().unwrap();
+                        if write {
+                            store.put(key, VALUE).unwrap();
+                        } else {
+                            store.get(key).unwrap();
+                        }
+                    })
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_mixed);
+criterion_main!(benches);
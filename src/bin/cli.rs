@@ -4,9 +4,17 @@
 //! Provides commands for verification, repair, and compaction of the distributed key-value store.
 
 use clap::{Parser, Subcommand};
+use minikv::common::{format_bytes, parse_duration, NodeRole};
+use minikv::coordinator::tenant;
+use minikv::coordinator::usage::UsageGrouping;
 use minikv::ops::{
-    auto_rebalance_cluster, compact_cluster, prepare_seamless_upgrade, repair_cluster,
-    stream_large_blob, verify_cluster,
+    auto_rebalance_cluster, cluster_status, compact_cluster, create_api_key, disk_usage,
+    drain_volume, export_keys, find_leader, finish_migration, gc_cluster, get_quota, hot_keys,
+    import_keys, list_api_keys, list_quotas, migrate_cluster, repair_cluster, replication_status,
+    revoke_api_key, rolling_upgrade, run_tiering, set_quota, set_upgrade_state, stream_large_blob,
+    tier_status, verify_cluster, BulkFormat, BulkOptions, BulkReport, ClusterStatus,
+    MigrateOptions, MigratePhase, QuotaInfo, QuotaLimits, StepPhase, UpgradeOptions,
+    UpgradeProgress, UpgradeState,
 };
 
 /// CLI arguments for cluster management.
@@ -19,6 +27,11 @@ struct Cli {
     #[arg(long, default_value = "http://localhost:5000")]
     coordinator: String,
 
+    /// Admin API key for the `/admin/*` endpoints (defaults to
+    /// `MINIKV_API_KEY`)
+    #[arg(long, global = true)]
+    api_key: Option<String>,
+
     /// Cluster operation to perform
     #[command(subcommand)]
     command: Commands,
@@ -41,14 +54,23 @@ enum Commands {
 
     /// Repair under-replicated keys
     /// Attempts to restore the desired replication factor for all keys.
+    /// An interrupted repair resumes from its checkpoint.
     Repair {
-        /// Target replication factor
-        #[arg(long, default_value = "3")]
-        replicas: usize,
+        /// Target replication factor (the coordinator's if omitted)
+        #[arg(long)]
+        replicas: Option<usize>,
+
+        /// Number of keys copied at once
+        #[arg(long, default_value = "8")]
+        parallelism: usize,
 
         /// Dry run (do not perform actual repair)
         #[arg(long)]
         dry_run: bool,
+
+        /// Return once the repair has started instead of waiting for it
+        #[arg(long)]
+        no_wait: bool,
     },
 
     /// Compact cluster
@@ -58,6 +80,18 @@ enum Commands {
         shard: Option<u64>,
     },
 
+    /// Delete blobs no key metadata points at
+    /// Orphans are deleted once they stayed orphaned for the grace period.
+    Gc {
+        /// Report orphans without deleting them
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Grace period in seconds (the coordinator's `gc_grace_secs` if omitted)
+        #[arg(long)]
+        grace_secs: Option<u64>,
+    },
+
     /// Put a blob
     Put {
         /// Key
@@ -84,11 +118,188 @@ enum Commands {
         key: String,
     },
 
+    /// Export keys and their values to a file
+    /// An interrupted export of the same file resumes from its checkpoint.
+    Export {
+        /// File to write
+        output: std::path::PathBuf,
+
+        /// Only keys under this prefix
+        #[arg(long, default_value = "")]
+        prefix: String,
+
+        /// File format, ndjson or tar (by the file extension if omitted)
+        #[arg(long)]
+        format: Option<BulkFormat>,
+
+        #[command(flatten)]
+        transfer: TransferArgs,
+    },
+
+    /// Import keys and their values from an export file
+    /// An interrupted import of the same file resumes from its checkpoint.
+    Import {
+        /// File to read
+        input: std::path::PathBuf,
+
+        /// File format, ndjson or tar (by the file extension if omitted)
+        #[arg(long)]
+        format: Option<BulkFormat>,
+
+        #[command(flatten)]
+        transfer: TransferArgs,
+    },
+
+    /// Decommission a volume
+    /// Moves all of its keys to other volumes, then removes it from the cluster.
+    Drain {
+        /// Volume to drain
+        volume_id: String,
+
+        /// Return once the drain has started instead of waiting for it
+        #[arg(long)]
+        no_wait: bool,
+    },
+
+    /// Show which coordinator currently leads the cluster
+    Leader {},
+
+    /// Show the Raft role and term, the volumes, the under-replicated keys
+    /// and the pending rebalance in one table
+    Status {
+        /// Print the status as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Show the bytes and keys stored per tenant or key prefix
+    Du {
+        /// Group by `tenant` or by key `prefix`
+        #[arg(long, default_value = "tenant")]
+        by: UsageGrouping,
+
+        /// Levels of the key grouped by, `/` separating them (with `--by prefix`)
+        #[arg(long, default_value = "1")]
+        depth: usize,
+
+        /// Only count this tenant's keys
+        #[arg(long)]
+        tenant: Option<String>,
+
+        /// Only count the keys starting with this prefix (requires `--tenant`)
+        #[arg(long, requires = "tenant")]
+        prefix: Option<String>,
+
+        /// Print the usage as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Show the most read, most written and largest keys served by the
+    /// coordinator over a window
+    Top {
+        /// How far back to look, e.g. `5m` or `1h`
+        #[arg(long, default_value = "5m")]
+        window: String,
+
+        /// Keys listed per ranking
+        #[arg(long, default_value = "10")]
+        limit: usize,
+
+        /// Print the rankings as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
     /// Trigger auto-rebalancing of cluster data
     Rebalance {},
 
-    /// Prepare cluster for seamless upgrade
-    Upgrade {},
+    /// Cold storage tiering
+    Tier {
+        #[command(subcommand)]
+        command: TierCommands,
+    },
+
+    /// Cross-cluster replication
+    Replication {
+        #[command(subcommand)]
+        command: ReplicationCommands,
+    },
+
+    /// Copy keys from one cluster to another, then verify them
+    /// With --cutover, the source also replicates its writes to the target
+    /// until the migration is finished with --finish.
+    Migrate {
+        /// Coordinator of the source cluster
+        #[arg(long)]
+        from: String,
+
+        /// Coordinator of the target cluster
+        #[arg(long)]
+        to: String,
+
+        /// Only keys of this tenant (repeatable)
+        #[arg(long = "tenant")]
+        tenants: Vec<String>,
+
+        /// Only stored keys (`<tenant>/<key>`) under this prefix (repeatable)
+        #[arg(long = "prefix")]
+        prefixes: Vec<String>,
+
+        /// Admin API key of the source cluster (defaults to --api-key)
+        #[arg(long)]
+        from_api_key: Option<String>,
+
+        /// Admin API key of the target cluster
+        #[arg(long)]
+        to_api_key: Option<String>,
+
+        /// Number of keys copied at once
+        #[arg(long, default_value = "8")]
+        parallelism: usize,
+
+        /// Keys copied per second (0 for no limit)
+        #[arg(long, default_value = "0")]
+        keys_per_sec: u64,
+
+        /// Value bytes copied per second (0 for no limit)
+        #[arg(long, default_value = "0")]
+        bytes_per_sec: u64,
+
+        /// Replicate the source's writes to the target during and after the copy
+        #[arg(long)]
+        cutover: bool,
+
+        /// Stop the replication of a cutover once clients use the target
+        #[arg(long, conflicts_with = "cutover")]
+        finish: bool,
+
+        /// Name of the cutover's replication target
+        #[arg(long, default_value = "migration")]
+        name: String,
+    },
+
+    /// API key administration
+    Keys {
+        #[command(subcommand)]
+        command: KeysCommands,
+    },
+
+    /// Tenant quota administration
+    Quota {
+        #[command(subcommand)]
+        command: QuotaCommands,
+    },
+
+    /// Rolling upgrade, restarting the nodes one at a time
+    Upgrade {
+        /// File the progress of the upgrade is saved to
+        #[arg(long, default_value = "minikv-upgrade.json")]
+        state: std::path::PathBuf,
+
+        #[command(subcommand)]
+        command: UpgradeCommands,
+    },
 
     /// Stream a large blob by key
     Stream {
@@ -98,11 +309,310 @@ enum Commands {
     },
 }
 
+/// How `export` and `import` move values
+#[derive(clap::Args)]
+struct TransferArgs {
+    /// Number of values in flight at once
+    #[arg(long, default_value = "8")]
+    parallelism: usize,
+
+    /// Keys moved per second (0 for no limit)
+    #[arg(long, default_value = "0")]
+    keys_per_sec: u64,
+
+    /// Value bytes moved per second (0 for no limit)
+    #[arg(long, default_value = "0")]
+    bytes_per_sec: u64,
+
+    /// Move values straight to and from the volumes
+    #[arg(long)]
+    direct: bool,
+}
+
+impl TransferArgs {
+    fn options(&self, api_key: Option<String>) -> BulkOptions {
+        BulkOptions {
+            api_key,
+            parallelism: self.parallelism,
+            keys_per_sec: self.keys_per_sec,
+            bytes_per_sec: self.bytes_per_sec,
+            direct: self.direct,
+        }
+    }
+}
+
+/// Summary of an export or import
+fn print_bulk_report(what: &str, report: &BulkReport) {
+    println!(
+        "{}{}: {} keys, {} bytes, {} failed",
+        what,
+        if report.resumed { " (resumed)" } else { "" },
+        report.keys,
+        report.bytes,
+        report.failed
+    );
+    for error in &report.errors {
+        println!("  Error: {}", error);
+    }
+}
+
+/// Table of `minikv status`
+fn print_status(status: &ClusterStatus) {
+    println!(
+        "Coordinator: {} (term {}, {} peers)",
+        status.role, status.leader.term, status.nb_peers
+    );
+    match &status.leader.leader_id {
+        Some(leader_id) => println!(
+            "Leader: {} at {}",
+            leader_id,
+            status.leader.leader_addr.as_deref().unwrap_or("unknown")
+        ),
+        None => println!("Leader: none elected"),
+    }
+
+    println!();
+    println!(
+        "{:<16} {:<10} {:<12} {:>10} {:>10} {:>10}",
+        "VOLUME", "STATE", "ZONE", "KEYS", "USED", "FREE"
+    );
+    for volume in &status.volumes {
+        println!(
+            "{:<16} {:<10} {:<12} {:>10} {:>10} {:>10}",
+            volume.volume_id,
+            volume.state.to_string(),
+            volume.zone.as_deref().unwrap_or("-"),
+            volume.total_keys,
+            format_bytes(volume.total_bytes),
+            format_bytes(volume.free_bytes)
+        );
+    }
+    let alive = status
+        .volumes
+        .iter()
+        .filter(|volume| volume.state.is_healthy())
+        .count();
+    println!("{} volumes, {} alive", status.volumes.len(), alive);
+
+    println!();
+    println!(
+        "Under-replicated: {} keys below {} replicas ({} to copy)",
+        status.under_replicated.keys,
+        status.under_replicated.replicas,
+        format_bytes(status.under_replicated.bytes)
+    );
+    println!(
+        "Rebalance: {}, {} of {} shards pending",
+        if status.rebalance.running {
+            "running"
+        } else {
+            "idle"
+        },
+        status.pending_shards(),
+        status.rebalance.shards.len()
+    );
+}
+
+/// Rolling upgrade operations
+#[derive(Subcommand)]
+enum UpgradeCommands {
+    /// Start an upgrade, or resume the one saved
+    Run {
+        /// Shell command restarting a node, `{role}`, `{id}` and `{address}`
+        /// passed as shell parameters, unquoted (e.g. "systemctl restart
+        /// minikv-{role}@{id}")
+        #[arg(long)]
+        restart_command: String,
+
+        /// HTTP URLs of the coordinators to restart, comma-separated
+        #[arg(long, value_delimiter = ',')]
+        coordinators: Vec<String>,
+
+        /// Only restart the coordinators
+        #[arg(long)]
+        skip_volumes: bool,
+
+        /// Restart the volumes without draining them first
+        #[arg(long)]
+        no_drain: bool,
+
+        /// How long the cluster has to settle, and a node to come back
+        #[arg(long, default_value = "10m")]
+        health_timeout: String,
+    },
+
+    /// Show the progress of the upgrade
+    Status {
+        /// Print the progress as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Pause the upgrade before its next phase
+    Pause {},
+
+    /// Resume a paused upgrade
+    Resume {},
+
+    /// Stop the upgrade before its next phase
+    Abort {},
+}
+
+/// Print the progress of a rolling upgrade, one line per node
+fn print_upgrade(progress: &UpgradeProgress) {
+    println!(
+        "Upgrade {:?}: {}/{} nodes done",
+        progress.state,
+        progress.done(),
+        progress.steps.len()
+    );
+    for step in &progress.steps {
+        let role = match step.role {
+            NodeRole::Coordinator => "coordinator",
+            NodeRole::Volume => "volume",
+        };
+        print!(
+            "  {} {} ({}): {:?}",
+            role, step.id, step.address, step.phase
+        );
+        if let Some(version) = &step.version {
+            print!(", version {}", version);
+        }
+        if let Some(error) = &step.error {
+            print!(", error: {}", error);
+        }
+        println!();
+    }
+}
+
+/// Cold storage tiering operations
+#[derive(Subcommand)]
+enum TierCommands {
+    /// Show the tiered keys and the last tiering pass
+    Status {},
+
+    /// Start a tiering pass now
+    Run {},
+}
+
+/// Cross-cluster replication operations
+#[derive(Subcommand)]
+enum ReplicationCommands {
+    /// Show how far each remote cluster lags behind
+    Status {},
+}
+
+/// API key administration
+#[derive(Subcommand)]
+enum KeysCommands {
+    /// Create a key; its secret is printed once
+    Create {
+        /// Name of the key, for humans
+        #[arg(long)]
+        name: String,
+
+        /// Tenant the key belongs to
+        #[arg(long, default_value = "default")]
+        tenant: String,
+
+        /// Role: admin, read_write or read_only
+        #[arg(long, default_value = "read_only")]
+        role: String,
+
+        /// Expire the key after this many seconds
+        #[arg(long)]
+        expires_in_secs: Option<u64>,
+    },
+
+    /// List keys, optionally for a single tenant
+    List {
+        #[arg(long)]
+        tenant: Option<String>,
+    },
+
+    /// Revoke a key by ID
+    Revoke {
+        /// Key ID, as shown by `minikv keys list`
+        key_id: String,
+    },
+}
+
+/// Tenant quota administration
+#[derive(Subcommand)]
+enum QuotaCommands {
+    /// Set a tenant's quota (limits of 0 are unlimited)
+    Set {
+        tenant: String,
+
+        /// Bytes the tenant may store
+        #[arg(long, default_value_t = 0)]
+        storage_limit: u64,
+
+        /// Keys the tenant may store
+        #[arg(long, default_value_t = 0)]
+        object_limit: u64,
+
+        /// Writes per second
+        #[arg(long, default_value_t = 0)]
+        rate_limit: u32,
+
+        /// Bytes per second the tenant may upload
+        #[arg(long, default_value_t = 0)]
+        ingress_limit: u64,
+
+        /// Bytes per second the tenant may download
+        #[arg(long, default_value_t = 0)]
+        egress_limit: u64,
+
+        /// Refuse every write of the tenant
+        #[arg(long)]
+        disable: bool,
+    },
+
+    /// Show the quota applying to a tenant and its usage
+    Get { tenant: String },
+
+    /// List the tenants with a quota of their own
+    List {},
+}
+
+/// One line describing a tenant's quota and usage
+fn format_quota(quota: &QuotaInfo) -> String {
+    let limit = |value: u64| {
+        if value == 0 {
+            "unlimited".to_string()
+        } else {
+            value.to_string()
+        }
+    };
+    format!(
+        "{}  storage={}/{} objects={}/{} rate={}/s ingress={}B/s egress={}B/s{}{}",
+        quota.tenant,
+        quota.storage_used,
+        limit(quota.storage_limit),
+        quota.object_count,
+        limit(quota.object_limit),
+        limit(quota.rate_limit as u64),
+        limit(quota.ingress_limit),
+        limit(quota.egress_limit),
+        if quota.enabled { "" } else { " disabled" },
+        if quota.default {
+            " (default quota)"
+        } else {
+            ""
+        }
+    )
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     tracing_subscriber::fmt::init();
 
     let cli = Cli::parse();
+    let api_key = cli
+        .api_key
+        .clone()
+        .or_else(|| std::env::var("MINIKV_API_KEY").ok());
 
     match cli.command {
         Commands::Verify { deep, concurrency } => {
@@ -113,14 +623,57 @@ async fn main() -> anyhow::Result<()> {
             println!("  Under-replicated: {}", report.under_replicated);
             println!("  Corrupted: {}", report.corrupted);
             println!("  Orphaned: {}", report.orphaned);
+            for problem in &report.problems {
+                match &problem.volume_id {
+                    Some(volume_id) => {
+                        println!("    {} on {}: {}", problem.key, volume_id, problem.problem)
+                    }
+                    None => println!("    {}: {}", problem.key, problem.problem),
+                }
+            }
+            for error in &report.errors {
+                println!("  Error: {}", error);
+            }
         }
 
-        Commands::Repair { replicas, dry_run } => {
-            let report = repair_cluster(&cli.coordinator, replicas, dry_run).await?;
-            println!("Repair report:");
-            println!("  Keys checked: {}", report.keys_checked);
-            println!("  Keys repaired: {}", report.keys_repaired);
-            println!("  Bytes copied: {}", report.bytes_copied);
+        Commands::Repair {
+            replicas,
+            parallelism,
+            dry_run,
+            no_wait,
+        } => {
+            let progress = repair_cluster(
+                &cli.coordinator,
+                replicas,
+                parallelism,
+                dry_run,
+                !no_wait,
+                |p| {
+                    println!(
+                        "  {}/{} keys repaired, {}/{} bytes copied, {} failed, ETA {}",
+                        p.keys_repaired,
+                        p.total_keys,
+                        p.bytes_copied,
+                        p.total_bytes,
+                        p.keys_failed,
+                        p.eta_secs
+                            .map(|s| format!("{}s", s))
+                            .unwrap_or_else(|| "unknown".to_string())
+                    );
+                },
+            )
+            .await?;
+            println!(
+                "Repair{}: {:?}",
+                if progress.dry_run { " (dry run)" } else { "" },
+                progress.state
+            );
+            println!("  Keys checked: {}", progress.keys_checked);
+            println!("  Keys repaired: {}", progress.keys_repaired);
+            println!("  Bytes copied: {}", progress.bytes_copied);
+            if let Some(error) = progress.error {
+                println!("  Last error: {}", error);
+            }
         }
 
         Commands::Compact { shard } => {
@@ -130,6 +683,35 @@ async fn main() -> anyhow::Result<()> {
             println!("  Bytes freed: {}", report.bytes_freed);
         }
 
+        Commands::Gc {
+            dry_run,
+            grace_secs,
+        } => {
+            let report = gc_cluster(&cli.coordinator, dry_run, grace_secs).await?;
+            println!(
+                "GC report{}:",
+                if report.dry_run { " (dry run)" } else { "" }
+            );
+            println!("  Volumes scanned: {}", report.volumes_scanned);
+            println!("  Blobs scanned: {}", report.blobs_scanned);
+            println!("  Orphans found: {}", report.orphans_found);
+            println!(
+                "  Within grace period ({}s): {}",
+                report.grace_secs, report.orphans_pending
+            );
+            println!("  Orphans deleted: {}", report.orphans_deleted);
+            println!("  Bytes freed: {}", report.bytes_freed);
+            for orphan in &report.orphans {
+                println!(
+                    "    {} on {} ({} bytes)",
+                    orphan.key, orphan.volume_id, orphan.size
+                );
+            }
+            for error in &report.errors {
+                println!("  Error: {}", error);
+            }
+        }
+
         Commands::Put { key, file } => {
             // Read value from file
             let value = std::fs::read(&file)?;
@@ -139,16 +721,418 @@ async fn main() -> anyhow::Result<()> {
             println!("PUT {}: {}", key, resp.status());
         }
 
+        Commands::Drain { volume_id, no_wait } => {
+            let progress = drain_volume(&cli.coordinator, &volume_id, !no_wait, |p| {
+                println!(
+                    "  {}/{} keys, {}/{} bytes moved, {} failed, ETA {}",
+                    p.keys_moved,
+                    p.total_keys,
+                    p.bytes_moved,
+                    p.total_bytes,
+                    p.keys_failed,
+                    p.eta_secs
+                        .map(|s| format!("{}s", s))
+                        .unwrap_or_else(|| "unknown".to_string())
+                );
+            })
+            .await?;
+            println!("Drain of {}: {:?}", volume_id, progress.state);
+            if let Some(error) = progress.error {
+                println!("  Last error: {}", error);
+            }
+        }
+
+        Commands::Leader {} => {
+            let info = find_leader(&cli.coordinator).await?;
+            match info.leader_id {
+                Some(leader_id) => {
+                    println!("Leader: {} (term {})", leader_id, info.term);
+                    println!(
+                        "  Address: {}",
+                        info.leader_addr.as_deref().unwrap_or("unknown")
+                    );
+                }
+                None => println!("No leader elected (term {})", info.term),
+            }
+        }
+
+        Commands::Status { json } => {
+            let status = cluster_status(&cli.coordinator, api_key.as_deref()).await?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&status)?);
+            } else {
+                print_status(&status);
+            }
+        }
+
+        Commands::Du {
+            by,
+            depth,
+            tenant,
+            prefix,
+            json,
+        } => {
+            let within = match tenant {
+                Some(tenant) => tenant::prefix(&tenant) + &prefix.unwrap_or_default(),
+                None => String::new(),
+            };
+            let report =
+                disk_usage(&cli.coordinator, api_key.as_deref(), by, depth, &within).await?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            } else {
+                println!("{:>10} {:>10}  PREFIX", "SIZE", "KEYS");
+                for usage in &report.usage {
+                    println!(
+                        "{:>10} {:>10}  {}",
+                        format_bytes(usage.bytes),
+                        usage.keys,
+                        usage.prefix
+                    );
+                }
+                println!(
+                    "{:>10} {:>10}  total",
+                    format_bytes(report.total_bytes),
+                    report.total_keys
+                );
+            }
+        }
+
+        Commands::Top {
+            window,
+            limit,
+            json,
+        } => {
+            let window = parse_duration(&window)?;
+            let report = hot_keys(
+                &cli.coordinator,
+                api_key.as_deref(),
+                window.as_secs(),
+                limit,
+            )
+            .await?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            } else {
+                println!(
+                    "{} keys accessed in the last {}s (sample rate {})",
+                    report.tracked_keys, report.window_secs, report.sample_rate
+                );
+                for (title, keys) in [
+                    ("Most read", &report.most_read),
+                    ("Most written", &report.most_written),
+                    ("Largest", &report.largest),
+                ] {
+                    println!();
+                    println!("{}:", title);
+                    println!("{:>10} {:>10} {:>10}  KEY", "READS", "WRITES", "SIZE");
+                    for key in keys {
+                        println!(
+                            "{:>10} {:>10} {:>10}  {}",
+                            key.reads,
+                            key.writes,
+                            format_bytes(key.size),
+                            key.key
+                        );
+                    }
+                }
+            }
+        }
+
         Commands::Rebalance {} => {
             auto_rebalance_cluster(&cli.coordinator).await?;
             println!("Auto-rebalancing triggered.");
+            println!("Progress: {}/admin/rebalance/status", cli.coordinator);
+        }
+
+        Commands::Tier {
+            command: TierCommands::Status {},
+        } => {
+            let status = tier_status(&cli.coordinator).await?;
+            if !status.enabled {
+                println!("Cold storage tiering is not configured");
+            } else {
+                println!(
+                    "Cold storage: bucket {}",
+                    status.bucket.as_deref().unwrap_or("unknown")
+                );
+            }
+            println!(
+                "  Tiered keys: {} ({} bytes)",
+                status.tiered_keys, status.tiered_bytes
+            );
+            println!("  Pass running: {}", status.running);
+            if let Some(run) = status.last_run {
+                println!(
+                    "  Last pass: {} keys scanned, {} tiered ({} bytes), {} failed, finished at {}",
+                    run.keys_scanned,
+                    run.keys_tiered,
+                    run.bytes_tiered,
+                    run.failed,
+                    run.finished_at
+                );
+            }
         }
 
-        Commands::Upgrade {} => {
-            prepare_seamless_upgrade(&cli.coordinator).await?;
-            println!("Seamless upgrade prepared.");
+        Commands::Tier {
+            command: TierCommands::Run {},
+        } => {
+            run_tiering(&cli.coordinator).await?;
+            println!("Tiering pass started.");
+            println!("Progress: minikv tier status");
         }
 
+        Commands::Replication {
+            command: ReplicationCommands::Status {},
+        } => {
+            let status = replication_status(&cli.coordinator, api_key.as_deref()).await?;
+            if status.targets.is_empty() {
+                println!("No replication targets configured");
+            }
+            for target in status.targets {
+                println!("Target {} ({})", target.name, target.url);
+                println!(
+                    "  Cursor: {} ({} log entries pending, lag {}s)",
+                    target.cursor, target.pending_entries, target.lag_secs
+                );
+                println!(
+                    "  Replicated: {}, conflicts: {}, rejected: {}",
+                    target.stats.replicated, target.stats.conflicts, target.stats.rejected
+                );
+                if let Some(at) = target.stats.last_push_at {
+                    println!("  Last push at: {}", at);
+                }
+                if let Some(error) = target.stats.last_error {
+                    println!("  Last error: {}", error);
+                }
+            }
+        }
+
+        Commands::Migrate {
+            from,
+            to,
+            tenants,
+            prefixes,
+            from_api_key,
+            to_api_key,
+            parallelism,
+            keys_per_sec,
+            bytes_per_sec,
+            cutover,
+            finish,
+            name,
+        } => {
+            let from_api_key = from_api_key.or(api_key);
+            if finish {
+                finish_migration(&from, from_api_key.as_deref(), &name).await?;
+                println!(
+                    "Migration {} finished: {} no longer replicates to {}",
+                    name, from, to
+                );
+                return Ok(());
+            }
+            let options = MigrateOptions {
+                from_api_key,
+                to_api_key,
+                tenants,
+                prefixes,
+                parallelism,
+                keys_per_sec,
+                bytes_per_sec,
+                cutover,
+                name,
+            };
+            let report = migrate_cluster(&from, &to, &options, |phase, report| match phase {
+                MigratePhase::Copying => println!(
+                    "Copying: {} keys scanned, {} copied ({} bytes), {} failed",
+                    report.keys_scanned, report.keys_copied, report.bytes_copied, report.failed
+                ),
+                MigratePhase::CatchingUp => {
+                    println!("Waiting for {} to replicate the writes made meanwhile", to)
+                }
+                MigratePhase::Verifying => println!(
+                    "Verifying: {} keys verified, {} newer on the target, {} mismatched",
+                    report.keys_verified, report.newer_on_target, report.mismatched
+                ),
+            })
+            .await?;
+            println!(
+                "Migrated {} to {}: {} keys scanned, {} copied ({} bytes), {} failed",
+                from,
+                to,
+                report.keys_scanned,
+                report.keys_copied,
+                report.bytes_copied,
+                report.failed
+            );
+            println!(
+                "Verified: {} keys, {} newer on the target, {} mismatched",
+                report.keys_verified, report.newer_on_target, report.mismatched
+            );
+            for error in &report.errors {
+                println!("  Error: {}", error);
+            }
+            if let Some(target) = report.cutover_target {
+                println!(
+                    "{} keeps replicating its writes to {} as {}",
+                    from, to, target
+                );
+                println!(
+                    "Switch clients to {}, then: minikv migrate --from {} --to {} --finish --name {}",
+                    to, from, to, target
+                );
+            }
+        }
+
+        Commands::Keys {
+            command:
+                KeysCommands::Create {
+                    name,
+                    tenant,
+                    role,
+                    expires_in_secs,
+                },
+        } => {
+            let created = create_api_key(
+                &cli.coordinator,
+                api_key.as_deref(),
+                &name,
+                &tenant,
+                &role,
+                expires_in_secs,
+            )
+            .await?;
+            println!(
+                "Created key {} ({}, tenant {})",
+                created.id, created.role, created.tenant
+            );
+            println!("  {}", created.key);
+            println!("Store this key securely - it cannot be retrieved again!");
+        }
+
+        Commands::Keys {
+            command: KeysCommands::List { tenant },
+        } => {
+            let keys =
+                list_api_keys(&cli.coordinator, api_key.as_deref(), tenant.as_deref()).await?;
+            println!("{} keys", keys.len());
+            for key in keys {
+                println!(
+                    "  {}  {}  tenant={} role={} {}{}",
+                    key.id,
+                    key.name,
+                    key.tenant,
+                    key.role,
+                    if key.active { "active" } else { "revoked" },
+                    key.expires_at
+                        .map(|at| format!(" expires_at={}", at))
+                        .unwrap_or_default()
+                );
+            }
+        }
+
+        Commands::Keys {
+            command: KeysCommands::Revoke { key_id },
+        } => {
+            revoke_api_key(&cli.coordinator, api_key.as_deref(), &key_id).await?;
+            println!("Revoked key {}", key_id);
+        }
+
+        Commands::Quota {
+            command:
+                QuotaCommands::Set {
+                    tenant,
+                    storage_limit,
+                    object_limit,
+                    rate_limit,
+                    ingress_limit,
+                    egress_limit,
+                    disable,
+                },
+        } => {
+            let limits = QuotaLimits {
+                storage_limit,
+                object_limit,
+                rate_limit,
+                ingress_limit,
+                egress_limit,
+                enabled: !disable,
+            };
+            let quota = set_quota(&cli.coordinator, api_key.as_deref(), &tenant, &limits).await?;
+            println!("{}", format_quota(&quota));
+        }
+
+        Commands::Quota {
+            command: QuotaCommands::Get { tenant },
+        } => {
+            let quota = get_quota(&cli.coordinator, api_key.as_deref(), &tenant).await?;
+            println!("{}", format_quota(&quota));
+        }
+
+        Commands::Quota {
+            command: QuotaCommands::List {},
+        } => {
+            let quotas = list_quotas(&cli.coordinator, api_key.as_deref()).await?;
+            println!("{} tenant quotas", quotas.len());
+            for quota in quotas {
+                println!("  {}", format_quota(&quota));
+            }
+        }
+
+        Commands::Upgrade { state, command } => match command {
+            UpgradeCommands::Run {
+                restart_command,
+                coordinators,
+                skip_volumes,
+                no_drain,
+                health_timeout,
+            } => {
+                let options = UpgradeOptions {
+                    admin_key: api_key.clone(),
+                    restart_command,
+                    coordinators,
+                    skip_volumes,
+                    drain: !no_drain,
+                    health_timeout: parse_duration(&health_timeout)?,
+                    state_path: state,
+                };
+                let progress = rolling_upgrade(&cli.coordinator, &options, |p| {
+                    let current = p.steps.iter().find(|s| s.phase != StepPhase::Done);
+                    match current {
+                        Some(step) => println!(
+                            "  {}/{} nodes done, {}: {:?}",
+                            p.done(),
+                            p.steps.len(),
+                            step.id,
+                            step.phase
+                        ),
+                        None => println!("  {}/{} nodes done", p.done(), p.steps.len()),
+                    }
+                })
+                .await?;
+                print_upgrade(&progress);
+            }
+            UpgradeCommands::Status { json } => match UpgradeProgress::load(&state)? {
+                Some(progress) if json => {
+                    println!("{}", serde_json::to_string_pretty(&progress)?)
+                }
+                Some(progress) => print_upgrade(&progress),
+                None => println!("No upgrade at {}", state.display()),
+            },
+            UpgradeCommands::Pause {} => {
+                set_upgrade_state(&state, UpgradeState::Paused)?;
+                println!("Upgrade paused; it stops before its next phase.");
+            }
+            UpgradeCommands::Resume {} => {
+                set_upgrade_state(&state, UpgradeState::Running)?;
+                println!("Upgrade resumed.");
+            }
+            UpgradeCommands::Abort {} => {
+                set_upgrade_state(&state, UpgradeState::Aborted)?;
+                println!("Upgrade aborted; it stops before its next phase.");
+            }
+        },
+
         Commands::Stream { key } => {
             stream_large_blob("volume-1", &key).await?;
             println!("Streaming large blob for key: {}", key);
@@ -166,6 +1150,29 @@ async fn main() -> anyhow::Result<()> {
             }
         }
 
+        Commands::Export {
+            output,
+            prefix,
+            format,
+            transfer,
+        } => {
+            let format = format.unwrap_or_else(|| BulkFormat::for_path(&output));
+            let options = transfer.options(api_key);
+            let report = export_keys(&cli.coordinator, &prefix, format, &output, &options).await?;
+            print_bulk_report(&format!("Exported to {}", output.display()), &report);
+        }
+
+        Commands::Import {
+            input,
+            format,
+            transfer,
+        } => {
+            let format = format.unwrap_or_else(|| BulkFormat::for_path(&input));
+            let options = transfer.options(api_key);
+            let report = import_keys(&cli.coordinator, format, &input, &options).await?;
+            print_bulk_report(&format!("Imported from {}", input.display()), &report);
+        }
+
         Commands::Delete { key } => {
             let url = format!("{}/{}", cli.coordinator, key);
             let client = reqwest::Client::new();
@@ -1,9 +1,12 @@
 //! Coordinator binary
 
 use clap::{Parser, Subcommand};
-use minikv::{common::CoordinatorConfig, Coordinator};
+use minikv::common::lifecycle::{self, PidFile};
+use minikv::common::{ConfigSources, CoordinatorConfig};
+use minikv::Coordinator;
+use std::net::SocketAddr;
 use std::path::PathBuf;
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+use std::process::ExitCode;
 
 #[derive(Parser)]
 #[command(name = "minikv-coord")]
@@ -15,93 +18,142 @@ struct Cli {
 
 #[derive(Subcommand)]
 enum Commands {
-    /// Start coordinator server
+    /// Start coordinator server. Flags override `MINIKV_*` variables, which
+    /// override the configuration file.
     Serve {
-        /// Node ID
+        /// Configuration file (default: `MINIKV_CONFIG`, else config.toml
+        /// and config.local.toml)
         #[arg(long)]
-        id: String,
+        config: Option<PathBuf>,
 
-        /// Bind address for HTTP
-        #[arg(long, default_value = "0.0.0.0:5000")]
-        bind: String,
+        /// Print the effective configuration as TOML and exit
+        #[arg(long)]
+        print_config: bool,
+
+        /// Node ID (`node_id`)
+        #[arg(long)]
+        id: Option<String>,
 
-        /// Bind address for gRPC
-        #[arg(long, default_value = "0.0.0.0:5001")]
-        grpc: String,
+        /// Bind address for HTTP (default 0.0.0.0:5000)
+        #[arg(long)]
+        bind: Option<String>,
 
-        /// Database directory
-        #[arg(long, default_value = "./coord-data")]
-        db: PathBuf,
+        /// Bind address for gRPC (default 0.0.0.0:5001)
+        #[arg(long)]
+        grpc: Option<String>,
+
+        /// Bind address for the public gRPC API (disabled if unset)
+        #[arg(long)]
+        client_grpc: Option<String>,
+
+        /// Bind address for the Redis protocol listener (disabled if unset)
+        #[arg(long)]
+        resp: Option<String>,
+
+        /// Bind address for the memcached protocol listener (disabled if unset)
+        #[arg(long)]
+        memcached: Option<String>,
+
+        /// Database directory (default ./coord-data)
+        #[arg(long)]
+        db: Option<PathBuf>,
 
         /// Raft peers (comma-separated)
         #[arg(long, value_delimiter = ',')]
         peers: Vec<String>,
 
-        /// Replication factor
-        #[arg(long, default_value = "3")]
-        replicas: usize,
+        /// Replication factor (default 3)
+        #[arg(long)]
+        replicas: Option<usize>,
+
+        /// HTTP URL clients are redirected to while this node leads
+        #[arg(long)]
+        advertise: Option<String>,
+
+        /// File to write the process ID to while serving
+        #[arg(long)]
+        pid_file: Option<PathBuf>,
     },
 }
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::EnvFilter::try_from_default_env().unwrap_or_else(|_| "info".into()),
-        )
-        .with(tracing_subscriber::fmt::layer())
-        .init();
+/// Parse the address given to `--<flag>`
+#[allow(clippy::result_large_err)]
+fn parse_addr(flag: &str, addr: Option<String>) -> minikv::Result<Option<String>> {
+    addr.map(|addr| {
+        addr.parse::<SocketAddr>()
+            .map(|addr| addr.to_string())
+            .map_err(|e| minikv::Error::InvalidConfig(format!("--{} {}: {}", flag, addr, e)))
+    })
+    .transpose()
+}
 
-    let cli = Cli::parse();
+/// A path given on the command line, as a configuration value
+fn path_value(path: Option<PathBuf>) -> Option<String> {
+    path.map(|path| path.to_string_lossy().into_owned())
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    match run(Cli::parse()).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            lifecycle::exit_code(&e)
+        }
+    }
+}
 
+async fn run(cli: Cli) -> minikv::Result<()> {
     match cli.command {
         Commands::Serve {
+            config,
+            print_config,
             id,
             bind,
             grpc,
+            client_grpc,
+            resp,
+            memcached,
             db,
             peers,
             replicas,
+            advertise,
+            pid_file,
         } => {
-            // Load config from file, then override with CLI arguments
-            let config = minikv::common::config::Config::load();
-            // Override fields if provided via CLI
-            let bind_addr = bind.parse()?;
-            let grpc_addr = grpc.parse()?;
-            let db_path = db;
-            let mut coord_config = CoordinatorConfig {
-                bind_addr,
-                grpc_addr,
-                db_path,
-                peers,
-                replicas,
-                ..Default::default()
-            };
-            // If file config exists, merge it (CLI has priority)
-            if let Some(file_conf) = config.coordinator {
-                let bind_addr = file_conf.bind_addr;
-                let grpc_addr = file_conf.grpc_addr;
-                let db_path = file_conf.db_path.clone();
-                let peers = file_conf.peers.clone();
-                let replicas = file_conf.replicas;
-                if bind_addr != "0.0.0.0:5000".parse().unwrap() {
-                    coord_config.bind_addr = bind_addr;
-                }
-                if grpc_addr != "0.0.0.0:5001".parse().unwrap() {
-                    coord_config.grpc_addr = grpc_addr;
-                }
-                if db_path.as_path() != std::path::Path::new("./coord-data") {
-                    coord_config.db_path = db_path;
-                }
-                if !peers.is_empty() {
-                    coord_config.peers = peers;
-                }
-                if replicas != 3 {
-                    coord_config.replicas = replicas;
-                }
-                // ... other fields if needed
+            let sources = ConfigSources::default()
+                .with_file(config)
+                .with_default("role", "coordinator")
+                .with_flag("node_id", id)
+                .with_flag("coordinator.bind_addr", parse_addr("bind", bind)?)
+                .with_flag("coordinator.grpc_addr", parse_addr("grpc", grpc)?)
+                .with_flag(
+                    "coordinator.client_grpc_addr",
+                    parse_addr("client-grpc", client_grpc)?,
+                )
+                .with_flag("coordinator.resp_addr", parse_addr("resp", resp)?)
+                .with_flag(
+                    "coordinator.memcached_addr",
+                    parse_addr("memcached", memcached)?,
+                )
+                .with_flag("coordinator.db_path", path_value(db))
+                .with_flag("coordinator.peers", (!peers.is_empty()).then_some(peers))
+                .with_flag("coordinator.replicas", replicas.map(|n| n as i64))
+                .with_flag("coordinator.advertise_addr", advertise)
+                .with_flag("pid_file", path_value(pid_file));
+            let mut config = sources.load()?;
+            let coord_config = config
+                .coordinator
+                .get_or_insert_with(CoordinatorConfig::default)
+                .clone();
+            if print_config {
+                print!("{}", config.to_toml()?);
+                return Ok(());
             }
-            let coord = Coordinator::new(coord_config, id);
+            // Reloads read the same sources
+            sources.install();
+            minikv::common::logging::init(&config.logging, &config.node_id);
+            let _pid_file = config.pid_file.map(PidFile::create).transpose()?;
+            let coord = Coordinator::new(coord_config, config.node_id);
             coord.serve().await?;
         }
     }
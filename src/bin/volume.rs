@@ -1,10 +1,409 @@
+//! Volume binary
+
+use clap::{Args, Parser, Subcommand};
+use minikv::common::lifecycle::{self, PidFile, EXIT_FAILURE};
+use minikv::common::{ConfigSources, VolumeConfig, ENCRYPTION_MANAGER};
+use minikv::volume::blob::BlobStore;
+use minikv::volume::fsck::{fsck, FsckAction};
+use minikv::volume::rekey::{rekey_store, DEFAULT_BATCH_SIZE};
 use minikv::volume::server::VolumeServer;
+use minikv::volume::wal::{Wal, WalRecord};
+use std::net::SocketAddr;
 use std::path::PathBuf;
+use std::process::ExitCode;
+
+#[derive(Parser)]
+#[command(name = "minikv-volume")]
+#[command(about = "minikv volume server")]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Commands>,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// Start the volume server (the default). Flags override `MINIKV_*`
+    /// variables, which override the configuration file.
+    Serve(ServeArgs),
+
+    /// Re-encrypt a stopped volume under the current master key of
+    /// `[volume.encryption]`, then compact it. Previous keys must be listed
+    /// in `previous_keys` until this has run.
+    Rekey {
+        /// Data directory (defaults to `[volume].data_path`)
+        #[arg(long)]
+        data: Option<PathBuf>,
+
+        /// WAL directory (defaults to `[volume].wal_path`)
+        #[arg(long)]
+        wal: Option<PathBuf>,
+
+        /// Keys re-encrypted per batch
+        #[arg(long, default_value_t = DEFAULT_BATCH_SIZE)]
+        batch_size: usize,
+    },
+
+    /// Check the segments of a stopped volume against their checksums and
+    /// the index snapshot, and print a repair plan
+    Fsck {
+        /// Data directory, repeated for each disk with the primary first
+        /// (defaults to `[volume].data_path`)
+        #[arg(long)]
+        data: Vec<PathBuf>,
+
+        /// WAL directory (defaults to `[volume].wal_path`)
+        #[arg(long)]
+        wal: Option<PathBuf>,
+
+        /// Carry out the repair plan
+        #[arg(long)]
+        fix: bool,
+
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Inspect the write-ahead log
+    Wal {
+        #[command(subcommand)]
+        command: WalCommands,
+    },
+}
+
+#[derive(Args, Default)]
+struct ServeArgs {
+    /// Configuration file (default: `MINIKV_CONFIG`, else config.toml and
+    /// config.local.toml)
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    /// Print the effective configuration as TOML and exit
+    #[arg(long)]
+    print_config: bool,
+
+    /// Node ID (`node_id`, default volume)
+    #[arg(long)]
+    id: Option<String>,
+
+    /// Bind address for HTTP (default 0.0.0.0:6000)
+    #[arg(long)]
+    bind: Option<SocketAddr>,
+
+    /// Bind address for gRPC (default 0.0.0.0:6001)
+    #[arg(long)]
+    grpc: Option<SocketAddr>,
+
+    /// Data directory (default volume_data)
+    #[arg(long)]
+    data: Option<PathBuf>,
+
+    /// WAL directory (default `wal` next to the data directory)
+    #[arg(long)]
+    wal: Option<PathBuf>,
+
+    /// Coordinator URLs (comma-separated)
+    #[arg(long, value_delimiter = ',')]
+    coordinators: Vec<String>,
+
+    /// File to write the process ID to while serving
+    #[arg(long)]
+    pid_file: Option<PathBuf>,
+}
+
+#[derive(Subcommand)]
+enum WalCommands {
+    /// Print the entries of a WAL (a file, or a WAL directory holding
+    /// `wal.log`) with their checksum status. Values are not decrypted.
+    Dump {
+        /// WAL file or directory
+        path: PathBuf,
+
+        /// Only entries of this key
+        #[arg(long)]
+        key: Option<String>,
+
+        /// Only entries of keys under this prefix
+        #[arg(long)]
+        prefix: Option<String>,
+
+        /// Only entries from this sequence number on
+        #[arg(long)]
+        from_seq: Option<u64>,
+
+        /// Only entries up to this sequence number
+        #[arg(long)]
+        to_seq: Option<u64>,
+
+        /// Print one JSON object per entry, then the summary on stderr
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+/// The configuration sources of a volume, with `file` in place of the
+/// default files if given
+fn volume_sources(file: Option<PathBuf>) -> ConfigSources {
+    ConfigSources::default()
+        .with_file(file)
+        .with_default("role", "volume")
+        .with_default("node_id", "volume")
+}
+
+/// A path given on the command line, as a configuration value
+fn path_value(path: Option<PathBuf>) -> Option<String> {
+    path.map(|path| path.to_string_lossy().into_owned())
+}
+
+/// Whether a dumped entry passes the filters of `wal dump`
+fn wanted(
+    record: &WalRecord,
+    key: Option<&str>,
+    prefix: Option<&str>,
+    from_seq: Option<u64>,
+    to_seq: Option<u64>,
+) -> bool {
+    key.map_or(true, |key| record.key == key)
+        && prefix.map_or(true, |prefix| record.key.starts_with(prefix))
+        && from_seq.map_or(true, |from| record.sequence >= from)
+        && to_seq.map_or(true, |to| record.sequence <= to)
+}
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let path = PathBuf::from("volume_data");
-    let server = VolumeServer::new(path)?; // unwrap Result
-    server.serve().await?;
+async fn main() -> ExitCode {
+    match run(Cli::parse()).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            e.downcast_ref::<minikv::Error>()
+                .map_or(ExitCode::from(EXIT_FAILURE), lifecycle::exit_code)
+        }
+    }
+}
+
+async fn run(cli: Cli) -> Result<(), Box<dyn std::error::Error>> {
+    match cli
+        .command
+        .unwrap_or_else(|| Commands::Serve(ServeArgs::default()))
+    {
+        Commands::Serve(args) => {
+            // The WAL sits next to the data directory unless configured
+            let wal_default = args
+                .data
+                .as_ref()
+                .map_or(PathBuf::from("wal"), |data| data.with_file_name("wal"));
+            let sources = volume_sources(args.config)
+                .with_default("volume.data_path", "volume_data")
+                .with_default(
+                    "volume.wal_path",
+                    wal_default.to_string_lossy().into_owned(),
+                )
+                .with_flag("node_id", args.id)
+                .with_flag("volume.bind_addr", args.bind.map(|addr| addr.to_string()))
+                .with_flag("volume.grpc_addr", args.grpc.map(|addr| addr.to_string()))
+                .with_flag("volume.data_path", path_value(args.data))
+                .with_flag("volume.wal_path", path_value(args.wal))
+                .with_flag(
+                    "volume.coordinators",
+                    (!args.coordinators.is_empty()).then_some(args.coordinators),
+                )
+                .with_flag("pid_file", path_value(args.pid_file));
+            let mut config = sources.load()?;
+            let volume = config
+                .volume
+                .get_or_insert_with(VolumeConfig::default)
+                .clone();
+            if args.print_config {
+                print!("{}", config.to_toml()?);
+                return Ok(());
+            }
+            // Reloads read the same sources
+            sources.install();
+            minikv::common::logging::init(&config.logging, &config.node_id);
+            let _pid_file = config.pid_file.map(PidFile::create).transpose()?;
+            let server = VolumeServer::from_config(&volume)
+                .await?
+                .with_volume_id(config.node_id);
+            server.serve().await?;
+        }
+        Commands::Rekey {
+            data,
+            wal,
+            batch_size,
+        } => {
+            let volume = volume_sources(None).load()?.volume.ok_or_else(|| {
+                minikv::Error::InvalidConfig("no [volume] section in the configuration".into())
+            })?;
+            let encryption = volume.encryption.resolve_keys().await?;
+            ENCRYPTION_MANAGER.write().unwrap().configure(&encryption)?;
+            let data = data.map(Into::into).unwrap_or(volume.data_path);
+            let wal = wal.unwrap_or(volume.wal_path);
+
+            let store = BlobStore::open_dirs(&data.dirs(), &wal, volume.wal_sync)?;
+            let progress = tokio::task::spawn_blocking(move || {
+                rekey_store(&store, batch_size, |p| {
+                    if !p.done {
+                        println!("{} keys scanned, {} re-encrypted", p.scanned, p.rewritten);
+                    }
+                })
+            })
+            .await??;
+            println!(
+                "Re-encrypted {} of {} keys with key version {}",
+                progress.rewritten, progress.scanned, progress.key_version
+            );
+        }
+        Commands::Fsck {
+            data,
+            wal,
+            fix,
+            json,
+        } => {
+            let volume = volume_sources(None).load()?.volume;
+            if let Some(volume) = &volume {
+                let encryption = volume.encryption.resolve_keys().await?;
+                ENCRYPTION_MANAGER.write().unwrap().configure(&encryption)?;
+            }
+            let data = match (data.is_empty(), &volume) {
+                (false, _) => data,
+                (true, Some(volume)) => volume.data_path.dirs(),
+                (true, None) => {
+                    return Err(minikv::Error::InvalidConfig(
+                        "no --data and no [volume] section".into(),
+                    )
+                    .into())
+                }
+            };
+            let wal = wal.or_else(|| volume.map(|volume| volume.wal_path));
+            let report =
+                tokio::task::spawn_blocking(move || fsck(&data, wal.as_deref(), fix)).await??;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&report)?);
+                return Ok(());
+            }
+
+            println!(
+                "{} segments, {} records, {} bytes",
+                report.segments, report.records, report.bytes
+            );
+            match report.indexed_keys {
+                Some(keys) => println!(
+                    "Index snapshot: {} keys ({} logged in the WAL since)",
+                    keys, report.keys_in_wal
+                ),
+                None => println!("No index snapshot: it is rebuilt from the segments on open"),
+            }
+            for record in &report.corrupted_records {
+                println!(
+                    "Corrupted record: {} (segment {}, offset {})",
+                    record.key, record.segment, record.offset
+                );
+            }
+            for torn in &report.torn_segments {
+                println!(
+                    "Torn segment {}: {} bytes from offset {} ({})",
+                    torn.segment, torn.bytes, torn.offset, torn.reason
+                );
+            }
+            for entry in &report.dangling {
+                println!(
+                    "Dangling index entry: {} (segment {}, offset {}): {}",
+                    entry.key, entry.segment, entry.offset, entry.problem
+                );
+            }
+            for record in &report.unreferenced {
+                println!(
+                    "Unreferenced record: {} (segment {}, offset {})",
+                    record.key, record.segment, record.offset
+                );
+            }
+            if !report.unreferenced.is_empty() {
+                println!(
+                    "{} unreferenced bytes, dropped by the next compaction",
+                    report.unreferenced_bytes
+                );
+            }
+            if report.plan.is_empty() {
+                println!("Nothing to repair");
+            } else {
+                println!(
+                    "Repair plan{}:",
+                    if report.fixed { " (carried out)" } else { "" }
+                );
+                for action in &report.plan {
+                    match action {
+                        FsckAction::Truncate { segment, offset } => {
+                            println!("  Cut segment {} off at offset {}", segment, offset)
+                        }
+                        FsckAction::Unindex { key } => {
+                            println!("  Remove {} from the index snapshot", key)
+                        }
+                    }
+                }
+                if report.fixed {
+                    println!(
+                        "Run `minikv repair` to copy the removed keys back from other replicas"
+                    );
+                } else {
+                    println!("Run again with --fix to carry it out");
+                }
+            }
+        }
+        Commands::Wal {
+            command:
+                WalCommands::Dump {
+                    path,
+                    key,
+                    prefix,
+                    from_seq,
+                    to_seq,
+                    json,
+                },
+        } => {
+            let path = if path.is_dir() {
+                path.join("wal.log")
+            } else {
+                path
+            };
+            if !json {
+                println!(
+                    "{:>12} {:>20} {:<13} {:>10} {:<4} KEY",
+                    "OFFSET", "SEQUENCE", "OP", "VALUE", "CRC"
+                );
+            }
+            let mut shown = 0u64;
+            let scan = Wal::inspect(&path, |record| {
+                if !wanted(&record, key.as_deref(), prefix.as_deref(), from_seq, to_seq) {
+                    return;
+                }
+                shown += 1;
+                if json {
+                    println!("{}", serde_json::to_string(&record).unwrap_or_default());
+                } else {
+                    println!(
+                        "{:>12} {:>20} {:<13} {:>10} {:<4} {}",
+                        record.offset,
+                        record.sequence,
+                        record.op,
+                        record.value_size,
+                        if record.crc_ok { "ok" } else { "BAD" },
+                        record.key
+                    );
+                }
+            })?;
+            let summary = format!(
+                "{} entries ({} shown), {} failing their CRC, {} bytes readable",
+                scan.entries, shown, scan.corrupted, scan.valid_bytes
+            );
+            if json {
+                eprintln!("{}", summary);
+            } else {
+                println!("{}", summary);
+            }
+            if let Some(stopped) = scan.stopped {
+                eprintln!("Stopped {}", stopped);
+            }
+        }
+    }
     Ok(())
 }
@@ -0,0 +1,511 @@
+//! Rust client for the coordinator HTTP API
+//!
+//! `MiniKvClient` talks to any coordinators of a cluster and follows the
+//! leader: writes sent to a follower come back as `307` pointing at the
+//! leader, which the client then uses for every request, and an unreachable
+//! coordinator makes it move on to the next one it knows. Requests failing
+//! that way, or with a consensus timeout, are retried with exponential
+//! backoff.
+//!
+//! ```no_run
+//! # async fn example() -> minikv::Result<()> {
+//! use minikv::client::MiniKvClient;
+//!
+//! let client = MiniKvClient::new(["http://coord-1:5000", "http://coord-2:5000"])
+//!     .with_api_key("mk_...");
+//! client.put("greeting", "hello").await?;
+//! assert_eq!(client.get("greeting").await?.as_deref(), Some(&b"hello"[..]));
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Streamed values (`put_stream`, `get_stream`) are sent once, to the leader
+//! found beforehand: a body already consumed can't be replayed.
+
+use crate::common::utils::retry_with_backoff;
+use crate::common::{encode_key, Error, Result};
+use crate::coordinator::http::KeyChangeEvent;
+use crate::coordinator::tenant::TENANT_HEADER;
+use crate::ops::KeyPage;
+use bytes::Bytes;
+use futures_util::{Stream, StreamExt};
+use reqwest::{Method, RequestBuilder, Response, StatusCode, Url};
+use serde::{Deserialize, Serialize};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+/// Attempts of a request before giving up
+const DEFAULT_ATTEMPTS: usize = 5;
+
+/// Wait before the first retry, doubled after each
+const DEFAULT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Deadline of a request, streams excepted
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Values go through the coordinator rather than being redirected to a
+/// volume (`coordinator::redirect`)
+const PROXIED: &[(&str, &str)] = &[("redirect", "false")];
+
+/// Chunks of a value read with `get_stream`
+pub type ValueStream = Pin<Box<dyn Stream<Item = Result<Bytes>> + Send>>;
+
+/// Key changes delivered by `watch`
+pub type WatchStream = Pin<Box<dyn Stream<Item = Result<KeyChangeEvent>> + Send>>;
+
+/// One operation of a `batch`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum BatchOp {
+    Put {
+        key: String,
+        value: String,
+    },
+    /// Reads the key's metadata, not its value
+    Get {
+        key: String,
+    },
+    Delete {
+        key: String,
+    },
+}
+
+/// Outcome of one operation of a `batch`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchResult {
+    pub ok: bool,
+    pub key: String,
+    /// Metadata of the key as JSON, for a get
+    pub value: Option<String>,
+    pub error: Option<String>,
+}
+
+/// Credentials sent with every request
+#[derive(Clone)]
+enum Credentials {
+    ApiKey(String),
+    Bearer(String),
+}
+
+/// Client of a minikv cluster
+#[derive(Clone)]
+pub struct MiniKvClient {
+    http: reqwest::Client,
+    endpoints: Arc<Vec<String>>,
+    /// Coordinator requests go to: the leader once known
+    current: Arc<RwLock<String>>,
+    next_endpoint: Arc<AtomicUsize>,
+    credentials: Option<Credentials>,
+    tenant: Option<String>,
+    attempts: usize,
+    backoff: Duration,
+    timeout: Duration,
+}
+
+impl MiniKvClient {
+    /// Client of the cluster whose coordinators serve HTTP at `endpoints`
+    /// (`http://host:port`). Any of them will do; the first is tried first.
+    ///
+    /// # Panics
+    /// If `endpoints` is empty
+    pub fn new<I, S>(endpoints: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let endpoints: Vec<String> = endpoints
+            .into_iter()
+            .map(|e| e.into().trim_end_matches('/').to_string())
+            .collect();
+        assert!(!endpoints.is_empty(), "a coordinator endpoint is required");
+        let http = reqwest::Client::builder()
+            // Leader redirects are followed here, to remember the leader
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .expect("failed to build the HTTP client");
+        Self {
+            http,
+            current: Arc::new(RwLock::new(endpoints[0].clone())),
+            endpoints: Arc::new(endpoints),
+            next_endpoint: Arc::new(AtomicUsize::new(1)),
+            credentials: None,
+            tenant: None,
+            attempts: DEFAULT_ATTEMPTS,
+            backoff: DEFAULT_BACKOFF,
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// Authenticate with an API key (`Authorization: ApiKey ...`)
+    pub fn with_api_key(mut self, key: impl Into<String>) -> Self {
+        self.credentials = Some(Credentials::ApiKey(key.into()));
+        self
+    }
+
+    /// Authenticate with a JWT (`Authorization: Bearer ...`)
+    pub fn with_bearer_token(mut self, token: impl Into<String>) -> Self {
+        self.credentials = Some(Credentials::Bearer(token.into()));
+        self
+    }
+
+    /// Work in `tenant`, for admin keys and clusters without authentication;
+    /// other keys are bound to their own tenant
+    pub fn with_tenant(mut self, tenant: impl Into<String>) -> Self {
+        self.tenant = Some(tenant.into());
+        self
+    }
+
+    /// Make up to `attempts` attempts of each request, waiting `backoff`
+    /// before the first retry and twice as long before each next one
+    pub fn with_retries(mut self, attempts: usize, backoff: Duration) -> Self {
+        self.attempts = attempts.max(1);
+        self.backoff = backoff;
+        self
+    }
+
+    /// Give up on a request after `timeout`; streams are not bounded
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Coordinator the next request goes to
+    pub fn endpoint(&self) -> String {
+        self.current.read().unwrap().clone()
+    }
+
+    /// Store `value` under `key`
+    pub async fn put(&self, key: &str, value: impl Into<Bytes>) -> Result<()> {
+        let value = value.into();
+        let path = key_path(key);
+        self.send(Method::POST, &path, |request| {
+            request.query(PROXIED).body(value.clone())
+        })
+        .await?;
+        Ok(())
+    }
+
+    /// Store the value streamed by `body` under `key`, without holding it in
+    /// memory
+    pub async fn put_stream<S>(&self, key: &str, body: S) -> Result<()>
+    where
+        S: Stream<Item = std::io::Result<Bytes>> + Send + 'static,
+    {
+        let endpoint = self.find_leader().await?;
+        let url = format!("{}{}", endpoint, key_path(key));
+        let request = self
+            .request(Method::POST, &url)
+            .query(PROXIED)
+            .body(reqwest::Body::wrap_stream(body));
+        let resp = request
+            .send()
+            .await
+            .map_err(|e| self.network_error(&endpoint, e))?;
+        check(key, resp).await?;
+        Ok(())
+    }
+
+    /// The value of `key`, `None` if there is none
+    pub async fn get(&self, key: &str) -> Result<Option<Bytes>> {
+        let path = key_path(key);
+        match self
+            .send(Method::GET, &path, |request| request.query(PROXIED))
+            .await
+        {
+            Ok(resp) => Ok(Some(resp.bytes().await.map_err(http_error)?)),
+            Err(Error::NotFound(_)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// The value of `key` as it arrives, `None` if there is none
+    pub async fn get_stream(&self, key: &str) -> Result<Option<ValueStream>> {
+        let endpoint = self.find_leader().await?;
+        let url = format!("{}{}", endpoint, key_path(key));
+        let resp = self
+            .request(Method::GET, &url)
+            .query(PROXIED)
+            .send()
+            .await
+            .map_err(|e| self.network_error(&endpoint, e))?;
+        match check(key, resp).await {
+            Ok(resp) => Ok(Some(Box::pin(
+                resp.bytes_stream().map(|chunk| chunk.map_err(http_error)),
+            ))),
+            Err(Error::NotFound(_)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Delete `key`. Fails with `NotFound` if there is no such key.
+    pub async fn delete(&self, key: &str) -> Result<()> {
+        let path = key_path(key);
+        self.send(Method::DELETE, &path, |request| request).await?;
+        Ok(())
+    }
+
+    /// One page of at most `limit` keys under `prefix`, in key order, after
+    /// `after` (the `next_after` of the previous page)
+    pub async fn list(&self, prefix: &str, after: Option<&str>, limit: usize) -> Result<KeyPage> {
+        let mut query = vec![("prefix", prefix.to_string()), ("limit", limit.to_string())];
+        if let Some(after) = after {
+            query.push(("after", after.to_string()));
+        }
+        let resp = self
+            .send(Method::GET, "/keys", |request| request.query(&query))
+            .await?;
+        json(resp).await
+    }
+
+    /// Run `ops` in order, each succeeding or failing on its own
+    pub async fn batch(&self, ops: &[BatchOp]) -> Result<Vec<BatchResult>> {
+        #[derive(Deserialize)]
+        struct BatchResponse {
+            results: Vec<BatchResult>,
+        }
+        let body = serde_json::json!({ "ops": ops });
+        let resp = self
+            .send(Method::POST, "/batch", |request| request.json(&body))
+            .await?;
+        let resp: BatchResponse = json(resp).await?;
+        Ok(resp.results)
+    }
+
+    /// Changes of keys from now on (`GET /watch/sse`), until the
+    /// coordinator closes the stream
+    pub async fn watch(&self) -> Result<WatchStream> {
+        let endpoint = self.find_leader().await?;
+        let url = format!("{}/watch/sse", endpoint);
+        let resp = self
+            .request(Method::GET, &url)
+            .send()
+            .await
+            .map_err(|e| self.network_error(&endpoint, e))?;
+        let mut chunks = check("/watch/sse", resp).await?.bytes_stream();
+        Ok(Box::pin(async_stream::stream! {
+            let mut buffer = Vec::new();
+            while let Some(chunk) = chunks.next().await {
+                let chunk = match chunk {
+                    Ok(chunk) => chunk,
+                    Err(e) => {
+                        yield Err(http_error(e));
+                        return;
+                    }
+                };
+                buffer.extend_from_slice(&chunk);
+                while let Some(event) = take_sse_event(&mut buffer) {
+                    yield serde_json::from_str(&event)
+                        .map_err(|e| Error::Http(format!("invalid watch event: {}", e)));
+                }
+            }
+        }))
+    }
+
+    /// Point the client at the leader, asking the coordinators it knows
+    pub async fn find_leader(&self) -> Result<String> {
+        retry_with_backoff(
+            move || async move {
+                let endpoint = self.endpoint();
+                let info = crate::ops::find_leader(&endpoint).await.map_err(|e| {
+                    self.rotate(&endpoint);
+                    Error::ConnectionFailed(format!("{}: {}", endpoint, e))
+                })?;
+                if info.is_leader {
+                    return Ok(endpoint);
+                }
+                match info.leader_addr.filter(|addr| !addr.is_empty()) {
+                    Some(addr) => {
+                        *self.current.write().unwrap() = addr.clone();
+                        Ok(addr)
+                    }
+                    None => Err(Error::NotLeader(format!(
+                        "unknown to {} (term {})",
+                        endpoint, info.term
+                    ))),
+                }
+            },
+            self.attempts,
+            self.backoff,
+        )
+        .await
+    }
+
+    /// Send a request built by `build` to `path` on the current coordinator,
+    /// retrying on the leader or the next coordinator as needed. Error
+    /// statuses come back as the matching `Error`.
+    async fn send(
+        &self,
+        method: Method,
+        path: &str,
+        build: impl Fn(RequestBuilder) -> RequestBuilder,
+    ) -> Result<Response> {
+        let build = &build;
+        retry_with_backoff(
+            move || {
+                let method = method.clone();
+                async move {
+                    let endpoint = self.endpoint();
+                    let request = self
+                        .request(method, &format!("{}{}", endpoint, path))
+                        .timeout(self.timeout);
+                    let resp = build(request)
+                        .send()
+                        .await
+                        .map_err(|e| self.network_error(&endpoint, e))?;
+                    if resp.status() == StatusCode::TEMPORARY_REDIRECT {
+                        return Err(self.follow_leader(&endpoint, &resp));
+                    }
+                    check(path, resp).await
+                }
+            },
+            self.attempts,
+            self.backoff,
+        )
+        .await
+    }
+
+    fn request(&self, method: Method, url: &str) -> RequestBuilder {
+        let mut request = self.http.request(method, url);
+        match &self.credentials {
+            Some(Credentials::ApiKey(key)) => {
+                request = request.header("Authorization", format!("ApiKey {}", key))
+            }
+            Some(Credentials::Bearer(token)) => request = request.bearer_auth(token),
+            None => {}
+        }
+        if let Some(tenant) = &self.tenant {
+            request = request.header(TENANT_HEADER, tenant);
+        }
+        request
+    }
+
+    /// Use the leader a `307` from `endpoint` points at
+    fn follow_leader(&self, endpoint: &str, resp: &Response) -> Error {
+        let leader = resp
+            .headers()
+            .get(reqwest::header::LOCATION)
+            .and_then(|location| location.to_str().ok())
+            .and_then(|location| Url::parse(location).ok())
+            .map(|url| url.origin().ascii_serialization());
+        match leader {
+            Some(leader) => {
+                tracing::debug!("{} redirected to the leader at {}", endpoint, leader);
+                *self.current.write().unwrap() = leader.clone();
+                Error::NotLeader(leader)
+            }
+            // No leader elected yet: wait and ask again
+            None => Error::NotLeader(format!("unknown to {}", endpoint)),
+        }
+    }
+
+    /// Error for a request that got no answer, moving on to the next
+    /// coordinator if it couldn't be reached
+    fn network_error(&self, endpoint: &str, e: reqwest::Error) -> Error {
+        if e.is_timeout() {
+            return Error::Timeout(e.to_string());
+        }
+        self.rotate(endpoint);
+        Error::ConnectionFailed(e.to_string())
+    }
+
+    /// Move on from `failed` to the next configured coordinator, unless
+    /// another request already did
+    fn rotate(&self, failed: &str) {
+        let mut current = self.current.write().unwrap();
+        if *current == failed {
+            let next = self.next_endpoint.fetch_add(1, Ordering::Relaxed);
+            *current = self.endpoints[next % self.endpoints.len()].clone();
+        }
+    }
+}
+
+fn key_path(key: &str) -> String {
+    format!("/{}", encode_key(key))
+}
+
+fn http_error(e: reqwest::Error) -> Error {
+    Error::Http(e.to_string())
+}
+
+async fn json<T: serde::de::DeserializeOwned>(resp: Response) -> Result<T> {
+    let body = resp.bytes().await.map_err(http_error)?;
+    serde_json::from_slice(&body).map_err(|e| Error::Http(e.to_string()))
+}
+
+/// `resp` if it succeeded, else the error its status stands for
+async fn check(what: &str, resp: Response) -> Result<Response> {
+    let status = resp.status();
+    if status.is_success() {
+        return Ok(resp);
+    }
+    let body = resp.text().await.unwrap_or_default();
+    Err(match status {
+        StatusCode::NOT_FOUND => Error::NotFound(what.trim_start_matches('/').to_string()),
+        StatusCode::TEMPORARY_REDIRECT => Error::NotLeader(body),
+        StatusCode::BAD_REQUEST => Error::InvalidRequest(body),
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => Error::PermissionDenied(body),
+        StatusCode::REQUEST_TIMEOUT => Error::Timeout(body),
+        StatusCode::INSUFFICIENT_STORAGE => Error::StorageFull(body),
+        _ => Error::Http(format!("{}: {}", status, body)),
+    })
+}
+
+/// Take the data of the first complete Server-Sent Event from `buffer`
+fn take_sse_event(buffer: &mut Vec<u8>) -> Option<String> {
+    loop {
+        let end = buffer.windows(2).position(|w| w == b"\n\n")?;
+        let event: Vec<u8> = buffer.drain(..end + 2).collect();
+        let event = String::from_utf8_lossy(&event);
+        let data: Vec<&str> = event
+            .lines()
+            .filter_map(|line| line.strip_prefix("data:"))
+            .map(|data| data.strip_prefix(' ').unwrap_or(data))
+            .collect();
+        // Comments and keep-alives carry no data
+        if !data.is_empty() {
+            return Some(data.join("\n"));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_take_sse_event() {
+        let mut buffer = b": keep-alive\n\ndata: {\"a\":1}\n\ndata: {\"b\"".to_vec();
+        assert_eq!(take_sse_event(&mut buffer).as_deref(), Some("{\"a\":1}"));
+        assert_eq!(take_sse_event(&mut buffer), None);
+        buffer.extend_from_slice(b":2}\n\n");
+        assert_eq!(take_sse_event(&mut buffer).as_deref(), Some("{\"b\":2}"));
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn test_batch_op_format() {
+        let op = BatchOp::Put {
+            key: "k".into(),
+            value: "v".into(),
+        };
+        assert_eq!(
+            serde_json::to_value(&op).unwrap(),
+            serde_json::json!({"op": "put", "key": "k", "value": "v"})
+        );
+    }
+
+    #[test]
+    fn test_rotate() {
+        let client = MiniKvClient::new(["http://a", "http://b/"]);
+        assert_eq!(client.endpoint(), "http://a");
+        client.rotate("http://a");
+        assert_eq!(client.endpoint(), "http://b");
+        // Someone else already moved on
+        client.rotate("http://a");
+        assert_eq!(client.endpoint(), "http://b");
+        client.rotate("http://b");
+        assert_eq!(client.endpoint(), "http://a");
+    }
+}
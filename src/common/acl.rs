@@ -0,0 +1,214 @@
+//! Per-prefix access control lists
+//!
+//! ACL rules refine RBAC within a tenant's keyspace. A rule such as
+//! `tenant=acme, prefix=invoices/, min_role=ReadWrite` requires callers of
+//! tenant `acme` to hold at least a ReadWrite key to reach `invoices/...`.
+//! A rule naming a `key_id` applies to that API key only, and confines it:
+//! once a key has rules of its own, it may only reach the prefixes they
+//! cover.
+//!
+//! For a given key, the rule with the longest matching prefix decides
+//! (a key's own rule over a tenant-wide one of the same prefix). Keys no
+//! rule covers stay governed by RBAC alone. Admins are not subject to ACLs.
+
+use crate::common::auth::{AuthContext, Role};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+/// Global ACL store, replicated through Raft
+pub static ACL_STORE: Lazy<AclStore> = Lazy::new(AclStore::new);
+
+/// An access rule on the keys of `tenant` starting with `prefix`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AclRule {
+    /// Rule identifier
+    pub id: String,
+    /// Tenant whose keyspace the rule covers
+    pub tenant: String,
+    /// Keys (as the tenant sees them) starting with this prefix
+    pub prefix: String,
+    /// API key the rule applies to; every key of the tenant if unset
+    pub key_id: Option<String>,
+    /// Least role a caller needs to reach these keys
+    pub min_role: Role,
+}
+
+impl AclRule {
+    fn applies_to(&self, ctx: &AuthContext) -> bool {
+        self.tenant == ctx.tenant
+            && match &self.key_id {
+                Some(key_id) => *key_id == ctx.key_id,
+                None => true,
+            }
+    }
+
+    /// Ordering of rules matching the same key: longer prefixes, then rules
+    /// of a single API key, win
+    fn specificity(&self) -> (usize, bool) {
+        (self.prefix.len(), self.key_id.is_some())
+    }
+}
+
+/// In-memory copy of the ACL rules
+#[derive(Debug, Default)]
+pub struct AclStore {
+    rules: RwLock<HashMap<String, AclRule>>,
+}
+
+impl AclStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add or replace a rule
+    pub fn put_rule(&self, rule: AclRule) {
+        self.rules.write().unwrap().insert(rule.id.clone(), rule);
+    }
+
+    /// Remove a rule
+    pub fn remove_rule(&self, id: &str) -> Option<AclRule> {
+        self.rules.write().unwrap().remove(id)
+    }
+
+    /// Get a rule by ID
+    pub fn get_rule(&self, id: &str) -> Option<AclRule> {
+        self.rules.read().unwrap().get(id).cloned()
+    }
+
+    /// All rules, or a tenant's, ordered by tenant and prefix
+    pub fn list_rules(&self, tenant: Option<&str>) -> Vec<AclRule> {
+        let mut rules: Vec<AclRule> = self
+            .rules
+            .read()
+            .unwrap()
+            .values()
+            .filter(|rule| tenant.map_or(true, |tenant| rule.tenant == tenant))
+            .cloned()
+            .collect();
+        rules.sort_by(|a, b| (&a.tenant, &a.prefix, &a.id).cmp(&(&b.tenant, &b.prefix, &b.id)));
+        rules
+    }
+
+    /// Replace every rule (startup, snapshot install)
+    pub fn load_rules(&self, rules: impl IntoIterator<Item = AclRule>) {
+        let mut map = self.rules.write().unwrap();
+        map.clear();
+        for rule in rules {
+            map.insert(rule.id.clone(), rule);
+        }
+    }
+
+    /// Whether `ctx` may reach `key`, a key of its tenant as the tenant sees it
+    pub fn allows(&self, ctx: &AuthContext, key: &str) -> bool {
+        if ctx.can_admin() {
+            return true;
+        }
+        let rules = self.rules.read().unwrap();
+        let mut confined = false;
+        let mut decisive: Option<&AclRule> = None;
+        for rule in rules.values().filter(|rule| rule.applies_to(ctx)) {
+            confined |= rule.key_id.is_some();
+            if key.starts_with(&rule.prefix)
+                && decisive.map_or(true, |best| rule.specificity() > best.specificity())
+            {
+                decisive = Some(rule);
+            }
+        }
+        match decisive {
+            Some(rule) => ctx.role.at_least(rule.min_role),
+            None => !confined,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ctx(key_id: &str, role: Role) -> AuthContext {
+        AuthContext {
+            key_id: key_id.to_string(),
+            tenant: "acme".to_string(),
+            role,
+        }
+    }
+
+    fn rule(id: &str, prefix: &str, key_id: Option<&str>, min_role: Role) -> AclRule {
+        AclRule {
+            id: id.to_string(),
+            tenant: "acme".to_string(),
+            prefix: prefix.to_string(),
+            key_id: key_id.map(str::to_string),
+            min_role,
+        }
+    }
+
+    #[test]
+    fn test_prefix_rules() {
+        let store = AclStore::new();
+        let reader = ctx("reader", Role::ReadOnly);
+        let writer = ctx("writer", Role::ReadWrite);
+        assert!(store.allows(&reader, "invoices/1"));
+
+        store.put_rule(rule("r1", "invoices/", None, Role::ReadWrite));
+        assert!(!store.allows(&reader, "invoices/1"));
+        assert!(store.allows(&writer, "invoices/1"));
+        assert!(store.allows(&reader, "docs/1"));
+
+        // A longer prefix overrides
+        store.put_rule(rule("r2", "invoices/public/", None, Role::ReadOnly));
+        assert!(store.allows(&reader, "invoices/public/1"));
+        assert!(!store.allows(&reader, "invoices/2"));
+
+        // Other tenants are unaffected
+        let other = AuthContext {
+            tenant: "globex".to_string(),
+            ..reader.clone()
+        };
+        assert!(store.allows(&other, "invoices/1"));
+
+        store.remove_rule("r1");
+        assert!(store.allows(&reader, "invoices/2"));
+    }
+
+    #[test]
+    fn test_key_confined() {
+        let store = AclStore::new();
+        let ci = ctx("ci", Role::ReadWrite);
+        store.put_rule(rule("ci", "builds/", Some("ci"), Role::ReadOnly));
+
+        assert!(store.allows(&ci, "builds/42"));
+        assert!(!store.allows(&ci, "invoices/1"));
+        // Other keys of the tenant are not confined
+        assert!(store.allows(&ctx("other", Role::ReadOnly), "invoices/1"));
+
+        // The key's own rule beats a tenant-wide one of the same prefix
+        store.put_rule(rule("t", "builds/", None, Role::Admin));
+        assert!(store.allows(&ci, "builds/42"));
+        assert!(!store.allows(&ctx("other", Role::ReadWrite), "builds/42"));
+
+        // Admins are not subject to ACLs
+        store.put_rule(rule("a", "", Some("root"), Role::Admin));
+        assert!(store.allows(&ctx("root", Role::Admin), "invoices/1"));
+    }
+
+    #[test]
+    fn test_list_and_load() {
+        let store = AclStore::new();
+        store.put_rule(rule("b", "z/", None, Role::ReadOnly));
+        store.put_rule(rule("a", "a/", None, Role::ReadOnly));
+        let ids: Vec<String> = store
+            .list_rules(Some("acme"))
+            .into_iter()
+            .map(|r| r.id)
+            .collect();
+        assert_eq!(ids, vec!["a", "b"]);
+        assert!(store.list_rules(Some("globex")).is_empty());
+
+        store.load_rules(vec![rule("c", "c/", None, Role::ReadOnly)]);
+        assert!(store.get_rule("a").is_none());
+        assert_eq!(store.list_rules(None).len(), 1);
+    }
+}
@@ -1,14 +1,61 @@
 //! Audit logging module for MiniKV v0.6.0+
 //!
 //! Provides structured audit logs for admin and sensitive actions.
-//! Logs to file and/or stdout. Integrate hooks in key management, auth, and data modification endpoints.
+//! Logs to file and/or stdout, as set by `[coordinator.audit]`. Key
+//! management, authentication failures, admin operations and data deletes
+//! are recorded, with the acting API key taken from the `AuthContext`.
+//!
+//! The file is rotated by size and/or age; rotated files are gzipped and
+//! only the newest `max_files` are kept. Entries can also be shipped to an
+//! HTTP or syslog collector (see `audit_shipper`).
 
+use crate::common::audit_shipper::{AuditShipConfig, AuditShipper};
+use crate::common::auth::AuthContext;
 use chrono::{DateTime, Utc};
 use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
-use std::fs::{File, OpenOptions};
-use std::io::Write;
-use std::sync::Mutex;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+/// Audit log configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AuditConfig {
+    /// Record audit events
+    pub enabled: bool,
+    /// File the entries are appended to, one JSON object per line
+    pub path: String,
+    /// Also print entries to stdout
+    pub stdout: bool,
+    /// Rotate the file once it would grow past this many bytes (0 = never)
+    pub max_size_bytes: u64,
+    /// Rotate the file once it is this old, in seconds (0 = never)
+    pub rotate_interval_secs: u64,
+    /// Rotated files kept, oldest deleted first (0 = all)
+    pub max_files: usize,
+    /// Gzip rotated files
+    pub compress: bool,
+    /// Also ship entries to a collector
+    pub remote: Option<AuditShipConfig>,
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            path: "audit.log".to_string(),
+            stdout: false,
+            max_size_bytes: 100 * 1024 * 1024, // 100 MB
+            rotate_interval_secs: 0,
+            max_files: 10,
+            compress: true,
+            remote: None,
+        }
+    }
+}
 
 /// Audit log event types
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -24,6 +71,7 @@ pub enum AuditEventType {
     ConfigChanged,
     QuotaExceeded,
     System,
+    AdminOperation,
 }
 
 /// Audit log entry
@@ -38,37 +86,216 @@ pub struct AuditEntry {
     pub meta: Option<serde_json::Value>,
 }
 
+/// When and how the log file is rotated
+#[derive(Debug, Clone, Copy)]
+struct Rotation {
+    max_size: u64,
+    interval: Option<Duration>,
+    max_files: usize,
+    compress: bool,
+}
+
+impl From<&AuditConfig> for Rotation {
+    fn from(config: &AuditConfig) -> Self {
+        Self {
+            max_size: config.max_size_bytes,
+            interval: (config.rotate_interval_secs > 0)
+                .then(|| Duration::from_secs(config.rotate_interval_secs)),
+            max_files: config.max_files,
+            compress: config.compress,
+        }
+    }
+}
+
+/// The log file being appended to
+struct AuditFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    opened_at: Instant,
+    rotation: Rotation,
+}
+
+impl AuditFile {
+    fn open(path: &Path, rotation: Rotation) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            size: file.metadata()?.len(),
+            file,
+            opened_at: Instant::now(),
+            rotation,
+        })
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let len = line.len() as u64 + 1;
+        if self.should_rotate(len) {
+            self.rotate()?;
+        }
+        writeln!(self.file, "{}", line)?;
+        self.size += len;
+        Ok(())
+    }
+
+    fn should_rotate(&self, len: u64) -> bool {
+        let Rotation {
+            max_size, interval, ..
+        } = self.rotation;
+        self.size > 0
+            && ((max_size > 0 && self.size + len > max_size)
+                || interval.is_some_and(|interval| self.opened_at.elapsed() >= interval))
+    }
+
+    /// Move the file aside and start a new one; compression and pruning of
+    /// the rotated files happen in the background
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        let stamp = Utc::now().format("%Y%m%d-%H%M%S%.3f");
+        let base = format!("{}.{}", self.path.display(), stamp);
+        let mut rotated = PathBuf::from(&base);
+        let mut n = 1;
+        while rotated.exists() || Path::new(&format!("{}.gz", rotated.display())).exists() {
+            rotated = PathBuf::from(format!("{}-{}", base, n));
+            n += 1;
+        }
+        fs::rename(&self.path, &rotated)?;
+        let reopened = Self::open(&self.path, self.rotation)?;
+        *self = reopened;
+
+        let (path, rotation) = (self.path.clone(), self.rotation);
+        std::thread::spawn(move || {
+            if let Err(e) = finish_rotation(&path, &rotated, rotation) {
+                tracing::warn!("Audit log rotation of {}: {}", rotated.display(), e);
+            }
+        });
+        Ok(())
+    }
+}
+
+/// Gzip `rotated` if configured, then delete the oldest rotated files of
+/// `path` beyond `max_files`
+fn finish_rotation(path: &Path, rotated: &Path, rotation: Rotation) -> io::Result<()> {
+    if rotation.compress {
+        let gz = PathBuf::from(format!("{}.gz", rotated.display()));
+        let mut encoder =
+            flate2::write::GzEncoder::new(File::create(&gz)?, flate2::Compression::default());
+        io::copy(&mut File::open(rotated)?, &mut encoder)?;
+        encoder.finish()?.sync_all()?;
+        fs::remove_file(rotated)?;
+    }
+    if rotation.max_files == 0 {
+        return Ok(());
+    }
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let prefix = format!(
+        "{}.",
+        path.file_name().unwrap_or_default().to_string_lossy()
+    );
+    // Timestamps in the names sort oldest first
+    let mut rotated_files: Vec<PathBuf> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_name().to_string_lossy().starts_with(&prefix))
+        .map(|entry| entry.path())
+        .collect();
+    rotated_files.sort();
+    let excess = rotated_files.len().saturating_sub(rotation.max_files);
+    for old in &rotated_files[..excess] {
+        fs::remove_file(old)?;
+    }
+    Ok(())
+}
+
+/// Where entries go
+struct AuditSink {
+    file: Option<Mutex<AuditFile>>,
+    to_stdout: bool,
+    shipper: Option<AuditShipper>,
+}
+
+impl AuditSink {
+    fn none() -> Self {
+        Self {
+            file: None,
+            to_stdout: false,
+            shipper: None,
+        }
+    }
+}
+
 /// Audit logger (singleton)
 pub struct AuditLogger {
-    file: Option<Mutex<File>>,
-    to_stdout: bool,
+    sink: RwLock<AuditSink>,
 }
 
-pub static AUDIT_LOGGER: Lazy<AuditLogger> = Lazy::new(|| AuditLogger::new("audit.log", true));
+/// Records nothing until configured (`AuditLogger::configure`)
+pub static AUDIT_LOGGER: Lazy<AuditLogger> = Lazy::new(AuditLogger::disabled);
 
 impl AuditLogger {
     /// Create a new audit logger
     pub fn new(path: &str, to_stdout: bool) -> Self {
-        let file = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(path)
+        let rotation = Rotation::from(&AuditConfig::default());
+        let file = AuditFile::open(Path::new(path), rotation)
             .ok()
             .map(Mutex::new);
-        Self { file, to_stdout }
+        Self {
+            sink: RwLock::new(AuditSink {
+                file,
+                to_stdout,
+                shipper: None,
+            }),
+        }
+    }
+
+    /// A logger that drops every entry
+    pub fn disabled() -> Self {
+        Self {
+            sink: RwLock::new(AuditSink::none()),
+        }
+    }
+
+    /// Apply `config`, reopening the log file and (re)starting the shipping
+    /// to the remote sink, which needs a Tokio runtime
+    pub fn configure(&self, config: &AuditConfig) -> crate::common::Result<()> {
+        let sink = if config.enabled {
+            AuditSink {
+                file: Some(Mutex::new(AuditFile::open(
+                    Path::new(&config.path),
+                    Rotation::from(config),
+                )?)),
+                to_stdout: config.stdout,
+                shipper: config.remote.clone().map(AuditShipper::start).transpose()?,
+            }
+        } else {
+            AuditSink::none()
+        };
+        *self.sink.write().unwrap() = sink;
+        Ok(())
     }
 
     /// Log an audit entry
     pub fn log(&self, entry: AuditEntry) {
+        let sink = self.sink.read().unwrap();
+        if sink.file.is_none() && !sink.to_stdout && sink.shipper.is_none() {
+            return;
+        }
         let line = serde_json::to_string(&entry).unwrap_or_else(|_| "{}".to_string());
-        if let Some(file) = &self.file {
+        if let Some(file) = &sink.file {
             if let Ok(mut f) = file.lock() {
-                let _ = writeln!(f, "{}", line);
+                if let Err(e) = f.write_line(&line) {
+                    tracing::warn!("Audit log write failed: {}", e);
+                }
             }
         }
-        if self.to_stdout {
+        if sink.to_stdout {
             println!("[AUDIT] {}", line);
         }
+        if let Some(shipper) = &sink.shipper {
+            shipper.ship(entry);
+        }
     }
 
     /// Convenience for logging an event
@@ -92,6 +319,11 @@ impl AuditLogger {
     }
 }
 
+/// Actor recorded for a request: the caller's API key ID, or `anonymous`
+pub fn actor(ctx: Option<&AuthContext>) -> String {
+    ctx.map_or_else(|| "anonymous".to_string(), |ctx| ctx.key_id.clone())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -107,4 +339,108 @@ mod tests {
             None,
         );
     }
+
+    #[test]
+    fn test_configure() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.log");
+        let logger = AuditLogger::disabled();
+        logger.log_event(AuditEventType::System, "system", None, "dropped", None);
+
+        let config = AuditConfig {
+            path: path.to_string_lossy().into_owned(),
+            ..Default::default()
+        };
+        logger.configure(&config).unwrap();
+        logger.log_event(
+            AuditEventType::DataDelete,
+            "key-1",
+            Some("tenant/key".to_string()),
+            "Key deleted",
+            None,
+        );
+        let lines = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(lines.lines().count(), 1);
+        let entry: AuditEntry = serde_json::from_str(lines.trim()).unwrap();
+        assert_eq!(entry.actor, "key-1");
+        assert!(matches!(entry.event, AuditEventType::DataDelete));
+
+        logger
+            .configure(&AuditConfig {
+                enabled: false,
+                ..config
+            })
+            .unwrap();
+        logger.log_event(AuditEventType::System, "system", None, "dropped", None);
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 1);
+    }
+
+    #[test]
+    fn test_rotation() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.log");
+        let rotation = Rotation {
+            max_size: 100,
+            interval: None,
+            max_files: 0,
+            compress: false,
+        };
+        let mut file = AuditFile::open(&path, rotation).unwrap();
+        for _ in 0..3 {
+            file.write_line(&"x".repeat(60)).unwrap();
+        }
+        // Each line would overflow the previous file
+        let rotated = |dir: &Path| {
+            let mut names: Vec<String> = fs::read_dir(dir)
+                .unwrap()
+                .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+                .filter(|name| name.starts_with("audit.log."))
+                .collect();
+            names.sort();
+            names
+        };
+        assert_eq!(rotated(dir.path()).len(), 2);
+        assert_eq!(fs::read_to_string(&path).unwrap().len(), 61);
+
+        // Compress one, keep the newest two of three
+        let names = rotated(dir.path());
+        let newest = dir.path().join(&names[1]);
+        let third = dir.path().join("audit.log.99999999-000000.000");
+        fs::write(&third, "y\n").unwrap();
+        let rotation = Rotation {
+            max_files: 2,
+            compress: true,
+            ..rotation
+        };
+        finish_rotation(&path, &newest, rotation).unwrap();
+        let names = rotated(dir.path());
+        assert_eq!(
+            names,
+            vec![
+                format!("{}.gz", newest.file_name().unwrap().to_string_lossy()),
+                "audit.log.99999999-000000.000".to_string()
+            ]
+        );
+        let mut decoded = String::new();
+        io::Read::read_to_string(
+            &mut flate2::read::GzDecoder::new(File::open(dir.path().join(&names[0])).unwrap()),
+            &mut decoded,
+        )
+        .unwrap();
+        assert_eq!(decoded, format!("{}\n", "x".repeat(60)));
+
+        // Age-based rotation
+        let mut file = AuditFile::open(
+            &path,
+            Rotation {
+                max_size: 0,
+                interval: Some(Duration::ZERO),
+                max_files: 0,
+                compress: false,
+            },
+        )
+        .unwrap();
+        file.write_line("z").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "z\n");
+    }
 }
@@ -0,0 +1,329 @@
+//! Remote shipping of audit entries
+//!
+//! Entries are queued in a bounded buffer and delivered in batches by a
+//! background task, to an HTTP collector (one NDJSON POST per batch) or a
+//! syslog server (RFC 5424 over UDP, one datagram per entry). A failed
+//! delivery is retried with exponential backoff; entries are dropped, and
+//! counted in `minikv_audit_entries_dropped`, when the buffer is full or
+//! the retries are exhausted.
+
+use crate::common::audit::{AuditEntry, AuditEventType};
+use crate::common::{Error, Result, METRICS};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// Where audit entries are shipped (`[coordinator.audit.remote]`)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum AuditSinkConfig {
+    /// POST batches as NDJSON, e.g. `{ type = "http", url = "http://collector/audit" }`
+    Http { url: String },
+    /// Send to a syslog server over UDP, e.g. `{ type = "syslog", address = "logs:514" }`
+    Syslog { address: String },
+}
+
+/// Remote shipping configuration
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditShipConfig {
+    #[serde(flatten)]
+    pub sink: AuditSinkConfig,
+    /// Entries held while the sink is slow or down
+    #[serde(default = "default_buffer_size")]
+    pub buffer_size: usize,
+    /// Entries sent per delivery
+    #[serde(default = "default_batch_size")]
+    pub batch_size: usize,
+    /// Longest an entry waits for its batch to fill, in milliseconds
+    #[serde(default = "default_flush_interval")]
+    pub flush_interval_ms: u64,
+    /// Retries of a failed delivery before its entries are dropped
+    #[serde(default = "default_max_retries")]
+    pub max_retries: u32,
+}
+
+fn default_buffer_size() -> usize {
+    10_000
+}
+fn default_batch_size() -> usize {
+    100
+}
+fn default_flush_interval() -> u64 {
+    1000
+}
+fn default_max_retries() -> u32 {
+    5
+}
+
+/// Longest wait between two delivery attempts
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Handle on the shipping task; dropping it lets the task deliver what is
+/// buffered and stop
+pub struct AuditShipper {
+    tx: mpsc::Sender<AuditEntry>,
+}
+
+impl AuditShipper {
+    /// Start shipping to `config.sink` on the current Tokio runtime
+    pub fn start(config: AuditShipConfig) -> Result<Self> {
+        let handle = tokio::runtime::Handle::try_current().map_err(|_| {
+            Error::InvalidConfig("audit shipping needs a Tokio runtime".to_string())
+        })?;
+        let (tx, rx) = mpsc::channel(config.buffer_size.max(1));
+        handle.spawn(run(config, rx));
+        Ok(Self { tx })
+    }
+
+    /// Queue an entry, dropping it if the buffer is full
+    pub fn ship(&self, entry: AuditEntry) {
+        if self.tx.try_send(entry).is_err() {
+            METRICS.audit_entries_dropped.inc();
+        }
+    }
+}
+
+/// Connection to the sink
+enum Transport {
+    Http {
+        client: reqwest::Client,
+        url: String,
+    },
+    Syslog {
+        socket: tokio::net::UdpSocket,
+        address: String,
+        hostname: String,
+    },
+}
+
+impl Transport {
+    async fn open(sink: &AuditSinkConfig) -> Result<Self> {
+        Ok(match sink {
+            AuditSinkConfig::Http { url } => Transport::Http {
+                client: reqwest::Client::builder()
+                    .timeout(Duration::from_secs(10))
+                    .build()
+                    .map_err(|e| Error::Other(e.to_string()))?,
+                url: url.clone(),
+            },
+            AuditSinkConfig::Syslog { address } => Transport::Syslog {
+                socket: tokio::net::UdpSocket::bind("0.0.0.0:0").await?,
+                address: address.clone(),
+                hostname: std::env::var("HOSTNAME").unwrap_or_else(|_| "-".to_string()),
+            },
+        })
+    }
+
+    async fn send(&self, batch: &[AuditEntry]) -> Result<()> {
+        match self {
+            Transport::Http { client, url } => {
+                let mut body = String::new();
+                for entry in batch {
+                    body.push_str(&entry_json(entry));
+                    body.push('\n');
+                }
+                let resp = client
+                    .post(url)
+                    .header("content-type", "application/x-ndjson")
+                    .body(body)
+                    .send()
+                    .await
+                    .map_err(|e| Error::ConnectionFailed(format!("{}: {}", url, e)))?;
+                if !resp.status().is_success() {
+                    return Err(Error::Http(format!("{} answered {}", url, resp.status())));
+                }
+                Ok(())
+            }
+            Transport::Syslog {
+                socket,
+                address,
+                hostname,
+            } => {
+                for entry in batch {
+                    let message = syslog_message(entry, hostname);
+                    socket.send_to(message.as_bytes(), address.as_str()).await?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// An entry as an RFC 5424 message from facility local0: warnings for
+/// authentication failures, notices otherwise
+fn syslog_message(entry: &AuditEntry, hostname: &str) -> String {
+    const LOCAL0: u8 = 16;
+    let severity = match entry.event {
+        AuditEventType::AuthFailure => 4,
+        _ => 5,
+    };
+    format!(
+        "<{}>1 {} {} minikv - audit - {}",
+        LOCAL0 * 8 + severity,
+        entry.timestamp.to_rfc3339(),
+        hostname,
+        entry_json(entry)
+    )
+}
+
+fn entry_json(entry: &AuditEntry) -> String {
+    serde_json::to_string(entry).unwrap_or_else(|_| "{}".to_string())
+}
+
+async fn run(config: AuditShipConfig, mut rx: mpsc::Receiver<AuditEntry>) {
+    let transport = match Transport::open(&config.sink).await {
+        Ok(transport) => transport,
+        Err(e) => {
+            tracing::error!("Audit shipping disabled: {}", e);
+            return;
+        }
+    };
+    let batch_size = config.batch_size.max(1);
+    let flush_interval = Duration::from_millis(config.flush_interval_ms);
+    while let Some(first) = rx.recv().await {
+        let mut batch = vec![first];
+        let deadline = tokio::time::sleep(flush_interval);
+        tokio::pin!(deadline);
+        while batch.len() < batch_size {
+            tokio::select! {
+                entry = rx.recv() => match entry {
+                    Some(entry) => batch.push(entry),
+                    None => break,
+                },
+                _ = &mut deadline => break,
+            }
+        }
+        deliver(&transport, &batch, config.max_retries).await;
+    }
+}
+
+async fn deliver(transport: &Transport, batch: &[AuditEntry], max_retries: u32) {
+    let mut backoff = Duration::from_millis(100);
+    for attempt in 0..=max_retries {
+        match transport.send(batch).await {
+            Ok(()) => {
+                METRICS.audit_entries_shipped.add(batch.len() as u64);
+                return;
+            }
+            Err(e) => {
+                tracing::warn!(
+                    "Shipping {} audit entries failed (attempt {}): {}",
+                    batch.len(),
+                    attempt + 1,
+                    e
+                );
+                if attempt < max_retries {
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                }
+            }
+        }
+    }
+    METRICS.audit_entries_dropped.add(batch.len() as u64);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{extract::State, http::StatusCode, routing::post, Router};
+    use chrono::Utc;
+    use std::sync::{Arc, Mutex};
+
+    fn entry(event: AuditEventType, target: &str) -> AuditEntry {
+        AuditEntry {
+            timestamp: Utc::now(),
+            event,
+            actor: "key-1".to_string(),
+            target: Some(target.to_string()),
+            message: "test".to_string(),
+            meta: None,
+        }
+    }
+
+    fn config(sink: AuditSinkConfig) -> AuditShipConfig {
+        AuditShipConfig {
+            sink,
+            buffer_size: 100,
+            batch_size: 10,
+            flush_interval_ms: 10,
+            max_retries: 3,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ship_http_with_retry() {
+        // The collector fails its first request
+        #[derive(Clone, Default)]
+        struct Collector {
+            calls: Arc<Mutex<u32>>,
+            lines: Arc<Mutex<Vec<String>>>,
+        }
+        async fn collect(State(c): State<Collector>, body: String) -> StatusCode {
+            let mut calls = c.calls.lock().unwrap();
+            *calls += 1;
+            if *calls == 1 {
+                return StatusCode::SERVICE_UNAVAILABLE;
+            }
+            c.lines
+                .lock()
+                .unwrap()
+                .extend(body.lines().map(str::to_string));
+            StatusCode::OK
+        }
+        let collector = Collector::default();
+        let app = Router::new()
+            .route("/audit", post(collect))
+            .with_state(collector.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/audit", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let shipper = AuditShipper::start(config(AuditSinkConfig::Http { url })).unwrap();
+        for target in ["a", "b", "c"] {
+            shipper.ship(entry(AuditEventType::DataDelete, target));
+        }
+        for _ in 0..100 {
+            if collector.lines.lock().unwrap().len() == 3 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        let lines = collector.lines.lock().unwrap().clone();
+        assert_eq!(lines.len(), 3);
+        let first: AuditEntry = serde_json::from_str(&lines[0]).unwrap();
+        assert_eq!(first.target.as_deref(), Some("a"));
+        assert!(*collector.calls.lock().unwrap() >= 2);
+    }
+
+    #[tokio::test]
+    async fn test_ship_syslog() {
+        let server = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let address = server.local_addr().unwrap().to_string();
+        let shipper = AuditShipper::start(config(AuditSinkConfig::Syslog { address })).unwrap();
+        shipper.ship(entry(AuditEventType::AuthFailure, "/admin/keys"));
+
+        let mut buf = vec![0; 4096];
+        let n = tokio::time::timeout(Duration::from_secs(5), server.recv(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        let message = String::from_utf8_lossy(&buf[..n]);
+        assert!(message.starts_with("<132>1 "), "{}", message);
+        assert!(message.contains(r#""target":"/admin/keys""#), "{}", message);
+    }
+
+    #[test]
+    fn test_config() {
+        let config: AuditShipConfig =
+            serde_json::from_str(r#"{"type": "syslog", "address": "logs:514", "batch_size": 5}"#)
+                .unwrap();
+        assert_eq!(
+            config.sink,
+            AuditSinkConfig::Syslog {
+                address: "logs:514".to_string()
+            }
+        );
+        assert_eq!(config.batch_size, 5);
+        assert_eq!(config.max_retries, default_max_retries());
+    }
+}
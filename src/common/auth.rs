@@ -5,19 +5,32 @@
 //! - JWT token support for stateless authentication
 //! - Role-based access control (RBAC)
 //! - Tenant isolation for multi-tenancy
+//!
+//! On a coordinator, API keys are replicated through Raft and persisted in
+//! the metadata store (see `MetadataCommand::PutApiKey`); `KEY_STORE` is the
+//! in-memory copy requests are authenticated against, refreshed as commands
+//! are applied and loaded at startup.
+//!
+//! API keys look like `mkv_<key id>_<secret>`: validating one is a lookup by
+//! ID and a single Argon2 verification, skipped altogether for a key verified
+//! less than `VERIFIED_KEY_TTL` ago.
 
 use argon2::{
     password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
     Argon2,
 };
-use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use base64::{
+    engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD},
+    Engine,
+};
 use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
 use once_cell::sync::Lazy;
 use rand::Rng;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::collections::HashMap;
 use std::sync::{Arc, RwLock};
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 /// Default JWT secret (should be overridden in production via config)
 const DEFAULT_JWT_SECRET: &[u8] = b"minikv-default-secret-change-in-production";
@@ -25,9 +38,13 @@ const DEFAULT_JWT_SECRET: &[u8] = b"minikv-default-secret-change-in-production";
 /// API key prefix for easy identification
 const API_KEY_PREFIX: &str = "mkv_";
 
-/// API key length (excluding prefix)
+/// API key secret length in random bytes (excluding prefix and key ID)
 const API_KEY_LENGTH: usize = 32;
 
+/// How long a successfully verified API key is trusted without running
+/// Argon2 again
+pub const VERIFIED_KEY_TTL: Duration = Duration::from_secs(60);
+
 /// JWT token expiration (24 hours by default)
 const JWT_EXPIRATION_HOURS: u64 = 24;
 
@@ -58,6 +75,19 @@ impl Role {
     pub fn can_read(&self) -> bool {
         true // All roles can read
     }
+
+    /// Check if this role grants at least the permissions of `other`
+    pub fn at_least(&self, other: Role) -> bool {
+        self.rank() >= other.rank()
+    }
+
+    fn rank(&self) -> u8 {
+        match self {
+            Role::ReadOnly => 0,
+            Role::ReadWrite => 1,
+            Role::Admin => 2,
+        }
+    }
 }
 
 /// Represents an API key with associated metadata
@@ -104,6 +134,52 @@ impl ApiKey {
     }
 }
 
+/// An API key as persisted and replicated, hash included
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ApiKeyRecord {
+    pub id: String,
+    pub name: String,
+    pub key_hash: String,
+    pub tenant: String,
+    pub role: Role,
+    pub created_at: u64,
+    pub expires_at: Option<u64>,
+    pub active: bool,
+    pub last_used_at: Option<u64>,
+}
+
+impl From<&ApiKey> for ApiKeyRecord {
+    fn from(key: &ApiKey) -> Self {
+        Self {
+            id: key.id.clone(),
+            name: key.name.clone(),
+            key_hash: key.key_hash.clone(),
+            tenant: key.tenant.clone(),
+            role: key.role,
+            created_at: key.created_at,
+            expires_at: key.expires_at,
+            active: key.active,
+            last_used_at: key.last_used_at,
+        }
+    }
+}
+
+impl From<ApiKeyRecord> for ApiKey {
+    fn from(record: ApiKeyRecord) -> Self {
+        Self {
+            id: record.id,
+            name: record.name,
+            key_hash: record.key_hash,
+            tenant: record.tenant,
+            role: record.role,
+            created_at: record.created_at,
+            expires_at: record.expires_at,
+            active: record.active,
+            last_used_at: record.last_used_at,
+        }
+    }
+}
+
 /// JWT claims structure
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Claims {
@@ -161,12 +237,13 @@ pub enum AuthResult {
 pub struct KeyStore {
     /// Map of key_id -> ApiKey
     keys: RwLock<HashMap<String, ApiKey>>,
-    /// Map of key_hash -> key_id for fast lookup
-    hash_to_id: RwLock<HashMap<String, String>>,
-    /// JWT encoding key
-    jwt_encoding_key: EncodingKey,
-    /// JWT decoding key
-    jwt_decoding_key: DecodingKey,
+    /// Map of key_id -> (SHA-256 of the plaintext key, verification time)
+    /// for keys whose Argon2 hash recently matched
+    verified: RwLock<HashMap<String, ([u8; 32], Instant)>>,
+    /// How long entries of `verified` are trusted
+    cache_ttl: Duration,
+    /// JWT encoding and decoding keys
+    jwt_keys: RwLock<(EncodingKey, DecodingKey)>,
     /// Argon2 hasher
     argon2: Argon2<'static>,
 }
@@ -181,13 +258,54 @@ impl KeyStore {
     pub fn with_secret(secret: &[u8]) -> Self {
         Self {
             keys: RwLock::new(HashMap::new()),
-            hash_to_id: RwLock::new(HashMap::new()),
-            jwt_encoding_key: EncodingKey::from_secret(secret),
-            jwt_decoding_key: DecodingKey::from_secret(secret),
+            verified: RwLock::new(HashMap::new()),
+            cache_ttl: VERIFIED_KEY_TTL,
+            jwt_keys: RwLock::new((
+                EncodingKey::from_secret(secret),
+                DecodingKey::from_secret(secret),
+            )),
             argon2: Argon2::default(),
         }
     }
 
+    /// Replace the secret JWTs are signed and checked with
+    pub fn set_jwt_secret(&self, secret: &[u8]) {
+        *self.jwt_keys.write().unwrap() = (
+            EncodingKey::from_secret(secret),
+            DecodingKey::from_secret(secret),
+        );
+    }
+
+    /// Apply an `AuthConfig`: its `jwt_secret` if set, otherwise a random
+    /// secret when authentication is enabled, so tokens signed with the
+    /// built-in default secret are never accepted
+    pub fn configure(&self, config: &AuthConfig) -> Result<(), AuthError> {
+        match &config.jwt_secret {
+            Some(secret) => {
+                let secret = STANDARD
+                    .decode(secret)
+                    .map_err(|e| AuthError::JwtError(format!("jwt_secret is not base64: {}", e)))?;
+                self.set_jwt_secret(&secret);
+            }
+            None if config.enabled => {
+                tracing::warn!(
+                    "auth.jwt_secret is not set: JWTs are only valid on this node until it restarts"
+                );
+                let secret: [u8; 32] = rand::thread_rng().gen();
+                self.set_jwt_secret(&secret);
+            }
+            None => {}
+        }
+        Ok(())
+    }
+
+    /// Set how long a verified key is trusted without re-hashing it
+    /// (`Duration::ZERO` verifies every request)
+    pub fn with_cache_ttl(mut self, ttl: Duration) -> Self {
+        self.cache_ttl = ttl;
+        self
+    }
+
     /// Generate a new API key
     /// Returns (key_id, plaintext_key) - the plaintext key is only shown once!
     pub fn generate_key(
@@ -197,15 +315,30 @@ impl KeyStore {
         role: Role,
         expires_in: Option<Duration>,
     ) -> Result<(String, String), AuthError> {
-        // Generate random key
-        let mut rng = rand::thread_rng();
-        let random_bytes: [u8; API_KEY_LENGTH] = rng.gen();
-        let key_suffix = URL_SAFE_NO_PAD.encode(random_bytes);
-        let plaintext_key = format!("{}{}", API_KEY_PREFIX, key_suffix);
+        let (api_key, plaintext_key) = self.create_key(name, tenant, role, expires_in)?;
+        let key_id = api_key.id.clone();
+        self.insert_key(api_key);
+        Ok((key_id, plaintext_key))
+    }
 
-        // Generate key ID
+    /// Generate a new API key without storing it, so it can be replicated
+    /// first. Returns the key record and the plaintext key.
+    pub fn create_key(
+        &self,
+        name: &str,
+        tenant: &str,
+        role: Role,
+        expires_in: Option<Duration>,
+    ) -> Result<(ApiKey, String), AuthError> {
+        // Generate key ID (a UUID never contains '_')
         let key_id = uuid::Uuid::new_v4().to_string();
 
+        // Generate random key, carrying its ID for the lookup
+        let mut rng = rand::thread_rng();
+        let random_bytes: [u8; API_KEY_LENGTH] = rng.gen();
+        let secret = URL_SAFE_NO_PAD.encode(random_bytes);
+        let plaintext_key = format!("{}{}_{}", API_KEY_PREFIX, key_id, secret);
+
         // Hash the key
         let salt = SaltString::generate(&mut OsRng);
         let key_hash = self
@@ -224,9 +357,9 @@ impl KeyStore {
 
         // Create API key record
         let api_key = ApiKey {
-            id: key_id.clone(),
+            id: key_id,
             name: name.to_string(),
-            key_hash: key_hash.clone(),
+            key_hash,
             tenant: tenant.to_string(),
             role,
             created_at: now,
@@ -235,53 +368,102 @@ impl KeyStore {
             last_used_at: None,
         };
 
-        // Store the key
-        {
-            let mut keys = self.keys.write().unwrap();
-            keys.insert(key_id.clone(), api_key);
-        }
-        {
-            let mut hash_to_id = self.hash_to_id.write().unwrap();
-            hash_to_id.insert(key_hash, key_id.clone());
+        Ok((api_key, plaintext_key))
+    }
+
+    /// Store a key, replacing any key with the same ID. A more recent
+    /// `last_used_at` already known here is kept, since usage isn't replicated.
+    pub fn insert_key(&self, mut api_key: ApiKey) {
+        let mut keys = self.keys.write().unwrap();
+        if let Some(previous) = keys.get(&api_key.id) {
+            api_key.last_used_at = api_key.last_used_at.max(previous.last_used_at);
         }
+        self.verified.write().unwrap().remove(&api_key.id);
+        keys.insert(api_key.id.clone(), api_key);
+    }
 
-        Ok((key_id, plaintext_key))
+    /// Remove a key, returning it if it was stored
+    pub fn remove_key(&self, key_id: &str) -> Option<ApiKey> {
+        let mut keys = self.keys.write().unwrap();
+        let key = keys.remove(key_id)?;
+        self.verified.write().unwrap().remove(key_id);
+        Some(key)
+    }
+
+    /// Replace every stored key, e.g. with the persisted ones at startup
+    pub fn load_keys(&self, api_keys: impl IntoIterator<Item = ApiKey>) {
+        let last_used: HashMap<String, Option<u64>> = self
+            .keys
+            .read()
+            .unwrap()
+            .values()
+            .map(|key| (key.id.clone(), key.last_used_at))
+            .collect();
+        let mut keys = self.keys.write().unwrap();
+        keys.clear();
+        self.verified.write().unwrap().clear();
+        for mut api_key in api_keys {
+            if let Some(used) = last_used.get(&api_key.id) {
+                api_key.last_used_at = api_key.last_used_at.max(*used);
+            }
+            keys.insert(api_key.id.clone(), api_key);
+        }
     }
 
     /// Validate an API key and return the auth context
     pub fn validate_key(&self, key: &str) -> AuthResult {
-        // Check prefix
-        if !key.starts_with(API_KEY_PREFIX) {
-            return AuthResult::Invalid("Invalid key format".to_string());
-        }
+        // Check format: mkv_<key id>_<secret>
+        let key_id = match key
+            .strip_prefix(API_KEY_PREFIX)
+            .and_then(|rest| rest.split_once('_'))
+        {
+            Some((key_id, secret)) if !key_id.is_empty() && !secret.is_empty() => key_id,
+            _ => return AuthResult::Invalid("Invalid key format".to_string()),
+        };
 
-        // Find the key by trying to verify against all stored hashes
-        let keys = self.keys.read().unwrap();
-        for api_key in keys.values() {
-            if let Ok(parsed_hash) = PasswordHash::new(&api_key.key_hash) {
-                if self
-                    .argon2
+        // Look the key up by ID; the lock isn't held while hashing
+        let api_key = match self.keys.read().unwrap().get(key_id) {
+            Some(api_key) => api_key.clone(),
+            None => return AuthResult::Invalid("Invalid API key".to_string()),
+        };
+
+        let digest: [u8; 32] = Sha256::digest(key.as_bytes()).into();
+        let cached = self
+            .verified
+            .read()
+            .unwrap()
+            .get(key_id)
+            .is_some_and(|(verified, at)| *verified == digest && at.elapsed() < self.cache_ttl);
+        if !cached {
+            let matches = PasswordHash::new(&api_key.key_hash).is_ok_and(|parsed_hash| {
+                self.argon2
                     .verify_password(key.as_bytes(), &parsed_hash)
                     .is_ok()
-                {
-                    // Found matching key
-                    if !api_key.active {
-                        return AuthResult::Invalid("Key is disabled".to_string());
-                    }
-                    if api_key.is_expired() {
-                        return AuthResult::Expired;
-                    }
-
-                    return AuthResult::Ok(AuthContext {
-                        key_id: api_key.id.clone(),
-                        tenant: api_key.tenant.clone(),
-                        role: api_key.role,
-                    });
-                }
+            });
+            if !matches {
+                return AuthResult::Invalid("Invalid API key".to_string());
+            }
+            if !self.cache_ttl.is_zero() {
+                self.verified
+                    .write()
+                    .unwrap()
+                    .insert(api_key.id.clone(), (digest, Instant::now()));
             }
         }
 
-        AuthResult::Invalid("Invalid API key".to_string())
+        // Checked on every request, so revocation applies immediately
+        if !api_key.active {
+            return AuthResult::Invalid("Key is disabled".to_string());
+        }
+        if api_key.is_expired() {
+            return AuthResult::Expired;
+        }
+
+        AuthResult::Ok(AuthContext {
+            key_id: api_key.id,
+            tenant: api_key.tenant,
+            role: api_key.role,
+        })
     }
 
     /// Generate a JWT token for an authenticated key
@@ -299,15 +481,19 @@ impl KeyStore {
             iat: now,
         };
 
-        encode(&Header::default(), &claims, &self.jwt_encoding_key)
-            .map_err(|e| AuthError::JwtError(e.to_string()))
+        encode(
+            &Header::default(),
+            &claims,
+            &self.jwt_keys.read().unwrap().0,
+        )
+        .map_err(|e| AuthError::JwtError(e.to_string()))
     }
 
     /// Validate a JWT token
     pub fn validate_jwt(&self, token: &str) -> AuthResult {
         let validation = Validation::default();
 
-        match decode::<Claims>(token, &self.jwt_decoding_key, &validation) {
+        match decode::<Claims>(token, &self.jwt_keys.read().unwrap().1, &validation) {
             Ok(token_data) => {
                 let claims = token_data.claims;
 
@@ -380,14 +566,9 @@ impl KeyStore {
 
     /// Delete an API key permanently
     pub fn delete_key(&self, key_id: &str) -> Result<(), AuthError> {
-        let mut keys = self.keys.write().unwrap();
-        if let Some(key) = keys.remove(key_id) {
-            let mut hash_to_id = self.hash_to_id.write().unwrap();
-            hash_to_id.remove(&key.key_hash);
-            Ok(())
-        } else {
-            Err(AuthError::KeyNotFound(key_id.to_string()))
-        }
+        self.remove_key(key_id)
+            .map(|_| ())
+            .ok_or_else(|| AuthError::KeyNotFound(key_id.to_string()))
     }
 
     /// Update last_used_at timestamp for a key
@@ -427,8 +608,9 @@ pub enum AuthError {
 /// Global key store instance
 pub static KEY_STORE: Lazy<Arc<KeyStore>> = Lazy::new(|| Arc::new(KeyStore::new()));
 
-/// Configuration for authentication
+/// Configuration for authentication (`[coordinator.auth]`)
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct AuthConfig {
     /// Whether authentication is enabled
     pub enabled: bool,
@@ -493,6 +675,46 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_key_lookup_and_cache() {
+        let store = KeyStore::new();
+        let (key_id, plaintext) = store
+            .generate_key("test-key", "default", Role::ReadWrite, None)
+            .unwrap();
+        assert!(plaintext.starts_with(&format!("{}{}_", API_KEY_PREFIX, key_id)));
+
+        assert!(matches!(store.validate_key(&plaintext), AuthResult::Ok(_)));
+        assert!(store.verified.read().unwrap().contains_key(&key_id));
+        // Served from the cache
+        assert!(matches!(store.validate_key(&plaintext), AuthResult::Ok(_)));
+
+        // A known ID with the wrong secret is still rejected
+        let forged = format!("{}{}_{}", API_KEY_PREFIX, key_id, "not-the-secret");
+        assert!(matches!(
+            store.validate_key(&forged),
+            AuthResult::Invalid(_)
+        ));
+        assert!(matches!(
+            store.validate_key("mkv_nosecret"),
+            AuthResult::Invalid(_)
+        ));
+
+        // Revocation isn't delayed by the cache
+        store.revoke_key(&key_id).unwrap();
+        assert!(matches!(
+            store.validate_key(&plaintext),
+            AuthResult::Invalid(_)
+        ));
+
+        // Without a TTL, nothing is cached
+        let store = KeyStore::new().with_cache_ttl(Duration::ZERO);
+        let (key_id, plaintext) = store
+            .generate_key("test-key", "default", Role::ReadOnly, None)
+            .unwrap();
+        assert!(matches!(store.validate_key(&plaintext), AuthResult::Ok(_)));
+        assert!(!store.verified.read().unwrap().contains_key(&key_id));
+    }
+
     #[test]
     fn test_jwt_generation_and_validation() {
         let store = KeyStore::new();
@@ -538,6 +760,81 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_reload_from_records() {
+        let store = KeyStore::new();
+        let (api_key, plaintext) = store
+            .create_key("persisted", "acme", Role::ReadWrite, None)
+            .unwrap();
+        // Not stored until inserted
+        assert!(store.get_key(&api_key.id).is_none());
+
+        let record = ApiKeyRecord::from(&api_key);
+        let bytes = bincode::serialize(&record).unwrap();
+        let decoded: ApiKeyRecord = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(decoded, record);
+
+        // A restarted node only has the records
+        let restarted = KeyStore::new();
+        restarted.load_keys(vec![ApiKey::from(decoded)]);
+        match restarted.validate_key(&plaintext) {
+            AuthResult::Ok(ctx) => assert_eq!(ctx.tenant, "acme"),
+            other => panic!("Expected a valid key, got {:?}", other),
+        }
+
+        restarted.touch_key(&api_key.id);
+        let mut revoked = api_key.clone();
+        revoked.active = false;
+        restarted.insert_key(revoked);
+        let key = restarted.get_key(&api_key.id).unwrap();
+        assert!(!key.active);
+        assert!(key.last_used_at.is_some());
+
+        assert!(restarted.remove_key(&api_key.id).is_some());
+        assert!(restarted.list_keys().is_empty());
+    }
+
+    #[test]
+    fn test_configure_jwt_secret() {
+        let ctx = AuthContext {
+            key_id: "test".to_string(),
+            tenant: "default".to_string(),
+            role: Role::Admin,
+        };
+        let forged = KeyStore::new().generate_jwt(&ctx).unwrap();
+
+        // Enabled without a secret: the default one no longer validates
+        let store = KeyStore::new();
+        let enabled = AuthConfig {
+            enabled: true,
+            ..Default::default()
+        };
+        store.configure(&enabled).unwrap();
+        assert!(matches!(
+            store.validate_jwt(&forged),
+            AuthResult::Invalid(_)
+        ));
+        let token = store.generate_jwt(&ctx).unwrap();
+        assert!(matches!(store.validate_jwt(&token), AuthResult::Ok(_)));
+
+        // A configured secret is shared by every node
+        let config = AuthConfig {
+            jwt_secret: Some(STANDARD.encode(b"shared-secret")),
+            ..enabled
+        };
+        let (a, b) = (KeyStore::new(), KeyStore::new());
+        a.configure(&config).unwrap();
+        b.configure(&config).unwrap();
+        let token = a.generate_jwt(&ctx).unwrap();
+        assert!(matches!(b.validate_jwt(&token), AuthResult::Ok(_)));
+
+        let invalid = AuthConfig {
+            jwt_secret: Some("not base64!".to_string()),
+            ..Default::default()
+        };
+        assert!(KeyStore::new().configure(&invalid).is_err());
+    }
+
     #[test]
     fn test_roles() {
         assert!(Role::Admin.can_read());
@@ -551,6 +848,10 @@ mod tests {
         assert!(Role::ReadOnly.can_read());
         assert!(!Role::ReadOnly.can_write());
         assert!(!Role::ReadOnly.can_admin());
+
+        assert!(Role::Admin.at_least(Role::ReadWrite));
+        assert!(Role::ReadWrite.at_least(Role::ReadWrite));
+        assert!(!Role::ReadOnly.at_least(Role::ReadWrite));
     }
 
     #[test]
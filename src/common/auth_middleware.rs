@@ -5,37 +5,64 @@
 
 use axum::{
     body::Body,
-    extract::{Request, State},
+    extract::{Path, Request, State},
     http::{header::AUTHORIZATION, StatusCode},
     middleware::Next,
     response::{IntoResponse, Response},
-    Json,
+    Json, RequestExt,
 };
 use serde_json::json;
-use std::sync::Arc;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
 
+use crate::common::acl::ACL_STORE;
+use crate::common::audit::{self, AuditEventType, AUDIT_LOGGER};
 use crate::common::auth::{AuthConfig, AuthContext, AuthResult, KeyStore, KEY_STORE};
 
+/// Record a refused request in the audit log
+fn audit_failure(request: &Request<Body>, ctx: Option<&AuthContext>, reason: &str) {
+    AUDIT_LOGGER.log_event(
+        AuditEventType::AuthFailure,
+        audit::actor(ctx),
+        Some(request.uri().path().to_string()),
+        reason,
+        Some(json!({ "method": request.method().as_str() })),
+    );
+}
+
 /// Extension type for passing auth context to handlers
 #[derive(Clone, Debug)]
 pub struct AuthExtension(pub Option<AuthContext>);
 
+/// Extension marking a request that went through `auth_middleware` with
+/// authentication disabled: the permission checks let it through
+#[derive(Clone, Copy, Debug)]
+pub struct AuthDisabled;
+
 /// State for auth middleware
 #[derive(Clone)]
 pub struct AuthState {
     pub key_store: Arc<KeyStore>,
-    pub config: AuthConfig,
+    /// Shared with whoever may change it while serving (see
+    /// `common::reload`)
+    pub config: Arc<RwLock<AuthConfig>>,
 }
 
-impl Default for AuthState {
-    fn default() -> Self {
+impl AuthState {
+    pub fn new(key_store: Arc<KeyStore>, config: AuthConfig) -> Self {
         Self {
-            key_store: KEY_STORE.clone(),
-            config: AuthConfig::default(),
+            key_store,
+            config: Arc::new(RwLock::new(config)),
         }
     }
 }
 
+impl Default for AuthState {
+    fn default() -> Self {
+        Self::new(KEY_STORE.clone(), AuthConfig::default())
+    }
+}
+
 /// Authentication middleware
 /// Validates the Authorization header and adds AuthContext to request extensions
 pub async fn auth_middleware(
@@ -43,20 +70,25 @@ pub async fn auth_middleware(
     mut request: Request<Body>,
     next: Next,
 ) -> Response {
+    let (enabled, public, require_auth_for_reads) = {
+        let config = state.config.read().unwrap();
+        let path = request.uri().path();
+        (
+            config.enabled,
+            config.public_paths.iter().any(|p| path.starts_with(p)),
+            config.require_auth_for_reads,
+        )
+    };
+
     // Skip auth if disabled
-    if !state.config.enabled {
+    if !enabled {
         request.extensions_mut().insert(AuthExtension(None));
+        request.extensions_mut().insert(AuthDisabled);
         return next.run(request).await;
     }
 
     // Check if path is public
-    let path = request.uri().path();
-    if state
-        .config
-        .public_paths
-        .iter()
-        .any(|p| path.starts_with(p))
-    {
+    if public {
         request.extensions_mut().insert(AuthExtension(None));
         return next.run(request).await;
     }
@@ -80,7 +112,7 @@ pub async fn auth_middleware(
     } else {
         // Check if reads require auth
         let is_read = matches!(request.method().as_str(), "GET" | "HEAD" | "OPTIONS");
-        if is_read && !state.config.require_auth_for_reads {
+        if is_read && !require_auth_for_reads {
             request.extensions_mut().insert(AuthExtension(None));
             return next.run(request).await;
         }
@@ -94,38 +126,50 @@ pub async fn auth_middleware(
             request.extensions_mut().insert(AuthExtension(Some(ctx)));
             next.run(request).await
         }
-        AuthResult::Missing => (
-            StatusCode::UNAUTHORIZED,
-            Json(json!({
-                "error": "Authentication required",
-                "hint": "Provide Authorization header with 'Bearer <jwt>' or 'ApiKey <key>'"
-            })),
-        )
-            .into_response(),
-        AuthResult::Invalid(msg) => (
-            StatusCode::UNAUTHORIZED,
-            Json(json!({
-                "error": "Invalid credentials",
-                "message": msg
-            })),
-        )
-            .into_response(),
-        AuthResult::Expired => (
-            StatusCode::UNAUTHORIZED,
-            Json(json!({
-                "error": "Credentials expired",
-                "hint": "Please generate a new API key or refresh your token"
-            })),
-        )
-            .into_response(),
-        AuthResult::Forbidden(msg) => (
-            StatusCode::FORBIDDEN,
-            Json(json!({
-                "error": "Access denied",
-                "message": msg
-            })),
-        )
-            .into_response(),
+        AuthResult::Missing => {
+            audit_failure(&request, None, "Authentication required");
+            (
+                StatusCode::UNAUTHORIZED,
+                Json(json!({
+                    "error": "Authentication required",
+                    "hint": "Provide Authorization header with 'Bearer <jwt>' or 'ApiKey <key>'"
+                })),
+            )
+                .into_response()
+        }
+        AuthResult::Invalid(msg) => {
+            audit_failure(&request, None, &format!("Invalid credentials: {}", msg));
+            (
+                StatusCode::UNAUTHORIZED,
+                Json(json!({
+                    "error": "Invalid credentials",
+                    "message": msg
+                })),
+            )
+                .into_response()
+        }
+        AuthResult::Expired => {
+            audit_failure(&request, None, "Credentials expired");
+            (
+                StatusCode::UNAUTHORIZED,
+                Json(json!({
+                    "error": "Credentials expired",
+                    "hint": "Please generate a new API key or refresh your token"
+                })),
+            )
+                .into_response()
+        }
+        AuthResult::Forbidden(msg) => {
+            audit_failure(&request, None, &format!("Access denied: {}", msg));
+            (
+                StatusCode::FORBIDDEN,
+                Json(json!({
+                    "error": "Access denied",
+                    "message": msg
+                })),
+            )
+                .into_response()
+        }
     }
 }
 
@@ -134,6 +178,7 @@ pub async fn auth_middleware(
 pub async fn require_write_middleware(request: Request<Body>, next: Next) -> Response {
     if let Some(AuthExtension(Some(ref ctx))) = request.extensions().get::<AuthExtension>() {
         if !ctx.can_write() {
+            audit_failure(&request, Some(ctx), "Write permission required");
             return (
                 StatusCode::FORBIDDEN,
                 Json(json!({
@@ -150,8 +195,12 @@ pub async fn require_write_middleware(request: Request<Body>, next: Next) -> Res
 /// Require admin permission middleware
 /// Must be used after auth_middleware
 pub async fn require_admin_middleware(request: Request<Body>, next: Next) -> Response {
+    if request.extensions().get::<AuthDisabled>().is_some() {
+        return next.run(request).await;
+    }
     if let Some(AuthExtension(Some(ref ctx))) = request.extensions().get::<AuthExtension>() {
         if !ctx.can_admin() {
+            audit_failure(&request, Some(ctx), "Admin permission required");
             return (
                 StatusCode::FORBIDDEN,
                 Json(json!({
@@ -163,6 +212,7 @@ pub async fn require_admin_middleware(request: Request<Body>, next: Next) -> Res
         }
     } else {
         // No auth context = no admin access
+        audit_failure(&request, None, "Admin permission required");
         return (
             StatusCode::FORBIDDEN,
             Json(json!({
@@ -175,6 +225,42 @@ pub async fn require_admin_middleware(request: Request<Body>, next: Next) -> Res
     next.run(request).await
 }
 
+/// Per-prefix ACL middleware for routes naming a key in their path
+/// (`/:key`, or `/s3/:bucket/:key` for key `bucket/key`)
+/// Must be used after auth_middleware; routes without a key pass through
+pub async fn acl_middleware(mut request: Request<Body>, next: Next) -> Response {
+    let Some(AuthExtension(Some(ctx))) = request.extensions().get::<AuthExtension>().cloned()
+    else {
+        return next.run(request).await;
+    };
+    let key = match request
+        .extract_parts::<Path<HashMap<String, String>>>()
+        .await
+    {
+        Ok(Path(params)) => match (params.get("bucket"), params.get("key")) {
+            (Some(bucket), Some(key)) => Some(format!("{}/{}", bucket, key)),
+            (None, Some(key)) => Some(key.clone()),
+            _ => None,
+        },
+        Err(_) => None,
+    };
+    if let Some(key) = key {
+        if !ACL_STORE.allows(&ctx, &key) {
+            audit_failure(&request, Some(&ctx), "Denied by ACL");
+            return (
+                StatusCode::FORBIDDEN,
+                Json(json!({
+                    "error": "Access denied by ACL",
+                    "key": key,
+                    "role": format!("{:?}", ctx.role)
+                })),
+            )
+                .into_response();
+        }
+    }
+    next.run(request).await
+}
+
 /// Extract tenant from request
 /// Returns the tenant from auth context, or "default" if no auth
 pub fn get_tenant_from_request(request: &Request<Body>) -> String {
@@ -203,7 +289,101 @@ mod tests {
     #[test]
     fn test_auth_state_default() {
         let state = AuthState::default();
-        assert!(!state.config.enabled);
+        assert!(!state.config.read().unwrap().enabled);
+    }
+
+    #[tokio::test]
+    async fn test_require_admin() {
+        use crate::common::auth::Role;
+        use tower::ServiceExt;
+
+        let key_store = Arc::new(KeyStore::new());
+        let (_, admin) = key_store
+            .generate_key("admin", "default", Role::Admin, None)
+            .unwrap();
+        let (_, reader) = key_store
+            .generate_key("reader", "default", Role::ReadOnly, None)
+            .unwrap();
+        let router = |enabled: bool| {
+            let state = AuthState::new(
+                key_store.clone(),
+                AuthConfig {
+                    enabled,
+                    ..Default::default()
+                },
+            );
+            axum::Router::new()
+                .route("/admin/keys", axum::routing::get(|| async { "ok" }))
+                .route_layer(axum::middleware::from_fn(require_admin_middleware))
+                .route_layer(axum::middleware::from_fn_with_state(state, auth_middleware))
+        };
+        let status = |enabled: bool, key: Option<&str>| {
+            let mut request = Request::get("/admin/keys");
+            if let Some(key) = key {
+                request = request.header(AUTHORIZATION, format!("ApiKey {}", key));
+            }
+            let request = request.body(Body::empty()).unwrap();
+            async move { router(enabled).oneshot(request).await.unwrap().status() }
+        };
+
+        assert_eq!(status(true, Some(&admin)).await, StatusCode::OK);
+        assert_eq!(status(true, Some(&reader)).await, StatusCode::FORBIDDEN);
+        assert_eq!(status(true, None).await, StatusCode::FORBIDDEN);
+        assert_eq!(
+            status(true, Some("mkv_unknown")).await,
+            StatusCode::UNAUTHORIZED
+        );
+        // Authentication disabled: nothing to check against
+        assert_eq!(status(false, None).await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_acl() {
+        use crate::common::acl::AclRule;
+        use crate::common::auth::Role;
+        use tower::ServiceExt;
+
+        let key_store = Arc::new(KeyStore::new());
+        let (_, reader) = key_store
+            .generate_key("reader", "acl-test", Role::ReadOnly, None)
+            .unwrap();
+        ACL_STORE.put_rule(AclRule {
+            id: "acl-middleware-test".to_string(),
+            tenant: "acl-test".to_string(),
+            prefix: "invoices/".to_string(),
+            key_id: None,
+            min_role: Role::ReadWrite,
+        });
+        let state = AuthState::new(
+            key_store,
+            AuthConfig {
+                enabled: true,
+                ..Default::default()
+            },
+        );
+        let router = axum::Router::new()
+            .route("/:key", axum::routing::get(|| async { "ok" }))
+            .route("/s3/:bucket/:key", axum::routing::get(|| async { "ok" }))
+            .route("/keys", axum::routing::get(|| async { "ok" }))
+            .route_layer(axum::middleware::from_fn(acl_middleware))
+            .route_layer(axum::middleware::from_fn_with_state(state, auth_middleware));
+        let status = |path: &str| {
+            let request = Request::get(path)
+                .header(AUTHORIZATION, format!("ApiKey {}", reader))
+                .body(Body::empty())
+                .unwrap();
+            let router = router.clone();
+            async move { router.oneshot(request).await.unwrap().status() }
+        };
+
+        assert_eq!(status("/docs%2F1").await, StatusCode::OK);
+        assert_eq!(status("/invoices%2F1").await, StatusCode::FORBIDDEN);
+        assert_eq!(status("/s3/invoices/1").await, StatusCode::FORBIDDEN);
+        assert_eq!(status("/s3/docs/1").await, StatusCode::OK);
+        // No key in the path: left to the handler
+        assert_eq!(status("/keys").await, StatusCode::OK);
+
+        ACL_STORE.remove_rule("acl-middleware-test");
     }
 
     #[test]
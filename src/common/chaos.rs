@@ -0,0 +1,178 @@
+//! Fault injection, for resilience tests
+//!
+//! `FAULTS` holds the faults a node injects into its own operations, set
+//! through `PUT /admin/chaos` on a coordinator or a volume and cleared with
+//! `DELETE /admin/chaos`:
+//! - `grpc_drop_rate`: share of the internal gRPC calls the node makes that
+//!   fail as `unavailable` before being sent;
+//! - `wal_fsync_delay_ms`: delay added to each fsync of the WAL;
+//! - `corrupt_records`: the next records written to a segment get a wrong
+//!   CRC32, as after bit rot, for reads, scrubbing and repair to catch;
+//! - `kill_compactions`: the next compaction runs die after copying their
+//!   first record, as if the process crashed, leaving their copies behind.
+//!
+//! Faults can only be set on nodes started with `fault_injection = true`;
+//! elsewhere none is ever injected.
+
+use crate::common::{Error, Result};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Global fault injector
+pub static FAULTS: Lazy<FaultInjector> = Lazy::new(FaultInjector::new);
+
+/// Faults to inject, body of `PUT /admin/chaos`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Faults {
+    /// Share of outgoing internal gRPC calls dropped (0.0-1.0)
+    pub grpc_drop_rate: f64,
+    /// Milliseconds added to each WAL fsync
+    pub wal_fsync_delay_ms: u64,
+    /// Records written to a segment from now on with a wrong CRC32
+    pub corrupt_records: u64,
+    /// Compaction runs from now on killed mid-run
+    pub kill_compactions: u64,
+}
+
+/// Faults injected by this node
+pub struct FaultInjector {
+    enabled: AtomicBool,
+    faults: Mutex<Faults>,
+}
+
+impl FaultInjector {
+    pub fn new() -> Self {
+        Self {
+            enabled: AtomicBool::new(false),
+            faults: Mutex::new(Faults::default()),
+        }
+    }
+
+    /// Allow faults to be set (from `fault_injection`); disabling clears them
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+        if !enabled {
+            self.clear();
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Faults left to inject
+    pub fn get(&self) -> Faults {
+        *self.faults.lock().unwrap()
+    }
+
+    /// Replace the faults injected
+    pub fn set(&self, faults: Faults) -> Result<Faults> {
+        if !self.is_enabled() {
+            return Err(Error::PermissionDenied(
+                "fault injection is disabled (`fault_injection = true` enables it)".into(),
+            ));
+        }
+        if !(0.0..=1.0).contains(&faults.grpc_drop_rate) {
+            return Err(Error::InvalidRequest(
+                "grpc_drop_rate must be between 0 and 1".into(),
+            ));
+        }
+        *self.faults.lock().unwrap() = faults;
+        tracing::warn!("Injecting faults: {:?}", faults);
+        Ok(faults)
+    }
+
+    /// Stop injecting faults
+    pub fn clear(&self) {
+        *self.faults.lock().unwrap() = Faults::default();
+    }
+
+    /// Whether to drop an outgoing internal gRPC call
+    pub fn drop_grpc_call(&self) -> bool {
+        if !self.is_enabled() {
+            return false;
+        }
+        let rate = self.faults.lock().unwrap().grpc_drop_rate;
+        rate > 0.0 && rand::random::<f64>() < rate
+    }
+
+    /// Delay to add to a WAL fsync
+    pub fn wal_fsync_delay(&self) -> Option<Duration> {
+        if !self.is_enabled() {
+            return None;
+        }
+        let delay = self.faults.lock().unwrap().wal_fsync_delay_ms;
+        (delay > 0).then(|| Duration::from_millis(delay))
+    }
+
+    /// Whether to corrupt the record being written, counting it
+    pub fn corrupt_record(&self) -> bool {
+        self.take(|faults| &mut faults.corrupt_records)
+    }
+
+    /// Whether to kill the compaction running, counting it
+    pub fn kill_compaction(&self) -> bool {
+        self.take(|faults| &mut faults.kill_compactions)
+    }
+
+    fn take(&self, count: fn(&mut Faults) -> &mut u64) -> bool {
+        if !self.is_enabled() {
+            return false;
+        }
+        let mut faults = self.faults.lock().unwrap();
+        let left = count(&mut faults);
+        if *left == 0 {
+            return false;
+        }
+        *left -= 1;
+        true
+    }
+}
+
+impl Default for FaultInjector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_faults() {
+        let faults = FaultInjector::new();
+        let set = Faults {
+            grpc_drop_rate: 1.0,
+            wal_fsync_delay_ms: 20,
+            corrupt_records: 2,
+            kill_compactions: 1,
+        };
+        assert!(faults.set(set).is_err());
+        assert!(!faults.drop_grpc_call());
+
+        faults.set_enabled(true);
+        faults.set(set).unwrap();
+        assert!(faults.drop_grpc_call());
+        assert_eq!(faults.wal_fsync_delay(), Some(Duration::from_millis(20)));
+        assert!(faults.corrupt_record() && faults.corrupt_record());
+        assert!(!faults.corrupt_record());
+        assert!(faults.kill_compaction());
+        assert!(!faults.kill_compaction());
+        assert_eq!(faults.get().corrupt_records, 0);
+
+        assert!(faults
+            .set(Faults {
+                grpc_drop_rate: 2.0,
+                ..Default::default()
+            })
+            .is_err());
+
+        faults.set_enabled(false);
+        assert_eq!(faults.get(), Faults::default());
+    }
+}
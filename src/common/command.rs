@@ -0,0 +1,130 @@
+//! Commands carried from the coordinator to volumes in heartbeat responses
+//!
+//! The coordinator queues work for a volume and hands it over on the volume's
+//! next heartbeat, so no separate connection back to the volume is needed.
+//! On the wire each command is a short string:
+//!
+//! | Command                                  | Meaning                                       |
+//! |------------------------------------------|-----------------------------------------------|
+//! | `compact`                                | Compact all segments                          |
+//! | `compact_segment:<id>`                   | Compact segment `<id>`                        |
+//! | `migrate_shard:<shard>:<shards>:<addr>`  | Copy local keys of a shard to another volume  |
+//! | `snapshot`                               | Save an index snapshot                        |
+//! | `drain`                                  | Stop accepting new writes                     |
+//! | `rekey`                                  | Re-encrypt under the current master key       |
+
+use crate::common::{Error, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// A unit of work the coordinator asks a volume to perform
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum VolumeCommand {
+    /// Compact every segment
+    Compact,
+    /// Compact one segment, moving its live records to the active segment
+    CompactSegment(u64),
+    /// Copy every local key of `shard` (out of `num_shards`) to the volume
+    /// at the gRPC address `target`. The coordinator owns the key metadata,
+    /// so it repoints keys itself once the copy is done.
+    MigrateShard {
+        shard: u64,
+        num_shards: u64,
+        target: String,
+    },
+    /// Persist the in-memory index
+    Snapshot,
+    /// Reject new writes; reads keep being served while keys are moved off
+    Drain,
+    /// Re-encrypt, in the background, every record not sealed with the
+    /// current master key
+    Rekey,
+}
+
+impl fmt::Display for VolumeCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VolumeCommand::Compact => write!(f, "compact"),
+            VolumeCommand::CompactSegment(id) => write!(f, "compact_segment:{}", id),
+            VolumeCommand::MigrateShard {
+                shard,
+                num_shards,
+                target,
+            } => write!(f, "migrate_shard:{}:{}:{}", shard, num_shards, target),
+            VolumeCommand::Snapshot => write!(f, "snapshot"),
+            VolumeCommand::Drain => write!(f, "drain"),
+            VolumeCommand::Rekey => write!(f, "rekey"),
+        }
+    }
+}
+
+impl FromStr for VolumeCommand {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || Error::InvalidCommand(s.to_string());
+        let (name, args) = s.split_once(':').unwrap_or((s, ""));
+        match (name, args) {
+            ("compact", "") => Ok(VolumeCommand::Compact),
+            ("compact_segment", id) => id
+                .parse()
+                .map(VolumeCommand::CompactSegment)
+                .map_err(|_| invalid()),
+            ("migrate_shard", args) => {
+                // The target address may itself contain ':'
+                let mut parts = args.splitn(3, ':');
+                let shard = parts.next().and_then(|p| p.parse().ok());
+                let num_shards = parts.next().and_then(|p| p.parse().ok());
+                let target = parts.next().filter(|t| !t.is_empty());
+                match (shard, num_shards, target) {
+                    (Some(shard), Some(num_shards), Some(target))
+                        if num_shards > 0 && shard < num_shards =>
+                    {
+                        Ok(VolumeCommand::MigrateShard {
+                            shard,
+                            num_shards,
+                            target: target.to_string(),
+                        })
+                    }
+                    _ => Err(invalid()),
+                }
+            }
+            ("snapshot", "") => Ok(VolumeCommand::Snapshot),
+            ("drain", "") => Ok(VolumeCommand::Drain),
+            ("rekey", "") => Ok(VolumeCommand::Rekey),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command_roundtrip() {
+        let commands = vec![
+            VolumeCommand::Compact,
+            VolumeCommand::CompactSegment(7),
+            VolumeCommand::MigrateShard {
+                shard: 3,
+                num_shards: 256,
+                target: "http://10.0.0.2:5101".to_string(),
+            },
+            VolumeCommand::Snapshot,
+            VolumeCommand::Drain,
+            VolumeCommand::Rekey,
+        ];
+        for cmd in commands {
+            assert_eq!(cmd.to_string().parse::<VolumeCommand>().unwrap(), cmd);
+        }
+
+        assert!("rebalance".parse::<VolumeCommand>().is_err());
+        assert!("compact_segment:x".parse::<VolumeCommand>().is_err());
+        assert!("migrate_shard:300:256:http://a:1"
+            .parse::<VolumeCommand>()
+            .is_err());
+        assert!("snapshot:1".parse::<VolumeCommand>().is_err());
+    }
+}
@@ -1,20 +1,115 @@
 impl Config {
-    /// Loads configuration from a TOML file and overrides with environment variables (prefix MINIKV_)
+    /// Loads configuration from the sources installed by the binary serving
+    /// (see `ConfigSources`)
     pub fn load() -> Self {
-        let s = config::Config::builder()
-            .add_source(config::File::with_name("config.toml").required(false))
-            .add_source(config::File::with_name("config.local.toml").required(false))
-            .add_source(config::Environment::with_prefix("MINIKV").separator("_"))
+        Self::try_load().expect("Failed to load config")
+    }
+
+    /// Like `load`, returning an error instead of panicking
+    pub fn try_load() -> crate::common::Result<Self> {
+        SOURCES.read().unwrap().load()
+    }
+}
+
+/// Sources of the configuration of this process, read again on reload
+static SOURCES: Lazy<RwLock<ConfigSources>> = Lazy::new(Default::default);
+
+/// Environment variable naming the configuration file, when `--config` is
+/// not given
+pub const CONFIG_ENV: &str = "MINIKV_CONFIG";
+
+/// Where the configuration comes from, each layer overriding the ones
+/// before it:
+/// 1. the defaults of the binary, such as its role;
+/// 2. the TOML file given with `--config` or `MINIKV_CONFIG`, or else
+///    `config.toml` then `config.local.toml` in the working directory;
+/// 3. `MINIKV_*` environment variables, with `__` between table and field
+///    (`MINIKV_COORDINATOR__BIND_ADDR`) and commas between list items
+///    (`peers`, `coordinators`);
+/// 4. command-line flags.
+///
+/// Keys are dotted field paths, e.g. `volume.wal_path`.
+#[derive(Debug, Clone, Default)]
+pub struct ConfigSources {
+    file: Option<PathBuf>,
+    defaults: Vec<(String, config::Value)>,
+    overrides: Vec<(String, config::Value)>,
+}
+
+impl ConfigSources {
+    /// Read `path` in place of the default files
+    pub fn with_file(mut self, path: Option<PathBuf>) -> Self {
+        self.file = path;
+        self
+    }
+
+    /// Default `key` to `value`, below the file
+    pub fn with_default(mut self, key: &str, value: impl Into<config::Value>) -> Self {
+        self.defaults.push((key.to_string(), value.into()));
+        self
+    }
+
+    /// Set `key` to `value` if given, above every other source
+    pub fn with_flag(mut self, key: &str, value: Option<impl Into<config::Value>>) -> Self {
+        if let Some(value) = value {
+            self.overrides.push((key.to_string(), value.into()));
+        }
+        self
+    }
+
+    /// Make these the sources of `Config::try_load`
+    pub fn install(self) {
+        *SOURCES.write().unwrap() = self;
+    }
+
+    /// Merge the layers into a configuration
+    #[allow(clippy::result_large_err)]
+    pub fn load(&self) -> crate::common::Result<Config> {
+        self.load_with(environment())
+    }
+
+    #[allow(clippy::result_large_err)]
+    fn load_with(&self, environment: config::Environment) -> crate::common::Result<Config> {
+        let invalid = |e: config::ConfigError| crate::Error::InvalidConfig(e.to_string());
+        let mut builder = config::Config::builder();
+        for (key, value) in &self.defaults {
+            builder = builder.set_default(key, value.clone()).map_err(invalid)?;
+        }
+        builder = match self
+            .file
+            .clone()
+            .or_else(|| std::env::var_os(CONFIG_ENV).map(PathBuf::from))
+        {
+            Some(path) => builder.add_source(config::File::from(path)),
+            None => builder
+                .add_source(config::File::with_name("config.toml").required(false))
+                .add_source(config::File::with_name("config.local.toml").required(false)),
+        };
+        builder = builder.add_source(environment);
+        for (key, value) in &self.overrides {
+            builder = builder.set_override(key, value.clone()).map_err(invalid)?;
+        }
+        builder
             .build()
-            .expect("Failed to load config");
-        s.try_deserialize().expect("Failed to parse config")
+            .and_then(|s| s.try_deserialize())
+            .map_err(invalid)
     }
 }
 
+use crate::common::audit::AuditConfig;
+use crate::common::auth::AuthConfig;
+use crate::common::encryption::EncryptionConfig;
+use crate::common::grpc_auth::GrpcAuthConfig;
+use crate::common::ip_filter::IpFilterConfig;
+use crate::common::ratelimit::RateLimitConfig;
+use crate::common::tls::GrpcTlsConfig;
 /// Configuration for minikv components
+use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::path::PathBuf;
+use std::sync::RwLock;
 use std::time::Duration;
 
 /// Global configuration
@@ -34,15 +129,55 @@ pub struct Config {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub volume: Option<VolumeConfig>,
 
-    /// Logging level
+    /// Log level, format and sampling
+    #[serde(flatten)]
+    pub logging: LogConfig,
+
+    /// File the process ID is written to while serving (see
+    /// `common::lifecycle`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pid_file: Option<PathBuf>,
+}
+
+/// Log output (see `common::logging`)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LogConfig {
+    /// Logging level, or directives as in `RUST_LOG` (which overrides it)
     #[serde(default = "default_log_level")]
     pub log_level: String,
+
+    /// `text`, or `json` for one object per line
+    #[serde(default)]
+    pub log_format: LogFormat,
+
+    /// Debug and trace events logged per second and call site, the others
+    /// being dropped (0 = no sampling)
+    #[serde(default)]
+    pub log_debug_per_sec: u32,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            log_level: default_log_level(),
+            log_format: LogFormat::default(),
+            log_debug_per_sec: 0,
+        }
+    }
 }
 
 fn default_log_level() -> String {
     "info".to_string()
 }
 
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum NodeRole {
@@ -54,15 +189,31 @@ pub enum NodeRole {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CoordinatorConfig {
     /// Bind address for HTTP API
+    #[serde(default = "default_coordinator_bind_addr")]
     pub bind_addr: SocketAddr,
 
     /// Bind address for internal gRPC
+    #[serde(default = "default_coordinator_grpc_addr")]
     pub grpc_addr: SocketAddr,
 
+    /// Bind address for the public gRPC API (`KvService`); unset disables it
+    #[serde(default)]
+    pub client_grpc_addr: Option<SocketAddr>,
+
+    /// Bind address for the Redis protocol (RESP) listener; unset disables it
+    #[serde(default)]
+    pub resp_addr: Option<SocketAddr>,
+
+    /// Bind address for the memcached text protocol listener; unset disables it
+    #[serde(default)]
+    pub memcached_addr: Option<SocketAddr>,
+
     /// RocksDB path for metadata
+    #[serde(default = "default_db_path")]
     pub db_path: PathBuf,
 
     /// Raft peers (other coordinators)
+    #[serde(default)]
     pub peers: Vec<String>,
 
     /// Replication factor
@@ -85,6 +236,61 @@ pub struct CoordinatorConfig {
     #[serde(default = "default_num_shards")]
     pub num_shards: u64,
 
+    /// Failure domain replicas must be spread across
+    #[serde(default)]
+    pub failure_domain: FailureDomain,
+
+    /// Allow placements that cannot satisfy `failure_domain`
+    /// (replicas then share a domain instead of the write being refused)
+    #[serde(default)]
+    pub allow_placement_violation: bool,
+
+    /// Volumes with less free space than this take no new writes
+    /// and are flipped to `readonly`
+    #[serde(default = "default_min_free_bytes")]
+    pub min_free_bytes: u64,
+
+    /// Bandwidth cap for shard migration, in bytes per second (0 = unlimited)
+    #[serde(default = "default_migration_bandwidth")]
+    pub migration_bandwidth_bytes_per_sec: u64,
+
+    /// Maximum number of shards migrating at once
+    #[serde(default = "default_max_concurrent_transfers")]
+    pub max_concurrent_transfers: usize,
+
+    /// Rebalance existing shards onto volumes when they join
+    #[serde(default = "default_true")]
+    pub auto_rebalance: bool,
+
+    /// Seconds without a heartbeat before a volume is `suspect`
+    #[serde(default = "default_suspect_after")]
+    pub suspect_after_secs: u64,
+
+    /// Seconds without a heartbeat before a volume is `dead` and its keys are re-replicated
+    #[serde(default = "default_dead_after")]
+    pub dead_after_secs: u64,
+
+    /// Healthy volumes with a writable WAL needed for `/health/ready`
+    #[serde(default = "default_ready_min_volumes")]
+    pub ready_min_volumes: usize,
+
+    /// Seconds a shutdown may take, draining requests included (see
+    /// `common::shutdown`)
+    #[serde(default = "default_shutdown_timeout")]
+    pub shutdown_timeout_secs: u64,
+
+    /// Seconds between anti-entropy rounds (0 disables)
+    #[serde(default = "default_anti_entropy_interval")]
+    pub anti_entropy_interval_secs: u64,
+
+    /// Seconds between orphaned blob collections (0 disables)
+    #[serde(default = "default_gc_interval")]
+    pub gc_interval_secs: u64,
+
+    /// Seconds a blob must stay orphaned before it is deleted
+    #[serde(default = "default_gc_grace")]
+    pub gc_grace_secs: u64,
+
     /// TLS certificate path (PEM)
     #[serde(default)]
     pub tls_cert_path: Option<String>,
@@ -92,8 +298,509 @@ pub struct CoordinatorConfig {
     /// TLS private key path (PEM)
     #[serde(default)]
     pub tls_key_path: Option<String>,
+
+    /// HTTP URL followers redirect clients to while this node leads
+    /// (defaults to the scheme and `bind_addr` it listens on)
+    #[serde(default)]
+    pub advertise_addr: Option<String>,
+
+    /// Change data capture retention and sinks
+    #[serde(default)]
+    pub cdc: CdcConfig,
+
+    /// Asynchronous replication of key changes to other clusters
+    #[serde(default)]
+    pub replication: ReplicationConfig,
+
+    /// Cold storage tiering to an external S3-compatible bucket
+    #[serde(default)]
+    pub tiering: TieringConfig,
+
+    /// Store identical values once per tenant, as refcounted references
+    #[serde(default)]
+    pub dedup: bool,
+
+    /// Check every GET against the committed blake3 (or only those with
+    /// `?verify=true`), answering 502 and repairing replicas that diverge
+    #[serde(default)]
+    pub verify_on_read: bool,
+
+    /// Splitting of large values into chunks
+    #[serde(default)]
+    pub chunking: ChunkingConfig,
+
+    /// Redirect GETs and PUTs of single values to the volumes
+    #[serde(default)]
+    pub redirect: RedirectConfig,
+
+    /// Sampling of key accesses for `GET /admin/hotkeys`
+    #[serde(default)]
+    pub hotkeys: HotKeysConfig,
+
+    /// Allow faults to be injected through `PUT /admin/chaos`, for tests
+    #[serde(default)]
+    pub fault_injection: bool,
+
+    /// WASM modules run on the puts and gets of keys (`[[coordinator.plugins]]`)
+    #[serde(default)]
+    pub plugins: Vec<PluginConfig>,
+
+    /// HTTP callbacks on key changes, quota breaches, volume state changes
+    /// and repairs
+    #[serde(default)]
+    pub webhooks: WebhooksConfig,
+
+    /// API key / JWT authentication and role checks on the HTTP API
+    #[serde(default)]
+    pub auth: AuthConfig,
+
+    /// Default tenant quota and usage accounting
+    #[serde(default)]
+    pub quota: QuotaConfig,
+
+    /// Per-client-IP rate limiting of the HTTP API
+    #[serde(default)]
+    pub rate_limit: RateLimitConfig,
+
+    /// Writes buffered at once by the HTTP API
+    #[serde(default)]
+    pub write_budget: WriteBudgetConfig,
+
+    /// CIDR allow/deny lists of the HTTP API, admin routes and internal gRPC
+    #[serde(default)]
+    pub ip_filter: IpFilterConfig,
+
+    /// Mutual TLS between coordinators and volumes
+    #[serde(default)]
+    pub grpc_tls: GrpcTlsConfig,
+
+    /// Tokens internal gRPC calls must carry
+    #[serde(default)]
+    pub grpc_auth: GrpcAuthConfig,
+
+    /// Audit log of key management, auth failures, admin operations and deletes
+    #[serde(default)]
+    pub audit: AuditConfig,
+}
+
+/// Change data capture configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CdcConfig {
+    /// How long key change events are kept, in seconds (0 = forever)
+    #[serde(default = "default_cdc_retention")]
+    pub retention_secs: u64,
+
+    /// Where the leader delivers events
+    #[serde(default)]
+    pub sinks: Vec<CdcSinkConfig>,
+}
+
+impl Default for CdcConfig {
+    fn default() -> Self {
+        Self {
+            retention_secs: default_cdc_retention(),
+            sinks: vec![],
+        }
+    }
+}
+
+/// A CDC destination, e.g. `{ type = "webhook", url = "http://indexer/cdc" }`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum CdcSinkConfig {
+    /// POST batches of events as NDJSON
+    Webhook { url: String },
+    /// Append `key<TAB>event` records, ready for a Kafka producer
+    File { path: PathBuf },
+}
+
+/// Cross-cluster replication (`[coordinator.replication]`)
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ReplicationConfig {
+    /// Clusters the leader pushes key changes to
+    #[serde(default)]
+    pub targets: Vec<ReplicationTarget>,
+}
+
+/// A remote cluster, e.g.
+/// `{ name = "eu", url = "https://eu-coord:5000", tenants = ["acme"] }`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReplicationTarget {
+    /// Names the target in its status, metrics and cursor
+    pub name: String,
+
+    /// HTTP URL of a coordinator of the remote cluster
+    pub url: String,
+
+    /// Admin API key of the remote cluster, when it has authentication enabled
+    #[serde(default)]
+    pub api_key: Option<String>,
+
+    /// Tenants replicated in full
+    #[serde(default)]
+    pub tenants: Vec<String>,
+
+    /// Stored key prefixes replicated (`tenant/path/`); without tenants or
+    /// prefixes every key is
+    #[serde(default)]
+    pub prefixes: Vec<String>,
+
+    /// Changes pushed at once
+    #[serde(default = "default_replication_parallelism")]
+    pub parallelism: usize,
+}
+
+/// Splitting of large values into chunks stored as separate keys
+/// (`[coordinator.chunking]`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChunkingConfig {
+    /// Values larger than this are chunked; 0 disables chunking
+    #[serde(default = "default_chunk_threshold")]
+    pub threshold_bytes: u64,
+    /// Size of each chunk but the last
+    #[serde(default = "default_chunk_size")]
+    pub chunk_size: u64,
+    /// Chunks written or read at once for one request
+    #[serde(default = "default_chunk_parallelism")]
+    pub max_parallel: usize,
+    /// Largest value a PUT may carry
+    #[serde(default = "default_max_object_bytes")]
+    pub max_object_bytes: u64,
+}
+
+impl Default for ChunkingConfig {
+    fn default() -> Self {
+        Self {
+            threshold_bytes: default_chunk_threshold(),
+            chunk_size: default_chunk_size(),
+            max_parallel: default_chunk_parallelism(),
+            max_object_bytes: default_max_object_bytes(),
+        }
+    }
+}
+
+/// Direct transfers between clients and volumes (`[coordinator.redirect]`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RedirectConfig {
+    /// Answer every eligible GET and PUT with a redirect to a volume, not
+    /// only those with `?redirect=true`
+    #[serde(default)]
+    pub enabled: bool,
+    /// How long a redirect URL stays valid
+    #[serde(default = "default_redirect_url_ttl")]
+    pub url_ttl_secs: u64,
+}
+
+impl Default for RedirectConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            url_ttl_secs: default_redirect_url_ttl(),
+        }
+    }
+}
+
+fn default_redirect_url_ttl() -> u64 {
+    300
+}
+
+/// A WASM plugin (see `coordinator::plugins`)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PluginConfig {
+    /// Name in logs, errors and metrics
+    pub name: String,
+    /// The module, binary (`.wasm`) or text (`.wat`)
+    pub path: PathBuf,
+    /// Requests the plugin runs on
+    #[serde(default = "default_plugin_hooks")]
+    pub hooks: Vec<PluginHook>,
+    /// Only keys starting with this prefix are passed to the plugin
+    #[serde(default)]
+    pub prefix: String,
+    /// A call running longer is interrupted
+    #[serde(default = "default_plugin_timeout")]
+    pub timeout_ms: u64,
+    /// Largest linear memory the module may grow to
+    #[serde(default = "default_plugin_max_memory")]
+    pub max_memory_bytes: usize,
+    /// Let requests through unchanged when the plugin fails or times out,
+    /// instead of refusing them
+    #[serde(default)]
+    pub fail_open: bool,
+}
+
+/// Request a plugin runs on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PluginHook {
+    Put,
+    Get,
+}
+
+/// Webhook notifications (see `coordinator::webhooks`)
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WebhooksConfig {
+    /// Events that could not be delivered are appended here, as NDJSON
+    /// (unset: they are only logged)
+    #[serde(default)]
+    pub dead_letter_path: Option<PathBuf>,
+
+    /// Endpoints notified (`[[coordinator.webhooks.hooks]]`)
+    #[serde(default)]
+    pub hooks: Vec<WebhookConfig>,
+}
+
+/// An endpoint events are POSTed to, one JSON object per request
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WebhookConfig {
+    pub url: String,
+    /// Event types sent, exactly or by family (`key.*`); `*` is every event
+    #[serde(default = "default_webhook_events")]
+    pub events: Vec<String>,
+    /// Signs bodies with HMAC-SHA256, in `X-Minikv-Signature: sha256=<hex>`
+    #[serde(default)]
+    pub secret: Option<String>,
+    /// Retries of a failed delivery before the event is dead-lettered
+    #[serde(default = "default_webhook_max_retries")]
+    pub max_retries: u32,
+    /// Timeout of each delivery attempt
+    #[serde(default = "default_webhook_timeout")]
+    pub timeout_ms: u64,
+}
+
+fn default_webhook_events() -> Vec<String> {
+    vec!["*".to_string()]
+}
+fn default_webhook_max_retries() -> u32 {
+    5
+}
+fn default_webhook_timeout() -> u64 {
+    5000
+}
+
+fn default_plugin_hooks() -> Vec<PluginHook> {
+    vec![PluginHook::Put, PluginHook::Get]
+}
+fn default_plugin_timeout() -> u64 {
+    100
+}
+fn default_plugin_max_memory() -> usize {
+    64 * 1024 * 1024
+}
+
+/// Sampling of the key accesses served by a coordinator
+/// (`[coordinator.hotkeys]`)
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct HotKeysConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// Share of the reads and writes counted, from 0 to 1
+    #[serde(default = "default_hotkeys_sample_rate")]
+    pub sample_rate: f64,
+    /// Keys counted per minute; past that the least accessed gives way
+    #[serde(default = "default_hotkeys_max_keys")]
+    pub max_keys: usize,
+    /// How far back accesses are kept, in seconds
+    #[serde(default = "default_hotkeys_retention")]
+    pub retention_secs: u64,
+}
+
+impl Default for HotKeysConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            sample_rate: default_hotkeys_sample_rate(),
+            max_keys: default_hotkeys_max_keys(),
+            retention_secs: default_hotkeys_retention(),
+        }
+    }
+}
+
+fn default_hotkeys_sample_rate() -> f64 {
+    0.1
+}
+fn default_hotkeys_max_keys() -> usize {
+    10_000
+}
+fn default_hotkeys_retention() -> u64 {
+    3600
+}
+
+fn default_chunk_threshold() -> u64 {
+    16 * 1024 * 1024
+}
+fn default_chunk_size() -> u64 {
+    4 * 1024 * 1024
+}
+fn default_chunk_parallelism() -> usize {
+    4
+}
+fn default_max_object_bytes() -> u64 {
+    256 * 1024 * 1024
+}
+
+/// Cold storage tiering configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TieringConfig {
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// S3 endpoint, e.g. `http://minio:9000` (path-style requests)
+    #[serde(default)]
+    pub endpoint: String,
+
+    #[serde(default)]
+    pub bucket: String,
+
+    #[serde(default = "default_tier_region")]
+    pub region: String,
+
+    /// Credentials (`AWS_ACCESS_KEY_ID` / `AWS_SECRET_ACCESS_KEY` when empty)
+    #[serde(default)]
+    pub access_key: String,
+    #[serde(default)]
+    pub secret_key: String,
+
+    /// Prepended to the key to name its object in the bucket
+    #[serde(default = "default_tier_prefix")]
+    pub prefix: String,
+
+    /// Seconds between tiering passes
+    #[serde(default = "default_tier_interval")]
+    pub interval_secs: u64,
+
+    /// What a GET of a tiered key does
+    #[serde(default)]
+    pub on_read: TierReadMode,
+
+    /// Policy for keys whose tenant has none of its own
+    #[serde(default)]
+    pub default_policy: TierPolicy,
+
+    /// Per-tenant policies; a key belongs to the tenant named by its first
+    /// path segment (`<tenant>/...`)
+    #[serde(default)]
+    pub tenants: HashMap<String, TierPolicy>,
+}
+
+impl Default for TieringConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            endpoint: String::new(),
+            bucket: String::new(),
+            region: default_tier_region(),
+            access_key: String::new(),
+            secret_key: String::new(),
+            prefix: default_tier_prefix(),
+            interval_secs: default_tier_interval(),
+            on_read: TierReadMode::default(),
+            default_policy: TierPolicy::default(),
+            tenants: HashMap::new(),
+        }
+    }
 }
 
+impl TieringConfig {
+    /// Policy applying to `key`
+    pub fn policy_for(&self, key: &str) -> &TierPolicy {
+        key.split_once('/')
+            .and_then(|(tenant, _)| self.tenants.get(tenant))
+            .unwrap_or(&self.default_policy)
+    }
+}
+
+/// Tenant quota configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QuotaConfig {
+    /// Bytes a tenant without a quota of its own may store (0 = unlimited)
+    #[serde(default)]
+    pub storage_limit: u64,
+
+    /// Keys a tenant without a quota of its own may store (0 = unlimited)
+    #[serde(default)]
+    pub object_limit: u64,
+
+    /// Writes per second of a tenant without a quota of its own (0 = unlimited)
+    #[serde(default)]
+    pub rate_limit: u32,
+
+    /// Bytes per second a tenant without a quota of its own may upload
+    /// (0 = unlimited)
+    #[serde(default)]
+    pub ingress_limit: u64,
+
+    /// Bytes per second a tenant without a quota of its own may download
+    /// (0 = unlimited)
+    #[serde(default)]
+    pub egress_limit: u64,
+
+    /// Seconds between recomputations of tenant usage from the key metadata
+    /// (0 = only at startup)
+    #[serde(default = "default_usage_reconcile_interval")]
+    pub reconcile_interval_secs: u64,
+}
+
+impl Default for QuotaConfig {
+    fn default() -> Self {
+        Self {
+            storage_limit: 0,
+            object_limit: 0,
+            rate_limit: 0,
+            ingress_limit: 0,
+            egress_limit: 0,
+            reconcile_interval_secs: default_usage_reconcile_interval(),
+        }
+    }
+}
+
+/// When a key counts as cold
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TierPolicy {
+    /// Seconds since the last write (0 = never tier)
+    #[serde(default)]
+    pub min_age_secs: u64,
+
+    /// Seconds since the last read (0 = ignore reads)
+    #[serde(default)]
+    pub min_idle_secs: u64,
+}
+
+/// How tiered keys are read back
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TierReadMode {
+    /// Serve the value from the bucket, leaving the key tiered
+    #[default]
+    Proxy,
+    /// Serve it and write it back to the volumes
+    Rehydrate,
+}
+
+impl CoordinatorConfig {
+    /// HTTP URL clients should use to reach this coordinator
+    pub fn advertise_url(&self) -> String {
+        if let Some(addr) = &self.advertise_addr {
+            return addr.trim_end_matches('/').to_string();
+        }
+        let scheme = if self.tls_cert_path.is_some() && self.tls_key_path.is_some() {
+            "https"
+        } else {
+            "http"
+        };
+        format!("{}://{}", scheme, self.bind_addr)
+    }
+}
+
+fn default_coordinator_bind_addr() -> SocketAddr {
+    "0.0.0.0:5000".parse().unwrap()
+}
+fn default_coordinator_grpc_addr() -> SocketAddr {
+    "0.0.0.0:5001".parse().unwrap()
+}
+fn default_db_path() -> PathBuf {
+    PathBuf::from("./coord-data")
+}
 fn default_replicas() -> usize {
     3
 }
@@ -109,21 +816,105 @@ fn default_snapshot_threshold() -> u64 {
 fn default_num_shards() -> u64 {
     256
 }
+fn default_min_free_bytes() -> u64 {
+    1024 * 1024 * 1024 // 1 GB
+}
+fn default_migration_bandwidth() -> u64 {
+    50 * 1024 * 1024 // 50 MB/s
+}
+fn default_max_concurrent_transfers() -> usize {
+    4
+}
+fn default_suspect_after() -> u64 {
+    30
+}
+fn default_dead_after() -> u64 {
+    120
+}
+fn default_ready_min_volumes() -> usize {
+    1
+}
+fn default_shutdown_timeout() -> u64 {
+    30
+}
+fn default_anti_entropy_interval() -> u64 {
+    600
+}
+fn default_gc_interval() -> u64 {
+    3600
+}
+fn default_gc_grace() -> u64 {
+    3600
+}
+fn default_usage_reconcile_interval() -> u64 {
+    300
+}
+fn default_cdc_retention() -> u64 {
+    7 * 24 * 3600 // 7 days
+}
+fn default_replication_parallelism() -> usize {
+    8
+}
+fn default_tier_region() -> String {
+    "us-east-1".to_string()
+}
+fn default_tier_prefix() -> String {
+    "minikv/".to_string()
+}
+fn default_tier_interval() -> u64 {
+    3600
+}
 
 impl Default for CoordinatorConfig {
     fn default() -> Self {
         Self {
-            bind_addr: "0.0.0.0:5000".parse().unwrap(),
-            grpc_addr: "0.0.0.0:5001".parse().unwrap(),
-            db_path: PathBuf::from("./coord-data"),
+            bind_addr: default_coordinator_bind_addr(),
+            grpc_addr: default_coordinator_grpc_addr(),
+            client_grpc_addr: None,
+            resp_addr: None,
+            memcached_addr: None,
+            db_path: default_db_path(),
             peers: vec![],
             replicas: default_replicas(),
             election_timeout_ms: default_election_timeout(),
             heartbeat_interval_ms: default_heartbeat_interval(),
             snapshot_threshold: default_snapshot_threshold(),
             num_shards: default_num_shards(),
+            failure_domain: FailureDomain::default(),
+            allow_placement_violation: false,
+            min_free_bytes: default_min_free_bytes(),
+            migration_bandwidth_bytes_per_sec: default_migration_bandwidth(),
+            max_concurrent_transfers: default_max_concurrent_transfers(),
+            auto_rebalance: true,
+            suspect_after_secs: default_suspect_after(),
+            dead_after_secs: default_dead_after(),
+            ready_min_volumes: default_ready_min_volumes(),
+            shutdown_timeout_secs: default_shutdown_timeout(),
+            anti_entropy_interval_secs: default_anti_entropy_interval(),
+            gc_interval_secs: default_gc_interval(),
+            gc_grace_secs: default_gc_grace(),
             tls_cert_path: None,
             tls_key_path: None,
+            advertise_addr: None,
+            cdc: CdcConfig::default(),
+            replication: ReplicationConfig::default(),
+            tiering: TieringConfig::default(),
+            dedup: false,
+            verify_on_read: false,
+            chunking: ChunkingConfig::default(),
+            redirect: RedirectConfig::default(),
+            hotkeys: HotKeysConfig::default(),
+            fault_injection: false,
+            plugins: vec![],
+            webhooks: WebhooksConfig::default(),
+            auth: AuthConfig::default(),
+            quota: QuotaConfig::default(),
+            rate_limit: RateLimitConfig::default(),
+            write_budget: WriteBudgetConfig::default(),
+            ip_filter: IpFilterConfig::default(),
+            grpc_tls: GrpcTlsConfig::default(),
+            grpc_auth: GrpcAuthConfig::default(),
+            audit: AuditConfig::default(),
         }
     }
 }
@@ -132,18 +923,31 @@ impl Default for CoordinatorConfig {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct VolumeConfig {
     /// Bind address for HTTP API
+    #[serde(default = "default_volume_bind_addr")]
     pub bind_addr: SocketAddr,
 
     /// Bind address for internal gRPC
+    #[serde(default = "default_volume_grpc_addr")]
     pub grpc_addr: SocketAddr,
 
-    /// Data directory for blobs
-    pub data_path: PathBuf,
+    /// Data directory for blobs, or a list of them on different disks
+    #[serde(default = "default_data_path")]
+    pub data_path: DataPaths,
 
     /// WAL directory
+    #[serde(default = "default_wal_path")]
     pub wal_path: PathBuf,
 
+    /// HTTP URL the coordinators hand to clients (default: from `bind_addr`)
+    #[serde(default)]
+    pub advertise_addr: Option<String>,
+
+    /// gRPC URL the coordinators call (default: from `grpc_addr`)
+    #[serde(default)]
+    pub grpc_advertise_addr: Option<String>,
+
     /// Coordinator addresses
+    #[serde(default = "default_coordinators")]
     pub coordinators: Vec<String>,
 
     /// Max blob size (bytes)
@@ -154,10 +958,27 @@ pub struct VolumeConfig {
     #[serde(default = "default_compaction_interval")]
     pub compaction_interval_secs: u64,
 
-    /// Compaction threshold (segments)
+    /// Compaction threshold: most segments compacted per background pass
+    /// (0 = no limit)
     #[serde(default = "default_compaction_threshold")]
     pub compaction_threshold: usize,
 
+    /// Share of dead bytes that gets a segment compacted
+    #[serde(default = "default_compaction_dead_ratio")]
+    pub compaction_dead_ratio: f64,
+
+    /// Pace of the compaction copy (0 = unthrottled)
+    #[serde(default)]
+    pub compaction_max_bytes_per_sec: u64,
+
+    /// Between background scrubs of all segments (0 = never scrub)
+    #[serde(default = "default_scrub_interval")]
+    pub scrub_interval_secs: u64,
+
+    /// Pace of the scrub reads (0 = unthrottled)
+    #[serde(default = "default_scrub_max_bytes_per_sec")]
+    pub scrub_max_bytes_per_sec: u64,
+
     /// Heartbeat interval
     #[serde(default = "default_volume_heartbeat")]
     pub heartbeat_interval_secs: u64,
@@ -170,20 +991,197 @@ pub struct VolumeConfig {
     #[serde(default = "default_true")]
     pub enable_snapshots: bool,
 
+    /// Between index snapshots, taken when keys changed (0 = only on
+    /// compaction and on request)
+    #[serde(default = "default_snapshot_interval")]
+    pub snapshot_interval_secs: u64,
+
+    /// Seconds a shutdown may take, draining requests included (see
+    /// `common::shutdown`)
+    #[serde(default = "default_shutdown_timeout")]
+    pub shutdown_timeout_secs: u64,
+
     /// WAL sync policy
     #[serde(default)]
     pub wal_sync: WalSyncPolicy,
+
+    /// Batching of fsyncs with `wal_sync = "group"`
+    #[serde(default)]
+    pub wal_group_commit: GroupCommitConfig,
+
+    /// Blob store operations issued by the async request handlers
+    #[serde(default)]
+    pub io: StoreIoConfig,
+
+    /// Writes buffered at once by the HTTP API and the prepare phase
+    #[serde(default)]
+    pub write_budget: WriteBudgetConfig,
+
+    /// Per-client-IP rate limiting of the HTTP API
+    #[serde(default)]
+    pub rate_limit: RateLimitConfig,
+
+    /// Cache of recently read blobs
+    #[serde(default)]
+    pub cache: BlobCacheConfig,
+
+    /// Read segments through memory maps instead of a file read per request
+    #[serde(default)]
+    pub mmap_reads: bool,
+
+    /// Size past which appends move on to a new segment
+    #[serde(default = "default_segment_size")]
+    pub segment_size_bytes: u64,
+
+    /// Most segments a volume may have (0 = no limit); writes past it fail
+    /// as storage full until compaction reclaims space
+    #[serde(default)]
+    pub max_segments: u64,
+
+    /// Free space below which the volume turns read-only, refusing writes
+    /// as storage full until it has 10% more again (0 = never)
+    #[serde(default = "default_volume_min_free_bytes")]
+    pub min_free_bytes: u64,
+
+    /// Availability zone label, used by zone-aware placement
+    #[serde(default)]
+    pub zone: Option<String>,
+
+    /// Rack label, used by rack-aware placement
+    #[serde(default)]
+    pub rack: Option<String>,
+
+    /// Placement weight; defaults to the volume's free space
+    #[serde(default)]
+    pub weight: Option<f64>,
+
+    /// Compression of newly written blobs
+    #[serde(default)]
+    pub compression: CompressionConfig,
+
+    /// Encryption at rest of blobs, WAL entries and index snapshots
+    #[serde(default)]
+    pub encryption: EncryptionConfig,
+
+    /// Mutual TLS between coordinators and volumes
+    #[serde(default)]
+    pub grpc_tls: GrpcTlsConfig,
+
+    /// Tokens internal gRPC calls must carry
+    #[serde(default)]
+    pub grpc_auth: GrpcAuthConfig,
+
+    /// Allow faults to be injected through `PUT /admin/chaos`, for tests
+    #[serde(default)]
+    pub fault_injection: bool,
 }
 
+/// Blob compression on a volume.
+/// Each record notes how it was stored, so changing the algorithm only
+/// affects new writes (and blobs rewritten by compaction).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CompressionConfig {
+    /// Algorithm for new blobs
+    #[serde(default)]
+    pub algorithm: CompressionMode,
+    /// Blobs smaller than this many bytes are stored as is
+    #[serde(default = "default_compression_min_size")]
+    pub min_size: usize,
+    /// zstd compression level (1-22)
+    #[serde(default = "default_zstd_level")]
+    pub zstd_level: i32,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            algorithm: CompressionMode::default(),
+            min_size: default_compression_min_size(),
+            zstd_level: default_zstd_level(),
+        }
+    }
+}
+
+/// Compression algorithm for blobs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum CompressionMode {
+    /// No compression
+    #[default]
+    None,
+    /// LZ4 compression (fast)
+    Lz4,
+    /// zstd compression (better ratio)
+    Zstd,
+}
+
+fn default_compression_min_size() -> usize {
+    128
+}
+fn default_zstd_level() -> i32 {
+    3
+}
+
+impl VolumeConfig {
+    /// HTTP URL clients should use to reach this volume
+    pub fn advertise_url(&self) -> String {
+        match &self.advertise_addr {
+            Some(addr) => addr.trim_end_matches('/').to_string(),
+            None => format!("http://{}", self.bind_addr),
+        }
+    }
+
+    /// gRPC URL the coordinators should use to reach this volume
+    pub fn grpc_advertise_url(&self) -> String {
+        match &self.grpc_advertise_addr {
+            Some(addr) => addr.trim_end_matches('/').to_string(),
+            None => format!("http://{}", self.grpc_addr),
+        }
+    }
+}
+
+fn default_volume_bind_addr() -> SocketAddr {
+    "0.0.0.0:6000".parse().unwrap()
+}
+fn default_volume_grpc_addr() -> SocketAddr {
+    "0.0.0.0:6001".parse().unwrap()
+}
+fn default_data_path() -> DataPaths {
+    PathBuf::from("./vol-data").into()
+}
+fn default_wal_path() -> PathBuf {
+    PathBuf::from("./vol-wal")
+}
+fn default_coordinators() -> Vec<String> {
+    vec!["http://localhost:5000".to_string()]
+}
 fn default_max_blob_size() -> u64 {
     1024 * 1024 * 1024 // 1 GB
 }
 fn default_compaction_interval() -> u64 {
     300 // 5 minutes
 }
+fn default_segment_size() -> u64 {
+    64 * 1024 * 1024 // 64 MB
+}
+fn default_volume_min_free_bytes() -> u64 {
+    256 * 1024 * 1024 // 256 MB, below the coordinator's floor
+}
 fn default_compaction_threshold() -> usize {
     10
 }
+fn default_compaction_dead_ratio() -> f64 {
+    0.5
+}
+fn default_scrub_interval() -> u64 {
+    3600
+}
+fn default_scrub_max_bytes_per_sec() -> u64 {
+    8 * 1024 * 1024
+}
+fn default_snapshot_interval() -> u64 {
+    300
+}
 fn default_volume_heartbeat() -> u64 {
     10
 }
@@ -201,23 +1199,208 @@ pub enum WalSyncPolicy {
     Interval,
     /// Never fsync (fastest, least durable)
     Never,
+    /// Batch concurrent writes into one fsync (see `GroupCommitConfig`);
+    /// writers still wait for their entry to be on disk
+    Group,
+}
+
+/// Group commit of the WAL: a background fsync covers every entry appended
+/// since the previous one, once `max_batch` entries are pending or
+/// `interval_ms` after the first of them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GroupCommitConfig {
+    /// Entries pending before an fsync is issued right away
+    #[serde(default = "default_group_commit_batch")]
+    pub max_batch: usize,
+    /// Longest wait, in milliseconds, for a batch to fill up
+    #[serde(default = "default_group_commit_interval")]
+    pub interval_ms: u64,
+}
+
+impl Default for GroupCommitConfig {
+    fn default() -> Self {
+        Self {
+            max_batch: default_group_commit_batch(),
+            interval_ms: default_group_commit_interval(),
+        }
+    }
+}
+
+fn default_group_commit_batch() -> usize {
+    64
+}
+fn default_group_commit_interval() -> u64 {
+    2
+}
+
+/// Blob store I/O from async handlers: each operation runs on the blocking
+/// pool, at most `max_in_flight` at a time, and fails after `timeout_ms`
+/// (waiting for a slot included)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StoreIoConfig {
+    /// Operations running at once; further ones wait for a slot
+    #[serde(default = "default_store_io_in_flight")]
+    pub max_in_flight: usize,
+    /// Deadline of an operation, in milliseconds
+    #[serde(default = "default_store_io_timeout")]
+    pub timeout_ms: u64,
+}
+
+impl Default for StoreIoConfig {
+    fn default() -> Self {
+        Self {
+            max_in_flight: default_store_io_in_flight(),
+            timeout_ms: default_store_io_timeout(),
+        }
+    }
+}
+
+fn default_store_io_in_flight() -> usize {
+    64
+}
+fn default_store_io_timeout() -> u64 {
+    30_000
+}
+
+/// Writes a node holds in memory at once: past either bound, writes are
+/// refused with `503` and `Retry-After` (see `common::write_budget`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WriteBudgetConfig {
+    /// Writes in flight (0 = no limit)
+    #[serde(default = "default_write_budget_writes")]
+    pub max_writes: usize,
+    /// Bytes of the values of writes in flight (0 = no limit)
+    #[serde(default = "default_write_budget_bytes")]
+    pub max_bytes: u64,
+    /// `Retry-After` of a refused write, in seconds
+    #[serde(default = "default_write_budget_retry_after")]
+    pub retry_after_secs: u64,
+}
+
+impl Default for WriteBudgetConfig {
+    fn default() -> Self {
+        Self {
+            max_writes: default_write_budget_writes(),
+            max_bytes: default_write_budget_bytes(),
+            retry_after_secs: default_write_budget_retry_after(),
+        }
+    }
+}
+
+fn default_write_budget_writes() -> usize {
+    1024
+}
+fn default_write_budget_bytes() -> u64 {
+    1024 * 1024 * 1024
+}
+fn default_write_budget_retry_after() -> u64 {
+    1
+}
+
+/// LRU cache of recently read blobs on a volume
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlobCacheConfig {
+    /// Total size of the cached values; 0 disables the cache
+    #[serde(default = "default_blob_cache_capacity")]
+    pub capacity_bytes: u64,
+    /// Larger blobs are never cached
+    #[serde(default = "default_blob_cache_max_entry")]
+    pub max_entry_bytes: u64,
+}
+
+impl Default for BlobCacheConfig {
+    fn default() -> Self {
+        Self {
+            capacity_bytes: default_blob_cache_capacity(),
+            max_entry_bytes: default_blob_cache_max_entry(),
+        }
+    }
+}
+
+fn default_blob_cache_capacity() -> u64 {
+    64 * 1024 * 1024
+}
+fn default_blob_cache_max_entry() -> u64 {
+    1024 * 1024
+}
+
+/// Data directories of a volume: one path, or a list whose first entry is
+/// the primary directory (see `crate::volume::disks`)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum DataPaths {
+    One(PathBuf),
+    Many(Vec<PathBuf>),
+}
+
+impl DataPaths {
+    pub fn dirs(&self) -> Vec<PathBuf> {
+        match self {
+            DataPaths::One(path) => vec![path.clone()],
+            DataPaths::Many(paths) => paths.clone(),
+        }
+    }
+}
+
+impl From<PathBuf> for DataPaths {
+    fn from(path: PathBuf) -> Self {
+        DataPaths::One(path)
+    }
+}
+
+/// Failure domain used to spread the replicas of a key
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum FailureDomain {
+    /// No constraint beyond distinct volumes
+    None,
+    /// Replicas on distinct racks
+    Rack,
+    /// Replicas in distinct zones
+    #[default]
+    Zone,
 }
 
 impl Default for VolumeConfig {
     fn default() -> Self {
         Self {
-            bind_addr: "0.0.0.0:6000".parse().unwrap(),
-            grpc_addr: "0.0.0.0:6001".parse().unwrap(),
-            data_path: PathBuf::from("./vol-data"),
-            wal_path: PathBuf::from("./vol-wal"),
-            coordinators: vec!["http://localhost:5000".to_string()],
+            bind_addr: default_volume_bind_addr(),
+            grpc_addr: default_volume_grpc_addr(),
+            data_path: default_data_path(),
+            wal_path: default_wal_path(),
+            advertise_addr: None,
+            grpc_advertise_addr: None,
+            coordinators: default_coordinators(),
             max_blob_size: default_max_blob_size(),
             compaction_interval_secs: default_compaction_interval(),
             compaction_threshold: default_compaction_threshold(),
+            compaction_dead_ratio: default_compaction_dead_ratio(),
+            compaction_max_bytes_per_sec: 0,
+            scrub_interval_secs: default_scrub_interval(),
+            scrub_max_bytes_per_sec: default_scrub_max_bytes_per_sec(),
             heartbeat_interval_secs: default_volume_heartbeat(),
             enable_bloom: true,
             enable_snapshots: true,
+            snapshot_interval_secs: default_snapshot_interval(),
+            shutdown_timeout_secs: default_shutdown_timeout(),
             wal_sync: WalSyncPolicy::default(),
+            wal_group_commit: GroupCommitConfig::default(),
+            io: StoreIoConfig::default(),
+            write_budget: WriteBudgetConfig::default(),
+            rate_limit: RateLimitConfig::default(),
+            cache: BlobCacheConfig::default(),
+            mmap_reads: false,
+            segment_size_bytes: default_segment_size(),
+            max_segments: 0,
+            min_free_bytes: default_volume_min_free_bytes(),
+            zone: None,
+            rack: None,
+            weight: None,
+            compression: CompressionConfig::default(),
+            encryption: EncryptionConfig::default(),
+            grpc_tls: GrpcTlsConfig::default(),
+            grpc_auth: GrpcAuthConfig::default(),
+            fault_injection: false,
         }
     }
 }
@@ -254,24 +1437,37 @@ impl Default for RuntimeConfig {
 }
 
 impl Config {
-    /// Load from file
+    /// Load from file alone, TOML or JSON after its extension
     #[allow(clippy::result_large_err)]
     pub fn from_file(path: impl AsRef<std::path::Path>) -> crate::Result<Self> {
-        let content = std::fs::read_to_string(path)?;
-        let config: Config = serde_json::from_str(&content)
-            .map_err(|e| crate::Error::Other(format!("Failed to parse config: {}", e)))?;
-        Ok(config)
+        config::Config::builder()
+            .add_source(config::File::from(path.as_ref()))
+            .build()
+            .and_then(|s| s.try_deserialize())
+            .map_err(|e| crate::Error::InvalidConfig(e.to_string()))
     }
 
-    /// Save to file
+    /// Save to file, as JSON if its extension is `json` and TOML otherwise
     #[allow(clippy::result_large_err)]
     pub fn to_file(&self, path: impl AsRef<std::path::Path>) -> crate::Result<()> {
-        let content = serde_json::to_string_pretty(self)
-            .map_err(|e| crate::Error::Other(format!("Failed to serialize config: {}", e)))?;
+        let path = path.as_ref();
+        let content = if path.extension().and_then(|ext| ext.to_str()) == Some("json") {
+            serde_json::to_string_pretty(self)
+                .map_err(|e| crate::Error::Other(format!("Failed to serialize config: {}", e)))?
+        } else {
+            self.to_toml()?
+        };
         std::fs::write(path, content)?;
         Ok(())
     }
 
+    /// The configuration as a TOML file would hold it
+    #[allow(clippy::result_large_err)]
+    pub fn to_toml(&self) -> crate::Result<String> {
+        toml::to_string_pretty(self)
+            .map_err(|e| crate::Error::Other(format!("Failed to serialize config: {}", e)))
+    }
+
     /// Validate configuration
     #[allow(clippy::result_large_err)]
     pub fn validate(&self) -> crate::Result<()> {
@@ -281,10 +1477,15 @@ impl Config {
 
         match self.role {
             NodeRole::Coordinator => {
-                if self.coordinator.is_none() {
+                let Some(coordinator) = &self.coordinator else {
                     return Err(crate::Error::InvalidConfig(
                         "coordinator config required".into(),
                     ));
+                };
+                if coordinator.redirect.enabled && !coordinator.grpc_auth.enabled {
+                    return Err(crate::Error::InvalidConfig(
+                        "redirect needs grpc_auth enabled to sign transfer URLs".into(),
+                    ));
                 }
             }
             NodeRole::Volume => {
@@ -297,3 +1498,96 @@ impl Config {
         Ok(())
     }
 }
+
+/// The `MINIKV_*` environment variables, as configuration keys
+fn environment() -> config::Environment {
+    config::Environment::with_prefix("MINIKV")
+        .prefix_separator("_")
+        .separator("__")
+        .try_parsing(true)
+        .list_separator(",")
+        .with_list_parse_key("coordinator.peers")
+        .with_list_parse_key("volume.coordinators")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn environment_of(vars: &[(&str, &str)]) -> config::Environment {
+        environment().source(Some(
+            vars.iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        ))
+    }
+
+    #[test]
+    fn test_layers() {
+        let dir = tempdir().unwrap();
+        let file = dir.path().join("node.toml");
+        std::fs::write(
+            &file,
+            "node_id = 'from-file'\n\
+             [coordinator]\n\
+             replicas = 5\n\
+             db_path = '/file/db'\n\
+             bind_addr = '127.0.0.1:7000'\n",
+        )
+        .unwrap();
+        let sources = ConfigSources::default()
+            .with_file(Some(file))
+            .with_default("role", "coordinator")
+            .with_default("coordinator.replicas", 1i64)
+            .with_flag("coordinator.bind_addr", Some("127.0.0.1:9000"))
+            .with_flag("coordinator.grpc_addr", None::<String>);
+        let env = environment_of(&[
+            ("MINIKV_COORDINATOR__DB_PATH", "/env/db"),
+            ("MINIKV_COORDINATOR__PEERS", "http://a:5001,http://b:5001"),
+            ("MINIKV_COORDINATOR__BIND_ADDR", "127.0.0.1:8000"),
+            ("MINIKV_LOG_LEVEL", "debug"),
+        ]);
+
+        let config = sources.load_with(env).unwrap();
+        assert_eq!(config.node_id, "from-file");
+        assert_eq!(config.role, NodeRole::Coordinator);
+        assert_eq!(config.logging.log_level, "debug");
+        let coordinator = config.coordinator.unwrap();
+        assert_eq!(coordinator.replicas, 5);
+        assert_eq!(coordinator.db_path, PathBuf::from("/env/db"));
+        assert_eq!(coordinator.peers, ["http://a:5001", "http://b:5001"]);
+        assert_eq!(coordinator.bind_addr, "127.0.0.1:9000".parse().unwrap());
+        // Unset everywhere
+        assert_eq!(coordinator.grpc_addr, default_coordinator_grpc_addr());
+
+        let missing = ConfigSources::default().with_file(Some(dir.path().join("missing.toml")));
+        assert!(matches!(
+            missing.load_with(environment_of(&[])),
+            Err(crate::Error::InvalidConfig(_))
+        ));
+    }
+
+    #[test]
+    fn test_file_round_trip() {
+        let dir = tempdir().unwrap();
+        let config = Config {
+            node_id: "vol-1".to_string(),
+            role: NodeRole::Volume,
+            coordinator: None,
+            volume: Some(VolumeConfig::default()),
+            logging: Default::default(),
+            pid_file: None,
+        };
+        for name in ["node.toml", "node.json"] {
+            let path = dir.path().join(name);
+            config.to_file(&path).unwrap();
+            let read = Config::from_file(&path).unwrap();
+            assert_eq!(read.node_id, "vol-1");
+            assert_eq!(
+                read.volume.unwrap().wal_path,
+                VolumeConfig::default().wal_path
+            );
+        }
+    }
+}
@@ -7,16 +7,27 @@
 //!
 //! The encryption is designed to be transparent to the application layer,
 //! encrypting data before storage and decrypting on retrieval.
+//!
+//! Encrypted data carries the version of the master key that sealed it, so
+//! the master key can be rotated: the new key encrypts new data while the
+//! previous ones keep decrypting the old data until it is re-encrypted.
+//!
+//! Master keys can be kept out of the config and fetched from a
+//! [`KeyProvider`]: an environment variable, a file, HashiCorp Vault or AWS
+//! KMS (see [`EncryptionConfig::resolve_keys`]).
 
 use aes_gcm::{
     aead::{Aead, KeyInit},
     Aes256Gcm, Nonce,
 };
 use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use futures_util::future::BoxFuture;
 use once_cell::sync::Lazy;
 use rand::RngCore;
 use serde::{Deserialize, Serialize};
 use sha2::Sha256;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
 use std::sync::RwLock;
 
 /// Size of AES-256 key in bytes
@@ -30,20 +41,47 @@ const TAG_SIZE: usize = 16;
 
 /// Magic bytes to identify encrypted data
 const ENCRYPTION_MAGIC: &[u8] = b"MKVENC01";
+/// Magic bytes of encrypted data followed by the version of its master key;
+/// written since key rotation, the magic above is only read
+const ENCRYPTION_MAGIC_VERSIONED: &[u8] = b"MKVENC02";
 
 /// Global encryption manager
 pub static ENCRYPTION_MANAGER: Lazy<RwLock<EncryptionManager>> =
     Lazy::new(|| RwLock::new(EncryptionManager::new()));
 
+/// Environment variable holding the master key when the config has none
+pub const MASTER_KEY_ENV: &str = "MINIKV_MASTER_KEY";
+
 /// Encryption configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct EncryptionConfig {
     /// Whether encryption is enabled
     pub enabled: bool,
-    /// Master key (base64 encoded)
+    /// Master key (base64 encoded); fetched from `key_provider` when one is
+    /// set, and falls back to `MINIKV_MASTER_KEY`
     pub master_key: Option<String>,
+    /// External source of the master key
+    pub key_provider: Option<KeyProviderConfig>,
     /// Key derivation info for different contexts
     pub key_contexts: Vec<String>,
+    /// Version of `master_key`, written with everything it encrypts
+    pub key_version: u8,
+    /// Retired master keys, still used to decrypt data they encrypted
+    pub previous_keys: Vec<VersionedKey>,
+}
+
+/// A retired master key and its version
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VersionedKey {
+    /// Version the key was current as
+    pub version: u8,
+    /// Master key (base64 encoded)
+    #[serde(default)]
+    pub key: String,
+    /// External source of the key, instead of `key`
+    #[serde(default)]
+    pub provider: Option<KeyProviderConfig>,
 }
 
 impl Default for EncryptionConfig {
@@ -51,11 +89,14 @@ impl Default for EncryptionConfig {
         Self {
             enabled: false,
             master_key: None,
+            key_provider: None,
             key_contexts: vec![
                 "minikv-data".to_string(),
                 "minikv-wal".to_string(),
                 "minikv-index".to_string(),
             ],
+            key_version: 1,
+            previous_keys: Vec::new(),
         }
     }
 }
@@ -78,6 +119,8 @@ pub enum EncryptionError {
     InvalidFormat(String),
     /// Key derivation failed
     KeyDerivationFailed(String),
+    /// A key provider couldn't supply its key
+    KeyProvider(String),
 }
 
 impl std::fmt::Display for EncryptionError {
@@ -93,12 +136,302 @@ impl std::fmt::Display for EncryptionError {
             EncryptionError::KeyDerivationFailed(msg) => {
                 write!(f, "Key derivation failed: {}", msg)
             }
+            EncryptionError::KeyProvider(msg) => write!(f, "Key provider failed: {}", msg),
         }
     }
 }
 
 impl std::error::Error for EncryptionError {}
 
+/// Where a master key is fetched from instead of being stored in the config
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum KeyProviderConfig {
+    /// An environment variable holding the base64 key
+    Env { var: String },
+    /// A file holding the base64 key (surrounding whitespace is ignored)
+    File { path: PathBuf },
+    /// A field of a HashiCorp Vault KV secret (v1 or v2), read over the HTTP
+    /// API with `token` or `VAULT_TOKEN`
+    Vault {
+        addr: String,
+        #[serde(default)]
+        token: Option<String>,
+        /// Secret path, e.g. `secret/data/minikv` for KV v2
+        path: String,
+        #[serde(default = "default_vault_field")]
+        field: String,
+    },
+    /// A data key wrapped by AWS KMS (envelope encryption): `ciphertext_blob`
+    /// is the base64 `CiphertextBlob` of `aws kms generate-data-key`, and
+    /// KMS `Decrypt` unwraps it into the master key. Credentials fall back to
+    /// the usual AWS environment variables.
+    AwsKms {
+        region: String,
+        ciphertext_blob: String,
+        /// Defaults to `https://kms.<region>.amazonaws.com`
+        #[serde(default)]
+        endpoint: Option<String>,
+        #[serde(default)]
+        access_key: String,
+        #[serde(default)]
+        secret_key: String,
+    },
+}
+
+fn default_vault_field() -> String {
+    "key".to_string()
+}
+
+impl KeyProviderConfig {
+    /// The provider this configuration describes
+    pub fn build(&self) -> Box<dyn KeyProvider> {
+        match self.clone() {
+            KeyProviderConfig::Env { var } => Box::new(EnvKeyProvider { var }),
+            KeyProviderConfig::File { path } => Box::new(FileKeyProvider { path }),
+            KeyProviderConfig::Vault {
+                addr,
+                token,
+                path,
+                field,
+            } => Box::new(VaultKeyProvider {
+                addr,
+                token,
+                path,
+                field,
+            }),
+            KeyProviderConfig::AwsKms {
+                region,
+                ciphertext_blob,
+                endpoint,
+                access_key,
+                secret_key,
+            } => Box::new(AwsKmsKeyProvider {
+                region,
+                ciphertext_blob,
+                endpoint,
+                access_key,
+                secret_key,
+            }),
+        }
+    }
+}
+
+/// Source of a base64 master key
+///
+/// The built-in providers are described by [`KeyProviderConfig`]; other
+/// sources implement this trait and have their key set as `master_key`
+/// before `EncryptionManager::configure`.
+pub trait KeyProvider: Send + Sync {
+    /// Short name of the provider, for errors and logs
+    fn name(&self) -> &'static str;
+
+    /// Fetch the master key, base64 encoded
+    fn master_key(&self) -> BoxFuture<'_, EncryptionResult<String>>;
+}
+
+/// Master key from an environment variable
+pub struct EnvKeyProvider {
+    pub var: String,
+}
+
+impl KeyProvider for EnvKeyProvider {
+    fn name(&self) -> &'static str {
+        "env"
+    }
+
+    fn master_key(&self) -> BoxFuture<'_, EncryptionResult<String>> {
+        Box::pin(async move {
+            std::env::var(&self.var).map_err(|_| {
+                EncryptionError::KeyProvider(format!(
+                    "environment variable {} is not set",
+                    self.var
+                ))
+            })
+        })
+    }
+}
+
+/// Master key from a file
+pub struct FileKeyProvider {
+    pub path: PathBuf,
+}
+
+impl KeyProvider for FileKeyProvider {
+    fn name(&self) -> &'static str {
+        "file"
+    }
+
+    fn master_key(&self) -> BoxFuture<'_, EncryptionResult<String>> {
+        Box::pin(async move {
+            let key = tokio::fs::read_to_string(&self.path).await.map_err(|e| {
+                EncryptionError::KeyProvider(format!("{}: {}", self.path.display(), e))
+            })?;
+            Ok(key.trim().to_string())
+        })
+    }
+}
+
+/// Master key from a HashiCorp Vault KV secret
+pub struct VaultKeyProvider {
+    pub addr: String,
+    pub token: Option<String>,
+    pub path: String,
+    pub field: String,
+}
+
+impl KeyProvider for VaultKeyProvider {
+    fn name(&self) -> &'static str {
+        "vault"
+    }
+
+    fn master_key(&self) -> BoxFuture<'_, EncryptionResult<String>> {
+        Box::pin(async move {
+            let error = |msg: String| EncryptionError::KeyProvider(format!("vault: {}", msg));
+            let token = match &self.token {
+                Some(token) => token.clone(),
+                None => std::env::var("VAULT_TOKEN")
+                    .map_err(|_| error("no token configured and VAULT_TOKEN is not set".into()))?,
+            };
+            let url = format!(
+                "{}/v1/{}",
+                self.addr.trim_end_matches('/'),
+                self.path.trim_start_matches('/')
+            );
+            let body: serde_json::Value = provider_client()
+                .get(&url)
+                .header("X-Vault-Token", token)
+                .send()
+                .await
+                .and_then(|resp| resp.error_for_status())
+                .map_err(|e| error(e.to_string()))?
+                .json()
+                .await
+                .map_err(|e| error(e.to_string()))?;
+
+            // KV v2 nests the secret under data.data, KV v1 under data
+            let data = &body["data"];
+            let secret = if data["data"].is_object() {
+                &data["data"]
+            } else {
+                data
+            };
+            secret[&self.field]
+                .as_str()
+                .map(str::to_string)
+                .ok_or_else(|| error(format!("no field {} in {}", self.field, self.path)))
+        })
+    }
+}
+
+/// Master key unwrapped by AWS KMS from an encrypted data key
+pub struct AwsKmsKeyProvider {
+    pub region: String,
+    pub ciphertext_blob: String,
+    pub endpoint: Option<String>,
+    pub access_key: String,
+    pub secret_key: String,
+}
+
+impl KeyProvider for AwsKmsKeyProvider {
+    fn name(&self) -> &'static str {
+        "aws_kms"
+    }
+
+    fn master_key(&self) -> BoxFuture<'_, EncryptionResult<String>> {
+        Box::pin(async move {
+            use crate::common::s3_client::{sigv4_authorization, SigV4Credentials};
+            use sha2::Digest;
+
+            let error = |msg: String| EncryptionError::KeyProvider(format!("aws kms: {}", msg));
+            let env_or = |value: &str, var: &str| {
+                if value.is_empty() {
+                    std::env::var(var).unwrap_or_default()
+                } else {
+                    value.to_string()
+                }
+            };
+            let access_key = env_or(&self.access_key, "AWS_ACCESS_KEY_ID");
+            let secret_key = env_or(&self.secret_key, "AWS_SECRET_ACCESS_KEY");
+            let endpoint = self
+                .endpoint
+                .clone()
+                .unwrap_or_else(|| format!("https://kms.{}.amazonaws.com", self.region));
+            let url = reqwest::Url::parse(&endpoint).map_err(|e| error(e.to_string()))?;
+            let host = match (url.host_str(), url.port()) {
+                (Some(host), Some(port)) => format!("{}:{}", host, port),
+                (Some(host), None) => host.to_string(),
+                (None, _) => return Err(error(format!("endpoint has no host: {}", endpoint))),
+            };
+
+            let body = serde_json::json!({ "CiphertextBlob": self.ciphertext_blob }).to_string();
+            let amz_date = chrono::Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+            let payload_hash = hex::encode(Sha256::digest(body.as_bytes()));
+            let authorization = sigv4_authorization(
+                &SigV4Credentials {
+                    access_key: &access_key,
+                    secret_key: &secret_key,
+                    region: &self.region,
+                    service: "kms",
+                },
+                "POST",
+                "/",
+                &host,
+                &amz_date,
+                &payload_hash,
+            );
+
+            let resp: serde_json::Value = provider_client()
+                .post(url)
+                .header("content-type", "application/x-amz-json-1.1")
+                .header("x-amz-target", "TrentService.Decrypt")
+                .header("x-amz-date", &amz_date)
+                .header("x-amz-content-sha256", &payload_hash)
+                .header("authorization", authorization)
+                .body(body)
+                .send()
+                .await
+                .and_then(|resp| resp.error_for_status())
+                .map_err(|e| error(e.to_string()))?
+                .json()
+                .await
+                .map_err(|e| error(e.to_string()))?;
+            resp["Plaintext"]
+                .as_str()
+                .map(str::to_string)
+                .ok_or_else(|| error("no Plaintext in the Decrypt response".into()))
+        })
+    }
+}
+
+/// HTTP client for the remote key providers
+fn provider_client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .timeout(std::time::Duration::from_secs(10))
+        .build()
+        .unwrap_or_default()
+}
+
+impl EncryptionConfig {
+    /// This configuration with the keys of its providers fetched into
+    /// `master_key` and `previous_keys`, ready for `EncryptionManager::configure`
+    pub async fn resolve_keys(&self) -> EncryptionResult<Self> {
+        let mut resolved = self.clone();
+        if !self.enabled {
+            return Ok(resolved);
+        }
+        if let Some(provider) = &self.key_provider {
+            resolved.master_key = Some(provider.build().master_key().await?);
+        }
+        for previous in &mut resolved.previous_keys {
+            if let Some(provider) = &previous.provider {
+                previous.key = provider.build().master_key().await?;
+            }
+        }
+        Ok(resolved)
+    }
+}
+
 /// Encrypted data wrapper with metadata
 #[derive(Debug, Clone)]
 pub struct EncryptedData {
@@ -106,14 +439,24 @@ pub struct EncryptedData {
     pub nonce: [u8; NONCE_SIZE],
     /// Ciphertext with authentication tag
     pub ciphertext: Vec<u8>,
+    /// Version of the master key it was encrypted with; `None` for data
+    /// written before key versioning
+    pub key_version: Option<u8>,
 }
 
 impl EncryptedData {
-    /// Serialize to bytes: MAGIC || NONCE || CIPHERTEXT
+    /// Serialize to bytes: MAGIC || VERSION || NONCE || CIPHERTEXT, without
+    /// the version byte for unversioned data
     pub fn to_bytes(&self) -> Vec<u8> {
         let mut bytes =
-            Vec::with_capacity(ENCRYPTION_MAGIC.len() + NONCE_SIZE + self.ciphertext.len());
-        bytes.extend_from_slice(ENCRYPTION_MAGIC);
+            Vec::with_capacity(ENCRYPTION_MAGIC.len() + 1 + NONCE_SIZE + self.ciphertext.len());
+        match self.key_version {
+            Some(version) => {
+                bytes.extend_from_slice(ENCRYPTION_MAGIC_VERSIONED);
+                bytes.push(version);
+            }
+            None => bytes.extend_from_slice(ENCRYPTION_MAGIC),
+        }
         bytes.extend_from_slice(&self.nonce);
         bytes.extend_from_slice(&self.ciphertext);
         bytes
@@ -131,13 +474,24 @@ impl EncryptedData {
         }
 
         // Verify magic bytes
-        if &bytes[..ENCRYPTION_MAGIC.len()] != ENCRYPTION_MAGIC {
+        let magic = &bytes[..ENCRYPTION_MAGIC.len()];
+        let (key_version, nonce_start) = if magic == ENCRYPTION_MAGIC_VERSIONED {
+            if bytes.len() < min_size + 1 {
+                return Err(EncryptionError::InvalidFormat(format!(
+                    "Data too short: {} bytes, minimum {} bytes",
+                    bytes.len(),
+                    min_size + 1
+                )));
+            }
+            (Some(bytes[magic.len()]), magic.len() + 1)
+        } else if magic == ENCRYPTION_MAGIC {
+            (None, magic.len())
+        } else {
             return Err(EncryptionError::InvalidFormat(
                 "Invalid magic bytes - data may not be encrypted".to_string(),
             ));
-        }
+        };
 
-        let nonce_start = ENCRYPTION_MAGIC.len();
         let ciphertext_start = nonce_start + NONCE_SIZE;
 
         let mut nonce = [0u8; NONCE_SIZE];
@@ -145,17 +499,68 @@ impl EncryptedData {
 
         let ciphertext = bytes[ciphertext_start..].to_vec();
 
-        Ok(Self { nonce, ciphertext })
+        Ok(Self {
+            nonce,
+            ciphertext,
+            key_version,
+        })
     }
 
     /// Check if data appears to be encrypted (has magic bytes)
     pub fn is_encrypted(bytes: &[u8]) -> bool {
         bytes.len() >= ENCRYPTION_MAGIC.len()
-            && &bytes[..ENCRYPTION_MAGIC.len()] == ENCRYPTION_MAGIC
+            && (&bytes[..ENCRYPTION_MAGIC.len()] == ENCRYPTION_MAGIC
+                || &bytes[..ENCRYPTION_MAGIC.len()] == ENCRYPTION_MAGIC_VERSIONED)
+    }
+}
+
+/// Ciphers derived from one master key, one per context
+struct KeySet {
+    data: Aes256Gcm,
+    wal: Aes256Gcm,
+    index: Aes256Gcm,
+}
+
+impl KeySet {
+    /// Decode a base64 master key and derive its ciphers
+    fn from_master_key(
+        master_key: &str,
+    ) -> EncryptionResult<(Self, [u8; KEY_SIZE], [u8; KEY_SIZE])> {
+        let key_bytes = BASE64
+            .decode(master_key)
+            .map_err(|e| EncryptionError::InvalidKey(format!("Invalid base64: {}", e)))?;
+
+        if key_bytes.len() < 32 {
+            return Err(EncryptionError::InvalidKey(format!(
+                "Master key too short: {} bytes, minimum 32 bytes",
+                key_bytes.len()
+            )));
+        }
+
+        // Derive one key per context using HKDF
+        let data_key = EncryptionManager::derive_key(&key_bytes, b"minikv-data")?;
+        let wal_key = EncryptionManager::derive_key(&key_bytes, b"minikv-wal")?;
+        let index_key = EncryptionManager::derive_key(&key_bytes, b"minikv-index")?;
+
+        let cipher = |key: &[u8; KEY_SIZE], context: &str| {
+            Aes256Gcm::new_from_slice(key).map_err(|e| {
+                EncryptionError::InvalidKey(format!("Failed to create {} cipher: {}", context, e))
+            })
+        };
+        let keys = Self {
+            data: cipher(&data_key, "data")?,
+            wal: cipher(&wal_key, "WAL")?,
+            index: cipher(&index_key, "index")?,
+        };
+        Ok((keys, data_key, wal_key))
     }
 }
 
 /// Encryption manager for handling all encryption operations
+///
+/// New data is encrypted with the current master key and tagged with its
+/// version. Retired keys (`previous_keys`) stay loaded to decrypt what they
+/// encrypted until it has been rewritten (`minikv-volume rekey`).
 pub struct EncryptionManager {
     /// Configuration
     config: EncryptionConfig,
@@ -163,10 +568,12 @@ pub struct EncryptionManager {
     data_key: Option<[u8; KEY_SIZE]>,
     /// Derived encryption key for WAL
     wal_key: Option<[u8; KEY_SIZE]>,
-    /// Cipher instance for data
-    data_cipher: Option<Aes256Gcm>,
-    /// Cipher instance for WAL
-    wal_cipher: Option<Aes256Gcm>,
+    /// Version of the current master key
+    key_version: u8,
+    /// Ciphers of the current master key
+    current: Option<KeySet>,
+    /// Ciphers of retired master keys, by version
+    previous: BTreeMap<u8, KeySet>,
 }
 
 impl EncryptionManager {
@@ -176,44 +583,69 @@ impl EncryptionManager {
             config: EncryptionConfig::default(),
             data_key: None,
             wal_key: None,
-            data_cipher: None,
-            wal_cipher: None,
+            key_version: 1,
+            current: None,
+            previous: BTreeMap::new(),
         }
     }
 
-    /// Initialize encryption with a master key
-    pub fn initialize(&mut self, master_key: &str) -> EncryptionResult<()> {
-        let key_bytes = BASE64
-            .decode(master_key)
-            .map_err(|e| EncryptionError::InvalidKey(format!("Invalid base64: {}", e)))?;
-
-        if key_bytes.len() < 32 {
-            return Err(EncryptionError::InvalidKey(format!(
-                "Master key too short: {} bytes, minimum 32 bytes",
-                key_bytes.len()
-            )));
+    /// Apply a configuration: initialize from its master key (or
+    /// `MINIKV_MASTER_KEY`) and load its previous keys when enabled, turn
+    /// encryption off otherwise. Keys from providers must have been fetched
+    /// with `EncryptionConfig::resolve_keys` first.
+    pub fn configure(&mut self, config: &EncryptionConfig) -> EncryptionResult<()> {
+        *self = Self::new();
+        if !config.enabled {
+            return Ok(());
         }
-
-        // Derive data encryption key using HKDF
-        self.data_key = Some(Self::derive_key(&key_bytes, b"minikv-data")?);
-        self.wal_key = Some(Self::derive_key(&key_bytes, b"minikv-wal")?);
-
-        // Initialize ciphers
-        if let Some(key) = &self.data_key {
-            self.data_cipher = Some(Aes256Gcm::new_from_slice(key).map_err(|e| {
-                EncryptionError::InvalidKey(format!("Failed to create cipher: {}", e))
-            })?);
+        let master_key = match (&config.master_key, &config.key_provider) {
+            (Some(key), _) => key.clone(),
+            (None, Some(provider)) => {
+                return Err(EncryptionError::InvalidKey(format!(
+                    "the {} key provider's key wasn't fetched (EncryptionConfig::resolve_keys)",
+                    provider.build().name()
+                )))
+            }
+            (None, None) => std::env::var(MASTER_KEY_ENV).map_err(|_| {
+                EncryptionError::InvalidKey(format!(
+                    "encryption is enabled but no master key is configured (set {})",
+                    MASTER_KEY_ENV
+                ))
+            })?,
+        };
+        self.key_version = config.key_version;
+        self.initialize(&master_key)?;
+        for previous in &config.previous_keys {
+            self.add_previous_key(previous.version, &previous.key)?;
         }
+        self.config.key_contexts = config.key_contexts.clone();
+        Ok(())
+    }
 
-        if let Some(key) = &self.wal_key {
-            self.wal_cipher = Some(Aes256Gcm::new_from_slice(key).map_err(|e| {
-                EncryptionError::InvalidKey(format!("Failed to create WAL cipher: {}", e))
-            })?);
-        }
+    /// Initialize encryption with a master key
+    pub fn initialize(&mut self, master_key: &str) -> EncryptionResult<()> {
+        let (keys, data_key, wal_key) = KeySet::from_master_key(master_key)?;
+        self.data_key = Some(data_key);
+        self.wal_key = Some(wal_key);
+        self.current = Some(keys);
 
         self.config.enabled = true;
         self.config.master_key = Some(master_key.to_string());
+        self.config.key_version = self.key_version;
+
+        Ok(())
+    }
 
+    /// Load a retired master key, used only to decrypt data tagged with `version`
+    pub fn add_previous_key(&mut self, version: u8, master_key: &str) -> EncryptionResult<()> {
+        if version == self.key_version {
+            return Err(EncryptionError::InvalidKey(format!(
+                "previous key version {} is the current key version",
+                version
+            )));
+        }
+        let (keys, _, _) = KeySet::from_master_key(master_key)?;
+        self.previous.insert(version, keys);
         Ok(())
     }
 
@@ -231,15 +663,26 @@ impl EncryptionManager {
 
     /// Check if encryption is enabled
     pub fn is_enabled(&self) -> bool {
-        self.config.enabled && self.data_cipher.is_some()
+        self.config.enabled && self.current.is_some()
     }
 
-    /// Encrypt data for storage
-    pub fn encrypt(&self, plaintext: &[u8]) -> EncryptionResult<EncryptedData> {
-        let cipher = self
-            .data_cipher
-            .as_ref()
-            .ok_or(EncryptionError::NotEnabled)?;
+    /// Version of the master key new data is encrypted with
+    pub fn key_version(&self) -> u8 {
+        self.key_version
+    }
+
+    /// Whether `encrypted` was sealed with the current master key
+    pub fn is_current(&self, encrypted: &EncryptedData) -> bool {
+        encrypted.key_version == Some(self.key_version)
+    }
+
+    /// Encrypt with the current key's cipher for a context
+    fn seal(
+        &self,
+        plaintext: &[u8],
+        cipher: fn(&KeySet) -> &Aes256Gcm,
+    ) -> EncryptionResult<EncryptedData> {
+        let keys = self.current.as_ref().ok_or(EncryptionError::NotEnabled)?;
 
         // Generate random nonce
         let mut nonce_bytes = [0u8; NONCE_SIZE];
@@ -247,28 +690,62 @@ impl EncryptionManager {
         let nonce = Nonce::from_slice(&nonce_bytes);
 
         // Encrypt
-        let ciphertext = cipher
+        let ciphertext = cipher(keys)
             .encrypt(nonce, plaintext)
             .map_err(|e| EncryptionError::EncryptionFailed(format!("{}", e)))?;
 
         Ok(EncryptedData {
             nonce: nonce_bytes,
             ciphertext,
+            key_version: Some(self.key_version),
         })
     }
 
-    /// Decrypt data from storage
-    pub fn decrypt(&self, encrypted: &EncryptedData) -> EncryptionResult<Vec<u8>> {
-        let cipher = self
-            .data_cipher
-            .as_ref()
-            .ok_or(EncryptionError::NotEnabled)?;
+    /// Decrypt with the cipher for a context of the key `encrypted` was
+    /// sealed with; unversioned data is tried against every loaded key
+    fn open(
+        &self,
+        encrypted: &EncryptedData,
+        cipher: fn(&KeySet) -> &Aes256Gcm,
+    ) -> EncryptionResult<Vec<u8>> {
+        let current = self.current.as_ref().ok_or(EncryptionError::NotEnabled)?;
+        let candidates: Vec<&KeySet> = match encrypted.key_version {
+            Some(version) if version == self.key_version => vec![current],
+            Some(version) => match self.previous.get(&version) {
+                Some(keys) => vec![keys],
+                None => {
+                    return Err(EncryptionError::DecryptionFailed(format!(
+                        "no master key loaded for key version {}",
+                        version
+                    )))
+                }
+            },
+            None => std::iter::once(current)
+                .chain(self.previous.values())
+                .collect(),
+        };
 
         let nonce = Nonce::from_slice(&encrypted.nonce);
+        let mut result = Err(EncryptionError::NotEnabled);
+        for keys in candidates {
+            result = cipher(keys)
+                .decrypt(nonce, encrypted.ciphertext.as_ref())
+                .map_err(|e| EncryptionError::DecryptionFailed(format!("{}", e)));
+            if result.is_ok() {
+                break;
+            }
+        }
+        result
+    }
+
+    /// Encrypt data for storage
+    pub fn encrypt(&self, plaintext: &[u8]) -> EncryptionResult<EncryptedData> {
+        self.seal(plaintext, |keys| &keys.data)
+    }
 
-        cipher
-            .decrypt(nonce, encrypted.ciphertext.as_ref())
-            .map_err(|e| EncryptionError::DecryptionFailed(format!("{}", e)))
+    /// Decrypt data from storage
+    pub fn decrypt(&self, encrypted: &EncryptedData) -> EncryptionResult<Vec<u8>> {
+        self.open(encrypted, |keys| &keys.data)
     }
 
     /// Encrypt data and return bytes (convenience method)
@@ -298,37 +775,22 @@ impl EncryptionManager {
 
     /// Encrypt WAL entry
     pub fn encrypt_wal(&self, plaintext: &[u8]) -> EncryptionResult<EncryptedData> {
-        let cipher = self
-            .wal_cipher
-            .as_ref()
-            .ok_or(EncryptionError::NotEnabled)?;
-
-        let mut nonce_bytes = [0u8; NONCE_SIZE];
-        rand::thread_rng().fill_bytes(&mut nonce_bytes);
-        let nonce = Nonce::from_slice(&nonce_bytes);
-
-        let ciphertext = cipher
-            .encrypt(nonce, plaintext)
-            .map_err(|e| EncryptionError::EncryptionFailed(format!("{}", e)))?;
-
-        Ok(EncryptedData {
-            nonce: nonce_bytes,
-            ciphertext,
-        })
+        self.seal(plaintext, |keys| &keys.wal)
     }
 
     /// Decrypt WAL entry
     pub fn decrypt_wal(&self, encrypted: &EncryptedData) -> EncryptionResult<Vec<u8>> {
-        let cipher = self
-            .wal_cipher
-            .as_ref()
-            .ok_or(EncryptionError::NotEnabled)?;
+        self.open(encrypted, |keys| &keys.wal)
+    }
 
-        let nonce = Nonce::from_slice(&encrypted.nonce);
+    /// Encrypt an index snapshot
+    pub fn encrypt_index(&self, plaintext: &[u8]) -> EncryptionResult<EncryptedData> {
+        self.seal(plaintext, |keys| &keys.index)
+    }
 
-        cipher
-            .decrypt(nonce, encrypted.ciphertext.as_ref())
-            .map_err(|e| EncryptionError::DecryptionFailed(format!("{}", e)))
+    /// Decrypt an index snapshot
+    pub fn decrypt_index(&self, encrypted: &EncryptedData) -> EncryptionResult<Vec<u8>> {
+        self.open(encrypted, |keys| &keys.index)
     }
 
     /// Generate a new random master key (for initial setup)
@@ -352,6 +814,8 @@ impl EncryptionManager {
             } else {
                 None
             },
+            key_version: self.is_enabled().then_some(self.key_version),
+            previous_key_versions: self.previous.keys().copied().collect(),
         }
     }
 }
@@ -371,6 +835,12 @@ pub struct EncryptionStatus {
     pub algorithm: Option<String>,
     /// Key derivation function in use
     pub key_derivation: Option<String>,
+    /// Version of the current master key
+    #[serde(default)]
+    pub key_version: Option<u8>,
+    /// Versions of the retired master keys still loaded for decryption
+    #[serde(default)]
+    pub previous_key_versions: Vec<u8>,
 }
 
 /// Helper function to encrypt data if encryption is enabled
@@ -455,6 +925,7 @@ mod tests {
         let parsed = EncryptedData::from_bytes(&bytes).unwrap();
         assert_eq!(encrypted.nonce, parsed.nonce);
         assert_eq!(encrypted.ciphertext, parsed.ciphertext);
+        assert_eq!(parsed.key_version, Some(1));
     }
 
     #[test]
@@ -480,6 +951,183 @@ mod tests {
         assert_ne!(manager.data_key, manager.wal_key);
     }
 
+    #[test]
+    fn test_configure() {
+        let mut manager = EncryptionManager::new();
+        let key = get_test_key();
+        manager
+            .configure(&EncryptionConfig {
+                enabled: true,
+                master_key: Some(key),
+                ..Default::default()
+            })
+            .unwrap();
+        assert!(manager.is_enabled());
+
+        let encrypted = manager.encrypt_index(b"index").unwrap();
+        assert_eq!(manager.decrypt_index(&encrypted).unwrap(), b"index");
+        // Each context has its own key
+        assert!(manager.decrypt_wal(&encrypted).is_err());
+
+        manager.configure(&EncryptionConfig::default()).unwrap();
+        assert!(!manager.is_enabled());
+        assert!(manager.encrypt_index(b"index").is_err());
+    }
+
+    #[test]
+    fn test_key_rotation() {
+        let (old_key, new_key) = (get_test_key(), get_test_key());
+        let mut old = EncryptionManager::new();
+        old.initialize(&old_key).unwrap();
+        let sealed = old.encrypt(b"old value").unwrap().to_bytes();
+        let sealed_wal = old.encrypt_wal(b"old entry").unwrap();
+
+        let mut manager = EncryptionManager::new();
+        manager
+            .configure(&EncryptionConfig {
+                enabled: true,
+                master_key: Some(new_key),
+                key_version: 2,
+                previous_keys: vec![VersionedKey {
+                    version: 1,
+                    key: old_key,
+                    provider: None,
+                }],
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(manager.key_version(), 2);
+        assert_eq!(manager.status().previous_key_versions, vec![1]);
+
+        // Data sealed with the previous key still opens, and isn't current
+        let parsed = EncryptedData::from_bytes(&sealed).unwrap();
+        assert_eq!(parsed.key_version, Some(1));
+        assert!(!manager.is_current(&parsed));
+        assert_eq!(manager.decrypt(&parsed).unwrap(), b"old value");
+        assert_eq!(manager.decrypt_wal(&sealed_wal).unwrap(), b"old entry");
+
+        let fresh = manager.encrypt(b"new value").unwrap();
+        assert!(manager.is_current(&fresh));
+        assert!(old.decrypt(&fresh).is_err());
+
+        // Without the previous key, its data can't be opened
+        let mut rotated = EncryptionManager::new();
+        rotated.key_version = 2;
+        rotated.initialize(&get_test_key()).unwrap();
+        assert!(rotated.decrypt(&parsed).is_err());
+        assert!(rotated.add_previous_key(2, &get_test_key()).is_err());
+    }
+
+    #[test]
+    fn test_unversioned_data_is_readable() {
+        let mut manager = EncryptionManager::new();
+        manager.initialize(&get_test_key()).unwrap();
+        let mut legacy = manager.encrypt(b"legacy").unwrap();
+        legacy.key_version = None;
+        let bytes = legacy.to_bytes();
+        assert!(bytes.starts_with(ENCRYPTION_MAGIC));
+        assert!(EncryptedData::is_encrypted(&bytes));
+
+        let parsed = EncryptedData::from_bytes(&bytes).unwrap();
+        assert_eq!(parsed.key_version, None);
+        assert!(!manager.is_current(&parsed));
+        assert_eq!(manager.decrypt(&parsed).unwrap(), b"legacy");
+    }
+
+    #[tokio::test]
+    async fn test_key_providers() {
+        use axum::http::{HeaderMap, StatusCode};
+        use axum::routing::{get, post};
+        use axum::Json;
+        use serde_json::{json, Value};
+
+        let key = get_test_key();
+        std::env::set_var("MINIKV_TEST_PROVIDER_KEY", &key);
+        let env = KeyProviderConfig::Env {
+            var: "MINIKV_TEST_PROVIDER_KEY".into(),
+        };
+        assert_eq!(env.build().master_key().await.unwrap(), key);
+
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("master.key"), format!("{}\n", key)).unwrap();
+        let file = KeyProviderConfig::File {
+            path: dir.path().join("master.key"),
+        };
+        assert_eq!(file.build().master_key().await.unwrap(), key);
+
+        // Vault (KV v2) and KMS stand-ins
+        let app = axum::Router::new()
+            .route(
+                "/v1/secret/data/minikv",
+                get(|headers: HeaderMap| async move {
+                    if headers.get("x-vault-token").and_then(|t| t.to_str().ok()) != Some("t0ken") {
+                        return Err(StatusCode::FORBIDDEN);
+                    }
+                    Ok(Json(json!({ "data": { "data": { "key": "vault-key" } } })))
+                }),
+            )
+            .route(
+                "/",
+                post(|headers: HeaderMap, Json(body): Json<Value>| async move {
+                    let signed = headers["authorization"]
+                        .to_str()
+                        .unwrap()
+                        .contains("/eu-west-1/kms/aws4_request");
+                    if headers["x-amz-target"] != "TrentService.Decrypt"
+                        || !signed
+                        || body["CiphertextBlob"] != "d3JhcHBlZA=="
+                    {
+                        return Err(StatusCode::BAD_REQUEST);
+                    }
+                    Ok(Json(json!({ "Plaintext": "kms-key" })))
+                }),
+            );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let vault = |token: &str| KeyProviderConfig::Vault {
+            addr: addr.clone(),
+            token: Some(token.into()),
+            path: "secret/data/minikv".into(),
+            field: "key".into(),
+        };
+        assert_eq!(
+            vault("t0ken").build().master_key().await.unwrap(),
+            "vault-key"
+        );
+        assert!(vault("wrong").build().master_key().await.is_err());
+
+        let kms: KeyProviderConfig = serde_json::from_value(json!({
+            "type": "aws_kms",
+            "region": "eu-west-1",
+            "ciphertext_blob": "d3JhcHBlZA==",
+            "endpoint": addr,
+            "access_key": "AKID",
+            "secret_key": "secret",
+        }))
+        .unwrap();
+        assert_eq!(kms.build().master_key().await.unwrap(), "kms-key");
+
+        // Resolved into the config, current and previous keys alike
+        let config = EncryptionConfig {
+            enabled: true,
+            key_provider: Some(env.clone()),
+            key_version: 2,
+            previous_keys: vec![VersionedKey {
+                version: 1,
+                key: String::new(),
+                provider: Some(file),
+            }],
+            ..Default::default()
+        };
+        let mut manager = EncryptionManager::new();
+        assert!(manager.configure(&config).is_err());
+        let resolved = config.resolve_keys().await.unwrap();
+        assert_eq!(resolved.master_key.as_deref(), Some(key.as_str()));
+        assert_eq!(resolved.previous_keys[0].key, key);
+    }
+
     #[test]
     fn test_invalid_key() {
         let mut manager = EncryptionManager::new();
@@ -23,6 +23,15 @@ pub enum Error {
     #[error("WAL error: {0}")]
     Wal(String),
 
+    #[error("Storage full: {0}")]
+    StorageFull(String),
+
+    #[error("Disk unavailable: {0}")]
+    DiskUnavailable(String),
+
+    #[error("Encryption error: {0}")]
+    Encryption(#[from] crate::common::EncryptionError),
+
     // === Raft Errors ===
     #[error("Not leader: current leader is {0}")]
     NotLeader(String),
@@ -47,6 +56,9 @@ pub enum Error {
     #[error("Insufficient replicas: need {needed}, have {available}")]
     InsufficientReplicas { needed: usize, available: usize },
 
+    #[error("Placement violates failure domain policy: {0}")]
+    PlacementViolation(String),
+
     #[error("Shard not found: {0}")]
     ShardNotFound(u64),
 
@@ -81,6 +93,9 @@ pub enum Error {
     #[error("Compact failed: {0}")]
     CompactFailed(String),
 
+    #[error("Invalid volume command: {0}")]
+    InvalidCommand(String),
+
     // === Generic ===
     #[error("Internal error: {0}")]
     Internal(String),
@@ -88,6 +103,12 @@ pub enum Error {
     #[error("Operation timeout: {0}")]
     Timeout(String),
 
+    #[error("Invalid request: {0}")]
+    InvalidRequest(String),
+
+    #[error("Permission denied: {0}")]
+    PermissionDenied(String),
+
     #[error("{0}")]
     Other(String),
 }
@@ -117,12 +138,26 @@ impl Error {
                     .insert("leader", leader.parse().unwrap());
                 status
             }
-            Error::InvalidConfig(_) | Error::InsufficientReplicas { .. } => {
+            Error::InvalidConfig(_)
+            | Error::InvalidCommand(_)
+            | Error::InvalidRequest(_)
+            | Error::InsufficientReplicas { .. } => {
                 tonic::Status::new(Code::InvalidArgument, self.to_string())
             }
+            Error::PlacementViolation(_) => {
+                tonic::Status::new(Code::FailedPrecondition, self.to_string())
+            }
             Error::ConsensusTimeout | Error::Timeout(_) => {
                 tonic::Status::new(Code::DeadlineExceeded, self.to_string())
             }
+            Error::PermissionDenied(_) => {
+                tonic::Status::new(Code::PermissionDenied, self.to_string())
+            }
+            Error::StorageFull(_) => tonic::Status::new(Code::ResourceExhausted, self.to_string()),
+            Error::DiskUnavailable(_) => tonic::Status::new(Code::Unavailable, self.to_string()),
+            Error::Corrupted(_) | Error::ChecksumMismatch { .. } => {
+                tonic::Status::new(Code::DataLoss, self.to_string())
+            }
             Error::Grpc(status) => status.clone(),
             _ => tonic::Status::new(Code::Internal, self.to_string()),
         }
@@ -134,11 +169,16 @@ impl Error {
         match self {
             Error::NotFound(_) => StatusCode::NOT_FOUND,
             Error::NotLeader(_) => StatusCode::TEMPORARY_REDIRECT,
-            Error::InvalidConfig(_) => StatusCode::BAD_REQUEST,
-            Error::Timeout(_) | Error::ConsensusTimeout => StatusCode::REQUEST_TIMEOUT,
-            Error::NoHealthyVolumes | Error::InsufficientReplicas { .. } => {
-                StatusCode::SERVICE_UNAVAILABLE
+            Error::InvalidConfig(_) | Error::InvalidCommand(_) | Error::InvalidRequest(_) => {
+                StatusCode::BAD_REQUEST
             }
+            Error::PermissionDenied(_) => StatusCode::FORBIDDEN,
+            Error::Timeout(_) | Error::ConsensusTimeout => StatusCode::REQUEST_TIMEOUT,
+            Error::StorageFull(_) => StatusCode::INSUFFICIENT_STORAGE,
+            Error::NoHealthyVolumes
+            | Error::InsufficientReplicas { .. }
+            | Error::PlacementViolation(_)
+            | Error::DiskUnavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
             _ => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
@@ -0,0 +1,271 @@
+//! Token authentication of internal gRPC calls
+//!
+//! Internal RPCs (Join, Heartbeat, AppendEntries, ...) carry a bearer
+//! token in their `authorization` metadata. A node accepts any token listed
+//! in `tokens` and sends its own `token` (by default the first listed), so
+//! a cluster can share one secret or give each node its own.
+//!
+//! Tokens are rotated without downtime by reloading the configuration
+//! (SIGHUP, see `common::reload`): add the new token to `tokens`
+//! everywhere, switch `token`, then drop the old one.
+//!
+//! The same tokens sign the URLs coordinators hand out for direct transfers
+//! to a volume (see `coordinator::redirect`): an HMAC-SHA256 keyed by the
+//! blake3 hash of the sender's token, which a volume checks against every
+//! token it accepts. With tokens disabled nothing can be signed, so no
+//! transfer URL is issued or honored.
+
+use crate::common::{connect_channel, timestamp_now, Error, Result, FAULTS};
+use hmac::{Hmac, Mac};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::sync::{Arc, RwLock};
+use tonic::metadata::MetadataValue;
+use tonic::service::interceptor::{InterceptedService, Interceptor};
+use tonic::transport::Channel;
+use tonic::{Request, Status};
+
+/// Internal gRPC token configuration (`[coordinator.grpc_auth]`, `[volume.grpc_auth]`)
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GrpcAuthConfig {
+    /// Reject internal calls without a valid token
+    pub enabled: bool,
+    /// Tokens accepted from peers
+    pub tokens: Vec<String>,
+    /// Token this node sends (defaults to the first of `tokens`)
+    pub token: Option<String>,
+}
+
+/// Loaded tokens; accepted ones are kept as hashes, compared in constant time
+#[derive(Debug, Clone)]
+struct GrpcTokens {
+    send: String,
+    accepted: Vec<blake3::Hash>,
+}
+
+impl GrpcTokens {
+    fn load(config: &GrpcAuthConfig) -> Result<Option<Self>> {
+        if !config.enabled {
+            return Ok(None);
+        }
+        let Some(send) = config.token.as_ref().or(config.tokens.first()) else {
+            return Err(Error::InvalidConfig(
+                "grpc_auth needs at least one token".into(),
+            ));
+        };
+        Ok(Some(Self {
+            send: send.clone(),
+            accepted: config
+                .tokens
+                .iter()
+                .map(|t| blake3::hash(t.as_bytes()))
+                .collect(),
+        }))
+    }
+
+    fn accepts(&self, token: &str) -> bool {
+        let hash = blake3::hash(token.as_bytes());
+        // Check every token so timing doesn't reveal which one matched
+        self.accepted
+            .iter()
+            .fold(false, |found, accepted| (*accepted == hash) | found)
+    }
+
+    fn sign(&self, payload: &str) -> String {
+        let key = blake3::hash(self.send.as_bytes());
+        hex::encode(transfer_mac(&key, payload).finalize().into_bytes())
+    }
+
+    fn verifies(&self, payload: &str, signature: &str) -> bool {
+        let Ok(signature) = hex::decode(signature) else {
+            return false;
+        };
+        self.accepted.iter().fold(false, |found, key| {
+            transfer_mac(key, payload).verify_slice(&signature).is_ok() | found
+        })
+    }
+}
+
+fn transfer_mac(key: &blake3::Hash, payload: &str) -> Hmac<Sha256> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(key.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(payload.as_bytes());
+    mac
+}
+
+static GRPC_AUTH: Lazy<RwLock<Option<Arc<GrpcTokens>>>> = Lazy::new(|| RwLock::new(None));
+
+/// Load `config` as this process's internal gRPC tokens, replacing the
+/// previous ones
+pub fn configure_grpc_auth(config: &GrpcAuthConfig) -> Result<()> {
+    let tokens = GrpcTokens::load(config)?.map(Arc::new);
+    *GRPC_AUTH.write().unwrap() = tokens;
+    Ok(())
+}
+
+fn current() -> Option<Arc<GrpcTokens>> {
+    GRPC_AUTH.read().unwrap().clone()
+}
+
+/// Whether internal tokens are enabled in this process
+pub fn tokens_enabled() -> bool {
+    current().is_some()
+}
+
+/// gRPC interceptor rejecting calls without an accepted token
+pub fn check_token(request: Request<()>) -> std::result::Result<Request<()>, Status> {
+    let Some(tokens) = current() else {
+        return Ok(request);
+    };
+    let token = request
+        .metadata()
+        .get("authorization")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+    match token {
+        Some(token) if tokens.accepts(token) => Ok(request),
+        Some(_) => Err(Status::unauthenticated("invalid internal token")),
+        None => Err(Status::unauthenticated("internal token required")),
+    }
+}
+
+/// Whether an HTTP `Authorization` header carries an accepted token; always
+/// true with tokens disabled. Guards the data routes of the volume HTTP API.
+pub fn check_http_token(authorization: Option<&str>) -> bool {
+    let Some(tokens) = current() else {
+        return true;
+    };
+    authorization
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .is_some_and(|token| tokens.accepts(token))
+}
+
+/// What a direct transfer URL grants: `method` on `key` until `expires`
+/// (Unix seconds), with `params` the other parameters it is signed with
+fn transfer_payload(method: &str, key: &str, expires: u64, params: &str) -> String {
+    format!("{}\n{}\n{}\n{}", method, key, expires, params)
+}
+
+/// Signature of a direct transfer URL, `None` with tokens disabled
+pub fn sign_transfer(method: &str, key: &str, expires: u64, params: &str) -> Option<String> {
+    current().map(|tokens| tokens.sign(&transfer_payload(method, key, expires, params)))
+}
+
+/// Whether a direct transfer URL is unexpired and carries the signature of
+/// a node holding an accepted token; always false with tokens disabled
+pub fn check_transfer(
+    method: &str,
+    key: &str,
+    expires: u64,
+    params: &str,
+    signature: Option<&str>,
+) -> bool {
+    if expires < timestamp_now() {
+        return false;
+    }
+    let Some(tokens) = current() else {
+        return false;
+    };
+    signature.is_some_and(|signature| {
+        tokens.verifies(&transfer_payload(method, key, expires, params), signature)
+    })
+}
+
+/// Client interceptor adding this node's token to outgoing calls, and
+/// dropping the ones fault injection picks
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AttachToken;
+
+impl Interceptor for AttachToken {
+    fn call(&mut self, mut request: Request<()>) -> std::result::Result<Request<()>, Status> {
+        if FAULTS.drop_grpc_call() {
+            return Err(Status::unavailable("call dropped by fault injection"));
+        }
+        if let Some(tokens) = current() {
+            let value = MetadataValue::try_from(format!("Bearer {}", tokens.send))
+                .map_err(|_| Status::internal("internal token is not valid metadata"))?;
+            request.metadata_mut().insert("authorization", value);
+        }
+        Ok(request)
+    }
+}
+
+/// Channel to another node, authenticated with this node's token
+pub type InternalChannel = InterceptedService<Channel, AttachToken>;
+
+/// Open a channel to an internal gRPC endpoint (over mTLS if configured)
+/// whose calls carry this node's token
+pub async fn connect_internal(
+    addr: &str,
+) -> std::result::Result<InternalChannel, tonic::transport::Error> {
+    Ok(InterceptedService::new(
+        connect_channel(addr).await?,
+        AttachToken,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(tokens: &[&str], token: Option<&str>) -> GrpcAuthConfig {
+        GrpcAuthConfig {
+            enabled: true,
+            tokens: tokens.iter().map(|t| t.to_string()).collect(),
+            token: token.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn test_load() {
+        assert!(GrpcTokens::load(&GrpcAuthConfig::default())
+            .unwrap()
+            .is_none());
+        assert!(GrpcTokens::load(&config(&[], None)).is_err());
+
+        let tokens = GrpcTokens::load(&config(&["a", "b"], None))
+            .unwrap()
+            .unwrap();
+        assert_eq!(tokens.send, "a");
+        let tokens = GrpcTokens::load(&config(&["a"], Some("node-1")))
+            .unwrap()
+            .unwrap();
+        assert_eq!(tokens.send, "node-1");
+    }
+
+    #[test]
+    fn test_rotation() {
+        // Old and new tokens are both accepted while nodes switch over
+        let tokens = GrpcTokens::load(&config(&["old", "new"], Some("new")))
+            .unwrap()
+            .unwrap();
+        assert!(tokens.accepts("old"));
+        assert!(tokens.accepts("new"));
+        assert!(!tokens.accepts("other"));
+        assert!(!tokens.accepts(""));
+
+        let tokens = GrpcTokens::load(&config(&["new"], None)).unwrap().unwrap();
+        assert!(!tokens.accepts("old"));
+        assert!(tokens.accepts("new"));
+    }
+
+    #[test]
+    fn test_transfer_signature() {
+        let signer = GrpcTokens::load(&config(&["a"], None)).unwrap().unwrap();
+        let payload = transfer_payload("GET", "acme/k", 100, "");
+        let signature = signer.sign(&payload);
+
+        let verifier = GrpcTokens::load(&config(&["b", "a"], Some("b")))
+            .unwrap()
+            .unwrap();
+        assert!(verifier.verifies(&payload, &signature));
+        assert!(!verifier.verifies(&transfer_payload("GET", "acme/j", 100, ""), &signature));
+        assert!(!verifier.verifies(&transfer_payload("GET", "acme/k", 101, ""), &signature));
+        assert!(!verifier.verifies(&payload, "not hex"));
+
+        let other = GrpcTokens::load(&config(&["b"], None)).unwrap().unwrap();
+        assert!(!other.verifies(&payload, &signature));
+    }
+}
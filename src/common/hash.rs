@@ -70,6 +70,29 @@ pub fn hrw_hash(key: &str, nodes: &[String]) -> Vec<String> {
     weights.into_iter().map(|(node, _)| node).collect()
 }
 
+/// Weighted HRW hashing
+///
+/// Like `hrw_hash`, but each node wins a share of keys proportional to its
+/// weight (logarithmic method: score = -weight / ln(h), h uniform in (0, 1)).
+/// Nodes with a zero weight are ranked last.
+pub fn weighted_hrw_hash(key: &str, nodes: &[(String, f64)]) -> Vec<String> {
+    let mut scores: Vec<(String, f64)> = nodes
+        .iter()
+        .map(|(node, weight)| {
+            let combined = format!("{}{}", key, node);
+            let hash = blake3::hash(combined.as_bytes());
+            let bits = u64::from_le_bytes(hash.as_bytes()[0..8].try_into().unwrap()) >> 11;
+            let unit = (bits as f64 + 1.0) / ((1u64 << 53) as f64 + 2.0);
+            (node.clone(), -weight.max(0.0) / unit.ln())
+        })
+        .collect();
+
+    // Sort by score (descending)
+    scores.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+    scores.into_iter().map(|(node, _)| node).collect()
+}
+
 /// Select N replicas using HRW hashing
 pub fn select_replicas(key: &str, nodes: &[String], n: usize) -> Vec<String> {
     let sorted = hrw_hash(key, nodes);
@@ -119,6 +142,11 @@ impl ConsistentHashRing {
         self.shard_to_nodes.get(&shard).map(|v| v.as_slice())
     }
 
+    /// Current shard → nodes assignments
+    pub fn assignments(&self) -> &HashMap<u64, Vec<String>> {
+        &self.shard_to_nodes
+    }
+
     /// Rebalance: redistribute shards across available nodes
     pub fn rebalance(&mut self, available_nodes: &[String], replicas: usize) {
         for shard in 0..self.num_shards {
@@ -207,6 +235,18 @@ mod tests {
         assert_eq!(replicas.len(), 2);
     }
 
+    #[test]
+    fn test_weighted_hrw_hash() {
+        let nodes = vec![("big".to_string(), 9.0), ("small".to_string(), 1.0)];
+
+        let big_first = (0..1000)
+            .filter(|i| weighted_hrw_hash(&format!("key-{}", i), &nodes)[0] == "big")
+            .count();
+
+        // Expect ~90% of keys on the heavier node
+        assert!((850..=950).contains(&big_first), "{}", big_first);
+    }
+
     #[test]
     fn test_blob_prefix() {
         let key = "my-blob-key";
@@ -0,0 +1,304 @@
+//! IP allow/deny lists per listener
+//!
+//! For deployments without a fronting proxy, the coordinator can refuse
+//! clients by address before doing any other work. Each listener has its own
+//! lists of CIDR blocks (`10.0.0.0/8`, `2001:db8::/32`, or a bare address):
+//! an address matching `deny` is refused, and so is one matching none of
+//! `allow` when `allow` is not empty.
+//!
+//! The `admin` lists apply to the `/admin/` routes on top of the `http`
+//! ones, so the admin API can be narrowed to an operator network while the
+//! data API stays open. The `grpc` lists guard internal gRPC.
+
+use crate::common::{Error, Result, METRICS};
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, State},
+    http::{Request, Response, StatusCode},
+    middleware::Next,
+};
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// CIDR lists of one listener
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IpListConfig {
+    /// Only these blocks may connect (anyone when empty)
+    pub allow: Vec<String>,
+    /// These blocks are refused, even if allowed
+    pub deny: Vec<String>,
+}
+
+/// IP filtering configuration (`[coordinator.ip_filter]`)
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IpFilterConfig {
+    /// Every HTTP route
+    pub http: IpListConfig,
+    /// `/admin/` routes, in addition to `http`
+    pub admin: IpListConfig,
+    /// Internal gRPC
+    pub grpc: IpListConfig,
+}
+
+/// A block of addresses, e.g. `192.168.0.0/16`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl Cidr {
+    /// Whether `ip` is in the block. IPv4-mapped IPv6 addresses
+    /// (`::ffff:10.0.0.1`) match IPv4 blocks.
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+            v4 => v4,
+        };
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => prefix_matches(
+                u32::from(net).into(),
+                u32::from(ip).into(),
+                32,
+                self.prefix_len,
+            ),
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                prefix_matches(u128::from(net), u128::from(ip), 128, self.prefix_len)
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Whether the first `prefix_len` of the `bits` low bits of `a` and `b` agree
+fn prefix_matches(a: u128, b: u128, bits: u8, prefix_len: u8) -> bool {
+    if prefix_len == 0 {
+        return true;
+    }
+    let shift = bits - prefix_len;
+    (a >> shift) == (b >> shift)
+}
+
+impl FromStr for Cidr {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || Error::InvalidConfig(format!("invalid CIDR block {:?}", s));
+        let (addr, prefix_len) = match s.split_once('/') {
+            Some((addr, len)) => (addr, Some(len)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr.trim().parse().map_err(|_| invalid())?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix_len {
+            Some(len) => len.trim().parse().map_err(|_| invalid())?,
+            None => max,
+        };
+        if prefix_len > max {
+            return Err(invalid());
+        }
+        Ok(Self { addr, prefix_len })
+    }
+}
+
+/// Loaded lists of one listener
+#[derive(Debug, Clone, Default)]
+pub struct IpList {
+    allow: Vec<Cidr>,
+    deny: Vec<Cidr>,
+}
+
+impl IpList {
+    pub fn from_config(config: &IpListConfig) -> Result<Self> {
+        let parse = |blocks: &[String]| {
+            blocks
+                .iter()
+                .map(|b| b.parse())
+                .collect::<Result<Vec<Cidr>>>()
+        };
+        Ok(Self {
+            allow: parse(&config.allow)?,
+            deny: parse(&config.deny)?,
+        })
+    }
+
+    /// Whether `ip` may connect
+    pub fn allows(&self, ip: IpAddr) -> bool {
+        if self.deny.iter().any(|cidr| cidr.contains(ip)) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|cidr| cidr.contains(ip))
+    }
+}
+
+/// Loaded lists of every listener
+#[derive(Debug, Clone, Default)]
+pub struct IpFilter {
+    pub http: IpList,
+    pub admin: IpList,
+    pub grpc: IpList,
+}
+
+impl IpFilter {
+    pub fn from_config(config: &IpFilterConfig) -> Result<Self> {
+        Ok(Self {
+            http: IpList::from_config(&config.http)?,
+            admin: IpList::from_config(&config.admin)?,
+            grpc: IpList::from_config(&config.grpc)?,
+        })
+    }
+
+    /// Whether `ip` may request `path` over HTTP
+    pub fn allows_http(&self, ip: IpAddr, path: &str) -> bool {
+        self.http.allows(ip) && (!path.starts_with("/admin/") || self.admin.allows(ip))
+    }
+
+    /// gRPC interceptor refusing peers outside the `grpc` lists. Calls
+    /// without a known peer address go through.
+    pub fn check_grpc(
+        &self,
+        request: tonic::Request<()>,
+    ) -> std::result::Result<tonic::Request<()>, tonic::Status> {
+        match request.remote_addr() {
+            Some(addr) if !self.grpc.allows(addr.ip()) => {
+                METRICS.ip_rejected_grpc.inc();
+                tracing::debug!("Refused gRPC call from {}", addr);
+                Err(tonic::Status::permission_denied("address not allowed"))
+            }
+            _ => Ok(request),
+        }
+    }
+}
+
+/// Axum middleware refusing clients outside the `http` lists, or the `admin`
+/// ones for `/admin/` routes. Requests without a known peer address (served
+/// without connect info) go through.
+pub async fn ip_filter_middleware(
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    State(filter): State<Arc<IpFilter>>,
+    request: Request<Body>,
+    next: Next,
+) -> Response<Body> {
+    let Some(ConnectInfo(addr)) = connect_info else {
+        return next.run(request).await;
+    };
+    let path = request.uri().path();
+    if filter.allows_http(addr.ip(), path) {
+        return next.run(request).await;
+    }
+    if filter.http.allows(addr.ip()) {
+        METRICS.ip_rejected_admin.inc();
+    } else {
+        METRICS.ip_rejected_http.inc();
+    }
+    tracing::debug!("Refused {} from {}", path, addr);
+    let mut response = Response::new(Body::from("Forbidden"));
+    *response.status_mut() = StatusCode::FORBIDDEN;
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_cidr() {
+        let net: Cidr = "10.1.0.0/16".parse().unwrap();
+        assert!(net.contains(ip("10.1.2.3")));
+        assert!(!net.contains(ip("10.2.0.1")));
+        assert!(net.contains(ip("::ffff:10.1.0.9")));
+        assert!(!net.contains(ip("2001:db8::1")));
+
+        let v6: Cidr = "2001:db8::/32".parse().unwrap();
+        assert!(v6.contains(ip("2001:db8:1::1")));
+        assert!(!v6.contains(ip("2001:db9::1")));
+
+        let host: Cidr = "192.168.1.7".parse().unwrap();
+        assert!(host.contains(ip("192.168.1.7")));
+        assert!(!host.contains(ip("192.168.1.8")));
+        assert!("0.0.0.0/0".parse::<Cidr>().unwrap().contains(ip("8.8.8.8")));
+
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert!("not-an-ip/8".parse::<Cidr>().is_err());
+    }
+
+    #[test]
+    fn test_lists() {
+        let filter = IpFilter::from_config(&IpFilterConfig {
+            http: IpListConfig {
+                allow: vec![],
+                deny: vec!["203.0.113.0/24".to_string()],
+            },
+            admin: IpListConfig {
+                allow: vec!["10.0.0.0/8".to_string()],
+                deny: vec!["10.9.0.0/16".to_string()],
+            },
+            grpc: IpListConfig::default(),
+        })
+        .unwrap();
+
+        assert!(filter.allows_http(ip("198.51.100.1"), "/mykey"));
+        assert!(!filter.allows_http(ip("203.0.113.5"), "/mykey"));
+        assert!(!filter.allows_http(ip("198.51.100.1"), "/admin/status"));
+        assert!(filter.allows_http(ip("10.0.0.1"), "/admin/status"));
+        // Deny wins over allow
+        assert!(!filter.allows_http(ip("10.9.0.1"), "/admin/status"));
+        assert!(filter.grpc.allows(ip("203.0.113.5")));
+
+        let invalid = IpFilterConfig {
+            grpc: IpListConfig {
+                allow: vec!["10.0.0.0/8".to_string(), "nope".to_string()],
+                deny: vec![],
+            },
+            ..Default::default()
+        };
+        assert!(IpFilter::from_config(&invalid).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_middleware() {
+        use tower::ServiceExt;
+
+        let filter = Arc::new(
+            IpFilter::from_config(&IpFilterConfig {
+                admin: IpListConfig {
+                    allow: vec!["127.0.0.1".to_string()],
+                    deny: vec![],
+                },
+                ..Default::default()
+            })
+            .unwrap(),
+        );
+        let router = axum::Router::new()
+            .route("/admin/status", axum::routing::get(|| async { "ok" }))
+            .layer(axum::middleware::from_fn_with_state(
+                filter,
+                ip_filter_middleware,
+            ));
+        let send = |peer: Option<&str>| {
+            let mut request = Request::get("/admin/status").body(Body::empty()).unwrap();
+            if let Some(peer) = peer {
+                let addr: SocketAddr = peer.parse().unwrap();
+                request.extensions_mut().insert(ConnectInfo(addr));
+            }
+            router.clone().oneshot(request)
+        };
+
+        let rejected = METRICS.ip_rejected_admin.get();
+        let resp = send(Some("127.0.0.1:1234")).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = send(Some("10.0.0.1:1234")).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        assert!(METRICS.ip_rejected_admin.get() > rejected);
+        let resp = send(None).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,158 @@
+//! Process lifecycle, for supervisors
+//!
+//! - Under systemd with `Type=notify`, nodes tell the service manager they
+//!   serve (`READY=1`) once their listeners are bound, and that they are
+//!   stopping (`STOPPING=1`) when a shutdown starts, through
+//!   `$NOTIFY_SOCKET`. Without it, nothing is sent.
+//! - `PidFile` holds the process ID while the node runs (`--pid-file` or
+//!   `pid_file`), for init scripts and container entrypoints.
+//! - `exit_code` tells a configuration error (78, `EX_CONFIG`), which
+//!   restarting won't fix, from other failures (1).
+
+use crate::common::{Error, Result};
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+/// Exit status for an invalid configuration (`EX_CONFIG` of sysexits.h)
+pub const EXIT_CONFIG: u8 = 78;
+
+/// Exit status for any other failure
+pub const EXIT_FAILURE: u8 = 1;
+
+/// Exit status of a run that failed with `error`
+pub fn exit_code(error: &Error) -> ExitCode {
+    match error {
+        Error::InvalidConfig(_) => ExitCode::from(EXIT_CONFIG),
+        _ => ExitCode::from(EXIT_FAILURE),
+    }
+}
+
+/// Tell systemd the node serves requests
+pub fn notify_ready() {
+    notify("READY=1\nSTATUS=Serving");
+}
+
+/// Tell systemd the node is shutting down
+pub fn notify_stopping() {
+    notify("STOPPING=1\nSTATUS=Shutting down");
+}
+
+/// Send `state` to the service manager, if there is one
+fn notify(state: &str) {
+    let Some(socket) = std::env::var_os("NOTIFY_SOCKET") else {
+        return;
+    };
+    if let Err(e) = send_notify(&socket, state) {
+        tracing::warn!("Cannot notify systemd: {}", e);
+    }
+}
+
+/// Send `state` to the datagram socket at `socket`: a path, or an abstract
+/// name after `@`
+fn send_notify(socket: &OsStr, state: &str) -> io::Result<()> {
+    let datagram = UnixDatagram::unbound()?;
+    match socket.as_bytes().strip_prefix(b"@") {
+        #[cfg(target_os = "linux")]
+        Some(name) => {
+            use std::os::linux::net::SocketAddrExt;
+            let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+            datagram.send_to_addr(state.as_bytes(), &addr)?;
+        }
+        #[cfg(not(target_os = "linux"))]
+        Some(_) => {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "abstract sockets are Linux-only",
+            ))
+        }
+        None => {
+            datagram.send_to(state.as_bytes(), Path::new(socket))?;
+        }
+    }
+    Ok(())
+}
+
+/// A file holding the ID of this process, removed when dropped
+#[derive(Debug)]
+pub struct PidFile {
+    path: PathBuf,
+}
+
+impl PidFile {
+    /// Write the process ID to `path`. Fails if it names a process still
+    /// running, as when another node was started with the same file.
+    #[allow(clippy::result_large_err)]
+    pub fn create(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        if let Some(pid) = fs::read_to_string(&path)
+            .ok()
+            .and_then(|pid| pid.trim().parse::<libc::pid_t>().ok())
+        {
+            // Signal 0 only checks the process exists
+            if pid != std::process::id() as libc::pid_t && unsafe { libc::kill(pid, 0) } == 0 {
+                return Err(Error::Other(format!(
+                    "{} names process {}, still running",
+                    path.display(),
+                    pid
+                )));
+            }
+        }
+        fs::write(&path, format!("{}\n", std::process::id())).map_err(|e| {
+            Error::InvalidConfig(format!("cannot write pid file {}: {}", path.display(), e))
+        })?;
+        Ok(Self { path })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_send_notify() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("notify.sock");
+        let manager = UnixDatagram::bind(&path).unwrap();
+
+        send_notify(path.as_os_str(), "READY=1").unwrap();
+        let mut buf = [0; 64];
+        let n = manager.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"READY=1");
+    }
+
+    #[test]
+    fn test_pid_file() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("node.pid");
+
+        let pid_file = PidFile::create(&path).unwrap();
+        let written = fs::read_to_string(&path).unwrap();
+        assert_eq!(written.trim(), std::process::id().to_string());
+        drop(pid_file);
+        assert!(!path.exists());
+
+        // Left behind by a process that is gone
+        fs::write(&path, "999999999\n").unwrap();
+        let _pid_file = PidFile::create(&path).unwrap();
+
+        let missing = dir.path().join("missing").join("node.pid");
+        let err = PidFile::create(missing).unwrap_err();
+        assert_eq!(exit_code(&err), ExitCode::from(EXIT_CONFIG));
+    }
+}
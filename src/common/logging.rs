@@ -0,0 +1,260 @@
+//! Log output
+//!
+//! `log_level` (or `RUST_LOG`) sets what is logged and `log_format` how:
+//! `text` for people, or `json` for Loki, ELK and the like, one object per
+//! line holding the event's fields, those of the spans it happened in
+//! (`request_id` and `tenant` on HTTP requests) and the `node_id`.
+//!
+//! With `log_debug_per_sec`, debug and trace events past that many in a
+//! second at one call site are dropped, and counted in
+//! `minikv_log_events_sampled_out`.
+//!
+//! `log_level` can be changed while running (see `common::reload`).
+
+use crate::common::{Error, LogConfig, LogFormat, Result, METRICS};
+use once_cell::sync::OnceCell;
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Write;
+use std::sync::Mutex;
+use std::time::Instant;
+use tracing::callsite::Identifier;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::layer::{Context, SubscriberExt};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{reload, EnvFilter, Layer, Registry};
+
+/// Swaps the filter of the global subscriber
+static FILTER: OnceCell<reload::Handle<EnvFilter, Registry>> = OnceCell::new();
+
+/// Install the global subscriber for `config`, on node `node_id`
+pub fn init(config: &LogConfig, node_id: &str) {
+    let filter =
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(&config.log_level));
+    let (filter, handle) = reload::Layer::new(filter);
+    let _ = FILTER.set(handle);
+    let sampler = (config.log_debug_per_sec > 0).then(|| Sampler::new(config.log_debug_per_sec));
+    let (json, text) = match config.log_format {
+        LogFormat::Json => (Some(JsonLayer::new(node_id, std::io::stdout)), None),
+        LogFormat::Text => (None, Some(tracing_subscriber::fmt::layer())),
+    };
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(sampler)
+        .with(json)
+        .with(text)
+        .init();
+}
+
+/// Log at `level` from now on, unless `RUST_LOG` is set
+#[allow(clippy::result_large_err)]
+pub fn set_level(level: &str) -> Result<()> {
+    let filter = match EnvFilter::try_from_default_env() {
+        Ok(filter) => filter,
+        Err(_) => EnvFilter::try_new(level)
+            .map_err(|e| Error::InvalidConfig(format!("log_level {}: {}", level, e)))?,
+    };
+    let handle = FILTER
+        .get()
+        .ok_or_else(|| Error::Other("logging is not initialized".into()))?;
+    handle
+        .reload(filter)
+        .map_err(|e| Error::Other(format!("log_level: {}", e)))
+}
+
+/// Drops the debug and trace events of a call site past `per_sec` a second
+pub struct Sampler {
+    per_sec: u32,
+    start: Instant,
+    /// Second and count of the events let through, per call site
+    seen: Mutex<HashMap<Identifier, (u64, u32)>>,
+}
+
+impl Sampler {
+    pub fn new(per_sec: u32) -> Self {
+        Self {
+            per_sec,
+            start: Instant::now(),
+            seen: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<S: Subscriber> Layer<S> for Sampler {
+    fn event_enabled(&self, event: &Event<'_>, _ctx: Context<'_, S>) -> bool {
+        let metadata = event.metadata();
+        if *metadata.level() < Level::DEBUG {
+            return true;
+        }
+        let second = self.start.elapsed().as_secs();
+        let mut seen = self.seen.lock().unwrap();
+        let (at, count) = seen.entry(metadata.callsite()).or_insert((second, 0));
+        if *at != second {
+            (*at, *count) = (second, 0);
+        }
+        if *count >= self.per_sec {
+            METRICS.log_events_sampled_out.inc();
+            return false;
+        }
+        *count += 1;
+        true
+    }
+}
+
+/// Writes events as JSON lines
+pub struct JsonLayer<W> {
+    node_id: String,
+    make_writer: W,
+}
+
+impl<W> JsonLayer<W>
+where
+    W: for<'a> MakeWriter<'a> + 'static,
+{
+    pub fn new(node_id: &str, make_writer: W) -> Self {
+        Self {
+            node_id: node_id.to_string(),
+            make_writer,
+        }
+    }
+}
+
+/// The fields recorded on a span
+struct SpanFields(Map<String, Value>);
+
+struct JsonVisitor<'a>(&'a mut Map<String, Value>);
+
+impl Visit for JsonVisitor<'_> {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0.insert(field.name().to_string(), json!(value));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().to_string(), json!(value));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().to_string(), json!(value));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().to_string(), json!(value));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), json!(value));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0
+            .insert(field.name().to_string(), json!(format!("{:?}", value)));
+    }
+}
+
+impl<S, W> Layer<S> for JsonLayer<W>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'a> MakeWriter<'a> + 'static,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let mut fields = Map::new();
+        attrs.record(&mut JsonVisitor(&mut fields));
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(SpanFields(fields));
+        }
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(SpanFields(fields)) = span.extensions_mut().get_mut::<SpanFields>() {
+                values.record(&mut JsonVisitor(fields));
+            }
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+        let mut line = Map::new();
+        line.insert(
+            "timestamp".to_string(),
+            json!(chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true)),
+        );
+        line.insert("level".to_string(), json!(metadata.level().as_str()));
+        line.insert("target".to_string(), json!(metadata.target()));
+        line.insert("node_id".to_string(), json!(self.node_id));
+        // Inner spans and the event itself win over outer spans
+        if let Some(scope) = ctx.event_scope(event) {
+            for span in scope.from_root() {
+                if let Some(SpanFields(fields)) = span.extensions().get::<SpanFields>() {
+                    line.extend(fields.clone());
+                }
+            }
+        }
+        event.record(&mut JsonVisitor(&mut line));
+
+        let mut bytes = serde_json::to_vec(&line).unwrap_or_default();
+        bytes.push(b'\n');
+        let _ = self.make_writer.make_writer().write_all(&bytes);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use tracing::field::Empty;
+    use tracing_subscriber::Registry;
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_json_lines_and_sampling() {
+        let buffer = Buffer::default();
+        let writer = buffer.clone();
+        let subscriber = Registry::default()
+            .with(Sampler::new(2))
+            .with(JsonLayer::new("coord-1", move || writer.clone()));
+
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("http_request", request_id = "r-1", tenant = Empty);
+            let _guard = span.enter();
+            span.record("tenant", "acme");
+            tracing::info!(key = "a", size = 3u64, "PUT done");
+            for _ in 0..5 {
+                tracing::debug!("noisy");
+            }
+        });
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<Value> = output
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 3);
+        let put = &lines[0];
+        assert_eq!(put["message"], "PUT done");
+        assert_eq!(put["level"], "INFO");
+        assert_eq!(put["node_id"], "coord-1");
+        assert_eq!(put["request_id"], "r-1");
+        assert_eq!(put["tenant"], "acme");
+        assert_eq!((&put["key"], &put["size"]), (&json!("a"), &json!(3)));
+        assert!(lines[1..].iter().all(|line| line["message"] == "noisy"));
+    }
+}
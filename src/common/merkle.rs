@@ -0,0 +1,148 @@
+//! Merkle trees over key → BLAKE3 listings
+//!
+//! Used by anti-entropy to find divergent key ranges between what a volume
+//! actually stores and what it should store, without shipping full key lists.
+//! Keys are spread over a fixed number of leaf buckets; each leaf hashes the
+//! sorted `(key, blake3)` pairs of its bucket and parents hash their children.
+
+/// Number of leaf buckets per tree
+pub const MERKLE_LEAVES: usize = 256;
+
+/// A BLAKE3 digest
+pub type Digest = [u8; 32];
+
+/// Leaf bucket a key belongs to
+pub fn bucket_for(key: &str) -> usize {
+    // Use the last byte: the first ones already pick the shard
+    blake3::hash(key.as_bytes()).as_bytes()[31] as usize % MERKLE_LEAVES
+}
+
+/// Hash a bucket's entries (must be sorted by key)
+fn leaf_hash(entries: &[(&str, &str)]) -> Digest {
+    let mut hasher = blake3::Hasher::new();
+    for (key, hash) in entries {
+        hasher.update(key.as_bytes());
+        hasher.update(&[0]);
+        hasher.update(hash.as_bytes());
+        hasher.update(&[b'\n']);
+    }
+    *hasher.finalize().as_bytes()
+}
+
+/// Binary Merkle tree; `levels[0]` holds the leaves, the last level the root
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MerkleTree {
+    levels: Vec<Vec<Digest>>,
+}
+
+impl MerkleTree {
+    /// Build a tree from `(key, blake3)` pairs
+    pub fn build<'a, I>(entries: I) -> Self
+    where
+        I: IntoIterator<Item = (&'a str, &'a str)>,
+    {
+        let mut buckets: Vec<Vec<(&str, &str)>> = vec![Vec::new(); MERKLE_LEAVES];
+        for (key, hash) in entries {
+            buckets[bucket_for(key)].push((key, hash));
+        }
+
+        let leaves = buckets
+            .into_iter()
+            .map(|mut bucket| {
+                bucket.sort_unstable();
+                leaf_hash(&bucket)
+            })
+            .collect();
+
+        Self::from_leaves(leaves)
+    }
+
+    /// Rebuild a tree from leaf digests (e.g. received over gRPC)
+    pub fn from_leaves(mut leaves: Vec<Digest>) -> Self {
+        if leaves.is_empty() {
+            leaves.push(leaf_hash(&[]));
+        }
+
+        let mut levels = vec![leaves];
+        while levels.last().map(|l| l.len()).unwrap_or(0) > 1 {
+            let next = levels
+                .last()
+                .unwrap()
+                .chunks(2)
+                .map(|pair| {
+                    let mut hasher = blake3::Hasher::new();
+                    for digest in pair {
+                        hasher.update(digest);
+                    }
+                    *hasher.finalize().as_bytes()
+                })
+                .collect();
+            levels.push(next);
+        }
+
+        Self { levels }
+    }
+
+    /// Root digest
+    pub fn root(&self) -> Digest {
+        self.levels.last().unwrap()[0]
+    }
+
+    /// Leaf digests
+    pub fn leaves(&self) -> &[Digest] {
+        &self.levels[0]
+    }
+
+    /// Leaf buckets whose contents differ between the two trees.
+    /// Only descends into subtrees whose hashes differ.
+    pub fn diff(&self, other: &MerkleTree) -> Vec<usize> {
+        let leaf_count = self.levels[0].len();
+        if leaf_count != other.levels[0].len() {
+            return (0..leaf_count.max(other.levels[0].len())).collect();
+        }
+
+        let mut differing = vec![0usize];
+        for level in (0..self.levels.len() - 1).rev() {
+            differing = differing
+                .into_iter()
+                .filter(|&i| self.levels[level + 1][i] != other.levels[level + 1][i])
+                .flat_map(|i| [2 * i, 2 * i + 1])
+                .filter(|&child| child < self.levels[level].len())
+                .collect();
+        }
+
+        differing
+            .into_iter()
+            .filter(|&i| self.levels[0][i] != other.levels[0][i])
+            .collect()
+    }
+}
+
+/// Convert a digest received as bytes; malformed input maps to the zero digest
+pub fn digest_from_bytes(bytes: &[u8]) -> Digest {
+    bytes.try_into().unwrap_or([0u8; 32])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_identical_trees() {
+        let a = MerkleTree::build([("k1", "h1"), ("k2", "h2")]);
+        let b = MerkleTree::build([("k2", "h2"), ("k1", "h1")]);
+        assert_eq!(a.root(), b.root());
+        assert!(a.diff(&b).is_empty());
+    }
+
+    #[test]
+    fn test_diff_finds_bucket() {
+        let a = MerkleTree::build([("k1", "h1"), ("k2", "h2"), ("k3", "h3")]);
+        let b = MerkleTree::build([("k1", "h1"), ("k2", "stale"), ("k3", "h3")]);
+        assert_ne!(a.root(), b.root());
+        assert_eq!(a.diff(&b), vec![bucket_for("k2")]);
+
+        let rebuilt = MerkleTree::from_leaves(a.leaves().to_vec());
+        assert_eq!(rebuilt.root(), a.root());
+    }
+}
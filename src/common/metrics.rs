@@ -5,6 +5,8 @@
 //! - Request counters by endpoint and status
 //! - Error rates
 //! - System metrics
+//! - Raft state (term, commit/apply progress, elections, per-peer heartbeat latency)
+//! - WASM plugin calls, failures and latency
 
 use std::collections::HashMap;
 use std::sync::atomic::{AtomicU64, Ordering};
@@ -196,6 +198,81 @@ pub struct MetricsRegistry {
     pub compressed_blobs: Gauge,
     pub rate_limited_requests: Counter,
 
+    /// Connections refused by the IP allow/deny lists, per listener
+    pub ip_rejected_http: Counter,
+    pub ip_rejected_admin: Counter,
+    pub ip_rejected_grpc: Counter,
+
+    /// Audit entries delivered to, or dropped on the way to, the remote sink
+    pub audit_entries_shipped: Counter,
+    pub audit_entries_dropped: Counter,
+
+    /// Webhook notifications delivered, and those dead-lettered
+    pub webhook_deliveries: Counter,
+    pub webhook_dead_letters: Counter,
+
+    /// Debug and trace events dropped by log sampling
+    pub log_events_sampled_out: Counter,
+
+    /// Blob store operations running on the blocking pool, and those that
+    /// missed their deadline
+    pub store_io_in_flight: Gauge,
+    pub store_io_timeouts: Counter,
+
+    /// Writes admitted by the write budget and still running, the bytes
+    /// they hold, and writes refused because it was spent
+    pub writes_in_flight: Gauge,
+    pub write_bytes_in_flight: Gauge,
+    pub writes_rejected: Counter,
+
+    /// Volume compactions run, and the segment bytes they reclaimed
+    pub compactions: Counter,
+    pub compaction_reclaimed_bytes: Counter,
+
+    /// Volume records checked by scrubbing, and those found corrupted
+    pub scrubbed_records: Counter,
+    pub scrub_corrupted_records: Counter,
+
+    /// Volume bloom filter lookups let through for missing keys, keys it is
+    /// sized for, and rebuilds at a new size
+    pub bloom_false_positives: Counter,
+    pub bloom_capacity_keys: Gauge,
+    pub bloom_resizes: Counter,
+
+    /// 1 while the volume refuses writes for lack of free space
+    pub volume_read_only: Gauge,
+
+    /// Time the volume store took to open, and segments it scanned to
+    /// rebuild its index
+    pub volume_startup_ms: Gauge,
+    pub volume_startup_segments_scanned: Gauge,
+
+    /// Volume blob cache lookups and size
+    pub blob_cache_hits: Counter,
+    pub blob_cache_misses: Counter,
+    pub blob_cache_bytes: Gauge,
+
+    /// Bytes of the blob cache held per tenant
+    blob_cache_tenants: Mutex<HashMap<String, u64>>,
+
+    /// Raft state, refreshed by the Raft node
+    pub raft_term: Gauge,
+    pub raft_is_leader: Gauge,
+    pub raft_commit_index: Gauge,
+    pub raft_last_applied: Gauge,
+    pub raft_log_entries: Gauge,
+    pub raft_elections: Counter,
+    pub raft_leader_changes: Counter,
+
+    /// Heartbeat round-trip time per Raft peer
+    peer_heartbeats: Mutex<HashMap<String, Arc<Histogram>>>,
+
+    /// WAL fsync duration per sync policy
+    wal_syncs: Mutex<HashMap<String, Arc<Histogram>>>,
+
+    /// Calls, failures and latency per WASM plugin
+    plugins: Mutex<HashMap<String, Arc<EndpointMetrics>>>,
+
     /// Start time for uptime calculation
     start_time: Instant,
 }
@@ -213,6 +290,43 @@ impl MetricsRegistry {
             keys_with_ttl: Gauge::new(),
             compressed_blobs: Gauge::new(),
             rate_limited_requests: Counter::new(),
+            ip_rejected_http: Counter::new(),
+            ip_rejected_admin: Counter::new(),
+            ip_rejected_grpc: Counter::new(),
+            audit_entries_shipped: Counter::new(),
+            audit_entries_dropped: Counter::new(),
+            webhook_deliveries: Counter::new(),
+            webhook_dead_letters: Counter::new(),
+            log_events_sampled_out: Counter::new(),
+            store_io_in_flight: Gauge::new(),
+            store_io_timeouts: Counter::new(),
+            writes_in_flight: Gauge::new(),
+            write_bytes_in_flight: Gauge::new(),
+            writes_rejected: Counter::new(),
+            compactions: Counter::new(),
+            compaction_reclaimed_bytes: Counter::new(),
+            scrubbed_records: Counter::new(),
+            scrub_corrupted_records: Counter::new(),
+            bloom_false_positives: Counter::new(),
+            bloom_capacity_keys: Gauge::new(),
+            bloom_resizes: Counter::new(),
+            volume_read_only: Gauge::new(),
+            volume_startup_ms: Gauge::new(),
+            volume_startup_segments_scanned: Gauge::new(),
+            blob_cache_hits: Counter::new(),
+            blob_cache_misses: Counter::new(),
+            blob_cache_bytes: Gauge::new(),
+            blob_cache_tenants: Mutex::new(HashMap::new()),
+            raft_term: Gauge::new(),
+            raft_is_leader: Gauge::new(),
+            raft_commit_index: Gauge::new(),
+            raft_last_applied: Gauge::new(),
+            raft_log_entries: Gauge::new(),
+            raft_elections: Counter::new(),
+            raft_leader_changes: Counter::new(),
+            peer_heartbeats: Mutex::new(HashMap::new()),
+            wal_syncs: Mutex::new(HashMap::new()),
+            plugins: Mutex::new(HashMap::new()),
             start_time: Instant::now(),
         }
     }
@@ -243,6 +357,58 @@ impl MetricsRegistry {
         }
     }
 
+    /// Record the round-trip time of a heartbeat to a Raft peer
+    pub fn record_heartbeat(&self, peer: &str, duration: Duration) {
+        let histogram = self
+            .peer_heartbeats
+            .lock()
+            .unwrap()
+            .entry(peer.to_string())
+            .or_insert_with(|| Arc::new(Histogram::new()))
+            .clone();
+        histogram.observe(duration.as_secs_f64() * 1000.0);
+    }
+
+    /// Record the duration of a WAL fsync under `policy` ("always", "group")
+    pub fn record_wal_sync(&self, policy: &str, duration: Duration) {
+        let histogram = self
+            .wal_syncs
+            .lock()
+            .unwrap()
+            .entry(policy.to_string())
+            .or_insert_with(|| Arc::new(Histogram::new()))
+            .clone();
+        histogram.observe(duration.as_secs_f64() * 1000.0);
+    }
+
+    /// Record a call of a WASM plugin, failed if it trapped or timed out
+    pub fn record_plugin_call(&self, plugin: &str, duration: Duration, success: bool) {
+        let metrics = self
+            .plugins
+            .lock()
+            .unwrap()
+            .entry(plugin.to_string())
+            .or_insert_with(|| Arc::new(EndpointMetrics::new()))
+            .clone();
+        metrics.requests_total.inc();
+        metrics.latency.observe(duration.as_secs_f64() * 1000.0);
+        if success {
+            metrics.requests_success.inc();
+        } else {
+            metrics.requests_error.inc();
+        }
+    }
+
+    /// Set the bytes of the blob cache held by `tenant`
+    pub fn set_blob_cache_tenant_bytes(&self, tenant: &str, bytes: u64) {
+        let mut tenants = self.blob_cache_tenants.lock().unwrap();
+        if bytes == 0 {
+            tenants.remove(tenant);
+        } else {
+            tenants.insert(tenant.to_string(), bytes);
+        }
+    }
+
     /// Get uptime in seconds
     pub fn uptime_seconds(&self) -> u64 {
         self.start_time.elapsed().as_secs()
@@ -293,6 +459,15 @@ impl MetricsRegistry {
         out.push_str("# TYPE minikv_keys_with_ttl gauge\n");
         writeln!(out, "minikv_keys_with_ttl {}", self.keys_with_ttl.get()).unwrap();
 
+        out.push_str("# HELP minikv_compressed_blobs Number of blobs stored compressed\n");
+        out.push_str("# TYPE minikv_compressed_blobs gauge\n");
+        writeln!(
+            out,
+            "minikv_compressed_blobs {}",
+            self.compressed_blobs.get()
+        )
+        .unwrap();
+
         out.push_str("# HELP minikv_rate_limited_requests Total rate limited requests\n");
         out.push_str("# TYPE minikv_rate_limited_requests counter\n");
         writeln!(
@@ -302,10 +477,376 @@ impl MetricsRegistry {
         )
         .unwrap();
 
+        out.push_str(
+            "# HELP minikv_ip_rejected_total Connections refused by the IP allow/deny lists\n",
+        );
+        out.push_str("# TYPE minikv_ip_rejected_total counter\n");
+        for (listener, counter) in [
+            ("http", &self.ip_rejected_http),
+            ("admin", &self.ip_rejected_admin),
+            ("grpc", &self.ip_rejected_grpc),
+        ] {
+            writeln!(
+                out,
+                "minikv_ip_rejected_total{{listener=\"{}\"}} {}",
+                listener,
+                counter.get()
+            )
+            .unwrap();
+        }
+
+        out.push_str(
+            "# HELP minikv_audit_entries_shipped Audit entries delivered to the remote sink\n",
+        );
+        out.push_str("# TYPE minikv_audit_entries_shipped counter\n");
+        writeln!(
+            out,
+            "minikv_audit_entries_shipped {}",
+            self.audit_entries_shipped.get()
+        )
+        .unwrap();
+
+        out.push_str(
+            "# HELP minikv_audit_entries_dropped Audit entries the remote sink never got\n",
+        );
+        out.push_str("# TYPE minikv_audit_entries_dropped counter\n");
+        writeln!(
+            out,
+            "minikv_audit_entries_dropped {}",
+            self.audit_entries_dropped.get()
+        )
+        .unwrap();
+
+        out.push_str("# HELP minikv_webhook_deliveries Webhook notifications delivered\n");
+        out.push_str("# TYPE minikv_webhook_deliveries counter\n");
+        writeln!(
+            out,
+            "minikv_webhook_deliveries {}",
+            self.webhook_deliveries.get()
+        )
+        .unwrap();
+
+        out.push_str("# HELP minikv_webhook_dead_letters Webhook notifications given up on\n");
+        out.push_str("# TYPE minikv_webhook_dead_letters counter\n");
+        writeln!(
+            out,
+            "minikv_webhook_dead_letters {}",
+            self.webhook_dead_letters.get()
+        )
+        .unwrap();
+
+        out.push_str(
+            "# HELP minikv_log_events_sampled_out Debug and trace events dropped by log sampling\n",
+        );
+        out.push_str("# TYPE minikv_log_events_sampled_out counter\n");
+        writeln!(
+            out,
+            "minikv_log_events_sampled_out {}",
+            self.log_events_sampled_out.get()
+        )
+        .unwrap();
+
+        out.push_str(
+            "# HELP minikv_store_io_in_flight Blob store operations running on the blocking pool\n",
+        );
+        out.push_str("# TYPE minikv_store_io_in_flight gauge\n");
+        writeln!(
+            out,
+            "minikv_store_io_in_flight {}",
+            self.store_io_in_flight.get()
+        )
+        .unwrap();
+
+        out.push_str(
+            "# HELP minikv_store_io_timeouts Blob store operations that missed their deadline\n",
+        );
+        out.push_str("# TYPE minikv_store_io_timeouts counter\n");
+        writeln!(
+            out,
+            "minikv_store_io_timeouts {}",
+            self.store_io_timeouts.get()
+        )
+        .unwrap();
+
+        out.push_str("# HELP minikv_writes_in_flight Writes admitted by the write budget\n");
+        out.push_str("# TYPE minikv_writes_in_flight gauge\n");
+        writeln!(
+            out,
+            "minikv_writes_in_flight {}",
+            self.writes_in_flight.get()
+        )
+        .unwrap();
+
+        out.push_str(
+            "# HELP minikv_write_bytes_in_flight Bytes of the writes admitted by the write budget\n",
+        );
+        out.push_str("# TYPE minikv_write_bytes_in_flight gauge\n");
+        writeln!(
+            out,
+            "minikv_write_bytes_in_flight {}",
+            self.write_bytes_in_flight.get()
+        )
+        .unwrap();
+
+        out.push_str(
+            "# HELP minikv_writes_rejected_total Writes refused with 503 by the write budget\n",
+        );
+        out.push_str("# TYPE minikv_writes_rejected_total counter\n");
+        writeln!(
+            out,
+            "minikv_writes_rejected_total {}",
+            self.writes_rejected.get()
+        )
+        .unwrap();
+
+        out.push_str("# HELP minikv_compactions_total Volume compactions run\n");
+        out.push_str("# TYPE minikv_compactions_total counter\n");
+        writeln!(out, "minikv_compactions_total {}", self.compactions.get()).unwrap();
+        out.push_str(
+            "# HELP minikv_compaction_reclaimed_bytes_total Segment bytes reclaimed by compaction\n",
+        );
+        out.push_str("# TYPE minikv_compaction_reclaimed_bytes_total counter\n");
+        writeln!(
+            out,
+            "minikv_compaction_reclaimed_bytes_total {}",
+            self.compaction_reclaimed_bytes.get()
+        )
+        .unwrap();
+
+        out.push_str("# HELP minikv_scrubbed_records_total Volume records checked by scrubbing\n");
+        out.push_str("# TYPE minikv_scrubbed_records_total counter\n");
+        writeln!(
+            out,
+            "minikv_scrubbed_records_total {}",
+            self.scrubbed_records.get()
+        )
+        .unwrap();
+        out.push_str(
+            "# HELP minikv_scrub_corrupted_records_total Volume records found corrupted and quarantined by scrubbing\n",
+        );
+        out.push_str("# TYPE minikv_scrub_corrupted_records_total counter\n");
+        writeln!(
+            out,
+            "minikv_scrub_corrupted_records_total {}",
+            self.scrub_corrupted_records.get()
+        )
+        .unwrap();
+
+        out.push_str(
+            "# HELP minikv_bloom_false_positives_total Lookups the volume bloom filter let through for missing keys\n",
+        );
+        out.push_str("# TYPE minikv_bloom_false_positives_total counter\n");
+        writeln!(
+            out,
+            "minikv_bloom_false_positives_total {}",
+            self.bloom_false_positives.get()
+        )
+        .unwrap();
+        out.push_str(
+            "# HELP minikv_bloom_capacity_keys Keys the volume bloom filter is sized for\n",
+        );
+        out.push_str("# TYPE minikv_bloom_capacity_keys gauge\n");
+        writeln!(
+            out,
+            "minikv_bloom_capacity_keys {}",
+            self.bloom_capacity_keys.get()
+        )
+        .unwrap();
+        out.push_str(
+            "# HELP minikv_bloom_resizes_total Volume bloom filter rebuilds at a new size\n",
+        );
+        out.push_str("# TYPE minikv_bloom_resizes_total counter\n");
+        writeln!(
+            out,
+            "minikv_bloom_resizes_total {}",
+            self.bloom_resizes.get()
+        )
+        .unwrap();
+        out.push_str(
+            "# HELP minikv_volume_read_only Whether the volume refuses writes for lack of free space\n",
+        );
+        out.push_str("# TYPE minikv_volume_read_only gauge\n");
+        writeln!(
+            out,
+            "minikv_volume_read_only {}",
+            self.volume_read_only.get()
+        )
+        .unwrap();
+        out.push_str("# HELP minikv_volume_startup_seconds Time the volume store took to open\n");
+        out.push_str("# TYPE minikv_volume_startup_seconds gauge\n");
+        writeln!(
+            out,
+            "minikv_volume_startup_seconds {:.3}",
+            self.volume_startup_ms.get() as f64 / 1000.0
+        )
+        .unwrap();
+        out.push_str(
+            "# HELP minikv_volume_startup_segments_scanned Segments scanned to rebuild the volume index on open\n",
+        );
+        out.push_str("# TYPE minikv_volume_startup_segments_scanned gauge\n");
+        writeln!(
+            out,
+            "minikv_volume_startup_segments_scanned {}",
+            self.volume_startup_segments_scanned.get()
+        )
+        .unwrap();
+
+        out.push_str("# HELP minikv_blob_cache_requests_total Volume blob cache lookups\n");
+        out.push_str("# TYPE minikv_blob_cache_requests_total counter\n");
+        for (result, counter) in [
+            ("hit", &self.blob_cache_hits),
+            ("miss", &self.blob_cache_misses),
+        ] {
+            writeln!(
+                out,
+                "minikv_blob_cache_requests_total{{result=\"{}\"}} {}",
+                result,
+                counter.get()
+            )
+            .unwrap();
+        }
+
+        out.push_str("# HELP minikv_blob_cache_bytes Bytes held by the volume blob cache\n");
+        out.push_str("# TYPE minikv_blob_cache_bytes gauge\n");
+        writeln!(
+            out,
+            "minikv_blob_cache_bytes {}",
+            self.blob_cache_bytes.get()
+        )
+        .unwrap();
+        out.push_str(
+            "# HELP minikv_blob_cache_tenant_bytes Bytes of the blob cache held per tenant\n",
+        );
+        out.push_str("# TYPE minikv_blob_cache_tenant_bytes gauge\n");
+        for (tenant, bytes) in self.blob_cache_tenants.lock().unwrap().iter() {
+            writeln!(
+                out,
+                "minikv_blob_cache_tenant_bytes{{tenant=\"{}\"}} {}",
+                tenant, bytes
+            )
+            .unwrap();
+        }
+
         out.push_str("# HELP minikv_uptime_seconds Server uptime in seconds\n");
         out.push_str("# TYPE minikv_uptime_seconds gauge\n");
         writeln!(out, "minikv_uptime_seconds {}", self.uptime_seconds()).unwrap();
 
+        // Raft metrics
+        let raft_gauges = [
+            ("minikv_raft_term", "Current Raft term", &self.raft_term),
+            (
+                "minikv_raft_is_leader",
+                "Whether this node is the Raft leader (1) or not (0)",
+                &self.raft_is_leader,
+            ),
+            (
+                "minikv_raft_commit_index",
+                "Highest Raft log index known to be committed",
+                &self.raft_commit_index,
+            ),
+            (
+                "minikv_raft_last_applied",
+                "Highest Raft log index applied to the metadata store",
+                &self.raft_last_applied,
+            ),
+            (
+                "minikv_raft_log_entries",
+                "Raft log entries not yet compacted into a snapshot",
+                &self.raft_log_entries,
+            ),
+        ];
+        for (name, help, gauge) in raft_gauges {
+            writeln!(out, "# HELP {} {}", name, help).unwrap();
+            writeln!(out, "# TYPE {} gauge", name).unwrap();
+            writeln!(out, "{} {}", name, gauge.get()).unwrap();
+        }
+
+        out.push_str("# HELP minikv_raft_elections_total Elections started by this node\n");
+        out.push_str("# TYPE minikv_raft_elections_total counter\n");
+        writeln!(
+            out,
+            "minikv_raft_elections_total {}",
+            self.raft_elections.get()
+        )
+        .unwrap();
+
+        out.push_str("# HELP minikv_raft_leader_changes_total Times this node saw a new leader\n");
+        out.push_str("# TYPE minikv_raft_leader_changes_total counter\n");
+        writeln!(
+            out,
+            "minikv_raft_leader_changes_total {}",
+            self.raft_leader_changes.get()
+        )
+        .unwrap();
+
+        let peer_heartbeats = self.peer_heartbeats.lock().unwrap();
+        out.push_str(
+            "# HELP minikv_raft_heartbeat_duration_ms Heartbeat round-trip time per Raft peer\n",
+        );
+        out.push_str("# TYPE minikv_raft_heartbeat_duration_ms histogram\n");
+        for (peer, latency) in peer_heartbeats.iter() {
+            write_histogram(
+                &mut out,
+                "minikv_raft_heartbeat_duration_ms",
+                "peer",
+                peer,
+                latency,
+            );
+        }
+        drop(peer_heartbeats);
+
+        let wal_syncs = self.wal_syncs.lock().unwrap();
+        out.push_str("# HELP minikv_wal_sync_duration_ms WAL fsync duration per sync policy\n");
+        out.push_str("# TYPE minikv_wal_sync_duration_ms histogram\n");
+        for (policy, latency) in wal_syncs.iter() {
+            write_histogram(
+                &mut out,
+                "minikv_wal_sync_duration_ms",
+                "policy",
+                policy,
+                latency,
+            );
+        }
+        drop(wal_syncs);
+
+        let plugins = self.plugins.lock().unwrap();
+        out.push_str("# HELP minikv_plugin_calls_total Calls per WASM plugin\n");
+        out.push_str("# TYPE minikv_plugin_calls_total counter\n");
+        for (plugin, metrics) in plugins.iter() {
+            writeln!(
+                out,
+                "minikv_plugin_calls_total{{plugin=\"{}\"}} {}",
+                plugin,
+                metrics.requests_total.get()
+            )
+            .unwrap();
+        }
+        out.push_str(
+            "# HELP minikv_plugin_failures_total WASM plugin calls that trapped or timed out\n",
+        );
+        out.push_str("# TYPE minikv_plugin_failures_total counter\n");
+        for (plugin, metrics) in plugins.iter() {
+            writeln!(
+                out,
+                "minikv_plugin_failures_total{{plugin=\"{}\"}} {}",
+                plugin,
+                metrics.requests_error.get()
+            )
+            .unwrap();
+        }
+        out.push_str("# HELP minikv_plugin_duration_ms WASM plugin call duration\n");
+        out.push_str("# TYPE minikv_plugin_duration_ms histogram\n");
+        for (plugin, metrics) in plugins.iter() {
+            write_histogram(
+                &mut out,
+                "minikv_plugin_duration_ms",
+                "plugin",
+                plugin,
+                &metrics.latency,
+            );
+        }
+        drop(plugins);
+
         // Per-endpoint metrics
         let endpoints = self.endpoints.lock().unwrap();
 
@@ -337,43 +878,55 @@ impl MetricsRegistry {
         out.push_str("# HELP minikv_request_duration_ms Request duration in milliseconds\n");
         out.push_str("# TYPE minikv_request_duration_ms histogram\n");
         for (path, metrics) in endpoints.iter() {
-            for (le, count) in metrics.latency.get_buckets() {
-                if le.is_infinite() {
-                    writeln!(
-                        out,
-                        "minikv_request_duration_ms_bucket{{path=\"{}\",le=\"+Inf\"}} {}",
-                        path, count
-                    )
-                    .unwrap();
-                } else {
-                    writeln!(
-                        out,
-                        "minikv_request_duration_ms_bucket{{path=\"{}\",le=\"{}\"}} {}",
-                        path, le, count
-                    )
-                    .unwrap();
-                }
-            }
-            writeln!(
-                out,
-                "minikv_request_duration_ms_sum{{path=\"{}\"}} {}",
+            write_histogram(
+                &mut out,
+                "minikv_request_duration_ms",
+                "path",
                 path,
-                metrics.latency.sum()
-            )
-            .unwrap();
-            writeln!(
-                out,
-                "minikv_request_duration_ms_count{{path=\"{}\"}} {}",
-                path,
-                metrics.latency.count()
-            )
-            .unwrap();
+                &metrics.latency,
+            );
         }
 
         out
     }
 }
 
+/// Write the buckets, sum and count of one labelled histogram series
+fn write_histogram(out: &mut String, name: &str, label: &str, value: &str, histogram: &Histogram) {
+    use std::fmt::Write;
+    for (le, count) in histogram.get_buckets() {
+        let le = if le.is_infinite() {
+            "+Inf".to_string()
+        } else {
+            le.to_string()
+        };
+        writeln!(
+            out,
+            "{}_bucket{{{}=\"{}\",le=\"{}\"}} {}",
+            name, label, value, le, count
+        )
+        .unwrap();
+    }
+    writeln!(
+        out,
+        "{}_sum{{{}=\"{}\"}} {}",
+        name,
+        label,
+        value,
+        histogram.sum()
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "{}_count{{{}=\"{}\"}} {}",
+        name,
+        label,
+        value,
+        histogram.count()
+    )
+    .unwrap();
+}
+
 impl Default for MetricsRegistry {
     fn default() -> Self {
         Self::new()
@@ -426,6 +979,25 @@ mod tests {
         assert_eq!(gauge.get(), 10);
     }
 
+    #[test]
+    fn test_raft_metrics() {
+        let registry = MetricsRegistry::new();
+
+        registry.raft_term.set(7);
+        registry.raft_elections.inc();
+        registry.record_heartbeat("http://10.0.0.2:5001", Duration::from_millis(3));
+
+        let out = registry.to_prometheus();
+        assert!(out.contains("minikv_raft_term 7\n"));
+        assert!(out.contains("minikv_raft_elections_total 1\n"));
+        assert!(out.contains(
+            "minikv_raft_heartbeat_duration_ms_bucket{peer=\"http://10.0.0.2:5001\",le=\"5\"} 1\n"
+        ));
+        assert!(out.contains(
+            "minikv_raft_heartbeat_duration_ms_count{peer=\"http://10.0.0.2:5001\"} 1\n"
+        ));
+    }
+
     #[test]
     fn test_metrics_registry() {
         let registry = MetricsRegistry::new();
@@ -1,43 +1,87 @@
 pub mod storage;
 pub use storage::{KVStore, MemStore, Storage};
+pub mod acl;
 pub mod audit;
+pub mod audit_shipper;
 /// Common utilities and types shared across minikv
 pub mod auth;
 pub mod auth_middleware;
+pub mod chaos;
+pub mod command;
 pub mod config;
 pub mod encryption;
 pub mod error;
+pub mod grpc_auth;
 pub mod hash;
+pub mod ip_filter;
+pub mod lifecycle;
+pub mod logging;
+pub mod merkle;
 pub mod metrics;
 pub mod quota;
 pub mod raft;
+pub mod range;
 pub mod ratelimit;
+pub mod reload;
+pub mod s3_client;
+pub mod shutdown;
+pub mod tls;
 pub mod tracing_middleware;
 pub mod utils;
+pub mod write_budget;
 
+pub use acl::{AclRule, AclStore, ACL_STORE};
 pub use auth::{ApiKey, AuthConfig, AuthContext, AuthError, AuthResult, KeyStore, Role, KEY_STORE};
 pub use auth_middleware::{
-    auth_middleware, get_tenant_from_request, is_admin_request, require_admin_middleware,
-    require_write_middleware, AuthExtension, AuthState,
+    acl_middleware, auth_middleware, get_tenant_from_request, is_admin_request,
+    require_admin_middleware, require_write_middleware, AuthDisabled, AuthExtension, AuthState,
+};
+pub use chaos::{FaultInjector, Faults, FAULTS};
+pub use command::VolumeCommand;
+pub use config::{
+    BlobCacheConfig, CdcConfig, CdcSinkConfig, ChunkingConfig, CompressionConfig, CompressionMode,
+    Config, ConfigSources, CoordinatorConfig, DataPaths, FailureDomain, GroupCommitConfig,
+    HotKeysConfig, LogConfig, LogFormat, NodeRole, PluginConfig, PluginHook, QuotaConfig,
+    RedirectConfig, ReplicationConfig, ReplicationTarget, RuntimeConfig, StoreIoConfig, TierPolicy,
+    TierReadMode, TieringConfig, VolumeConfig, WalSyncPolicy, WebhookConfig, WebhooksConfig,
+    WriteBudgetConfig,
 };
-pub use config::{Config, CoordinatorConfig, NodeRole, RuntimeConfig, VolumeConfig, WalSyncPolicy};
 pub use encryption::{
     maybe_decrypt, maybe_encrypt, EncryptedData, EncryptionConfig, EncryptionError,
-    EncryptionManager, EncryptionResult, EncryptionStatus, ENCRYPTION_MANAGER,
+    EncryptionManager, EncryptionResult, EncryptionStatus, KeyProvider, KeyProviderConfig,
+    VersionedKey, ENCRYPTION_MANAGER, MASTER_KEY_ENV,
 };
 pub use error::{Error, Result};
+pub use grpc_auth::{
+    check_http_token, check_token, check_transfer, configure_grpc_auth, connect_internal,
+    sign_transfer, tokens_enabled, AttachToken, GrpcAuthConfig, InternalChannel,
+};
 pub use hash::{
-    blake3_hash, blob_prefix, hrw_hash, select_replicas, shard_key, Blake3Hasher,
-    ConsistentHashRing,
+    blake3_hash, blob_prefix, hrw_hash, select_replicas, shard_key, weighted_hrw_hash,
+    Blake3Hasher, ConsistentHashRing,
 };
+pub use ip_filter::{ip_filter_middleware, IpFilter, IpFilterConfig, IpListConfig};
 pub use metrics::{Counter, Gauge, Histogram, MetricsRegistry, METRICS};
 pub use quota::{QuotaCheckResult, QuotaManager, TenantQuota, TenantUsage, QUOTA_MANAGER};
-pub use ratelimit::{RateLimitConfig, RateLimitResult, RateLimitStats, RateLimiter};
+pub use range::ByteRange;
+pub use ratelimit::{
+    start_eviction_task, IdleEviction, RateLimitConfig, RateLimitResult, RateLimitStats,
+    RateLimiter, SlidingWindow,
+};
+pub use reload::{ReloadReport, Reloader, RELOADER};
+pub use s3_client::S3Client;
+pub use shutdown::{Shutdown, SHUTDOWN};
+pub use tls::{
+    check_peer, configure_grpc_tls, connect_channel, server_tls_config, GrpcTls, GrpcTlsConfig,
+};
 pub use tracing_middleware::{
     generate_request_id, request_id_middleware, request_tracing_middleware, REQUEST_ID_HEADER,
 };
 pub use utils::{
-    crc32, decode_key, encode_key, format_bytes, parse_duration, timestamp_now, NodeState,
+    crc32, decode_key, disk_free_bytes, encode_key, format_bytes, parse_duration, timestamp_now,
+    NodeState,
 };
+pub use write_budget::{write_budget_middleware, WriteBudget, WritePermit};
 
-pub use audit::{AuditEntry, AuditEventType, AuditLogger, AUDIT_LOGGER};
+pub use audit::{AuditConfig, AuditEntry, AuditEventType, AuditLogger, AUDIT_LOGGER};
+pub use audit_shipper::{AuditShipConfig, AuditShipper, AuditSinkConfig};
@@ -4,9 +4,11 @@
 //! - Storage limits (bytes)
 //! - Object count limits
 //! - Request rate limiting
+//! - Ingress/egress bandwidth limits (bytes per second)
 //!
 //! Quotas can be configured per-tenant and are enforced at the coordinator level.
 
+use crate::common::ratelimit::{IdleEviction, SlidingWindow};
 use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
@@ -18,6 +20,8 @@ const DEFAULT_STORAGE_LIMIT: u64 = 10 * 1024 * 1024 * 1024; // 10 GB
 const DEFAULT_OBJECT_LIMIT: u64 = 1_000_000; // 1 million objects
 const DEFAULT_RATE_LIMIT: u32 = 1000; // requests per second
 const DEFAULT_RATE_WINDOW: Duration = Duration::from_secs(1);
+/// Window over which bandwidth rates are measured
+const BANDWIDTH_WINDOW: Duration = Duration::from_secs(1);
 
 /// Global quota manager instance
 pub static QUOTA_MANAGER: Lazy<QuotaManager> = Lazy::new(QuotaManager::new);
@@ -33,6 +37,10 @@ pub struct TenantQuota {
     pub object_limit: u64,
     /// Maximum requests per rate window (0 = unlimited)
     pub rate_limit: u32,
+    /// Maximum bytes per second received from the tenant (0 = unlimited)
+    pub ingress_limit: u64,
+    /// Maximum bytes per second sent to the tenant (0 = unlimited)
+    pub egress_limit: u64,
     /// Whether the tenant is enabled
     pub enabled: bool,
     /// When the quota was created
@@ -48,6 +56,8 @@ impl TenantQuota {
             storage_limit: DEFAULT_STORAGE_LIMIT,
             object_limit: DEFAULT_OBJECT_LIMIT,
             rate_limit: DEFAULT_RATE_LIMIT,
+            ingress_limit: 0,
+            egress_limit: 0,
             enabled: true,
             created_at: Some(Instant::now()),
         }
@@ -60,6 +70,8 @@ impl TenantQuota {
             storage_limit: 0,
             object_limit: 0,
             rate_limit: 0,
+            ingress_limit: 0,
+            egress_limit: 0,
             enabled: true,
             created_at: Some(Instant::now()),
         }
@@ -77,6 +89,8 @@ impl TenantQuota {
             storage_limit,
             object_limit,
             rate_limit,
+            ingress_limit: 0,
+            egress_limit: 0,
             enabled: true,
             created_at: Some(Instant::now()),
         }
@@ -90,8 +104,96 @@ pub struct TenantUsage {
     pub storage_used: u64,
     /// Current number of objects
     pub object_count: u64,
-    /// Request timestamps for rate limiting
-    pub request_times: Vec<Instant>,
+    /// Requests over the last rate window, for rate limiting
+    pub requests: SlidingWindow,
+    /// Bytes received from the tenant
+    pub ingress: ByteRate,
+    /// Bytes sent to the tenant
+    pub egress: ByteRate,
+}
+
+/// Direction of a tenant's traffic, for bandwidth limits
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Request bodies (uploads)
+    Ingress,
+    /// Response bodies (downloads)
+    Egress,
+}
+
+/// Token bucket holding a tenant's traffic in one direction to its limit,
+/// along with a meter of its current rate. Transfers reserve their bytes
+/// up front and wait out any debt, so concurrent transfers of a tenant
+/// share its limit in arrival order.
+#[derive(Debug, Clone, Default)]
+pub struct ByteRate {
+    /// Bytes that may pass without waiting; negative while in debt
+    tokens: f64,
+    /// When `tokens` was last refilled, `None` before the first transfer
+    refilled_at: Option<Instant>,
+    /// Start of the current measurement window
+    window_start: Option<Instant>,
+    /// Bytes seen in the current window
+    window_bytes: u64,
+    /// Bytes per second over the last complete window
+    last_rate: u64,
+}
+
+impl ByteRate {
+    /// Account `bytes` passing at `now` under a limit of `limit` bytes per
+    /// second (0 = unlimited), returning how long to wait before sending them.
+    /// Up to one second worth of bytes may burst.
+    pub fn reserve(&mut self, limit: u64, bytes: u64, now: Instant) -> Duration {
+        self.measure(bytes, now);
+        if limit == 0 {
+            self.refilled_at = None;
+            return Duration::ZERO;
+        }
+        let limit = limit as f64;
+        self.tokens = match self.refilled_at {
+            Some(at) => (self.tokens + now.duration_since(at).as_secs_f64() * limit).min(limit),
+            None => limit,
+        };
+        self.refilled_at = Some(now);
+        self.tokens -= bytes as f64;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / limit)
+        }
+    }
+
+    fn measure(&mut self, bytes: u64, now: Instant) {
+        let start = *self.window_start.get_or_insert(now);
+        let elapsed = now.duration_since(start);
+        if elapsed >= BANDWIDTH_WINDOW {
+            self.last_rate = if elapsed >= 2 * BANDWIDTH_WINDOW {
+                0
+            } else {
+                (self.window_bytes as f64 / elapsed.as_secs_f64()) as u64
+            };
+            self.window_start = Some(now);
+            self.window_bytes = 0;
+        }
+        self.window_bytes += bytes;
+    }
+
+    /// Whether nothing moved for two windows, so the state can be dropped
+    pub fn is_idle(&self, now: Instant) -> bool {
+        let recent = |at: Option<Instant>| matches!(at, Some(at) if now.saturating_duration_since(at) < 2 * BANDWIDTH_WINDOW);
+        !recent(self.window_start) && !recent(self.refilled_at)
+    }
+
+    /// Bytes per second over the last complete window, 0 once idle
+    pub fn rate(&self, now: Instant) -> u64 {
+        match self.window_start {
+            Some(start) if now.duration_since(start) < BANDWIDTH_WINDOW => self.last_rate,
+            Some(start) if now.duration_since(start) < 2 * BANDWIDTH_WINDOW => {
+                (self.window_bytes as f64 / now.duration_since(start).as_secs_f64()) as u64
+            }
+            _ => 0,
+        }
+    }
 }
 
 impl TenantUsage {
@@ -117,19 +219,27 @@ impl TenantUsage {
             return true; // Unlimited
         }
 
-        let now = Instant::now();
-        let window_start = now - DEFAULT_RATE_WINDOW;
-
-        // Remove old request timestamps
-        self.request_times.retain(|&t| t > window_start);
-
-        // Check if under the limit
-        (self.request_times.len() as u32) < quota.rate_limit
+        self.request_rate() < quota.rate_limit as f64
     }
 
     /// Record a request for rate limiting
     pub fn record_request(&mut self) {
-        self.request_times.push(Instant::now());
+        self.requests.record(DEFAULT_RATE_WINDOW, Instant::now());
+    }
+
+    /// Requests over the last rate window
+    pub fn request_rate(&self) -> f64 {
+        self.requests.estimate(DEFAULT_RATE_WINDOW, Instant::now())
+    }
+
+    /// Whether nothing but rate state is kept: no stored data, no recent
+    /// requests or transfers
+    fn is_idle(&self, now: Instant) -> bool {
+        self.storage_used == 0
+            && self.object_count == 0
+            && self.requests.is_idle(DEFAULT_RATE_WINDOW, now)
+            && self.ingress.is_idle(now)
+            && self.egress.is_idle(now)
     }
 
     /// Update storage usage
@@ -183,6 +293,18 @@ impl QuotaCheckResult {
         )
     }
 
+    /// HTTP status answering a request refused with this result
+    pub fn http_status(&self) -> axum::http::StatusCode {
+        use axum::http::StatusCode;
+        match self {
+            QuotaCheckResult::Allowed | QuotaCheckResult::TenantNotFound => StatusCode::OK,
+            QuotaCheckResult::StorageLimitExceeded { .. }
+            | QuotaCheckResult::ObjectLimitExceeded { .. } => StatusCode::INSUFFICIENT_STORAGE,
+            QuotaCheckResult::RateLimitExceeded { .. } => StatusCode::TOO_MANY_REQUESTS,
+            QuotaCheckResult::TenantDisabled => StatusCode::FORBIDDEN,
+        }
+    }
+
     /// Convert to an error message if not allowed
     pub fn error_message(&self) -> Option<String> {
         match self {
@@ -215,7 +337,7 @@ pub struct QuotaManager {
     /// Current usage per tenant
     usage: RwLock<HashMap<String, TenantUsage>>,
     /// Default quota for tenants without explicit configuration
-    default_quota: TenantQuota,
+    default_quota: RwLock<TenantQuota>,
 }
 
 impl QuotaManager {
@@ -224,13 +346,27 @@ impl QuotaManager {
         Self {
             quotas: RwLock::new(HashMap::new()),
             usage: RwLock::new(HashMap::new()),
-            default_quota: TenantQuota::new("__default__".to_string()),
+            default_quota: RwLock::new(TenantQuota::new("__default__".to_string())),
         }
     }
 
     /// Set the default quota for new tenants
-    pub fn set_default_quota(&mut self, quota: TenantQuota) {
-        self.default_quota = quota;
+    pub fn set_default_quota(&self, quota: TenantQuota) {
+        *self.default_quota.write().unwrap() = quota;
+    }
+
+    /// Quota of tenants without one of their own
+    pub fn get_default_quota(&self) -> TenantQuota {
+        self.default_quota.read().unwrap().clone()
+    }
+
+    /// Quota applying to a tenant: its own, or the default one
+    fn effective_quota(&self, tenant_id: &str) -> TenantQuota {
+        let quotas = self.quotas.read().unwrap();
+        match quotas.get(tenant_id) {
+            Some(quota) => quota.clone(),
+            None => self.default_quota.read().unwrap().clone(),
+        }
     }
 
     /// Create or update a tenant's quota
@@ -263,10 +399,18 @@ impl QuotaManager {
         quotas.values().cloned().collect()
     }
 
+    /// Replace all configured quotas (loaded from persistent storage)
+    pub fn load_quotas(&self, quotas: impl IntoIterator<Item = TenantQuota>) {
+        let mut map = self.quotas.write().unwrap();
+        map.clear();
+        for quota in quotas {
+            map.insert(quota.tenant_id.clone(), quota);
+        }
+    }
+
     /// Check if a storage operation is allowed
     pub fn check_storage(&self, tenant_id: &str, additional_bytes: u64) -> QuotaCheckResult {
-        let quotas = self.quotas.read().unwrap();
-        let quota = quotas.get(tenant_id).unwrap_or(&self.default_quota);
+        let quota = self.effective_quota(tenant_id);
 
         if !quota.enabled {
             return QuotaCheckResult::TenantDisabled;
@@ -275,7 +419,7 @@ impl QuotaManager {
         let usage = self.usage.read().unwrap();
         let tenant_usage = usage.get(tenant_id).cloned().unwrap_or_default();
 
-        if tenant_usage.check_storage(quota, additional_bytes) {
+        if tenant_usage.check_storage(&quota, additional_bytes) {
             QuotaCheckResult::Allowed
         } else {
             QuotaCheckResult::StorageLimitExceeded {
@@ -288,8 +432,7 @@ impl QuotaManager {
 
     /// Check if adding an object is allowed
     pub fn check_objects(&self, tenant_id: &str) -> QuotaCheckResult {
-        let quotas = self.quotas.read().unwrap();
-        let quota = quotas.get(tenant_id).unwrap_or(&self.default_quota);
+        let quota = self.effective_quota(tenant_id);
 
         if !quota.enabled {
             return QuotaCheckResult::TenantDisabled;
@@ -298,7 +441,7 @@ impl QuotaManager {
         let usage = self.usage.read().unwrap();
         let tenant_usage = usage.get(tenant_id).cloned().unwrap_or_default();
 
-        if tenant_usage.check_objects(quota, 1) {
+        if tenant_usage.check_objects(&quota, 1) {
             QuotaCheckResult::Allowed
         } else {
             QuotaCheckResult::ObjectLimitExceeded {
@@ -310,8 +453,7 @@ impl QuotaManager {
 
     /// Check and record a request for rate limiting
     pub fn check_and_record_request(&self, tenant_id: &str) -> QuotaCheckResult {
-        let quotas = self.quotas.read().unwrap();
-        let quota = quotas.get(tenant_id).unwrap_or(&self.default_quota);
+        let quota = self.effective_quota(tenant_id);
 
         if !quota.enabled {
             return QuotaCheckResult::TenantDisabled;
@@ -320,7 +462,7 @@ impl QuotaManager {
         let mut usage = self.usage.write().unwrap();
         let tenant_usage = usage.entry(tenant_id.to_string()).or_default();
 
-        if tenant_usage.check_rate(quota) {
+        if tenant_usage.check_rate(&quota) {
             tenant_usage.record_request();
             QuotaCheckResult::Allowed
         } else {
@@ -331,6 +473,39 @@ impl QuotaManager {
         }
     }
 
+    /// Account `bytes` of a tenant's traffic, returning how long to wait
+    /// before moving them to stay within its bandwidth limit
+    pub fn reserve_bandwidth(&self, tenant_id: &str, direction: Direction, bytes: u64) -> Duration {
+        let quota = self.effective_quota(tenant_id);
+        let mut usage = self.usage.write().unwrap();
+        let tenant_usage = usage.entry(tenant_id.to_string()).or_default();
+        let now = Instant::now();
+        match direction {
+            Direction::Ingress => tenant_usage
+                .ingress
+                .reserve(quota.ingress_limit, bytes, now),
+            Direction::Egress => tenant_usage.egress.reserve(quota.egress_limit, bytes, now),
+        }
+    }
+
+    /// Wait until `bytes` of a tenant's traffic may move (see `reserve_bandwidth`)
+    pub async fn throttle(&self, tenant_id: &str, direction: Direction, bytes: u64) {
+        let wait = self.reserve_bandwidth(tenant_id, direction, bytes);
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Current bytes per second of a tenant's traffic in `direction`
+    pub fn bandwidth_rate(&self, tenant_id: &str, direction: Direction) -> u64 {
+        let usage = self.usage.read().unwrap();
+        let now = Instant::now();
+        usage.get(tenant_id).map_or(0, |u| match direction {
+            Direction::Ingress => u.ingress.rate(now),
+            Direction::Egress => u.egress.rate(now),
+        })
+    }
+
     /// Record storage being added for a tenant
     pub fn record_storage_add(&self, tenant_id: &str, bytes: u64) {
         let mut usage = self.usage.write().unwrap();
@@ -348,14 +523,32 @@ impl QuotaManager {
         }
     }
 
+    /// Replace the storage and object counts of every tenant with
+    /// `(storage_used, object_count)` recomputed from the stored keys;
+    /// tenants missing from `counts` no longer use anything
+    pub fn reconcile_usage(&self, counts: &HashMap<String, (u64, u64)>) {
+        let mut usage = self.usage.write().unwrap();
+        for (tenant_id, tenant_usage) in usage.iter_mut() {
+            let (storage_used, object_count) = counts.get(tenant_id).copied().unwrap_or_default();
+            tenant_usage.storage_used = storage_used;
+            tenant_usage.object_count = object_count;
+        }
+        for (tenant_id, &(storage_used, object_count)) in counts {
+            let tenant_usage = usage.entry(tenant_id.clone()).or_default();
+            tenant_usage.storage_used = storage_used;
+            tenant_usage.object_count = object_count;
+        }
+    }
+
     /// Get usage statistics in Prometheus format
     pub fn to_prometheus(&self) -> String {
         let mut out = String::new();
         let usage = self.usage.read().unwrap();
         let quotas = self.quotas.read().unwrap();
+        let default_quota = self.default_quota.read().unwrap();
 
         for (tenant_id, tenant_usage) in usage.iter() {
-            let quota = quotas.get(tenant_id).unwrap_or(&self.default_quota);
+            let quota = quotas.get(tenant_id).unwrap_or(&default_quota);
 
             out += &format!(
                 "minikv_tenant_storage_used_bytes{{tenant=\"{}\"}} {}\n",
@@ -373,12 +566,35 @@ impl QuotaManager {
                 "minikv_tenant_object_limit{{tenant=\"{}\"}} {}\n",
                 tenant_id, quota.object_limit
             );
+            let now = Instant::now();
+            out += &format!(
+                "minikv_tenant_ingress_bytes_per_second{{tenant=\"{}\"}} {}\n",
+                tenant_id,
+                tenant_usage.ingress.rate(now)
+            );
+            out += &format!(
+                "minikv_tenant_egress_bytes_per_second{{tenant=\"{}\"}} {}\n",
+                tenant_id,
+                tenant_usage.egress.rate(now)
+            );
         }
 
         out
     }
 }
 
+impl IdleEviction for QuotaManager {
+    /// Drop the usage of tenants storing nothing and idle lately; it is
+    /// recreated on their next request
+    fn evict_idle(&self) -> usize {
+        let mut usage = self.usage.write().unwrap();
+        let before = usage.len();
+        let now = Instant::now();
+        usage.retain(|_, tenant_usage| !tenant_usage.is_idle(now));
+        before - usage.len()
+    }
+}
+
 impl Default for QuotaManager {
     fn default() -> Self {
         Self::new()
@@ -473,6 +689,85 @@ mod tests {
         assert!(matches!(result, QuotaCheckResult::RateLimitExceeded { .. }));
     }
 
+    #[test]
+    fn test_reconcile_usage() {
+        let manager = QuotaManager::new();
+        manager.set_default_quota(TenantQuota::with_limits("__default__".into(), 1000, 2, 0));
+        manager.record_storage_add("stale", 100);
+        manager.record_storage_add("acme", 100);
+
+        let counts = HashMap::from([("acme".to_string(), (900, 2))]);
+        manager.reconcile_usage(&counts);
+        assert_eq!(manager.get_usage("stale").storage_used, 0);
+        let usage = manager.get_usage("acme");
+        assert_eq!((usage.storage_used, usage.object_count), (900, 2));
+
+        // The default quota applies to tenants without one
+        assert!(!manager.check_storage("acme", 200).is_allowed());
+        assert!(!manager.check_objects("acme").is_allowed());
+        assert!(manager.check_objects("stale").is_allowed());
+    }
+
+    #[test]
+    fn test_bandwidth_limit() {
+        let start = Instant::now();
+        let mut rate = ByteRate::default();
+
+        // A second worth of bytes bursts, the rest waits its turn
+        assert_eq!(rate.reserve(1000, 1000, start), Duration::ZERO);
+        assert_eq!(rate.reserve(1000, 500, start), Duration::from_millis(500));
+        assert_eq!(rate.reserve(1000, 500, start), Duration::from_secs(1));
+        // Debt is paid back over time
+        let later = start + Duration::from_secs(2);
+        assert_eq!(rate.reserve(1000, 100, later), Duration::ZERO);
+
+        // Unlimited traffic is measured, never held back
+        let mut rate = ByteRate::default();
+        for ms in 0..10 {
+            let now = start + Duration::from_millis(100 * ms);
+            assert_eq!(rate.reserve(0, 400, now), Duration::ZERO);
+        }
+        assert_eq!(
+            rate.reserve(0, 400, start + Duration::from_secs(1)),
+            Duration::ZERO
+        );
+        assert_eq!(rate.rate(start + Duration::from_millis(1500)), 4000);
+        assert_eq!(rate.rate(start + Duration::from_secs(5)), 0);
+
+        let manager = QuotaManager::new();
+        let mut quota = TenantQuota::unlimited("bulk".to_string());
+        quota.ingress_limit = 1000;
+        manager.set_quota(quota);
+        assert!(manager.reserve_bandwidth("bulk", Direction::Ingress, 3000) > Duration::ZERO);
+        assert_eq!(
+            manager.reserve_bandwidth("bulk", Direction::Egress, 3000),
+            Duration::ZERO
+        );
+        assert_eq!(
+            manager.reserve_bandwidth("other", Direction::Ingress, 3000),
+            Duration::ZERO
+        );
+    }
+
+    #[test]
+    fn test_evict_idle() {
+        let start = Instant::now();
+        let mut usage = TenantUsage::default();
+        usage.requests.record(DEFAULT_RATE_WINDOW, start);
+        usage.egress.reserve(0, 100, start);
+        assert!(!usage.is_idle(start));
+        assert!(usage.is_idle(start + Duration::from_secs(3)));
+        // Stored data is never evicted
+        usage.add_storage(10);
+        assert!(!usage.is_idle(start + Duration::from_secs(3)));
+
+        let manager = QuotaManager::new();
+        manager.record_storage_add("stored", 10);
+        manager.check_and_record_request("busy");
+        assert_eq!(manager.evict_idle(), 0);
+        assert_eq!(manager.usage.read().unwrap().len(), 2);
+    }
+
     #[test]
     fn test_unlimited_quota() {
         let manager = QuotaManager::new();
@@ -6,6 +6,7 @@ impl From<&VoteRequest> for crate::proto::VoteRequest {
             candidate_id: req.candidate_id.clone(),
             last_log_index: req.last_log_index,
             last_log_term: req.last_log_term,
+            pre_vote: req.pre_vote,
         }
     }
 }
@@ -28,6 +29,7 @@ impl From<&AppendRequest> for crate::proto::AppendRequest {
             prev_log_term: req.prev_log_term,
             entries: req.entries.iter().map(|e| e.into()).collect(),
             leader_commit: req.leader_commit,
+            leader_addr: req.leader_addr.clone(),
         }
     }
 }
@@ -42,6 +44,61 @@ impl From<&crate::proto::AppendResponse> for AppendResponse {
     }
 }
 
+impl From<crate::proto::VoteRequest> for VoteRequest {
+    fn from(req: crate::proto::VoteRequest) -> Self {
+        Self {
+            term: req.term,
+            candidate_id: req.candidate_id,
+            last_log_index: req.last_log_index,
+            last_log_term: req.last_log_term,
+            pre_vote: req.pre_vote,
+        }
+    }
+}
+
+impl From<&VoteResponse> for crate::proto::VoteResponse {
+    fn from(resp: &VoteResponse) -> Self {
+        Self {
+            term: resp.term,
+            vote_granted: resp.vote_granted,
+        }
+    }
+}
+
+impl From<crate::proto::AppendRequest> for AppendRequest {
+    fn from(req: crate::proto::AppendRequest) -> Self {
+        Self {
+            term: req.term,
+            leader_id: req.leader_id,
+            prev_log_index: req.prev_log_index,
+            prev_log_term: req.prev_log_term,
+            entries: req.entries.into_iter().map(LogEntry::from).collect(),
+            leader_commit: req.leader_commit,
+            leader_addr: req.leader_addr,
+        }
+    }
+}
+
+impl From<&AppendResponse> for crate::proto::AppendResponse {
+    fn from(resp: &AppendResponse) -> Self {
+        Self {
+            term: resp.term,
+            success: resp.success,
+            conflict_index: resp.conflict_index,
+        }
+    }
+}
+
+impl From<crate::proto::LogEntry> for LogEntry {
+    fn from(e: crate::proto::LogEntry) -> Self {
+        Self {
+            term: e.term,
+            index: e.index,
+            data: e.data,
+        }
+    }
+}
+
 impl From<&LogEntry> for crate::proto::LogEntry {
     fn from(e: &LogEntry) -> Self {
         Self {
@@ -60,6 +117,8 @@ pub struct VoteRequest {
     pub candidate_id: String,
     pub last_log_index: u64,
     pub last_log_term: u64,
+    /// Pre-vote probe: asks whether the vote would be granted, changing no state
+    pub pre_vote: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -76,6 +135,8 @@ pub struct AppendRequest {
     pub prev_log_term: u64,
     pub entries: Vec<LogEntry>,
     pub leader_commit: u64,
+    /// HTTP address clients are redirected to ("" if unknown)
+    pub leader_addr: String,
 }
 
 #[derive(Debug, Clone)]
@@ -85,9 +146,24 @@ pub struct AppendResponse {
     pub conflict_index: u64,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub struct LogEntry {
     pub term: u64,
     pub index: u64,
     pub data: Vec<u8>,
 }
+
+/// Position in the log covered by a state machine snapshot
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct SnapshotMeta {
+    pub last_included_index: u64,
+    pub last_included_term: u64,
+}
+
+/// State a node must persist before answering any RPC, so that a restarted
+/// node never votes twice in the same term
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct HardState {
+    pub term: u64,
+    pub voted_for: Option<String>,
+}
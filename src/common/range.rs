@@ -0,0 +1,138 @@
+//! HTTP byte ranges
+//!
+//! Parses the `Range: bytes=…` header of GET requests, so large blobs can be
+//! streamed or downloaded in pieces. A single range is supported, in any of
+//! its three forms (`bytes=a-b`, `bytes=a-`, `bytes=-n` for the last `n`
+//! bytes); a header asking for several ranges is ignored and the whole value
+//! is served, as RFC 9110 allows.
+
+use axum::http::{header, HeaderMap, HeaderValue};
+
+/// One range of a `Range: bytes=…` header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteRange {
+    /// `bytes=a-b`, both ends included
+    Span(u64, u64),
+    /// `bytes=a-`, to the end
+    From(u64),
+    /// `bytes=-n`, the last `n` bytes
+    Suffix(u64),
+}
+
+impl ByteRange {
+    /// Parse a header value. Returns `None` for anything but a single byte
+    /// range, which callers serve as a plain GET.
+    pub fn parse(value: &str) -> Option<Self> {
+        let spec = value.trim().strip_prefix("bytes=")?.trim();
+        if spec.contains(',') {
+            return None;
+        }
+        let (start, end) = spec.split_once('-')?;
+        let (start, end) = (start.trim(), end.trim());
+        match (start.is_empty(), end.is_empty()) {
+            (true, false) => end.parse().ok().map(ByteRange::Suffix),
+            (false, true) => start.parse().ok().map(ByteRange::From),
+            (false, false) => {
+                let (start, end) = (start.parse().ok()?, end.parse().ok()?);
+                (start <= end).then_some(ByteRange::Span(start, end))
+            }
+            (true, true) => None,
+        }
+    }
+
+    /// The range requested by `headers`, if any
+    pub fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        headers
+            .get(header::RANGE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(Self::parse)
+    }
+
+    /// `(offset, length)` of the range within a value of `size` bytes, or
+    /// `None` if it is unsatisfiable
+    pub fn resolve(&self, size: u64) -> Option<(u64, u64)> {
+        let (start, end) = match *self {
+            ByteRange::Span(start, end) => (start, end.min(size.saturating_sub(1))),
+            ByteRange::From(start) => (start, size.saturating_sub(1)),
+            ByteRange::Suffix(0) => return None,
+            ByteRange::Suffix(n) => (size.saturating_sub(n), size.saturating_sub(1)),
+        };
+        if start < size {
+            Some((start, end - start + 1))
+        } else {
+            None
+        }
+    }
+}
+
+/// `length` bytes of `value` from `offset`, cut short at its end
+pub fn slice_of(value: &[u8], offset: u64, length: u64) -> &[u8] {
+    let start = (offset as usize).min(value.len());
+    let end = start.saturating_add(length as usize).min(value.len());
+    &value[start..end]
+}
+
+/// `Content-Range` of a partial response
+pub fn content_range(offset: u64, length: u64, size: u64) -> HeaderValue {
+    HeaderValue::from_str(&format!(
+        "bytes {}-{}/{}",
+        offset,
+        offset + length - 1,
+        size
+    ))
+    .unwrap()
+}
+
+/// `Content-Range` of a 416 response
+pub fn unsatisfiable_range(size: u64) -> HeaderValue {
+    HeaderValue::from_str(&format!("bytes */{}", size)).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(ByteRange::parse("bytes=0-99"), Some(ByteRange::Span(0, 99)));
+        assert_eq!(ByteRange::parse("bytes=100-"), Some(ByteRange::From(100)));
+        assert_eq!(ByteRange::parse("bytes=-20"), Some(ByteRange::Suffix(20)));
+        assert_eq!(
+            ByteRange::parse(" bytes= 5 - 9"),
+            Some(ByteRange::Span(5, 9))
+        );
+
+        assert_eq!(ByteRange::parse("bytes=9-5"), None);
+        assert_eq!(ByteRange::parse("bytes=0-1,5-6"), None);
+        assert_eq!(ByteRange::parse("bytes=-"), None);
+        assert_eq!(ByteRange::parse("items=0-1"), None);
+        assert_eq!(ByteRange::parse("bytes=a-b"), None);
+    }
+
+    #[test]
+    fn test_resolve() {
+        assert_eq!(ByteRange::Span(0, 99).resolve(1000), Some((0, 100)));
+        // The end is clamped to the value
+        assert_eq!(ByteRange::Span(990, 2000).resolve(1000), Some((990, 10)));
+        assert_eq!(ByteRange::From(400).resolve(1000), Some((400, 600)));
+        assert_eq!(ByteRange::Suffix(10).resolve(1000), Some((990, 10)));
+        assert_eq!(ByteRange::Suffix(5000).resolve(1000), Some((0, 1000)));
+
+        assert_eq!(ByteRange::From(1000).resolve(1000), None);
+        assert_eq!(ByteRange::Suffix(0).resolve(1000), None);
+        assert_eq!(ByteRange::Span(0, 0).resolve(0), None);
+    }
+
+    #[test]
+    fn test_slice_of() {
+        assert_eq!(slice_of(b"abcdef", 1, 3), b"bcd");
+        assert_eq!(slice_of(b"abcdef", 4, 10), b"ef");
+        assert_eq!(slice_of(b"abcdef", 10, 1), b"");
+    }
+
+    #[test]
+    fn test_content_range() {
+        assert_eq!(content_range(0, 100, 1000), "bytes 0-99/1000");
+        assert_eq!(unsatisfiable_range(1000), "bytes */1000");
+    }
+}
@@ -1,29 +1,35 @@
 //! Rate limiting middleware for HTTP endpoints (v0.5.0)
 //!
-//! This module provides a token bucket rate limiter with per-IP tracking.
-//! It can be configured with burst capacity and refill rate.
+//! This module provides a GCRA (generic cell rate algorithm) rate limiter
+//! with per-IP tracking, configured with burst capacity and refill rate, and
+//! a sliding-window request counter. Both keep a fixed amount of state per
+//! client whatever its request rate; state left idle is dropped by the
+//! eviction task (`start_eviction_task`).
 
+use crate::common::METRICS;
 use axum::{
     body::Body,
     extract::ConnectInfo,
     http::{Request, Response, StatusCode},
     middleware::Next,
 };
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::net::SocketAddr;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, RwLock};
 use std::time::{Duration, Instant};
 
 /// Rate limiter configuration
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct RateLimitConfig {
     /// Maximum number of requests in a burst
     pub burst_size: u32,
     /// Number of requests allowed per second
     pub requests_per_second: f64,
-    /// Time window for rate limit reset
-    pub window_duration: Duration,
-    /// Whether to enable rate limiting
+    /// How often the state of idle clients is evicted, in seconds
+    pub eviction_interval_secs: u64,
+    /// Whether to enable rate limiting (off unless configured)
     pub enabled: bool,
 }
 
@@ -32,128 +38,236 @@ impl Default for RateLimitConfig {
         Self {
             burst_size: 100,
             requests_per_second: 50.0,
-            window_duration: Duration::from_secs(60),
-            enabled: true,
+            eviction_interval_secs: 60,
+            enabled: false,
         }
     }
 }
 
-/// Token bucket for a single client
-#[derive(Debug, Clone)]
-struct TokenBucket {
-    tokens: f64,
-    last_refill: Instant,
-    burst_size: u32,
-    refill_rate: f64,
+impl RateLimitConfig {
+    /// How often the state of idle clients is evicted
+    pub fn eviction_interval(&self) -> Duration {
+        Duration::from_secs(self.eviction_interval_secs.max(1))
+    }
 }
 
-impl TokenBucket {
-    fn new(burst_size: u32, refill_rate: f64) -> Self {
-        Self {
-            tokens: burst_size as f64,
-            last_refill: Instant::now(),
-            burst_size,
-            refill_rate,
+/// GCRA state of a single client: its theoretical arrival time, when its
+/// next request would be on schedule. Equivalent to a token bucket holding
+/// `burst` tokens refilled every `interval`, in a single `Instant`.
+#[derive(Debug, Clone, Copy)]
+struct Gcra {
+    tat: Instant,
+}
+
+impl Gcra {
+    fn new(now: Instant) -> Self {
+        Self { tat: now }
+    }
+
+    /// Admit a request at `now`, returning the requests left in the burst,
+    /// or refuse it, returning how long until one would be admitted
+    fn try_acquire(
+        &mut self,
+        now: Instant,
+        interval: Duration,
+        burst: u32,
+    ) -> std::result::Result<u32, Duration> {
+        let tolerance = interval * burst;
+        let tat = self.tat.max(now) + interval;
+        let ahead = tat - now;
+        if ahead > tolerance {
+            return Err(ahead - tolerance);
         }
+        self.tat = tat;
+        Ok(((tolerance - ahead).as_secs_f64() / interval.as_secs_f64()) as u32)
     }
 
-    /// Try to consume a token. Returns true if allowed, false if rate limited.
-    fn try_consume(&mut self) -> bool {
-        self.refill();
-        if self.tokens >= 1.0 {
-            self.tokens -= 1.0;
-            true
+    /// Whether the client is back to a full burst, so its state can go
+    fn is_idle(&self, now: Instant) -> bool {
+        self.tat <= now
+    }
+}
+
+/// Requests counted over a sliding window with constant memory: the counts
+/// of the current and previous fixed windows, the previous one weighted by
+/// how much of it the sliding window still covers.
+#[derive(Debug, Clone, Default)]
+pub struct SlidingWindow {
+    /// Start of the current fixed window, `None` before the first request
+    current_start: Option<Instant>,
+    current: u64,
+    previous: u64,
+}
+
+impl SlidingWindow {
+    /// Requests over the `window` ending at `now`
+    pub fn estimate(&self, window: Duration, now: Instant) -> f64 {
+        let Some(start) = self.current_start else {
+            return 0.0;
+        };
+        let elapsed = now.saturating_duration_since(start);
+        let window_secs = window.as_secs_f64();
+        if elapsed >= 2 * window {
+            0.0
+        } else if elapsed >= window {
+            let overlap = 1.0 - (elapsed - window).as_secs_f64() / window_secs;
+            self.current as f64 * overlap
         } else {
-            false
+            let overlap = 1.0 - elapsed.as_secs_f64() / window_secs;
+            self.previous as f64 * overlap + self.current as f64
         }
     }
 
-    /// Refill tokens based on elapsed time
-    fn refill(&mut self) {
-        let now = Instant::now();
-        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
-        self.tokens = (self.tokens + elapsed * self.refill_rate).min(self.burst_size as f64);
-        self.last_refill = now;
+    /// Count a request at `now`
+    pub fn record(&mut self, window: Duration, now: Instant) {
+        match self.current_start {
+            Some(start) if now.saturating_duration_since(start) >= 2 * window => {
+                self.previous = 0;
+                self.current = 0;
+                self.current_start = Some(now);
+            }
+            Some(start) if now.saturating_duration_since(start) >= window => {
+                self.previous = self.current;
+                self.current = 0;
+                self.current_start = Some(start + window);
+            }
+            Some(_) => {}
+            None => self.current_start = Some(now),
+        }
+        self.current += 1;
     }
 
-    /// Get remaining tokens (for headers)
-    fn remaining(&self) -> u32 {
-        self.tokens as u32
+    /// Count a request at `now` if fewer than `limit` were made over the window
+    pub fn try_acquire(&mut self, limit: u64, window: Duration, now: Instant) -> bool {
+        if self.estimate(window, now) >= limit as f64 {
+            return false;
+        }
+        self.record(window, now);
+        true
     }
 
-    /// Get time until next token is available
-    fn retry_after(&self) -> Duration {
-        if self.tokens >= 1.0 {
-            Duration::ZERO
-        } else {
-            let needed = 1.0 - self.tokens;
-            Duration::from_secs_f64(needed / self.refill_rate)
-        }
+    /// Whether no request was counted over the last `window`
+    pub fn is_idle(&self, window: Duration, now: Instant) -> bool {
+        self.estimate(window, now) == 0.0
+    }
+}
+
+/// Rate limiting state kept per client or tenant, dropped once idle
+pub trait IdleEviction: Send + Sync {
+    /// Drop the state of idle clients, returning how many were dropped
+    fn evict_idle(&self) -> usize;
+}
+
+impl<T: IdleEviction + ?Sized> IdleEviction for &'static T {
+    fn evict_idle(&self) -> usize {
+        (**self).evict_idle()
     }
 }
 
+/// Evict the idle state of every target each `interval`
+pub fn start_eviction_task(
+    targets: Vec<Box<dyn IdleEviction>>,
+    interval: Duration,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            let evicted: usize = targets.iter().map(|target| target.evict_idle()).sum();
+            if evicted > 0 {
+                tracing::debug!("Evicted {} idle rate limiting entries", evicted);
+            }
+        }
+    })
+}
+
 /// Shared rate limiter state
 #[derive(Clone)]
 pub struct RateLimiter {
-    buckets: Arc<Mutex<HashMap<String, TokenBucket>>>,
-    config: RateLimitConfig,
+    clients: Arc<Mutex<HashMap<String, Gcra>>>,
+    config: Arc<RwLock<RateLimitConfig>>,
 }
 
 impl RateLimiter {
     /// Create a new rate limiter with the given configuration
     pub fn new(config: RateLimitConfig) -> Self {
         Self {
-            buckets: Arc::new(Mutex::new(HashMap::new())),
-            config,
+            clients: Arc::new(Mutex::new(HashMap::new())),
+            config: Arc::new(RwLock::new(config)),
         }
     }
 
+    /// Apply `config` to the next requests, of this limiter and its clones.
+    /// Clients keep their state, checked against the new limits.
+    pub fn set_config(&self, config: RateLimitConfig) {
+        *self.config.write().unwrap() = config;
+    }
+
     /// Check if a request from the given IP is allowed
     pub fn check(&self, ip: &str) -> RateLimitResult {
-        if !self.config.enabled {
+        let (enabled, burst_size, requests_per_second) = {
+            let config = self.config.read().unwrap();
+            (
+                config.enabled,
+                config.burst_size,
+                config.requests_per_second,
+            )
+        };
+        if !enabled {
             return RateLimitResult::Allowed {
-                remaining: self.config.burst_size,
-                limit: self.config.burst_size,
+                remaining: burst_size,
+                limit: burst_size,
             };
         }
 
-        let mut buckets = self.buckets.lock().unwrap();
-        let bucket = buckets.entry(ip.to_string()).or_insert_with(|| {
-            TokenBucket::new(self.config.burst_size, self.config.requests_per_second)
-        });
-
-        if bucket.try_consume() {
-            RateLimitResult::Allowed {
-                remaining: bucket.remaining(),
-                limit: self.config.burst_size,
-            }
-        } else {
-            RateLimitResult::Limited {
-                retry_after: bucket.retry_after(),
-                limit: self.config.burst_size,
-            }
+        let now = Instant::now();
+        // A client without refill gets its burst, then one request every ~136 years
+        let interval = Duration::try_from_secs_f64(1.0 / requests_per_second)
+            .unwrap_or(Duration::from_secs(u32::MAX as u64));
+        let mut clients = self.clients.lock().unwrap();
+        let client = clients
+            .entry(ip.to_string())
+            .or_insert_with(|| Gcra::new(now));
+
+        match client.try_acquire(now, interval, burst_size) {
+            Ok(remaining) => RateLimitResult::Allowed {
+                remaining,
+                limit: burst_size,
+            },
+            Err(retry_after) => RateLimitResult::Limited {
+                retry_after,
+                limit: burst_size,
+            },
         }
     }
 
-    /// Clean up old entries to prevent memory leaks
-    pub fn cleanup(&self) {
-        let mut buckets = self.buckets.lock().unwrap();
+    /// Drop the state of clients back to a full burst, which is what an
+    /// absent client gets anyway
+    pub fn cleanup(&self) -> usize {
+        let mut clients = self.clients.lock().unwrap();
+        let before = clients.len();
         let now = Instant::now();
-        buckets.retain(|_, bucket| {
-            now.duration_since(bucket.last_refill) < self.config.window_duration
-        });
+        clients.retain(|_, client| !client.is_idle(now));
+        before - clients.len()
     }
 
     /// Get statistics about the rate limiter
     pub fn stats(&self) -> RateLimitStats {
-        let buckets = self.buckets.lock().unwrap();
+        let clients = self.clients.lock().unwrap();
         RateLimitStats {
-            tracked_ips: buckets.len(),
-            config: self.config.clone(),
+            tracked_ips: clients.len(),
+            config: self.config.read().unwrap().clone(),
         }
     }
 }
 
+impl IdleEviction for RateLimiter {
+    fn evict_idle(&self) -> usize {
+        self.cleanup()
+    }
+}
+
 /// Result of a rate limit check
 #[derive(Debug, Clone)]
 pub enum RateLimitResult {
@@ -164,19 +278,23 @@ pub enum RateLimitResult {
 }
 
 /// Statistics about the rate limiter
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct RateLimitStats {
     pub tracked_ips: usize,
     pub config: RateLimitConfig,
 }
 
-/// Axum middleware layer for rate limiting
+/// Axum middleware layer for rate limiting, by client IP. Requests without
+/// a known peer address (served without connect info) go through.
 pub async fn rate_limit_middleware(
-    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
     state: axum::extract::State<Arc<RateLimiter>>,
     request: Request<Body>,
     next: Next,
 ) -> Response<Body> {
+    let Some(ConnectInfo(addr)) = connect_info else {
+        return next.run(request).await;
+    };
     let ip = addr.ip().to_string();
 
     match state.check(&ip) {
@@ -194,6 +312,7 @@ pub async fn rate_limit_middleware(
             response
         }
         RateLimitResult::Limited { retry_after, limit } => {
+            METRICS.rate_limited_requests.inc();
             let mut response = Response::new(Body::from("Too Many Requests"));
             *response.status_mut() = StatusCode::TOO_MANY_REQUESTS;
 
@@ -202,7 +321,10 @@ pub async fn rate_limit_middleware(
             headers.insert("X-RateLimit-Remaining", "0".parse().unwrap());
             headers.insert(
                 "Retry-After",
-                retry_after.as_secs().to_string().parse().unwrap(),
+                (retry_after.as_secs_f64().ceil() as u64)
+                    .to_string()
+                    .parse()
+                    .unwrap(),
             );
 
             response
@@ -215,16 +337,107 @@ mod tests {
     use super::*;
 
     #[test]
-    fn test_token_bucket() {
-        let mut bucket = TokenBucket::new(10, 1.0);
+    fn test_gcra() {
+        let now = Instant::now();
+        let mut client = Gcra::new(now);
 
         // Should allow burst
+        for remaining in (0..10).rev() {
+            assert_eq!(
+                client.try_acquire(now, Duration::from_secs(1), 10),
+                Ok(remaining)
+            );
+        }
+
+        // Should be rate limited until the next request is due
+        assert_eq!(
+            client.try_acquire(now, Duration::from_secs(1), 10),
+            Err(Duration::from_secs(1))
+        );
+        let later = now + Duration::from_secs(1);
+        assert_eq!(client.try_acquire(later, Duration::from_secs(1), 10), Ok(0));
+        assert!(!client.is_idle(later));
+        assert!(client.is_idle(now + Duration::from_secs(11)));
+    }
+
+    #[test]
+    fn test_sliding_window() {
+        let window = Duration::from_secs(1);
+        let start = Instant::now();
+        let mut counter = SlidingWindow::default();
+
         for _ in 0..10 {
-            assert!(bucket.try_consume());
+            assert!(counter.try_acquire(10, window, start));
+        }
+        assert!(!counter.try_acquire(10, window, start));
+
+        // Half way through the next window, half of the previous one counts
+        let later = start + Duration::from_millis(1500);
+        assert_eq!(counter.estimate(window, later), 5.0);
+        for _ in 0..5 {
+            assert!(counter.try_acquire(10, window, later));
         }
+        assert!(!counter.try_acquire(10, window, later));
 
-        // Should be rate limited
-        assert!(!bucket.try_consume());
+        assert!(!counter.is_idle(window, later));
+        assert!(counter.is_idle(window, start + Duration::from_secs(3)));
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_middleware() {
+        use tower::ServiceExt;
+
+        let limiter = Arc::new(RateLimiter::new(RateLimitConfig {
+            burst_size: 2,
+            requests_per_second: 0.001,
+            eviction_interval_secs: 60,
+            enabled: true,
+        }));
+        let router = axum::Router::new()
+            .route("/", axum::routing::get(|| async { "ok" }))
+            .layer(axum::middleware::from_fn_with_state(
+                limiter,
+                rate_limit_middleware,
+            ));
+        let send = |peer: Option<&str>| {
+            let mut request = Request::get("/").body(Body::empty()).unwrap();
+            if let Some(peer) = peer {
+                let addr: SocketAddr = peer.parse().unwrap();
+                request.extensions_mut().insert(ConnectInfo(addr));
+            }
+            router.clone().oneshot(request)
+        };
+
+        let rejected = METRICS.rate_limited_requests.get();
+        for _ in 0..2 {
+            let resp = send(Some("10.0.0.1:1234")).await.unwrap();
+            assert_eq!(resp.status(), StatusCode::OK);
+        }
+        let resp = send(Some("10.0.0.1:4321")).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(resp.headers().contains_key("Retry-After"));
+        assert!(METRICS.rate_limited_requests.get() > rejected);
+
+        // Other clients, and requests without a peer address, are unaffected
+        let resp = send(Some("10.0.0.2:1234")).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = send(None).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn test_cleanup() {
+        let limiter = RateLimiter::new(RateLimitConfig {
+            burst_size: 5,
+            requests_per_second: 1000.0,
+            eviction_interval_secs: 60,
+            enabled: true,
+        });
+        limiter.check("127.0.0.1");
+        assert_eq!(limiter.stats().tracked_ips, 1);
+        std::thread::sleep(Duration::from_millis(5));
+        assert_eq!(limiter.evict_idle(), 1);
+        assert_eq!(limiter.stats().tracked_ips, 0);
     }
 
     #[test]
@@ -232,7 +445,7 @@ mod tests {
         let config = RateLimitConfig {
             burst_size: 5,
             requests_per_second: 1.0,
-            window_duration: Duration::from_secs(60),
+            eviction_interval_secs: 60,
             enabled: true,
         };
 
@@ -264,11 +477,11 @@ mod tests {
         let config = RateLimitConfig {
             burst_size: 1,
             requests_per_second: 0.1,
-            window_duration: Duration::from_secs(60),
+            eviction_interval_secs: 60,
             enabled: false,
         };
 
-        let limiter = RateLimiter::new(config);
+        let limiter = RateLimiter::new(config.clone());
 
         // Should always allow when disabled
         for _ in 0..100 {
@@ -277,5 +490,19 @@ mod tests {
                 RateLimitResult::Limited { .. } => panic!("Should be allowed when disabled"),
             }
         }
+
+        // Enabled through a clone, as on a configuration reload
+        limiter.clone().set_config(RateLimitConfig {
+            enabled: true,
+            ..config
+        });
+        assert!(matches!(
+            limiter.check("127.0.0.1"),
+            RateLimitResult::Allowed { .. }
+        ));
+        assert!(matches!(
+            limiter.check("127.0.0.1"),
+            RateLimitResult::Limited { .. }
+        ));
     }
 }
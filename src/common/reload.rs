@@ -0,0 +1,304 @@
+//! Configuration reload
+//!
+//! On SIGHUP, or `POST /admin/config/reload`, a node re-reads its
+//! configuration (`Config::try_load`) and compares it field by field with
+//! the one it runs. Changed fields that are safe to change while running
+//! are applied at once:
+//! - `log_level`;
+//! - on a coordinator, the `rate_limit` limits and toggle, the default
+//!   `quota` limits, `auth` and `grpc_auth`;
+//! - on a volume, the `compaction_*` settings and `grpc_auth`.
+//!
+//! The others (listen addresses, paths, intervals of background tasks, ...)
+//! are reported as needing a restart, and keep being reported until then.
+//! A configuration that fails to load changes nothing; one that fails to
+//! apply is tried again in full on the next reload. The memcached listener
+//! keeps requiring a login or not as it started.
+
+use crate::common::{Config, Error, Result};
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use serde_json::Value;
+use std::sync::Mutex;
+
+/// Global reloader, installed by the node serving
+pub static RELOADER: Lazy<Reloader> = Lazy::new(Reloader::default);
+
+/// Applies the changed fields, named as in `ReloadReport`, of a new
+/// configuration
+type Apply = Box<dyn Fn(&Config, &[String]) -> Result<()> + Send + Sync>;
+
+/// What a reload changed
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ReloadReport {
+    /// Fields now in effect, e.g. `coordinator.rate_limit.burst_size`
+    pub applied: Vec<String>,
+    /// Fields changed that only take effect on restart
+    pub restart_required: Vec<String>,
+}
+
+struct Installed {
+    /// The configuration in effect
+    running: Value,
+    /// Fields, or tables of fields, that can be applied while running
+    hot: &'static [&'static str],
+    apply: Apply,
+}
+
+/// Applies reloaded configurations to a node
+#[derive(Default)]
+pub struct Reloader {
+    installed: Mutex<Option<Installed>>,
+}
+
+impl Reloader {
+    /// Reload onto `running`, applying the changes to the fields listed in
+    /// `hot` (or under them) with `apply`. Replaces any previous install.
+    #[allow(clippy::result_large_err)]
+    pub fn install(
+        &self,
+        running: &Config,
+        hot: &'static [&'static str],
+        apply: impl Fn(&Config, &[String]) -> Result<()> + Send + Sync + 'static,
+    ) -> Result<()> {
+        *self.installed.lock().unwrap() = Some(Installed {
+            running: to_value(running)?,
+            hot,
+            apply: Box::new(apply),
+        });
+        Ok(())
+    }
+
+    /// Re-read the configuration and apply it
+    #[allow(clippy::result_large_err)]
+    pub fn reload(&self) -> Result<ReloadReport> {
+        self.reload_from(&Config::try_load()?)
+    }
+
+    /// Apply `config` in place of the running configuration
+    #[allow(clippy::result_large_err)]
+    pub fn reload_from(&self, config: &Config) -> Result<ReloadReport> {
+        let mut installed = self.installed.lock().unwrap();
+        let Some(installed) = installed.as_mut() else {
+            return Err(Error::InvalidConfig(
+                "this node has no configuration to reload".into(),
+            ));
+        };
+        let new = to_value(config)?;
+        let mut changed = Vec::new();
+        changed_fields("", &installed.running, &new, &mut changed);
+        let (applied, restart_required): (Vec<_>, Vec<_>) = changed
+            .into_iter()
+            .partition(|field| is_hot(installed.hot, field));
+        if !applied.is_empty() {
+            (installed.apply)(config, &applied)?;
+            for field in &applied {
+                set_field(&mut installed.running, &new, field);
+            }
+        }
+        Ok(ReloadReport {
+            applied,
+            restart_required,
+        })
+    }
+}
+
+/// Whether one of `fields` is `name` or under it
+pub fn touched(fields: &[String], name: &str) -> bool {
+    fields.iter().any(|field| is_under(field, name))
+}
+
+fn is_under(field: &str, name: &str) -> bool {
+    field == name
+        || field
+            .strip_prefix(name)
+            .is_some_and(|rest| rest.starts_with('.'))
+}
+
+fn is_hot(hot: &[&str], field: &str) -> bool {
+    hot.iter().any(|name| is_under(field, name))
+}
+
+#[allow(clippy::result_large_err)]
+fn to_value(config: &Config) -> Result<Value> {
+    serde_json::to_value(config).map_err(|e| Error::Other(format!("configuration: {}", e)))
+}
+
+/// Collect the dotted names of the fields that differ between `old` and
+/// `new` under `prefix`, descending into tables
+fn changed_fields(prefix: &str, old: &Value, new: &Value, changed: &mut Vec<String>) {
+    let name = |key: &str| {
+        if prefix.is_empty() {
+            key.to_string()
+        } else {
+            format!("{}.{}", prefix, key)
+        }
+    };
+    match (old, new) {
+        (Value::Object(old), Value::Object(new)) => {
+            for (key, value) in old {
+                changed_fields(
+                    &name(key),
+                    value,
+                    new.get(key).unwrap_or(&Value::Null),
+                    changed,
+                );
+            }
+            for (key, value) in new {
+                if !old.contains_key(key) {
+                    changed_fields(&name(key), &Value::Null, value, changed);
+                }
+            }
+        }
+        (Value::Object(old), Value::Null) => {
+            for (key, value) in old {
+                changed_fields(&name(key), value, &Value::Null, changed);
+            }
+        }
+        (Value::Null, Value::Object(new)) => {
+            for (key, value) in new {
+                changed_fields(&name(key), &Value::Null, value, changed);
+            }
+        }
+        (old, new) if old != new => changed.push(prefix.to_string()),
+        _ => {}
+    }
+}
+
+/// Set the field named `field` of `running` to its value in `new`
+fn set_field(running: &mut Value, new: &Value, field: &str) {
+    let mut target = running;
+    let mut source = Some(new);
+    let mut keys = field.split('.').peekable();
+    while let Some(key) = keys.next() {
+        source = source.and_then(|value| value.get(key));
+        if !target.is_object() {
+            *target = Value::Object(Default::default());
+        }
+        let Value::Object(map) = target else {
+            unreachable!()
+        };
+        if keys.peek().is_none() {
+            match source {
+                Some(value) => map.insert(key.to_string(), value.clone()),
+                None => map.remove(key),
+            };
+            return;
+        }
+        target = map.entry(key.to_string()).or_insert(Value::Null);
+    }
+}
+
+/// Reload the configuration with `RELOADER` on SIGHUP
+pub fn start_reload_task() -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut hangup = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())
+        {
+            Ok(signal) => signal,
+            Err(e) => {
+                tracing::warn!("Cannot listen for SIGHUP: {}", e);
+                return;
+            }
+        };
+        while hangup.recv().await.is_some() {
+            let _ = reload_now().await;
+        }
+    })
+}
+
+/// Reload with `RELOADER` off the async runtime, logging the outcome
+pub async fn reload_now() -> Result<ReloadReport> {
+    let result = tokio::task::spawn_blocking(|| RELOADER.reload())
+        .await
+        .unwrap_or_else(|e| Err(Error::Other(format!("reload panicked: {}", e))));
+    match &result {
+        Ok(report) => {
+            tracing::info!(
+                "Reloaded configuration: applied {:?}, needing a restart {:?}",
+                report.applied,
+                report.restart_required
+            );
+        }
+        Err(e) => tracing::warn!("Keeping the running configuration: {}", e),
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::{CoordinatorConfig, NodeRole};
+    use std::sync::Arc;
+
+    fn config(burst_size: u32, replicas: usize) -> Config {
+        let mut coordinator = CoordinatorConfig {
+            replicas,
+            ..Default::default()
+        };
+        coordinator.rate_limit.burst_size = burst_size;
+        Config {
+            node_id: "coord-1".to_string(),
+            role: NodeRole::Coordinator,
+            coordinator: Some(coordinator),
+            volume: None,
+            logging: Default::default(),
+            pid_file: None,
+        }
+    }
+
+    #[test]
+    fn test_reload() {
+        let reloader = Reloader::default();
+        assert!(reloader.reload_from(&config(100, 3)).is_err());
+
+        let bursts = Arc::new(Mutex::new(Vec::new()));
+        reloader
+            .install(&config(100, 3), &["log_level", "coordinator.rate_limit"], {
+                let bursts = bursts.clone();
+                move |config, fields| {
+                    if touched(fields, "coordinator.rate_limit") {
+                        let burst = config.coordinator.as_ref().unwrap().rate_limit.burst_size;
+                        bursts.lock().unwrap().push(burst);
+                    }
+                    Ok(())
+                }
+            })
+            .unwrap();
+        assert_eq!(
+            reloader.reload_from(&config(100, 3)).unwrap(),
+            ReloadReport::default()
+        );
+
+        let report = reloader.reload_from(&config(200, 5)).unwrap();
+        assert_eq!(report.applied, ["coordinator.rate_limit.burst_size"]);
+        assert_eq!(report.restart_required, ["coordinator.replicas"]);
+        assert_eq!(*bursts.lock().unwrap(), [200]);
+
+        // Applied once; the restart is still needed
+        let report = reloader.reload_from(&config(200, 5)).unwrap();
+        assert!(report.applied.is_empty());
+        assert_eq!(report.restart_required, ["coordinator.replicas"]);
+
+        let mut quiet = config(200, 3);
+        quiet.logging.log_level = "warn".to_string();
+        let report = reloader.reload_from(&quiet).unwrap();
+        assert_eq!(report.applied, ["log_level"]);
+        assert!(report.restart_required.is_empty());
+    }
+
+    #[test]
+    fn test_changed_fields() {
+        let old = serde_json::json!({"a": {"b": 1, "c": [1]}, "d": null});
+        let new = serde_json::json!({"a": {"b": 2, "c": [1]}, "d": {"e": true}});
+        let mut changed = Vec::new();
+        changed_fields("", &old, &new, &mut changed);
+        assert_eq!(changed, ["a.b", "d.e"]);
+
+        let mut running = old.clone();
+        set_field(&mut running, &new, "d.e");
+        assert_eq!(running["d"]["e"], true);
+        assert!(touched(&changed, "a"));
+        assert!(!touched(&changed, "a.c"));
+        assert!(!is_under("ab", "a"));
+    }
+}
@@ -0,0 +1,105 @@
+//! Graceful shutdown
+//!
+//! `SHUTDOWN` is triggered by SIGTERM or Ctrl-C (see `start_signal_task`),
+//! or by a listener failing. Coordinators and volumes then stop accepting
+//! connections and give the requests in flight until `shutdown_timeout_secs`
+//! to finish before cleaning up and exiting:
+//! - a coordinator resolves the two-phase commits left unfinished (aborting
+//!   those without a commit decision) and hands leadership to a caught-up
+//!   peer if it leads, then flushes its metadata store;
+//! - a volume tells the coordinators it is going away, so they stop placing
+//!   writes on it, then syncs its WAL and saves an index snapshot.
+
+use once_cell::sync::Lazy;
+use tokio::sync::watch;
+
+/// Global shutdown state
+pub static SHUTDOWN: Lazy<Shutdown> = Lazy::new(Shutdown::default);
+
+/// Whether the process is shutting down, for tasks to wait on
+pub struct Shutdown {
+    tx: watch::Sender<bool>,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self {
+            tx: watch::channel(false).0,
+        }
+    }
+}
+
+impl Shutdown {
+    /// Start shutting down; later calls do nothing
+    pub fn trigger(&self) {
+        if !self.tx.send_replace(true) {
+            tracing::info!("Shutting down");
+        }
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        *self.tx.borrow()
+    }
+
+    /// Resolves once shutdown is triggered
+    pub async fn wait(&self) {
+        let mut rx = self.tx.subscribe();
+        let _ = rx.wait_for(|triggered| *triggered).await;
+    }
+}
+
+/// Trigger `SHUTDOWN` on SIGTERM or Ctrl-C
+pub fn start_signal_task() -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut terminate =
+            match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+                Ok(signal) => signal,
+                Err(e) => {
+                    tracing::warn!("Cannot listen for SIGTERM: {}", e);
+                    let _ = tokio::signal::ctrl_c().await;
+                    SHUTDOWN.trigger();
+                    return;
+                }
+            };
+        tokio::select! {
+            _ = terminate.recv() => tracing::info!("Received SIGTERM"),
+            _ = tokio::signal::ctrl_c() => tracing::info!("Received Ctrl-C"),
+        }
+        SHUTDOWN.trigger();
+    })
+}
+
+/// Run the listener `server` until it stops, logging why if it failed, in
+/// which case the shutdown starts
+pub async fn run_listener<E: std::fmt::Display>(
+    name: &str,
+    server: impl std::future::Future<Output = std::result::Result<(), E>>,
+) {
+    if let Err(e) = server.await {
+        tracing::error!("{} error: {}", name, e);
+        SHUTDOWN.trigger();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_trigger_wakes_waiters() {
+        let shutdown = Arc::new(Shutdown::default());
+        let waiter = tokio::spawn({
+            let shutdown = shutdown.clone();
+            async move { shutdown.wait().await }
+        });
+        tokio::task::yield_now().await;
+        assert!(!waiter.is_finished());
+
+        shutdown.trigger();
+        waiter.await.unwrap();
+        assert!(shutdown.is_triggered());
+        // Already triggered: returns at once
+        shutdown.wait().await;
+    }
+}
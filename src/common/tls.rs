@@ -0,0 +1,218 @@
+//! Mutual TLS for the internal gRPC traffic between coordinators and volumes
+//!
+//! Every node presents a certificate signed by the cluster CA and only
+//! accepts peers presenting one too. Node identity is the certificate's
+//! subject alternative names (DNS or IP): with `allowed_peers` set, a
+//! peer is only accepted if one of its SANs matches an entry.
+
+use crate::common::{Error, Result};
+use once_cell::sync::Lazy;
+use rustls::pki_types::{CertificateDer, ServerName};
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, RwLock};
+use tonic::transport::{
+    Certificate, Channel, ClientTlsConfig, Endpoint, Identity, ServerTlsConfig,
+};
+use tonic::{Request, Status};
+
+/// Internal gRPC TLS configuration (`[coordinator.grpc_tls]`, `[volume.grpc_tls]`)
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GrpcTlsConfig {
+    /// Require mutual TLS on internal gRPC
+    pub enabled: bool,
+    /// CA certificate peers' certificates must be signed by (PEM)
+    pub ca_path: Option<String>,
+    /// This node's certificate (PEM)
+    pub cert_path: Option<String>,
+    /// This node's private key (PEM)
+    pub key_path: Option<String>,
+    /// Names (DNS or IP SANs) of the peers allowed to connect; empty accepts
+    /// any certificate signed by the CA
+    pub allowed_peers: Vec<String>,
+}
+
+/// Loaded CA, identity and peer allow-list
+#[derive(Clone)]
+pub struct GrpcTls {
+    ca: Certificate,
+    identity: Identity,
+    allowed_peers: Vec<String>,
+}
+
+impl GrpcTls {
+    /// Read the PEM files of `config`; `None` if mTLS is disabled
+    pub fn load(config: &GrpcTlsConfig) -> Result<Option<Self>> {
+        if !config.enabled {
+            return Ok(None);
+        }
+        let (Some(ca_path), Some(cert_path), Some(key_path)) =
+            (&config.ca_path, &config.cert_path, &config.key_path)
+        else {
+            return Err(Error::InvalidConfig(
+                "grpc_tls needs ca_path, cert_path and key_path".into(),
+            ));
+        };
+        let read = |path: &str| {
+            std::fs::read(path).map_err(|e| Error::InvalidConfig(format!("{}: {}", path, e)))
+        };
+        Ok(Some(Self {
+            ca: Certificate::from_pem(read(ca_path)?),
+            identity: Identity::from_pem(read(cert_path)?, read(key_path)?),
+            allowed_peers: config.allowed_peers.clone(),
+        }))
+    }
+
+    /// Server side: present our identity and require a CA-signed client certificate
+    pub fn server_config(&self) -> ServerTlsConfig {
+        ServerTlsConfig::new()
+            .identity(self.identity.clone())
+            .client_ca_root(self.ca.clone())
+    }
+
+    /// Client side: present our identity and verify the server against the CA
+    pub fn client_config(&self, host: &str) -> ClientTlsConfig {
+        ClientTlsConfig::new()
+            .ca_certificate(self.ca.clone())
+            .identity(self.identity.clone())
+            .domain_name(host)
+    }
+
+    /// Whether a peer presenting `certs` (leaf first) may talk to us
+    pub fn peer_allowed(&self, certs: &[CertificateDer<'_>]) -> bool {
+        let Some(leaf) = certs.first() else {
+            return false;
+        };
+        if self.allowed_peers.is_empty() {
+            return true;
+        }
+        let Ok(parsed) = rustls::server::ParsedCertificate::try_from(leaf) else {
+            return false;
+        };
+        self.allowed_peers.iter().any(|name| {
+            ServerName::try_from(name.as_str())
+                .is_ok_and(|name| rustls::client::verify_server_name(&parsed, &name).is_ok())
+        })
+    }
+}
+
+static GRPC_TLS: Lazy<RwLock<Option<Arc<GrpcTls>>>> = Lazy::new(|| RwLock::new(None));
+
+/// Load `config` as this process's internal gRPC TLS settings
+pub fn configure_grpc_tls(config: &GrpcTlsConfig) -> Result<()> {
+    let tls = GrpcTls::load(config)?.map(Arc::new);
+    *GRPC_TLS.write().unwrap() = tls;
+    Ok(())
+}
+
+fn current() -> Option<Arc<GrpcTls>> {
+    GRPC_TLS.read().unwrap().clone()
+}
+
+/// Server TLS settings requiring client certificates, if mTLS is configured
+pub fn server_tls_config() -> Option<ServerTlsConfig> {
+    current().map(|tls| tls.server_config())
+}
+
+/// Open a channel to an internal gRPC endpoint, over mTLS if configured
+pub async fn connect_channel(addr: &str) -> std::result::Result<Channel, tonic::transport::Error> {
+    if let Some(channel) = crate::sim::network::connect(addr).await {
+        return channel;
+    }
+    let Some(tls) = current() else {
+        return Endpoint::from_shared(addr.to_string())?.connect().await;
+    };
+    let rest = addr
+        .strip_prefix("http://")
+        .or_else(|| addr.strip_prefix("https://"))
+        .unwrap_or(addr);
+    let authority = rest.split('/').next().unwrap_or(rest);
+    let host = match authority.rsplit_once(':') {
+        Some((host, port)) if port.parse::<u16>().is_ok() => host,
+        _ => authority,
+    };
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    Endpoint::from_shared(format!("https://{}", rest))?
+        .tls_config(tls.client_config(host))?
+        .connect()
+        .await
+}
+
+/// gRPC interceptor rejecting peers whose certificate names aren't allowed
+pub fn check_peer(request: Request<()>) -> std::result::Result<Request<()>, Status> {
+    let Some(tls) = current() else {
+        return Ok(request);
+    };
+    match request.peer_certs() {
+        Some(certs) if tls.peer_allowed(&certs) => Ok(request),
+        Some(_) => Err(Status::permission_denied("peer certificate not allowed")),
+        None => Err(Status::unauthenticated("client certificate required")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/certs/test");
+
+    fn config(allowed_peers: &[&str]) -> GrpcTlsConfig {
+        GrpcTlsConfig {
+            enabled: true,
+            ca_path: Some(format!("{}/ca.pem", FIXTURES)),
+            cert_path: Some(format!("{}/node.pem", FIXTURES)),
+            key_path: Some(format!("{}/node.key", FIXTURES)),
+            allowed_peers: allowed_peers.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+
+    fn node_cert() -> Vec<CertificateDer<'static>> {
+        let pem = std::fs::read(format!("{}/node.pem", FIXTURES)).unwrap();
+        rustls_pemfile::certs(&mut pem.as_slice())
+            .collect::<std::result::Result<_, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_load() {
+        assert!(GrpcTls::load(&GrpcTlsConfig::default()).unwrap().is_none());
+        assert!(GrpcTls::load(&config(&[])).unwrap().is_some());
+
+        let missing_key = GrpcTlsConfig {
+            key_path: None,
+            ..config(&[])
+        };
+        assert!(GrpcTls::load(&missing_key).is_err());
+        let missing_file = GrpcTlsConfig {
+            ca_path: Some("/nonexistent/ca.pem".into()),
+            ..config(&[])
+        };
+        assert!(GrpcTls::load(&missing_file).is_err());
+    }
+
+    #[test]
+    fn test_peer_allowed() {
+        let certs = node_cert();
+
+        // Any CA-signed peer when no names are listed
+        let tls = GrpcTls::load(&config(&[])).unwrap().unwrap();
+        assert!(tls.peer_allowed(&certs));
+        assert!(!tls.peer_allowed(&[]));
+
+        // DNS and IP SANs
+        let tls = GrpcTls::load(&config(&["coord-1.minikv.internal"]))
+            .unwrap()
+            .unwrap();
+        assert!(tls.peer_allowed(&certs));
+        let tls = GrpcTls::load(&config(&["10.0.0.1", "127.0.0.1"]))
+            .unwrap()
+            .unwrap();
+        assert!(tls.peer_allowed(&certs));
+
+        // The subject CN is not an identity
+        let tls = GrpcTls::load(&config(&["coord-1", "coord-2.minikv.internal"]))
+            .unwrap()
+            .unwrap();
+        assert!(!tls.peer_allowed(&certs));
+    }
+}
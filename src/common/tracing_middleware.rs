@@ -13,7 +13,7 @@ use axum::{
 };
 use std::net::SocketAddr;
 use std::time::Instant;
-use tracing::{info, warn};
+use tracing::{info, warn, Instrument};
 use uuid::Uuid;
 
 /// Header name for request ID
@@ -108,7 +108,8 @@ pub async fn request_tracing_middleware(
     response
 }
 
-/// Simpler middleware that just adds request ID without full tracing
+/// Simpler middleware that adds a request ID, and runs the request in an
+/// `http_request` span with it, whose `tenant` the handlers fill in
 pub async fn request_id_middleware(request: Request<Body>, next: Next) -> Response<Body> {
     // Generate or extract request ID
     let request_id = request
@@ -118,7 +119,14 @@ pub async fn request_id_middleware(request: Request<Body>, next: Next) -> Respon
         .map(|s| s.to_string())
         .unwrap_or_else(generate_request_id);
 
-    let mut response = next.run(request).await;
+    let span = tracing::info_span!(
+        "http_request",
+        request_id = %request_id,
+        method = %request.method(),
+        path = %request.uri().path(),
+        tenant = tracing::field::Empty,
+    );
+    let mut response = next.run(request).instrument(span).await;
 
     // Add request ID to response headers
     response
@@ -106,6 +106,8 @@ pub enum NodeState {
     Suspect,
     Dead,
     Draining,
+    /// Below the free-space floor: serves reads, takes no new writes
+    ReadOnly,
 }
 
 impl NodeState {
@@ -118,7 +120,10 @@ impl NodeState {
     }
 
     pub fn can_read(&self) -> bool {
-        matches!(self, NodeState::Alive | NodeState::Draining)
+        matches!(
+            self,
+            NodeState::Alive | NodeState::Draining | NodeState::ReadOnly
+        )
     }
 }
 
@@ -129,6 +134,7 @@ impl std::fmt::Display for NodeState {
             NodeState::Suspect => write!(f, "suspect"),
             NodeState::Dead => write!(f, "dead"),
             NodeState::Draining => write!(f, "draining"),
+            NodeState::ReadOnly => write!(f, "readonly"),
         }
     }
 }
@@ -175,6 +181,27 @@ pub fn generate_upload_id() -> String {
     format!("{}-{}", timestamp, counter)
 }
 
+/// Space available to unprivileged users on the filesystem holding `path`.
+/// Returns `None` where it can't be measured.
+#[cfg(unix)]
+pub fn disk_free_bytes(path: &std::path::Path) -> Option<u64> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_path = std::ffi::CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    // SAFETY: c_path is NUL-terminated and stat is a valid, writable statvfs
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    #[allow(clippy::unnecessary_cast)]
+    Some(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+#[cfg(not(unix))]
+pub fn disk_free_bytes(_path: &std::path::Path) -> Option<u64> {
+    None
+}
+
 /// Calculate CRC32 checksum
 pub fn crc32(data: &[u8]) -> u32 {
     crc32fast::hash(data)
@@ -215,4 +242,12 @@ mod tests {
         let decoded = decode_key(&encoded).unwrap();
         assert_eq!(decoded, key);
     }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_disk_free_bytes() {
+        let dir = std::env::temp_dir();
+        assert!(disk_free_bytes(&dir).unwrap() > 0);
+        assert!(disk_free_bytes(&dir.join("does-not-exist")).is_none());
+    }
 }
@@ -0,0 +1,214 @@
+//! Bound on the writes a node holds in memory at once
+//!
+//! Write handlers buffer whole values before storing or forwarding them, so
+//! a burst of large uploads could exhaust memory. A `WriteBudget` admits a
+//! write only while fewer than `max_writes` writes and `max_bytes` bytes of
+//! values are in flight. Past either bound `write_budget_middleware`
+//! answers `503` with `Retry-After` before the body is read, and clients
+//! back off instead of piling up.
+//!
+//! A write is charged its `Content-Length`, or an even share of the budget
+//! (`max_bytes / max_writes`) when its size is unknown. A write larger than
+//! the whole budget is admitted once nothing else is in flight, so it waits
+//! its turn rather than being refused for good; the body limit is what
+//! refuses values that are too large.
+
+use crate::common::{WriteBudgetConfig, METRICS};
+use axum::{
+    body::{Body, HttpBody},
+    extract::State,
+    http::{header, Request, Response, StatusCode},
+    middleware::Next,
+};
+use std::sync::{Arc, Mutex};
+
+/// Writes in flight and the bytes they hold
+#[derive(Debug, Default)]
+struct Usage {
+    writes: usize,
+    bytes: u64,
+}
+
+/// Shared count of the writes in flight on a node
+#[derive(Debug)]
+pub struct WriteBudget {
+    config: WriteBudgetConfig,
+    usage: Mutex<Usage>,
+}
+
+/// A write admitted by the budget, given back when dropped
+#[derive(Debug)]
+pub struct WritePermit {
+    budget: Arc<WriteBudget>,
+    bytes: u64,
+}
+
+impl Drop for WritePermit {
+    fn drop(&mut self) {
+        let mut usage = self.budget.usage.lock().unwrap();
+        usage.writes -= 1;
+        usage.bytes -= self.bytes;
+        publish(&usage);
+    }
+}
+
+impl WriteBudget {
+    pub fn new(config: WriteBudgetConfig) -> Self {
+        Self {
+            config,
+            usage: Mutex::new(Usage::default()),
+        }
+    }
+
+    /// Seconds a refused write is told to wait
+    pub fn retry_after_secs(&self) -> u64 {
+        self.config.retry_after_secs
+    }
+
+    /// Admit a write of `bytes` (`None` if unknown), or refuse it if the
+    /// budget is spent
+    pub fn try_acquire(self: &Arc<Self>, bytes: Option<u64>) -> Option<WritePermit> {
+        let config = &self.config;
+        let bytes = bytes.unwrap_or(config.max_bytes / config.max_writes.max(1) as u64);
+        let mut usage = self.usage.lock().unwrap();
+        let writes_full = config.max_writes > 0 && usage.writes >= config.max_writes;
+        let bytes_full = config.max_bytes > 0
+            && usage.writes > 0
+            && usage.bytes.saturating_add(bytes) > config.max_bytes;
+        if writes_full || bytes_full {
+            METRICS.writes_rejected.inc();
+            return None;
+        }
+        usage.writes += 1;
+        usage.bytes += bytes;
+        publish(&usage);
+        Some(WritePermit {
+            budget: self.clone(),
+            bytes,
+        })
+    }
+
+    /// Writes in flight and the bytes they hold
+    pub fn in_flight(&self) -> (usize, u64) {
+        let usage = self.usage.lock().unwrap();
+        (usage.writes, usage.bytes)
+    }
+
+    /// `503` telling the client to retry a refused write later
+    pub fn refused(&self) -> Response<Body> {
+        let mut response = Response::new(Body::from("Too many writes in flight"));
+        *response.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+        response.headers_mut().insert(
+            header::RETRY_AFTER,
+            self.config.retry_after_secs.to_string().parse().unwrap(),
+        );
+        response
+    }
+}
+
+fn publish(usage: &Usage) {
+    METRICS.writes_in_flight.set(usage.writes as u64);
+    METRICS.write_bytes_in_flight.set(usage.bytes);
+}
+
+/// Axum middleware holding each request to the write budget for as long as
+/// it runs, refusing it with `503` when the budget is spent
+pub async fn write_budget_middleware(
+    State(budget): State<Arc<WriteBudget>>,
+    request: Request<Body>,
+    next: Next,
+) -> Response<Body> {
+    let bytes = request
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok())
+        .or_else(|| request.body().size_hint().exact());
+    let Some(_permit) = budget.try_acquire(bytes) else {
+        return budget.refused();
+    };
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn budget(max_writes: usize, max_bytes: u64) -> Arc<WriteBudget> {
+        Arc::new(WriteBudget::new(WriteBudgetConfig {
+            max_writes,
+            max_bytes,
+            retry_after_secs: 3,
+        }))
+    }
+
+    #[test]
+    fn test_write_budget() {
+        let budget = budget(2, 100);
+
+        let a = budget.try_acquire(Some(60)).unwrap();
+        // Over the bytes bound
+        assert!(budget.try_acquire(Some(50)).is_none());
+        let b = budget.try_acquire(Some(40)).unwrap();
+        assert_eq!(budget.in_flight(), (2, 100));
+        // Over the writes bound
+        assert!(budget.try_acquire(Some(0)).is_none());
+
+        drop(a);
+        drop(b);
+        assert_eq!(budget.in_flight(), (0, 0));
+
+        // Unknown sizes take an even share
+        let _c = budget.try_acquire(None).unwrap();
+        assert_eq!(budget.in_flight(), (1, 50));
+    }
+
+    #[test]
+    fn test_oversized_write_waits_its_turn() {
+        let budget = budget(10, 100);
+
+        let small = budget.try_acquire(Some(1)).unwrap();
+        assert!(budget.try_acquire(Some(500)).is_none());
+        drop(small);
+        let big = budget.try_acquire(Some(500)).unwrap();
+        assert!(budget.try_acquire(Some(1)).is_none());
+        drop(big);
+
+        // No bounds
+        let unbounded = budget(0, 0);
+        let permits: Vec<_> = (0..100)
+            .map(|_| unbounded.try_acquire(None).unwrap())
+            .collect();
+        assert_eq!(unbounded.in_flight(), (100, 0));
+        drop(permits);
+    }
+
+    #[tokio::test]
+    async fn test_write_budget_middleware() {
+        use tower::ServiceExt;
+
+        let budget = budget(1, 1024);
+        let router = axum::Router::new()
+            .route("/", axum::routing::post(|| async { "ok" }))
+            .layer(axum::middleware::from_fn_with_state(
+                budget.clone(),
+                write_budget_middleware,
+            ));
+        let send = || {
+            let request = Request::post("/").body(Body::from("value")).unwrap();
+            router.clone().oneshot(request)
+        };
+
+        let resp = send().await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(budget.in_flight(), (0, 0));
+
+        let held = budget.try_acquire(Some(1)).unwrap();
+        let resp = send().await.unwrap();
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(resp.headers()[header::RETRY_AFTER], "3");
+        drop(held);
+        let resp = send().await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,321 @@
+//! Merkle-tree based anti-entropy between replicas
+//!
+//! Each volume builds one Merkle tree per shard over its `key → blake3` listing.
+//! The coordinator builds the tree every volume *should* have from the key
+//! metadata and compares roots; for a diverged shard it fetches the leaves,
+//! descends into the differing buckets only and then:
+//! - copies missing or stale keys from a healthy replica,
+//! - deletes keys the volume holds but is no longer a replica for.
+//!
+//! This runs in the background on the leader and catches silent divergence
+//! between `verify` runs.
+
+use crate::common::merkle::{bucket_for, digest_from_bytes, MerkleTree};
+use crate::common::{blake3_hash, shard_key, Result};
+use crate::coordinator::metadata::{KeyState, MetadataStore, VolumeMetadata};
+use crate::coordinator::raft_node::RaftNode;
+use crate::coordinator::txn::write_replica;
+use crate::coordinator::volume_client::VolumeClient;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+/// Summary of one anti-entropy round
+#[derive(Debug, Default, Serialize)]
+pub struct AntiEntropyReport {
+    /// Volumes whose trees were compared
+    pub volumes_checked: usize,
+    /// (volume, shard) pairs whose roots differed
+    pub shards_diverged: usize,
+    /// Keys copied to a replica that was missing them or held a stale copy
+    pub keys_repaired: usize,
+    /// Keys removed from volumes that are not replicas for them
+    pub keys_removed: usize,
+    /// Divergent keys that could not be fixed this round
+    pub errors: usize,
+}
+
+/// Expected `(key, blake3)` listing per volume and shard, derived from key metadata
+type ExpectedListing = HashMap<String, HashMap<u64, Vec<(String, String)>>>;
+
+fn expected_listing(metadata: &MetadataStore, num_shards: u64) -> Result<ExpectedListing> {
+    let mut expected: ExpectedListing = HashMap::new();
+    for key in metadata.list_keys()? {
+        let Some(meta) = metadata.get_key(&key)? else {
+            continue;
+        };
+        if meta.state != KeyState::Active {
+            continue;
+        }
+        let shard = shard_key(&key, num_shards);
+        for volume_id in &meta.replicas {
+            expected
+                .entry(volume_id.clone())
+                .or_default()
+                .entry(shard)
+                .or_default()
+                .push((key.clone(), meta.blake3.clone()));
+        }
+    }
+    Ok(expected)
+}
+
+/// Run one anti-entropy round over every readable volume
+pub async fn run_anti_entropy(
+    metadata: &MetadataStore,
+    num_shards: u64,
+) -> Result<AntiEntropyReport> {
+    let mut report = AntiEntropyReport::default();
+    let expected = expected_listing(metadata, num_shards)?;
+    // Keys being written right now may legitimately disagree with the metadata
+    let in_flight: HashSet<String> = metadata.list_txns()?.into_iter().map(|t| t.key).collect();
+
+    for volume in metadata.list_volumes()? {
+        if !volume.state.can_read() {
+            continue;
+        }
+        let empty = HashMap::new();
+        let volume_expected = expected.get(&volume.volume_id).unwrap_or(&empty);
+        if let Err(e) = sync_volume(
+            metadata,
+            &volume,
+            volume_expected,
+            &in_flight,
+            num_shards,
+            &mut report,
+        )
+        .await
+        {
+            tracing::warn!("Anti-entropy on {} failed: {}", volume.volume_id, e);
+            report.errors += 1;
+            continue;
+        }
+        report.volumes_checked += 1;
+    }
+
+    tracing::info!(
+        "Anti-entropy: {} volumes, {} diverged shards, {} repaired, {} removed, {} errors",
+        report.volumes_checked,
+        report.shards_diverged,
+        report.keys_repaired,
+        report.keys_removed,
+        report.errors
+    );
+    Ok(report)
+}
+
+async fn sync_volume(
+    metadata: &MetadataStore,
+    volume: &VolumeMetadata,
+    expected: &HashMap<u64, Vec<(String, String)>>,
+    in_flight: &HashSet<String>,
+    num_shards: u64,
+    report: &mut AntiEntropyReport,
+) -> Result<()> {
+    let mut client = VolumeClient::connect(volume.grpc_address.clone()).await?;
+    let roots = client.merkle_roots(num_shards).await?;
+
+    for shard in 0..num_shards {
+        let shard_expected = expected.get(&shard).map(Vec::as_slice).unwrap_or(&[]);
+        let expected_tree =
+            MerkleTree::build(shard_expected.iter().map(|(k, h)| (k.as_str(), h.as_str())));
+        let actual_root = roots
+            .get(shard as usize)
+            .map(|r| digest_from_bytes(r))
+            .unwrap_or_default();
+        if actual_root == expected_tree.root() {
+            continue;
+        }
+        report.shards_diverged += 1;
+
+        let leaves = client.merkle_leaves(num_shards, shard).await?;
+        let actual_tree =
+            MerkleTree::from_leaves(leaves.iter().map(|l| digest_from_bytes(l)).collect());
+
+        for bucket in expected_tree.diff(&actual_tree) {
+            let actual: HashMap<String, String> = client
+                .list_bucket(num_shards, shard, bucket as u32)
+                .await?
+                .into_iter()
+                .map(|entry| (entry.key, entry.blake3))
+                .collect();
+            let wanted: HashMap<&str, &str> = shard_expected
+                .iter()
+                .filter(|(key, _)| bucket_for(key) == bucket)
+                .map(|(k, h)| (k.as_str(), h.as_str()))
+                .collect();
+
+            for (key, hash) in &wanted {
+                if actual.get(*key).map(String::as_str) == Some(*hash) || in_flight.contains(*key) {
+                    continue;
+                }
+                match copy_key(metadata, key, volume).await {
+                    Ok(true) => report.keys_repaired += 1,
+                    Ok(false) => report.errors += 1,
+                    Err(e) => {
+                        tracing::warn!("Repair of {} on {} failed: {}", key, volume.volume_id, e);
+                        report.errors += 1;
+                    }
+                }
+            }
+
+            for key in actual.keys() {
+                if wanted.contains_key(key.as_str()) || in_flight.contains(key) {
+                    continue;
+                }
+                // Re-check: the key may have been written to this volume since the listing
+                let still_replica = metadata
+                    .get_key(key)?
+                    .map(|m| m.state == KeyState::Active && m.replicas.contains(&volume.volume_id))
+                    .unwrap_or(false);
+                if still_replica {
+                    continue;
+                }
+                let resp = client.delete(key.clone()).await?;
+                if resp.ok {
+                    report.keys_removed += 1;
+                } else {
+                    report.errors += 1;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Copy `key` to `target` from another replica holding the committed version.
+/// Returns `false` if no replica has a copy matching the metadata checksum.
+pub(crate) async fn copy_key(
+    metadata: &MetadataStore,
+    key: &str,
+    target: &VolumeMetadata,
+) -> Result<bool> {
+    let Some(meta) = metadata.get_key(key)? else {
+        return Ok(false);
+    };
+
+    for source_id in meta.replicas.iter().filter(|id| **id != target.volume_id) {
+        let Some(source) = metadata.get_volume(source_id)? else {
+            continue;
+        };
+        let mut source_client = match VolumeClient::connect(source.grpc_address.clone()).await {
+            Ok(client) => client,
+            Err(_) => continue,
+        };
+        let data = match source_client.read(key.to_string()).await {
+            Ok(Some(data)) if blake3_hash(&data) == meta.blake3 => data,
+            _ => continue,
+        };
+
+        write_replica(target, key, &meta.blake3, data).await?;
+        return Ok(true);
+    }
+
+    Ok(false)
+}
+
+/// Copy `keys`, quarantined by scrubbing on `volume`, back to it from their
+/// other replicas. Keys it no longer holds a replica of are skipped.
+pub async fn repair_quarantined(
+    metadata: Arc<MetadataStore>,
+    volume: VolumeMetadata,
+    keys: Vec<String>,
+) {
+    for key in keys {
+        let is_replica = matches!(
+            metadata.get_key(&key),
+            Ok(Some(meta)) if meta.state == KeyState::Active && meta.replicas.contains(&volume.volume_id)
+        );
+        if !is_replica {
+            continue;
+        }
+        match copy_key(&metadata, &key, &volume).await {
+            Ok(true) => tracing::info!("Repaired corrupted {} on {}", key, volume.volume_id),
+            Ok(false) => tracing::error!(
+                "No healthy replica of {} to repair {} from",
+                key,
+                volume.volume_id
+            ),
+            Err(e) => tracing::warn!("Repair of {} on {} failed: {}", key, volume.volume_id, e),
+        }
+    }
+}
+
+/// Run anti-entropy periodically while this node is leader.
+/// An interval of zero disables the task.
+pub fn start_anti_entropy_task(
+    metadata: Arc<MetadataStore>,
+    raft: Arc<RaftNode>,
+    num_shards: u64,
+    interval_secs: u64,
+) -> Option<tokio::task::JoinHandle<()>> {
+    if interval_secs == 0 {
+        return None;
+    }
+    Some(tokio::spawn(async move {
+        let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(interval_secs));
+        // The first tick fires immediately; skip it so startup is not slowed down
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            if !raft.is_leader() {
+                continue;
+            }
+            if let Err(e) = run_anti_entropy(&metadata, num_shards).await {
+                tracing::error!("Anti-entropy round failed: {}", e);
+            }
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coordinator::metadata::KeyMetadata;
+    use tempfile::tempdir;
+
+    fn key_meta(key: &str, replicas: &[&str], state: KeyState) -> KeyMetadata {
+        KeyMetadata {
+            key: key.to_string(),
+            replicas: replicas.iter().map(|r| r.to_string()).collect(),
+            size: 1,
+            blake3: blake3_hash(key.as_bytes()),
+            created_at: 0,
+            updated_at: 0,
+            state,
+            tags: Default::default(),
+            blob: None,
+            chunks: None,
+        }
+    }
+
+    #[test]
+    fn test_expected_listing() {
+        let dir = tempdir().unwrap();
+        let store = MetadataStore::open(dir.path().join("test.db")).unwrap();
+        store
+            .put_key(&key_meta("a", &["vol-1", "vol-2"], KeyState::Active))
+            .unwrap();
+        store
+            .put_key(&key_meta("b", &["vol-2"], KeyState::Active))
+            .unwrap();
+        store
+            .put_key(&key_meta("c", &["vol-1"], KeyState::Tombstone))
+            .unwrap();
+
+        let expected = expected_listing(&store, 4).unwrap();
+        let count = |volume: &str| -> usize {
+            expected
+                .get(volume)
+                .map(|shards| shards.values().map(Vec::len).sum())
+                .unwrap_or(0)
+        };
+        assert_eq!(count("vol-1"), 1);
+        assert_eq!(count("vol-2"), 2);
+        assert_eq!(
+            expected["vol-1"][&shard_key("a", 4)],
+            vec![("a".to_string(), blake3_hash(b"a"))]
+        );
+    }
+}
@@ -0,0 +1,67 @@
+//! Per-tenant bandwidth throttling
+//!
+//! Request and response bodies of the data routes are wrapped so their bytes
+//! pass through the tenant's token bucket in `QUOTA_MANAGER` as they stream:
+//! an upload over the tenant's ingress limit is read more slowly (TCP
+//! backpressure does the rest) and a download over its egress limit is sent
+//! more slowly, without holding up other tenants.
+
+use crate::common::quota::Direction;
+use crate::common::QUOTA_MANAGER;
+use axum::body::Body;
+use futures_util::StreamExt;
+
+/// Largest piece of a body moved at once, so a single large chunk (a whole
+/// value read from a volume) is spread over time rather than sent in a burst
+/// after a long wait
+pub const THROTTLE_CHUNK: usize = 64 * 1024;
+
+/// `body`, its bytes accounted to and held to the bandwidth limit of
+/// `tenant` in `direction`
+pub fn throttle_body(body: Body, tenant: String, direction: Direction) -> Body {
+    let mut data = body.into_data_stream();
+    Body::from_stream(async_stream::stream! {
+        while let Some(chunk) = data.next().await {
+            let mut bytes = match chunk {
+                Ok(bytes) => bytes,
+                Err(e) => {
+                    yield Err(e);
+                    break;
+                }
+            };
+            while !bytes.is_empty() {
+                let piece = bytes.split_to(bytes.len().min(THROTTLE_CHUNK));
+                QUOTA_MANAGER
+                    .throttle(&tenant, direction, piece.len() as u64)
+                    .await;
+                yield Ok(piece);
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::TenantQuota;
+    use std::time::{Duration, Instant};
+
+    #[tokio::test]
+    async fn test_throttle_body() {
+        let mut quota = TenantQuota::unlimited("bandwidth-test".to_string());
+        quota.egress_limit = 100 * 1024;
+        QUOTA_MANAGER.set_quota(quota);
+
+        // 100 KiB burst, the next 50 KiB wait half a second
+        let value = vec![7u8; 150 * 1024];
+        let body = throttle_body(
+            Body::from(value.clone()),
+            "bandwidth-test".to_string(),
+            Direction::Egress,
+        );
+        let start = Instant::now();
+        let bytes = axum::body::to_bytes(body, usize::MAX).await.unwrap();
+        assert_eq!(bytes, value);
+        assert!(start.elapsed() >= Duration::from_millis(400));
+    }
+}
@@ -0,0 +1,284 @@
+//! Change data capture (CDC)
+//!
+//! Every key write applied through Raft is recorded in the `cdc` column
+//! family, keyed by the index of its Raft log entry. The sequence numbers are
+//! therefore the same on every coordinator and only ever grow (with gaps where
+//! entries changed something other than keys). Events older than the
+//! configured retention are trimmed.
+//!
+//! The leader delivers the log to the configured sinks, at least once and in
+//! order: a webhook receiving NDJSON batches, or a file of `key<TAB>event`
+//! records as read by `kafka-console-producer --property parse.key=true`.
+//! Each sink's cursor is committed through Raft, so a new leader resumes
+//! where the old one stopped. Other systems (NATS, ...) can be fed from the
+//! webhook or the file.
+//! Consumers can also page through the log with `GET /cdc?from=<seq>`.
+
+use crate::common::{CdcConfig, CdcSinkConfig, Result};
+use crate::coordinator::dedup;
+use crate::coordinator::http::KeyChangeEvent;
+use crate::coordinator::metadata::{MetadataCommand, MetadataStore};
+use crate::coordinator::raft_node::RaftNode;
+use crate::coordinator::tenant;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Events handed to a sink at once
+const SINK_BATCH_SIZE: usize = 500;
+
+/// How often the CDC task looks for new events
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often expired events are trimmed
+const TRIM_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Config key holding the last sequence number delivered to a sink
+const CURSOR_PREFIX: &str = "cdc/cursor/";
+
+/// Kind of change
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CdcOp {
+    Put,
+    Delete,
+}
+
+/// One key change, as recorded in the CDC log
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CdcEvent {
+    /// Raft log index of the write
+    pub seq: u64,
+    pub op: CdcOp,
+    pub key: String,
+    /// Value size and BLAKE3 after a put
+    pub size: Option<u64>,
+    pub blake3: Option<String>,
+    /// Unix time (seconds) the change was applied on this node
+    pub timestamp: u64,
+}
+
+impl CdcEvent {
+    /// The event for a metadata command applied at Raft index `seq`, if it changes a key
+    pub fn from_command(seq: u64, command: &MetadataCommand) -> Option<Self> {
+        let timestamp = chrono::Utc::now().timestamp().max(0) as u64;
+        match command {
+            // Deduplicated content is internal; its keys are not reported
+            MetadataCommand::PutKey(meta) if dedup::is_reserved(&meta.key) => None,
+            MetadataCommand::PutKey(meta) | MetadataCommand::PutDedupKey { key: meta, .. } => {
+                Some(Self {
+                    seq,
+                    op: CdcOp::Put,
+                    key: meta.key.clone(),
+                    size: Some(meta.size),
+                    blake3: Some(meta.blake3.clone()),
+                    timestamp,
+                })
+            }
+            MetadataCommand::DeleteKey(key) => Some(Self {
+                seq,
+                op: CdcOp::Delete,
+                key: key.clone(),
+                size: None,
+                blake3: None,
+                timestamp,
+            }),
+            _ => None,
+        }
+    }
+
+    /// The notification of this change for watchers, if its key belongs to a tenant
+    pub fn watch_event(&self) -> Option<KeyChangeEvent> {
+        let (tenant, key) = self.key.split_once('/')?;
+        if !tenant::is_valid(tenant) {
+            return None;
+        }
+        let event = match self.op {
+            CdcOp::Put => "put",
+            CdcOp::Delete => "delete",
+        };
+        Some(KeyChangeEvent {
+            event: event.to_string(),
+            key: key.to_string(),
+            tenant: Some(tenant.to_string()),
+            timestamp: self.timestamp as i64,
+        })
+    }
+}
+
+impl CdcSinkConfig {
+    /// Stable identifier of the sink, used for its cursor
+    pub fn id(&self) -> String {
+        match self {
+            CdcSinkConfig::Webhook { url } => format!("webhook:{}", url),
+            CdcSinkConfig::File { path } => format!("file:{}", path.display()),
+        }
+    }
+
+    /// Deliver a batch of events. Fails if any of them may not have arrived.
+    pub async fn deliver(&self, events: &[CdcEvent]) -> Result<()> {
+        match self {
+            CdcSinkConfig::Webhook { url } => {
+                let body: String = events
+                    .iter()
+                    .map(|e| format!("{}\n", serde_json::to_string(e).unwrap()))
+                    .collect();
+                let resp = reqwest::Client::new()
+                    .post(url)
+                    .header("content-type", "application/x-ndjson")
+                    .body(body)
+                    .send()
+                    .await
+                    .map_err(|e| crate::Error::Http(e.to_string()))?;
+                if !resp.status().is_success() {
+                    return Err(crate::Error::Http(format!(
+                        "CDC webhook {} answered {}",
+                        url,
+                        resp.status()
+                    )));
+                }
+                Ok(())
+            }
+            CdcSinkConfig::File { path } => {
+                use tokio::io::AsyncWriteExt;
+                let mut records = String::new();
+                for e in events {
+                    records.push_str(&e.key);
+                    records.push('\t');
+                    records.push_str(&serde_json::to_string(e).unwrap());
+                    records.push('\n');
+                }
+                let mut file = tokio::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .await?;
+                file.write_all(records.as_bytes()).await?;
+                file.sync_data().await?;
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Last sequence number delivered to `sink`
+pub fn sink_cursor(metadata: &MetadataStore, sink: &CdcSinkConfig) -> Result<u64> {
+    let key = format!("{}{}", CURSOR_PREFIX, sink.id());
+    Ok(metadata
+        .get_config(&key)?
+        .and_then(|bytes| String::from_utf8(bytes).ok())
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(0))
+}
+
+/// Deliver the events `sink` hasn't seen yet, one batch at a time, committing
+/// its cursor after each. Returns how many events were delivered.
+pub async fn deliver_pending(
+    metadata: &MetadataStore,
+    raft: &RaftNode,
+    sink: &CdcSinkConfig,
+) -> Result<usize> {
+    let mut delivered = 0;
+    loop {
+        let cursor = sink_cursor(metadata, sink)?;
+        let events = metadata.cdc_events(cursor + 1, SINK_BATCH_SIZE)?;
+        let Some(last) = events.last().map(|e| e.seq) else {
+            return Ok(delivered);
+        };
+        sink.deliver(&events).await?;
+        raft.propose(&MetadataCommand::PutConfig {
+            key: format!("{}{}", CURSOR_PREFIX, sink.id()),
+            value: last.to_string().into_bytes(),
+        })
+        .await?;
+        delivered += events.len();
+        if events.len() < SINK_BATCH_SIZE {
+            return Ok(delivered);
+        }
+    }
+}
+
+/// Background task: trims expired events and, on the leader, feeds the sinks
+pub fn start_cdc_task(
+    metadata: Arc<MetadataStore>,
+    raft: Arc<RaftNode>,
+    config: CdcConfig,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut last_trim = tokio::time::Instant::now();
+        loop {
+            tokio::time::sleep(POLL_INTERVAL).await;
+
+            if config.retention_secs > 0 && last_trim.elapsed() >= TRIM_INTERVAL {
+                last_trim = tokio::time::Instant::now();
+                let now = chrono::Utc::now().timestamp().max(0) as u64;
+                match metadata.trim_cdc(now.saturating_sub(config.retention_secs)) {
+                    Ok(0) => {}
+                    Ok(n) => tracing::debug!("Trimmed {} expired CDC events", n),
+                    Err(e) => tracing::warn!("CDC trim failed: {}", e),
+                }
+            }
+
+            if !raft.is_leader() {
+                continue;
+            }
+            for sink in &config.sinks {
+                if let Err(e) = deliver_pending(&metadata, &raft, sink).await {
+                    tracing::warn!("CDC delivery to {} failed: {}", sink.id(), e);
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_file_sink_writes_keyed_records() {
+        let dir = tempfile::tempdir().unwrap();
+        let sink = CdcSinkConfig::File {
+            path: dir.path().join("cdc.log"),
+        };
+        let event = CdcEvent {
+            seq: 3,
+            op: CdcOp::Delete,
+            key: "a".to_string(),
+            size: None,
+            blake3: None,
+            timestamp: 1,
+        };
+        sink.deliver(&[event.clone()]).await.unwrap();
+        sink.deliver(&[event.clone()]).await.unwrap();
+
+        let written = std::fs::read_to_string(dir.path().join("cdc.log")).unwrap();
+        let lines: Vec<&str> = written.lines().collect();
+        assert_eq!(lines.len(), 2);
+        let (key, json) = lines[0].split_once('\t').unwrap();
+        assert_eq!(key, "a");
+        assert_eq!(serde_json::from_str::<CdcEvent>(json).unwrap(), event);
+    }
+
+    #[test]
+    fn test_watch_event() {
+        let mut event = CdcEvent {
+            seq: 1,
+            op: CdcOp::Put,
+            key: "acme/photos/cat.jpg".to_string(),
+            size: Some(3),
+            blake3: Some("h".to_string()),
+            timestamp: 7,
+        };
+        let change = event.watch_event().unwrap();
+        assert_eq!(change.event, "put");
+        assert_eq!(change.key, "photos/cat.jpg");
+        assert_eq!(change.tenant.as_deref(), Some("acme"));
+        assert_eq!(change.timestamp, 7);
+
+        event.key = ".cas/acme/h".to_string();
+        assert!(event.watch_event().is_none());
+        event.key = "untenanted".to_string();
+        assert!(event.watch_event().is_none());
+    }
+}
@@ -0,0 +1,263 @@
+//! Large object chunking
+//!
+//! A PUT larger than `threshold_bytes` is split into `chunk_size` chunks, each
+//! stored as its own key, `.chunks/<tenant>/<upload id>/<index>`, written
+//! through 2PC like any other key. Chunks are placed independently, so a large
+//! value spreads across segments and volumes instead of landing whole in one
+//! segment. The user's key holds the manifest (`KeyMetadata::chunks`) listing
+//! the chunk keys in order, and has no replicas of its own.
+//!
+//! Chunks are written and read `max_parallel` at a time. Each chunk read is
+//! checked against its own hash, and a whole value against the key's; a range
+//! read only fetches the chunks it overlaps.
+//!
+//! Overwriting or deleting the key removes its chunks' metadata in the state
+//! machine, leaving their bytes to the orphan GC. A failed upload drops the
+//! chunks it already wrote the same way. Chunk keys are reserved like
+//! deduplicated content, and chunked values are never tiered.
+//!
+//! Values above the threshold are chunked even with deduplication enabled.
+
+use crate::common::range::slice_of;
+use crate::common::{blake3_hash, timestamp_now, ChunkingConfig, Result};
+use crate::coordinator::consistency::{self, ConsistencyLevel};
+use crate::coordinator::metadata::{
+    ChunkManifest, KeyMetadata, KeyState, MetadataCommand, MetadataStore,
+};
+use crate::coordinator::placement::PlacementManager;
+use crate::coordinator::raft_node::RaftNode;
+use crate::coordinator::txn::execute_2pc;
+use futures_util::stream::{self, StreamExt, TryStreamExt};
+use once_cell::sync::Lazy;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex, RwLock};
+
+/// Prefix of the keys holding chunks of large values
+pub const CHUNK_PREFIX: &str = ".chunks/";
+
+static CONFIG: Lazy<RwLock<ChunkingConfig>> = Lazy::new(Default::default);
+
+/// Set the chunking of new writes (from `CoordinatorConfig::chunking`)
+pub fn set_config(config: ChunkingConfig) {
+    *CONFIG.write().unwrap() = config;
+}
+
+/// Current chunking configuration
+pub fn config() -> ChunkingConfig {
+    *CONFIG.read().unwrap()
+}
+
+/// Whether a value of `size` bytes is written in chunks
+pub fn should_chunk(size: u64) -> bool {
+    let config = config();
+    config.threshold_bytes > 0 && size > config.threshold_bytes
+}
+
+/// Whether `key` holds a chunk
+pub fn is_chunk_key(key: &str) -> bool {
+    key.starts_with(CHUNK_PREFIX)
+}
+
+/// Key of chunk `index` of an upload of `key`, grouped under the key's
+/// tenant (its first path segment)
+pub fn chunk_key(key: &str, upload: &str, index: usize) -> String {
+    match key.split_once('/') {
+        Some((tenant, _)) => format!("{}{}/{}/{:06}", CHUNK_PREFIX, tenant, upload, index),
+        None => format!("{}{}/{:06}", CHUNK_PREFIX, upload, index),
+    }
+}
+
+/// Write `key` as chunks and its manifest. Returns the number of chunks.
+pub async fn put_chunked(
+    metadata: &Arc<MetadataStore>,
+    raft: &Arc<RaftNode>,
+    placement: &Mutex<PlacementManager>,
+    key: &str,
+    data: Vec<u8>,
+    tags: BTreeMap<String, String>,
+    level: ConsistencyLevel,
+) -> Result<usize> {
+    let config = config();
+    let chunk_size = config.chunk_size.max(1);
+    let upload = uuid::Uuid::new_v4().simple().to_string();
+    let chunks: Vec<String> = (0..data.len().div_ceil(chunk_size as usize))
+        .map(|index| chunk_key(key, &upload, index))
+        .collect();
+
+    let pieces = chunks.iter().zip(data.chunks(chunk_size as usize));
+    let writes: Vec<Result<()>> = stream::iter(pieces)
+        .map(|(chunk, bytes)| write_chunk(metadata, raft, placement, chunk, bytes.to_vec(), level))
+        .buffer_unordered(config.max_parallel.max(1))
+        .collect()
+        .await;
+    if let Some(e) = writes.into_iter().find_map(|write| write.err()) {
+        discard(raft, &chunks).await;
+        return Err(e);
+    }
+
+    let now = timestamp_now();
+    let created_at = metadata
+        .get_key(key)?
+        .map(|meta| meta.created_at)
+        .unwrap_or(now);
+    let count = chunks.len();
+    let manifest = KeyMetadata {
+        key: key.to_string(),
+        replicas: Vec::new(),
+        size: data.len() as u64,
+        blake3: blake3_hash(&data),
+        created_at,
+        updated_at: now,
+        state: KeyState::Active,
+        tags,
+        blob: None,
+        chunks: Some(ChunkManifest {
+            chunk_size,
+            chunks: chunks.clone(),
+        }),
+    };
+    if let Err(e) = raft.propose(&MetadataCommand::PutKey(manifest)).await {
+        discard(raft, &chunks).await;
+        return Err(e);
+    }
+    Ok(count)
+}
+
+/// Store one chunk on its own placement
+async fn write_chunk(
+    metadata: &Arc<MetadataStore>,
+    raft: &Arc<RaftNode>,
+    placement: &Mutex<PlacementManager>,
+    chunk: &str,
+    bytes: Vec<u8>,
+    level: ConsistencyLevel,
+) -> Result<()> {
+    let volumes = metadata.get_healthy_volumes()?;
+    let target_ids = placement.lock().unwrap().select_volumes(chunk, &volumes)?;
+    let targets: Vec<_> = volumes
+        .into_iter()
+        .filter(|v| target_ids.contains(&v.volume_id))
+        .collect();
+    execute_2pc(
+        metadata,
+        raft,
+        chunk,
+        &targets,
+        bytes,
+        BTreeMap::new(),
+        level,
+    )
+    .await?;
+    Ok(())
+}
+
+/// Drop the metadata of the chunks of a failed upload, leaving their bytes
+/// to the orphan GC
+async fn discard(raft: &RaftNode, chunks: &[String]) {
+    for chunk in chunks {
+        if let Err(e) = raft
+            .propose(&MetadataCommand::DeleteKey(chunk.clone()))
+            .await
+        {
+            tracing::warn!("Dropping chunk {} of a failed upload failed: {}", chunk, e);
+        }
+    }
+}
+
+/// Read the value of `meta`, or `length` bytes of it from `offset`, from its
+/// chunks or its replicas. With `verify`, the replicas are read with
+/// `consistency::verified_read`, a range of an unchunked value by reading
+/// the whole value.
+pub async fn read_value(
+    metadata: &MetadataStore,
+    meta: &KeyMetadata,
+    level: ConsistencyLevel,
+    range: Option<(u64, u64)>,
+    verify: bool,
+) -> Result<Vec<u8>> {
+    match (&meta.chunks, range) {
+        (Some(manifest), _) => read_chunked(metadata, meta, manifest, level, range, verify).await,
+        (None, Some((offset, length))) if verify => {
+            let value = consistency::verified_read(metadata, meta, level).await?;
+            Ok(slice_of(&value, offset, length).to_vec())
+        }
+        (None, Some((offset, length))) => {
+            consistency::quorum_read_range(metadata, meta, level, offset, length).await
+        }
+        (None, None) if verify => consistency::verified_read(metadata, meta, level).await,
+        (None, None) => consistency::quorum_read(metadata, meta, level).await,
+    }
+}
+
+/// Read the chunks of `meta` overlapping `range` (all of them by default)
+async fn read_chunked(
+    metadata: &MetadataStore,
+    meta: &KeyMetadata,
+    manifest: &ChunkManifest,
+    level: ConsistencyLevel,
+    range: Option<(u64, u64)>,
+    verify: bool,
+) -> Result<Vec<u8>> {
+    let (offset, length) = range.unwrap_or((0, meta.size));
+    if length == 0 {
+        return Ok(Vec::new());
+    }
+    let chunk_size = manifest.chunk_size.max(1);
+    let first = (offset / chunk_size) as usize;
+    let last = ((offset + length - 1) / chunk_size) as usize;
+    let chunks = manifest
+        .chunks
+        .get(first..=last.min(manifest.chunks.len().saturating_sub(1)))
+        .unwrap_or_default();
+
+    let parts: Vec<Vec<u8>> = stream::iter(chunks)
+        .map(|chunk| async move {
+            let chunk_meta = metadata
+                .get_key(chunk)?
+                .filter(|m| m.state == KeyState::Active)
+                .ok_or_else(|| crate::Error::NotFound(format!("{} (chunk {})", meta.key, chunk)))?;
+            if verify {
+                consistency::verified_read(metadata, &chunk_meta, level).await
+            } else {
+                consistency::quorum_read(metadata, &chunk_meta, level).await
+            }
+        })
+        .buffered(config().max_parallel.max(1))
+        .try_collect()
+        .await?;
+    let value = parts.concat();
+
+    if range.is_none() {
+        let actual = blake3_hash(&value);
+        if actual != meta.blake3 {
+            return Err(crate::Error::ChecksumMismatch {
+                expected: meta.blake3.clone(),
+                actual,
+            });
+        }
+        return Ok(value);
+    }
+    let start = offset - first as u64 * chunk_size;
+    Ok(slice_of(&value, start, length).to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chunk_key() {
+        let key = chunk_key("acme/videos/a.mp4", "u1", 3);
+        assert_eq!(key, ".chunks/acme/u1/000003");
+        assert!(is_chunk_key(&key));
+        assert_eq!(chunk_key("plain", "u1", 0), ".chunks/u1/000000");
+        assert!(!is_chunk_key("acme/.chunks/a"));
+    }
+
+    #[test]
+    fn test_should_chunk() {
+        let default = ChunkingConfig::default();
+        assert!(!should_chunk(default.threshold_bytes));
+        assert!(should_chunk(default.threshold_bytes + 1));
+    }
+}
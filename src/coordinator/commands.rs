@@ -0,0 +1,73 @@
+//! Per-volume command queue
+//!
+//! Work for a volume is queued here and handed over in the response to the
+//! volume's next heartbeat. The queue lives in memory on the Raft leader,
+//! the only coordinator that queues commands and takes heartbeats; commands
+//! are lost with a change of leader, and not retried if the volume loses them.
+
+use crate::common::VolumeCommand;
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Commands waiting to be delivered, by volume id
+#[derive(Default)]
+pub struct CommandQueue {
+    pending: Mutex<HashMap<String, Vec<VolumeCommand>>>,
+}
+
+impl CommandQueue {
+    /// Queue a command for a volume. A command already waiting for the same
+    /// volume is not queued twice.
+    pub fn enqueue(&self, volume_id: &str, command: VolumeCommand) {
+        let mut pending = self.pending.lock().unwrap();
+        let queue = pending.entry(volume_id.to_string()).or_default();
+        if !queue.contains(&command) {
+            queue.push(command);
+        }
+    }
+
+    /// Take every command waiting for a volume
+    pub fn take(&self, volume_id: &str) -> Vec<VolumeCommand> {
+        self.pending
+            .lock()
+            .unwrap()
+            .remove(volume_id)
+            .unwrap_or_default()
+    }
+
+    /// Commands waiting for a volume, without removing them
+    pub fn pending(&self, volume_id: &str) -> Vec<VolumeCommand> {
+        self.pending
+            .lock()
+            .unwrap()
+            .get(volume_id)
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+/// Global command queue, drained by the heartbeat handler
+pub static VOLUME_COMMANDS: Lazy<CommandQueue> = Lazy::new(CommandQueue::default);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_enqueue_take() {
+        let queue = CommandQueue::default();
+        queue.enqueue("vol-1", VolumeCommand::Snapshot);
+        queue.enqueue("vol-1", VolumeCommand::Snapshot);
+        queue.enqueue("vol-1", VolumeCommand::CompactSegment(2));
+        queue.enqueue("vol-2", VolumeCommand::Drain);
+
+        assert_eq!(queue.pending("vol-1").len(), 2);
+        assert_eq!(
+            queue.take("vol-1"),
+            vec![VolumeCommand::Snapshot, VolumeCommand::CompactSegment(2)]
+        );
+        assert!(queue.take("vol-1").is_empty());
+        assert_eq!(queue.take("vol-2"), vec![VolumeCommand::Drain]);
+    }
+}
@@ -0,0 +1,375 @@
+//! Tunable consistency levels
+//!
+//! Reads and writes accept `?consistency=one|quorum|all`. The level decides how
+//! many replica acknowledgements the coordinator waits for before answering.
+//! Each tenant can store its own default in the metadata config column family;
+//! the cluster-wide fallback is `quorum`.
+//!
+//! With `verify_on_read` (or `?verify=true`), a GET is served only once its
+//! data was checked against the committed blake3, ranges included, and a
+//! replica that answered with diverged data is repaired in the background
+//! from the verified value.
+
+use crate::common::{blake3_hash, Result};
+use crate::coordinator::metadata::{KeyMetadata, MetadataCommand, MetadataStore, VolumeMetadata};
+use crate::coordinator::raft_node::RaftNode;
+use crate::coordinator::txn::write_replica;
+use crate::coordinator::volume_client::VolumeClient;
+use futures_util::stream::{FuturesUnordered, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+const TENANT_DEFAULT_PREFIX: &str = "consistency/";
+
+static VERIFY_ON_READ: AtomicBool = AtomicBool::new(false);
+
+/// Turn verification of every read on or off (from
+/// `CoordinatorConfig::verify_on_read`)
+pub fn set_verify_on_read(enabled: bool) {
+    VERIFY_ON_READ.store(enabled, Ordering::Relaxed);
+}
+
+/// Whether every read is verified
+pub fn verify_on_read() -> bool {
+    VERIFY_ON_READ.load(Ordering::Relaxed)
+}
+
+/// How many replicas must acknowledge a read or write
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConsistencyLevel {
+    /// A single replica is enough
+    One,
+    /// A strict majority of replicas
+    #[default]
+    Quorum,
+    /// Every replica
+    All,
+}
+
+impl ConsistencyLevel {
+    /// Number of acknowledgements required out of `replicas`
+    pub fn required_acks(&self, replicas: usize) -> usize {
+        match self {
+            ConsistencyLevel::One => replicas.min(1),
+            ConsistencyLevel::Quorum => replicas / 2 + 1,
+            ConsistencyLevel::All => replicas,
+        }
+        .min(replicas)
+    }
+}
+
+impl std::str::FromStr for ConsistencyLevel {
+    type Err = crate::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "one" => Ok(ConsistencyLevel::One),
+            "quorum" => Ok(ConsistencyLevel::Quorum),
+            "all" => Ok(ConsistencyLevel::All),
+            other => Err(crate::Error::InvalidConfig(format!(
+                "unknown consistency level: {}",
+                other
+            ))),
+        }
+    }
+}
+
+impl std::fmt::Display for ConsistencyLevel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConsistencyLevel::One => write!(f, "one"),
+            ConsistencyLevel::Quorum => write!(f, "quorum"),
+            ConsistencyLevel::All => write!(f, "all"),
+        }
+    }
+}
+
+/// Get the default consistency level configured for a tenant
+pub fn tenant_default(metadata: &MetadataStore, tenant: &str) -> ConsistencyLevel {
+    metadata
+        .get_config(&format!("{}{}", TENANT_DEFAULT_PREFIX, tenant))
+        .ok()
+        .flatten()
+        .and_then(|bytes| String::from_utf8(bytes).ok())
+        .and_then(|s| s.parse().ok())
+        .unwrap_or_default()
+}
+
+fn tenant_default_command(tenant: &str, level: ConsistencyLevel) -> MetadataCommand {
+    MetadataCommand::PutConfig {
+        key: format!("{}{}", TENANT_DEFAULT_PREFIX, tenant),
+        value: level.to_string().into_bytes(),
+    }
+}
+
+/// Set the default consistency level for a tenant
+pub async fn set_tenant_default(
+    raft: &RaftNode,
+    tenant: &str,
+    level: ConsistencyLevel,
+) -> Result<()> {
+    raft.propose(&tenant_default_command(tenant, level)).await
+}
+
+/// Resolve the level for a request: explicit query parameter first, then the tenant default
+pub fn resolve(
+    metadata: &MetadataStore,
+    tenant: &str,
+    requested: Option<&str>,
+) -> Result<ConsistencyLevel> {
+    match requested {
+        Some(level) => level.parse(),
+        None => Ok(tenant_default(metadata, tenant)),
+    }
+}
+
+/// Read `meta.key` from its replicas until `level` is satisfied.
+///
+/// All replicas are queried in parallel. A response counts towards the level only
+/// if its content hash matches the committed BLAKE3 in the key metadata, so stale
+/// or diverged replicas can never make up a quorum on their own.
+pub async fn quorum_read(
+    metadata: &MetadataStore,
+    meta: &KeyMetadata,
+    level: ConsistencyLevel,
+) -> Result<Vec<u8>> {
+    read_replicas(metadata, meta, level, false).await
+}
+
+/// `quorum_read` for a verified read: replicas seen answering with data
+/// that doesn't match the committed BLAKE3 are repaired from the value
+/// read, and if none matched the read fails with `ChecksumMismatch` rather
+/// than `InsufficientReplicas`.
+pub async fn verified_read(
+    metadata: &MetadataStore,
+    meta: &KeyMetadata,
+    level: ConsistencyLevel,
+) -> Result<Vec<u8>> {
+    read_replicas(metadata, meta, level, true).await
+}
+
+async fn read_replicas(
+    metadata: &MetadataStore,
+    meta: &KeyMetadata,
+    level: ConsistencyLevel,
+    verify: bool,
+) -> Result<Vec<u8>> {
+    let required = level.required_acks(meta.replicas.len());
+    if required == 0 {
+        return Err(crate::Error::NotFound(meta.key.clone()));
+    }
+
+    let mut reads: FuturesUnordered<_> = meta
+        .replicas
+        .iter()
+        .filter_map(|volume_id| metadata.get_volume(volume_id).ok().flatten())
+        .map(|volume| {
+            let key = meta.key.clone();
+            async move {
+                let result = match VolumeClient::connect(volume.grpc_address.clone()).await {
+                    Ok(mut client) => client.read(key).await,
+                    Err(e) => Err(e),
+                };
+                (volume, result)
+            }
+        })
+        .collect();
+
+    let mut matching = 0;
+    let mut value = None;
+    let mut diverged: Vec<(VolumeMetadata, String)> = Vec::new();
+    while let Some((volume, result)) = reads.next().await {
+        let volume_id = &volume.volume_id;
+        match result {
+            Ok(Some(data)) if blake3_hash(&data) == meta.blake3 => {
+                matching += 1;
+                value.get_or_insert(data);
+                if matching >= required {
+                    let value = value.unwrap_or_default();
+                    if verify {
+                        repair_diverged(meta, diverged, &value);
+                    }
+                    return Ok(value);
+                }
+            }
+            Ok(Some(data)) => {
+                tracing::warn!("Replica {} diverges for key {}", volume_id, meta.key);
+                diverged.push((volume, blake3_hash(&data)));
+            }
+            Ok(None) => {
+                tracing::warn!("Replica {} is missing key {}", volume_id, meta.key);
+            }
+            Err(e) => {
+                tracing::warn!("Read of {} from {} failed: {}", meta.key, volume_id, e);
+            }
+        }
+    }
+
+    if verify {
+        match value {
+            Some(value) => repair_diverged(meta, diverged, &value),
+            None => {
+                if let Some((_, actual)) = diverged.into_iter().next() {
+                    return Err(crate::Error::ChecksumMismatch {
+                        expected: meta.blake3.clone(),
+                        actual,
+                    });
+                }
+            }
+        }
+    }
+    Err(crate::Error::InsufficientReplicas {
+        needed: required,
+        available: matching,
+    })
+}
+
+/// Write `value`, checked against `meta`, over the copies of the replicas
+/// that answered a read with other data, in the background
+fn repair_diverged(meta: &KeyMetadata, diverged: Vec<(VolumeMetadata, String)>, value: &[u8]) {
+    for (volume, _) in diverged {
+        let (key, blake3, value) = (meta.key.clone(), meta.blake3.clone(), value.to_vec());
+        tokio::spawn(async move {
+            match write_replica(&volume, &key, &blake3, value).await {
+                Ok(()) => tracing::info!("Read-repaired {} on {}", key, volume.volume_id),
+                Err(e) => {
+                    tracing::warn!(
+                        "Read-repair of {} on {} failed: {}",
+                        key,
+                        volume.volume_id,
+                        e
+                    )
+                }
+            }
+        });
+    }
+}
+
+/// Read `length` bytes of `meta.key` from `offset` until `level` is satisfied.
+///
+/// A slice can't be checked against the committed BLAKE3, so replicas count
+/// towards the level when they agree with each other: the first slice of the
+/// expected length returned by enough replicas wins.
+pub async fn quorum_read_range(
+    metadata: &MetadataStore,
+    meta: &KeyMetadata,
+    level: ConsistencyLevel,
+    offset: u64,
+    length: u64,
+) -> Result<Vec<u8>> {
+    let required = level.required_acks(meta.replicas.len());
+    if required == 0 {
+        return Err(crate::Error::NotFound(meta.key.clone()));
+    }
+
+    let mut reads: FuturesUnordered<_> = meta
+        .replicas
+        .iter()
+        .filter_map(|volume_id| metadata.get_volume(volume_id).ok().flatten())
+        .map(|volume| {
+            let key = meta.key.clone();
+            async move {
+                let result = match VolumeClient::connect(volume.grpc_address.clone()).await {
+                    Ok(mut client) => client.read_range(key, offset, length).await,
+                    Err(e) => Err(e),
+                };
+                (volume.volume_id, result)
+            }
+        })
+        .collect();
+
+    let mut agreeing: HashMap<String, usize> = HashMap::new();
+    let mut best = 0;
+    while let Some((volume_id, result)) = reads.next().await {
+        match result {
+            Ok(Some(data)) if data.len() as u64 == length => {
+                let count = agreeing.entry(blake3_hash(&data)).or_insert(0);
+                *count += 1;
+                best = best.max(*count);
+                if *count >= required {
+                    return Ok(data);
+                }
+            }
+            Ok(Some(_)) => {
+                tracing::warn!("Replica {} diverges for key {}", volume_id, meta.key);
+            }
+            Ok(None) => {
+                tracing::warn!("Replica {} is missing key {}", volume_id, meta.key);
+            }
+            Err(e) => {
+                tracing::warn!("Read of {} from {} failed: {}", meta.key, volume_id, e);
+            }
+        }
+    }
+
+    Err(crate::Error::InsufficientReplicas {
+        needed: required,
+        available: best,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_required_acks() {
+        assert_eq!(ConsistencyLevel::One.required_acks(3), 1);
+        assert_eq!(ConsistencyLevel::Quorum.required_acks(3), 2);
+        assert_eq!(ConsistencyLevel::Quorum.required_acks(4), 3);
+        assert_eq!(ConsistencyLevel::All.required_acks(3), 3);
+        assert_eq!(ConsistencyLevel::Quorum.required_acks(0), 0);
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            "QUORUM".parse::<ConsistencyLevel>().unwrap(),
+            ConsistencyLevel::Quorum
+        );
+        assert!("most".parse::<ConsistencyLevel>().is_err());
+    }
+
+    #[test]
+    fn test_tenant_default() {
+        let dir = tempdir().unwrap();
+        let store = MetadataStore::open(dir.path().join("test.db")).unwrap();
+
+        assert_eq!(tenant_default(&store, "acme"), ConsistencyLevel::Quorum);
+        store
+            .apply(&tenant_default_command("acme", ConsistencyLevel::One))
+            .unwrap();
+        assert_eq!(tenant_default(&store, "acme"), ConsistencyLevel::One);
+        assert_eq!(
+            resolve(&store, "acme", Some("all")).unwrap(),
+            ConsistencyLevel::All
+        );
+    }
+
+    #[tokio::test]
+    async fn test_verified_read_without_data_is_not_a_mismatch() {
+        let dir = tempdir().unwrap();
+        let store = MetadataStore::open(dir.path().join("test.db")).unwrap();
+        let meta = KeyMetadata {
+            key: "k".to_string(),
+            replicas: vec!["vol-1".to_string()],
+            size: 1,
+            blake3: blake3_hash(b"v"),
+            created_at: 0,
+            updated_at: 0,
+            state: crate::coordinator::metadata::KeyState::Active,
+            tags: Default::default(),
+            blob: None,
+            chunks: None,
+        };
+
+        // No replica answered at all: unavailable, not corrupted
+        let err = verified_read(&store, &meta, ConsistencyLevel::One)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, crate::Error::InsufficientReplicas { .. }));
+    }
+}
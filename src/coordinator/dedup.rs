@@ -0,0 +1,172 @@
+//! Content-addressable deduplication
+//!
+//! With `dedup = true`, a PUT hashes the value first. The bytes are stored once
+//! per tenant under a content key, `.cas/<tenant>/<blake3>`, written through
+//! 2PC like any other key. The user's key only references it: its metadata
+//! carries the content key in `blob` and has no replicas of its own. A later
+//! PUT of the same bytes within the tenant proposes the reference without
+//! touching the volumes.
+//!
+//! The state machine counts references per content key (see
+//! `MetadataStore::blob_refs`). Overwriting or deleting a referencing key drops
+//! its reference, and dropping the last one removes the content key's metadata.
+//! Its bytes are then orphaned and reclaimed by the GC after the grace period.
+//!
+//! Content keys are reserved: clients can't write or delete them, and they are
+//! never tiered. So are the chunk keys of large values (see
+//! `coordinator::chunking`).
+
+use crate::common::{blake3_hash, timestamp_now, Result};
+use crate::coordinator::chunking;
+use crate::coordinator::consistency::ConsistencyLevel;
+use crate::coordinator::metadata::{KeyMetadata, KeyState, MetadataCommand, MetadataStore};
+use crate::coordinator::placement::PlacementManager;
+use crate::coordinator::raft_node::RaftNode;
+use crate::coordinator::txn::{execute_2pc, WriteOutcome};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Prefix of the keys holding deduplicated content
+pub const CAS_PREFIX: &str = ".cas/";
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Turn deduplication of new writes on or off
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Whether new writes are deduplicated
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Whether `key` is reserved for deduplicated content or chunks
+pub fn is_reserved(key: &str) -> bool {
+    key.starts_with(CAS_PREFIX) || chunking::is_chunk_key(key)
+}
+
+/// Content key for a value of `key` with hash `blake3`: shared within the
+/// key's tenant (its first path segment)
+pub fn cas_key(key: &str, blake3: &str) -> String {
+    match key.split_once('/') {
+        Some((tenant, _)) => format!("{}{}/{}", CAS_PREFIX, tenant, blake3),
+        None => format!("{}{}", CAS_PREFIX, blake3),
+    }
+}
+
+/// Outcome of a deduplicating write
+#[derive(Debug, Clone)]
+pub struct DedupOutcome {
+    /// Content key the value is stored under
+    pub blob: String,
+    /// The 2PC write of new content; `None` if it was already stored
+    pub write: Option<WriteOutcome>,
+}
+
+/// Write `key` as a reference to its content, storing the content first if
+/// the tenant doesn't have it yet
+pub async fn put_dedup(
+    metadata: &Arc<MetadataStore>,
+    raft: &Arc<RaftNode>,
+    placement: &Mutex<PlacementManager>,
+    key: &str,
+    data: Vec<u8>,
+    tags: BTreeMap<String, String>,
+    level: ConsistencyLevel,
+) -> Result<DedupOutcome> {
+    let blake3 = blake3_hash(&data);
+    let blob_key = cas_key(key, &blake3);
+    let size = data.len() as u64;
+
+    let existing = metadata
+        .get_key(&blob_key)?
+        .filter(|meta| meta.state == KeyState::Active && meta.blake3 == blake3);
+    let (blob, write) = match existing {
+        Some(blob) => (blob, None),
+        None => {
+            let volumes = metadata.get_healthy_volumes()?;
+            let target_ids = placement
+                .lock()
+                .unwrap()
+                .select_volumes(&blob_key, &volumes)?;
+            let targets: Vec<_> = volumes
+                .into_iter()
+                .filter(|v| target_ids.contains(&v.volume_id))
+                .collect();
+            let outcome = execute_2pc(
+                metadata,
+                raft,
+                &blob_key,
+                &targets,
+                data,
+                BTreeMap::new(),
+                level,
+            )
+            .await?;
+            let blob = metadata.get_key(&blob_key)?.ok_or_else(|| {
+                crate::Error::Internal(format!("{} missing after its write", blob_key))
+            })?;
+            (blob, Some(outcome))
+        }
+    };
+
+    let now = timestamp_now();
+    let created_at = metadata
+        .get_key(key)?
+        .map(|meta| meta.created_at)
+        .unwrap_or(now);
+    raft.propose(&MetadataCommand::PutDedupKey {
+        key: KeyMetadata {
+            key: key.to_string(),
+            replicas: Vec::new(),
+            size,
+            blake3,
+            created_at,
+            updated_at: now,
+            state: KeyState::Active,
+            tags,
+            blob: Some(blob_key.clone()),
+            chunks: None,
+        },
+        blob,
+    })
+    .await?;
+
+    Ok(DedupOutcome {
+        blob: blob_key,
+        write,
+    })
+}
+
+/// Metadata of the key actually holding the bytes of `meta`: its content key
+/// when deduplicated, `meta` itself otherwise
+pub fn resolve(metadata: &MetadataStore, meta: KeyMetadata) -> Result<KeyMetadata> {
+    let Some(blob) = &meta.blob else {
+        return Ok(meta);
+    };
+    match metadata.get_key(blob)? {
+        Some(content) if content.state == KeyState::Active => Ok(content),
+        _ => Err(crate::Error::NotFound(format!(
+            "{} (content {})",
+            meta.key, blob
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cas_key_is_per_tenant() {
+        assert_eq!(cas_key("acme/img/a.png", "ab12"), ".cas/acme/ab12");
+        assert_eq!(cas_key("acme/b", "ab12"), cas_key("acme/c/d", "ab12"));
+        assert_ne!(cas_key("acme/b", "ab12"), cas_key("other/b", "ab12"));
+        assert_eq!(cas_key("plain", "ab12"), ".cas/ab12");
+        assert!(is_reserved(&cas_key("acme/b", "ab12")));
+        assert!(!is_reserved("acme/.cas/b"));
+        assert!(is_reserved(".chunks/acme/u1/000000"));
+    }
+}
@@ -0,0 +1,283 @@
+//! Volume decommissioning
+//!
+//! Draining a volume marks it `Draining` (readable, no new writes), moves each
+//! of its replicas to another volume chosen by the placement policy, and finally
+//! removes the volume from the registry. Progress is persisted in the metadata
+//! config column family so it survives restarts and can be polled by the CLI.
+//! Every write goes through Raft, so drains are started on the leader.
+
+use crate::common::{timestamp_now, NodeState, Result, VolumeCommand};
+use crate::coordinator::anti_entropy::copy_key;
+use crate::coordinator::commands::VOLUME_COMMANDS;
+use crate::coordinator::metadata::{KeyState, MetadataCommand, MetadataStore};
+use crate::coordinator::placement::PlacementManager;
+use crate::coordinator::raft_node::RaftNode;
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+
+const DRAIN_PREFIX: &str = "drain/";
+
+/// Save progress every this many keys
+const PROGRESS_INTERVAL: usize = 100;
+
+/// Phase of a drain
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DrainState {
+    Running,
+    /// All keys moved and the volume was removed from the registry
+    Completed,
+    /// Some keys could not be moved; the volume stays `Draining`
+    Failed,
+}
+
+/// Progress of a volume drain
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DrainProgress {
+    pub volume_id: String,
+    pub state: DrainState,
+    pub total_keys: u64,
+    pub total_bytes: u64,
+    pub keys_moved: u64,
+    pub bytes_moved: u64,
+    pub keys_failed: u64,
+    pub started_at: u64,
+    pub updated_at: u64,
+    /// Estimated seconds left, once some data has moved
+    pub eta_secs: Option<u64>,
+    pub error: Option<String>,
+}
+
+impl DrainProgress {
+    fn new(volume_id: &str) -> Self {
+        let now = timestamp_now();
+        Self {
+            volume_id: volume_id.to_string(),
+            state: DrainState::Running,
+            total_keys: 0,
+            total_bytes: 0,
+            keys_moved: 0,
+            bytes_moved: 0,
+            keys_failed: 0,
+            started_at: now,
+            updated_at: now,
+            eta_secs: None,
+            error: None,
+        }
+    }
+
+    /// Refresh the timestamp and the ETA from the average throughput so far
+    fn touch(&mut self) {
+        self.updated_at = timestamp_now();
+        let elapsed = self.updated_at.saturating_sub(self.started_at).max(1);
+        let (done, total) = if self.total_bytes > 0 {
+            (self.bytes_moved, self.total_bytes)
+        } else {
+            (self.keys_moved, self.total_keys)
+        };
+        self.eta_secs = (done > 0).then(|| total.saturating_sub(done) * elapsed / done);
+    }
+}
+
+/// Get the progress of the last drain of a volume
+pub fn get_progress(metadata: &MetadataStore, volume_id: &str) -> Result<Option<DrainProgress>> {
+    match metadata.get_config(&format!("{}{}", DRAIN_PREFIX, volume_id))? {
+        Some(bytes) => serde_json::from_slice(&bytes)
+            .map(Some)
+            .map_err(|e| crate::Error::MetadataCorrupted(e.to_string())),
+        None => Ok(None),
+    }
+}
+
+fn progress_command(progress: &DrainProgress) -> Result<MetadataCommand> {
+    let value = serde_json::to_vec(progress)
+        .map_err(|e| crate::Error::Internal(format!("Serialize error: {}", e)))?;
+    Ok(MetadataCommand::PutConfig {
+        key: format!("{}{}", DRAIN_PREFIX, progress.volume_id),
+        value,
+    })
+}
+
+async fn save_progress(raft: &RaftNode, progress: &DrainProgress) -> Result<()> {
+    raft.propose(&progress_command(progress)?).await
+}
+
+/// Mark a volume `Draining` and start moving its keys in the background
+pub async fn start_drain(
+    metadata: Arc<MetadataStore>,
+    placement: Arc<Mutex<PlacementManager>>,
+    raft: Arc<RaftNode>,
+    volume_id: &str,
+) -> Result<DrainProgress> {
+    raft.ensure_leader()?;
+    let mut volume = metadata
+        .get_volume(volume_id)?
+        .ok_or_else(|| crate::Error::NotFound(volume_id.to_string()))?;
+    if let Some(progress) = get_progress(&metadata, volume_id)? {
+        if progress.state == DrainState::Running {
+            return Ok(progress);
+        }
+    }
+
+    volume.state = NodeState::Draining;
+    raft.propose(&MetadataCommand::PutVolume(volume)).await?;
+    // Have the volume itself refuse writes that bypass the coordinator
+    VOLUME_COMMANDS.enqueue(volume_id, VolumeCommand::Drain);
+
+    let progress = DrainProgress::new(volume_id);
+    save_progress(&raft, &progress).await?;
+
+    let task_progress = progress.clone();
+    tokio::spawn(async move {
+        let volume_id = task_progress.volume_id.clone();
+        if let Err(e) = run_drain(&metadata, &placement, &raft, task_progress).await {
+            tracing::error!("Drain of {} failed: {}", volume_id, e);
+            if let Ok(Some(mut progress)) = get_progress(&metadata, &volume_id) {
+                progress.state = DrainState::Failed;
+                progress.error = Some(e.to_string());
+                progress.touch();
+                let _ = save_progress(&raft, &progress).await;
+            }
+        }
+    });
+
+    Ok(progress)
+}
+
+async fn run_drain(
+    metadata: &MetadataStore,
+    placement: &Mutex<PlacementManager>,
+    raft: &RaftNode,
+    mut progress: DrainProgress,
+) -> Result<()> {
+    let volume_id = progress.volume_id.clone();
+
+    let mut keys = Vec::new();
+    for key in metadata.list_keys()? {
+        if let Some(meta) = metadata.get_key(&key)? {
+            if meta.state == KeyState::Active && meta.replicas.contains(&volume_id) {
+                progress.total_bytes += meta.size;
+                keys.push(key);
+            }
+        }
+    }
+    progress.total_keys = keys.len() as u64;
+    save_progress(raft, &progress).await?;
+    tracing::info!(
+        "Draining {}: {} keys, {} bytes",
+        volume_id,
+        progress.total_keys,
+        progress.total_bytes
+    );
+
+    for (i, key) in keys.iter().enumerate() {
+        match move_replica(metadata, placement, raft, key, &volume_id).await {
+            Ok(bytes) => {
+                progress.keys_moved += 1;
+                progress.bytes_moved += bytes;
+            }
+            Err(e) => {
+                tracing::warn!("Drain: moving {} off {} failed: {}", key, volume_id, e);
+                progress.keys_failed += 1;
+                progress.error = Some(e.to_string());
+            }
+        }
+        if (i + 1) % PROGRESS_INTERVAL == 0 {
+            progress.touch();
+            save_progress(raft, &progress).await?;
+        }
+    }
+
+    progress.touch();
+    if progress.keys_failed > 0 {
+        progress.state = DrainState::Failed;
+    } else {
+        raft.propose(&MetadataCommand::DeleteVolume(volume_id.clone()))
+            .await?;
+        progress.state = DrainState::Completed;
+        progress.eta_secs = Some(0);
+        tracing::info!("Drain of {} complete, volume removed", volume_id);
+    }
+    save_progress(raft, &progress).await
+}
+
+/// Copy one key off a volume that is going away (draining or dead) and
+/// repoint its metadata. Returns the number of bytes moved.
+pub(crate) async fn move_replica(
+    metadata: &MetadataStore,
+    placement: &Mutex<PlacementManager>,
+    raft: &RaftNode,
+    key: &str,
+    volume_id: &str,
+) -> Result<u64> {
+    let Some(meta) = metadata.get_key(key)? else {
+        return Ok(0);
+    };
+    if meta.state != KeyState::Active || !meta.replicas.contains(&volume_id.to_string()) {
+        // Overwritten or deleted since the drain started
+        return Ok(0);
+    }
+
+    let remaining: Vec<String> = meta
+        .replicas
+        .iter()
+        .filter(|id| *id != volume_id)
+        .cloned()
+        .collect();
+    let volumes = metadata.list_volumes()?;
+    let target_id = placement
+        .lock()
+        .unwrap()
+        .select_replacement(key, &remaining, &volumes)?;
+    let target = volumes
+        .into_iter()
+        .find(|v| v.volume_id == target_id)
+        .ok_or_else(|| crate::Error::NotFound(target_id.clone()))?;
+
+    if !copy_key(metadata, key, &target).await? {
+        return Err(crate::Error::Internal(format!(
+            "no replica of {} matches its checksum",
+            key
+        )));
+    }
+
+    // Only repoint if the key didn't change while it was being copied
+    let Some(mut current) = metadata.get_key(key)? else {
+        return Ok(0);
+    };
+    if current.blake3 != meta.blake3 || !current.replicas.contains(&volume_id.to_string()) {
+        return Ok(0);
+    }
+    current.replicas = remaining;
+    current.replicas.push(target_id);
+    current.updated_at = timestamp_now();
+    raft.propose(&MetadataCommand::PutKey(current)).await?;
+
+    Ok(meta.size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_progress_roundtrip() {
+        let dir = tempdir().unwrap();
+        let store = MetadataStore::open(dir.path().join("test.db")).unwrap();
+
+        assert!(get_progress(&store, "vol-1").unwrap().is_none());
+
+        let mut progress = DrainProgress::new("vol-1");
+        progress.total_bytes = 1000;
+        progress.bytes_moved = 250;
+        progress.keys_moved = 1;
+        progress.touch();
+        store.apply(&progress_command(&progress).unwrap()).unwrap();
+
+        let loaded = get_progress(&store, "vol-1").unwrap().unwrap();
+        assert_eq!(loaded.state, DrainState::Running);
+        assert_eq!(loaded.bytes_moved, 250);
+        assert!(loaded.eta_secs.is_some());
+    }
+}
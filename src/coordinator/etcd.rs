@@ -0,0 +1,784 @@
+//! A subset of etcd's v3 JSON gateway
+//!
+//! Tools keeping small configuration data in etcd can run against minikv:
+//! keys, values and leases are held by the Raft metadata store itself, not
+//! by volumes. As with etcd's gateway, requests and responses are JSON with
+//! base64 keys and values, and 64-bit integers as decimal strings.
+//!
+//! Supported: `/v3/kv/range`, `/v3/kv/put`, `/v3/kv/deleterange`,
+//! `/v3/lease/grant`, `/v3/lease/revoke`, `/v3/lease/keepalive`,
+//! `/v3/lease/timetolive` and `/v3/watch`. There are no transactions and no
+//! history: ranges read the latest revision and watches start from the next.
+//! Revisions are the Raft indexes of the changes, so they increase with
+//! gaps.
+//!
+//! Each tenant has a key space of its own, stored under `<tenant>/`. Writes
+//! need the ReadWrite role; ACL rules don't apply to etcd keys.
+
+use crate::common::{require_write_middleware, AuthExtension};
+use crate::coordinator::http::{resolve_tenant, CoordState};
+use crate::coordinator::metadata::{MetadataCommand, MetadataStore};
+use crate::coordinator::raft_node::RaftNode;
+use async_stream::stream;
+use axum::body::{Body, Bytes};
+use axum::extract::State;
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Router;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use std::convert::Infallible;
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
+use tokio::time::{Duration, Instant};
+
+/// Largest request body, etcd's default `--max-request-bytes`
+pub const MAX_REQUEST_BYTES: usize = 1536 * 1024;
+
+/// How often the leader looks for expired leases
+const LEASE_CHECK_INTERVAL: Duration = Duration::from_millis(500);
+
+/// When each lease expires unless kept alive, by coordinator and lease ID.
+/// Only leaders track deadlines; a new leader gives every lease its full TTL.
+static LEASE_DEADLINES: Lazy<Mutex<HashMap<(String, i64), Instant>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// A stored etcd key
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EtcdKeyValue {
+    /// Key in the tenant's key space (`<tenant>/<key>`)
+    pub key: Vec<u8>,
+    pub value: Vec<u8>,
+    pub create_revision: i64,
+    pub mod_revision: i64,
+    /// Number of puts since the key was created
+    pub version: i64,
+    /// Lease the key is attached to, 0 if none
+    pub lease: i64,
+}
+
+/// A lease keys can be attached to, deleted with them when not kept alive
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EtcdLease {
+    pub id: i64,
+    /// Seconds
+    pub ttl: i64,
+    pub tenant: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EtcdEventKind {
+    Put,
+    Delete,
+}
+
+/// A change to one key
+#[derive(Debug, Clone)]
+pub struct EtcdEvent {
+    pub kind: EtcdEventKind,
+    /// The key after the change; only its key and `mod_revision` when deleted
+    pub kv: EtcdKeyValue,
+    pub prev_kv: Option<EtcdKeyValue>,
+}
+
+impl EtcdEvent {
+    pub fn put(kv: EtcdKeyValue, prev_kv: Option<EtcdKeyValue>) -> Self {
+        Self {
+            kind: EtcdEventKind::Put,
+            kv,
+            prev_kv,
+        }
+    }
+
+    pub fn deleted(prev_kv: EtcdKeyValue, revision: i64) -> Self {
+        Self {
+            kind: EtcdEventKind::Delete,
+            kv: EtcdKeyValue {
+                key: prev_kv.key.clone(),
+                value: Vec::new(),
+                create_revision: 0,
+                mod_revision: revision,
+                version: 0,
+                lease: 0,
+            },
+            prev_kv: Some(prev_kv),
+        }
+    }
+}
+
+/// The changes made by one revision
+#[derive(Debug, Clone)]
+pub struct EtcdChange {
+    pub revision: i64,
+    pub events: Vec<EtcdEvent>,
+}
+
+/// Stored keys of `tenant`, as `[start, end)`
+pub fn tenant_range(tenant: &str) -> (Vec<u8>, Vec<u8>) {
+    (
+        format!("{}/", tenant).into_bytes(),
+        format!("{}0", tenant).into_bytes(),
+    )
+}
+
+/// The key space of a request's tenant
+struct Scope {
+    tenant: String,
+}
+
+impl Scope {
+    fn resolve(
+        state: &CoordState,
+        auth: &Option<axum::Extension<AuthExtension>>,
+        headers: &HeaderMap,
+    ) -> Result<Self, Response> {
+        match resolve_tenant(state, auth, headers) {
+            Ok(tenant) => Ok(Self { tenant }),
+            Err(e) => Err(failed(e)),
+        }
+    }
+
+    fn stored(&self, key: &[u8]) -> Vec<u8> {
+        [self.tenant.as_bytes(), b"/", key].concat()
+    }
+
+    fn user<'a>(&self, stored: &'a [u8]) -> &'a [u8] {
+        &stored[self.tenant.len() + 1..]
+    }
+
+    /// Stored keys named by `key` and `range_end`: the key alone if
+    /// `range_end` is empty, every key from `key` on if it is `\0`
+    fn bounds(&self, key: &[u8], range_end: &[u8]) -> (Vec<u8>, Option<Vec<u8>>) {
+        let end = match range_end {
+            [] => None,
+            [0] => Some(tenant_range(&self.tenant).1),
+            end => Some(self.stored(end)),
+        };
+        (self.stored(key), end)
+    }
+
+    fn kv(&self, kv: &EtcdKeyValue, keys_only: bool) -> Value {
+        let mut json = json!({
+            "key": BASE64.encode(self.user(&kv.key)),
+            "create_revision": kv.create_revision.to_string(),
+            "mod_revision": kv.mod_revision.to_string(),
+            "version": kv.version.to_string(),
+        });
+        if !keys_only {
+            json["value"] = BASE64.encode(&kv.value).into();
+        }
+        if kv.lease != 0 {
+            json["lease"] = kv.lease.to_string().into();
+        }
+        json
+    }
+}
+
+/// An etcd error: gRPC status code and message, with the HTTP status
+fn error(status: StatusCode, message: impl std::fmt::Display) -> Response {
+    let code = match status {
+        StatusCode::BAD_REQUEST => 3,
+        StatusCode::NOT_FOUND => 5,
+        StatusCode::CONFLICT => 6,
+        StatusCode::FORBIDDEN => 7,
+        StatusCode::TOO_MANY_REQUESTS => 8,
+        StatusCode::TEMPORARY_REDIRECT | StatusCode::SERVICE_UNAVAILABLE => 14,
+        StatusCode::UNAUTHORIZED => 16,
+        _ => 13,
+    };
+    let message = message.to_string();
+    (
+        status,
+        axum::Json(json!({ "error": message, "code": code, "message": message })),
+    )
+        .into_response()
+}
+
+fn failed(e: crate::Error) -> Response {
+    error(e.to_http_status(), e)
+}
+
+fn lease_not_found() -> Response {
+    error(
+        StatusCode::NOT_FOUND,
+        "etcdserver: requested lease not found",
+    )
+}
+
+fn decode(field: &str, value: &str) -> Result<Vec<u8>, Response> {
+    BASE64
+        .decode(value)
+        .map_err(|e| error(StatusCode::BAD_REQUEST, format!("invalid {}: {}", field, e)))
+}
+
+/// A JSON request, whatever its content type (etcd's gateway ignores it)
+fn parse<T: serde::de::DeserializeOwned>(body: &[u8]) -> Result<T, Response> {
+    serde_json::from_slice(body).map_err(|e| error(StatusCode::BAD_REQUEST, e))
+}
+
+/// An etcd key, which can't be empty
+fn decode_key(value: &str) -> Result<Vec<u8>, Response> {
+    let key = decode("key", value)?;
+    if key.is_empty() {
+        return Err(error(
+            StatusCode::BAD_REQUEST,
+            "etcdserver: key is not provided",
+        ));
+    }
+    Ok(key)
+}
+
+/// A 64-bit integer, as a decimal string or a number
+fn int64<'de, D: Deserializer<'de>>(deserializer: D) -> Result<i64, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Int64 {
+        Number(i64),
+        String(String),
+    }
+    match Int64::deserialize(deserializer)? {
+        Int64::Number(n) => Ok(n),
+        Int64::String(s) => s.parse().map_err(serde::de::Error::custom),
+    }
+}
+
+fn header(state: &CoordState, revision: i64) -> Value {
+    let id = blake3::hash(state.raft.node_id().as_bytes());
+    let member_id = u64::from_le_bytes(id.as_bytes()[..8].try_into().unwrap());
+    json!({
+        "cluster_id": "0",
+        "member_id": member_id.to_string(),
+        "revision": revision.to_string(),
+        "raft_term": state.raft.get_term().to_string(),
+    })
+}
+
+fn current_header(state: &CoordState) -> Result<Value, Response> {
+    let revision = state.metadata.etcd_revision().map_err(failed)?;
+    Ok(header(state, revision))
+}
+
+/// A lease of the scope's tenant
+fn tenant_lease(state: &CoordState, scope: &Scope, id: i64) -> Result<EtcdLease, Response> {
+    match state.metadata.get_etcd_lease(id).map_err(failed)? {
+        Some(lease) if lease.tenant == scope.tenant => Ok(lease),
+        _ => Err(lease_not_found()),
+    }
+}
+
+fn deadline_key(state: &CoordState, id: i64) -> (String, i64) {
+    (state.raft.node_id().to_string(), id)
+}
+
+type EtcdResult = Result<axum::Json<Value>, Response>;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct RangeRequest {
+    key: String,
+    range_end: String,
+    #[serde(deserialize_with = "int64")]
+    limit: i64,
+    #[serde(deserialize_with = "int64")]
+    revision: i64,
+    keys_only: bool,
+    count_only: bool,
+    /// Read this node's state, not the leader's
+    serializable: bool,
+}
+
+async fn range(
+    State(state): State<CoordState>,
+    auth: Option<axum::Extension<AuthExtension>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> EtcdResult {
+    let req: RangeRequest = parse(&body)?;
+    let scope = Scope::resolve(&state, &auth, &headers)?;
+    let key = decode_key(&req.key)?;
+    let range_end = decode("range_end", &req.range_end)?;
+    if !req.serializable {
+        state.raft.read_index().await.map_err(failed)?;
+    }
+    let revision = state.metadata.etcd_revision().map_err(failed)?;
+    if req.revision != 0 && req.revision != revision {
+        return Err(error(
+            StatusCode::BAD_REQUEST,
+            "etcdserver: only the current revision can be read",
+        ));
+    }
+    let (start, end) = scope.bounds(&key, &range_end);
+    let mut kvs = state
+        .metadata
+        .etcd_range(&start, end.as_deref())
+        .map_err(failed)?;
+    let count = kvs.len();
+    if req.limit > 0 {
+        kvs.truncate(req.limit as usize);
+    }
+    let mut resp = json!({
+        "header": header(&state, revision),
+        "count": count.to_string(),
+        "more": kvs.len() < count,
+    });
+    if !req.count_only {
+        resp["kvs"] = kvs
+            .iter()
+            .map(|kv| scope.kv(kv, req.keys_only))
+            .collect::<Vec<_>>()
+            .into();
+    }
+    Ok(axum::Json(resp))
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct PutRequest {
+    key: String,
+    value: String,
+    #[serde(deserialize_with = "int64")]
+    lease: i64,
+    prev_kv: bool,
+}
+
+async fn put(
+    State(state): State<CoordState>,
+    auth: Option<axum::Extension<AuthExtension>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> EtcdResult {
+    let req: PutRequest = parse(&body)?;
+    let scope = Scope::resolve(&state, &auth, &headers)?;
+    let key = scope.stored(&decode_key(&req.key)?);
+    let value = decode("value", &req.value)?;
+    state.raft.ensure_leader().map_err(failed)?;
+    if req.lease != 0 {
+        tenant_lease(&state, &scope, req.lease)?;
+    }
+    let prev = if req.prev_kv {
+        state.metadata.etcd_range(&key, None).map_err(failed)?.pop()
+    } else {
+        None
+    };
+    state
+        .raft
+        .propose(&MetadataCommand::EtcdPut {
+            key,
+            value,
+            lease: req.lease,
+        })
+        .await
+        .map_err(failed)?;
+    let mut resp = json!({ "header": current_header(&state)? });
+    if let Some(prev) = prev {
+        resp["prev_kv"] = scope.kv(&prev, false);
+    }
+    Ok(axum::Json(resp))
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct DeleteRangeRequest {
+    key: String,
+    range_end: String,
+    prev_kv: bool,
+}
+
+async fn delete_range(
+    State(state): State<CoordState>,
+    auth: Option<axum::Extension<AuthExtension>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> EtcdResult {
+    let req: DeleteRangeRequest = parse(&body)?;
+    let scope = Scope::resolve(&state, &auth, &headers)?;
+    let key = decode_key(&req.key)?;
+    let range_end = decode("range_end", &req.range_end)?;
+    state.raft.ensure_leader().map_err(failed)?;
+    let (start, end) = scope.bounds(&key, &range_end);
+    let deleted = state
+        .metadata
+        .etcd_range(&start, end.as_deref())
+        .map_err(failed)?;
+    if !deleted.is_empty() {
+        state
+            .raft
+            .propose(&MetadataCommand::EtcdDeleteRange {
+                key: start,
+                range_end: end,
+            })
+            .await
+            .map_err(failed)?;
+    }
+    let mut resp = json!({
+        "header": current_header(&state)?,
+        "deleted": deleted.len().to_string(),
+    });
+    if req.prev_kv {
+        resp["prev_kvs"] = deleted
+            .iter()
+            .map(|kv| scope.kv(kv, false))
+            .collect::<Vec<_>>()
+            .into();
+    }
+    Ok(axum::Json(resp))
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct LeaseGrantRequest {
+    #[serde(rename = "TTL", deserialize_with = "int64")]
+    ttl: i64,
+    /// Chosen by the server if 0
+    #[serde(rename = "ID", deserialize_with = "int64")]
+    id: i64,
+}
+
+async fn lease_grant(
+    State(state): State<CoordState>,
+    auth: Option<axum::Extension<AuthExtension>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> EtcdResult {
+    let req: LeaseGrantRequest = parse(&body)?;
+    let scope = Scope::resolve(&state, &auth, &headers)?;
+    if req.ttl <= 0 {
+        return Err(error(
+            StatusCode::BAD_REQUEST,
+            "etcdserver: lease TTL must be positive",
+        ));
+    }
+    state.raft.ensure_leader().map_err(failed)?;
+    let id = match req.id {
+        0 => rand::random::<i64>() & i64::MAX,
+        id => id,
+    };
+    if id <= 0 || state.metadata.get_etcd_lease(id).map_err(failed)?.is_some() {
+        return Err(error(
+            StatusCode::CONFLICT,
+            "etcdserver: lease already exists",
+        ));
+    }
+    let lease = EtcdLease {
+        id,
+        ttl: req.ttl,
+        tenant: scope.tenant,
+    };
+    state
+        .raft
+        .propose(&MetadataCommand::EtcdLeaseGrant(lease))
+        .await
+        .map_err(failed)?;
+    LEASE_DEADLINES.lock().unwrap().insert(
+        deadline_key(&state, id),
+        Instant::now() + Duration::from_secs(req.ttl as u64),
+    );
+    Ok(axum::Json(json!({
+        "header": current_header(&state)?,
+        "ID": id.to_string(),
+        "TTL": req.ttl.to_string(),
+    })))
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct LeaseRequest {
+    #[serde(rename = "ID", deserialize_with = "int64")]
+    id: i64,
+    /// List the keys attached (time to live only)
+    keys: bool,
+}
+
+async fn lease_revoke(
+    State(state): State<CoordState>,
+    auth: Option<axum::Extension<AuthExtension>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> EtcdResult {
+    let req: LeaseRequest = parse(&body)?;
+    let scope = Scope::resolve(&state, &auth, &headers)?;
+    state.raft.ensure_leader().map_err(failed)?;
+    tenant_lease(&state, &scope, req.id)?;
+    state
+        .raft
+        .propose(&MetadataCommand::EtcdLeaseRevoke(req.id))
+        .await
+        .map_err(failed)?;
+    LEASE_DEADLINES
+        .lock()
+        .unwrap()
+        .remove(&deadline_key(&state, req.id));
+    Ok(axum::Json(json!({ "header": current_header(&state)? })))
+}
+
+/// Renew a lease, answered as one message of etcd's keep-alive stream
+async fn lease_keepalive(
+    State(state): State<CoordState>,
+    auth: Option<axum::Extension<AuthExtension>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> EtcdResult {
+    let req: LeaseRequest = parse(&body)?;
+    let scope = Scope::resolve(&state, &auth, &headers)?;
+    state.raft.ensure_leader().map_err(failed)?;
+    let lease = tenant_lease(&state, &scope, req.id)?;
+    LEASE_DEADLINES.lock().unwrap().insert(
+        deadline_key(&state, lease.id),
+        Instant::now() + Duration::from_secs(lease.ttl as u64),
+    );
+    Ok(axum::Json(json!({
+        "result": {
+            "header": current_header(&state)?,
+            "ID": lease.id.to_string(),
+            "TTL": lease.ttl.to_string(),
+        }
+    })))
+}
+
+async fn lease_time_to_live(
+    State(state): State<CoordState>,
+    auth: Option<axum::Extension<AuthExtension>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> EtcdResult {
+    let req: LeaseRequest = parse(&body)?;
+    let scope = Scope::resolve(&state, &auth, &headers)?;
+    // Deadlines are only known to the leader
+    state.raft.ensure_leader().map_err(failed)?;
+    let lease = match tenant_lease(&state, &scope, req.id) {
+        Ok(lease) => lease,
+        // As etcd, an unknown lease has a TTL of -1
+        Err(_) => {
+            return Ok(axum::Json(json!({
+                "header": current_header(&state)?,
+                "ID": req.id.to_string(),
+                "TTL": "-1",
+            })))
+        }
+    };
+    let remaining = LEASE_DEADLINES
+        .lock()
+        .unwrap()
+        .get(&deadline_key(&state, lease.id))
+        .map_or(lease.ttl, |deadline| {
+            deadline.saturating_duration_since(Instant::now()).as_secs() as i64
+        });
+    let mut resp = json!({
+        "header": current_header(&state)?,
+        "ID": lease.id.to_string(),
+        "TTL": remaining.to_string(),
+        "grantedTTL": lease.ttl.to_string(),
+    });
+    if req.keys {
+        let (start, end) = tenant_range(&scope.tenant);
+        let kvs = state
+            .metadata
+            .etcd_range(&start, Some(end.as_slice()))
+            .map_err(failed)?;
+        resp["keys"] = kvs
+            .iter()
+            .filter(|kv| kv.lease == lease.id)
+            .map(|kv| BASE64.encode(scope.user(&kv.key)))
+            .collect::<Vec<_>>()
+            .into();
+    }
+    Ok(axum::Json(resp))
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct WatchCreateRequest {
+    key: String,
+    range_end: String,
+    #[serde(deserialize_with = "int64")]
+    start_revision: i64,
+    prev_kv: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct WatchRequest {
+    create_request: WatchCreateRequest,
+}
+
+/// Stream the changes to a key or range as they are applied on this node,
+/// one JSON object per line, starting with a `created` message
+async fn watch(
+    State(state): State<CoordState>,
+    auth: Option<axum::Extension<AuthExtension>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response, Response> {
+    let req: WatchRequest = parse(&body)?;
+    let scope = Scope::resolve(&state, &auth, &headers)?;
+    let req = req.create_request;
+    let key = decode_key(&req.key)?;
+    let range_end = decode("range_end", &req.range_end)?;
+    let (start, end) = scope.bounds(&key, &range_end);
+    let mut changes = state.metadata.watch_etcd();
+    let revision = state.metadata.etcd_revision().map_err(failed)?;
+
+    let line = |message: Value| Ok::<_, Infallible>(Bytes::from(format!("{}\n", message)));
+    let created = json!({ "result": {
+        "header": header(&state, revision),
+        "watch_id": "0",
+        "created": true,
+    }});
+    // Past revisions are not kept
+    if req.start_revision != 0 && req.start_revision <= revision {
+        let mut canceled = created;
+        canceled["result"]["canceled"] = true.into();
+        canceled["result"]["compact_revision"] = (revision + 1).to_string().into();
+        return Ok(Body::from_stream(futures_util::stream::iter([line(canceled)])).into_response());
+    }
+    let stream = stream! {
+        yield line(created);
+        loop {
+            let change = match changes.recv().await {
+                Ok(change) => change,
+                Err(broadcast::error::RecvError::Lagged(_)) => {
+                    yield line(json!({ "result": {
+                        "header": header(&state, revision),
+                        "watch_id": "0",
+                        "canceled": true,
+                        "cancel_reason": "watcher fell behind",
+                    }}));
+                    break;
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            };
+            let events: Vec<Value> = change
+                .events
+                .iter()
+                .filter(|event| match &end {
+                    Some(end) => event.kv.key >= start && event.kv.key < *end,
+                    None => event.kv.key == start,
+                })
+                .map(|event| {
+                    let mut json = json!({ "kv": scope.kv(&event.kv, false) });
+                    if event.kind == EtcdEventKind::Delete {
+                        json["type"] = "DELETE".into();
+                        json["kv"] = json!({
+                            "key": BASE64.encode(scope.user(&event.kv.key)),
+                            "mod_revision": event.kv.mod_revision.to_string(),
+                        });
+                    }
+                    if let (true, Some(prev)) = (req.prev_kv, &event.prev_kv) {
+                        json["prev_kv"] = scope.kv(prev, false);
+                    }
+                    json
+                })
+                .collect();
+            if !events.is_empty() {
+                yield line(json!({ "result": {
+                    "header": header(&state, change.revision),
+                    "watch_id": "0",
+                    "events": events,
+                }}));
+            }
+        }
+    };
+    Ok(Body::from_stream(stream).into_response())
+}
+
+/// The etcd gateway routes. Those changing data need the ReadWrite role.
+pub fn routes() -> Router<CoordState> {
+    let writes = Router::new()
+        .route("/v3/kv/put", axum::routing::post(put))
+        .route("/v3/kv/deleterange", axum::routing::post(delete_range))
+        .route("/v3/lease/grant", axum::routing::post(lease_grant))
+        .route("/v3/lease/revoke", axum::routing::post(lease_revoke))
+        .route("/v3/lease/keepalive", axum::routing::post(lease_keepalive))
+        .route_layer(axum::middleware::from_fn(require_write_middleware));
+    Router::new()
+        .route("/v3/kv/range", axum::routing::post(range))
+        .route(
+            "/v3/lease/timetolive",
+            axum::routing::post(lease_time_to_live),
+        )
+        .route("/v3/watch", axum::routing::post(watch))
+        .merge(writes)
+        .layer(axum::extract::DefaultBodyLimit::max(MAX_REQUEST_BYTES))
+}
+
+/// Revoke the leases not kept alive in time, if this node is the leader
+pub async fn expire_leases(metadata: &MetadataStore, raft: &RaftNode) -> crate::Result<()> {
+    let node = raft.node_id().to_string();
+    if !raft.is_leader() {
+        LEASE_DEADLINES
+            .lock()
+            .unwrap()
+            .retain(|(owner, _), _| *owner != node);
+        return Ok(());
+    }
+    let leases = metadata.list_etcd_leases()?;
+    let now = Instant::now();
+    let expired: Vec<i64> = {
+        let mut deadlines = LEASE_DEADLINES.lock().unwrap();
+        let live: HashSet<i64> = leases.iter().map(|lease| lease.id).collect();
+        deadlines.retain(|(owner, id), _| *owner != node || live.contains(id));
+        leases
+            .iter()
+            .filter(|lease| {
+                let deadline = deadlines
+                    .entry((node.clone(), lease.id))
+                    .or_insert_with(|| now + Duration::from_secs(lease.ttl as u64));
+                *deadline <= now
+            })
+            .map(|lease| lease.id)
+            .collect()
+    };
+    for id in expired {
+        tracing::debug!("etcd lease {} expired", id);
+        raft.propose(&MetadataCommand::EtcdLeaseRevoke(id)).await?;
+    }
+    Ok(())
+}
+
+/// Start revoking expired etcd leases in the background
+pub fn start_lease_expiry_task(
+    metadata: Arc<MetadataStore>,
+    raft: Arc<RaftNode>,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(LEASE_CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            if let Err(e) = expire_leases(&metadata, &raft).await {
+                tracing::warn!("etcd lease expiry failed: {}", e);
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scope_bounds() {
+        let scope = Scope {
+            tenant: "acme".to_string(),
+        };
+        assert_eq!(scope.bounds(b"a", b""), (b"acme/a".to_vec(), None));
+        assert_eq!(
+            scope.bounds(b"a", b"b"),
+            (b"acme/a".to_vec(), Some(b"acme/b".to_vec()))
+        );
+        // `\0` ends the range at the end of the tenant's keys
+        assert_eq!(
+            scope.bounds(b"a", b"\0"),
+            (b"acme/a".to_vec(), Some(b"acme0".to_vec()))
+        );
+        assert_eq!(scope.user(b"acme/a/b"), b"a/b");
+    }
+
+    #[test]
+    fn test_int64_strings() {
+        let req: LeaseGrantRequest = serde_json::from_str(r#"{"TTL":"10","ID":7}"#).unwrap();
+        assert_eq!((req.ttl, req.id), (10, 7));
+        assert!(serde_json::from_str::<LeaseGrantRequest>(r#"{"TTL":"ten"}"#).is_err());
+    }
+}
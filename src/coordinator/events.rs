@@ -0,0 +1,269 @@
+//! Cluster events
+//!
+//! Leadership changes, volumes joining or changing state, volume commands
+//! finishing (compactions, snapshots, ...) and repairs starting or finishing
+//! are published here. The last `CAPACITY` events are kept in memory,
+//! numbered in order, for `GET /admin/events` to stream as Server-Sent
+//! Events: a client reconnecting with `Last-Event-ID` (or `?after=<seq>`)
+//! first gets the events it missed, if still buffered. Each event also goes
+//! to the webhooks (see `webhooks`), along with the key changes and quota
+//! breaches only they receive.
+//!
+//! Events are those the coordinator saw itself, mostly as leader, and
+//! sequence numbers restart with the process.
+
+use crate::common::NodeState;
+use crate::coordinator::repair::RepairState;
+use crate::coordinator::webhooks::WEBHOOKS;
+use async_stream::stream;
+use axum::extract::Query;
+use axum::http::HeaderMap;
+use axum::response::sse::{self, KeepAlive, Sse};
+use futures_util::Stream;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::convert::Infallible;
+use std::sync::Mutex;
+use tokio::sync::broadcast;
+
+/// Events kept for reconnecting clients
+pub const CAPACITY: usize = 1024;
+
+/// Global event log
+pub static EVENTS: Lazy<EventLog> = Lazy::new(EventLog::default);
+
+/// Something that happened in the cluster
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum ClusterEvent {
+    #[serde(rename = "leader.elected")]
+    LeaderElected { node_id: String, term: u64 },
+    #[serde(rename = "volume.joined")]
+    VolumeJoined { volume_id: String, address: String },
+    #[serde(rename = "volume.state")]
+    VolumeState {
+        volume_id: String,
+        previous: NodeState,
+        state: NodeState,
+    },
+    /// A command queued for a volume ran, e.g. `compact`
+    #[serde(rename = "command.finished")]
+    CommandFinished {
+        volume_id: String,
+        command: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+    #[serde(rename = "repair.started")]
+    RepairStarted {
+        replicas: usize,
+        dry_run: bool,
+        /// Resumed from the checkpoint of an interrupted repair
+        resumed: bool,
+    },
+    #[serde(rename = "repair.finished")]
+    RepairFinished {
+        state: RepairState,
+        keys_repaired: u64,
+        bytes_copied: u64,
+        keys_failed: u64,
+    },
+    /// Webhooks only
+    #[serde(rename = "key.put")]
+    KeyPut {
+        tenant: String,
+        key: String,
+        size: u64,
+        /// CDC sequence number of the change
+        seq: u64,
+    },
+    /// Webhooks only
+    #[serde(rename = "key.delete")]
+    KeyDelete {
+        tenant: String,
+        key: String,
+        seq: u64,
+    },
+    /// Webhooks only
+    #[serde(rename = "quota.exceeded")]
+    QuotaExceeded { tenant: String, message: String },
+}
+
+impl ClusterEvent {
+    /// Event type, as in filters and the `type` field
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ClusterEvent::LeaderElected { .. } => "leader.elected",
+            ClusterEvent::VolumeJoined { .. } => "volume.joined",
+            ClusterEvent::VolumeState { .. } => "volume.state",
+            ClusterEvent::CommandFinished { .. } => "command.finished",
+            ClusterEvent::RepairStarted { .. } => "repair.started",
+            ClusterEvent::RepairFinished { .. } => "repair.finished",
+            ClusterEvent::KeyPut { .. } => "key.put",
+            ClusterEvent::KeyDelete { .. } => "key.delete",
+            ClusterEvent::QuotaExceeded { .. } => "quota.exceeded",
+        }
+    }
+}
+
+/// A published event
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Event {
+    /// Position in this coordinator's log, from 1
+    pub seq: u64,
+    /// Unix time (seconds) it was published
+    pub timestamp: i64,
+    #[serde(flatten)]
+    pub event: ClusterEvent,
+}
+
+/// The last events published, and their live subscribers
+pub struct EventLog {
+    /// Sequence number of the last event, and the buffered events
+    log: Mutex<(u64, VecDeque<Event>)>,
+    tx: broadcast::Sender<Event>,
+}
+
+impl Default for EventLog {
+    fn default() -> Self {
+        Self {
+            log: Mutex::new((0, VecDeque::with_capacity(CAPACITY))),
+            tx: broadcast::channel(CAPACITY).0,
+        }
+    }
+}
+
+impl EventLog {
+    /// Number, buffer and broadcast `event`
+    pub fn push(&self, event: ClusterEvent) -> Event {
+        let mut log = self.log.lock().unwrap();
+        log.0 += 1;
+        let event = Event {
+            seq: log.0,
+            timestamp: chrono::Utc::now().timestamp(),
+            event,
+        };
+        if log.1.len() == CAPACITY {
+            log.1.pop_front();
+        }
+        log.1.push_back(event.clone());
+        // Sent under the lock, so subscribers see no gap or repeat
+        let _ = self.tx.send(event.clone());
+        event
+    }
+
+    /// The buffered events after `after`, and a receiver of the next ones
+    pub fn subscribe(&self, after: u64) -> (Vec<Event>, broadcast::Receiver<Event>) {
+        let log = self.log.lock().unwrap();
+        let missed = log.1.iter().filter(|e| e.seq > after).cloned().collect();
+        (missed, self.tx.subscribe())
+    }
+
+    /// Sequence number of the last event published
+    pub fn last_seq(&self) -> u64 {
+        self.log.lock().unwrap().0
+    }
+}
+
+/// Publish `event` to `/admin/events` and the webhooks
+pub fn publish(event: ClusterEvent) {
+    tracing::debug!("Cluster event {}", event.kind());
+    EVENTS.push(event.clone());
+    WEBHOOKS.notify(event);
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct EventsQuery {
+    after: Option<u64>,
+}
+
+fn sse_event(event: &Event) -> sse::Event {
+    sse::Event::default()
+        .id(event.seq.to_string())
+        .event(event.event.kind())
+        .data(serde_json::to_string(event).unwrap_or_default())
+}
+
+/// `GET /admin/events`: the events after `?after=<seq>` or `Last-Event-ID`,
+/// then the new ones as they are published. Without either, only new ones.
+pub(crate) async fn stream_events(
+    Query(query): Query<EventsQuery>,
+    headers: HeaderMap,
+) -> Sse<impl Stream<Item = Result<sse::Event, Infallible>>> {
+    let last_event_id = headers
+        .get("last-event-id")
+        .and_then(|id| id.to_str().ok())
+        .and_then(|id| id.trim().parse().ok());
+    let after = query
+        .after
+        .or(last_event_id)
+        .unwrap_or_else(|| EVENTS.last_seq());
+    let (missed, mut rx) = EVENTS.subscribe(after);
+    let stream = stream! {
+        let mut last = after;
+        for event in missed {
+            last = event.seq;
+            yield Ok(sse_event(&event));
+        }
+        loop {
+            match rx.recv().await {
+                Ok(event) if event.seq > last => {
+                    last = event.seq;
+                    yield Ok(sse_event(&event));
+                }
+                Ok(_) => {}
+                // Catch up from the buffer
+                Err(broadcast::error::RecvError::Lagged(_)) => {
+                    let (missed, next) = EVENTS.subscribe(last);
+                    rx = next;
+                    for event in missed {
+                        last = event.seq;
+                        yield Ok(sse_event(&event));
+                    }
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    };
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn joined(volume_id: &str) -> ClusterEvent {
+        ClusterEvent::VolumeJoined {
+            volume_id: volume_id.to_string(),
+            address: "http://vol:5000".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_event_log() {
+        let log = EventLog::default();
+        for i in 0..CAPACITY + 2 {
+            log.push(joined(&format!("vol-{}", i)));
+        }
+        assert_eq!(log.last_seq(), CAPACITY as u64 + 2);
+
+        // The two oldest fell out of the buffer
+        let (missed, mut rx) = log.subscribe(0);
+        assert_eq!(missed.len(), CAPACITY);
+        assert_eq!(missed[0].seq, 3);
+        let (missed, _) = log.subscribe(CAPACITY as u64);
+        assert_eq!(
+            missed.iter().map(|e| e.seq).collect::<Vec<_>>(),
+            [1025, 1026]
+        );
+
+        let pushed = log.push(joined("vol-new"));
+        assert_eq!(rx.recv().await.unwrap(), pushed);
+
+        let json = serde_json::to_value(&pushed).unwrap();
+        assert_eq!(json["type"], "volume.joined");
+        assert_eq!(json["seq"], CAPACITY as u64 + 3);
+        assert_eq!(json["volume_id"], "vol-new");
+    }
+}
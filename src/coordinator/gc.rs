@@ -0,0 +1,293 @@
+//! Garbage collection of orphaned blobs
+//!
+//! A blob is orphaned when the key metadata does not list its volume as a
+//! replica: the key is gone, tiered, or was moved elsewhere (e.g. after an
+//! aborted 2PC or a migration that failed to clean up). The leader pages
+//! through every volume's blobs over `ListBlobs` and cross-checks them against
+//! the metadata.
+//!
+//! Orphans are only deleted once they have been seen orphaned for the grace
+//! period, which covers writes whose metadata is not published yet. Keys with
+//! an in-flight 2PC transaction are skipped altogether. Sightings are kept in
+//! the leader's memory, so a new leader restarts the grace period. Deletion
+//! happens on the volume through `DeleteBlobs`, which leaves a blob alone if
+//! its hash changed since it was listed. A dry run reports without deleting.
+
+use crate::common::{timestamp_now, NodeState, Result};
+use crate::coordinator::metadata::{KeyState, MetadataStore, VolumeMetadata};
+use crate::coordinator::raft_node::RaftNode;
+use crate::coordinator::volume_client::VolumeClient;
+use crate::proto::KeyHash;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Blobs listed per ListBlobs call
+const LIST_PAGE_SIZE: u32 = 1000;
+
+/// Orphans deleted per DeleteBlobs call
+const DELETE_BATCH_SIZE: usize = 500;
+
+/// Orphans listed individually in a report
+const MAX_REPORTED_ORPHANS: usize = 1000;
+
+/// Orphan sightings and the last report, shared by the task and the HTTP handlers
+pub static GC: Lazy<GcTracker> = Lazy::new(|| GcTracker {
+    first_seen: Mutex::new(HashMap::new()),
+    grace_secs: AtomicU64::new(3600),
+    running: AtomicBool::new(false),
+    last_run: Mutex::new(None),
+});
+
+/// `(volume_id, key)` of an orphan → `(blake3, first seen)`
+type Sightings = HashMap<(String, String), (String, u64)>;
+
+pub struct GcTracker {
+    first_seen: Mutex<Sightings>,
+    grace_secs: AtomicU64,
+    running: AtomicBool,
+    last_run: Mutex<Option<GcReport>>,
+}
+
+impl GcTracker {
+    /// Set the configured grace period
+    pub fn set_grace_secs(&self, secs: u64) {
+        self.grace_secs.store(secs, Ordering::Relaxed);
+    }
+
+    /// Grace period used when a pass doesn't ask for another
+    pub fn grace_secs(&self) -> u64 {
+        self.grace_secs.load(Ordering::Relaxed)
+    }
+
+    /// Report of the last pass, if any
+    pub fn last_run(&self) -> Option<GcReport> {
+        self.last_run.lock().unwrap().clone()
+    }
+}
+
+/// One orphaned blob
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OrphanBlob {
+    pub volume_id: String,
+    pub key: String,
+    pub size: u64,
+    /// When the blob was first seen orphaned (Unix seconds)
+    pub first_seen: u64,
+}
+
+/// Outcome of a GC pass
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct GcReport {
+    pub dry_run: bool,
+    pub grace_secs: u64,
+    pub started_at: u64,
+    pub finished_at: u64,
+    pub volumes_scanned: u64,
+    pub blobs_scanned: u64,
+    pub orphans_found: u64,
+    /// Orphans still within the grace period
+    pub orphans_pending: u64,
+    /// Orphans deleted (or, in a dry run, that would have been)
+    pub orphans_deleted: u64,
+    pub bytes_freed: u64,
+    /// Volumes that could not be scanned or cleaned
+    pub errors: Vec<String>,
+    /// The orphans found, up to 1000
+    pub orphans: Vec<OrphanBlob>,
+}
+
+/// Run one GC pass over every reachable volume. Only one pass runs at a time.
+pub async fn run_gc(
+    metadata: &MetadataStore,
+    raft: &RaftNode,
+    grace_secs: u64,
+    dry_run: bool,
+) -> Result<GcReport> {
+    raft.ensure_leader()?;
+    if GC.running.swap(true, Ordering::SeqCst) {
+        return Err(crate::Error::Internal(
+            "a GC pass is already running".into(),
+        ));
+    }
+
+    let mut report = GcReport {
+        dry_run,
+        grace_secs,
+        started_at: timestamp_now(),
+        ..Default::default()
+    };
+    let result = gc_pass(metadata, grace_secs, dry_run, &mut report).await;
+    report.finished_at = timestamp_now();
+    GC.running.store(false, Ordering::SeqCst);
+    result?;
+
+    *GC.last_run.lock().unwrap() = Some(report.clone());
+    Ok(report)
+}
+
+async fn gc_pass(
+    metadata: &MetadataStore,
+    grace_secs: u64,
+    dry_run: bool,
+    report: &mut GcReport,
+) -> Result<()> {
+    let in_flight: HashSet<String> = metadata.list_txns()?.into_iter().map(|t| t.key).collect();
+    let volumes: Vec<VolumeMetadata> = metadata
+        .list_volumes()?
+        .into_iter()
+        .filter(|v| !matches!(v.state, NodeState::Dead | NodeState::Suspect))
+        .collect();
+
+    let previous = std::mem::take(&mut *GC.first_seen.lock().unwrap());
+    let mut sightings = Sightings::new();
+    let now = timestamp_now();
+
+    for volume in &volumes {
+        let result = gc_volume(
+            metadata,
+            volume,
+            &in_flight,
+            &previous,
+            &mut sightings,
+            now,
+            grace_secs,
+            dry_run,
+            report,
+        )
+        .await;
+        match result {
+            Ok(()) => report.volumes_scanned += 1,
+            Err(e) => {
+                tracing::warn!("GC of volume {} failed: {}", volume.volume_id, e);
+                report.errors.push(format!("{}: {}", volume.volume_id, e));
+                // Keep what was known about the volume for the next pass
+                sightings.extend(
+                    previous
+                        .iter()
+                        .filter(|((volume_id, _), _)| *volume_id == volume.volume_id)
+                        .map(|(k, v)| (k.clone(), v.clone())),
+                );
+            }
+        }
+    }
+
+    *GC.first_seen.lock().unwrap() = sightings;
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn gc_volume(
+    metadata: &MetadataStore,
+    volume: &VolumeMetadata,
+    in_flight: &HashSet<String>,
+    previous: &Sightings,
+    sightings: &mut Sightings,
+    now: u64,
+    grace_secs: u64,
+    dry_run: bool,
+    report: &mut GcReport,
+) -> Result<()> {
+    let mut client = VolumeClient::connect(volume.grpc_address.clone()).await?;
+    let mut expired = Vec::new();
+    let mut after = String::new();
+
+    loop {
+        let page = client.list_blobs(after.clone(), LIST_PAGE_SIZE).await?;
+        let Some(last) = page.last() else {
+            break;
+        };
+        after = last.key.clone();
+        let full = page.len() == LIST_PAGE_SIZE as usize;
+
+        for blob in page {
+            report.blobs_scanned += 1;
+            if in_flight.contains(&blob.key) {
+                continue;
+            }
+            let referenced = metadata.get_key(&blob.key)?.is_some_and(|meta| {
+                meta.state == KeyState::Active && meta.replicas.contains(&volume.volume_id)
+            });
+            if referenced {
+                continue;
+            }
+
+            let id = (volume.volume_id.clone(), blob.key.clone());
+            let first_seen = match previous.get(&id) {
+                Some((blake3, seen)) if *blake3 == blob.blake3 => *seen,
+                _ => now,
+            };
+            report.orphans_found += 1;
+            if report.orphans.len() < MAX_REPORTED_ORPHANS {
+                report.orphans.push(OrphanBlob {
+                    volume_id: volume.volume_id.clone(),
+                    key: blob.key.clone(),
+                    size: blob.size,
+                    first_seen,
+                });
+            }
+
+            // Tracked until the volume confirms the delete
+            sightings.insert(id, (blob.blake3.clone(), first_seen));
+            if now.saturating_sub(first_seen) < grace_secs {
+                report.orphans_pending += 1;
+            } else if dry_run {
+                report.orphans_deleted += 1;
+                report.bytes_freed += blob.size;
+            } else {
+                expired.push(KeyHash {
+                    key: blob.key,
+                    blake3: blob.blake3,
+                });
+            }
+        }
+        if !full {
+            break;
+        }
+    }
+
+    for batch in expired.chunks(DELETE_BATCH_SIZE) {
+        let resp = client.delete_blobs(batch.to_vec()).await?;
+        report.orphans_deleted += resp.deleted.len() as u64;
+        report.bytes_freed += resp.bytes_freed;
+        for key in resp.deleted {
+            sightings.remove(&(volume.volume_id.clone(), key));
+        }
+    }
+    Ok(())
+}
+
+/// Background task: a GC pass every `interval_secs` while this node leads (0 disables)
+pub fn start_gc_task(
+    metadata: Arc<MetadataStore>,
+    raft: Arc<RaftNode>,
+    interval_secs: u64,
+    grace_secs: u64,
+) -> tokio::task::JoinHandle<()> {
+    GC.set_grace_secs(grace_secs);
+    tokio::spawn(async move {
+        if interval_secs == 0 {
+            return;
+        }
+        let mut ticker = tokio::time::interval(Duration::from_secs(interval_secs));
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            if !raft.is_leader() {
+                continue;
+            }
+            match run_gc(&metadata, &raft, grace_secs, false).await {
+                Ok(report) if report.orphans_deleted > 0 => tracing::info!(
+                    "GC deleted {} orphaned blobs ({} bytes)",
+                    report.orphans_deleted,
+                    report.bytes_freed
+                ),
+                Ok(_) => {}
+                Err(e) => tracing::warn!("GC pass failed: {}", e),
+            }
+        }
+    })
+}
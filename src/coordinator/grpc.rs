@@ -6,12 +6,26 @@
 //! This module implements the internal gRPC protocol for cluster coordination.
 //! Used for Raft consensus, metadata replication, and distributed operations between nodes.
 
+use crate::coordinator::events::{self, ClusterEvent};
+use crate::coordinator::metadata::{
+    page_limit, KeyMetadata, KeyState, MetadataCommand, MetadataStore,
+};
+use crate::coordinator::placement::PlacementManager;
+use crate::coordinator::raft_node::RaftNode;
 use crate::proto::coordinator_internal_server::{CoordinatorInternal, CoordinatorInternalServer};
 use crate::proto::*;
+use std::sync::{Arc, Mutex};
 use tonic::{Request, Response, Status};
 
 /// CoordGrpcService implements the internal gRPC API for cluster coordination.
-pub struct CoordGrpcService {}
+pub struct CoordGrpcService {
+    /// Free-space floor applied to volumes on heartbeat
+    min_free_bytes: u64,
+    /// Set when joining volumes should trigger a rebalance
+    rebalance_on_join: Option<(Arc<MetadataStore>, Arc<Mutex<PlacementManager>>)>,
+    /// Local Raft node, target of the Raft RPCs
+    raft: Option<Arc<RaftNode>>,
+}
 
 impl Default for CoordGrpcService {
     fn default() -> Self {
@@ -21,7 +35,39 @@ impl Default for CoordGrpcService {
 
 impl CoordGrpcService {
     pub fn new() -> Self {
-        Self {}
+        Self {
+            min_free_bytes: 0,
+            rebalance_on_join: None,
+            raft: None,
+        }
+    }
+
+    /// Serve Raft RPCs for this node
+    pub fn with_raft(mut self, raft: Arc<RaftNode>) -> Self {
+        self.raft = Some(raft);
+        self
+    }
+
+    /// Schedule a background rebalance whenever a new volume joins
+    pub fn with_auto_rebalance(
+        mut self,
+        metadata: Arc<MetadataStore>,
+        placement: Arc<Mutex<PlacementManager>>,
+    ) -> Self {
+        self.rebalance_on_join = Some((metadata, placement));
+        self
+    }
+
+    /// Flip volumes reporting less free space than this to read-only
+    pub fn with_min_free_bytes(mut self, min_free_bytes: u64) -> Self {
+        self.min_free_bytes = min_free_bytes;
+        self
+    }
+
+    fn raft(&self) -> Result<&Arc<RaftNode>, Status> {
+        self.raft
+            .as_ref()
+            .ok_or_else(|| Status::unavailable("Raft is not running on this node"))
     }
 
     /// Converts this service into a gRPC server instance.
@@ -30,6 +76,47 @@ impl CoordGrpcService {
     }
 }
 
+/// Listing entry of a key
+pub(crate) fn key_info(meta: KeyMetadata) -> KeyInfo {
+    KeyInfo {
+        tombstone: meta.state == KeyState::Tombstone,
+        key: meta.key,
+        replicas: meta.replicas,
+        size: meta.size,
+        blake3: meta.blake3,
+        created_at: meta.created_at,
+        updated_at: meta.updated_at,
+    }
+}
+
+/// Zone or rack label sent by a volume, empty if unlabeled
+fn label(value: String) -> Option<String> {
+    Some(value).filter(|v| !v.is_empty())
+}
+
+/// Whether a volume id is safe to use in paths, URLs and restart commands
+fn valid_volume_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= 128
+        && !id.starts_with('.')
+        && id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"-_.".contains(&b))
+}
+
+/// Whether `address` is a URL with a host, made of URL characters only
+fn valid_address(address: &str) -> bool {
+    address
+        .bytes()
+        .all(|b| b.is_ascii_alphanumeric() || b":/.-_[]".contains(&b))
+        && reqwest::Url::parse(address).is_ok_and(|url| url.host_str().is_some())
+}
+
+/// Placement weight sent by a volume, 0 to weigh it by free space
+fn weight(value: f64) -> Option<f64> {
+    (value > 0.0).then_some(value)
+}
+
 #[tonic::async_trait]
 impl CoordinatorInternal for CoordGrpcService {
     async fn range(
@@ -64,12 +151,43 @@ impl CoordinatorInternal for CoordGrpcService {
         Ok(Response::new(resp))
     }
 
+    /// Lists one page of key metadata under a prefix
+    async fn list_keys(
+        &self,
+        req: Request<crate::proto::ListKeysRequest>,
+    ) -> Result<Response<crate::proto::ListKeysResponse>, Status> {
+        let store = crate::coordinator::metadata::get_global_store();
+        let req = req.into_inner();
+        let limit = page_limit(Some(req.limit as usize).filter(|&l| l > 0));
+        let start_after = Some(req.start_after.as_str()).filter(|s| !s.is_empty());
+        let page = store
+            .list_keys_paginated(&req.prefix, start_after, limit)
+            .map_err(|e| e.to_grpc_status())?;
+        let next_after = match page.last() {
+            Some(meta) if page.len() == limit => meta.key.clone(),
+            _ => String::new(),
+        };
+        let keys = page.into_iter().map(key_info).collect();
+        Ok(Response::new(crate::proto::ListKeysResponse {
+            keys,
+            next_after,
+        }))
+    }
+
     async fn batch(
         &self,
         req: Request<crate::proto::BatchRequest>,
     ) -> Result<Response<crate::proto::BatchResponse>, Status> {
         let store = crate::coordinator::metadata::get_global_store();
+        let raft = self.raft()?;
         let req = req.into_inner();
+        if req
+            .ops
+            .iter()
+            .any(|op| op.r#type != crate::proto::batch_op::Type::Get as i32)
+        {
+            raft.ensure_leader().map_err(|e| e.to_grpc_status())?;
+        }
         let mut results = Vec::new();
         for op in req.ops {
             use crate::proto::batch_op::Type;
@@ -83,8 +201,11 @@ impl CoordinatorInternal for CoordGrpcService {
                         created_at: 0,
                         updated_at: 0,
                         state: crate::coordinator::metadata::KeyState::Active,
+                        tags: Default::default(),
+                        blob: None,
+                        chunks: None,
                     };
-                    match store.put_key(&meta) {
+                    match raft.propose(&MetadataCommand::PutKey(meta)).await {
                         Ok(_) => (true, vec![], None),
                         Err(e) => (false, vec![], Some(format!("{}", e))),
                     }
@@ -94,7 +215,10 @@ impl CoordinatorInternal for CoordGrpcService {
                     Ok(None) => (false, vec![], Some("Not found".to_string())),
                     Err(e) => (false, vec![], Some(format!("{}", e))),
                 },
-                Ok(Type::Delete) => match store.delete_key(&op.key) {
+                Ok(Type::Delete) => match raft
+                    .propose(&MetadataCommand::DeleteKey(op.key.clone()))
+                    .await
+                {
                     Ok(_) => (true, vec![], None),
                     Err(e) => (false, vec![], Some(format!("{}", e))),
                 },
@@ -110,61 +234,320 @@ impl CoordinatorInternal for CoordGrpcService {
         let resp = crate::proto::BatchResponse { results };
         Ok(Response::new(resp))
     }
-    /// Handles Raft vote requests from other nodes.
+    /// Handles Raft vote requests (and pre-votes) from other coordinators
     async fn request_vote(
         &self,
         req: Request<VoteRequest>,
     ) -> Result<Response<VoteResponse>, Status> {
-        let vote_req = req.into_inner();
-
-        let current_term = 1;
-        let vote_granted = vote_req.term >= current_term;
-        let resp = VoteResponse {
-            term: current_term,
-            vote_granted,
-        };
-        Ok(Response::new(resp))
+        let raft = self.raft()?;
+        let resp = raft.handle_request_vote(req.into_inner().into());
+        Ok(Response::new((&resp).into()))
     }
 
+    /// Handles AppendEntries (replication and heartbeats) from the leader
     async fn append_entries(
         &self,
         req: Request<AppendRequest>,
     ) -> Result<Response<AppendResponse>, Status> {
-        let append_req = req.into_inner();
-
-        let current_term = 1;
-        let success = append_req.term >= current_term;
-        let resp = AppendResponse {
-            term: current_term,
-            success,
-            conflict_index: 0,
-        };
-        Ok(Response::new(resp))
+        let raft = self.raft()?;
+        let resp = raft.handle_append_entries(req.into_inner().into());
+        Ok(Response::new((&resp).into()))
     }
 
+    /// Receives a snapshot in chunks and replaces the local state machine with it
     async fn install_snapshot(
         &self,
-        _req: Request<SnapshotRequest>,
+        req: Request<tonic::Streaming<SnapshotRequest>>,
     ) -> Result<Response<SnapshotResponse>, Status> {
-        Err(Status::unimplemented("InstallSnapshot not implemented"))
+        let raft = self.raft()?;
+
+        let mut stream = req.into_inner();
+        let mut data = Vec::new();
+        let mut header = None;
+        while let Some(chunk) = stream.message().await? {
+            if chunk.offset != data.len() as u64 {
+                return Err(Status::invalid_argument(format!(
+                    "snapshot chunk at offset {}, expected {}",
+                    chunk.offset,
+                    data.len()
+                )));
+            }
+            data.extend_from_slice(&chunk.data);
+            let done = chunk.done;
+            header = Some(SnapshotRequest {
+                data: Vec::new(),
+                ..chunk
+            });
+            if done {
+                break;
+            }
+        }
+        let Some(header) = header.filter(|h| h.done) else {
+            return Err(Status::invalid_argument(
+                "snapshot stream ended before the last chunk",
+            ));
+        };
+
+        let current_term = raft.get_term();
+        if header.term < current_term {
+            return Ok(Response::new(SnapshotResponse { term: current_term }));
+        }
+        raft.follow_leader(header.term, header.leader_id)
+            .map_err(|e| e.to_grpc_status())?;
+        raft.apply_snapshot(data, header.last_included_index, header.last_included_term)
+            .map_err(|e| e.to_grpc_status())?;
+
+        Ok(Response::new(SnapshotResponse {
+            term: raft.get_term(),
+        }))
     }
 
-    async fn join(&self, _req: Request<JoinRequest>) -> Result<Response<JoinResponse>, Status> {
-        // Handle volume registration here
+    /// Starts an election right away when the leader hands over leadership
+    async fn timeout_now(
+        &self,
+        req: Request<TimeoutNowRequest>,
+    ) -> Result<Response<TimeoutNowResponse>, Status> {
+        let raft = self.raft()?;
+        let req = req.into_inner();
+        let ok = raft.handle_timeout_now(req.term, &req.leader_id);
+        if ok {
+            let raft = raft.clone();
+            tokio::spawn(async move {
+                raft.campaign_now().await;
+            });
+        }
+        Ok(Response::new(TimeoutNowResponse {
+            term: raft.get_term(),
+            ok,
+        }))
+    }
+
+    /// Moves leadership to another coordinator once its log has caught up
+    async fn transfer_leadership(
+        &self,
+        req: Request<TransferLeadershipRequest>,
+    ) -> Result<Response<TransferLeadershipResponse>, Status> {
+        let raft = self.raft()?;
+        let req = req.into_inner();
+        let timeout = match req.timeout_ms {
+            0 => crate::coordinator::raft_node::DEFAULT_TRANSFER_TIMEOUT,
+            ms => std::time::Duration::from_millis(ms),
+        };
+        raft.transfer_leadership(&req.target, timeout)
+            .await
+            .map_err(|e| e.to_grpc_status())?;
+        Ok(Response::new(TransferLeadershipResponse {
+            ok: true,
+            leader_id: raft.get_leader().unwrap_or_default(),
+        }))
+    }
+
+    /// Registers (or re-registers) a volume along with its zone/rack labels.
+    /// Only the leader takes joins and heartbeats, so that volumes get the
+    /// commands it queued.
+    async fn join(&self, req: Request<JoinRequest>) -> Result<Response<JoinResponse>, Status> {
+        let raft = self.raft()?;
+        raft.ensure_leader().map_err(|e| e.to_grpc_status())?;
+        let store = crate::coordinator::metadata::get_global_store();
+        let req = req.into_inner();
+        if !valid_volume_id(&req.volume_id) {
+            return Err(Status::invalid_argument(
+                "volume_id must be letters, digits, '-', '_' or '.'",
+            ));
+        }
+        if !valid_address(&req.address)
+            || !(req.grpc_address.is_empty() || valid_address(&req.grpc_address))
+        {
+            return Err(Status::invalid_argument("address must be a URL"));
+        }
+        let existing = store
+            .get_volume(&req.volume_id)
+            .map_err(|e| e.to_grpc_status())?;
+        let volume = crate::coordinator::metadata::VolumeMetadata {
+            volume_id: req.volume_id,
+            grpc_address: if req.grpc_address.is_empty() {
+                req.address.clone()
+            } else {
+                req.grpc_address
+            },
+            address: req.address,
+            state: crate::common::NodeState::Alive,
+            shards: req.shards.iter().filter_map(|s| s.parse().ok()).collect(),
+            total_keys: existing.as_ref().map(|v| v.total_keys).unwrap_or(0),
+            total_bytes: existing.as_ref().map(|v| v.total_bytes).unwrap_or(0),
+            free_bytes: existing.as_ref().map(|v| v.free_bytes).unwrap_or(0),
+            last_heartbeat: crate::common::timestamp_now(),
+            zone: label(req.zone),
+            rack: label(req.rack),
+            weight: weight(req.weight),
+            wal_error: None,
+        };
+        raft.propose(&MetadataCommand::PutVolume(volume.clone()))
+            .await
+            .map_err(|e| e.to_grpc_status())?;
+        tracing::info!(
+            "Volume {} joined (zone: {:?}, rack: {:?})",
+            volume.volume_id,
+            volume.zone,
+            volume.rack
+        );
+
+        // A new volume gets its share of the existing shards, not just of future writes
+        if existing.is_none() {
+            events::publish(ClusterEvent::VolumeJoined {
+                volume_id: volume.volume_id.clone(),
+                address: volume.address.clone(),
+            });
+            if let Some((metadata, placement)) = &self.rebalance_on_join {
+                crate::coordinator::migration::schedule_rebalance(
+                    metadata.clone(),
+                    placement.clone(),
+                    raft.clone(),
+                );
+            }
+        }
+
         Ok(Response::new(JoinResponse {
             ok: true,
             cluster_id: "cluster-1".to_string(),
         }))
     }
 
+    /// Records volume usage and placement labels and applies the free-space
+    /// floor, along with the volume's own, and has the keys the volume quarantined copied back to it.
     async fn heartbeat(
         &self,
-        _req: Request<HeartbeatRequest>,
+        req: Request<HeartbeatRequest>,
     ) -> Result<Response<HeartbeatResponse>, Status> {
-        // Update volume state here
-        Ok(Response::new(HeartbeatResponse {
-            ok: true,
-            commands: vec![],
-        }))
+        let raft = self.raft()?;
+        raft.ensure_leader().map_err(|e| e.to_grpc_status())?;
+        let store = crate::coordinator::metadata::get_global_store();
+        let req = req.into_inner();
+        let mut volume = store
+            .get_volume(&req.volume_id)
+            .map_err(|e| e.to_grpc_status())?
+            .ok_or_else(|| Status::not_found(format!("unknown volume {}", req.volume_id)))?;
+
+        volume.total_keys = req.total_keys;
+        volume.total_bytes = req.total_bytes;
+        volume.last_heartbeat = crate::common::timestamp_now();
+        volume.wal_error = Some(req.wal_error).filter(|e| !e.is_empty());
+        volume.zone = label(req.zone);
+        volume.rack = label(req.rack);
+        volume.weight = weight(req.weight);
+
+        let mut state = crate::coordinator::health::on_heartbeat(volume.state);
+        // Zero means the volume can't measure its disk; keep the last known value
+        if req.free_bytes > 0 {
+            volume.free_bytes = req.free_bytes;
+            state = crate::coordinator::placement::capacity_state(
+                state,
+                volume.free_bytes,
+                self.min_free_bytes,
+            );
+        }
+        if req.stopping
+            && matches!(
+                state,
+                crate::common::NodeState::Alive | crate::common::NodeState::ReadOnly
+            )
+        {
+            // Shutting down: no new writes until it's back
+            state = crate::common::NodeState::Suspect;
+        } else if req.read_only && state == crate::common::NodeState::Alive {
+            state = crate::common::NodeState::ReadOnly;
+        } else if !req.read_only
+            && req.free_bytes == 0
+            && state == crate::common::NodeState::ReadOnly
+        {
+            // Only the volume's say put it there when it can't measure its disk
+            state = crate::common::NodeState::Alive;
+        }
+        if state != volume.state {
+            tracing::warn!(
+                "Volume {} is now {} ({} bytes free)",
+                volume.volume_id,
+                state,
+                volume.free_bytes
+            );
+            events::publish(ClusterEvent::VolumeState {
+                volume_id: volume.volume_id.clone(),
+                previous: volume.state,
+                state,
+            });
+            volume.state = state;
+        }
+        raft.propose(&MetadataCommand::PutVolume(volume.clone()))
+            .await
+            .map_err(|e| e.to_grpc_status())?;
+
+        if !req.corrupted_keys.is_empty() {
+            tracing::warn!(
+                "Volume {} quarantined {} corrupted keys",
+                volume.volume_id,
+                req.corrupted_keys.len()
+            );
+            tokio::spawn(crate::coordinator::anti_entropy::repair_quarantined(
+                store.clone(),
+                volume.clone(),
+                req.corrupted_keys,
+            ));
+        }
+
+        for finished in req.finished_commands {
+            events::publish(ClusterEvent::CommandFinished {
+                volume_id: volume.volume_id.clone(),
+                command: finished.command,
+                error: Some(finished.error).filter(|e| !e.is_empty()),
+            });
+        }
+
+        let commands = crate::coordinator::commands::VOLUME_COMMANDS
+            .take(&volume.volume_id)
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+
+        Ok(Response::new(HeartbeatResponse { ok: true, commands }))
+    }
+
+    /// Publishes a value a volume took straight from a client through a
+    /// redirect (see `coordinator::redirect`)
+    async fn commit_upload(
+        &self,
+        req: Request<CommitUploadRequest>,
+    ) -> Result<Response<CommitUploadResponse>, Status> {
+        let store = crate::coordinator::metadata::get_global_store();
+        let req = req.into_inner();
+        crate::coordinator::redirect::commit_upload(
+            &store,
+            self.raft()?,
+            &req.upload_id,
+            &req.key,
+            req.size,
+            &req.blake3,
+            req.replicas,
+        )
+        .await
+        .map_err(|e| e.to_grpc_status())?;
+        Ok(Response::new(CommitUploadResponse { ok: true }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_join_validation() {
+        assert!(valid_volume_id("vol-1"));
+        assert!(!valid_volume_id(""));
+        assert!(!valid_volume_id("vol-1; reboot"));
+        assert!(!valid_volume_id("../vol"));
+        assert!(valid_address("http://127.0.0.1:5001"));
+        assert!(valid_address("sim://net/vol-1"));
+        assert!(valid_address("http://[::1]:5001"));
+        assert!(!valid_address("127.0.0.1:5001 $(reboot)"));
+        assert!(!valid_address("vol-1"));
     }
 }
@@ -0,0 +1,284 @@
+//! Volume health monitoring
+//!
+//! Volumes report in through the Heartbeat RPC. While leader, the coordinator
+//! checks how long ago each volume was last heard from:
+//! - `Alive`/`ReadOnly` → `Suspect` after `suspect_after_secs` (no new writes),
+//! - `Suspect` → `Dead` after `dead_after_secs`, at which point every key with a
+//!   replica on the volume is re-replicated elsewhere.
+//!
+//! A heartbeat from a `Suspect` or `Dead` volume brings it back to `Alive`;
+//! copies left on a volume that came back after re-replication are cleaned up by
+//! anti-entropy.
+//!
+//! `readiness` decides whether this coordinator should get traffic, for
+//! `GET /health/ready`: it isn't shutting down, its metadata store answers,
+//! the cluster has a leader, and at least `ready_min_volumes` volumes are
+//! alive with a WAL they can write to (as of their last heartbeat).
+
+use crate::common::{timestamp_now, NodeState, Result, SHUTDOWN};
+use crate::coordinator::drain::move_replica;
+use crate::coordinator::events::{self, ClusterEvent};
+use crate::coordinator::metadata::{KeyState, MetadataCommand, MetadataStore};
+use crate::coordinator::placement::PlacementManager;
+use crate::coordinator::raft_node::RaftNode;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// How often volume health is evaluated
+const CHECK_INTERVAL_SECS: u64 = 5;
+
+/// Heartbeat timeouts
+#[derive(Debug, Clone, Copy)]
+pub struct HealthConfig {
+    pub suspect_after_secs: u64,
+    pub dead_after_secs: u64,
+}
+
+/// State a volume moves to given the seconds since its last heartbeat
+pub fn next_state(current: NodeState, silent_secs: u64, config: &HealthConfig) -> NodeState {
+    match current {
+        NodeState::Alive | NodeState::ReadOnly if silent_secs >= config.suspect_after_secs => {
+            NodeState::Suspect
+        }
+        NodeState::Suspect if silent_secs >= config.dead_after_secs => NodeState::Dead,
+        state => state,
+    }
+}
+
+/// State a volume moves to when a heartbeat arrives
+pub fn on_heartbeat(current: NodeState) -> NodeState {
+    match current {
+        NodeState::Suspect | NodeState::Dead => NodeState::Alive,
+        state => state,
+    }
+}
+
+static READY_MIN_VOLUMES: AtomicUsize = AtomicUsize::new(1);
+
+/// Set the volumes needed to be ready (from
+/// `CoordinatorConfig::ready_min_volumes`)
+pub fn set_ready_min_volumes(volumes: usize) {
+    READY_MIN_VOLUMES.store(volumes, Ordering::Relaxed);
+}
+
+/// Whether the coordinator is ready, and what each check found
+#[derive(Debug, Clone, Serialize)]
+pub struct Readiness {
+    pub ready: bool,
+    /// By name, each with `ok` and its findings
+    pub checks: Map<String, Value>,
+}
+
+/// Run the readiness checks
+pub fn readiness(metadata: &MetadataStore, raft: &RaftNode) -> Readiness {
+    let required = READY_MIN_VOLUMES.load(Ordering::Relaxed);
+    let mut checks = Map::new();
+    checks.insert(
+        "shutdown".to_string(),
+        json!({ "ok": !SHUTDOWN.is_triggered() }),
+    );
+
+    let volumes = metadata.list_volumes();
+    checks.insert(
+        "metadata".to_string(),
+        match &volumes {
+            Ok(_) => json!({ "ok": true }),
+            Err(e) => json!({ "ok": false, "error": e.to_string() }),
+        },
+    );
+
+    let leader = if raft.is_leader() {
+        Some(raft.node_id().to_string())
+    } else {
+        raft.get_leader()
+    };
+    checks.insert(
+        "raft_leader".to_string(),
+        json!({ "ok": leader.is_some(), "leader_id": leader }),
+    );
+
+    let healthy: Vec<_> = volumes
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|v| v.state.is_healthy())
+        .collect();
+    let wal_errors: BTreeMap<_, _> = healthy
+        .iter()
+        .filter_map(|v| Some((v.volume_id.clone(), v.wal_error.clone()?)))
+        .collect();
+    let writable = healthy.len() - wal_errors.len();
+    checks.insert(
+        "volumes".to_string(),
+        json!({ "ok": healthy.len() >= required, "healthy": healthy.len(), "required": required }),
+    );
+    checks.insert(
+        "volume_wal".to_string(),
+        json!({ "ok": writable >= required, "writable": writable, "errors": wal_errors }),
+    );
+
+    Readiness {
+        ready: checks.values().all(|check| check["ok"] == true),
+        checks,
+    }
+}
+
+/// Run the health monitor while this node is leader
+pub fn start_health_monitor(
+    metadata: Arc<MetadataStore>,
+    placement: Arc<Mutex<PlacementManager>>,
+    raft: Arc<RaftNode>,
+    config: HealthConfig,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval =
+            tokio::time::interval(tokio::time::Duration::from_secs(CHECK_INTERVAL_SECS));
+        let mut was_leader = false;
+        loop {
+            interval.tick().await;
+            let is_leader = raft.is_leader();
+            if is_leader {
+                // On becoming leader, resume re-replication of volumes already dead
+                if let Err(e) =
+                    check_volumes(&metadata, &placement, &raft, &config, !was_leader).await
+                {
+                    tracing::error!("Health check failed: {}", e);
+                }
+            }
+            was_leader = is_leader;
+        }
+    })
+}
+
+async fn check_volumes(
+    metadata: &Arc<MetadataStore>,
+    placement: &Arc<Mutex<PlacementManager>>,
+    raft: &Arc<RaftNode>,
+    config: &HealthConfig,
+    resume_dead: bool,
+) -> Result<()> {
+    let now = timestamp_now();
+    for mut volume in metadata.list_volumes()? {
+        if volume.last_heartbeat == 0 {
+            continue;
+        }
+        let silent = now.saturating_sub(volume.last_heartbeat);
+        let state = next_state(volume.state, silent, config);
+
+        if state != volume.state {
+            tracing::warn!(
+                "Volume {} is now {} (no heartbeat for {}s)",
+                volume.volume_id,
+                state,
+                silent
+            );
+            events::publish(ClusterEvent::VolumeState {
+                volume_id: volume.volume_id.clone(),
+                previous: volume.state,
+                state,
+            });
+            volume.state = state;
+            raft.propose(&MetadataCommand::PutVolume(volume.clone()))
+                .await?;
+        } else if !(resume_dead && state == NodeState::Dead) {
+            continue;
+        }
+
+        if state == NodeState::Dead {
+            let metadata = metadata.clone();
+            let placement = placement.clone();
+            let raft = raft.clone();
+            let volume_id = volume.volume_id.clone();
+            tokio::spawn(async move {
+                if let Err(e) = rereplicate(&metadata, &placement, &raft, &volume_id).await {
+                    tracing::error!("Re-replication off {} failed: {}", volume_id, e);
+                }
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Move every replica held by a dead volume to a live one
+async fn rereplicate(
+    metadata: &MetadataStore,
+    placement: &Mutex<PlacementManager>,
+    raft: &RaftNode,
+    volume_id: &str,
+) -> Result<()> {
+    let mut moved = 0;
+    let mut failed = 0;
+    for key in metadata.list_keys()? {
+        // Stop if the volume came back in the meantime
+        if metadata
+            .get_volume(volume_id)?
+            .map(|v| v.state != NodeState::Dead)
+            .unwrap_or(true)
+        {
+            tracing::info!("Volume {} recovered, stopping re-replication", volume_id);
+            break;
+        }
+        let Some(meta) = metadata.get_key(&key)? else {
+            continue;
+        };
+        if meta.state != KeyState::Active || !meta.replicas.iter().any(|r| r == volume_id) {
+            continue;
+        }
+        match move_replica(metadata, placement, raft, &key, volume_id).await {
+            Ok(_) => moved += 1,
+            Err(e) => {
+                tracing::warn!("Re-replication of {} failed: {}", key, e);
+                failed += 1;
+            }
+        }
+    }
+
+    tracing::info!(
+        "Re-replication off {}: {} keys moved, {} failed",
+        volume_id,
+        moved,
+        failed
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_state_machine() {
+        let config = HealthConfig {
+            suspect_after_secs: 30,
+            dead_after_secs: 120,
+        };
+
+        assert_eq!(next_state(NodeState::Alive, 10, &config), NodeState::Alive);
+        assert_eq!(
+            next_state(NodeState::Alive, 30, &config),
+            NodeState::Suspect
+        );
+        assert_eq!(
+            next_state(NodeState::ReadOnly, 60, &config),
+            NodeState::Suspect
+        );
+        assert_eq!(
+            next_state(NodeState::Suspect, 60, &config),
+            NodeState::Suspect
+        );
+        assert_eq!(
+            next_state(NodeState::Suspect, 120, &config),
+            NodeState::Dead
+        );
+        assert_eq!(
+            next_state(NodeState::Draining, 600, &config),
+            NodeState::Draining
+        );
+
+        assert_eq!(on_heartbeat(NodeState::Dead), NodeState::Alive);
+        assert_eq!(on_heartbeat(NodeState::Suspect), NodeState::Alive);
+        assert_eq!(on_heartbeat(NodeState::Draining), NodeState::Draining);
+    }
+}
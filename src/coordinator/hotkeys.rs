@@ -0,0 +1,244 @@
+//! Hot key tracking
+//!
+//! `HOT_KEYS` samples the reads and writes of keys served by this
+//! coordinator and counts them per key in one-minute buckets, so
+//! `GET /admin/hotkeys` (and `minikv top`) can tell which keys take the most
+//! traffic, and which are the largest, over the last minutes. A sampled
+//! access counts for `1 / sample_rate`.
+//!
+//! Each bucket counts at most `max_keys` keys: past that, the least accessed
+//! key gives way to the new one, which takes over its count (space-saving).
+//! Counts are then estimates, erring high for rarely accessed keys, while
+//! the hottest keys keep theirs.
+
+use crate::common::{timestamp_now, HotKeysConfig};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Mutex, RwLock};
+
+/// Seconds covered by a bucket
+const BUCKET_SECS: u64 = 60;
+
+/// Global hot key tracker
+pub static HOT_KEYS: Lazy<HotKeys> = Lazy::new(HotKeys::new);
+
+/// Accesses of a key
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HotKey {
+    pub key: String,
+    pub reads: u64,
+    pub writes: u64,
+    /// Size of the value at its last access, 0 once deleted
+    pub size: u64,
+}
+
+/// Top keys over a window, answer of `GET /admin/hotkeys`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HotKeysReport {
+    pub window_secs: u64,
+    pub sample_rate: f64,
+    /// Keys counted over the window
+    pub tracked_keys: usize,
+    pub most_read: Vec<HotKey>,
+    pub most_written: Vec<HotKey>,
+    pub largest: Vec<HotKey>,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Counts {
+    reads: u64,
+    writes: u64,
+    size: u64,
+}
+
+struct Bucket {
+    start: u64,
+    keys: HashMap<String, Counts>,
+}
+
+/// Sampled access counters, per key and minute
+pub struct HotKeys {
+    config: RwLock<HotKeysConfig>,
+    buckets: Mutex<VecDeque<Bucket>>,
+}
+
+impl HotKeys {
+    pub fn new() -> Self {
+        Self {
+            config: RwLock::new(HotKeysConfig::default()),
+            buckets: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Set the sampling (from `CoordinatorConfig::hotkeys`)
+    pub fn set_config(&self, config: HotKeysConfig) {
+        *self.config.write().unwrap() = config;
+    }
+
+    /// Note a read of `key`, whose value has `size` bytes
+    pub fn record_read(&self, key: &str, size: u64) {
+        self.record(key, 1, 0, size);
+    }
+
+    /// Note a write of `key`, leaving `size` bytes (0 for a delete)
+    pub fn record_write(&self, key: &str, size: u64) {
+        self.record(key, 0, 1, size);
+    }
+
+    fn record(&self, key: &str, reads: u64, writes: u64, size: u64) {
+        let config = *self.config.read().unwrap();
+        if !config.enabled || config.sample_rate <= 0.0 {
+            return;
+        }
+        if config.sample_rate < 1.0 && rand::random::<f64>() >= config.sample_rate {
+            return;
+        }
+        let weight = (1.0 / config.sample_rate.min(1.0)).round() as u64;
+        self.add(timestamp_now(), key, reads * weight, writes * weight, size);
+    }
+
+    fn add(&self, now: u64, key: &str, reads: u64, writes: u64, size: u64) {
+        let config = *self.config.read().unwrap();
+        let start = now - now % BUCKET_SECS;
+        let mut buckets = self.buckets.lock().unwrap();
+        while buckets
+            .front()
+            .is_some_and(|b| b.start + config.retention_secs.max(BUCKET_SECS) <= start)
+        {
+            buckets.pop_front();
+        }
+        if buckets.back().map_or(true, |b| b.start != start) {
+            buckets.push_back(Bucket {
+                start,
+                keys: HashMap::new(),
+            });
+        }
+        let bucket = buckets.back_mut().unwrap();
+
+        if !bucket.keys.contains_key(key) && bucket.keys.len() >= config.max_keys.max(1) {
+            let coldest = bucket
+                .keys
+                .iter()
+                .min_by_key(|(_, c)| c.reads + c.writes)
+                .map(|(k, c)| (k.clone(), *c));
+            if let Some((coldest, counts)) = coldest {
+                bucket.keys.remove(&coldest);
+                bucket.keys.insert(key.to_string(), counts);
+            }
+        }
+        let counts = bucket.keys.entry(key.to_string()).or_default();
+        counts.reads += reads;
+        counts.writes += writes;
+        counts.size = size;
+    }
+
+    /// The `limit` most read, most written and largest keys accessed over
+    /// the last `window_secs`
+    pub fn report(&self, window_secs: u64, limit: usize) -> HotKeysReport {
+        self.report_at(timestamp_now(), window_secs, limit)
+    }
+
+    fn report_at(&self, now: u64, window_secs: u64, limit: usize) -> HotKeysReport {
+        let since = now.saturating_sub(window_secs);
+        let mut keys: HashMap<&str, Counts> = HashMap::new();
+        let buckets = self.buckets.lock().unwrap();
+        // Buckets are in time order, so the last size seen is the latest
+        for bucket in buckets.iter().filter(|b| b.start + BUCKET_SECS > since) {
+            for (key, counts) in &bucket.keys {
+                let total = keys.entry(key).or_default();
+                total.reads += counts.reads;
+                total.writes += counts.writes;
+                total.size = counts.size;
+            }
+        }
+
+        let top = |by: fn(&Counts) -> u64| -> Vec<HotKey> {
+            let mut top: Vec<_> = keys.iter().filter(|(_, c)| by(c) > 0).collect();
+            top.sort_by(|a, b| by(b.1).cmp(&by(a.1)).then(a.0.cmp(b.0)));
+            top.into_iter()
+                .take(limit)
+                .map(|(key, c)| HotKey {
+                    key: key.to_string(),
+                    reads: c.reads,
+                    writes: c.writes,
+                    size: c.size,
+                })
+                .collect()
+        };
+        HotKeysReport {
+            window_secs,
+            sample_rate: self.config.read().unwrap().sample_rate,
+            tracked_keys: keys.len(),
+            most_read: top(|c| c.reads),
+            most_written: top(|c| c.writes),
+            largest: top(|c| c.size),
+        }
+    }
+
+    /// Forget every access
+    pub fn clear(&self) {
+        self.buckets.lock().unwrap().clear();
+    }
+}
+
+impl Default for HotKeys {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tracker(max_keys: usize) -> HotKeys {
+        let hot_keys = HotKeys::new();
+        hot_keys.set_config(HotKeysConfig {
+            sample_rate: 1.0,
+            max_keys,
+            ..Default::default()
+        });
+        hot_keys
+    }
+
+    #[test]
+    fn test_report_window() {
+        let hot_keys = tracker(100);
+        let now = 10_000 * BUCKET_SECS;
+        hot_keys.add(now - 600, "acme/old", 50, 0, 1);
+        for _ in 0..3 {
+            hot_keys.add(now - 30, "acme/a", 1, 0, 10);
+        }
+        hot_keys.add(now, "acme/b", 1, 0, 5);
+        hot_keys.add(now, "acme/b", 0, 1, 500);
+        hot_keys.add(now, "acme/c", 0, 1, 0);
+
+        let report = hot_keys.report_at(now, 300, 10);
+        assert_eq!(report.tracked_keys, 3);
+        let keys = |top: &[HotKey]| top.iter().map(|k| k.key.clone()).collect::<Vec<_>>();
+        assert_eq!(keys(&report.most_read), vec!["acme/a", "acme/b"]);
+        assert_eq!(report.most_read[0].reads, 3);
+        assert_eq!(keys(&report.most_written), vec!["acme/b", "acme/c"]);
+        assert_eq!(keys(&report.largest), vec!["acme/b", "acme/a"]);
+        assert_eq!(report.largest[0].size, 500);
+
+        let report = hot_keys.report_at(now, 3600, 1);
+        assert_eq!(keys(&report.most_read), vec!["acme/old"]);
+    }
+
+    #[test]
+    fn test_coldest_key_gives_way() {
+        let hot_keys = tracker(2);
+        hot_keys.add(0, "hot", 5, 0, 0);
+        hot_keys.add(0, "cold", 1, 0, 0);
+        hot_keys.add(0, "new", 1, 0, 0);
+
+        let report = hot_keys.report_at(0, 60, 10);
+        assert_eq!(report.tracked_keys, 2);
+        assert_eq!(report.most_read[0].key, "hot");
+        // Takes over the count of the key it replaced
+        assert_eq!(report.most_read[1].key, "new");
+        assert_eq!(report.most_read[1].reads, 2);
+    }
+}
@@ -16,9 +16,19 @@
 use crate::common::storage::Storage;
 use std::time::Duration;
 
-use crate::common::auth::{Role, KEY_STORE};
-use crate::common::{AuditEventType, AUDIT_LOGGER};
+use crate::common::audit;
+use crate::common::auth::{AuthContext, Role, KEY_STORE};
+use crate::common::quota::Direction;
+use crate::common::ratelimit::{rate_limit_middleware, RateLimiter};
+use crate::common::reload::reload_now;
+use crate::common::{
+    acl_middleware, auth_middleware, ip_filter_middleware, request_id_middleware,
+    require_admin_middleware, require_write_middleware, write_budget_middleware, AuthConfig,
+    AuthState, Faults, IpFilter, PluginHook, ReplicationTarget, WriteBudget, FAULTS,
+};
+use crate::common::{AclRule, AuditEventType, TenantQuota, ACL_STORE, AUDIT_LOGGER, QUOTA_MANAGER};
 use async_stream::stream;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
 use once_cell::sync::Lazy;
 use std::convert::Infallible;
 use tokio::sync::broadcast;
@@ -38,27 +48,96 @@ pub struct KeyChangeEvent {
     pub timestamp: i64,
 }
 
+/// Which changes a watch stream shows its caller: the keys of one tenant, or
+/// of every tenant for admins who name none, as the ACLs allow. API key
+/// changes, which belong to no tenant, are shown to admins only.
+#[derive(Debug, Clone)]
+pub(crate) struct WatchScope {
+    tenant: Option<String>,
+    admin: bool,
+    caller: Option<AuthContext>,
+}
+
+impl WatchScope {
+    /// Scope of a watch request, resolved as for `/cdc`
+    pub(crate) fn resolve(
+        state: &CoordState,
+        auth: &Option<axum::Extension<AuthExtension>>,
+        headers: &axum::http::HeaderMap,
+    ) -> crate::Result<Self> {
+        let caller = auth.as_ref().and_then(|ext| ext.0 .0.clone());
+        let admin = !state.auth.enabled || caller.as_ref().is_some_and(|ctx| ctx.can_admin());
+        let tenant = if admin && !headers.contains_key(tenant::TENANT_HEADER) {
+            None
+        } else {
+            Some(resolve_tenant(state, auth, headers)?)
+        };
+        Ok(Self {
+            tenant,
+            admin,
+            caller,
+        })
+    }
+
+    /// Whether the caller may see `event`
+    pub(crate) fn shows(&self, event: &KeyChangeEvent) -> bool {
+        let Some(tenant) = &event.tenant else {
+            return self.admin;
+        };
+        self.tenant.as_ref().map_or(true, |own| own == tenant)
+            && self
+                .caller
+                .as_ref()
+                .map_or(true, |ctx| ACL_STORE.allows(ctx, &event.key))
+    }
+}
+
 /// SSE endpoint for key change notifications
 pub async fn watch_sse(
-) -> Sse<impl futures_util::Stream<Item = Result<axum::response::sse::Event, Infallible>>> {
+    State(state): State<CoordState>,
+    auth: Option<axum::Extension<AuthExtension>>,
+    headers: axum::http::HeaderMap,
+) -> axum::response::Response {
+    let scope = match WatchScope::resolve(&state, &auth, &headers) {
+        Ok(scope) => scope,
+        Err(e) => return (e.to_http_status(), e.to_string()).into_response(),
+    };
     let mut rx = WATCH_CHANNEL.subscribe();
+    let shown = scope.clone();
     let stream = stream! {
         while let Ok(event) = rx.recv().await {
+            if !shown.shows(&event) {
+                continue;
+            }
             let data = serde_json::to_string(&event).unwrap();
-            yield Ok(axum::response::sse::Event::default().data(data));
+            yield Ok::<_, Infallible>(axum::response::sse::Event::default().data(data));
         }
     };
-    Sse::new(stream)
+    let mut resp = Sse::new(stream).into_response();
+    // For callers in process (the gRPC Watch) filtering the channel themselves
+    resp.extensions_mut().insert(scope);
+    resp
 }
 
 /// WebSocket endpoint for key change notifications
-pub async fn watch_ws(ws: WebSocketUpgrade) -> impl IntoResponse {
-    ws.on_upgrade(handle_ws)
+pub async fn watch_ws(
+    State(state): State<CoordState>,
+    auth: Option<axum::Extension<AuthExtension>>,
+    headers: axum::http::HeaderMap,
+    ws: WebSocketUpgrade,
+) -> axum::response::Response {
+    match WatchScope::resolve(&state, &auth, &headers) {
+        Ok(scope) => ws.on_upgrade(|socket| handle_ws(socket, scope)),
+        Err(e) => (e.to_http_status(), e.to_string()).into_response(),
+    }
 }
 
-async fn handle_ws(mut socket: WebSocket) {
+async fn handle_ws(mut socket: WebSocket, scope: WatchScope) {
     let mut rx = WATCH_CHANNEL.subscribe();
     while let Ok(event) = rx.recv().await {
+        if !scope.shows(&event) {
+            continue;
+        }
         let msg = serde_json::to_string(&event).unwrap();
         if socket.send(Message::Text(msg)).await.is_err() {
             break;
@@ -69,18 +148,93 @@ async fn handle_ws(mut socket: WebSocket) {
 // Global storage backend (default: in-memory)
 pub static STORAGE: Lazy<Storage> = Lazy::new(Storage::new_memory);
 
-/// Admin endpoint: triggers cluster repair
-async fn admin_repair(State(_state): State<CoordState>) -> impl IntoResponse {
-    // Actual call to repair logic
-    let res = crate::ops::repair::repair_cluster("http://localhost:5000", 3, false).await;
-    match res {
-        Ok(report) => axum::Json(json!({ "status": "ok", "report": report })),
-        Err(e) => axum::Json(json!({ "status": "error", "error": format!("{}", e) })),
+/// Query parameters of POST /admin/repair
+#[derive(Debug, Deserialize)]
+struct RepairQuery {
+    /// Replicas each key should have (the placement replication factor if omitted)
+    replicas: Option<usize>,
+    /// Keys copied at once
+    parallelism: Option<usize>,
+    #[serde(default)]
+    dry_run: bool,
+}
+
+/// Admin endpoint: starts repairing under-replicated keys, or resumes an
+/// interrupted repair (idempotent while a repair is running)
+async fn admin_repair(
+    State(state): State<CoordState>,
+    Query(query): Query<RepairQuery>,
+    auth: Option<axum::Extension<AuthExtension>>,
+) -> impl IntoResponse {
+    if !query.dry_run {
+        audit_admin(&auth, None, "Cluster repair");
+    }
+    let replicas = query
+        .replicas
+        .unwrap_or_else(|| state.placement.lock().unwrap().replicas());
+    match repair::start_repair(
+        state.metadata.clone(),
+        state.placement.clone(),
+        state.raft.clone(),
+        replicas,
+        query.parallelism.unwrap_or(repair::DEFAULT_PARALLELISM),
+        query.dry_run,
+    )
+    .await
+    {
+        Ok(progress) => (StatusCode::ACCEPTED, axum::Json(json!(progress))),
+        Err(e) => (
+            e.to_http_status(),
+            axum::Json(json!({ "error": format!("{}", e) })),
+        ),
+    }
+}
+
+/// Admin endpoint: progress of the last cluster repair
+async fn admin_repair_status(State(state): State<CoordState>) -> impl IntoResponse {
+    match repair::get_progress(&state.metadata) {
+        Ok(Some(progress)) => (StatusCode::OK, axum::Json(json!(progress))),
+        Ok(None) => (
+            StatusCode::NOT_FOUND,
+            axum::Json(json!({ "error": "no repair has run" })),
+        ),
+        Err(e) => (
+            e.to_http_status(),
+            axum::Json(json!({ "error": format!("{}", e) })),
+        ),
+    }
+}
+
+/// Query parameters of GET /admin/repair/pending
+#[derive(Debug, Deserialize)]
+struct PendingRepairQuery {
+    /// Replicas each key should have (the placement replication factor if omitted)
+    replicas: Option<usize>,
+}
+
+/// Admin endpoint: counts the keys a repair would copy, and their bytes
+async fn admin_repair_pending(
+    State(state): State<CoordState>,
+    Query(query): Query<PendingRepairQuery>,
+) -> impl IntoResponse {
+    let replicas = query
+        .replicas
+        .unwrap_or_else(|| state.placement.lock().unwrap().replicas());
+    match repair::count_under_replicated(&state.metadata, replicas) {
+        Ok(pending) => (StatusCode::OK, axum::Json(json!(pending))),
+        Err(e) => (
+            e.to_http_status(),
+            axum::Json(json!({ "error": format!("{}", e) })),
+        ),
     }
 }
 
 /// Admin endpoint: triggers cluster compaction
-async fn admin_compact(State(_state): State<CoordState>) -> impl IntoResponse {
+async fn admin_compact(
+    State(_state): State<CoordState>,
+    auth: Option<axum::Extension<AuthExtension>>,
+) -> impl IntoResponse {
+    audit_admin(&auth, None, "Cluster compaction");
     // Actual call to compaction logic
     let res = crate::ops::compact::compact_cluster("http://localhost:5000", None).await;
     match res {
@@ -90,17 +244,29 @@ async fn admin_compact(State(_state): State<CoordState>) -> impl IntoResponse {
 }
 
 /// Admin endpoint: triggers cluster verification
-async fn admin_verify(State(_state): State<CoordState>) -> impl IntoResponse {
-    // Actual call to verification logic
-    let res = crate::ops::verify::verify_cluster("http://localhost:5000", false, 16).await;
+async fn admin_verify(State(state): State<CoordState>) -> impl IntoResponse {
+    // Actual call to verification logic, paging keys from the leader
+    let res = crate::ops::verify::verify_cluster(&leader_url(&state), false, 16).await;
     match res {
         Ok(report) => axum::Json(json!({ "status": "ok", "report": report })),
         Err(e) => axum::Json(json!({ "status": "error", "error": format!("{}", e) })),
     }
 }
 
+/// HTTP address of the Raft leader, falling back to the default local coordinator
+fn leader_url(state: &CoordState) -> String {
+    state
+        .raft
+        .get_leader_addr()
+        .unwrap_or_else(|| "http://localhost:5000".to_string())
+}
+
 /// Admin endpoint: triggers cluster scaling (add/remove volumes)
-async fn admin_scale(State(_state): State<CoordState>) -> impl IntoResponse {
+async fn admin_scale(
+    State(_state): State<CoordState>,
+    auth: Option<axum::Extension<AuthExtension>>,
+) -> impl IntoResponse {
+    audit_admin(&auth, None, "Cluster scaling");
     // Call scaling logic (stub, placement/metadata integration is now implemented)
     axum::Json(json!({ "status": "scaling triggered" }))
 }
@@ -108,7 +274,7 @@ async fn admin_scale(State(_state): State<CoordState>) -> impl IntoResponse {
 use axum::{
     body::Bytes,
     extract::{Path, Query, State},
-    http::StatusCode,
+    http::{header, HeaderValue, StatusCode},
     response::IntoResponse,
     Router,
 };
@@ -116,9 +282,32 @@ use serde::{Deserialize, Serialize};
 use serde_json::json;
 use std::sync::Arc;
 
-use crate::coordinator::metadata::MetadataStore;
+use crate::common::range::{self, ByteRange};
+use crate::common::{AuthExtension, VolumeCommand};
+use crate::coordinator::anti_entropy;
+use crate::coordinator::bandwidth;
+use crate::coordinator::chunking;
+use crate::coordinator::commands::VOLUME_COMMANDS;
+use crate::coordinator::consistency::{self, ConsistencyLevel};
+use crate::coordinator::dedup;
+use crate::coordinator::drain;
+use crate::coordinator::etcd;
+use crate::coordinator::events;
+use crate::coordinator::gc::{self, GC};
+use crate::coordinator::hotkeys::HOT_KEYS;
+use crate::coordinator::metadata::{KeyState, MetadataCommand, MetadataStore, VolumeMetadata};
+use crate::coordinator::migration;
 use crate::coordinator::placement::PlacementManager;
+use crate::coordinator::plugins::PLUGINS;
 use crate::coordinator::raft_node::RaftNode;
+use crate::coordinator::redirect;
+use crate::coordinator::repair;
+use crate::coordinator::replication::{self, Resolution, REPLICATION};
+use crate::coordinator::tenant;
+use crate::coordinator::tiering::{self, TIERING};
+use crate::coordinator::usage;
+use crate::coordinator::volume_client::VolumeClient;
+use crate::coordinator::webhooks::WEBHOOKS;
 use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
 use axum::response::Sse;
 
@@ -142,7 +331,27 @@ struct CreateKeyRequest {
 }
 
 fn default_tenant() -> String {
-    "default".to_string()
+    tenant::DEFAULT_TENANT.to_string()
+}
+
+/// Role named by a request: "admin", "read_write" or "read_only" (the default)
+fn parse_role(role: &str) -> Option<Role> {
+    match role.to_lowercase().as_str() {
+        "admin" => Some(Role::Admin),
+        "read_write" | "readwrite" | "rw" => Some(Role::ReadWrite),
+        "read_only" | "readonly" | "ro" | "" => Some(Role::ReadOnly),
+        _ => None,
+    }
+}
+
+fn invalid_role() -> (StatusCode, axum::Json<serde_json::Value>) {
+    (
+        StatusCode::BAD_REQUEST,
+        axum::Json(json!({
+            "error": "Invalid role",
+            "valid_roles": ["admin", "read_write", "read_only"]
+        })),
+    )
 }
 
 /// Response for a created API key
@@ -160,28 +369,40 @@ struct CreateKeyResponse {
     warning: String,
 }
 
-/// Create a new API key (Admin only)
-async fn admin_create_key(axum::Json(req): axum::Json<CreateKeyRequest>) -> impl IntoResponse {
-    let role = match req.role.to_lowercase().as_str() {
-        "admin" => Role::Admin,
-        "read_write" | "readwrite" | "rw" => Role::ReadWrite,
-        "read_only" | "readonly" | "ro" | "" => Role::ReadOnly,
-        _ => {
-            return (
-                StatusCode::BAD_REQUEST,
-                axum::Json(json!({
-                    "error": "Invalid role",
-                    "valid_roles": ["admin", "read_write", "read_only"]
-                })),
-            )
-                .into_response();
-        }
+/// Create a new API key (Admin only), replicated through Raft
+async fn admin_create_key(
+    State(state): State<CoordState>,
+    auth: Option<axum::Extension<AuthExtension>>,
+    axum::Json(req): axum::Json<CreateKeyRequest>,
+) -> impl IntoResponse {
+    let Some(role) = parse_role(&req.role) else {
+        return invalid_role().into_response();
     };
 
+    if !tenant::is_valid(&req.tenant) {
+        return (
+            StatusCode::BAD_REQUEST,
+            axum::Json(json!({
+                "error": "Invalid tenant",
+                "hint": "Tenants are non-empty, without '/' and don't start with '.'"
+            })),
+        )
+            .into_response();
+    }
+
     let expires_in = req.expires_in_secs.map(Duration::from_secs);
 
-    match KEY_STORE.generate_key(&req.name, &req.tenant, role, expires_in) {
-        Ok((id, key)) => {
+    match KEY_STORE.create_key(&req.name, &req.tenant, role, expires_in) {
+        Ok((api_key, key)) => {
+            let id = api_key.id.clone();
+            let command = MetadataCommand::PutApiKey((&api_key).into());
+            if let Err(e) = state.raft.propose(&command).await {
+                return (
+                    e.to_http_status(),
+                    axum::Json(json!({ "error": format!("{}", e) })),
+                )
+                    .into_response();
+            }
             let response = CreateKeyResponse {
                 id: id.clone(),
                 key,
@@ -191,12 +412,11 @@ async fn admin_create_key(axum::Json(req): axum::Json<CreateKeyRequest>) -> impl
             };
             AUDIT_LOGGER.log_event(
                 AuditEventType::ApiKeyCreated,
-                req.name.clone(),
+                audit_actor(&auth),
                 Some(id.clone()),
                 format!(
-                    "API key created for tenant {} with role {:?}",
-                    req.tenant.clone(),
-                    role
+                    "API key {} created for tenant {} with role {:?}",
+                    req.name, req.tenant, role
                 ),
                 None,
             );
@@ -274,12 +494,28 @@ async fn admin_get_key(Path(key_id): Path<String>) -> impl IntoResponse {
 }
 
 /// Revoke an API key (Admin only)
-async fn admin_revoke_key(Path(key_id): Path<String>) -> impl IntoResponse {
-    match KEY_STORE.revoke_key(&key_id) {
+async fn admin_revoke_key(
+    State(state): State<CoordState>,
+    Path(key_id): Path<String>,
+    auth: Option<axum::Extension<AuthExtension>>,
+) -> impl IntoResponse {
+    let Some(mut api_key) = KEY_STORE.get_key(&key_id) else {
+        return (
+            StatusCode::NOT_FOUND,
+            axum::Json(json!({ "error": format!("Key not found: {}", key_id) })),
+        )
+            .into_response();
+    };
+    api_key.active = false;
+    match state
+        .raft
+        .propose(&MetadataCommand::PutApiKey((&api_key).into()))
+        .await
+    {
         Ok(()) => {
             AUDIT_LOGGER.log_event(
                 AuditEventType::ApiKeyRevoked,
-                "admin", // TODO: extract actor from request context (for audit log)
+                audit_actor(&auth),
                 Some(key_id.clone()),
                 "API key revoked",
                 None,
@@ -298,7 +534,7 @@ async fn admin_revoke_key(Path(key_id): Path<String>) -> impl IntoResponse {
                 .into_response()
         }
         Err(e) => (
-            StatusCode::NOT_FOUND,
+            e.to_http_status(),
             axum::Json(json!({ "error": format!("{}", e) })),
         )
             .into_response(),
@@ -306,12 +542,27 @@ async fn admin_revoke_key(Path(key_id): Path<String>) -> impl IntoResponse {
 }
 
 /// Delete an API key permanently (Admin only)
-async fn admin_delete_key(Path(key_id): Path<String>) -> impl IntoResponse {
-    match KEY_STORE.delete_key(&key_id) {
+async fn admin_delete_key(
+    State(state): State<CoordState>,
+    Path(key_id): Path<String>,
+    auth: Option<axum::Extension<AuthExtension>>,
+) -> impl IntoResponse {
+    if KEY_STORE.get_key(&key_id).is_none() {
+        return (
+            StatusCode::NOT_FOUND,
+            axum::Json(json!({ "error": format!("Key not found: {}", key_id) })),
+        )
+            .into_response();
+    }
+    match state
+        .raft
+        .propose(&MetadataCommand::DeleteApiKey(key_id.clone()))
+        .await
+    {
         Ok(()) => {
             AUDIT_LOGGER.log_event(
                 AuditEventType::ApiKeyDeleted,
-                "admin", // TODO: extract actor from request context (for audit log)
+                audit_actor(&auth),
                 Some(key_id.clone()),
                 "API key deleted",
                 None,
@@ -330,7 +581,7 @@ async fn admin_delete_key(Path(key_id): Path<String>) -> impl IntoResponse {
                 .into_response()
         }
         Err(e) => (
-            StatusCode::NOT_FOUND,
+            e.to_http_status(),
             axum::Json(json!({ "error": format!("{}", e) })),
         )
             .into_response(),
@@ -341,196 +592,1274 @@ async fn admin_delete_key(Path(key_id): Path<String>) -> impl IntoResponse {
 // End API Key Management
 // ============================================================================
 
-/// Shared coordinator state for HTTP handlers.
-#[derive(Clone)]
-pub struct CoordState {
-    pub metadata: Arc<MetadataStore>,
-    pub placement: Arc<std::sync::Mutex<PlacementManager>>,
-    pub raft: Arc<RaftNode>,
+/// Request body for setting a tenant's default consistency level
+#[derive(Debug, Deserialize)]
+struct SetConsistencyRequest {
+    level: ConsistencyLevel,
 }
 
-/// Minimal S3-compatible PUT object endpoint
-/// Supports TTL via X-Minikv-TTL header (seconds) (v0.5.0)
-/// Supports multi-tenancy (v0.6.0)
-async fn s3_put_object(
-    State(state): State<CoordState>,
-    Path((bucket, key)): Path<(String, String)>,
-    headers: axum::http::HeaderMap,
-    body: Bytes,
-) -> impl IntoResponse {
-    // For demo: concatenate bucket/key for internal key
-    let full_key = format!("{}/{}", bucket, key);
+/// Request body for setting a tenant's quota; unset limits are unlimited
+#[derive(Debug, Deserialize)]
+struct SetQuotaRequest {
+    #[serde(default)]
+    storage_limit: u64,
+    #[serde(default)]
+    object_limit: u64,
+    #[serde(default)]
+    rate_limit: u32,
+    #[serde(default)]
+    ingress_limit: u64,
+    #[serde(default)]
+    egress_limit: u64,
+    #[serde(default = "default_quota_enabled")]
+    enabled: bool,
+}
 
-    // Extract TTL from header (v0.5.0)
-    let ttl_secs: Option<u64> = headers
-        .get("X-Minikv-TTL")
-        .and_then(|v| v.to_str().ok())
-        .and_then(|s| s.parse().ok());
+fn default_quota_enabled() -> bool {
+    true
+}
 
-    // let expires_at = ttl_secs.map(|ttl| {
-    //     let now = std::time::SystemTime::now()
-    //         .duration_since(std::time::UNIX_EPOCH)
-    //         .unwrap()
-    //         .as_millis() as u64;
-    //     now + (ttl * 1000) // Convert seconds to milliseconds
-    // });
+/// Request body for draining a volume
+#[derive(Debug, Deserialize)]
+struct DrainRequest {
+    volume_id: String,
+}
 
-    // Extract tenant from request (v0.6.0)
-    // For now, use "default" tenant - will be extracted from auth context when middleware is applied
-    // let tenant = "default".to_string();
+/// Admin endpoint: starts draining a volume (idempotent while a drain is running)
+async fn admin_drain(
+    State(state): State<CoordState>,
+    auth: Option<axum::Extension<AuthExtension>>,
+    axum::Json(req): axum::Json<DrainRequest>,
+) -> impl IntoResponse {
+    audit_admin(&auth, Some(req.volume_id.clone()), "Volume drain");
+    match drain::start_drain(
+        state.metadata.clone(),
+        state.placement.clone(),
+        state.raft.clone(),
+        &req.volume_id,
+    )
+    .await
+    {
+        Ok(progress) => (StatusCode::ACCEPTED, axum::Json(json!(progress))),
+        Err(e) => (
+            e.to_http_status(),
+            axum::Json(json!({ "error": format!("{}", e) })),
+        ),
+    }
+}
 
-    // Store the body in the selected backend
+/// Admin endpoint: progress of a volume drain
+async fn admin_drain_status(
+    State(state): State<CoordState>,
+    Path(volume_id): Path<String>,
+) -> impl IntoResponse {
+    match drain::get_progress(&state.metadata, &volume_id) {
+        Ok(Some(progress)) => (StatusCode::OK, axum::Json(json!(progress))),
+        Ok(None) => (
+            StatusCode::NOT_FOUND,
+            axum::Json(json!({ "error": format!("no drain for {}", volume_id) })),
+        ),
+        Err(e) => (
+            e.to_http_status(),
+            axum::Json(json!({ "error": format!("{}", e) })),
+        ),
+    }
+}
 
-    // For now, only the value is persisted; TTL/tenant can be handled via metadata in future
-    crate::coordinator::http::STORAGE.put(&full_key, body.to_vec());
-    let stored_bytes = body.len();
-    // Publish key change event (PUT)
-    let _ = WATCH_CHANNEL.send(KeyChangeEvent {
-        event: "put".to_string(),
-        key: full_key.clone(),
-        tenant: Some("default".to_string()), // TODO: extract tenant from authentication context
-        timestamp: chrono::Utc::now().timestamp(),
-    });
+/// Request body for a leadership transfer
+#[derive(Debug, Deserialize)]
+struct TransferLeadershipRequest {
+    /// Peer address of the coordinator to hand leadership to (the most
+    /// caught-up peer if omitted)
+    target: Option<String>,
+    /// Give up after this long (default 5s)
+    timeout_ms: Option<u64>,
+}
 
-    // Use existing 2PC logic (simplified)
-    let placement = state.placement.lock().unwrap();
-    let volumes = state.metadata.get_healthy_volumes().unwrap_or_default();
-    let target_volumes: Vec<String> = placement
-        .select_volumes(&full_key, &volumes)
-        .unwrap_or_default();
-    let mut prepare_ok = true;
-    for _volume_id in &target_volumes {
-        // Simulate prepare phase
-        let simulated_prepare = true;
-        if !simulated_prepare {
-            prepare_ok = false;
-            break;
-        }
+/// Admin endpoint: moves leadership to another coordinator, e.g. before
+/// maintenance on this one. Waits for the target's log to catch up first.
+async fn admin_transfer_leadership(
+    State(state): State<CoordState>,
+    auth: Option<axum::Extension<AuthExtension>>,
+    axum::Json(req): axum::Json<TransferLeadershipRequest>,
+) -> impl IntoResponse {
+    let Some(target) = req.target.or_else(|| state.raft.most_caught_up_peer()) else {
+        return (
+            StatusCode::BAD_REQUEST,
+            axum::Json(json!({ "error": "no peer to hand leadership to" })),
+        );
+    };
+    audit_admin(&auth, Some(target.clone()), "Leadership transfer");
+    let timeout = req
+        .timeout_ms
+        .map(Duration::from_millis)
+        .unwrap_or(crate::coordinator::raft_node::DEFAULT_TRANSFER_TIMEOUT);
+    match state.raft.transfer_leadership(&target, timeout).await {
+        Ok(()) => (
+            StatusCode::OK,
+            axum::Json(json!({
+                "status": "transferred",
+                "leader_id": state.raft.get_leader(),
+            })),
+        ),
+        Err(e) => (
+            e.to_http_status(),
+            axum::Json(json!({ "error": format!("{}", e) })),
+        ),
     }
-    if !prepare_ok {
+}
+
+/// Request body for queueing a volume command
+#[derive(Debug, Deserialize)]
+struct VolumeCommandRequest {
+    /// Command in wire form, e.g. `compact_segment:3`
+    command: String,
+}
+
+/// Admin endpoint: queues a command for delivery on the volume's next
+/// heartbeat (served by the leader, which takes the heartbeats)
+async fn admin_volume_command(
+    State(state): State<CoordState>,
+    Path(volume_id): Path<String>,
+    auth: Option<axum::Extension<AuthExtension>>,
+    axum::Json(req): axum::Json<VolumeCommandRequest>,
+) -> impl IntoResponse {
+    if let Err(e) = state.raft.ensure_leader() {
         return (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!(
-                "PUT S3 {}/{} failed: prepare phase error (2PC)",
-                bucket, key
-            ),
+            e.to_http_status(),
+            axum::Json(json!({ "error": e.to_string() })),
         );
     }
-    // Commit phase (simulated)
-    for _volume_id in &target_volumes {
-        // Simulate commit
+    let command = match req.command.parse::<VolumeCommand>() {
+        Ok(command) => command,
+        Err(e) => {
+            return (
+                e.to_http_status(),
+                axum::Json(json!({ "error": format!("{}", e) })),
+            )
+        }
+    };
+    match state.metadata.get_volume(&volume_id) {
+        Ok(Some(_)) => {}
+        Ok(None) => {
+            return (
+                StatusCode::NOT_FOUND,
+                axum::Json(json!({ "error": format!("unknown volume {}", volume_id) })),
+            )
+        }
+        Err(e) => {
+            return (
+                e.to_http_status(),
+                axum::Json(json!({ "error": format!("{}", e) })),
+            )
+        }
     }
 
-    // Build response message
-    let ttl_info = ttl_secs
-        .map(|t| format!(", TTL: {}s", t))
-        .unwrap_or_default();
+    audit_admin(
+        &auth,
+        Some(volume_id.clone()),
+        format!("Volume command {}", command),
+    );
+    VOLUME_COMMANDS.enqueue(&volume_id, command);
+    let pending: Vec<String> = VOLUME_COMMANDS
+        .pending(&volume_id)
+        .iter()
+        .map(ToString::to_string)
+        .collect();
     (
-        StatusCode::OK,
-        format!(
-            "PUT S3 {}/{} committed via 2PC ({} bytes{})",
-            bucket, key, stored_bytes, ttl_info
-        ),
+        StatusCode::ACCEPTED,
+        axum::Json(json!({ "volume_id": volume_id, "pending": pending })),
     )
 }
 
-/// Minimal S3-compatible GET object endpoint
-/// Supports multi-tenancy (v0.6.0)
-async fn s3_get_object(
-    State(_state): State<CoordState>,
-    Path((bucket, key)): Path<(String, String)>,
+/// Admin endpoint: recomputes the shard map and migrates changed shards
+async fn admin_rebalance(
+    State(state): State<CoordState>,
+    auth: Option<axum::Extension<AuthExtension>>,
 ) -> impl IntoResponse {
-    // Retrieve the value from the selected backend
-    let full_key = format!("{}/{}", bucket, key);
-    if let Some(data) = crate::coordinator::http::STORAGE.get(&full_key) {
-        // TODO: Check TTL and tenant if metadata is persisted
-        (StatusCode::OK, data)
-    } else {
-        (
-            StatusCode::NOT_FOUND,
-            format!("S3 object {}/{} not found", bucket, key).into_bytes(),
-        )
+    audit_admin(&auth, None, "Shard rebalance");
+    match migration::start_rebalance(
+        state.metadata.clone(),
+        state.placement.clone(),
+        state.raft.clone(),
+    ) {
+        Ok(status) => (StatusCode::ACCEPTED, axum::Json(json!(status))),
+        Err(e) => (
+            e.to_http_status(),
+            axum::Json(json!({ "error": format!("{}", e) })),
+        ),
     }
 }
 
-/// Creates the HTTP router with all public endpoints.
-/// Updated in v0.6.0 with authentication and key management
-pub fn create_router(state: CoordState) -> Router {
-    Router::new()
-        // S3-compatible minimal endpoints with TTL support
-        .route("/watch/sse", axum::routing::get(watch_sse))
-        .route("/watch/ws", axum::routing::get(watch_ws))
-        .route("/s3/:bucket/:key", axum::routing::put(s3_put_object))
-        .route("/s3/:bucket/:key", axum::routing::get(s3_get_object))
-        // Health check endpoints (v0.5.0)
-        .route("/health", axum::routing::get(health))
-        .route("/health/ready", axum::routing::get(health_ready))
-        .route("/health/live", axum::routing::get(health_live))
-        // Key operations
-        .route("/:key", axum::routing::post(put_key))
-        .route("/:key", axum::routing::get(get_key))
-        .route("/:key", axum::routing::delete(delete_key))
-        // Admin automation endpoints
+/// Admin endpoint: per-shard migration progress
+async fn admin_rebalance_status() -> impl IntoResponse {
+    axum::Json(json!(migration::MIGRATIONS.status()))
+}
+
+/// Query parameters of POST /admin/gc
+#[derive(Deserialize)]
+struct GcQuery {
+    /// Report orphans without deleting them
+    #[serde(default)]
+    dry_run: bool,
+    /// Overrides the configured grace period
+    grace_secs: Option<u64>,
+}
+
+/// Admin endpoint: runs one orphaned blob collection now and returns its report
+async fn admin_gc(
+    State(state): State<CoordState>,
+    Query(query): Query<GcQuery>,
+    auth: Option<axum::Extension<AuthExtension>>,
+) -> impl IntoResponse {
+    if !query.dry_run {
+        audit_admin(&auth, None, "Orphaned blob collection");
+    }
+    let grace_secs = query.grace_secs.unwrap_or_else(|| GC.grace_secs());
+    match gc::run_gc(&state.metadata, &state.raft, grace_secs, query.dry_run).await {
+        Ok(report) => (StatusCode::OK, axum::Json(json!(report))),
+        Err(e) => (
+            e.to_http_status(),
+            axum::Json(json!({ "error": e.to_string() })),
+        ),
+    }
+}
+
+/// Admin endpoint: report of the last orphaned blob collection
+async fn admin_gc_status(State(state): State<CoordState>) -> impl IntoResponse {
+    if let Err(e) = state.raft.ensure_leader() {
+        return (
+            e.to_http_status(),
+            axum::Json(json!({ "error": e.to_string() })),
+        );
+    }
+    (
+        StatusCode::OK,
+        axum::Json(json!({ "grace_secs": GC.grace_secs(), "last_run": GC.last_run() })),
+    )
+}
+
+/// Admin endpoint: every registered volume, whatever its state
+async fn admin_list_volumes(State(state): State<CoordState>) -> impl IntoResponse {
+    match state.metadata.list_volumes() {
+        Ok(volumes) => (StatusCode::OK, axum::Json(json!({ "volumes": volumes }))),
+        Err(e) => (
+            e.to_http_status(),
+            axum::Json(json!({ "error": e.to_string() })),
+        ),
+    }
+}
+
+/// Admin endpoint: one page of key metadata under an internal `prefix`, in
+/// key order. Unlike GET /keys it spans every tenant and includes the
+/// content-addressed and chunk keys, for the ops that check the whole store.
+async fn admin_list_metadata(
+    State(state): State<CoordState>,
+    Query(params): Query<KeyListQuery>,
+) -> impl IntoResponse {
+    if !params.stale {
+        if let Err(e) = state.raft.read_index().await {
+            return (
+                e.to_http_status(),
+                axum::Json(json!({ "error": e.to_string() })),
+            );
+        }
+    }
+    let limit = crate::coordinator::metadata::page_limit(params.limit);
+    match state
+        .metadata
+        .list_keys_paginated(&params.prefix, params.after.as_deref(), limit)
+    {
+        Ok(page) => {
+            let next_after = page
+                .last()
+                .filter(|_| page.len() == limit)
+                .map(|meta| meta.key.clone());
+            (
+                StatusCode::OK,
+                axum::Json(json!({ "keys": page, "next_after": next_after })),
+            )
+        }
+        Err(e) => (
+            e.to_http_status(),
+            axum::Json(json!({ "error": e.to_string() })),
+        ),
+    }
+}
+
+/// Query parameters of GET /admin/metadata/usage
+#[derive(Debug, Deserialize)]
+struct UsageQuery {
+    #[serde(default)]
+    by: usage::UsageGrouping,
+    /// Levels of the key under the tenant grouped by, with `by=prefix`
+    depth: Option<usize>,
+    /// Only count the keys under this internal prefix (`tenant/...`)
+    #[serde(default)]
+    prefix: String,
+}
+
+/// Admin endpoint: bytes and keys stored per tenant or key prefix, from the
+/// key metadata of this coordinator
+async fn admin_metadata_usage(
+    State(state): State<CoordState>,
+    Query(query): Query<UsageQuery>,
+) -> impl IntoResponse {
+    let levels = query.by.levels(query.depth.unwrap_or(1));
+    match usage::prefix_usage(&state.metadata, &query.prefix, levels) {
+        Ok(usage) => {
+            let total_bytes: u64 = usage.iter().map(|u| u.bytes).sum();
+            let total_keys: u64 = usage.iter().map(|u| u.keys).sum();
+            (
+                StatusCode::OK,
+                axum::Json(json!({
+                    "by": query.by,
+                    "usage": usage,
+                    "total_bytes": total_bytes,
+                    "total_keys": total_keys,
+                })),
+            )
+        }
+        Err(e) => (
+            e.to_http_status(),
+            axum::Json(json!({ "error": e.to_string() })),
+        ),
+    }
+}
+
+/// Query parameters of GET /admin/hotkeys
+#[derive(Debug, Deserialize)]
+struct HotKeysQuery {
+    /// Seconds looked back
+    window: Option<u64>,
+    /// Keys listed per ranking
+    limit: Option<usize>,
+}
+
+/// Admin endpoint: the most read, most written and largest keys accessed
+/// through this coordinator over a window
+async fn admin_hotkeys(Query(query): Query<HotKeysQuery>) -> impl IntoResponse {
+    let report = HOT_KEYS.report(query.window.unwrap_or(300), query.limit.unwrap_or(10));
+    axum::Json(json!(report))
+}
+
+/// Admin endpoint: the faults this coordinator injects
+async fn admin_chaos() -> impl IntoResponse {
+    axum::Json(json!({ "enabled": FAULTS.is_enabled(), "faults": FAULTS.get() }))
+}
+
+/// Admin endpoint: replace the faults this coordinator injects
+async fn admin_set_chaos(
+    auth: Option<axum::Extension<AuthExtension>>,
+    axum::Json(faults): axum::Json<Faults>,
+) -> impl IntoResponse {
+    match FAULTS.set(faults) {
+        Ok(faults) => {
+            audit_admin(&auth, None, format!("Fault injection: {:?}", faults));
+            (
+                StatusCode::OK,
+                axum::Json(json!({ "enabled": true, "faults": faults })),
+            )
+        }
+        Err(e) => (
+            e.to_http_status(),
+            axum::Json(json!({ "error": e.to_string() })),
+        ),
+    }
+}
+
+/// Admin endpoint: re-read the configuration and apply what can change at
+/// runtime, reporting the fields applied and those needing a restart (see
+/// `common::reload`)
+async fn admin_reload_config(auth: Option<axum::Extension<AuthExtension>>) -> impl IntoResponse {
+    match reload_now().await {
+        Ok(report) => {
+            audit_admin(
+                &auth,
+                None,
+                format!("Configuration reloaded: {}", report.applied.join(", ")),
+            );
+            (StatusCode::OK, axum::Json(json!(report)))
+        }
+        Err(e) => (
+            e.to_http_status(),
+            axum::Json(json!({ "error": e.to_string() })),
+        ),
+    }
+}
+
+/// Admin endpoint: stop injecting faults
+async fn admin_clear_chaos(auth: Option<axum::Extension<AuthExtension>>) -> impl IntoResponse {
+    FAULTS.clear();
+    audit_admin(&auth, None, "Fault injection cleared");
+    axum::Json(json!({ "enabled": FAULTS.is_enabled(), "faults": FAULTS.get() }))
+}
+
+/// Admin endpoint: cold storage tiering status (served by the leader)
+async fn admin_tier_status(State(state): State<CoordState>) -> impl IntoResponse {
+    if let Err(e) = state.raft.ensure_leader() {
+        return (
+            e.to_http_status(),
+            axum::Json(json!({ "error": e.to_string() })),
+        );
+    }
+    match tiering::tier_status(&state.metadata) {
+        Ok(status) => (StatusCode::OK, axum::Json(json!(status))),
+        Err(e) => (
+            e.to_http_status(),
+            axum::Json(json!({ "error": e.to_string() })),
+        ),
+    }
+}
+
+/// Admin endpoint: starts a tiering pass now
+async fn admin_tier_run(
+    State(state): State<CoordState>,
+    auth: Option<axum::Extension<AuthExtension>>,
+) -> impl IntoResponse {
+    if let Err(e) = state.raft.ensure_leader() {
+        return (
+            e.to_http_status(),
+            axum::Json(json!({ "error": e.to_string() })),
+        );
+    }
+    if TIERING.tier().is_none() {
+        return (
+            StatusCode::BAD_REQUEST,
+            axum::Json(json!({ "error": "cold storage tiering is not configured" })),
+        );
+    }
+    audit_admin(&auth, None, "Tiering pass");
+    tokio::spawn(async move {
+        if let Err(e) = tiering::run_tier_pass(&state.metadata, &state.raft).await {
+            tracing::warn!("Tiering pass failed: {}", e);
+        }
+    });
+    (StatusCode::ACCEPTED, axum::Json(json!({ "started": true })))
+}
+
+/// Admin endpoint: cursor, lag and counters of each replication target
+/// (served by the leader, which pushes the changes)
+async fn admin_replication_status(State(state): State<CoordState>) -> impl IntoResponse {
+    if let Err(e) = state.raft.ensure_leader() {
+        return (
+            e.to_http_status(),
+            axum::Json(json!({ "error": e.to_string() })),
+        );
+    }
+    match REPLICATION.status(&state.metadata) {
+        Ok(status) => (StatusCode::OK, axum::Json(json!(status))),
+        Err(e) => (
+            e.to_http_status(),
+            axum::Json(json!({ "error": e.to_string() })),
+        ),
+    }
+}
+
+/// Admin endpoint: adds a replication target at runtime, which receives the
+/// changes made from now on (idempotent for the same definition)
+async fn admin_add_replication_target(
+    State(state): State<CoordState>,
+    auth: Option<axum::Extension<AuthExtension>>,
+    axum::Json(target): axum::Json<ReplicationTarget>,
+) -> impl IntoResponse {
+    let name = target.name.clone();
+    match replication::add_target(&state.metadata, &state.raft, target).await {
+        Ok(()) => {
+            audit_admin(&auth, Some(name.clone()), "Replication target added");
+            (StatusCode::OK, axum::Json(json!({ "target": name })))
+        }
+        Err(e) => (
+            e.to_http_status(),
+            axum::Json(json!({ "error": e.to_string() })),
+        ),
+    }
+}
+
+/// Admin endpoint: removes a replication target added at runtime
+async fn admin_remove_replication_target(
+    State(state): State<CoordState>,
+    Path(name): Path<String>,
+    auth: Option<axum::Extension<AuthExtension>>,
+) -> impl IntoResponse {
+    match replication::remove_target(&state.metadata, &state.raft, &name).await {
+        Ok(true) => {
+            audit_admin(&auth, Some(name.clone()), "Replication target removed");
+            (StatusCode::OK, axum::Json(json!({ "removed": name })))
+        }
+        Ok(false) => (
+            StatusCode::NOT_FOUND,
+            axum::Json(json!({ "error": format!("no replication target {}", name) })),
+        ),
+        Err(e) => (
+            e.to_http_status(),
+            axum::Json(json!({ "error": e.to_string() })),
+        ),
+    }
+}
+
+/// Admin endpoint: runs one anti-entropy round now
+async fn admin_anti_entropy(
+    State(state): State<CoordState>,
+    auth: Option<axum::Extension<AuthExtension>>,
+) -> impl IntoResponse {
+    audit_admin(&auth, None, "Anti-entropy round");
+    let num_shards = state.placement.lock().unwrap().num_shards();
+    match anti_entropy::run_anti_entropy(&state.metadata, num_shards).await {
+        Ok(report) => axum::Json(json!({ "status": "ok", "report": report })),
+        Err(e) => axum::Json(json!({ "status": "error", "error": format!("{}", e) })),
+    }
+}
+
+/// Get a tenant's default consistency level (Admin only)
+async fn admin_get_consistency(
+    State(state): State<CoordState>,
+    Path(tenant): Path<String>,
+) -> impl IntoResponse {
+    let level = consistency::tenant_default(&state.metadata, &tenant);
+    axum::Json(json!({ "tenant": tenant, "level": level }))
+}
+
+/// Set a tenant's default consistency level (Admin only)
+async fn admin_set_consistency(
+    State(state): State<CoordState>,
+    Path(tenant): Path<String>,
+    auth: Option<axum::Extension<AuthExtension>>,
+    axum::Json(req): axum::Json<SetConsistencyRequest>,
+) -> impl IntoResponse {
+    match consistency::set_tenant_default(&state.raft, &tenant, req.level).await {
+        Ok(()) => {
+            AUDIT_LOGGER.log_event(
+                AuditEventType::ConfigChanged,
+                audit_actor(&auth),
+                Some(tenant.clone()),
+                format!("Default consistency set to {:?}", req.level),
+                None,
+            );
+            (
+                StatusCode::OK,
+                axum::Json(json!({ "tenant": tenant, "level": req.level })),
+            )
+        }
+        Err(e) => (
+            e.to_http_status(),
+            axum::Json(json!({ "error": format!("{}", e) })),
+        ),
+    }
+}
+
+/// Rate limiter settings, clients tracked and requests refused (Admin only)
+async fn admin_ratelimit(State(state): State<CoordState>) -> impl IntoResponse {
+    let stats = state.rate_limiter.stats();
+    axum::Json(json!({
+        "enabled": stats.config.enabled,
+        "requests_per_second": stats.config.requests_per_second,
+        "burst_size": stats.config.burst_size,
+        "tracked_ips": stats.tracked_ips,
+        "rate_limited_requests": crate::common::METRICS.rate_limited_requests.get(),
+    }))
+}
+
+/// A tenant's quota and usage, as answered by the `/admin/quotas` endpoints
+fn quota_json(tenant: &str) -> serde_json::Value {
+    let own = QUOTA_MANAGER.get_quota(tenant);
+    let is_default = own.is_none();
+    let quota = own.unwrap_or_else(|| QUOTA_MANAGER.get_default_quota());
+    let usage = QUOTA_MANAGER.get_usage(tenant);
+    json!({
+        "tenant": tenant,
+        "storage_limit": quota.storage_limit,
+        "object_limit": quota.object_limit,
+        "rate_limit": quota.rate_limit,
+        "ingress_limit": quota.ingress_limit,
+        "egress_limit": quota.egress_limit,
+        "enabled": quota.enabled,
+        "default": is_default,
+        "storage_used": usage.storage_used,
+        "object_count": usage.object_count,
+    })
+}
+
+/// List the tenants with a quota of their own, and the default quota (Admin only)
+async fn admin_list_quotas() -> impl IntoResponse {
+    let mut tenants: Vec<String> = QUOTA_MANAGER
+        .list_quotas()
+        .into_iter()
+        .map(|quota| quota.tenant_id)
+        .collect();
+    tenants.sort();
+    let default = QUOTA_MANAGER.get_default_quota();
+    axum::Json(json!({
+        "quotas": tenants.iter().map(|t| quota_json(t)).collect::<Vec<_>>(),
+        "default": {
+            "storage_limit": default.storage_limit,
+            "object_limit": default.object_limit,
+            "rate_limit": default.rate_limit,
+            "ingress_limit": default.ingress_limit,
+            "egress_limit": default.egress_limit,
+        },
+    }))
+}
+
+/// Get the quota applying to a tenant and its usage (Admin only)
+async fn admin_get_quota(Path(tenant): Path<String>) -> impl IntoResponse {
+    axum::Json(quota_json(&tenant))
+}
+
+/// Set a tenant's quota, replicated through Raft (Admin only)
+async fn admin_set_quota(
+    State(state): State<CoordState>,
+    Path(tenant): Path<String>,
+    auth: Option<axum::Extension<AuthExtension>>,
+    axum::Json(req): axum::Json<SetQuotaRequest>,
+) -> impl IntoResponse {
+    if !tenant::is_valid(&tenant) {
+        return (
+            StatusCode::BAD_REQUEST,
+            axum::Json(json!({ "error": format!("Invalid tenant: {}", tenant) })),
+        );
+    }
+    let mut quota = TenantQuota::with_limits(
+        tenant.clone(),
+        req.storage_limit,
+        req.object_limit,
+        req.rate_limit,
+    );
+    quota.ingress_limit = req.ingress_limit;
+    quota.egress_limit = req.egress_limit;
+    quota.enabled = req.enabled;
+    if let Err(e) = state.raft.propose(&MetadataCommand::PutQuota(quota)).await {
+        return (
+            e.to_http_status(),
+            axum::Json(json!({ "error": format!("{}", e) })),
+        );
+    }
+    AUDIT_LOGGER.log_event(
+        AuditEventType::ConfigChanged,
+        audit_actor(&auth),
+        Some(tenant.clone()),
+        format!(
+            "Quota set: storage_limit={} object_limit={} rate_limit={} ingress_limit={} egress_limit={} enabled={}",
+            req.storage_limit,
+            req.object_limit,
+            req.rate_limit,
+            req.ingress_limit,
+            req.egress_limit,
+            req.enabled
+        ),
+        None,
+    );
+    (StatusCode::OK, axum::Json(quota_json(&tenant)))
+}
+
+/// A tenant's current usage against its quota, transfer rates included (Admin only)
+async fn admin_quota_usage(Path(tenant): Path<String>) -> impl IntoResponse {
+    let quota = QUOTA_MANAGER
+        .get_quota(&tenant)
+        .unwrap_or_else(|| QUOTA_MANAGER.get_default_quota());
+    let usage = QUOTA_MANAGER.get_usage(&tenant);
+    axum::Json(json!({
+        "tenant": tenant,
+        "storage_used": usage.storage_used,
+        "storage_limit": quota.storage_limit,
+        "object_count": usage.object_count,
+        "object_limit": quota.object_limit,
+        "requests_per_sec": usage.request_rate(),
+        "rate_limit": quota.rate_limit,
+        "ingress_bytes_per_sec": QUOTA_MANAGER.bandwidth_rate(&tenant, Direction::Ingress),
+        "ingress_limit": quota.ingress_limit,
+        "egress_bytes_per_sec": QUOTA_MANAGER.bandwidth_rate(&tenant, Direction::Egress),
+        "egress_limit": quota.egress_limit,
+    }))
+}
+
+/// Remove a tenant's quota, so the default one applies (Admin only)
+async fn admin_delete_quota(
+    State(state): State<CoordState>,
+    Path(tenant): Path<String>,
+    auth: Option<axum::Extension<AuthExtension>>,
+) -> impl IntoResponse {
+    if QUOTA_MANAGER.get_quota(&tenant).is_none() {
+        return (
+            StatusCode::NOT_FOUND,
+            axum::Json(json!({ "error": format!("No quota for tenant {}", tenant) })),
+        );
+    }
+    if let Err(e) = state
+        .raft
+        .propose(&MetadataCommand::DeleteQuota(tenant.clone()))
+        .await
+    {
+        return (
+            e.to_http_status(),
+            axum::Json(json!({ "error": format!("{}", e) })),
+        );
+    }
+    AUDIT_LOGGER.log_event(
+        AuditEventType::ConfigChanged,
+        audit_actor(&auth),
+        Some(tenant.clone()),
+        "Quota removed",
+        None,
+    );
+    (StatusCode::OK, axum::Json(quota_json(&tenant)))
+}
+
+/// Request body for setting an ACL rule
+#[derive(Debug, Deserialize)]
+struct SetAclRuleRequest {
+    tenant: String,
+    /// Keys starting with this prefix, as the tenant sees them
+    #[serde(default)]
+    prefix: String,
+    /// Confine this API key only; every key of the tenant if unset
+    key_id: Option<String>,
+    /// Least role needed: "admin", "read_write" or "read_only"
+    #[serde(default)]
+    min_role: String,
+}
+
+/// Query parameters of GET /admin/acls
+#[derive(Debug, Deserialize)]
+struct ListAclQuery {
+    tenant: Option<String>,
+}
+
+/// List ACL rules, optionally of one tenant (Admin only)
+async fn admin_list_acls(Query(query): Query<ListAclQuery>) -> impl IntoResponse {
+    let rules = ACL_STORE.list_rules(query.tenant.as_deref());
+    axum::Json(json!({ "rules": rules }))
+}
+
+/// Get an ACL rule (Admin only)
+async fn admin_get_acl(Path(id): Path<String>) -> impl IntoResponse {
+    match ACL_STORE.get_rule(&id) {
+        Some(rule) => (StatusCode::OK, axum::Json(json!(rule))),
+        None => (
+            StatusCode::NOT_FOUND,
+            axum::Json(json!({ "error": format!("No ACL rule {}", id) })),
+        ),
+    }
+}
+
+/// Create or replace an ACL rule, replicated through Raft (Admin only)
+async fn admin_set_acl(
+    State(state): State<CoordState>,
+    Path(id): Path<String>,
+    auth: Option<axum::Extension<AuthExtension>>,
+    axum::Json(req): axum::Json<SetAclRuleRequest>,
+) -> impl IntoResponse {
+    let Some(min_role) = parse_role(&req.min_role) else {
+        return invalid_role();
+    };
+    if !tenant::is_valid(&req.tenant) {
+        return (
+            StatusCode::BAD_REQUEST,
+            axum::Json(json!({ "error": format!("Invalid tenant: {}", req.tenant) })),
+        );
+    }
+    let rule = AclRule {
+        id,
+        tenant: req.tenant,
+        prefix: req.prefix,
+        key_id: req.key_id,
+        min_role,
+    };
+    if let Err(e) = state
+        .raft
+        .propose(&MetadataCommand::PutAclRule(rule.clone()))
+        .await
+    {
+        return (
+            e.to_http_status(),
+            axum::Json(json!({ "error": format!("{}", e) })),
+        );
+    }
+    AUDIT_LOGGER.log_event(
+        AuditEventType::ConfigChanged,
+        audit_actor(&auth),
+        Some(rule.tenant.clone()),
+        format!(
+            "ACL rule {} set: prefix={:?} key_id={:?} min_role={:?}",
+            rule.id, rule.prefix, rule.key_id, rule.min_role
+        ),
+        None,
+    );
+    (StatusCode::OK, axum::Json(json!(rule)))
+}
+
+/// Remove an ACL rule (Admin only)
+async fn admin_delete_acl(
+    State(state): State<CoordState>,
+    Path(id): Path<String>,
+    auth: Option<axum::Extension<AuthExtension>>,
+) -> impl IntoResponse {
+    let Some(rule) = ACL_STORE.get_rule(&id) else {
+        return (
+            StatusCode::NOT_FOUND,
+            axum::Json(json!({ "error": format!("No ACL rule {}", id) })),
+        );
+    };
+    if let Err(e) = state
+        .raft
+        .propose(&MetadataCommand::DeleteAclRule(id.clone()))
+        .await
+    {
+        return (
+            e.to_http_status(),
+            axum::Json(json!({ "error": format!("{}", e) })),
+        );
+    }
+    AUDIT_LOGGER.log_event(
+        AuditEventType::ConfigChanged,
+        audit_actor(&auth),
+        Some(rule.tenant.clone()),
+        format!("ACL rule {} removed", id),
+        None,
+    );
+    (StatusCode::OK, axum::Json(json!(rule)))
+}
+
+/// Whether the caller's ACL rules let it reach `key` of its tenant
+/// (see `common::acl`); always true without authentication
+fn acl_allows(auth: &Option<axum::Extension<AuthExtension>>, key: &str) -> bool {
+    auth.as_ref()
+        .and_then(|ext| ext.0 .0.as_ref())
+        .map_or(true, |ctx| ACL_STORE.allows(ctx, key))
+}
+
+/// Key ID of the caller, for the audit log
+fn audit_actor(auth: &Option<axum::Extension<AuthExtension>>) -> String {
+    audit::actor(auth.as_ref().and_then(|ext| ext.0 .0.as_ref()))
+}
+
+/// Record an admin operation requested by the caller
+fn audit_admin(
+    auth: &Option<axum::Extension<AuthExtension>>,
+    target: Option<String>,
+    operation: impl Into<String>,
+) {
+    AUDIT_LOGGER.log_event(
+        AuditEventType::AdminOperation,
+        audit_actor(auth),
+        target,
+        operation,
+        None,
+    );
+}
+
+/// Record the deletion of `internal` (`tenant/key`)
+fn audit_delete(auth: &Option<axum::Extension<AuthExtension>>, internal: &str) {
+    AUDIT_LOGGER.log_event(
+        AuditEventType::DataDelete,
+        audit_actor(auth),
+        Some(internal.to_string()),
+        "Key deleted",
+        None,
+    );
+}
+
+/// Shared coordinator state for HTTP handlers.
+#[derive(Clone)]
+pub struct CoordState {
+    pub metadata: Arc<MetadataStore>,
+    pub placement: Arc<std::sync::Mutex<PlacementManager>>,
+    pub raft: Arc<RaftNode>,
+    /// Authentication settings applied by `create_router`, changed by
+    /// configuration reloads
+    pub auth: Arc<std::sync::RwLock<AuthConfig>>,
+    /// Per-IP rate limiter applied by `create_router`
+    pub rate_limiter: Arc<RateLimiter>,
+    /// IP allow/deny lists applied by `create_router`
+    pub ip_filter: Arc<IpFilter>,
+    /// Bound on the writes in flight, applied to the write routes
+    pub writes: Arc<WriteBudget>,
+}
+
+/// Minimal S3-compatible PUT object endpoint
+/// Supports TTL via X-Minikv-TTL header (seconds) (v0.5.0)
+/// Supports multi-tenancy (v0.6.0)
+async fn s3_put_object(
+    State(state): State<CoordState>,
+    Path((bucket, key)): Path<(String, String)>,
+    auth: Option<axum::Extension<AuthExtension>>,
+    headers: axum::http::HeaderMap,
+    body: Bytes,
+) -> impl IntoResponse {
+    let tenant = match resolve_tenant(&state, &auth, &headers) {
+        Ok(tenant) => tenant,
+        Err(e) => return (e.to_http_status(), e.to_string()),
+    };
+    // For demo: concatenate bucket/key for internal key, in the tenant's namespace
+    let object = format!("{}/{}", bucket, key);
+    // Held to the same checks as PUT /:key
+    if !acl_allows(&auth, &object) {
+        return (
+            StatusCode::FORBIDDEN,
+            format!("PUT S3 {} denied by ACL", object),
+        );
+    }
+    if dedup::is_reserved(&object) {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!(
+                "{} and {} are reserved for deduplicated content and chunks",
+                dedup::CAS_PREFIX,
+                chunking::CHUNK_PREFIX
+            ),
+        );
+    }
+    let full_key = tenant::internal_key(&tenant, &object);
+    // Objects carry no tags: only the value the plugins answer is kept
+    let body = match PLUGINS.on_put(&object, body, Default::default()).await {
+        Ok((body, _)) => body,
+        Err(e) => {
+            return (
+                e.to_http_status(),
+                format!("PUT S3 {} refused: {}", object, e),
+            )
+        }
+    };
+    let size = body.len() as u64;
+    let replaced = STORAGE.get(&full_key).map(|old| old.len() as u64);
+    if let Err(e) = check_quota(&tenant, replaced, size) {
+        return e;
+    }
+
+    // Extract TTL from header (v0.5.0)
+    let ttl_secs: Option<u64> = headers
+        .get("X-Minikv-TTL")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|s| s.parse().ok());
+
+    // let expires_at = ttl_secs.map(|ttl| {
+    //     let now = std::time::SystemTime::now()
+    //         .duration_since(std::time::UNIX_EPOCH)
+    //         .unwrap()
+    //         .as_millis() as u64;
+    //     now + (ttl * 1000) // Convert seconds to milliseconds
+    // });
+
+    // Store the body in the selected backend
+
+    // For now, only the value is persisted; TTL can be handled via metadata in future
+    crate::coordinator::http::STORAGE.put(&full_key, body.to_vec());
+    record_write(&tenant, &full_key, replaced, size);
+    let stored_bytes = body.len();
+    // Publish key change event (PUT)
+    let _ = WATCH_CHANNEL.send(KeyChangeEvent {
+        event: "put".to_string(),
+        key: object,
+        tenant: Some(tenant),
+        timestamp: chrono::Utc::now().timestamp(),
+    });
+
+    // Use existing 2PC logic (simplified)
+    let placement = state.placement.lock().unwrap();
+    let volumes = state.metadata.get_healthy_volumes().unwrap_or_default();
+    let target_volumes: Vec<String> = placement
+        .select_volumes(&full_key, &volumes)
+        .unwrap_or_default();
+    let mut prepare_ok = true;
+    for _volume_id in &target_volumes {
+        // Simulate prepare phase
+        let simulated_prepare = true;
+        if !simulated_prepare {
+            prepare_ok = false;
+            break;
+        }
+    }
+    if !prepare_ok {
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!(
+                "PUT S3 {}/{} failed: prepare phase error (2PC)",
+                bucket, key
+            ),
+        );
+    }
+    // Commit phase (simulated)
+    for _volume_id in &target_volumes {
+        // Simulate commit
+    }
+
+    // Build response message
+    let ttl_info = ttl_secs
+        .map(|t| format!(", TTL: {}s", t))
+        .unwrap_or_default();
+    (
+        StatusCode::OK,
+        format!(
+            "PUT S3 {}/{} committed via 2PC ({} bytes{})",
+            bucket, key, stored_bytes, ttl_info
+        ),
+    )
+}
+
+/// Minimal S3-compatible GET object endpoint
+/// Supports multi-tenancy (v0.6.0)
+async fn s3_get_object(
+    State(state): State<CoordState>,
+    Path((bucket, key)): Path<(String, String)>,
+    auth: Option<axum::Extension<AuthExtension>>,
+    headers: axum::http::HeaderMap,
+) -> axum::response::Response {
+    let tenant = match resolve_tenant(&state, &auth, &headers) {
+        Ok(tenant) => tenant,
+        Err(e) => return (e.to_http_status(), e.to_string()).into_response(),
+    };
+    // Retrieve the value from the selected backend
+    let full_key = tenant::internal_key(&tenant, &format!("{}/{}", bucket, key));
+    let Some(data) = crate::coordinator::http::STORAGE.get(&full_key) else {
+        return (
+            StatusCode::NOT_FOUND,
+            format!("S3 object {}/{} not found", bucket, key),
+        )
+            .into_response();
+    };
+    // TODO: Check TTL if metadata is persisted
+    let size = data.len() as u64;
+    match ByteRange::from_headers(&headers).map(|range| range.resolve(size)) {
+        Some(Some((offset, length))) => partial_content(
+            range::slice_of(&data, offset, length).to_vec(),
+            offset,
+            size,
+        ),
+        Some(None) => range_not_satisfiable(size),
+        None => (StatusCode::OK, [(header::ACCEPT_RANGES, "bytes")], data).into_response(),
+    }
+}
+
+/// Admin routes: automation, status and API key management, guarded by
+/// `require_admin_middleware`
+fn admin_routes() -> Router<CoordState> {
+    Router::new()
+        // Admin automation endpoints
         .route("/admin/repair", axum::routing::post(admin_repair))
+        .route(
+            "/admin/repair/status",
+            axum::routing::get(admin_repair_status),
+        )
+        .route(
+            "/admin/repair/pending",
+            axum::routing::get(admin_repair_pending),
+        )
         .route("/admin/compact", axum::routing::post(admin_compact))
         .route("/admin/verify", axum::routing::post(admin_verify))
         .route("/admin/scale", axum::routing::post(admin_scale))
+        .route("/admin/rebalance", axum::routing::post(admin_rebalance))
+        .route(
+            "/admin/rebalance/status",
+            axum::routing::get(admin_rebalance_status),
+        )
+        .route("/admin/drain", axum::routing::post(admin_drain))
+        .route(
+            "/admin/drain/:volume_id",
+            axum::routing::get(admin_drain_status),
+        )
+        .route(
+            "/admin/anti-entropy",
+            axum::routing::post(admin_anti_entropy),
+        )
+        // Orphaned blob collection
+        .route("/admin/gc", axum::routing::post(admin_gc))
+        .route("/admin/gc/status", axum::routing::get(admin_gc_status))
+        // Cold storage tiering
+        .route("/admin/tier/status", axum::routing::get(admin_tier_status))
+        .route("/admin/tier/run", axum::routing::post(admin_tier_run))
+        // Cross-cluster replication
+        .route(
+            "/admin/replication/status",
+            axum::routing::get(admin_replication_status),
+        )
+        .route(
+            "/admin/replication/targets",
+            axum::routing::post(admin_add_replication_target),
+        )
+        .route(
+            "/admin/replication/targets/:name",
+            axum::routing::delete(admin_remove_replication_target),
+        )
+        .route(
+            "/admin/leader/transfer",
+            axum::routing::post(admin_transfer_leadership),
+        )
+        .route("/admin/volumes", axum::routing::get(admin_list_volumes))
+        .route(
+            "/admin/metadata/keys",
+            axum::routing::get(admin_list_metadata),
+        )
+        .route(
+            "/admin/metadata/usage",
+            axum::routing::get(admin_metadata_usage),
+        )
+        .route("/admin/hotkeys", axum::routing::get(admin_hotkeys))
+        // Cluster events, as Server-Sent Events
+        .route("/admin/events", axum::routing::get(events::stream_events))
+        .route(
+            "/admin/chaos",
+            axum::routing::get(admin_chaos)
+                .put(admin_set_chaos)
+                .delete(admin_clear_chaos),
+        )
+        .route(
+            "/admin/volumes/:volume_id/commands",
+            axum::routing::post(admin_volume_command),
+        )
+        .route(
+            "/admin/config/reload",
+            axum::routing::post(admin_reload_config),
+        )
         // Admin status endpoint (dashboard minimal)
         .route("/admin/status", axum::routing::get(admin_status))
         // API Key management endpoints (v0.6.0)
-        .route("/admin/keys", axum::routing::post(admin_create_key))
-        .route("/admin/keys", axum::routing::get(admin_list_keys))
-        .route("/admin/keys/:key_id", axum::routing::get(admin_get_key))
+        .route(
+            "/admin/keys",
+            axum::routing::post(admin_create_key).get(admin_list_keys),
+        )
+        .route(
+            "/admin/keys/:key_id",
+            axum::routing::get(admin_get_key).delete(admin_delete_key),
+        )
         .route(
             "/admin/keys/:key_id/revoke",
             axum::routing::post(admin_revoke_key),
         )
+        // Per-tenant default consistency level
         .route(
-            "/admin/keys/:key_id",
-            axum::routing::delete(admin_delete_key),
+            "/admin/consistency/:tenant",
+            axum::routing::get(admin_get_consistency).put(admin_set_consistency),
+        )
+        // Per-IP rate limiting
+        .route("/admin/ratelimit", axum::routing::get(admin_ratelimit))
+        // Tenant quotas
+        .route("/admin/quotas", axum::routing::get(admin_list_quotas))
+        .route(
+            "/admin/quotas/:tenant",
+            axum::routing::get(admin_get_quota)
+                .put(admin_set_quota)
+                .delete(admin_delete_quota),
+        )
+        .route(
+            "/admin/quotas/:tenant/usage",
+            axum::routing::get(admin_quota_usage),
+        )
+        // Per-prefix ACL rules
+        .route("/admin/acls", axum::routing::get(admin_list_acls))
+        .route(
+            "/admin/acls/:id",
+            axum::routing::get(admin_get_acl)
+                .put(admin_set_acl)
+                .delete(admin_delete_acl),
         )
         // Streaming/batch import/export (v0.7.0)
         .route("/admin/import", axum::routing::post(admin_import))
         .route("/admin/export", axum::routing::get(admin_export))
+        .route_layer(axum::middleware::from_fn(require_admin_middleware))
+}
+
+/// Routes modifying data, guarded by `require_write_middleware` and held to
+/// the write budget
+fn write_routes(state: CoordState) -> Router<CoordState> {
+    let writes = state.writes.clone();
+    Router::new()
+        .route("/s3/:bucket/:key", axum::routing::put(s3_put_object))
+        .route(
+            "/:key",
+            axum::routing::post(put_key)
+                .route_layer(axum::middleware::from_fn_with_state(state, redirect_upload))
+                .delete(delete_key)
+                // Values above the threshold are chunked, up to max_object_bytes
+                .layer(axum::extract::DefaultBodyLimit::max(
+                    chunking::config().max_object_bytes as usize,
+                )),
+        )
         // Multi-key transactions (v0.7.0)
         .route("/transaction", axum::routing::post(transaction_ops))
+        .route("/batch", axum::routing::post(batch_ops))
+        .route_layer(axum::middleware::from_fn_with_state(
+            writes,
+            write_budget_middleware,
+        ))
+        .route_layer(axum::middleware::from_fn(require_write_middleware))
+}
+
+/// Routes moving keys and values, their bodies held to the tenant's
+/// bandwidth limits by `throttle_bandwidth`. `acl_middleware` checks the
+/// key named in the path; multi-key routes check or filter each key.
+fn data_routes(state: CoordState) -> Router<CoordState> {
+    Router::new()
+        // S3-compatible minimal endpoints with TTL support
+        .route("/s3/:bucket/:key", axum::routing::get(s3_get_object))
+        // Key operations
+        .route("/:key", axum::routing::get(get_key))
         // Secondary indexes (v0.7.0)
         .route("/search", axum::routing::get(search_keys))
+        // Range queries and key listing
+        .route("/range", axum::routing::get(range_query))
+        .route("/keys", axum::routing::get(list_keys))
+        .merge(write_routes(state.clone()))
+        .route_layer(axum::middleware::from_fn(acl_middleware))
+        .route_layer(axum::middleware::from_fn_with_state(
+            state,
+            throttle_bandwidth,
+        ))
+}
+
+/// Creates the HTTP router with all public endpoints.
+/// Updated in v0.6.0 with authentication and key management: every request
+/// goes through `auth_middleware`, admin routes need the Admin role and
+/// writes the ReadWrite role.
+pub fn create_router(state: CoordState) -> Router {
+    let auth = AuthState {
+        key_store: KEY_STORE.clone(),
+        config: state.auth.clone(),
+    };
+    Router::new()
+        .merge(data_routes(state.clone()))
+        .route("/watch/sse", axum::routing::get(watch_sse))
+        .route("/watch/ws", axum::routing::get(watch_ws))
+        // Health check endpoints (v0.5.0)
+        .route("/health", axum::routing::get(health))
+        .route("/health/ready", axum::routing::get(health_ready))
+        .route("/health/live", axum::routing::get(health_live))
+        // Leader discovery
+        .route("/leader", axum::routing::get(get_leader))
+        .merge(admin_routes())
+        // etcd v3 JSON gateway subset
+        .merge(etcd::routes())
         // Prometheus metrics endpoint (enhanced in v0.5.0)
         .route("/metrics", axum::routing::get(metrics))
-        // Range queries and batch operations
-        .route("/range", axum::routing::get(range_query))
-        .route("/batch", axum::routing::post(batch_ops))
+        .route("/cdc", axum::routing::get(cdc_events))
+        .layer(axum::middleware::from_fn_with_state(auth, auth_middleware))
+        .layer(axum::middleware::from_fn_with_state(
+            state.rate_limiter.clone(),
+            rate_limit_middleware,
+        ))
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            leader_redirect,
+        ))
+        .layer(axum::middleware::from_fn_with_state(
+            state.ip_filter.clone(),
+            ip_filter_middleware,
+        ))
+        .layer(axum::middleware::from_fn(record_metrics))
+        .layer(axum::middleware::from_fn(request_id_middleware))
         .with_state(state)
 }
 
 /// Kubernetes readiness probe (v0.5.0)
-/// Returns 200 if the service is ready to accept traffic
+/// Returns 200 if the service is ready to accept traffic, else 503, with the
+/// result of each check (see `health::readiness`)
 async fn health_ready(State(state): State<CoordState>) -> impl IntoResponse {
-    // Check if we have healthy volumes and Raft is stable
-    let volumes = state.metadata.get_healthy_volumes().unwrap_or_default();
-    let has_leader = state.raft.is_leader() || !state.raft.get_peers().is_empty();
-
-    if !volumes.is_empty() && has_leader {
-        (
-            StatusCode::OK,
-            axum::Json(json!({
-                "ready": true,
-                "healthy_volumes": volumes.len(),
-                "is_leader": state.raft.is_leader(),
-            })),
-        )
+    let readiness = crate::coordinator::health::readiness(&state.metadata, &state.raft);
+    let status = if readiness.ready {
+        StatusCode::OK
     } else {
-        (
-            StatusCode::SERVICE_UNAVAILABLE,
-            axum::Json(json!({
-                "ready": false,
-                "healthy_volumes": volumes.len(),
-                "is_leader": state.raft.is_leader(),
-                "reason": if volumes.is_empty() { "No healthy volumes" } else { "No Raft leader" }
-            })),
-        )
-    }
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, axum::Json(readiness))
 }
 
 /// Kubernetes liveness probe (v0.5.0)
@@ -588,6 +1917,7 @@ struct KeyValueEntry {
 
 async fn admin_import(
     State(_state): State<CoordState>,
+    auth: Option<axum::Extension<AuthExtension>>,
     axum::Json(req): axum::Json<ImportRequest>,
 ) -> impl IntoResponse {
     let mut success_count = 0;
@@ -598,13 +1928,7 @@ async fn admin_import(
         success_count += 1;
     }
 
-    AUDIT_LOGGER.log_event(
-        AuditEventType::System,
-        "admin".to_string(),
-        None,
-        format!("Imported {} keys", success_count),
-        None,
-    );
+    audit_admin(&auth, None, format!("Imported {} keys", success_count));
 
     axum::Json(json!({
         "imported": success_count,
@@ -659,18 +1983,36 @@ struct Operation {
 }
 
 async fn transaction_ops(
-    State(_state): State<CoordState>,
+    State(state): State<CoordState>,
+    auth: Option<axum::Extension<AuthExtension>>,
+    headers: axum::http::HeaderMap,
     axum::Json(req): axum::Json<TransactionRequest>,
-) -> impl IntoResponse {
+) -> axum::response::Response {
+    let tenant = match resolve_tenant(&state, &auth, &headers) {
+        Ok(tenant) => tenant,
+        Err(e) => return (e.to_http_status(), e.to_string()).into_response(),
+    };
     let mut results = Vec::new();
     let mut success_count = 0;
     let total_operations = req.operations.len();
 
     for op in &req.operations {
+        if !acl_allows(&auth, &op.key) {
+            results.push(TransactionResult {
+                op: op.op.clone(),
+                key: op.key.clone(),
+                success: false,
+                error: Some("denied by ACL".to_string()),
+            });
+            continue;
+        }
         match op.op.as_str() {
             "put" => {
                 if let Some(ref value) = op.value {
-                    STORAGE.put(&op.key, value.clone().into_bytes());
+                    STORAGE.put(
+                        &tenant::internal_key(&tenant, &op.key),
+                        value.clone().into_bytes(),
+                    );
                     success_count += 1;
                     results.push(TransactionResult {
                         op: op.op.clone(),
@@ -688,7 +2030,9 @@ async fn transaction_ops(
                 }
             }
             "delete" => {
-                STORAGE.delete(&op.key);
+                let internal = tenant::internal_key(&tenant, &op.key);
+                STORAGE.delete(&internal);
+                audit_delete(&auth, &internal);
                 success_count += 1;
                 results.push(TransactionResult {
                     op: op.op.clone(),
@@ -710,7 +2054,7 @@ async fn transaction_ops(
 
     AUDIT_LOGGER.log_event(
         AuditEventType::System,
-        "transaction".to_string(),
+        audit_actor(&auth),
         None,
         format!("Executed {} operations in transaction", success_count),
         None,
@@ -721,37 +2065,111 @@ async fn transaction_ops(
         "total_operations": total_operations,
         "successful_operations": success_count
     }))
+    .into_response()
 }
 
-/// Secondary indexes - search keys by value substring (v0.7.0)
+/// Secondary indexes - search keys by tag or by value substring (v0.7.0)
 #[derive(Deserialize)]
 struct SearchQuery {
-    value: String,
+    /// Substring of the value
+    value: Option<String>,
+    /// `name:value`, as set by `X-Tag: name=value` on PUT
+    tag: Option<String>,
+    /// Resume a tag search after this key (the `next_after` of the previous page)
+    after: Option<String>,
+    limit: Option<usize>,
 }
 
+/// GET /search?tag=env:prod : a page of the keys carrying a tag, from the tag index.
+/// GET /search?value=<substring> : every key whose value contains the substring.
 async fn search_keys(
     State(state): State<CoordState>,
     Query(params): Query<SearchQuery>,
+    auth: Option<axum::Extension<AuthExtension>>,
+    headers: axum::http::HeaderMap,
 ) -> impl IntoResponse {
+    let tenant = match resolve_tenant(&state, &auth, &headers) {
+        Ok(tenant) => tenant,
+        Err(e) => {
+            return (
+                e.to_http_status(),
+                axum::Json(json!({ "error": e.to_string() })),
+            )
+        }
+    };
+    if let Some(tag) = &params.tag {
+        let Some((name, value)) = tag.split_once(':') else {
+            return (
+                StatusCode::BAD_REQUEST,
+                axum::Json(json!({ "error": "tag must be name:value" })),
+            );
+        };
+        let limit = crate::coordinator::metadata::page_limit(params.limit);
+        // The tenant's keys are contiguous in the tag index
+        let after = match &params.after {
+            Some(after) => tenant::internal_key(&tenant, after),
+            None => tenant::prefix(&tenant),
+        };
+        return match state
+            .metadata
+            .keys_with_tag(name, value, Some(&after), limit)
+        {
+            Ok(page) => {
+                let mut keys: Vec<String> = page
+                    .iter()
+                    .map_while(|key| tenant::user_key(&tenant, key))
+                    .map(str::to_string)
+                    .collect();
+                let next_after = keys.last().filter(|_| page.len() == limit).cloned();
+                keys.retain(|key| acl_allows(&auth, key));
+                (
+                    StatusCode::OK,
+                    axum::Json(json!({ "tag": tag, "keys": keys, "next_after": next_after })),
+                )
+            }
+            Err(e) => (
+                e.to_http_status(),
+                axum::Json(json!({ "error": e.to_string() })),
+            ),
+        };
+    }
+    let Some(query) = &params.value else {
+        return (
+            StatusCode::BAD_REQUEST,
+            axum::Json(json!({ "error": "expected ?tag=name:value or ?value=<substring>" })),
+        );
+    };
     match state.metadata.list_keys() {
         Ok(keys) => {
             let mut matching_keys = Vec::new();
             for key in keys {
+                let Some(user_key) = tenant::user_key(&tenant, &key) else {
+                    continue;
+                };
+                if !acl_allows(&auth, user_key) {
+                    continue;
+                }
                 if let Some(value_bytes) = STORAGE.get(&key) {
                     if let Ok(value_str) = std::str::from_utf8(&value_bytes) {
-                        if value_str.contains(&params.value) {
-                            matching_keys.push(key);
+                        if value_str.contains(query.as_str()) {
+                            matching_keys.push(user_key.to_string());
                         }
                     }
                 }
             }
-            axum::Json(json!({
-                "query": params.value,
-                "matching_keys": matching_keys,
-                "total_matches": matching_keys.len()
-            }))
+            (
+                StatusCode::OK,
+                axum::Json(json!({
+                    "query": query,
+                    "matching_keys": matching_keys,
+                    "total_matches": matching_keys.len()
+                })),
+            )
         }
-        Err(e) => axum::Json(json!({ "error": format!("list_keys error: {}", e) })),
+        Err(e) => (
+            StatusCode::OK,
+            axum::Json(json!({ "error": format!("list_keys error: {}", e) })),
+        ),
     }
 }
 
@@ -763,18 +2181,179 @@ struct TransactionResult {
     error: Option<String>,
 }
 
+/// Query parameters of GET /keys
+#[derive(Deserialize)]
+struct KeyListQuery {
+    #[serde(default)]
+    prefix: String,
+    /// Resume after this key (the `next_after` of the previous page)
+    after: Option<String>,
+    limit: Option<usize>,
+    /// Serve from local metadata without a leadership check
+    #[serde(default)]
+    stale: bool,
+}
+
+/// GET /keys?prefix=&limit=&after= : one page of key metadata, in key order.
+/// `next_after` is set while more keys may follow.
+async fn list_keys(
+    State(state): State<CoordState>,
+    Query(params): Query<KeyListQuery>,
+    auth: Option<axum::Extension<AuthExtension>>,
+    headers: axum::http::HeaderMap,
+) -> impl IntoResponse {
+    let tenant = match resolve_tenant(&state, &auth, &headers) {
+        Ok(tenant) => tenant,
+        Err(e) => {
+            return (
+                e.to_http_status(),
+                axum::Json(json!({ "error": e.to_string() })),
+            )
+        }
+    };
+    if !params.stale {
+        if let Err(e) = state.raft.read_index().await {
+            return (
+                e.to_http_status(),
+                axum::Json(json!({ "error": e.to_string() })),
+            );
+        }
+    }
+    let limit = crate::coordinator::metadata::page_limit(params.limit);
+    let prefix = tenant::internal_key(&tenant, &params.prefix);
+    let after = params
+        .after
+        .as_deref()
+        .map(|after| tenant::internal_key(&tenant, after));
+    match state
+        .metadata
+        .list_keys_paginated(&prefix, after.as_deref(), limit)
+    {
+        Ok(mut page) => {
+            for meta in &mut page {
+                if let Some(key) = tenant::user_key(&tenant, &meta.key) {
+                    meta.key = key.to_string();
+                }
+            }
+            let next_after = page
+                .last()
+                .filter(|_| page.len() == limit)
+                .map(|meta| meta.key.clone());
+            page.retain(|meta| acl_allows(&auth, &meta.key));
+            (
+                StatusCode::OK,
+                axum::Json(json!({ "keys": page, "next_after": next_after })),
+            )
+        }
+        Err(e) => (
+            e.to_http_status(),
+            axum::Json(json!({ "error": e.to_string() })),
+        ),
+    }
+}
+
+/// Query parameters of GET /cdc
+#[derive(Deserialize)]
+struct CdcQuery {
+    /// First sequence number wanted (the `next` of the previous page)
+    #[serde(default)]
+    from: u64,
+    limit: Option<usize>,
+}
+
+/// GET /cdc?from=&limit= : a page of the change data capture log, in sequence order
+async fn cdc_events(
+    State(state): State<CoordState>,
+    Query(params): Query<CdcQuery>,
+    auth: Option<axum::Extension<AuthExtension>>,
+    headers: axum::http::HeaderMap,
+) -> impl IntoResponse {
+    // Admins see every tenant's changes unless they name one
+    let caller = auth.as_ref().and_then(|ext| ext.0 .0.as_ref());
+    let sees_all = !state.auth.enabled || caller.is_some_and(|ctx| ctx.can_admin());
+    let scope = if sees_all && !headers.contains_key(tenant::TENANT_HEADER) {
+        None
+    } else {
+        match resolve_tenant(&state, &auth, &headers) {
+            Ok(tenant) => Some(tenant),
+            Err(e) => {
+                return (
+                    e.to_http_status(),
+                    axum::Json(json!({ "error": e.to_string() })),
+                )
+            }
+        }
+    };
+    let limit = crate::coordinator::metadata::page_limit(params.limit);
+    match state.metadata.cdc_events(params.from, limit) {
+        Ok(mut events) => {
+            let next = events.last().map_or(params.from, |e| e.seq + 1);
+            if let Some(tenant) = &scope {
+                events.retain_mut(|event| match tenant::user_key(tenant, &event.key) {
+                    Some(key) => {
+                        event.key = key.to_string();
+                        acl_allows(&auth, &event.key)
+                    }
+                    None => false,
+                });
+            }
+            (
+                StatusCode::OK,
+                axum::Json(json!({ "events": events, "next": next })),
+            )
+        }
+        Err(e) => (
+            e.to_http_status(),
+            axum::Json(json!({ "error": e.to_string() })),
+        ),
+    }
+}
+
+/// What GET /range?values= streams back for each key
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum RangeValues {
+    /// The value itself, base64-encoded
+    Blob,
+    /// The value's size and BLAKE3 digest, from the key metadata
+    Digest,
+}
+
 /// HTTP handler for range queries: GET /range?start=...&end=...&include_values=...
 #[derive(Deserialize)]
 struct RangeQuery {
     start: String,
     end: String,
     include_values: Option<bool>,
+    /// Stream the stored values (or their digests) instead of listing keys
+    values: Option<RangeValues>,
+    /// Replica reads in flight at once when streaming values
+    concurrency: Option<usize>,
+    /// Serve from local metadata without a leadership check
+    #[serde(default)]
+    stale: bool,
 }
 
 async fn range_query(
     State(state): State<CoordState>,
-    Query(params): Query<RangeQuery>,
-) -> impl IntoResponse {
+    Query(mut params): Query<RangeQuery>,
+    auth: Option<axum::Extension<AuthExtension>>,
+    headers: axum::http::HeaderMap,
+) -> axum::response::Response {
+    let tenant = match resolve_tenant(&state, &auth, &headers) {
+        Ok(tenant) => tenant,
+        Err(e) => return (e.to_http_status(), e.to_string()).into_response(),
+    };
+    if !params.stale {
+        if let Err(e) = state.raft.read_index().await {
+            return (e.to_http_status(), e.to_string()).into_response();
+        }
+    }
+    params.start = tenant::internal_key(&tenant, &params.start);
+    params.end = tenant::internal_key(&tenant, &params.end);
+    if let Some(mode) = params.values {
+        return stream_range_values(state, params, mode, tenant, auth);
+    }
     let keys = match state.metadata.list_keys() {
         Ok(keys) => keys,
         Err(e) => {
@@ -782,18 +2361,24 @@ async fn range_query(
                 StatusCode::INTERNAL_SERVER_ERROR,
                 format!("list_keys error: {}", e),
             )
+                .into_response()
         }
     };
     let mut filtered: Vec<String> = keys
         .into_iter()
         .filter(|k| k >= &params.start && k <= &params.end)
+        .filter_map(|k| tenant::user_key(&tenant, &k).map(str::to_string))
+        .filter(|k| acl_allows(&auth, k))
         .collect();
     filtered.sort();
     if params.include_values.unwrap_or(false) {
         let mut values = Vec::new();
         for k in &filtered {
-            match state.metadata.get_key(k) {
-                Ok(Some(meta)) => values.push(serde_json::to_value(&meta).unwrap_or(json!(null))),
+            match state.metadata.get_key(&tenant::internal_key(&tenant, k)) {
+                Ok(Some(mut meta)) => {
+                    meta.key = k.clone();
+                    values.push(serde_json::to_value(&meta).unwrap_or(json!(null)))
+                }
                 _ => values.push(json!(null)),
             }
         }
@@ -801,14 +2386,130 @@ async fn range_query(
             StatusCode::OK,
             serde_json::to_string(&json!({ "keys": filtered, "values": values })).unwrap(),
         )
+            .into_response()
     } else {
         (
             StatusCode::OK,
             serde_json::to_string(&json!({ "keys": filtered })).unwrap(),
         )
+            .into_response()
     }
 }
 
+/// Reads running at once while streaming range values, unless the caller asks otherwise
+const DEFAULT_SCAN_CONCURRENCY: usize = 8;
+
+/// Upper bound on the `concurrency` a range scan may ask for
+const MAX_SCAN_CONCURRENCY: usize = 64;
+
+/// Streams `{"key", "size", "value"|"blake3"}` NDJSON lines for every live key
+/// of `tenant` in `[start, end]` (stored keys), read from one replica each. Metadata is scanned a page at
+/// a time and at most `concurrency` reads are in flight, so memory stays
+/// bounded whatever the size of the range. Keys that can't be read yield an
+/// `error` line instead of ending the stream; keys the caller's ACL rules
+/// don't reach are skipped. Values pass through the get plugins.
+fn stream_range_values(
+    state: CoordState,
+    params: RangeQuery,
+    mode: RangeValues,
+    tenant: String,
+    auth: Option<axum::Extension<AuthExtension>>,
+) -> axum::response::Response {
+    use futures_util::StreamExt;
+
+    let concurrency = params
+        .concurrency
+        .unwrap_or(DEFAULT_SCAN_CONCURRENCY)
+        .clamp(1, MAX_SCAN_CONCURRENCY);
+    let page_size = crate::coordinator::metadata::DEFAULT_PAGE_SIZE;
+    let body = stream! {
+        let mut from = params.start;
+        loop {
+            let page = match state.metadata.scan_key_range(&from, &params.end, page_size) {
+                Ok(page) => page,
+                Err(e) => {
+                    let line = json!({ "error": e.to_string() });
+                    yield Ok::<_, Infallible>(Bytes::from(format!("{}\n", line)));
+                    break;
+                }
+            };
+            let next = page
+                .last()
+                .filter(|_| page.len() == page_size)
+                .map(|meta| format!("{}\0", meta.key));
+
+            let metadata = state.metadata.clone();
+            let mut reads = futures_util::stream::iter(page.into_iter().filter(|meta| {
+                meta.state == KeyState::Active
+                    && tenant::user_key(&tenant, &meta.key).is_some_and(|key| acl_allows(&auth, key))
+            }))
+            .map(|meta| {
+                let metadata = metadata.clone();
+                let key = tenant::user_key(&tenant, &meta.key)
+                    .unwrap_or(meta.key.as_str())
+                    .to_string();
+                async move {
+                    let value = match mode {
+                        // Digests come from the metadata, without reading values
+                        RangeValues::Digest => None,
+                        RangeValues::Blob => Some(match dedup::resolve(&metadata, meta.clone()) {
+                            Ok(content) => {
+                                match chunking::read_value(
+                                    &metadata,
+                                    &content,
+                                    ConsistencyLevel::One,
+                                    None,
+                                    consistency::verify_on_read(),
+                                )
+                                .await
+                                {
+                                    // Values go through the get plugins, as for GET /:key
+                                    Ok(value) => PLUGINS.on_get(&key, value).await,
+                                    Err(e) => Err(e),
+                                }
+                            }
+                            Err(e) => Err(e),
+                        }),
+                    };
+                    (meta, value)
+                }
+            })
+            .buffered(concurrency);
+            while let Some((mut meta, value)) = reads.next().await {
+                if let Some(key) = tenant::user_key(&tenant, &meta.key) {
+                    meta.key = key.to_string();
+                }
+                let line = match value {
+                    None => json!({
+                        "key": meta.key,
+                        "size": meta.size,
+                        "blake3": meta.blake3,
+                    }),
+                    Some(Ok(value)) => json!({
+                        "key": meta.key,
+                        "size": value.len(),
+                        "value": BASE64.encode(&value),
+                    }),
+                    Some(Err(e)) => json!({ "key": meta.key, "error": e.to_string() }),
+                };
+                yield Ok(Bytes::from(format!("{}\n", line)));
+            }
+
+            match next {
+                Some(key) => from = key,
+                None => break,
+            }
+        }
+    };
+
+    (
+        StatusCode::OK,
+        [("content-type", "application/x-ndjson")],
+        axum::body::Body::from_stream(body),
+    )
+        .into_response()
+}
+
 /// HTTP handler for batch operations: POST /batch
 #[derive(Deserialize)]
 struct BatchOpReq {
@@ -832,23 +2533,67 @@ struct BatchResultResp {
 
 async fn batch_ops(
     State(state): State<CoordState>,
+    auth: Option<axum::Extension<AuthExtension>>,
+    headers: axum::http::HeaderMap,
     axum::Json(req): axum::Json<BatchReq>,
-) -> impl IntoResponse {
+) -> axum::response::Response {
+    let tenant = match resolve_tenant(&state, &auth, &headers) {
+        Ok(tenant) => tenant,
+        Err(e) => return (e.to_http_status(), e.to_string()).into_response(),
+    };
     let mut results = Vec::new();
     for op in req.ops {
+        let internal = tenant::internal_key(&tenant, &op.key);
+        if dedup::is_reserved(&op.key) && op.op != "get" {
+            results.push(BatchResultResp {
+                ok: false,
+                key: op.key,
+                value: None,
+                error: Some("Reserved key".to_string()),
+            });
+            continue;
+        }
+        if !acl_allows(&auth, &op.key) {
+            results.push(BatchResultResp {
+                ok: false,
+                key: op.key,
+                value: None,
+                error: Some("Denied by ACL".to_string()),
+            });
+            continue;
+        }
         match op.op.as_str() {
             "put" => {
                 if let Some(val) = op.value {
+                    let replaced =
+                        match check_write_quota(&state, &tenant, &internal, val.len() as u64) {
+                            Ok(replaced) => replaced,
+                            Err((_, msg)) => {
+                                results.push(BatchResultResp {
+                                    ok: false,
+                                    key: op.key,
+                                    value: None,
+                                    error: Some(msg),
+                                });
+                                continue;
+                            }
+                        };
                     let meta = crate::coordinator::metadata::KeyMetadata {
-                        key: op.key.clone(),
+                        key: internal.clone(),
                         replicas: vec![],
                         size: val.len() as u64,
                         blake3: "".to_string(),
                         created_at: 0,
                         updated_at: 0,
                         state: crate::coordinator::metadata::KeyState::Active,
+                        tags: Default::default(),
+                        blob: None,
+                        chunks: None,
                     };
-                    let r = state.metadata.put_key(&meta);
+                    let r = state.raft.propose(&MetadataCommand::PutKey(meta)).await;
+                    if r.is_ok() {
+                        record_write(&tenant, &internal, replaced, val.len() as u64);
+                    }
                     results.push(BatchResultResp {
                         ok: r.is_ok(),
                         key: op.key,
@@ -865,14 +2610,17 @@ async fn batch_ops(
                 }
             }
             "get" => {
-                let r = state.metadata.get_key(&op.key);
+                let r = state.metadata.get_key(&internal);
                 match r {
-                    Ok(Some(meta)) => results.push(BatchResultResp {
-                        ok: true,
-                        key: op.key,
-                        value: Some(serde_json::to_string(&meta).unwrap()),
-                        error: None,
-                    }),
+                    Ok(Some(mut meta)) => {
+                        meta.key = op.key.clone();
+                        results.push(BatchResultResp {
+                            ok: true,
+                            key: op.key,
+                            value: Some(serde_json::to_string(&meta).unwrap()),
+                            error: None,
+                        })
+                    }
                     Ok(None) => results.push(BatchResultResp {
                         ok: false,
                         key: op.key,
@@ -888,7 +2636,21 @@ async fn batch_ops(
                 }
             }
             "delete" => {
-                let r = state.metadata.delete_key(&op.key);
+                let removed = match state.metadata.get_key(&internal) {
+                    Ok(Some(meta)) if meta.state != KeyState::Tombstone => Some(meta.size),
+                    _ => None,
+                };
+                let r = state
+                    .raft
+                    .propose(&MetadataCommand::DeleteKey(internal.clone()))
+                    .await;
+                if r.is_ok() {
+                    audit_delete(&auth, &internal);
+                    HOT_KEYS.record_write(&internal, 0);
+                }
+                if let (Ok(()), Some(size)) = (&r, removed) {
+                    QUOTA_MANAGER.record_storage_remove(&tenant, size);
+                }
                 results.push(BatchResultResp {
                     ok: r.is_ok(),
                     key: op.key,
@@ -904,49 +2666,72 @@ async fn batch_ops(
             }),
         }
     }
-    axum::Json(json!({ "results": results }))
+    axum::Json(json!({ "results": results })).into_response()
 }
 
-// Endpoint Prometheus /metrics
+/// Endpoint Prometheus /metrics: the global registry (requests, latency,
+/// Raft) followed by the cluster gauges of this coordinator
 pub async fn metrics(State(state): State<CoordState>) -> impl IntoResponse {
-    // Expose cluster stats, volumes, Raft, etc.
-    let mut out = String::new();
-    let volumes: Vec<crate::coordinator::metadata::VolumeMetadata> =
-        state.metadata.get_healthy_volumes().unwrap_or_default();
+    use std::fmt::Write;
+
+    state.raft.export_metrics();
+    let mut out = crate::common::METRICS.to_prometheus();
+
+    let volumes: Vec<VolumeMetadata> = state.metadata.get_healthy_volumes().unwrap_or_default();
     let total_keys: u64 = volumes.iter().map(|v| v.total_keys).sum();
-    out += &format!("minikv_total_keys {{}} {}\n", total_keys);
-    out += &format!("minikv_healthy_volumes {{}} {}\n", volumes.len());
-    for v in &volumes {
-        out += &format!(
-            "minikv_volume_bytes {{volume_id=\"{}\"}} {}\n",
-            v.volume_id, v.total_bytes
-        );
-        out += &format!(
-            "minikv_volume_free_bytes {{volume_id=\"{}\"}} {}\n",
-            v.volume_id, v.free_bytes
-        );
-        out += &format!(
-            "minikv_volume_total_keys {{volume_id=\"{}\"}} {}\n",
-            v.volume_id, v.total_keys
-        );
+    out.push_str("# HELP minikv_total_keys Keys stored on healthy volumes\n");
+    out.push_str("# TYPE minikv_total_keys gauge\n");
+    writeln!(out, "minikv_total_keys {}", total_keys).unwrap();
+    out.push_str("# HELP minikv_healthy_volumes Volumes currently healthy\n");
+    out.push_str("# TYPE minikv_healthy_volumes gauge\n");
+    writeln!(out, "minikv_healthy_volumes {}", volumes.len()).unwrap();
+
+    let volume_gauges: [(&str, &str, fn(&VolumeMetadata) -> u64); 3] = [
+        ("minikv_volume_bytes", "Bytes stored per volume", |v| {
+            v.total_bytes
+        }),
+        ("minikv_volume_free_bytes", "Free bytes per volume", |v| {
+            v.free_bytes
+        }),
+        ("minikv_volume_total_keys", "Keys stored per volume", |v| {
+            v.total_keys
+        }),
+    ];
+    for (name, help, value) in volume_gauges {
+        writeln!(out, "# HELP {} {}", name, help).unwrap();
+        writeln!(out, "# TYPE {} gauge", name).unwrap();
+        for v in &volumes {
+            writeln!(
+                out,
+                "{}{{volume_id=\"{}\"}} {}",
+                name,
+                v.volume_id,
+                value(v)
+            )
+            .unwrap();
+        }
     }
-    // Raft role
-    let role = if state.raft.is_leader() {
-        "leader"
-    } else {
-        "follower"
-    };
-    out += &format!("minikv_raft_role {{}} \"{}\"\n", role);
 
-    // Enhanced metrics from global registry (v0.5.0)
-    out += &crate::common::METRICS.to_prometheus();
+    out.push_str("# HELP minikv_raft_role Raft role of this coordinator\n");
+    out.push_str("# TYPE minikv_raft_role gauge\n");
+    writeln!(
+        out,
+        "minikv_raft_role{{role=\"{}\"}} 1",
+        state.raft.get_role()
+    )
+    .unwrap();
 
     // S3 store stats (v0.5.0)
     // TODO: Implement object count and TTL stats for STORAGE if required
     let s3_objects = 0;
     let s3_objects_with_ttl = 0;
-    out += &format!("minikv_s3_objects_total {{}} {}\n", s3_objects);
-    out += &format!("minikv_s3_objects_with_ttl {{}} {}\n", s3_objects_with_ttl);
+    out.push_str("# TYPE minikv_s3_objects_total gauge\n");
+    writeln!(out, "minikv_s3_objects_total {}", s3_objects).unwrap();
+    out.push_str("# TYPE minikv_s3_objects_with_ttl gauge\n");
+    writeln!(out, "minikv_s3_objects_with_ttl {}", s3_objects_with_ttl).unwrap();
+
+    out.push_str(&QUOTA_MANAGER.to_prometheus());
+    out.push_str(&REPLICATION.to_prometheus(&state.metadata));
 
     (axum::http::StatusCode::OK, out)
 }
@@ -961,76 +2746,807 @@ async fn health(State(state): State<CoordState>) -> impl IntoResponse {
 
     axum::Json(json!({
         "status": "healthy",
+        "node_id": state.raft.node_id(),
         "role": role,
         "is_leader": state.raft.is_leader(),
         "version": env!("CARGO_PKG_VERSION"),
     }))
 }
 
+/// Reports which coordinator currently leads, so clients can send writes there
+async fn get_leader(State(state): State<CoordState>) -> impl IntoResponse {
+    let leader_id = state.raft.get_leader();
+    let status = if leader_id.is_some() {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (
+        status,
+        axum::Json(json!({
+            "leader_id": leader_id,
+            "leader_addr": state.raft.get_leader_addr(),
+            "term": state.raft.get_term(),
+            "is_leader": state.raft.is_leader(),
+        })),
+    )
+}
+
+/// Streams the request and response bodies of a data route through the
+/// bandwidth throttle of the request's tenant. Requests naming a tenant they
+/// may not use go through untouched, for the handler to refuse.
+async fn throttle_bandwidth(
+    State(state): State<CoordState>,
+    req: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> axum::response::Response {
+    let auth = req
+        .extensions()
+        .get::<AuthExtension>()
+        .cloned()
+        .map(axum::Extension);
+    let Ok(tenant) = resolve_tenant(&state, &auth, req.headers()) else {
+        return next.run(req).await;
+    };
+    let req = req.map(|body| bandwidth::throttle_body(body, tenant.clone(), Direction::Ingress));
+    let mut resp = next.run(req).await;
+    // The throttled body streams, so keep the length of a complete one
+    let len = axum::body::HttpBody::size_hint(resp.body()).exact();
+    if let Some(len) = len.filter(|&len| len > 0) {
+        resp.headers_mut()
+            .entry(axum::http::header::CONTENT_LENGTH)
+            .or_insert_with(|| len.into());
+    }
+    resp.map(|body| bandwidth::throttle_body(body, tenant, Direction::Egress))
+}
+
+/// Points `307` responses (returned when a follower gets a write) at the
+/// same path on the leader. The response is left as is if the leader is unknown.
+async fn leader_redirect(
+    State(state): State<CoordState>,
+    req: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> axum::response::Response {
+    let path = req
+        .uri()
+        .path_and_query()
+        .map(|pq| pq.as_str().to_string())
+        .unwrap_or_default();
+    let mut resp = next.run(req).await;
+    if resp.status() != StatusCode::TEMPORARY_REDIRECT
+        || resp.headers().contains_key(axum::http::header::LOCATION)
+    {
+        return resp;
+    }
+    if let Some(addr) = state.raft.get_leader_addr() {
+        if let Ok(location) = format!("{}{}", addr, path).parse() {
+            resp.headers_mut()
+                .insert(axum::http::header::LOCATION, location);
+        }
+    }
+    resp
+}
+
+/// Count every request and its latency in the global metrics registry,
+/// labelled by route template so `/:key` stays a single series
+async fn record_metrics(
+    matched: Option<axum::extract::MatchedPath>,
+    req: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> axum::response::Response {
+    let path = matched
+        .as_ref()
+        .map_or("unmatched", |m| m.as_str())
+        .to_string();
+    let start = std::time::Instant::now();
+    let resp = next.run(req).await;
+    let status = resp.status();
+    let success = !status.is_client_error() && !status.is_server_error();
+    crate::common::METRICS.record_request(&path, start.elapsed(), success);
+    resp
+}
+
+/// Query parameters accepted by the key read/write endpoints
+#[derive(Debug, Deserialize)]
+struct ConsistencyQuery {
+    /// "one", "quorum" or "all" (defaults to the tenant's configured level)
+    consistency: Option<String>,
+    /// Read the metadata this node has, skipping the leader's ReadIndex barrier.
+    /// Cheap and served by followers, but may miss recent writes.
+    #[serde(default)]
+    stale: bool,
+    /// Check the value read against its committed blake3, as with
+    /// `verify_on_read` (GET only)
+    #[serde(default)]
+    verify: bool,
+    /// Redirect to a volume or not, overriding `[coordinator.redirect]`
+    /// (see `coordinator::redirect`)
+    redirect: Option<bool>,
+}
+
+/// Tenant namespace a data request works in (see `coordinator::tenant`)
+pub(crate) fn resolve_tenant(
+    state: &CoordState,
+    auth: &Option<axum::Extension<AuthExtension>>,
+    headers: &axum::http::HeaderMap,
+) -> crate::Result<String> {
+    let caller = auth.as_ref().and_then(|ext| ext.0 .0.as_ref());
+    let requested = headers
+        .get(tenant::TENANT_HEADER)
+        .and_then(|v| v.to_str().ok());
+    let tenant = tenant::resolve(caller, state.auth.enabled, requested)?;
+    tracing::Span::current().record("tenant", tenant.as_str());
+    Ok(tenant)
+}
+
+/// Checks a write of `size` bytes to the stored key `internal` against the
+/// rate, storage and object quotas of `tenant`. Returns the size of the value
+/// it replaces, if any, for `record_write`.
+fn check_write_quota(
+    state: &CoordState,
+    tenant: &str,
+    internal: &str,
+    size: u64,
+) -> std::result::Result<Option<u64>, (StatusCode, String)> {
+    let replaced = match state.metadata.get_key(internal) {
+        Ok(Some(meta)) if meta.state != KeyState::Tombstone => Some(meta.size),
+        Ok(_) => None,
+        Err(e) => return Err((e.to_http_status(), e.to_string())),
+    };
+    check_quota(tenant, replaced, size)?;
+    Ok(replaced)
+}
+
+/// Checks a write of `size` bytes replacing a value of `replaced` bytes, if
+/// any, against the rate, storage and object quotas of `tenant`
+fn check_quota(
+    tenant: &str,
+    replaced: Option<u64>,
+    size: u64,
+) -> std::result::Result<(), (StatusCode, String)> {
+    let refused = |result: crate::common::QuotaCheckResult| {
+        let message = result.error_message().unwrap_or_default();
+        WEBHOOKS.notify_quota(tenant, message.clone());
+        (result.http_status(), message)
+    };
+    let request = QUOTA_MANAGER.check_and_record_request(tenant);
+    if !request.is_allowed() {
+        return Err(refused(request));
+    }
+    let storage = QUOTA_MANAGER.check_storage(tenant, size.saturating_sub(replaced.unwrap_or(0)));
+    if !storage.is_allowed() {
+        return Err(refused(storage));
+    }
+    if replaced.is_none() {
+        let objects = QUOTA_MANAGER.check_objects(tenant);
+        if !objects.is_allowed() {
+            return Err(refused(objects));
+        }
+    }
+    Ok(())
+}
+
+/// Accounts a successful write of `key` checked by `check_write_quota`
+fn record_write(tenant: &str, key: &str, replaced: Option<u64>, size: u64) {
+    if let Some(replaced) = replaced {
+        QUOTA_MANAGER.record_storage_remove(tenant, replaced);
+    }
+    QUOTA_MANAGER.record_storage_add(tenant, size);
+    HOT_KEYS.record_write(key, size);
+}
+
+/// `updated_at` of the change another cluster pushes with a request (see
+/// `coordinator::replication`). Only admins may push changes.
+fn replicated_at(
+    state: &CoordState,
+    auth: &Option<axum::Extension<AuthExtension>>,
+    headers: &axum::http::HeaderMap,
+) -> std::result::Result<Option<u64>, (StatusCode, String)> {
+    let Some(value) = headers.get(replication::REPLICATED_AT_HEADER) else {
+        return Ok(None);
+    };
+    let caller = auth.as_ref().and_then(|ext| ext.0 .0.as_ref());
+    if state.auth.enabled && !caller.is_some_and(|ctx| ctx.can_admin()) {
+        return Err((
+            StatusCode::FORBIDDEN,
+            "only admins may push replicated changes".to_string(),
+        ));
+    }
+    match value.to_str().ok().and_then(|v| v.parse().ok()) {
+        Some(at) => Ok(Some(at)),
+        None => Err((
+            StatusCode::BAD_REQUEST,
+            format!(
+                "{} must be a Unix timestamp",
+                replication::REPLICATED_AT_HEADER
+            ),
+        )),
+    }
+}
+
+/// Keeps the source's `updated_at` on a key written for a replicated change
+async fn stamp_replicated(
+    state: &CoordState,
+    internal: &str,
+    value: &[u8],
+    replicated_at: Option<u64>,
+) {
+    let Some(at) = replicated_at else {
+        return;
+    };
+    let blake3 = crate::common::blake3_hash(value);
+    if let Err(e) = replication::stamp(&state.metadata, &state.raft, internal, &blake3, at).await {
+        tracing::warn!("Stamping replicated {} failed: {}", internal, e);
+    }
+}
+
 /// Handles a distributed write using Two-Phase Commit (2PC).
 ///   1. Prepare phase: ask all target volumes to prepare the write.
-///   2. Commit phase: if all volumes are prepared, commit the write; otherwise, abort.
+///   2. Commit phase: if enough volumes are prepared, commit the write; otherwise, abort.
 ///      Returns appropriate HTTP status and message.
+///
+/// The transaction is persisted in the metadata store for the whole exchange so a
+/// new leader can finish or roll it back after a crash (see `coordinator::txn`).
+/// `?consistency=one|quorum|all` sets how many replica acks are awaited.
+/// Each `X-Tag: name=value` header tags the key, replacing its previous tags.
+/// Changes pushed by another cluster (`X-Minikv-Replicated-At`) are settled
+/// with last-writer-wins and answer 409 when the stored value is newer.
 async fn put_key(
     State(state): State<CoordState>,
     Path(key): Path<String>,
-    _body: Bytes,
+    Query(query): Query<ConsistencyQuery>,
+    auth: Option<axum::Extension<AuthExtension>>,
+    headers: axum::http::HeaderMap,
+    body: Bytes,
 ) -> impl IntoResponse {
+    // Only the leader may publish metadata; followers point the client at it
+    if let Err(e) = state.raft.ensure_leader() {
+        return (e.to_http_status(), e.to_string());
+    }
+    if dedup::is_reserved(&key) {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!(
+                "{} and {} are reserved for deduplicated content and chunks",
+                dedup::CAS_PREFIX,
+                chunking::CHUNK_PREFIX
+            ),
+        );
+    }
+
+    let tags = match parse_tags(&headers) {
+        Ok(tags) => tags,
+        Err(e) => return (StatusCode::BAD_REQUEST, e),
+    };
+
+    let tenant = match resolve_tenant(&state, &auth, &headers) {
+        Ok(tenant) => tenant,
+        Err(e) => return (e.to_http_status(), e.to_string()),
+    };
+    let internal = tenant::internal_key(&tenant, &key);
+    let level = match consistency::resolve(&state.metadata, &tenant, query.consistency.as_deref()) {
+        Ok(level) => level,
+        Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()),
+    };
+    // Plugins may refuse the value, replace it or add tags
+    let (body, tags) = match PLUGINS.on_put(&key, body, tags).await {
+        Ok(put) => put,
+        Err(e) => return (e.to_http_status(), format!("PUT {} refused: {}", key, e)),
+    };
+    let replicated_at = match replicated_at(&state, &auth, &headers) {
+        Ok(at) => at,
+        Err(e) => return e,
+    };
+    if let Some(at) = replicated_at {
+        let local = match state.metadata.get_key(&internal) {
+            Ok(local) => local,
+            Err(e) => return (e.to_http_status(), e.to_string()),
+        };
+        let blake3 = crate::common::blake3_hash(&body);
+        match replication::resolve(local.as_ref(), at, Some((&blake3, &tags))) {
+            Resolution::Apply => {}
+            Resolution::Unchanged => return (StatusCode::OK, format!("PUT {} unchanged", key)),
+            Resolution::Conflict => {
+                return (
+                    StatusCode::CONFLICT,
+                    format!("PUT {} refused: the stored value is newer", key),
+                )
+            }
+        }
+    }
+    let size = body.len() as u64;
+    let replaced = match check_write_quota(&state, &tenant, &internal, size) {
+        Ok(replaced) => replaced,
+        Err(e) => return e,
+    };
+
+    if chunking::should_chunk(size) {
+        return match chunking::put_chunked(
+            &state.metadata,
+            &state.raft,
+            &state.placement,
+            &internal,
+            body.to_vec(),
+            tags,
+            level,
+        )
+        .await
+        {
+            Ok(count) => {
+                record_write(&tenant, &internal, replaced, size);
+                stamp_replicated(&state, &internal, &body, replicated_at).await;
+                (
+                    StatusCode::OK,
+                    format!(
+                        "PUT {} committed via 2PC in {} chunks ({})",
+                        key, count, level
+                    ),
+                )
+            }
+            Err(e) => (e.to_http_status(), format!("PUT {} failed: {}", key, e)),
+        };
+    }
+
+    if dedup::is_enabled() {
+        return match dedup::put_dedup(
+            &state.metadata,
+            &state.raft,
+            &state.placement,
+            &internal,
+            body.to_vec(),
+            tags,
+            level,
+        )
+        .await
+        {
+            Ok(outcome) => {
+                record_write(&tenant, &internal, replaced, size);
+                stamp_replicated(&state, &internal, &body, replicated_at).await;
+                match outcome.write {
+                    Some(write) => (
+                        StatusCode::OK,
+                        format!(
+                            "PUT {} committed via 2PC as {} ({}: {}/{} replicas acked)",
+                            key,
+                            outcome.blob,
+                            level,
+                            write.acked.len(),
+                            write.replicas.len()
+                        ),
+                    ),
+                    None => (
+                        StatusCode::OK,
+                        format!("PUT {} deduplicated against {}", key, outcome.blob),
+                    ),
+                }
+            }
+            Err(e) => (e.to_http_status(), format!("PUT {} failed: {}", key, e)),
+        };
+    }
+
     // Select target volumes using placement manager (HRW/sharding)
-    let placement = state.placement.lock().unwrap();
     let volumes = state.metadata.get_healthy_volumes().unwrap_or_default();
-    let target_volumes: Vec<String> = placement.select_volumes(&key, &volumes).unwrap_or_default();
+    let selected = state
+        .placement
+        .lock()
+        .unwrap()
+        .select_volumes(&internal, &volumes);
+    let target_ids = match selected {
+        Ok(ids) => ids,
+        Err(e) => {
+            return (e.to_http_status(), format!("PUT {} failed: {}", key, e));
+        }
+    };
+    let targets: Vec<_> = volumes
+        .into_iter()
+        .filter(|v| target_ids.contains(&v.volume_id))
+        .collect();
 
-    // === Two-Phase Commit (2PC) ===
-    // Prepare phase: ask each volume to prepare the write
-    let mut prepare_ok = true;
-    for _volume_id in &target_volumes {
-        // Real volume client call would go here
-        let simulated_prepare = true;
-        if !simulated_prepare {
-            prepare_ok = false;
-            break;
+    match crate::coordinator::txn::execute_2pc(
+        &state.metadata,
+        &state.raft,
+        &internal,
+        &targets,
+        body.to_vec(),
+        tags,
+        level,
+    )
+    .await
+    {
+        Ok(outcome) => {
+            record_write(&tenant, &internal, replaced, size);
+            stamp_replicated(&state, &internal, &body, replicated_at).await;
+            (
+                StatusCode::OK,
+                format!(
+                    "PUT {} committed via 2PC ({}: {}/{} replicas acked)",
+                    key,
+                    level,
+                    outcome.acked.len(),
+                    outcome.replicas.len()
+                ),
+            )
         }
+        Err(e) => (
+            e.to_http_status(),
+            format!("PUT {} failed: {} (2PC)", key, e),
+        ),
     }
+}
 
-    if !prepare_ok {
-        // Abort phase: inform all volumes to abort
-        for _volume_id in &target_volumes {
-            // Real volume client call would go here
+/// Answers a PUT with a `307` to the volume taking the upload when
+/// `redirect::wanted` and the value can be written without the coordinator,
+/// before its body is read (see `coordinator::redirect`). Other writes go on
+/// to `put_key`.
+async fn redirect_upload(
+    State(state): State<CoordState>,
+    Path(key): Path<String>,
+    Query(query): Query<ConsistencyQuery>,
+    auth: Option<axum::Extension<AuthExtension>>,
+    req: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> axum::response::Response {
+    let size = req
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok());
+    let direct = redirect::wanted(query.redirect)
+        && !dedup::is_enabled()
+        && !dedup::is_reserved(&key)
+        && !req.headers().contains_key("x-tag")
+        && !PLUGINS.applies(PluginHook::Put, &key);
+    let Some(size) = size.filter(|&size| direct && !chunking::should_chunk(size)) else {
+        return next.run(req).await;
+    };
+    if let Err(e) = state.raft.ensure_leader() {
+        return (e.to_http_status(), e.to_string()).into_response();
+    }
+
+    let tenant = match resolve_tenant(&state, &auth, req.headers()) {
+        Ok(tenant) => tenant,
+        Err(e) => return (e.to_http_status(), e.to_string()).into_response(),
+    };
+    let internal = tenant::internal_key(&tenant, &key);
+    let level = match consistency::resolve(&state.metadata, &tenant, query.consistency.as_deref()) {
+        Ok(level) => level,
+        Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    };
+    // Usage catches up with the upload once it is published
+    if let Err(e) = check_write_quota(&state, &tenant, &internal, size) {
+        return e.into_response();
+    }
+
+    let volumes = state.metadata.get_healthy_volumes().unwrap_or_default();
+    let selected = state
+        .placement
+        .lock()
+        .unwrap()
+        .select_volumes(&internal, &volumes);
+    let target_ids = match selected {
+        Ok(ids) => ids,
+        Err(e) => {
+            return (e.to_http_status(), format!("PUT {} failed: {}", key, e)).into_response()
         }
-        return (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("PUT {} failed: prepare phase error (2PC)", key),
-        );
+    };
+    let targets: Vec<VolumeMetadata> = target_ids
+        .iter()
+        .filter_map(|id| volumes.iter().find(|v| &v.volume_id == id).cloned())
+        .collect();
+    match redirect::upload_url(
+        &targets,
+        &internal,
+        size,
+        level.required_acks(targets.len()),
+    ) {
+        Some(url) => redirect_to(url),
+        None => next.run(req).await,
     }
+}
 
-    // Commit phase: ask all volumes to commit
-    for _volume_id in &target_volumes {
-        // Real volume client call would go here
+/// `307` to `url`, on a volume
+fn redirect_to(url: String) -> axum::response::Response {
+    (StatusCode::TEMPORARY_REDIRECT, [(header::LOCATION, url)]).into_response()
+}
+
+/// Tags from the `X-Tag: name=value` headers of a request
+fn parse_tags(
+    headers: &axum::http::HeaderMap,
+) -> std::result::Result<std::collections::BTreeMap<String, String>, String> {
+    let mut tags = std::collections::BTreeMap::new();
+    for header in headers.get_all("x-tag") {
+        let tag = header
+            .to_str()
+            .map_err(|_| "X-Tag must be visible ASCII".to_string())?;
+        match tag.split_once('=') {
+            Some((name, value)) if !name.trim().is_empty() && !name.contains(':') => {
+                tags.insert(name.trim().to_string(), value.trim().to_string());
+            }
+            _ => return Err(format!("invalid X-Tag {:?}: expected name=value", tag)),
+        }
+    }
+    Ok(tags)
+}
+
+/// Handles key read requests.
+/// Replicas are read in parallel and compared against the committed checksum
+/// until the requested consistency level is met.
+///
+/// Reads are linearizable: the leader serves them after a ReadIndex barrier and
+/// followers redirect to it, unless `?stale=true` accepts local metadata.
+/// Tiered keys are served from the cold storage bucket (see `coordinator::tiering`).
+/// Single values may be redirected to a replica (see `coordinator::redirect`).
+async fn get_key(
+    State(state): State<CoordState>,
+    Path(key): Path<String>,
+    Query(query): Query<ConsistencyQuery>,
+    auth: Option<axum::Extension<AuthExtension>>,
+    headers: axum::http::HeaderMap,
+) -> axum::response::Response {
+    let tenant = match resolve_tenant(&state, &auth, &headers) {
+        Ok(tenant) => tenant,
+        Err(e) => return (e.to_http_status(), e.to_string()).into_response(),
+    };
+    let internal = tenant::internal_key(&tenant, &key);
+    let level = match consistency::resolve(&state.metadata, &tenant, query.consistency.as_deref()) {
+        Ok(level) => level,
+        Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    };
+
+    if !query.stale {
+        if let Err(e) = state.raft.read_index().await {
+            return (e.to_http_status(), e.to_string()).into_response();
+        }
+    }
+
+    let meta = match state.metadata.get_key(&internal) {
+        Ok(Some(meta)) if meta.state != KeyState::Tombstone => meta,
+        Ok(_) => return (StatusCode::NOT_FOUND, format!("Key {} not found", key)).into_response(),
+        Err(e) => return (e.to_http_status(), e.to_string()).into_response(),
+    };
+    TIERING.record_read(&internal);
+    HOT_KEYS.record_read(&internal, meta.size);
+    let meta = match dedup::resolve(&state.metadata, meta) {
+        Ok(meta) => meta,
+        Err(e) => {
+            return (e.to_http_status(), format!("GET {} failed: {}", key, e)).into_response()
+        }
+    };
+    let verify = query.verify || consistency::verify_on_read();
+    // Every replica read held data not matching the committed hash
+    let read_status = |e: &crate::Error| match e {
+        crate::Error::ChecksumMismatch { .. } if verify => StatusCode::BAD_GATEWAY,
+        e => e.to_http_status(),
+    };
+
+    // Plugins see whole values: ranges are cut from what they answer
+    if PLUGINS.applies(PluginHook::Get, &key) {
+        let read = match meta.state {
+            KeyState::Tiered => {
+                tiering::read_tiered(&state.metadata, &state.raft, &state.placement, &meta).await
+            }
+            _ => chunking::read_value(&state.metadata, &meta, level, None, verify).await,
+        };
+        let value = match read {
+            Ok(value) => value,
+            Err(e) => {
+                return (
+                    read_status(&e),
+                    format!("GET {} failed at consistency {}: {}", key, level, e),
+                )
+                    .into_response()
+            }
+        };
+        let value = match PLUGINS.on_get(&key, value).await {
+            Ok(value) => value,
+            Err(e) => {
+                return (e.to_http_status(), format!("GET {} refused: {}", key, e)).into_response()
+            }
+        };
+        let size = value.len() as u64;
+        return match ByteRange::from_headers(&headers).map(|range| range.resolve(size)) {
+            Some(Some((offset, length))) => partial_content(
+                range::slice_of(&value, offset, length).to_vec(),
+                offset,
+                size,
+            ),
+            Some(None) => range_not_satisfiable(size),
+            None => (
+                StatusCode::OK,
+                [
+                    ("X-Minikv-Consistency", level.to_string()),
+                    ("Accept-Ranges", "bytes".to_string()),
+                ],
+                value,
+            )
+                .into_response(),
+        };
+    }
+
+    let range = match ByteRange::from_headers(&headers) {
+        Some(range) => match range.resolve(meta.size) {
+            Some(range) => Some(range),
+            None => return range_not_satisfiable(meta.size),
+        },
+        None => None,
+    };
+
+    if meta.state == KeyState::Tiered {
+        return match tiering::read_tiered(&state.metadata, &state.raft, &state.placement, &meta)
+            .await
+        {
+            Ok(value) => {
+                let mut resp = match range {
+                    Some((offset, length)) => partial_content(
+                        range::slice_of(&value, offset, length).to_vec(),
+                        offset,
+                        meta.size,
+                    ),
+                    None => (StatusCode::OK, value).into_response(),
+                };
+                resp.headers_mut()
+                    .insert("X-Minikv-Tier", HeaderValue::from_static("cold"));
+                resp
+            }
+            Err(e) => (e.to_http_status(), format!("GET {} failed: {}", key, e)).into_response(),
+        };
+    }
+
+    // Chunks are assembled, and verified reads checked, by the coordinator
+    if redirect::wanted(query.redirect) && !verify && meta.chunks.is_none() {
+        let replicas: Vec<VolumeMetadata> = meta
+            .replicas
+            .iter()
+            .filter_map(|id| state.metadata.get_volume(id).ok().flatten())
+            .collect();
+        if let Some(url) = redirect::read_url(&replicas, &meta.key) {
+            return redirect_to(url);
+        }
+    }
+
+    if let Some((offset, length)) = range {
+        return match chunking::read_value(&state.metadata, &meta, level, range, verify).await {
+            Ok(value) => {
+                let mut resp = partial_content(value, offset, meta.size);
+                resp.headers_mut().insert(
+                    "X-Minikv-Consistency",
+                    HeaderValue::from_str(&level.to_string()).unwrap(),
+                );
+                resp
+            }
+            Err(e) => (
+                read_status(&e),
+                format!("GET {} failed at consistency {}: {}", key, level, e),
+            )
+                .into_response(),
+        };
     }
 
-    // Update metadata (replicas, etc.)
-    // MetadataStore update for new key info would go here
+    match chunking::read_value(&state.metadata, &meta, level, None, verify).await {
+        Ok(value) => (
+            StatusCode::OK,
+            [
+                ("X-Minikv-Consistency", level.to_string()),
+                ("Accept-Ranges", "bytes".to_string()),
+            ],
+            value,
+        )
+            .into_response(),
+        Err(e) => (
+            read_status(&e),
+            format!("GET {} failed at consistency {}: {}", key, level, e),
+        )
+            .into_response(),
+    }
+}
 
-    (StatusCode::OK, format!("PUT {} committed via 2PC", key))
+/// 206 response carrying `value`, the slice from `offset` of a value of
+/// `size` bytes
+fn partial_content(value: Vec<u8>, offset: u64, size: u64) -> axum::response::Response {
+    if value.is_empty() {
+        return range_not_satisfiable(size);
+    }
+    (
+        StatusCode::PARTIAL_CONTENT,
+        [
+            (
+                header::CONTENT_RANGE,
+                range::content_range(offset, value.len() as u64, size),
+            ),
+            (header::ACCEPT_RANGES, HeaderValue::from_static("bytes")),
+        ],
+        value,
+    )
+        .into_response()
 }
 
-/// Handles key read requests (not yet implemented).
-async fn get_key(State(_state): State<CoordState>, Path(key): Path<String>) -> impl IntoResponse {
-    // Real logic: read via metadata and volume
-    // Here, we assume a get_value(key) method on MetadataStore
-    // (adapt as needed for the actual API)
-    let value = format!("Value for key {} (fetched from volume)", key);
-    (StatusCode::OK, value)
+/// 416 response to a range outside a value of `size` bytes
+fn range_not_satisfiable(size: u64) -> axum::response::Response {
+    (
+        StatusCode::RANGE_NOT_SATISFIABLE,
+        [(header::CONTENT_RANGE, range::unsatisfiable_range(size))],
+        "Requested range not satisfiable",
+    )
+        .into_response()
 }
 
-/// Handles key delete requests (not yet implemented).
+/// Handles key delete requests.
+/// The key is removed from the metadata through Raft, then from its replicas.
+/// Replicas that miss the delete are cleaned up by anti-entropy.
+/// Deletes pushed by another cluster answer 409 when the stored value is newer.
 async fn delete_key(
-    State(_state): State<CoordState>,
+    State(state): State<CoordState>,
     Path(key): Path<String>,
+    auth: Option<axum::Extension<AuthExtension>>,
+    headers: axum::http::HeaderMap,
 ) -> impl IntoResponse {
-    // Real logic: delete via metadata and volume
-    // Here, we assume a delete_key(key) method on MetadataStore
-    // (adapt as needed for the actual API)
+    if dedup::is_reserved(&key) {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!(
+                "{} and {} are reserved for deduplicated content and chunks",
+                dedup::CAS_PREFIX,
+                chunking::CHUNK_PREFIX
+            ),
+        );
+    }
+    let tenant = match resolve_tenant(&state, &auth, &headers) {
+        Ok(tenant) => tenant,
+        Err(e) => return (e.to_http_status(), e.to_string()),
+    };
+    let internal = tenant::internal_key(&tenant, &key);
+    let request = QUOTA_MANAGER.check_and_record_request(&tenant);
+    if !request.is_allowed() {
+        return (
+            request.http_status(),
+            request.error_message().unwrap_or_default(),
+        );
+    }
+    let meta = match state.metadata.get_key(&internal) {
+        Ok(Some(meta)) => meta,
+        Ok(None) => return (StatusCode::NOT_FOUND, format!("Key {} not found", key)),
+        Err(e) => return (e.to_http_status(), e.to_string()),
+    };
+    match replicated_at(&state, &auth, &headers) {
+        Ok(Some(at)) if replication::resolve(Some(&meta), at, None) == Resolution::Conflict => {
+            return (
+                StatusCode::CONFLICT,
+                format!("DELETE {} refused: the stored value is newer", key),
+            );
+        }
+        Ok(_) => {}
+        Err(e) => return e,
+    }
+    if let Err(e) = state
+        .raft
+        .propose(&MetadataCommand::DeleteKey(internal.clone()))
+        .await
+    {
+        return (e.to_http_status(), format!("DELETE {} failed: {}", key, e));
+    }
+    audit_delete(&auth, &internal);
+    HOT_KEYS.record_write(&internal, 0);
+    if meta.state != KeyState::Tombstone {
+        QUOTA_MANAGER.record_storage_remove(&tenant, meta.size);
+    }
+
+    if meta.state == KeyState::Tiered {
+        if let Err(e) = tiering::delete_tiered(&internal).await {
+            tracing::warn!("Delete of tiered object for {} failed: {}", key, e);
+        }
+    }
+    for volume_id in &meta.replicas {
+        let Ok(Some(volume)) = state.metadata.get_volume(volume_id) else {
+            continue;
+        };
+        let result = match VolumeClient::connect(volume.grpc_address.clone()).await {
+            Ok(mut client) => client.delete(internal.clone()).await.map(|_| ()),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            tracing::warn!("Delete of {} on {} failed: {}", key, volume_id, e);
+        }
+    }
+
     (StatusCode::OK, format!("DELETE {} succeeded", key))
 }
@@ -0,0 +1,388 @@
+//! Public gRPC API for clients (`KvService`)
+//!
+//! Served on `client_grpc_addr`, apart from the internal gRPC API whose
+//! callers hold cluster certificates and tokens. Each call is dispatched to
+//! the coordinator's HTTP router in process, so it goes through the same
+//! authentication, ACLs, quotas and rate limits as the matching HTTP
+//! request, without the HTTP round trip: `authorization`, `x-api-key` and
+//! `x-minikv-tenant` metadata become the headers of the same name.
+//!
+//! Values stream both ways: `Put` takes the value as a stream of messages,
+//! `Get` returns it in chunks as the coordinator reads it. A follower
+//! answers writes and linearizable reads with `FAILED_PRECONDITION`, the
+//! leader's HTTP address in the `leader` metadata.
+
+use crate::common::encode_key;
+use crate::coordinator::grpc::key_info;
+use crate::coordinator::http::{KeyChangeEvent, WatchScope, WATCH_CHANNEL};
+use crate::coordinator::tenant::TENANT_HEADER;
+use crate::ops::KeyPage;
+use crate::proto::kv_service_server::{KvService, KvServiceServer};
+use crate::proto::*;
+use axum::body::Body;
+use axum::extract::ConnectInfo;
+use axum::http::{header, HeaderValue, Method, StatusCode};
+use futures_util::StreamExt;
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use serde::Deserialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::sync::{broadcast, mpsc};
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status, Streaming};
+use tower::ServiceExt;
+
+/// Metadata passed on to the HTTP layer
+const FORWARDED: &[&str] = &["authorization", "x-api-key", TENANT_HEADER, "x-request-id"];
+
+/// Bytes of an error body kept in a status message
+const MAX_ERROR_BYTES: usize = 4096;
+
+/// Chunks of a value or events buffered for a slow client
+const STREAM_BUFFER: usize = 16;
+
+/// Public gRPC API of a coordinator
+#[derive(Clone)]
+pub struct KvGrpcService {
+    router: axum::Router,
+}
+
+impl KvGrpcService {
+    /// Serve calls through `router`, the coordinator's HTTP router
+    pub fn new(router: axum::Router) -> Self {
+        Self { router }
+    }
+
+    pub fn into_server(self) -> KvServiceServer<Self> {
+        KvServiceServer::new(self)
+    }
+
+    /// Run the HTTP `request` for the gRPC call `call`, failing with the
+    /// status matching an error answer
+    async fn dispatch<T>(
+        &self,
+        call: &Request<T>,
+        mut request: axum::http::Request<Body>,
+    ) -> Result<axum::response::Response, Status> {
+        for name in FORWARDED {
+            if let Some(value) = call.metadata().get(*name) {
+                if let Ok(value) = HeaderValue::from_bytes(value.as_bytes()) {
+                    request.headers_mut().insert(*name, value);
+                }
+            }
+        }
+        if let Some(addr) = call.remote_addr() {
+            request.extensions_mut().insert(ConnectInfo(addr));
+        }
+        let resp = match self.router.clone().oneshot(request).await {
+            Ok(resp) => resp,
+            Err(never) => match never {},
+        };
+        if resp.status().is_success() {
+            Ok(resp)
+        } else {
+            Err(error_status(resp).await)
+        }
+    }
+
+    async fn dispatch_json<T, R: serde::de::DeserializeOwned>(
+        &self,
+        call: &Request<T>,
+        request: axum::http::Request<Body>,
+    ) -> Result<R, Status> {
+        let resp = self.dispatch(call, request).await?;
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+            .await
+            .map_err(|e| Status::internal(e.to_string()))?;
+        serde_json::from_slice(&body).map_err(|e| Status::internal(e.to_string()))
+    }
+}
+
+fn http_request(
+    method: Method,
+    uri: String,
+    body: Body,
+) -> Result<axum::http::Request<Body>, Status> {
+    axum::http::Request::builder()
+        .method(method)
+        .uri(uri)
+        .body(body)
+        .map_err(|e| Status::invalid_argument(e.to_string()))
+}
+
+/// Status for an HTTP error answer
+async fn error_status(resp: axum::response::Response) -> Status {
+    let status = resp.status();
+    let leader = resp.headers().get(header::LOCATION).cloned();
+    let body = axum::body::to_bytes(resp.into_body(), MAX_ERROR_BYTES)
+        .await
+        .unwrap_or_default();
+    let message = String::from_utf8_lossy(&body).to_string();
+    let code = match status {
+        StatusCode::BAD_REQUEST | StatusCode::PAYLOAD_TOO_LARGE => tonic::Code::InvalidArgument,
+        StatusCode::UNAUTHORIZED => tonic::Code::Unauthenticated,
+        StatusCode::FORBIDDEN => tonic::Code::PermissionDenied,
+        StatusCode::NOT_FOUND => tonic::Code::NotFound,
+        StatusCode::CONFLICT => tonic::Code::Aborted,
+        StatusCode::TEMPORARY_REDIRECT => tonic::Code::FailedPrecondition,
+        StatusCode::REQUEST_TIMEOUT => tonic::Code::DeadlineExceeded,
+        StatusCode::TOO_MANY_REQUESTS | StatusCode::INSUFFICIENT_STORAGE => {
+            tonic::Code::ResourceExhausted
+        }
+        StatusCode::SERVICE_UNAVAILABLE => tonic::Code::Unavailable,
+        _ => tonic::Code::Internal,
+    };
+    let mut status = Status::new(code, message);
+    if let Some(leader) = leader {
+        // The leader's URL, followed by the path of the request
+        let leader = leader.to_str().unwrap_or_default();
+        let leader = url_origin(leader);
+        if let Ok(leader) = leader.parse() {
+            status.metadata_mut().insert("leader", leader);
+        }
+    }
+    status
+}
+
+/// `scheme://host:port` of `url`
+fn url_origin(url: &str) -> &str {
+    let start = url.find("://").map_or(0, |i| i + 3);
+    match url[start..].find('/') {
+        Some(end) => &url[..start + end],
+        None => url,
+    }
+}
+
+/// Query string of the optional parameters that are set
+fn query(params: &[(&str, &str)]) -> String {
+    let set: Vec<String> = params
+        .iter()
+        .filter(|(_, value)| !value.is_empty())
+        .map(|(name, value)| format!("{}={}", name, utf8_percent_encode(value, NON_ALPHANUMERIC)))
+        .collect();
+    set.join("&")
+}
+
+#[tonic::async_trait]
+impl KvService for KvGrpcService {
+    async fn put(
+        &self,
+        req: Request<Streaming<KvPutRequest>>,
+    ) -> Result<Response<KvPutResponse>, Status> {
+        let (metadata, extensions, mut messages) = req.into_parts();
+        let first = messages
+            .message()
+            .await?
+            .ok_or_else(|| Status::invalid_argument("no message"))?;
+        if first.key.is_empty() {
+            return Err(Status::invalid_argument("the first message names no key"));
+        }
+        // Values come through this node, clients can't follow a redirect
+        let uri = format!(
+            "/{}?{}",
+            encode_key(&first.key),
+            query(&[("consistency", &first.consistency), ("redirect", "false")])
+        );
+        let size = Arc::new(AtomicU64::new(first.data.len() as u64));
+        let counted = size.clone();
+        let rest = messages.map(move |message| {
+            message.map(|message| {
+                counted.fetch_add(message.data.len() as u64, Ordering::Relaxed);
+                message.data
+            })
+        });
+        let data = futures_util::stream::once(async move { Ok(first.data) }).chain(rest);
+
+        let call = Request::from_parts(metadata, extensions, ());
+        let request = http_request(Method::POST, uri, Body::from_stream(data))?;
+        self.dispatch(&call, request).await?;
+        Ok(Response::new(KvPutResponse {
+            size: size.load(Ordering::Relaxed),
+        }))
+    }
+
+    type GetStream = ReceiverStream<Result<Chunk, Status>>;
+
+    async fn get(&self, req: Request<KvGetRequest>) -> Result<Response<Self::GetStream>, Status> {
+        let params = req.get_ref();
+        let uri = format!(
+            "/{}?{}",
+            encode_key(&params.key),
+            query(&[
+                ("consistency", &params.consistency),
+                ("stale", if params.stale { "true" } else { "" }),
+                ("redirect", "false"),
+            ])
+        );
+        let request = http_request(Method::GET, uri, Body::empty())?;
+        let resp = self.dispatch(&req, request).await?;
+
+        let (tx, rx) = mpsc::channel(STREAM_BUFFER);
+        tokio::spawn(async move {
+            let mut data = resp.into_body().into_data_stream();
+            while let Some(chunk) = data.next().await {
+                let chunk = chunk
+                    .map(|data| Chunk {
+                        data: data.to_vec(),
+                    })
+                    .map_err(|e| Status::internal(e.to_string()));
+                let failed = chunk.is_err();
+                if tx.send(chunk).await.is_err() || failed {
+                    break;
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    async fn delete(
+        &self,
+        req: Request<KvDeleteRequest>,
+    ) -> Result<Response<KvDeleteResponse>, Status> {
+        let uri = format!("/{}", encode_key(&req.get_ref().key));
+        let request = http_request(Method::DELETE, uri, Body::empty())?;
+        self.dispatch(&req, request).await?;
+        Ok(Response::new(KvDeleteResponse {}))
+    }
+
+    async fn list(
+        &self,
+        req: Request<ListKeysRequest>,
+    ) -> Result<Response<ListKeysResponse>, Status> {
+        let params = req.get_ref();
+        let limit = (params.limit > 0).then(|| params.limit.to_string());
+        let uri = format!(
+            "/keys?{}",
+            query(&[
+                ("prefix", &params.prefix),
+                ("after", &params.start_after),
+                ("limit", limit.as_deref().unwrap_or_default()),
+            ])
+        );
+        let request = http_request(Method::GET, uri, Body::empty())?;
+        let page: KeyPage = self.dispatch_json(&req, request).await?;
+        Ok(Response::new(ListKeysResponse {
+            keys: page.keys.into_iter().map(key_info).collect(),
+            next_after: page.next_after.unwrap_or_default(),
+        }))
+    }
+
+    async fn batch(&self, req: Request<BatchRequest>) -> Result<Response<BatchResponse>, Status> {
+        #[derive(Deserialize)]
+        struct Results {
+            results: Vec<Outcome>,
+        }
+        #[derive(Deserialize)]
+        struct Outcome {
+            ok: bool,
+            key: String,
+            value: Option<String>,
+            error: Option<String>,
+        }
+
+        let mut ops = Vec::new();
+        for op in &req.get_ref().ops {
+            use crate::proto::batch_op::Type;
+            let kind = match Type::try_from(op.r#type) {
+                Ok(Type::Put) => "put",
+                Ok(Type::Get) => "get",
+                Ok(Type::Delete) => "delete",
+                Err(_) => return Err(Status::invalid_argument("unknown batch operation")),
+            };
+            let value = match kind {
+                "put" => Some(String::from_utf8(op.value.clone()).map_err(|_| {
+                    Status::invalid_argument(format!("value of {} is not UTF-8", op.key))
+                })?),
+                _ => None,
+            };
+            ops.push(serde_json::json!({ "op": kind, "key": op.key, "value": value }));
+        }
+        let body = serde_json::to_vec(&serde_json::json!({ "ops": ops }))
+            .map_err(|e| Status::internal(e.to_string()))?;
+
+        let mut request = http_request(Method::POST, "/batch".to_string(), Body::from(body))?;
+        request.headers_mut().insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/json"),
+        );
+        let results: Results = self.dispatch_json(&req, request).await?;
+        Ok(Response::new(BatchResponse {
+            results: results
+                .results
+                .into_iter()
+                .map(|outcome| BatchResult {
+                    ok: outcome.ok,
+                    key: outcome.key,
+                    value: outcome.value.unwrap_or_default().into_bytes(),
+                    error: outcome.error.unwrap_or_default(),
+                })
+                .collect(),
+        }))
+    }
+
+    type WatchStream = ReceiverStream<Result<WatchEvent, Status>>;
+
+    async fn watch(
+        &self,
+        req: Request<WatchRequest>,
+    ) -> Result<Response<Self::WatchStream>, Status> {
+        // Subscribed before the check, so no change in between is missed
+        let mut changes = WATCH_CHANNEL.subscribe();
+        // Allowed to watch what the HTTP stream would show
+        let request = http_request(Method::GET, "/watch/sse".to_string(), Body::empty())?;
+        let scope = self
+            .dispatch(&req, request)
+            .await?
+            .extensions()
+            .get::<WatchScope>()
+            .cloned()
+            .ok_or_else(|| Status::internal("watch scope missing"))?;
+
+        let prefix = req.into_inner().prefix;
+        let (tx, rx) = mpsc::channel(STREAM_BUFFER);
+        tokio::spawn(async move {
+            loop {
+                let change: KeyChangeEvent = match changes.recv().await {
+                    Ok(change) => change,
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        let status = Status::data_loss(format!("{} changes missed", missed));
+                        let _ = tx.send(Err(status)).await;
+                        break;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                if !scope.shows(&change) || !change.key.starts_with(&prefix) {
+                    continue;
+                }
+                let event = WatchEvent {
+                    event: change.event,
+                    key: change.key,
+                    tenant: change.tenant.unwrap_or_default(),
+                    timestamp: change.timestamp,
+                };
+                if tx.send(Ok(event)).await.is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_query() {
+        assert_eq!(
+            query(&[("consistency", ""), ("after", "a b/c"), ("limit", "5")]),
+            "after=a%20b%2Fc&limit=5"
+        );
+        assert_eq!(
+            url_origin("http://leader:5000/key?x=1"),
+            "http://leader:5000"
+        );
+        assert_eq!(url_origin("http://leader:5000"), "http://leader:5000");
+    }
+}
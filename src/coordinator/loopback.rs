@@ -0,0 +1,138 @@
+//! Requests to a coordinator's own HTTP API, run in process
+//!
+//! The text protocol front ends (`resp`, `memcached`) map their commands
+//! onto HTTP requests handled by the coordinator's router, so they go
+//! through the same authentication, ACLs, quotas and rate limits as HTTP
+//! clients, without a network round trip.
+
+use crate::coordinator::metadata::{KeyMetadata, KeyState};
+use crate::ops::KeyPage;
+use axum::body::{Body, Bytes};
+use axum::extract::ConnectInfo;
+use axum::http::{header, HeaderMap, Method, Request, StatusCode};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use std::borrow::Cow;
+use std::net::SocketAddr;
+use tower::ServiceExt;
+
+/// Largest answer read, as the largest value of the text protocols
+pub const MAX_ANSWER_BYTES: usize = 512 * 1024 * 1024;
+
+/// Credentials and address of a front end's client
+#[derive(Debug, Clone, Default)]
+pub struct Caller {
+    /// Sent with every request (`x-api-key`, `x-minikv-tenant`...)
+    pub headers: HeaderMap,
+    pub peer: Option<SocketAddr>,
+}
+
+/// An HTTP answer read in full
+#[derive(Debug)]
+pub struct Answer {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+}
+
+impl Answer {
+    fn failed(status: StatusCode, message: String) -> Self {
+        Self {
+            status,
+            headers: HeaderMap::new(),
+            body: Bytes::from(message),
+        }
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.status.is_success()
+    }
+
+    /// The body, as the message of an error
+    pub fn message(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.body)
+    }
+
+    /// The leader a follower redirected to
+    pub fn leader(&self) -> Option<&str> {
+        if self.status != StatusCode::TEMPORARY_REDIRECT {
+            return None;
+        }
+        self.headers
+            .get(header::LOCATION)
+            .and_then(|location| location.to_str().ok())
+    }
+}
+
+/// Run `method uri` with `body` for `caller`
+pub async fn call(
+    router: &axum::Router,
+    caller: &Caller,
+    method: Method,
+    uri: String,
+    body: Body,
+) -> Answer {
+    let mut request = match Request::builder().method(method).uri(uri).body(body) {
+        Ok(request) => request,
+        Err(e) => return Answer::failed(StatusCode::BAD_REQUEST, e.to_string()),
+    };
+    request.headers_mut().extend(caller.headers.clone());
+    if let Some(peer) = caller.peer {
+        request.extensions_mut().insert(ConnectInfo(peer));
+    }
+    let resp = match router.clone().oneshot(request).await {
+        Ok(resp) => resp,
+        Err(never) => match never {},
+    };
+    let status = resp.status();
+    let headers = resp.headers().clone();
+    match axum::body::to_bytes(resp.into_body(), MAX_ANSWER_BYTES).await {
+        Ok(body) => Answer {
+            status,
+            headers,
+            body,
+        },
+        Err(e) => Answer::failed(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    }
+}
+
+/// One page of at most `limit` keys under `prefix`, after `after`
+pub async fn list(
+    router: &axum::Router,
+    caller: &Caller,
+    prefix: &str,
+    after: Option<&str>,
+    limit: usize,
+) -> Result<KeyPage, Answer> {
+    let mut uri = format!(
+        "/keys?prefix={}&limit={}",
+        utf8_percent_encode(prefix, NON_ALPHANUMERIC),
+        limit
+    );
+    if let Some(after) = after {
+        uri.push_str(&format!(
+            "&after={}",
+            utf8_percent_encode(after, NON_ALPHANUMERIC)
+        ));
+    }
+    let answer = call(router, caller, Method::GET, uri, Body::empty()).await;
+    if !answer.is_success() {
+        return Err(answer);
+    }
+    serde_json::from_slice(&answer.body)
+        .map_err(|e| Answer::failed(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+}
+
+/// Metadata of `key`, `None` if it has no value
+pub async fn key_metadata(
+    router: &axum::Router,
+    caller: &Caller,
+    key: &str,
+) -> Result<Option<KeyMetadata>, Answer> {
+    // The key itself sorts first among those it prefixes
+    let page = list(router, caller, key, None, 1).await?;
+    Ok(page
+        .keys
+        .into_iter()
+        .next()
+        .filter(|meta| meta.key == key && meta.state != KeyState::Tombstone))
+}
@@ -0,0 +1,484 @@
+//! Memcached text protocol endpoint
+//!
+//! Served on `memcached_addr` for drop-in caching. `get`, `set`, `delete`
+//! and `stats` (with `version` and `quit`) are mapped onto the HTTP API,
+//! dispatched to the coordinator's router in process (`loopback`):
+//!
+//! | Command | Maps to |
+//! |---|---|
+//! | `get <key>*` | `GET /keys?prefix=key`, then `GET /key` for each hit |
+//! | `set <key> <flags> 0 <bytes> [noreply]` | `POST /key` |
+//! | `delete <key> [noreply]` | `DELETE /key` |
+//!
+//! Clients may pipeline: commands are read and answered in order, and the
+//! answers written out once no command is left in the connection's buffer.
+//! Flags are kept in the `memcached_flags` tag of the key. Keys never
+//! expire, so a `set` with an expiration time is refused. Items are up to
+//! 1 MiB, memcached's default.
+//!
+//! When authentication is enabled, clients authenticate the way of
+//! memcached's text protocol: the first command is a `set` of any key
+//! whose data holds the credentials, either `<username> <password>` or
+//! SASL PLAIN's `<authzid>\0<username>\0<password>`. The password is an
+//! API key, and the username, unless `default` (or the authzid when set),
+//! the tenant, as with the HTTP API's `x-minikv-tenant`.
+
+use crate::common::auth::{AuthResult, KEY_STORE};
+use crate::common::encode_key;
+use crate::coordinator::loopback::{self, Answer, Caller};
+use crate::coordinator::tenant::TENANT_HEADER;
+use axum::body::{Body, Bytes};
+use axum::http::{HeaderMap, HeaderValue, Method, StatusCode};
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+
+/// Longest command line
+const MAX_LINE_BYTES: u64 = 8 * 1024;
+
+/// Longest key, as in memcached
+const MAX_KEY_BYTES: usize = 250;
+
+/// Largest item, memcached's default `-I`
+const MAX_ITEM_BYTES: usize = 1024 * 1024;
+
+/// Tag holding the flags of a key
+const FLAGS_TAG: &str = "memcached_flags";
+
+/// Counters reported by `stats`
+#[derive(Default)]
+struct Stats {
+    curr_connections: AtomicU64,
+    total_connections: AtomicU64,
+    cmd_get: AtomicU64,
+    cmd_set: AtomicU64,
+    get_hits: AtomicU64,
+    get_misses: AtomicU64,
+    delete_hits: AtomicU64,
+    delete_misses: AtomicU64,
+    auth_cmds: AtomicU64,
+    auth_errors: AtomicU64,
+}
+
+/// Memcached protocol server of a coordinator
+#[derive(Clone)]
+pub struct MemcachedServer {
+    router: axum::Router,
+    auth: bool,
+    stats: Arc<Stats>,
+    started: Instant,
+}
+
+/// A command line, split on spaces
+struct Command<'a> {
+    name: &'a str,
+    args: Vec<&'a str>,
+}
+
+impl MemcachedServer {
+    /// Serve commands through `router`, the coordinator's HTTP router
+    pub fn new(router: axum::Router) -> Self {
+        Self {
+            router,
+            auth: false,
+            stats: Arc::new(Stats::default()),
+            started: Instant::now(),
+        }
+    }
+
+    /// Require clients to authenticate first (see the module docs)
+    pub fn with_auth(mut self, enabled: bool) -> Self {
+        self.auth = enabled;
+        self
+    }
+
+    /// Accept connections on `listener` until it fails
+    pub async fn serve(self, listener: TcpListener) -> io::Result<()> {
+        loop {
+            let (stream, peer) = listener.accept().await?;
+            let server = self.clone();
+            tokio::spawn(async move {
+                if let Err(e) = server.serve_connection(stream, Some(peer)).await {
+                    tracing::debug!("memcached connection from {} closed: {}", peer, e);
+                }
+            });
+        }
+    }
+
+    /// Answer the commands of one client, from `peer` if known
+    pub async fn serve_connection<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        stream: S,
+        peer: Option<SocketAddr>,
+    ) -> io::Result<()> {
+        self.stats.curr_connections.fetch_add(1, Ordering::Relaxed);
+        self.stats.total_connections.fetch_add(1, Ordering::Relaxed);
+        let result = self.converse(stream, peer).await;
+        self.stats.curr_connections.fetch_sub(1, Ordering::Relaxed);
+        result
+    }
+
+    async fn converse<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        stream: S,
+        peer: Option<SocketAddr>,
+    ) -> io::Result<()> {
+        let (reader, mut writer) = tokio::io::split(stream);
+        let mut reader = BufReader::new(reader);
+        let mut caller = Caller {
+            peer,
+            ..Default::default()
+        };
+        let mut authenticated = !self.auth;
+        let mut out = Vec::new();
+        loop {
+            // Pipelined commands are answered together
+            if reader.buffer().is_empty() && !out.is_empty() {
+                writer.write_all(&out).await?;
+                out.clear();
+            }
+            let mut line = Vec::new();
+            (&mut reader)
+                .take(MAX_LINE_BYTES)
+                .read_until(b'\n', &mut line)
+                .await?;
+            if line.is_empty() {
+                writer.write_all(&out).await?;
+                return Ok(());
+            }
+            if line.pop() != Some(b'\n') {
+                out.extend_from_slice(b"CLIENT_ERROR line too long\r\n");
+                writer.write_all(&out).await?;
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "line too long"));
+            }
+            if line.last() == Some(&b'\r') {
+                line.pop();
+            }
+            let Ok(line) = std::str::from_utf8(&line) else {
+                out.extend_from_slice(b"CLIENT_ERROR bad command line format\r\n");
+                continue;
+            };
+            let mut words = line.split(' ').filter(|word| !word.is_empty());
+            let Some(name) = words.next() else {
+                out.extend_from_slice(b"ERROR\r\n");
+                continue;
+            };
+            let command = Command {
+                name,
+                args: words.collect(),
+            };
+
+            // Storage commands carry a data block, read whatever the command
+            let data = if command.name == "set" {
+                match command.args.get(3).and_then(|n| n.parse::<usize>().ok()) {
+                    Some(len) if len > MAX_ITEM_BYTES => {
+                        // Skipped without buffering, as by memcached
+                        let skip = len as u64 + 2;
+                        let skipped =
+                            tokio::io::copy(&mut (&mut reader).take(skip), &mut tokio::io::sink())
+                                .await?;
+                        if skipped < skip {
+                            return Err(io::ErrorKind::UnexpectedEof.into());
+                        }
+                        out.extend_from_slice(b"SERVER_ERROR object too large for cache\r\n");
+                        continue;
+                    }
+                    Some(len) => {
+                        // Grows as the data arrives, not up front from the header
+                        let mut data = Vec::new();
+                        (&mut reader)
+                            .take(len as u64 + 2)
+                            .read_to_end(&mut data)
+                            .await?;
+                        if data.len() < len + 2 {
+                            return Err(io::ErrorKind::UnexpectedEof.into());
+                        }
+                        if !data.ends_with(b"\r\n") {
+                            out.extend_from_slice(b"CLIENT_ERROR bad data chunk\r\n");
+                            writer.write_all(&out).await?;
+                            return Err(io::Error::new(
+                                io::ErrorKind::InvalidData,
+                                "bad data chunk",
+                            ));
+                        }
+                        data.truncate(len);
+                        Some(Bytes::from(data))
+                    }
+                    _ => {
+                        out.extend_from_slice(b"CLIENT_ERROR bad command line format\r\n");
+                        continue;
+                    }
+                }
+            } else {
+                None
+            };
+
+            if command.name == "quit" {
+                writer.write_all(&out).await?;
+                return Ok(());
+            }
+            if !authenticated {
+                match data {
+                    Some(credentials) => {
+                        self.stats.auth_cmds.fetch_add(1, Ordering::Relaxed);
+                        match authenticate(&credentials) {
+                            Some(headers) => {
+                                caller.headers = headers;
+                                authenticated = true;
+                                out.extend_from_slice(b"STORED\r\n");
+                            }
+                            None => {
+                                self.stats.auth_errors.fetch_add(1, Ordering::Relaxed);
+                                out.extend_from_slice(b"CLIENT_ERROR authentication failure\r\n");
+                            }
+                        }
+                    }
+                    None => out.extend_from_slice(b"CLIENT_ERROR unauthenticated\r\n"),
+                }
+                continue;
+            }
+            self.execute(&caller, &command, data, &mut out).await;
+        }
+    }
+
+    /// Append the answer to `command` to `out`
+    async fn execute(
+        &self,
+        caller: &Caller,
+        command: &Command<'_>,
+        data: Option<Bytes>,
+        out: &mut Vec<u8>,
+    ) {
+        let args = &command.args;
+        match (command.name, data) {
+            ("get", _) if !args.is_empty() => {
+                for key in args {
+                    if let Err(e) = self.get(caller, key, out).await {
+                        out.extend_from_slice(e.as_bytes());
+                        return;
+                    }
+                }
+                out.extend_from_slice(b"END\r\n");
+            }
+            ("set", Some(data)) if args.len() == 4 || (args.len() == 5 && args[4] == "noreply") => {
+                let reply = self.set(caller, args, data).await;
+                if args.len() == 4 {
+                    out.extend_from_slice(reply.as_bytes());
+                }
+            }
+            ("delete", _) if !args.is_empty() && args.len() <= 3 => {
+                let noreply = args.len() > 1 && args.last() == Some(&"noreply");
+                // A delay, only 0 being accepted, may come before noreply
+                let delay = &args[1..args.len() - usize::from(noreply)];
+                let reply = match delay {
+                    [] | ["0"] => self.delete(caller, args[0]).await,
+                    _ => "CLIENT_ERROR bad command line format.  Usage: delete <key> [noreply]\r\n"
+                        .to_string(),
+                };
+                if !noreply {
+                    out.extend_from_slice(reply.as_bytes());
+                }
+            }
+            ("stats", _) if args.is_empty() => self.stats(out),
+            ("version", _) => out
+                .extend_from_slice(format!("VERSION {}\r\n", env!("CARGO_PKG_VERSION")).as_bytes()),
+            _ => out.extend_from_slice(b"ERROR\r\n"),
+        }
+    }
+
+    /// Append `key`'s value, if it has one, failing with the line to answer
+    async fn get(&self, caller: &Caller, key: &str, out: &mut Vec<u8>) -> Result<(), String> {
+        check_key(key)?;
+        self.stats.cmd_get.fetch_add(1, Ordering::Relaxed);
+        let meta = loopback::key_metadata(&self.router, caller, key)
+            .await
+            .map_err(|answer| error_line(&answer))?;
+        let Some(meta) = meta else {
+            self.stats.get_misses.fetch_add(1, Ordering::Relaxed);
+            return Ok(());
+        };
+        let uri = format!("/{}?redirect=false", encode_key(key));
+        let answer = loopback::call(&self.router, caller, Method::GET, uri, Body::empty()).await;
+        match answer.status {
+            status if status.is_success() => {
+                self.stats.get_hits.fetch_add(1, Ordering::Relaxed);
+                let flags = meta.tags.get(FLAGS_TAG).map_or("0", String::as_str);
+                out.extend_from_slice(
+                    format!("VALUE {} {} {}\r\n", key, flags, answer.body.len()).as_bytes(),
+                );
+                out.extend_from_slice(&answer.body);
+                out.extend_from_slice(b"\r\n");
+                Ok(())
+            }
+            // Deleted in between
+            StatusCode::NOT_FOUND => {
+                self.stats.get_misses.fetch_add(1, Ordering::Relaxed);
+                Ok(())
+            }
+            _ => Err(error_line(&answer)),
+        }
+    }
+
+    async fn set(&self, caller: &Caller, args: &[&str], data: Bytes) -> String {
+        if let Err(e) = check_key(args[0]) {
+            return e;
+        }
+        let Ok(flags) = args[1].parse::<u32>() else {
+            return "CLIENT_ERROR bad command line format\r\n".to_string();
+        };
+        if args[2] != "0" {
+            return "CLIENT_ERROR keys can't expire, exptime must be 0\r\n".to_string();
+        }
+        self.stats.cmd_set.fetch_add(1, Ordering::Relaxed);
+
+        let mut caller = caller.clone();
+        if flags != 0 {
+            let tag = format!("{}={}", FLAGS_TAG, flags);
+            caller.headers.insert(
+                "x-tag",
+                HeaderValue::from_str(&tag).expect("digits are a valid header"),
+            );
+        }
+        let uri = format!("/{}?redirect=false", encode_key(args[0]));
+        let answer =
+            loopback::call(&self.router, &caller, Method::POST, uri, Body::from(data)).await;
+        if answer.is_success() {
+            "STORED\r\n".to_string()
+        } else {
+            error_line(&answer)
+        }
+    }
+
+    async fn delete(&self, caller: &Caller, key: &str) -> String {
+        if let Err(e) = check_key(key) {
+            return e;
+        }
+        let uri = format!("/{}", encode_key(key));
+        let answer = loopback::call(&self.router, caller, Method::DELETE, uri, Body::empty()).await;
+        match answer.status {
+            status if status.is_success() => {
+                self.stats.delete_hits.fetch_add(1, Ordering::Relaxed);
+                "DELETED\r\n".to_string()
+            }
+            StatusCode::NOT_FOUND => {
+                self.stats.delete_misses.fetch_add(1, Ordering::Relaxed);
+                "NOT_FOUND\r\n".to_string()
+            }
+            _ => error_line(&answer),
+        }
+    }
+
+    fn stats(&self, out: &mut Vec<u8>) {
+        let stats = &self.stats;
+        let counters = [
+            ("curr_connections", &stats.curr_connections),
+            ("total_connections", &stats.total_connections),
+            ("cmd_get", &stats.cmd_get),
+            ("cmd_set", &stats.cmd_set),
+            ("get_hits", &stats.get_hits),
+            ("get_misses", &stats.get_misses),
+            ("delete_hits", &stats.delete_hits),
+            ("delete_misses", &stats.delete_misses),
+            ("auth_cmds", &stats.auth_cmds),
+            ("auth_errors", &stats.auth_errors),
+        ];
+        let mut line = |name: &str, value: &dyn std::fmt::Display| {
+            out.extend_from_slice(format!("STAT {} {}\r\n", name, value).as_bytes());
+        };
+        line("pid", &std::process::id());
+        line("uptime", &self.started.elapsed().as_secs());
+        line("time", &crate::common::timestamp_now());
+        line("version", &env!("CARGO_PKG_VERSION"));
+        for (name, counter) in counters {
+            line(name, &counter.load(Ordering::Relaxed));
+        }
+        out.extend_from_slice(b"END\r\n");
+    }
+}
+
+/// Headers for the credentials of an authentication `set`, if valid
+fn authenticate(credentials: &[u8]) -> Option<HeaderMap> {
+    let credentials = std::str::from_utf8(credentials).ok()?;
+    let (tenant, password) = if credentials.contains('\0') {
+        // SASL PLAIN: the authorization identity, if any, names the tenant
+        let mut parts = credentials.splitn(3, '\0');
+        let (authzid, username, password) = (parts.next()?, parts.next()?, parts.next()?);
+        (
+            if authzid.is_empty() {
+                username
+            } else {
+                authzid
+            },
+            password,
+        )
+    } else {
+        credentials.trim_end().split_once(' ')?
+    };
+    if !matches!(KEY_STORE.validate_key(password), AuthResult::Ok(_)) {
+        return None;
+    }
+    let mut headers = HeaderMap::new();
+    headers.insert("x-api-key", HeaderValue::from_str(password).ok()?);
+    if !tenant.is_empty() && tenant != "default" {
+        headers.insert(TENANT_HEADER, HeaderValue::from_str(tenant).ok()?);
+    }
+    Some(headers)
+}
+
+/// Memcached keys are short and free of spaces and control characters
+fn check_key(key: &str) -> Result<(), String> {
+    if key.len() > MAX_KEY_BYTES || key.bytes().any(|b| b.is_ascii_control()) {
+        return Err("CLIENT_ERROR bad command line format\r\n".to_string());
+    }
+    Ok(())
+}
+
+/// The error line for a failed request
+fn error_line(answer: &Answer) -> String {
+    let message = answer.message().replace(['\r', '\n'], " ");
+    if let Some(leader) = answer.leader() {
+        return format!("SERVER_ERROR not the leader, leader is at {}\r\n", leader);
+    }
+    match answer.status {
+        StatusCode::UNAUTHORIZED => "CLIENT_ERROR unauthenticated\r\n".to_string(),
+        status if status.is_client_error() => format!("CLIENT_ERROR {}\r\n", message),
+        _ => format!("SERVER_ERROR {}\r\n", message),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_key() {
+        assert!(check_key("user:1").is_ok());
+        assert!(check_key(&"k".repeat(MAX_KEY_BYTES + 1)).is_err());
+        assert!(check_key("tab\tkey").is_err());
+    }
+
+    #[test]
+    fn test_error_line() {
+        let answer = Answer {
+            status: StatusCode::SERVICE_UNAVAILABLE,
+            headers: HeaderMap::new(),
+            body: Bytes::from("no healthy\nvolumes"),
+        };
+        assert_eq!(error_line(&answer), "SERVER_ERROR no healthy volumes\r\n");
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "location",
+            HeaderValue::from_static("http://leader:5000/key"),
+        );
+        let answer = Answer {
+            status: StatusCode::TEMPORARY_REDIRECT,
+            headers,
+            body: Bytes::new(),
+        };
+        assert!(error_line(&answer).contains("http://leader:5000/key"));
+    }
+}
@@ -3,8 +3,8 @@ use std::sync::Arc;
 static GLOBAL_STORE: OnceCell<Arc<MetadataStore>> = OnceCell::new();
 
 /// Initializes the global store (call at startup)
-pub fn init_global_store(store: MetadataStore) {
-    let _ = GLOBAL_STORE.set(Arc::new(store));
+pub fn init_global_store(store: Arc<MetadataStore>) {
+    let _ = GLOBAL_STORE.set(store);
 }
 
 /// Access the global store
@@ -18,14 +18,72 @@ pub fn get_global_store() -> Arc<MetadataStore> {
 ///
 /// This module provides persistent storage for cluster metadata.
 /// It stores key metadata (replicas, size, hash, timestamps), volume registry (node_id to address, state, shards), and cluster configuration.
-use crate::common::{NodeState, Result};
-use rocksdb::{Options, DB};
+use crate::common::auth::{ApiKey, ApiKeyRecord, KEY_STORE};
+use crate::common::raft::{HardState, LogEntry, SnapshotMeta};
+use crate::common::{AclRule, NodeState, Result, TenantQuota, ACL_STORE, QUOTA_MANAGER};
+use crate::coordinator::cdc::CdcEvent;
+use crate::coordinator::etcd::{self, EtcdChange, EtcdEvent, EtcdKeyValue, EtcdLease};
+use crate::coordinator::http::WATCH_CHANNEL;
+use rocksdb::{Options, WriteBatch, WriteOptions, DB};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::path::Path;
+use tokio::sync::broadcast;
 
 const CF_KEYS: &str = "keys";
 const CF_VOLUMES: &str = "volumes";
 const CF_CONFIG: &str = "config";
+const CF_TXNS: &str = "txns";
+const CF_RAFT: &str = "raft";
+const CF_RAFT_LOG: &str = "raft_log";
+const CF_CDC: &str = "cdc";
+/// Inverted tag index: `name\0value\0key` -> empty
+const CF_TAGS: &str = "tags";
+/// Reference counts of deduplicated content: CAS key -> u64 (big-endian)
+const CF_BLOB_REFS: &str = "blob_refs";
+/// API keys: key ID -> `ApiKeyRecord`
+const CF_AUTH: &str = "auth";
+/// Tenant quotas: tenant -> `TenantQuota`
+const CF_QUOTAS: &str = "quotas";
+/// Per-prefix ACL rules: rule ID -> `AclRule`
+const CF_ACLS: &str = "acls";
+/// etcd gateway data: `k<key>` -> `EtcdKeyValue`, `l<lease ID>` -> `EtcdLease`
+/// and the current revision (see `coordinator::etcd`)
+const CF_ETCD: &str = "etcd";
+
+const HARD_STATE_KEY: &[u8] = b"hard_state";
+const SNAPSHOT_META_KEY: &[u8] = b"snapshot_meta";
+const SNAPSHOT_DATA_KEY: &[u8] = b"snapshot_data";
+
+/// Column families making up the replicated state machine (everything but Raft's own state)
+const STATE_CFS: [&str; 11] = [
+    CF_KEYS,
+    CF_VOLUMES,
+    CF_CONFIG,
+    CF_TXNS,
+    CF_TAGS,
+    CF_BLOB_REFS,
+    CF_AUTH,
+    CF_QUOTAS,
+    CF_ACLS,
+    CF_ETCD,
+    // So a follower installing a snapshot serves the same `/cdc` history
+    CF_CDC,
+];
+
+/// Serialized form of the state machine: `(column family, key, value)` triples
+type StateDump = Vec<(String, Vec<u8>, Vec<u8>)>;
+
+/// Keys per page when a listing sets no limit
+pub const DEFAULT_PAGE_SIZE: usize = 1000;
+
+/// Largest page a listing may ask for
+pub const MAX_PAGE_SIZE: usize = 10_000;
+
+/// Page size for a requested limit: the default when unset, capped at the maximum
+pub fn page_limit(limit: Option<usize>) -> usize {
+    limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE)
+}
 
 /// Key metadata
 /// Describes the state and replica set for a single key in the cluster.
@@ -38,6 +96,162 @@ pub struct KeyMetadata {
     pub created_at: u64,
     pub updated_at: u64,
     pub state: KeyState,
+    /// User-defined tags (`X-Tag: name=value` on PUT), indexed for `GET /search?tag=`
+    #[serde(default)]
+    pub tags: BTreeMap<String, String>,
+    /// Content key holding the bytes of a deduplicated value; `replicas` is
+    /// then empty (see `coordinator::dedup`)
+    #[serde(default)]
+    pub blob: Option<String>,
+    /// Chunks holding the bytes of a large value; `replicas` is then empty
+    /// (see `coordinator::chunking`)
+    #[serde(default)]
+    pub chunks: Option<ChunkManifest>,
+}
+
+/// Keys holding the consecutive chunks of a value, each `chunk_size` bytes
+/// but the last
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChunkManifest {
+    pub chunk_size: u64,
+    pub chunks: Vec<String>,
+}
+
+/// Key metadata as written before chunking existed
+#[derive(Deserialize)]
+struct KeyMetadataV3 {
+    key: String,
+    replicas: Vec<String>,
+    size: u64,
+    blake3: String,
+    created_at: u64,
+    updated_at: u64,
+    state: KeyState,
+    tags: BTreeMap<String, String>,
+    blob: Option<String>,
+}
+
+impl From<KeyMetadataV3> for KeyMetadata {
+    fn from(v3: KeyMetadataV3) -> Self {
+        Self {
+            key: v3.key,
+            replicas: v3.replicas,
+            size: v3.size,
+            blake3: v3.blake3,
+            created_at: v3.created_at,
+            updated_at: v3.updated_at,
+            state: v3.state,
+            tags: v3.tags,
+            blob: v3.blob,
+            chunks: None,
+        }
+    }
+}
+
+/// Key metadata as written before deduplication existed
+#[derive(Deserialize)]
+struct KeyMetadataV2 {
+    key: String,
+    replicas: Vec<String>,
+    size: u64,
+    blake3: String,
+    created_at: u64,
+    updated_at: u64,
+    state: KeyState,
+    tags: BTreeMap<String, String>,
+}
+
+impl From<KeyMetadataV2> for KeyMetadata {
+    fn from(v2: KeyMetadataV2) -> Self {
+        Self {
+            key: v2.key,
+            replicas: v2.replicas,
+            size: v2.size,
+            blake3: v2.blake3,
+            created_at: v2.created_at,
+            updated_at: v2.updated_at,
+            state: v2.state,
+            tags: v2.tags,
+            blob: None,
+            chunks: None,
+        }
+    }
+}
+
+/// Key metadata as written before tags existed
+#[derive(Deserialize)]
+struct KeyMetadataV1 {
+    key: String,
+    replicas: Vec<String>,
+    size: u64,
+    blake3: String,
+    created_at: u64,
+    updated_at: u64,
+    state: KeyState,
+}
+
+impl From<KeyMetadataV1> for KeyMetadata {
+    fn from(v1: KeyMetadataV1) -> Self {
+        Self {
+            key: v1.key,
+            replicas: v1.replicas,
+            size: v1.size,
+            blake3: v1.blake3,
+            created_at: v1.created_at,
+            updated_at: v1.updated_at,
+            state: v1.state,
+            tags: BTreeMap::new(),
+            blob: None,
+            chunks: None,
+        }
+    }
+}
+
+impl KeyMetadata {
+    /// Decode stored key metadata, including records written before tags,
+    /// deduplication or chunking existed
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        bincode::deserialize(bytes)
+            .or_else(|e| {
+                bincode::deserialize::<KeyMetadataV3>(bytes)
+                    .map(Self::from)
+                    .map_err(|_| e)
+            })
+            .or_else(|e| {
+                bincode::deserialize::<KeyMetadataV2>(bytes)
+                    .map(Self::from)
+                    .map_err(|_| e)
+            })
+            .or_else(|e| {
+                bincode::deserialize::<KeyMetadataV1>(bytes)
+                    .map(Self::from)
+                    .map_err(|_| crate::Error::MetadataCorrupted(e.to_string()))
+            })
+    }
+}
+
+/// Index entry for one tag of a key
+fn tag_index_key(name: &str, value: &str, key: &str) -> Vec<u8> {
+    format!("{}\0{}\0{}", name, value, key).into_bytes()
+}
+
+const ETCD_REVISION_KEY: &[u8] = b"revision";
+const ETCD_KV_PREFIX: &[u8] = b"k";
+const ETCD_LEASE_PREFIX: &[u8] = b"l";
+/// etcd changes a watcher can fall behind by
+const ETCD_EVENTS_CAPACITY: usize = 1024;
+
+fn etcd_kv_key(key: &[u8]) -> Vec<u8> {
+    [ETCD_KV_PREFIX, key].concat()
+}
+
+/// Big-endian, so leases are listed in ID order
+fn etcd_lease_key(id: i64) -> Vec<u8> {
+    [ETCD_LEASE_PREFIX, &id.to_be_bytes()].concat()
+}
+
+fn encode_etcd(value: &impl Serialize) -> Result<Vec<u8>> {
+    bincode::serialize(value).map_err(|e| crate::Error::Internal(format!("Serialize error: {}", e)))
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -45,6 +259,8 @@ pub struct KeyMetadata {
 pub enum KeyState {
     Active,
     Tombstone,
+    /// Moved to the cold storage bucket; `replicas` is empty (see `coordinator::tiering`)
+    Tiered,
 }
 
 /// Volume metadata
@@ -60,11 +276,144 @@ pub struct VolumeMetadata {
     pub total_bytes: u64,
     pub free_bytes: u64,
     pub last_heartbeat: u64,
+    /// Availability zone label (from the volume config)
+    pub zone: Option<String>,
+    /// Rack label (from the volume config)
+    pub rack: Option<String>,
+    /// Configured placement weight; `None` weighs by free space
+    pub weight: Option<f64>,
+    /// Why the volume's WAL can't be written to, as of its last heartbeat
+    pub wal_error: Option<String>,
+}
+
+/// In-flight 2PC transaction
+/// Persisted before the prepare phase so a restarted leader can finish or roll back the write.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TxnRecord {
+    pub upload_id: String,
+    pub key: String,
+    pub replicas: Vec<String>,
+    pub size: u64,
+    pub blake3: String,
+    pub state: TxnState,
+    pub created_at: u64,
+    /// Replicas that acknowledged the commit so far
+    pub acks: Vec<String>,
+}
+
+/// Phase of a 2PC transaction.
+/// `Committing` is the durable commit decision: once written, recovery always rolls forward.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TxnState {
+    Preparing,
+    Committing,
+    Aborting,
+}
+
+/// A metadata mutation, the payload of a Raft log entry.
+/// Every command writes whole values, so re-applying one is harmless.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum MetadataCommand {
+    PutKey(KeyMetadata),
+    DeleteKey(String),
+    PutVolume(VolumeMetadata),
+    DeleteVolume(String),
+    PutConfig {
+        key: String,
+        value: Vec<u8>,
+    },
+    PutTxn(TxnRecord),
+    DeleteTxn(String),
+    /// A deduplicated key and the content it references. The content's
+    /// metadata is restored if it was collected in the meantime.
+    PutDedupKey {
+        key: KeyMetadata,
+        blob: KeyMetadata,
+    },
+    /// Create or replace an API key (creation, revocation)
+    PutApiKey(ApiKeyRecord),
+    DeleteApiKey(String),
+    /// Create or replace a tenant's quota
+    PutQuota(TenantQuota),
+    /// Remove a tenant's quota; the default one applies again
+    DeleteQuota(String),
+    /// Create or replace an ACL rule
+    PutAclRule(AclRule),
+    DeleteAclRule(String),
+    /// Set an etcd key, attached to `lease` unless 0. Ignored if the lease
+    /// was revoked in the meantime.
+    EtcdPut {
+        key: Vec<u8>,
+        value: Vec<u8>,
+        lease: i64,
+    },
+    /// Delete the etcd key `key`, or the keys in `[key, range_end)`
+    EtcdDeleteRange {
+        key: Vec<u8>,
+        range_end: Option<Vec<u8>>,
+    },
+    EtcdLeaseGrant(EtcdLease),
+    /// Remove an etcd lease and the keys attached to it
+    EtcdLeaseRevoke(i64),
+    /// Replace a key's metadata with its `Tiered` stub, if the key still
+    /// holds the value the stub describes (same BLAKE3 and `updated_at`).
+    /// Ignored otherwise.
+    TierKey(KeyMetadata),
+}
+
+impl MetadataCommand {
+    /// A change to the etcd gateway's data (see `coordinator::etcd`)
+    pub fn is_etcd(&self) -> bool {
+        matches!(
+            self,
+            MetadataCommand::EtcdPut { .. }
+                | MetadataCommand::EtcdDeleteRange { .. }
+                | MetadataCommand::EtcdLeaseGrant(_)
+                | MetadataCommand::EtcdLeaseRevoke(_)
+        )
+    }
+
+    /// Serialize for a Raft log entry
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        bincode::serialize(self)
+            .map_err(|e| crate::Error::Internal(format!("Serialize error: {}", e)))
+    }
+
+    /// Deserialize from a Raft log entry
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        bincode::deserialize(data).or_else(|e| {
+            // `PutKey` and `PutDedupKey` entries logged before key metadata
+            // carried a chunk manifest
+            if let Ok((0, v3)) = bincode::deserialize::<(u32, KeyMetadataV3)>(data) {
+                return Ok(MetadataCommand::PutKey(v3.into()));
+            }
+            if let Ok((7, key, blob)) =
+                bincode::deserialize::<(u32, KeyMetadataV3, KeyMetadataV3)>(data)
+            {
+                return Ok(MetadataCommand::PutDedupKey {
+                    key: key.into(),
+                    blob: blob.into(),
+                });
+            }
+            // `PutKey` entries logged before key metadata carried a blob
+            // reference, or tags
+            if let Ok((0, v2)) = bincode::deserialize::<(u32, KeyMetadataV2)>(data) {
+                return Ok(MetadataCommand::PutKey(v2.into()));
+            }
+            match bincode::deserialize::<(u32, KeyMetadataV1)>(data) {
+                Ok((0, v1)) => Ok(MetadataCommand::PutKey(v1.into())),
+                _ => Err(crate::Error::MetadataCorrupted(e.to_string())),
+            }
+        })
+    }
 }
 
 /// Metadata store
 pub struct MetadataStore {
     db: DB,
+    /// Changes to etcd keys, as they are applied
+    etcd_events: broadcast::Sender<EtcdChange>,
 }
 
 impl MetadataStore {
@@ -75,9 +424,30 @@ impl MetadataStore {
         opts.create_if_missing(true);
         opts.create_missing_column_families(true);
 
-        let db = DB::open_cf(&opts, path, vec![CF_KEYS, CF_VOLUMES, CF_CONFIG])?;
+        let db = DB::open_cf(
+            &opts,
+            path,
+            vec![
+                CF_KEYS,
+                CF_VOLUMES,
+                CF_CONFIG,
+                CF_TXNS,
+                CF_RAFT,
+                CF_RAFT_LOG,
+                CF_CDC,
+                CF_TAGS,
+                CF_BLOB_REFS,
+                CF_AUTH,
+                CF_QUOTAS,
+                CF_ACLS,
+                CF_ETCD,
+            ],
+        )?;
 
-        Ok(Self { db })
+        Ok(Self {
+            db,
+            etcd_events: broadcast::channel(ETCD_EVENTS_CAPACITY).0,
+        })
     }
 
     // === Key operations ===
@@ -86,9 +456,39 @@ impl MetadataStore {
     #[allow(clippy::result_large_err)]
     pub fn put_key(&self, meta: &KeyMetadata) -> Result<()> {
         let cf = self.db.cf_handle(CF_KEYS).unwrap();
+        let tags_cf = self.db.cf_handle(CF_TAGS).unwrap();
         let value = bincode::serialize(meta)
             .map_err(|e| crate::Error::Internal(format!("Serialize error: {}", e)))?;
-        self.db.put_cf(cf, meta.key.as_bytes(), value)?;
+
+        let mut batch = WriteBatch::default();
+        let old = self.get_key(&meta.key)?;
+        if let Some(old) = &old {
+            for (name, tag_value) in &old.tags {
+                batch.delete_cf(tags_cf, tag_index_key(name, tag_value, &meta.key));
+            }
+        }
+        if let Some(old_chunks) = old.as_ref().and_then(|old| old.chunks.as_ref()) {
+            if meta.chunks.as_ref() != Some(old_chunks) {
+                self.release_chunks(&mut batch, old_chunks);
+            }
+        }
+        let old_blob = old.and_then(|old| old.blob);
+        if old_blob != meta.blob {
+            if let Some(blob) = &old_blob {
+                self.release_blob(&mut batch, blob)?;
+            }
+            if let Some(blob) = &meta.blob {
+                let refs = self.blob_refs(blob)? + 1;
+                batch.put_cf(self.refs_cf(), blob.as_bytes(), refs.to_be_bytes());
+            }
+        }
+        if meta.state != KeyState::Tombstone {
+            for (name, tag_value) in &meta.tags {
+                batch.put_cf(tags_cf, tag_index_key(name, tag_value, &meta.key), b"");
+            }
+        }
+        batch.put_cf(cf, meta.key.as_bytes(), value);
+        self.db.write(batch)?;
         Ok(())
     }
 
@@ -97,11 +497,7 @@ impl MetadataStore {
     pub fn get_key(&self, key: &str) -> Result<Option<KeyMetadata>> {
         let cf = self.db.cf_handle(CF_KEYS).unwrap();
         match self.db.get_cf(cf, key.as_bytes())? {
-            Some(bytes) => {
-                let meta: KeyMetadata = bincode::deserialize(&bytes)
-                    .map_err(|e| crate::Error::MetadataCorrupted(e.to_string()))?;
-                Ok(Some(meta))
-            }
+            Some(bytes) => Ok(Some(KeyMetadata::decode(&bytes)?)),
             None => Ok(None),
         }
     }
@@ -110,10 +506,107 @@ impl MetadataStore {
     #[allow(clippy::result_large_err)]
     pub fn delete_key(&self, key: &str) -> Result<()> {
         let cf = self.db.cf_handle(CF_KEYS).unwrap();
-        self.db.delete_cf(cf, key.as_bytes())?;
+        let tags_cf = self.db.cf_handle(CF_TAGS).unwrap();
+
+        let mut batch = WriteBatch::default();
+        if let Some(old) = self.get_key(key)? {
+            for (name, value) in &old.tags {
+                batch.delete_cf(tags_cf, tag_index_key(name, value, key));
+            }
+            if let Some(blob) = &old.blob {
+                self.release_blob(&mut batch, blob)?;
+            }
+            if let Some(chunks) = &old.chunks {
+                self.release_chunks(&mut batch, chunks);
+            }
+        }
+        batch.delete_cf(cf, key.as_bytes());
+        self.db.write(batch)?;
+        Ok(())
+    }
+
+    /// Number of keys referencing the deduplicated content stored under `blob`
+    #[allow(clippy::result_large_err)]
+    pub fn blob_refs(&self, blob: &str) -> Result<u64> {
+        match self.db.get_cf(self.refs_cf(), blob.as_bytes())? {
+            Some(bytes) => {
+                let bytes: [u8; 8] = bytes.as_slice().try_into().map_err(|_| {
+                    crate::Error::MetadataCorrupted(format!("reference count of {}", blob))
+                })?;
+                Ok(u64::from_be_bytes(bytes))
+            }
+            None => Ok(0),
+        }
+    }
+
+    /// Drop one reference to `blob` in `batch`. The last one removes the
+    /// content's metadata, leaving its bytes to the orphan GC.
+    #[allow(clippy::result_large_err)]
+    fn release_blob(&self, batch: &mut WriteBatch, blob: &str) -> Result<()> {
+        match self.blob_refs(blob)? {
+            0 | 1 => {
+                batch.delete_cf(self.refs_cf(), blob.as_bytes());
+                batch.delete_cf(self.db.cf_handle(CF_KEYS).unwrap(), blob.as_bytes());
+            }
+            refs => batch.put_cf(self.refs_cf(), blob.as_bytes(), (refs - 1).to_be_bytes()),
+        }
         Ok(())
     }
 
+    /// Remove the metadata of a dropped value's chunks in `batch`, leaving
+    /// their bytes to the orphan GC
+    fn release_chunks(&self, batch: &mut WriteBatch, manifest: &ChunkManifest) {
+        let cf = self.db.cf_handle(CF_KEYS).unwrap();
+        for chunk in &manifest.chunks {
+            batch.delete_cf(cf, chunk.as_bytes());
+        }
+    }
+
+    fn refs_cf(&self) -> &rocksdb::ColumnFamily {
+        self.db.cf_handle(CF_BLOB_REFS).unwrap()
+    }
+
+    /// Up to `limit` keys tagged `name=value`, in key order, resuming after
+    /// `start_after` (exclusive)
+    #[allow(clippy::result_large_err)]
+    pub fn keys_with_tag(
+        &self,
+        name: &str,
+        value: &str,
+        start_after: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<String>> {
+        let cf = self.db.cf_handle(CF_TAGS).unwrap();
+        let prefix = tag_index_key(name, value, "");
+        let seek = match start_after {
+            Some(after) => tag_index_key(name, value, after),
+            None => prefix.clone(),
+        };
+        let iter = self.db.iterator_cf(
+            cf,
+            rocksdb::IteratorMode::From(&seek, rocksdb::Direction::Forward),
+        );
+
+        let mut keys = Vec::new();
+        for item in iter {
+            if keys.len() >= limit {
+                break;
+            }
+            let (index_key, _) = item?;
+            let Some(key) = index_key.strip_prefix(prefix.as_slice()) else {
+                break;
+            };
+            let key = String::from_utf8(key.to_vec())
+                .map_err(|_| crate::Error::MetadataCorrupted("Invalid UTF-8".into()))?;
+            if Some(key.as_str()) == start_after {
+                continue;
+            }
+            keys.push(key);
+        }
+
+        Ok(keys)
+    }
+
     /// List all keys (for ops commands)
     pub fn list_keys(&self) -> Result<Vec<String>> {
         let cf = self.db.cf_handle(CF_KEYS).unwrap();
@@ -130,6 +623,64 @@ impl MetadataStore {
         Ok(keys)
     }
 
+    /// List up to `limit` keys starting with `prefix`, in key order, resuming
+    /// after `start_after` (exclusive). Only the requested page is read.
+    #[allow(clippy::result_large_err)]
+    pub fn list_keys_paginated(
+        &self,
+        prefix: &str,
+        start_after: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<KeyMetadata>> {
+        let cf = self.db.cf_handle(CF_KEYS).unwrap();
+        let seek = match start_after {
+            Some(after) if after > prefix => after,
+            _ => prefix,
+        };
+        let iter = self.db.iterator_cf(
+            cf,
+            rocksdb::IteratorMode::From(seek.as_bytes(), rocksdb::Direction::Forward),
+        );
+
+        let mut keys = Vec::new();
+        for item in iter {
+            if keys.len() >= limit {
+                break;
+            }
+            let (key_bytes, value) = item?;
+            if !key_bytes.starts_with(prefix.as_bytes()) {
+                break;
+            }
+            if Some(key_bytes.as_ref()) == start_after.map(str::as_bytes) {
+                continue;
+            }
+            keys.push(KeyMetadata::decode(&value)?);
+        }
+
+        Ok(keys)
+    }
+
+    /// List up to `limit` keys in `[from, to]`, in key order
+    #[allow(clippy::result_large_err)]
+    pub fn scan_key_range(&self, from: &str, to: &str, limit: usize) -> Result<Vec<KeyMetadata>> {
+        let cf = self.db.cf_handle(CF_KEYS).unwrap();
+        let iter = self.db.iterator_cf(
+            cf,
+            rocksdb::IteratorMode::From(from.as_bytes(), rocksdb::Direction::Forward),
+        );
+
+        let mut keys = Vec::new();
+        for item in iter {
+            let (key_bytes, value) = item?;
+            if keys.len() >= limit || key_bytes.as_ref() > to.as_bytes() {
+                break;
+            }
+            keys.push(KeyMetadata::decode(&value)?);
+        }
+
+        Ok(keys)
+    }
+
     // === Volume operations ===
 
     /// Register or update volume
@@ -154,6 +705,13 @@ impl MetadataStore {
         }
     }
 
+    /// Remove a volume from the registry
+    pub fn delete_volume(&self, volume_id: &str) -> Result<()> {
+        let cf = self.db.cf_handle(CF_VOLUMES).unwrap();
+        self.db.delete_cf(cf, volume_id.as_bytes())?;
+        Ok(())
+    }
+
     /// List all volumes
     pub fn list_volumes(&self) -> Result<Vec<VolumeMetadata>> {
         let cf = self.db.cf_handle(CF_VOLUMES).unwrap();
@@ -179,6 +737,288 @@ impl MetadataStore {
             .collect())
     }
 
+    // === API keys ===
+
+    /// Store an API key
+    pub fn put_api_key(&self, record: &ApiKeyRecord) -> Result<()> {
+        let cf = self.db.cf_handle(CF_AUTH).unwrap();
+        let value = bincode::serialize(record)
+            .map_err(|e| crate::Error::Internal(format!("Serialize error: {}", e)))?;
+        self.db.put_cf(cf, record.id.as_bytes(), value)?;
+        Ok(())
+    }
+
+    /// Get an API key by ID
+    pub fn get_api_key(&self, key_id: &str) -> Result<Option<ApiKeyRecord>> {
+        let cf = self.db.cf_handle(CF_AUTH).unwrap();
+        match self.db.get_cf(cf, key_id.as_bytes())? {
+            Some(bytes) => bincode::deserialize(&bytes)
+                .map(Some)
+                .map_err(|e| crate::Error::MetadataCorrupted(e.to_string())),
+            None => Ok(None),
+        }
+    }
+
+    /// Remove an API key
+    pub fn delete_api_key(&self, key_id: &str) -> Result<()> {
+        let cf = self.db.cf_handle(CF_AUTH).unwrap();
+        self.db.delete_cf(cf, key_id.as_bytes())?;
+        Ok(())
+    }
+
+    /// List all API keys
+    pub fn list_api_keys(&self) -> Result<Vec<ApiKeyRecord>> {
+        let cf = self.db.cf_handle(CF_AUTH).unwrap();
+        let mut records = Vec::new();
+        for item in self.db.iterator_cf(cf, rocksdb::IteratorMode::Start) {
+            let (_, value_bytes) = item?;
+            let record: ApiKeyRecord = bincode::deserialize(&value_bytes)
+                .map_err(|e| crate::Error::MetadataCorrupted(e.to_string()))?;
+            records.push(record);
+        }
+        Ok(records)
+    }
+
+    /// Replace the keys of the in-memory `KEY_STORE` with the persisted ones
+    pub fn load_api_keys(&self) -> Result<()> {
+        let records = self.list_api_keys()?;
+        KEY_STORE.load_keys(records.into_iter().map(ApiKey::from));
+        Ok(())
+    }
+
+    // === Tenant quotas ===
+
+    /// Store a tenant's quota
+    pub fn put_quota(&self, quota: &TenantQuota) -> Result<()> {
+        let cf = self.db.cf_handle(CF_QUOTAS).unwrap();
+        let value = bincode::serialize(quota)
+            .map_err(|e| crate::Error::Internal(format!("Serialize error: {}", e)))?;
+        self.db.put_cf(cf, quota.tenant_id.as_bytes(), value)?;
+        Ok(())
+    }
+
+    /// Get a tenant's quota
+    pub fn get_quota(&self, tenant: &str) -> Result<Option<TenantQuota>> {
+        let cf = self.db.cf_handle(CF_QUOTAS).unwrap();
+        match self.db.get_cf(cf, tenant.as_bytes())? {
+            Some(bytes) => bincode::deserialize(&bytes)
+                .map(Some)
+                .map_err(|e| crate::Error::MetadataCorrupted(e.to_string())),
+            None => Ok(None),
+        }
+    }
+
+    /// Remove a tenant's quota
+    pub fn delete_quota(&self, tenant: &str) -> Result<()> {
+        let cf = self.db.cf_handle(CF_QUOTAS).unwrap();
+        self.db.delete_cf(cf, tenant.as_bytes())?;
+        Ok(())
+    }
+
+    /// List all tenant quotas
+    pub fn list_quotas(&self) -> Result<Vec<TenantQuota>> {
+        let cf = self.db.cf_handle(CF_QUOTAS).unwrap();
+        let mut quotas = Vec::new();
+        for item in self.db.iterator_cf(cf, rocksdb::IteratorMode::Start) {
+            let (_, value_bytes) = item?;
+            let quota: TenantQuota = bincode::deserialize(&value_bytes)
+                .map_err(|e| crate::Error::MetadataCorrupted(e.to_string()))?;
+            quotas.push(quota);
+        }
+        Ok(quotas)
+    }
+
+    /// Replace the quotas of the in-memory `QUOTA_MANAGER` with the persisted ones
+    pub fn load_quotas(&self) -> Result<()> {
+        QUOTA_MANAGER.load_quotas(self.list_quotas()?);
+        Ok(())
+    }
+
+    // === ACL rules ===
+
+    /// Store an ACL rule
+    pub fn put_acl_rule(&self, rule: &AclRule) -> Result<()> {
+        let cf = self.db.cf_handle(CF_ACLS).unwrap();
+        let value = bincode::serialize(rule)
+            .map_err(|e| crate::Error::Internal(format!("Serialize error: {}", e)))?;
+        self.db.put_cf(cf, rule.id.as_bytes(), value)?;
+        Ok(())
+    }
+
+    /// Remove an ACL rule
+    pub fn delete_acl_rule(&self, id: &str) -> Result<()> {
+        let cf = self.db.cf_handle(CF_ACLS).unwrap();
+        self.db.delete_cf(cf, id.as_bytes())?;
+        Ok(())
+    }
+
+    /// List all ACL rules
+    pub fn list_acl_rules(&self) -> Result<Vec<AclRule>> {
+        let cf = self.db.cf_handle(CF_ACLS).unwrap();
+        let mut rules = Vec::new();
+        for item in self.db.iterator_cf(cf, rocksdb::IteratorMode::Start) {
+            let (_, value_bytes) = item?;
+            let rule: AclRule = bincode::deserialize(&value_bytes)
+                .map_err(|e| crate::Error::MetadataCorrupted(e.to_string()))?;
+            rules.push(rule);
+        }
+        Ok(rules)
+    }
+
+    /// Replace the rules of the in-memory `ACL_STORE` with the persisted ones
+    pub fn load_acl_rules(&self) -> Result<()> {
+        ACL_STORE.load_rules(self.list_acl_rules()?);
+        Ok(())
+    }
+
+    // === etcd gateway ===
+
+    /// Revision of the last change to the etcd keys, 0 before any
+    pub fn etcd_revision(&self) -> Result<i64> {
+        let cf = self.db.cf_handle(CF_ETCD).unwrap();
+        match self.db.get_cf(cf, ETCD_REVISION_KEY)? {
+            Some(bytes) => {
+                let bytes: [u8; 8] = bytes.as_slice().try_into().map_err(|_| {
+                    crate::Error::MetadataCorrupted("invalid etcd revision".to_string())
+                })?;
+                Ok(i64::from_be_bytes(bytes))
+            }
+            None => Ok(0),
+        }
+    }
+
+    /// The etcd key `key`, or the keys in `[key, end)`, in key order
+    pub fn etcd_range(&self, key: &[u8], end: Option<&[u8]>) -> Result<Vec<EtcdKeyValue>> {
+        let cf = self.db.cf_handle(CF_ETCD).unwrap();
+        let start = etcd_kv_key(key);
+        let iter = self.db.iterator_cf(
+            cf,
+            rocksdb::IteratorMode::From(&start, rocksdb::Direction::Forward),
+        );
+        let mut kvs = Vec::new();
+        for item in iter {
+            let (stored, value_bytes) = item?;
+            let Some(stored) = stored.strip_prefix(ETCD_KV_PREFIX) else {
+                break;
+            };
+            let in_range = match end {
+                Some(end) => stored < end,
+                None => stored == key,
+            };
+            if !in_range {
+                break;
+            }
+            let kv: EtcdKeyValue = bincode::deserialize(&value_bytes)
+                .map_err(|e| crate::Error::MetadataCorrupted(e.to_string()))?;
+            kvs.push(kv);
+        }
+        Ok(kvs)
+    }
+
+    /// Get an etcd lease
+    pub fn get_etcd_lease(&self, id: i64) -> Result<Option<EtcdLease>> {
+        let cf = self.db.cf_handle(CF_ETCD).unwrap();
+        match self.db.get_cf(cf, etcd_lease_key(id))? {
+            Some(bytes) => {
+                Ok(Some(bincode::deserialize(&bytes).map_err(|e| {
+                    crate::Error::MetadataCorrupted(e.to_string())
+                })?))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// List all etcd leases
+    pub fn list_etcd_leases(&self) -> Result<Vec<EtcdLease>> {
+        let cf = self.db.cf_handle(CF_ETCD).unwrap();
+        let iter = self.db.iterator_cf(
+            cf,
+            rocksdb::IteratorMode::From(ETCD_LEASE_PREFIX, rocksdb::Direction::Forward),
+        );
+        let mut leases = Vec::new();
+        for item in iter {
+            let (key, value_bytes) = item?;
+            if !key.starts_with(ETCD_LEASE_PREFIX) {
+                break;
+            }
+            let lease: EtcdLease = bincode::deserialize(&value_bytes)
+                .map_err(|e| crate::Error::MetadataCorrupted(e.to_string()))?;
+            leases.push(lease);
+        }
+        Ok(leases)
+    }
+
+    /// Changes to etcd keys from now on
+    pub fn watch_etcd(&self) -> broadcast::Receiver<EtcdChange> {
+        self.etcd_events.subscribe()
+    }
+
+    /// Apply an etcd command as the change of revision `revision`, then
+    /// publish its events to the watchers. Changes already made by a
+    /// later revision are left alone, so re-applying a command is harmless.
+    fn apply_etcd(&self, revision: i64, command: &MetadataCommand) -> Result<()> {
+        let cf = self.db.cf_handle(CF_ETCD).unwrap();
+        let mut batch = WriteBatch::default();
+        let mut events = Vec::new();
+        let mut deleted = Vec::new();
+        match command {
+            MetadataCommand::EtcdPut { key, value, lease } => {
+                if *lease != 0 && self.get_etcd_lease(*lease)?.is_none() {
+                    return Ok(());
+                }
+                let prev = self.etcd_range(key, None)?.pop();
+                if prev
+                    .as_ref()
+                    .is_some_and(|prev| prev.mod_revision >= revision)
+                {
+                    return Ok(());
+                }
+                let kv = EtcdKeyValue {
+                    key: key.clone(),
+                    value: value.clone(),
+                    create_revision: prev.as_ref().map_or(revision, |p| p.create_revision),
+                    mod_revision: revision,
+                    version: prev.as_ref().map_or(1, |p| p.version + 1),
+                    lease: *lease,
+                };
+                batch.put_cf(cf, etcd_kv_key(key), encode_etcd(&kv)?);
+                events.push(EtcdEvent::put(kv, prev));
+            }
+            MetadataCommand::EtcdDeleteRange { key, range_end } => {
+                deleted = self.etcd_range(key, range_end.as_deref())?;
+            }
+            MetadataCommand::EtcdLeaseGrant(lease) => {
+                batch.put_cf(cf, etcd_lease_key(lease.id), encode_etcd(lease)?);
+            }
+            MetadataCommand::EtcdLeaseRevoke(id) => {
+                let Some(lease) = self.get_etcd_lease(*id)? else {
+                    return Ok(());
+                };
+                batch.delete_cf(cf, etcd_lease_key(*id));
+                let (start, end) = etcd::tenant_range(&lease.tenant);
+                deleted = self.etcd_range(&start, Some(end.as_slice()))?;
+                deleted.retain(|kv| kv.lease == *id);
+            }
+            _ => return Ok(()),
+        }
+        for kv in deleted {
+            if kv.mod_revision < revision {
+                batch.delete_cf(cf, etcd_kv_key(&kv.key));
+                events.push(EtcdEvent::deleted(kv, revision));
+            }
+        }
+        // Revisions only move for changes to keys
+        if !events.is_empty() && revision > self.etcd_revision()? {
+            batch.put_cf(cf, ETCD_REVISION_KEY, revision.to_be_bytes());
+        }
+        self.db.write(batch)?;
+        if !events.is_empty() {
+            // No receiver is no error
+            let _ = self.etcd_events.send(EtcdChange { revision, events });
+        }
+        Ok(())
+    }
+
     // === Config operations ===
 
     /// Put config value
@@ -194,6 +1034,378 @@ impl MetadataStore {
         Ok(self.db.get_cf(cf, key.as_bytes())?)
     }
 
+    // === Transaction operations ===
+
+    /// Record or update an in-flight transaction
+    pub fn put_txn(&self, txn: &TxnRecord) -> Result<()> {
+        let cf = self.db.cf_handle(CF_TXNS).unwrap();
+        let value = bincode::serialize(txn)
+            .map_err(|e| crate::Error::Internal(format!("Serialize error: {}", e)))?;
+        self.db.put_cf(cf, txn.upload_id.as_bytes(), value)?;
+        Ok(())
+    }
+
+    /// Get an in-flight transaction
+    pub fn get_txn(&self, upload_id: &str) -> Result<Option<TxnRecord>> {
+        let cf = self.db.cf_handle(CF_TXNS).unwrap();
+        match self.db.get_cf(cf, upload_id.as_bytes())? {
+            Some(bytes) => {
+                let txn: TxnRecord = bincode::deserialize(&bytes)
+                    .map_err(|e| crate::Error::MetadataCorrupted(e.to_string()))?;
+                Ok(Some(txn))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Forget a finished transaction
+    pub fn delete_txn(&self, upload_id: &str) -> Result<()> {
+        let cf = self.db.cf_handle(CF_TXNS).unwrap();
+        self.db.delete_cf(cf, upload_id.as_bytes())?;
+        Ok(())
+    }
+
+    /// List all in-flight transactions (used by recovery)
+    pub fn list_txns(&self) -> Result<Vec<TxnRecord>> {
+        let cf = self.db.cf_handle(CF_TXNS).unwrap();
+        let iter = self.db.iterator_cf(cf, rocksdb::IteratorMode::Start);
+
+        let mut txns = Vec::new();
+        for item in iter {
+            let (_, value_bytes) = item?;
+            let txn: TxnRecord = bincode::deserialize(&value_bytes)
+                .map_err(|e| crate::Error::MetadataCorrupted(e.to_string()))?;
+            txns.push(txn);
+        }
+
+        Ok(txns)
+    }
+
+    /// Apply a committed command to the state machine
+    pub fn apply(&self, command: &MetadataCommand) -> Result<()> {
+        match command {
+            MetadataCommand::PutKey(meta) => self.put_key(meta),
+            MetadataCommand::DeleteKey(key) => self.delete_key(key),
+            MetadataCommand::PutVolume(meta) => self.put_volume(meta),
+            MetadataCommand::DeleteVolume(volume_id) => self.delete_volume(volume_id),
+            MetadataCommand::PutConfig { key, value } => self.put_config(key, value),
+            MetadataCommand::PutTxn(txn) => self.put_txn(txn),
+            MetadataCommand::DeleteTxn(upload_id) => self.delete_txn(upload_id),
+            MetadataCommand::PutDedupKey { key, blob } => {
+                if self.get_key(&blob.key)?.is_none() {
+                    self.put_key(blob)?;
+                }
+                self.put_key(key)
+            }
+            MetadataCommand::PutApiKey(record) => {
+                self.put_api_key(record)?;
+                KEY_STORE.insert_key(record.clone().into());
+                Ok(())
+            }
+            MetadataCommand::DeleteApiKey(key_id) => {
+                self.delete_api_key(key_id)?;
+                KEY_STORE.remove_key(key_id);
+                Ok(())
+            }
+            MetadataCommand::PutQuota(quota) => {
+                self.put_quota(quota)?;
+                QUOTA_MANAGER.set_quota(quota.clone());
+                Ok(())
+            }
+            MetadataCommand::DeleteQuota(tenant) => {
+                self.delete_quota(tenant)?;
+                QUOTA_MANAGER.remove_quota(tenant);
+                Ok(())
+            }
+            MetadataCommand::PutAclRule(rule) => {
+                self.put_acl_rule(rule)?;
+                ACL_STORE.put_rule(rule.clone());
+                Ok(())
+            }
+            MetadataCommand::DeleteAclRule(id) => {
+                self.delete_acl_rule(id)?;
+                ACL_STORE.remove_rule(id);
+                Ok(())
+            }
+            MetadataCommand::TierKey(stub) => match self.get_key(&stub.key)? {
+                Some(current)
+                    if current.state == KeyState::Active
+                        && current.blake3 == stub.blake3
+                        && current.updated_at == stub.updated_at =>
+                {
+                    self.put_key(stub)
+                }
+                _ => Ok(()),
+            },
+            MetadataCommand::EtcdPut { .. }
+            | MetadataCommand::EtcdDeleteRange { .. }
+            | MetadataCommand::EtcdLeaseGrant(_)
+            | MetadataCommand::EtcdLeaseRevoke(_) => {
+                self.apply_etcd(self.etcd_revision()? + 1, command)
+            }
+        }
+    }
+
+    /// Apply a command committed at Raft index `index`, recording key
+    /// changes in the CDC log under that index and notifying watchers
+    pub fn apply_at(&self, index: u64, command: &MetadataCommand) -> Result<()> {
+        // etcd revisions are the Raft indexes of the changes
+        if command.is_etcd() {
+            return self.apply_etcd(index as i64, command);
+        }
+        self.apply(command)?;
+        let Some(event) = CdcEvent::from_command(index, command) else {
+            return Ok(());
+        };
+        self.put_cdc_event(&event)?;
+        if let Some(change) = event.watch_event() {
+            let _ = WATCH_CHANNEL.send(change);
+        }
+        Ok(())
+    }
+
+    // === CDC log ===
+
+    /// Record a key change event
+    pub fn put_cdc_event(&self, event: &CdcEvent) -> Result<()> {
+        let cf = self.db.cf_handle(CF_CDC).unwrap();
+        let value = bincode::serialize(event)
+            .map_err(|e| crate::Error::Internal(format!("Serialize error: {}", e)))?;
+        self.db.put_cf(cf, event.seq.to_be_bytes(), value)?;
+        Ok(())
+    }
+
+    /// Up to `limit` events with a sequence number of at least `from`, in order
+    pub fn cdc_events(&self, from: u64, limit: usize) -> Result<Vec<CdcEvent>> {
+        let cf = self.db.cf_handle(CF_CDC).unwrap();
+        let start = from.to_be_bytes();
+        let iter = self.db.iterator_cf(
+            cf,
+            rocksdb::IteratorMode::From(&start[..], rocksdb::Direction::Forward),
+        );
+        let mut events = Vec::new();
+        for item in iter.take(limit) {
+            let (_, value) = item?;
+            events.push(
+                bincode::deserialize(&value)
+                    .map_err(|e| crate::Error::MetadataCorrupted(e.to_string()))?,
+            );
+        }
+        Ok(events)
+    }
+
+    /// Sequence number of the latest recorded event, 0 when the log is empty
+    pub fn last_cdc_seq(&self) -> Result<u64> {
+        let cf = self.db.cf_handle(CF_CDC).unwrap();
+        match self.db.iterator_cf(cf, rocksdb::IteratorMode::End).next() {
+            Some(item) => {
+                let (seq, _) = item?;
+                let seq: [u8; 8] = seq.as_ref().try_into().map_err(|_| {
+                    crate::Error::MetadataCorrupted("invalid CDC sequence number".to_string())
+                })?;
+                Ok(u64::from_be_bytes(seq))
+            }
+            None => Ok(0),
+        }
+    }
+
+    /// Drop the events recorded before `before` (Unix seconds). Returns how many were dropped.
+    pub fn trim_cdc(&self, before: u64) -> Result<usize> {
+        let cf = self.db.cf_handle(CF_CDC).unwrap();
+        let mut batch = WriteBatch::default();
+        let mut trimmed = 0;
+        for item in self.db.iterator_cf(cf, rocksdb::IteratorMode::Start) {
+            let (seq, value) = item?;
+            let event: CdcEvent = bincode::deserialize(&value)
+                .map_err(|e| crate::Error::MetadataCorrupted(e.to_string()))?;
+            if event.timestamp >= before {
+                break;
+            }
+            batch.delete_cf(cf, seq);
+            trimmed += 1;
+        }
+        self.db.write(batch)?;
+        Ok(trimmed)
+    }
+
+    // === Raft operations ===
+    //
+    // Raft state is written with fsync: a node must not answer a vote or an
+    // append before the change is on disk.
+
+    fn sync_write_opts() -> WriteOptions {
+        let mut opts = WriteOptions::default();
+        opts.set_sync(true);
+        opts
+    }
+
+    /// Persist the current term and vote
+    pub fn save_hard_state(&self, state: &HardState) -> Result<()> {
+        let cf = self.db.cf_handle(CF_RAFT).unwrap();
+        let value = bincode::serialize(state)
+            .map_err(|e| crate::Error::Internal(format!("Serialize error: {}", e)))?;
+        self.db
+            .put_cf_opt(cf, HARD_STATE_KEY, value, &Self::sync_write_opts())?;
+        Ok(())
+    }
+
+    /// Load the persisted term and vote, if any
+    pub fn load_hard_state(&self) -> Result<Option<HardState>> {
+        let cf = self.db.cf_handle(CF_RAFT).unwrap();
+        match self.db.get_cf(cf, HARD_STATE_KEY)? {
+            Some(bytes) => bincode::deserialize(&bytes)
+                .map(Some)
+                .map_err(|e| crate::Error::MetadataCorrupted(e.to_string())),
+            None => Ok(None),
+        }
+    }
+
+    /// Append log entries. Entries are keyed by index (big-endian, so they
+    /// iterate in order); an entry at an existing index replaces it.
+    pub fn append_raft_log(&self, entries: &[LogEntry]) -> Result<()> {
+        if entries.is_empty() {
+            return Ok(());
+        }
+        let cf = self.db.cf_handle(CF_RAFT_LOG).unwrap();
+        let mut batch = WriteBatch::default();
+        for entry in entries {
+            let value = bincode::serialize(entry)
+                .map_err(|e| crate::Error::Internal(format!("Serialize error: {}", e)))?;
+            batch.put_cf(cf, entry.index.to_be_bytes(), value);
+        }
+        self.db.write_opt(batch, &Self::sync_write_opts())?;
+        Ok(())
+    }
+
+    /// Remove log entries with index `from_index` and above (conflicting suffix)
+    pub fn truncate_raft_log(&self, from_index: u64) -> Result<()> {
+        let cf = self.db.cf_handle(CF_RAFT_LOG).unwrap();
+        let mut batch = WriteBatch::default();
+        for item in self.db.iterator_cf(
+            cf,
+            rocksdb::IteratorMode::From(&from_index.to_be_bytes(), rocksdb::Direction::Forward),
+        ) {
+            let (key, _) = item?;
+            batch.delete_cf(cf, key);
+        }
+        self.db.write_opt(batch, &Self::sync_write_opts())?;
+        Ok(())
+    }
+
+    /// Drop log entries up to and including `up_to_index` (covered by a snapshot)
+    pub fn compact_raft_log(&self, up_to_index: u64) -> Result<()> {
+        let cf = self.db.cf_handle(CF_RAFT_LOG).unwrap();
+        let mut batch = WriteBatch::default();
+        for item in self.db.iterator_cf(cf, rocksdb::IteratorMode::Start) {
+            let (key, _) = item?;
+            let index = key
+                .as_ref()
+                .try_into()
+                .map(u64::from_be_bytes)
+                .map_err(|_| crate::Error::MetadataCorrupted("Invalid log index".into()))?;
+            if index > up_to_index {
+                break;
+            }
+            batch.delete_cf(cf, key);
+        }
+        self.db.write_opt(batch, &Self::sync_write_opts())?;
+        Ok(())
+    }
+
+    /// Persist a state machine snapshot
+    pub fn save_snapshot(&self, meta: &SnapshotMeta, data: &[u8]) -> Result<()> {
+        let cf = self.db.cf_handle(CF_RAFT).unwrap();
+        let meta_bytes = bincode::serialize(meta)
+            .map_err(|e| crate::Error::Internal(format!("Serialize error: {}", e)))?;
+        let mut batch = WriteBatch::default();
+        batch.put_cf(cf, SNAPSHOT_META_KEY, meta_bytes);
+        batch.put_cf(cf, SNAPSHOT_DATA_KEY, data);
+        self.db.write_opt(batch, &Self::sync_write_opts())?;
+        Ok(())
+    }
+
+    /// Position of the latest snapshot, if any
+    pub fn load_snapshot_meta(&self) -> Result<Option<SnapshotMeta>> {
+        let cf = self.db.cf_handle(CF_RAFT).unwrap();
+        match self.db.get_cf(cf, SNAPSHOT_META_KEY)? {
+            Some(bytes) => bincode::deserialize(&bytes)
+                .map(Some)
+                .map_err(|e| crate::Error::MetadataCorrupted(e.to_string())),
+            None => Ok(None),
+        }
+    }
+
+    /// Latest snapshot with its data
+    pub fn load_snapshot(&self) -> Result<Option<(SnapshotMeta, Vec<u8>)>> {
+        let Some(meta) = self.load_snapshot_meta()? else {
+            return Ok(None);
+        };
+        let cf = self.db.cf_handle(CF_RAFT).unwrap();
+        match self.db.get_cf(cf, SNAPSHOT_DATA_KEY)? {
+            Some(data) => Ok(Some((meta, data))),
+            None => Err(crate::Error::MetadataCorrupted(
+                "snapshot data missing".into(),
+            )),
+        }
+    }
+
+    /// Serialize the whole state machine (keys, volumes, config, transactions)
+    pub fn export_state(&self) -> Result<Vec<u8>> {
+        let mut dump: StateDump = Vec::new();
+        for name in STATE_CFS {
+            let cf = self.db.cf_handle(name).unwrap();
+            for item in self.db.iterator_cf(cf, rocksdb::IteratorMode::Start) {
+                let (key, value) = item?;
+                dump.push((name.to_string(), key.to_vec(), value.to_vec()));
+            }
+        }
+        bincode::serialize(&dump)
+            .map_err(|e| crate::Error::Internal(format!("Serialize error: {}", e)))
+    }
+
+    /// Replace the whole state machine with an exported one, atomically
+    pub fn restore_state(&self, data: &[u8]) -> Result<()> {
+        let dump: StateDump = bincode::deserialize(data)
+            .map_err(|e| crate::Error::MetadataCorrupted(e.to_string()))?;
+
+        let mut batch = WriteBatch::default();
+        for name in STATE_CFS {
+            let cf = self.db.cf_handle(name).unwrap();
+            for item in self.db.iterator_cf(cf, rocksdb::IteratorMode::Start) {
+                let (key, _) = item?;
+                batch.delete_cf(cf, key);
+            }
+        }
+        for (name, key, value) in dump {
+            if !STATE_CFS.contains(&name.as_str()) {
+                return Err(crate::Error::MetadataCorrupted(format!(
+                    "unknown column family in snapshot: {}",
+                    name
+                )));
+            }
+            let cf = self.db.cf_handle(&name).unwrap();
+            batch.put_cf(cf, key, value);
+        }
+        self.db.write_opt(batch, &Self::sync_write_opts())?;
+        Ok(())
+    }
+
+    /// Load the whole persisted log, in index order
+    pub fn load_raft_log(&self) -> Result<Vec<LogEntry>> {
+        let cf = self.db.cf_handle(CF_RAFT_LOG).unwrap();
+        let iter = self.db.iterator_cf(cf, rocksdb::IteratorMode::Start);
+
+        let mut entries = Vec::new();
+        for item in iter {
+            let (_, value_bytes) = item?;
+            let entry: LogEntry = bincode::deserialize(&value_bytes)
+                .map_err(|e| crate::Error::MetadataCorrupted(e.to_string()))?;
+            entries.push(entry);
+        }
+
+        Ok(entries)
+    }
+
     /// Flush to disk
     pub fn flush(&self) -> Result<()> {
         self.db.flush()?;
@@ -220,6 +1432,9 @@ mod tests {
             created_at: 1234567890,
             updated_at: 1234567890,
             state: KeyState::Active,
+            tags: BTreeMap::new(),
+            blob: None,
+            chunks: None,
         };
         store.put_key(&meta).unwrap();
 
@@ -233,6 +1448,365 @@ mod tests {
         assert!(store.get_key("test-key").unwrap().is_none());
     }
 
+    #[test]
+    fn test_list_keys_paginated() {
+        let dir = tempdir().unwrap();
+        let store = MetadataStore::open(dir.path().join("test.db")).unwrap();
+
+        for key in ["a/1", "a/2", "a/3", "b/1"] {
+            store
+                .put_key(&KeyMetadata {
+                    key: key.to_string(),
+                    replicas: vec!["vol-1".to_string()],
+                    size: 1,
+                    blake3: String::new(),
+                    created_at: 0,
+                    updated_at: 0,
+                    state: KeyState::Active,
+                    tags: BTreeMap::new(),
+                    blob: None,
+                    chunks: None,
+                })
+                .unwrap();
+        }
+        let keys = |page: Vec<KeyMetadata>| page.into_iter().map(|m| m.key).collect::<Vec<_>>();
+
+        let first = store.list_keys_paginated("a/", None, 2).unwrap();
+        assert_eq!(keys(first), vec!["a/1", "a/2"]);
+        let second = store.list_keys_paginated("a/", Some("a/2"), 2).unwrap();
+        assert_eq!(keys(second), vec!["a/3"]);
+
+        // A cursor before the prefix starts at the prefix
+        let all = store.list_keys_paginated("b/", Some("a/9"), 10).unwrap();
+        assert_eq!(keys(all), vec!["b/1"]);
+        assert_eq!(store.list_keys_paginated("", None, 10).unwrap().len(), 4);
+        let range = store.scan_key_range("a/2", "b/1", 10).unwrap();
+        assert_eq!(keys(range), vec!["a/2", "a/3", "b/1"]);
+        let range = store.scan_key_range("a/2\0", "b/0", 10).unwrap();
+        assert_eq!(keys(range), vec!["a/3"]);
+        assert_eq!(page_limit(None), DEFAULT_PAGE_SIZE);
+        assert_eq!(page_limit(Some(1_000_000)), MAX_PAGE_SIZE);
+    }
+
+    #[test]
+    fn test_cdc_log() {
+        let dir = tempdir().unwrap();
+        let store = MetadataStore::open(dir.path().join("test.db")).unwrap();
+
+        let put = MetadataCommand::PutKey(KeyMetadata {
+            key: "a".to_string(),
+            replicas: vec!["vol-1".to_string()],
+            size: 3,
+            blake3: "abc".to_string(),
+            created_at: 0,
+            updated_at: 0,
+            state: KeyState::Active,
+            tags: BTreeMap::new(),
+            blob: None,
+            chunks: None,
+        });
+        store.apply_at(5, &put).unwrap();
+        store
+            .apply_at(
+                6,
+                &MetadataCommand::PutConfig {
+                    key: "x".to_string(),
+                    value: vec![],
+                },
+            )
+            .unwrap();
+        store
+            .apply_at(7, &MetadataCommand::DeleteKey("a".to_string()))
+            .unwrap();
+
+        // Only key changes are recorded, under their Raft index
+        let events = store.cdc_events(0, 10).unwrap();
+        let seqs: Vec<u64> = events.iter().map(|e| e.seq).collect();
+        assert_eq!(seqs, vec![5, 7]);
+        assert_eq!(events[0].size, Some(3));
+        assert_eq!(store.cdc_events(6, 10).unwrap()[0].seq, 7);
+        assert_eq!(store.cdc_events(0, 1).unwrap().len(), 1);
+        assert_eq!(store.last_cdc_seq().unwrap(), 7);
+
+        // Re-applying an entry rewrites the same event
+        store.apply_at(5, &put).unwrap();
+        assert_eq!(store.cdc_events(0, 10).unwrap().len(), 2);
+
+        assert_eq!(store.trim_cdc(0).unwrap(), 0);
+        assert_eq!(store.trim_cdc(u64::MAX).unwrap(), 2);
+        assert!(store.cdc_events(0, 10).unwrap().is_empty());
+        assert_eq!(store.last_cdc_seq().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_tag_index() {
+        let dir = tempdir().unwrap();
+        let store = MetadataStore::open(dir.path().join("test.db")).unwrap();
+        let tagged = |key: &str, tags: &[(&str, &str)]| KeyMetadata {
+            key: key.to_string(),
+            replicas: vec!["vol-1".to_string()],
+            size: 1,
+            blake3: String::new(),
+            created_at: 0,
+            updated_at: 0,
+            state: KeyState::Active,
+            tags: tags
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            blob: None,
+            chunks: None,
+        };
+
+        for key in ["a", "b", "c"] {
+            store
+                .put_key(&tagged(key, &[("env", "prod"), ("team", "x")]))
+                .unwrap();
+        }
+        store
+            .put_key(&tagged("d", &[("env", "production")]))
+            .unwrap();
+
+        assert_eq!(
+            store.keys_with_tag("env", "prod", None, 10).unwrap(),
+            vec!["a", "b", "c"]
+        );
+        assert_eq!(
+            store.keys_with_tag("env", "prod", None, 2).unwrap(),
+            vec!["a", "b"]
+        );
+        assert_eq!(
+            store.keys_with_tag("env", "prod", Some("b"), 2).unwrap(),
+            vec!["c"]
+        );
+
+        // Retagging and deleting drop the old index entries
+        store.put_key(&tagged("a", &[("env", "dev")])).unwrap();
+        store.delete_key("b").unwrap();
+        assert_eq!(
+            store.keys_with_tag("env", "prod", None, 10).unwrap(),
+            vec!["c"]
+        );
+        assert_eq!(
+            store.keys_with_tag("env", "dev", None, 10).unwrap(),
+            vec!["a"]
+        );
+        assert_eq!(
+            store.keys_with_tag("team", "x", None, 10).unwrap(),
+            vec!["c"]
+        );
+
+        // Records written before tags existed still decode
+        #[derive(Serialize)]
+        struct Legacy(String, Vec<String>, u64, String, u64, u64, KeyState);
+        let legacy = Legacy(
+            "old".into(),
+            vec![],
+            1,
+            String::new(),
+            0,
+            0,
+            KeyState::Active,
+        );
+        let meta = KeyMetadata::decode(&bincode::serialize(&legacy).unwrap()).unwrap();
+        assert_eq!(meta.key, "old");
+        assert!(meta.tags.is_empty());
+        let entry = bincode::serialize(&(0u32, &legacy)).unwrap();
+        assert!(matches!(
+            MetadataCommand::decode(&entry).unwrap(),
+            MetadataCommand::PutKey(m) if m.key == "old"
+        ));
+    }
+
+    #[test]
+    fn test_blob_refcounts() {
+        let dir = tempdir().unwrap();
+        let store = MetadataStore::open(dir.path().join("test.db")).unwrap();
+        let meta = |key: &str, replicas: &[&str], blob: Option<&str>| KeyMetadata {
+            key: key.to_string(),
+            replicas: replicas.iter().map(|r| r.to_string()).collect(),
+            size: 3,
+            blake3: "ab12".to_string(),
+            created_at: 0,
+            updated_at: 0,
+            state: KeyState::Active,
+            tags: BTreeMap::new(),
+            blob: blob.map(str::to_string),
+            chunks: None,
+        };
+        let content = meta(".cas/acme/ab12", &["vol-1"], None);
+
+        for key in ["acme/a", "acme/b"] {
+            store
+                .apply(&MetadataCommand::PutDedupKey {
+                    key: meta(key, &[], Some(".cas/acme/ab12")),
+                    blob: content.clone(),
+                })
+                .unwrap();
+        }
+        assert_eq!(store.blob_refs(".cas/acme/ab12").unwrap(), 2);
+
+        // Rewriting a reference in place doesn't count twice
+        store
+            .put_key(&meta("acme/a", &[], Some(".cas/acme/ab12")))
+            .unwrap();
+        assert_eq!(store.blob_refs(".cas/acme/ab12").unwrap(), 2);
+
+        // Overwriting with a plain value or deleting drops the reference,
+        // and the last one takes the content's metadata with it
+        store.put_key(&meta("acme/a", &["vol-2"], None)).unwrap();
+        assert_eq!(store.blob_refs(".cas/acme/ab12").unwrap(), 1);
+        assert!(store.get_key(".cas/acme/ab12").unwrap().is_some());
+        store.delete_key("acme/b").unwrap();
+        assert_eq!(store.blob_refs(".cas/acme/ab12").unwrap(), 0);
+        assert!(store.get_key(".cas/acme/ab12").unwrap().is_none());
+
+        // A reference to collected content brings its metadata back
+        store
+            .apply(&MetadataCommand::PutDedupKey {
+                key: meta("acme/c", &[], Some(".cas/acme/ab12")),
+                blob: content.clone(),
+            })
+            .unwrap();
+        assert_eq!(store.blob_refs(".cas/acme/ab12").unwrap(), 1);
+        assert_eq!(
+            store.get_key(".cas/acme/ab12").unwrap().unwrap().replicas,
+            vec!["vol-1"]
+        );
+
+        // Records written before deduplication existed still decode
+        #[derive(Serialize)]
+        struct Legacy(
+            String,
+            Vec<String>,
+            u64,
+            String,
+            u64,
+            u64,
+            KeyState,
+            BTreeMap<String, String>,
+        );
+        let legacy = Legacy(
+            "old".into(),
+            vec!["vol-1".into()],
+            1,
+            String::new(),
+            0,
+            0,
+            KeyState::Active,
+            [("env".to_string(), "prod".to_string())].into(),
+        );
+        let decoded = KeyMetadata::decode(&bincode::serialize(&legacy).unwrap()).unwrap();
+        assert_eq!(decoded.tags["env"], "prod");
+        assert!(decoded.blob.is_none());
+        let entry = bincode::serialize(&(0u32, &legacy)).unwrap();
+        assert!(matches!(
+            MetadataCommand::decode(&entry).unwrap(),
+            MetadataCommand::PutKey(m) if m.key == "old" && m.tags.len() == 1
+        ));
+    }
+
+    #[test]
+    fn test_chunk_manifest() {
+        let dir = tempdir().unwrap();
+        let store = MetadataStore::open(dir.path().join("test.db")).unwrap();
+        let meta = |key: &str, chunks: Option<ChunkManifest>| KeyMetadata {
+            key: key.to_string(),
+            replicas: if chunks.is_some() {
+                vec![]
+            } else {
+                vec!["vol-1".to_string()]
+            },
+            size: 3,
+            blake3: "ab12".to_string(),
+            created_at: 0,
+            updated_at: 0,
+            state: KeyState::Active,
+            tags: BTreeMap::new(),
+            blob: None,
+            chunks,
+        };
+        let manifest = |upload: &str| ChunkManifest {
+            chunk_size: 2,
+            chunks: vec![
+                format!(".chunks/acme/{}/0", upload),
+                format!(".chunks/acme/{}/1", upload),
+            ],
+        };
+        let write = |upload: &str| {
+            for chunk in manifest(upload).chunks {
+                store.put_key(&meta(&chunk, None)).unwrap();
+            }
+            store
+                .put_key(&meta("acme/big", Some(manifest(upload))))
+                .unwrap();
+        };
+
+        write("u1");
+        let stored = store.get_key("acme/big").unwrap().unwrap();
+        assert_eq!(stored.chunks, Some(manifest("u1")));
+
+        // Rewriting the same manifest keeps the chunks, a new one drops them
+        store
+            .put_key(&meta("acme/big", Some(manifest("u1"))))
+            .unwrap();
+        assert!(store.get_key(".chunks/acme/u1/0").unwrap().is_some());
+        write("u2");
+        assert!(store.get_key(".chunks/acme/u1/0").unwrap().is_none());
+        assert!(store.get_key(".chunks/acme/u1/1").unwrap().is_none());
+        assert!(store.get_key(".chunks/acme/u2/1").unwrap().is_some());
+
+        // And so does deleting the key
+        store.delete_key("acme/big").unwrap();
+        assert!(store.get_key(".chunks/acme/u2/0").unwrap().is_none());
+
+        // Records and log entries written before chunking existed still decode
+        #[derive(Serialize)]
+        struct Legacy(
+            String,
+            Vec<String>,
+            u64,
+            String,
+            u64,
+            u64,
+            KeyState,
+            BTreeMap<String, String>,
+            Option<String>,
+        );
+        let legacy = |key: &str, blob: Option<&str>| {
+            Legacy(
+                key.into(),
+                vec![],
+                1,
+                String::new(),
+                0,
+                0,
+                KeyState::Active,
+                BTreeMap::new(),
+                blob.map(str::to_string),
+            )
+        };
+        let reference = legacy("acme/a", Some(".cas/acme/ab12"));
+        let decoded = KeyMetadata::decode(&bincode::serialize(&reference).unwrap()).unwrap();
+        assert_eq!(decoded.blob.as_deref(), Some(".cas/acme/ab12"));
+        assert!(decoded.chunks.is_none());
+        let entry = bincode::serialize(&(0u32, &reference)).unwrap();
+        assert!(matches!(
+            MetadataCommand::decode(&entry).unwrap(),
+            MetadataCommand::PutKey(m) if m.blob.is_some()
+        ));
+        let content = legacy(
+            ".cas/acme/ab12ab12ab12ab12ab12ab12ab12ab12ab12ab12ab12ab12ab12ab12ab12ab12",
+            None,
+        );
+        let entry = bincode::serialize(&(7u32, &reference, &content)).unwrap();
+        assert!(matches!(
+            MetadataCommand::decode(&entry).unwrap(),
+            MetadataCommand::PutDedupKey { key, blob }
+                if key.key == "acme/a" && blob.key.starts_with(".cas/")
+        ));
+    }
+
     #[test]
     fn test_volume_registry() {
         let dir = tempdir().unwrap();
@@ -248,6 +1822,10 @@ mod tests {
             total_bytes: 1024000,
             free_bytes: 5000000,
             last_heartbeat: 1234567890,
+            zone: Some("eu-west-1a".to_string()),
+            rack: None,
+            weight: None,
+            wal_error: None,
         };
 
         store.put_volume(&vol).unwrap();
@@ -255,8 +1833,337 @@ mod tests {
         let retrieved = store.get_volume("vol-1").unwrap().unwrap();
         assert_eq!(retrieved.volume_id, "vol-1");
         assert_eq!(retrieved.shards.len(), 3);
+        assert_eq!(retrieved.zone.as_deref(), Some("eu-west-1a"));
 
         let volumes = store.list_volumes().unwrap();
         assert_eq!(volumes.len(), 1);
+
+        store.delete_volume("vol-1").unwrap();
+        assert!(store.get_volume("vol-1").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_api_keys() {
+        let dir = tempdir().unwrap();
+        let store = MetadataStore::open(dir.path().join("test.db")).unwrap();
+
+        let (api_key, plaintext) = KEY_STORE
+            .create_key("metadata-test", "acme", crate::common::Role::ReadOnly, None)
+            .unwrap();
+        let mut record = ApiKeyRecord::from(&api_key);
+        store
+            .apply(&MetadataCommand::PutApiKey(record.clone()))
+            .unwrap();
+        assert_eq!(store.get_api_key(&record.id).unwrap().unwrap(), record);
+        // Applying updates the in-memory store as well
+        assert!(KEY_STORE.get_key(&record.id).unwrap().active);
+
+        record.active = false;
+        store
+            .apply(&MetadataCommand::PutApiKey(record.clone()))
+            .unwrap();
+        assert!(!KEY_STORE.get_key(&record.id).unwrap().active);
+
+        // Survives a restart, hash included
+        drop(store);
+        let store = MetadataStore::open(dir.path().join("test.db")).unwrap();
+        let persisted = store.list_api_keys().unwrap();
+        assert_eq!(persisted, vec![record.clone()]);
+        let restarted = crate::common::KeyStore::new();
+        restarted.load_keys(persisted.into_iter().map(ApiKey::from));
+        assert!(matches!(
+            restarted.validate_key(&plaintext),
+            crate::common::AuthResult::Invalid(_)
+        ));
+
+        store
+            .apply(&MetadataCommand::DeleteApiKey(record.id.clone()))
+            .unwrap();
+        assert!(store.get_api_key(&record.id).unwrap().is_none());
+        assert!(KEY_STORE.get_key(&record.id).is_none());
+    }
+
+    #[test]
+    fn test_quotas() {
+        let dir = tempdir().unwrap();
+        let store = MetadataStore::open(dir.path().join("test.db")).unwrap();
+
+        let quota = TenantQuota::with_limits("metadata-test".to_string(), 1024, 10, 5);
+        store
+            .apply(&MetadataCommand::PutQuota(quota.clone()))
+            .unwrap();
+        assert_eq!(
+            store
+                .get_quota("metadata-test")
+                .unwrap()
+                .unwrap()
+                .storage_limit,
+            1024
+        );
+        // Applying updates the in-memory manager as well
+        assert_eq!(
+            QUOTA_MANAGER
+                .get_quota("metadata-test")
+                .unwrap()
+                .object_limit,
+            10
+        );
+
+        // Survives a restart
+        drop(store);
+        let store = MetadataStore::open(dir.path().join("test.db")).unwrap();
+        let persisted = store.list_quotas().unwrap();
+        assert_eq!(persisted.len(), 1);
+        assert_eq!(persisted[0].tenant_id, "metadata-test");
+        assert_eq!(persisted[0].rate_limit, 5);
+
+        store
+            .apply(&MetadataCommand::DeleteQuota("metadata-test".to_string()))
+            .unwrap();
+        assert!(store.get_quota("metadata-test").unwrap().is_none());
+        assert!(QUOTA_MANAGER.get_quota("metadata-test").is_none());
+    }
+
+    #[test]
+    fn test_acl_rules() {
+        let dir = tempdir().unwrap();
+        let store = MetadataStore::open(dir.path().join("test.db")).unwrap();
+
+        let rule = AclRule {
+            id: "metadata-test".to_string(),
+            tenant: "acme".to_string(),
+            prefix: "invoices/".to_string(),
+            key_id: None,
+            min_role: crate::common::Role::ReadWrite,
+        };
+        store
+            .apply(&MetadataCommand::PutAclRule(rule.clone()))
+            .unwrap();
+        // Applying updates the in-memory store as well
+        assert_eq!(ACL_STORE.get_rule("metadata-test"), Some(rule.clone()));
+
+        // Survives a restart
+        drop(store);
+        let store = MetadataStore::open(dir.path().join("test.db")).unwrap();
+        assert_eq!(store.list_acl_rules().unwrap(), vec![rule]);
+
+        store
+            .apply(&MetadataCommand::DeleteAclRule("metadata-test".to_string()))
+            .unwrap();
+        assert!(store.list_acl_rules().unwrap().is_empty());
+        assert!(ACL_STORE.get_rule("metadata-test").is_none());
+    }
+
+    #[test]
+    fn test_etcd_revisions() {
+        let dir = tempdir().unwrap();
+        let store = MetadataStore::open(dir.path().join("test.db")).unwrap();
+        let put = |key: &str, lease| MetadataCommand::EtcdPut {
+            key: key.as_bytes().to_vec(),
+            value: b"v".to_vec(),
+            lease,
+        };
+
+        store.apply_at(3, &put("t/a", 0)).unwrap();
+        store.apply_at(5, &put("t/a", 0)).unwrap();
+        // Re-applied: the key is left as revision 5 made it
+        store.apply_at(3, &put("t/a", 0)).unwrap();
+        let kv = store.etcd_range(b"t/a", None).unwrap().pop().unwrap();
+        assert_eq!((kv.create_revision, kv.mod_revision, kv.version), (3, 5, 2));
+        assert_eq!(store.etcd_revision().unwrap(), 5);
+
+        // Keys can't be attached to unknown leases
+        store.apply_at(6, &put("t/b", 7)).unwrap();
+        assert!(store.etcd_range(b"t/b", None).unwrap().is_empty());
+        let lease = EtcdLease {
+            id: 7,
+            ttl: 10,
+            tenant: "t".to_string(),
+        };
+        store
+            .apply_at(7, &MetadataCommand::EtcdLeaseGrant(lease.clone()))
+            .unwrap();
+        store.apply_at(8, &put("t/b", 7)).unwrap();
+        assert_eq!(store.list_etcd_leases().unwrap(), vec![lease]);
+
+        // Revoking the lease deletes its keys
+        store
+            .apply_at(9, &MetadataCommand::EtcdLeaseRevoke(7))
+            .unwrap();
+        let keys: Vec<_> = store
+            .etcd_range(b"t/", Some(b"t0"))
+            .unwrap()
+            .into_iter()
+            .map(|kv| kv.key)
+            .collect();
+        assert_eq!(keys, vec![b"t/a".to_vec()]);
+        assert!(store.get_etcd_lease(7).unwrap().is_none());
+        assert_eq!(store.etcd_revision().unwrap(), 9);
+    }
+
+    #[test]
+    fn test_txn_log() {
+        let dir = tempdir().unwrap();
+        let store = MetadataStore::open(dir.path().join("test.db")).unwrap();
+
+        let mut txn = TxnRecord {
+            upload_id: "upload-1".to_string(),
+            key: "test-key".to_string(),
+            replicas: vec!["vol-1".to_string(), "vol-2".to_string()],
+            size: 1024,
+            blake3: "abc123".to_string(),
+            state: TxnState::Preparing,
+            created_at: 1234567890,
+            acks: vec![],
+        };
+        store.put_txn(&txn).unwrap();
+
+        txn.state = TxnState::Committing;
+        store.put_txn(&txn).unwrap();
+
+        let txns = store.list_txns().unwrap();
+        assert_eq!(txns.len(), 1);
+        assert_eq!(txns[0].state, TxnState::Committing);
+
+        store.delete_txn("upload-1").unwrap();
+        assert!(store.get_txn("upload-1").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_apply_command() {
+        let dir = tempdir().unwrap();
+        let store = MetadataStore::open(dir.path().join("test.db")).unwrap();
+
+        let put = MetadataCommand::PutConfig {
+            key: "a".to_string(),
+            value: b"1".to_vec(),
+        };
+        let decoded = MetadataCommand::decode(&put.encode().unwrap()).unwrap();
+        store.apply(&decoded).unwrap();
+        store.apply(&decoded).unwrap();
+        assert_eq!(store.get_config("a").unwrap().unwrap(), b"1");
+
+        store
+            .apply(&MetadataCommand::DeleteVolume("vol-1".to_string()))
+            .unwrap();
+        assert!(MetadataCommand::decode(b"not a command").is_err());
+    }
+
+    #[test]
+    fn test_tier_key_only_if_unchanged() {
+        let dir = tempdir().unwrap();
+        let store = MetadataStore::open(dir.path().join("test.db")).unwrap();
+        let meta = KeyMetadata {
+            key: "a/cold".to_string(),
+            replicas: vec!["vol-1".to_string()],
+            size: 3,
+            blake3: "h1".to_string(),
+            created_at: 10,
+            updated_at: 10,
+            state: KeyState::Active,
+            tags: BTreeMap::new(),
+            blob: None,
+            chunks: None,
+        };
+        let stub = KeyMetadata {
+            replicas: Vec::new(),
+            state: KeyState::Tiered,
+            ..meta.clone()
+        };
+        // Written again while the old value was uploaded
+        let rewritten = KeyMetadata {
+            blake3: "h2".to_string(),
+            updated_at: 20,
+            ..meta.clone()
+        };
+        store.put_key(&rewritten).unwrap();
+        store
+            .apply(&MetadataCommand::TierKey(stub.clone()))
+            .unwrap();
+        let current = store.get_key("a/cold").unwrap().unwrap();
+        assert_eq!(current.state, KeyState::Active);
+        assert_eq!(current.blake3, "h2");
+
+        store.put_key(&meta).unwrap();
+        let command =
+            MetadataCommand::decode(&MetadataCommand::TierKey(stub.clone()).encode().unwrap())
+                .unwrap();
+        store.apply(&command).unwrap();
+        let current = store.get_key("a/cold").unwrap().unwrap();
+        assert_eq!(current.state, KeyState::Tiered);
+        assert!(current.replicas.is_empty());
+        // Applied again (replayed), the stub is left as is
+        store.apply(&command).unwrap();
+        let current = store.get_key("a/cold").unwrap().unwrap();
+        assert_eq!(current.state, KeyState::Tiered);
+    }
+
+    #[test]
+    fn test_raft_log() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.db");
+        let entry = |index: u64, term: u64| LogEntry {
+            term,
+            index,
+            data: vec![index as u8],
+        };
+
+        {
+            let store = MetadataStore::open(&path).unwrap();
+            assert!(store.load_hard_state().unwrap().is_none());
+            store
+                .save_hard_state(&HardState {
+                    term: 3,
+                    voted_for: Some("node-2".to_string()),
+                })
+                .unwrap();
+            store
+                .append_raft_log(&[entry(1, 1), entry(2, 1), entry(3, 2), entry(256, 3)])
+                .unwrap();
+            store.truncate_raft_log(3).unwrap();
+            store.append_raft_log(&[entry(3, 3)]).unwrap();
+        }
+
+        let store = MetadataStore::open(&path).unwrap();
+        let hard_state = store.load_hard_state().unwrap().unwrap();
+        assert_eq!(hard_state.term, 3);
+        assert_eq!(hard_state.voted_for.as_deref(), Some("node-2"));
+        assert_eq!(
+            store.load_raft_log().unwrap(),
+            vec![entry(1, 1), entry(2, 1), entry(3, 3)]
+        );
+
+        store.compact_raft_log(2).unwrap();
+        assert_eq!(store.load_raft_log().unwrap(), vec![entry(3, 3)]);
+    }
+
+    #[test]
+    fn test_snapshot_restore() {
+        let dir = tempdir().unwrap();
+        let leader = MetadataStore::open(dir.path().join("leader.db")).unwrap();
+        let follower = MetadataStore::open(dir.path().join("follower.db")).unwrap();
+
+        leader.put_config("a", b"1").unwrap();
+        leader
+            .apply_at(9, &MetadataCommand::DeleteKey("t/k".to_string()))
+            .unwrap();
+        follower.put_config("stale", b"x").unwrap();
+
+        let meta = SnapshotMeta {
+            last_included_index: 10,
+            last_included_term: 2,
+        };
+        let data = leader.export_state().unwrap();
+        follower.restore_state(&data).unwrap();
+        follower.save_snapshot(&meta, &data).unwrap();
+
+        assert_eq!(follower.get_config("a").unwrap().unwrap(), b"1");
+        assert!(follower.get_config("stale").unwrap().is_none());
+        let events = follower.cdc_events(0, 10).unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].seq, 9);
+        let (loaded_meta, loaded_data) = follower.load_snapshot().unwrap().unwrap();
+        assert_eq!(loaded_meta, meta);
+        assert_eq!(loaded_data, data);
     }
 }
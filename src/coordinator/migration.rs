@@ -0,0 +1,558 @@
+//! Shard migration engine
+//!
+//! A rebalance compares, key by key, the volumes holding each key with the
+//! ones `PlacementManager::select_volumes` picks for it now, the way writes
+//! and repairs place keys. Replicas on volumes no longer selected are pulled
+//! chunk by chunk over the `Pull` RPC (throttled to the configured
+//! bandwidth), written to a selected volume that lacks the key, the key
+//! metadata is repointed and the old copy deleted. Moves are grouped by
+//! shard; up to `max_concurrent_transfers` shards migrate at once. Per-shard
+//! progress is kept in [`MIGRATIONS`] and served at `/admin/rebalance/status`.
+//!
+//! Volumes joining the cluster schedule a rebalance through
+//! [`schedule_rebalance`], so they receive their share of existing keys.
+
+use crate::common::{blake3_hash, shard_key, timestamp_now, Result};
+use crate::coordinator::metadata::{
+    KeyMetadata, KeyState, MetadataCommand, MetadataStore, VolumeMetadata, MAX_PAGE_SIZE,
+};
+use crate::coordinator::placement::PlacementManager;
+use crate::coordinator::raft_node::RaftNode;
+use crate::coordinator::txn::write_replica;
+use crate::coordinator::volume_client::VolumeClient;
+use futures_util::stream::{self, StreamExt};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Data movement needed for one key
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct KeyMove {
+    pub key: String,
+    /// Volumes holding the key that placement no longer selects for it
+    pub from: Vec<String>,
+    /// Volumes placement selects for the key that don't hold it
+    pub to: Vec<String>,
+}
+
+/// Moves bringing `meta` to the volumes placement selects for it among
+/// `volumes`, if any
+pub fn plan_key_move(
+    placement: &PlacementManager,
+    volumes: &[VolumeMetadata],
+    meta: &KeyMetadata,
+) -> Option<KeyMove> {
+    if meta.state != KeyState::Active || meta.replicas.is_empty() {
+        return None;
+    }
+    let selected = placement.select_volumes(&meta.key, volumes).ok()?;
+    let from: Vec<String> = meta
+        .replicas
+        .iter()
+        .filter(|v| !selected.contains(v))
+        .cloned()
+        .collect();
+    let to: Vec<String> = selected
+        .into_iter()
+        .filter(|v| !meta.replicas.contains(v))
+        .collect();
+    (!from.is_empty() && !to.is_empty()).then(|| KeyMove {
+        key: meta.key.clone(),
+        from,
+        to,
+    })
+}
+
+/// Moves of every key out of place, by shard
+pub fn plan_migration(
+    metadata: &MetadataStore,
+    placement: &PlacementManager,
+) -> Result<BTreeMap<u64, Vec<KeyMove>>> {
+    let volumes = metadata.get_healthy_volumes()?;
+    let mut moves: BTreeMap<u64, Vec<KeyMove>> = BTreeMap::new();
+    let mut after: Option<String> = None;
+    loop {
+        let page = metadata.list_keys_paginated("", after.as_deref(), MAX_PAGE_SIZE)?;
+        let Some(last) = page.last() else {
+            return Ok(moves);
+        };
+        after = Some(last.key.clone());
+        for mv in page
+            .iter()
+            .filter_map(|meta| plan_key_move(placement, &volumes, meta))
+        {
+            let shard = shard_key(&mv.key, placement.num_shards());
+            moves.entry(shard).or_default().push(mv);
+        }
+    }
+}
+
+/// Phase of a shard migration
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MigrationState {
+    Pending,
+    Running,
+    Done,
+    Failed,
+}
+
+/// Progress of one shard
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ShardProgress {
+    pub shard: u64,
+    pub from: Vec<String>,
+    pub to: Vec<String>,
+    pub state: MigrationState,
+    pub keys_total: u64,
+    pub keys_moved: u64,
+    pub bytes_moved: u64,
+    pub keys_failed: u64,
+    pub error: Option<String>,
+}
+
+/// Status of the current (or last) rebalance
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RebalanceStatus {
+    pub running: bool,
+    pub started_at: Option<u64>,
+    pub finished_at: Option<u64>,
+    pub bandwidth_bytes_per_sec: u64,
+    pub shards: Vec<ShardProgress>,
+}
+
+/// Delay before a scheduled rebalance starts, so several joins coalesce
+const SCHEDULE_DELAY: Duration = Duration::from_secs(10);
+
+/// Tracks migration progress and settings
+pub struct MigrationTracker {
+    status: Mutex<RebalanceStatus>,
+    bandwidth: AtomicU64,
+    max_concurrent: AtomicUsize,
+    /// A rebalance was requested while one was running or scheduled
+    rerun: AtomicBool,
+    scheduled: AtomicBool,
+}
+
+/// Global migration tracker
+pub static MIGRATIONS: Lazy<MigrationTracker> = Lazy::new(|| MigrationTracker {
+    status: Mutex::new(RebalanceStatus::default()),
+    bandwidth: AtomicU64::new(0),
+    max_concurrent: AtomicUsize::new(1),
+    rerun: AtomicBool::new(false),
+    scheduled: AtomicBool::new(false),
+});
+
+impl MigrationTracker {
+    /// Cap migration traffic (bytes per second, 0 = unlimited)
+    pub fn set_bandwidth(&self, bytes_per_sec: u64) {
+        self.bandwidth.store(bytes_per_sec, Ordering::Relaxed);
+    }
+
+    /// Current migration bandwidth cap
+    pub fn bandwidth(&self) -> u64 {
+        self.bandwidth.load(Ordering::Relaxed)
+    }
+
+    /// Limit the number of shards migrating at once
+    pub fn set_max_concurrent_transfers(&self, max: usize) {
+        self.max_concurrent.store(max.max(1), Ordering::Relaxed);
+    }
+
+    /// Current limit on concurrently migrating shards
+    pub fn max_concurrent_transfers(&self) -> usize {
+        self.max_concurrent.load(Ordering::Relaxed)
+    }
+
+    /// Snapshot of the rebalance status
+    pub fn status(&self) -> RebalanceStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    fn update_shard(&self, shard: u64, f: impl FnOnce(&mut ShardProgress)) {
+        let mut status = self.status.lock().unwrap();
+        if let Some(progress) = status.shards.iter_mut().find(|p| p.shard == shard) {
+            f(progress);
+        }
+    }
+}
+
+/// Token bucket limiting migration bandwidth (one second of burst)
+pub struct Throttle {
+    bytes_per_sec: u64,
+    allowance: f64,
+    last: Instant,
+}
+
+impl Throttle {
+    pub fn new(bytes_per_sec: u64) -> Self {
+        Self {
+            bytes_per_sec,
+            allowance: bytes_per_sec as f64,
+            last: Instant::now(),
+        }
+    }
+
+    /// Account for `bytes`, sleeping if the budget is exhausted
+    pub async fn consume(&mut self, bytes: usize) {
+        if self.bytes_per_sec == 0 {
+            return;
+        }
+        let rate = self.bytes_per_sec as f64;
+        let now = Instant::now();
+        self.allowance =
+            (self.allowance + now.duration_since(self.last).as_secs_f64() * rate).min(rate);
+        self.last = now;
+
+        self.allowance -= bytes as f64;
+        if self.allowance < 0.0 {
+            tokio::time::sleep(Duration::from_secs_f64(-self.allowance / rate)).await;
+        }
+    }
+}
+
+/// Schedule a background rebalance shortly from now.
+/// Calls made while one is scheduled or running are folded into a single
+/// follow-up rebalance.
+pub fn schedule_rebalance(
+    metadata: Arc<MetadataStore>,
+    placement: Arc<Mutex<PlacementManager>>,
+    raft: Arc<RaftNode>,
+) {
+    if MIGRATIONS.scheduled.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    tokio::spawn(async move {
+        tokio::time::sleep(SCHEDULE_DELAY).await;
+        MIGRATIONS.scheduled.store(false, Ordering::SeqCst);
+        if let Err(e) = start_rebalance(metadata, placement, raft) {
+            tracing::error!("Scheduled rebalance failed to start: {}", e);
+        }
+    });
+}
+
+/// Recompute the shard map and start moving every key placement now puts
+/// elsewhere.
+/// If a rebalance is already running, another one is queued to run after it
+/// and the current status is returned.
+pub fn start_rebalance(
+    metadata: Arc<MetadataStore>,
+    placement: Arc<Mutex<PlacementManager>>,
+    raft: Arc<RaftNode>,
+) -> Result<RebalanceStatus> {
+    raft.ensure_leader()?;
+    let mut status = MIGRATIONS.status.lock().unwrap();
+    if status.running {
+        MIGRATIONS.rerun.store(true, Ordering::SeqCst);
+        return Ok(status.clone());
+    }
+
+    let volumes = metadata.list_volumes()?;
+    let moves = {
+        let mut placement = placement.lock().unwrap();
+        placement.rebalance(&volumes);
+        plan_migration(&metadata, &placement)?
+    };
+
+    *status = RebalanceStatus {
+        running: true,
+        started_at: Some(timestamp_now()),
+        finished_at: None,
+        bandwidth_bytes_per_sec: MIGRATIONS.bandwidth(),
+        shards: moves
+            .iter()
+            .map(|(shard, keys)| ShardProgress {
+                shard: *shard,
+                from: volume_ids(keys.iter().flat_map(|mv| &mv.from)),
+                to: volume_ids(keys.iter().flat_map(|mv| &mv.to)),
+                state: MigrationState::Pending,
+                keys_total: keys.len() as u64,
+                keys_moved: 0,
+                bytes_moved: 0,
+                keys_failed: 0,
+                error: None,
+            })
+            .collect(),
+    };
+    let snapshot = status.clone();
+    drop(status);
+
+    tracing::info!("Rebalance: {} shards to migrate", moves.len());
+    tokio::spawn(async move {
+        run_migration(&metadata, &raft, moves).await;
+        {
+            let mut status = MIGRATIONS.status.lock().unwrap();
+            status.running = false;
+            status.finished_at = Some(timestamp_now());
+        }
+        if MIGRATIONS.rerun.swap(false, Ordering::SeqCst) {
+            if let Err(e) = start_rebalance(metadata, placement, raft) {
+                tracing::error!("Queued rebalance failed to start: {}", e);
+            }
+        }
+    });
+
+    Ok(snapshot)
+}
+
+/// Distinct volume ids, sorted
+fn volume_ids<'a>(ids: impl Iterator<Item = &'a String>) -> Vec<String> {
+    let ids: BTreeSet<&String> = ids.collect();
+    ids.into_iter().cloned().collect()
+}
+
+async fn run_migration(
+    metadata: &MetadataStore,
+    raft: &RaftNode,
+    moves: BTreeMap<u64, Vec<KeyMove>>,
+) {
+    // One bandwidth budget shared by all concurrent transfers
+    let throttle = tokio::sync::Mutex::new(Throttle::new(MIGRATIONS.bandwidth()));
+    let transfers = moves
+        .into_iter()
+        .map(|(shard, keys)| migrate_shard(metadata, raft, shard, keys, &throttle));
+    stream::iter(transfers)
+        .buffer_unordered(MIGRATIONS.max_concurrent_transfers())
+        .collect::<Vec<()>>()
+        .await;
+}
+
+async fn migrate_shard(
+    metadata: &MetadataStore,
+    raft: &RaftNode,
+    shard: u64,
+    keys: Vec<KeyMove>,
+    throttle: &tokio::sync::Mutex<Throttle>,
+) {
+    MIGRATIONS.update_shard(shard, |p| p.state = MigrationState::Running);
+
+    for mv in &keys {
+        match move_key(metadata, raft, mv, throttle).await {
+            Ok(bytes) => MIGRATIONS.update_shard(shard, |p| {
+                p.keys_moved += 1;
+                p.bytes_moved += bytes;
+            }),
+            Err(e) => {
+                tracing::warn!("Migration of {} (shard {}) failed: {}", mv.key, shard, e);
+                MIGRATIONS.update_shard(shard, |p| {
+                    p.keys_failed += 1;
+                    p.error = Some(e.to_string());
+                });
+            }
+        }
+    }
+
+    MIGRATIONS.update_shard(shard, |p| {
+        p.state = if p.keys_failed == 0 {
+            MigrationState::Done
+        } else {
+            MigrationState::Failed
+        };
+    });
+}
+
+/// Move the replicas of a key held by volumes placement no longer selects.
+/// Returns the number of bytes copied.
+async fn move_key(
+    metadata: &MetadataStore,
+    raft: &RaftNode,
+    mv: &KeyMove,
+    throttle: &tokio::sync::Mutex<Throttle>,
+) -> Result<u64> {
+    let key = mv.key.as_str();
+    let mut moved = 0;
+
+    for source_id in &mv.from {
+        let Some(meta) = metadata.get_key(key)? else {
+            break;
+        };
+        if meta.state != KeyState::Active || !meta.replicas.contains(source_id) {
+            continue;
+        }
+        let Some(target_id) = mv.to.iter().find(|id| !meta.replicas.contains(id)) else {
+            break;
+        };
+        let target = metadata
+            .get_volume(target_id)?
+            .ok_or_else(|| crate::Error::NotFound(target_id.clone()))?;
+
+        let data = pull_throttled(metadata, &meta, source_id, throttle).await?;
+        write_replica(&target, key, &meta.blake3, data).await?;
+
+        // Only repoint if the key didn't change while it was being copied
+        let Some(mut current) = metadata.get_key(key)? else {
+            break;
+        };
+        if current.blake3 != meta.blake3 || !current.replicas.contains(source_id) {
+            continue;
+        }
+        for replica in current.replicas.iter_mut() {
+            if replica == source_id {
+                *replica = target_id.clone();
+            }
+        }
+        current.updated_at = timestamp_now();
+        raft.propose(&MetadataCommand::PutKey(current)).await?;
+        moved += meta.size;
+
+        // Best effort: anti-entropy removes the copy if this fails
+        if let Some(source) = metadata.get_volume(source_id)? {
+            if let Ok(mut client) = VolumeClient::connect(source.grpc_address).await {
+                let _ = client.delete(key.to_string()).await;
+            }
+        }
+    }
+
+    Ok(moved)
+}
+
+/// Pull a key chunk by chunk, preferring `source_id`, falling back to the other
+/// replicas. Only a copy matching the committed checksum is returned.
+async fn pull_throttled(
+    metadata: &MetadataStore,
+    meta: &KeyMetadata,
+    source_id: &str,
+    throttle: &tokio::sync::Mutex<Throttle>,
+) -> Result<Vec<u8>> {
+    let mut sources: Vec<VolumeMetadata> = Vec::new();
+    for volume_id in std::iter::once(source_id).chain(
+        meta.replicas
+            .iter()
+            .map(String::as_str)
+            .filter(|id| *id != source_id),
+    ) {
+        if let Some(volume) = metadata.get_volume(volume_id)? {
+            sources.push(volume);
+        }
+    }
+
+    for source in sources {
+        let Ok(mut client) = VolumeClient::connect(source.grpc_address.clone()).await else {
+            continue;
+        };
+        let Ok(Some(mut stream)) = client.pull(meta.key.clone()).await else {
+            continue;
+        };
+
+        let mut data = Vec::with_capacity(meta.size as usize);
+        let mut complete = true;
+        loop {
+            match stream.message().await {
+                Ok(Some(chunk)) => {
+                    throttle.lock().await.consume(chunk.data.len()).await;
+                    data.extend_from_slice(&chunk.data);
+                }
+                Ok(None) => break,
+                Err(_) => {
+                    complete = false;
+                    break;
+                }
+            }
+        }
+        if complete && blake3_hash(&data) == meta.blake3 {
+            return Ok(data);
+        }
+        tracing::warn!(
+            "Migration: copy of {} on {} is unusable",
+            meta.key,
+            source.volume_id
+        );
+    }
+
+    Err(crate::Error::Internal(format!(
+        "no replica of {} matches its checksum",
+        meta.key
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn volume(id: &str) -> VolumeMetadata {
+        VolumeMetadata {
+            volume_id: id.to_string(),
+            address: String::new(),
+            grpc_address: String::new(),
+            state: crate::common::NodeState::Alive,
+            shards: vec![],
+            total_keys: 0,
+            total_bytes: 0,
+            free_bytes: 0,
+            last_heartbeat: 0,
+            zone: None,
+            rack: None,
+            weight: None,
+            wal_error: None,
+        }
+    }
+
+    fn key(key: &str, replicas: Vec<String>) -> KeyMetadata {
+        KeyMetadata {
+            key: key.to_string(),
+            replicas,
+            size: 1,
+            blake3: String::new(),
+            created_at: 0,
+            updated_at: 0,
+            state: KeyState::Active,
+            tags: Default::default(),
+            blob: None,
+            chunks: None,
+        }
+    }
+
+    #[test]
+    fn test_plan_key_move() {
+        let placement = PlacementManager::new(16, 2);
+        let volumes: Vec<_> = ["vol-1", "vol-2", "vol-3"].map(volume).into();
+        let selected = placement.select_volumes("a", &volumes).unwrap();
+
+        // Where placement puts it: nothing to move
+        assert_eq!(
+            plan_key_move(&placement, &volumes, &key("a", selected.clone())),
+            None
+        );
+
+        // One replica on the volume placement skips
+        let skipped = ["vol-1", "vol-2", "vol-3"]
+            .into_iter()
+            .find(|id| !selected.iter().any(|s| s == id))
+            .unwrap();
+        let replicas = vec![selected[0].clone(), skipped.to_string()];
+        assert_eq!(
+            plan_key_move(&placement, &volumes, &key("a", replicas)),
+            Some(KeyMove {
+                key: "a".to_string(),
+                from: vec![skipped.to_string()],
+                to: vec![selected[1].clone()],
+            })
+        );
+
+        // Nowhere to move to, and keys that aren't live
+        let replicas = vec!["vol-1".to_string(), "vol-9".to_string()];
+        assert_eq!(
+            plan_key_move(&placement, &volumes[..1], &key("a", replicas.clone())),
+            None
+        );
+        let mut deleted = key("a", replicas);
+        deleted.state = KeyState::Tombstone;
+        assert_eq!(plan_key_move(&placement, &volumes, &deleted), None);
+    }
+
+    #[tokio::test]
+    async fn test_throttle() {
+        let mut throttle = Throttle::new(1000);
+        let start = Instant::now();
+        // The first second is burst; the next 500 bytes must wait ~0.5s
+        throttle.consume(1000).await;
+        throttle.consume(500).await;
+        assert!(start.elapsed() >= Duration::from_millis(400));
+
+        let mut unlimited = Throttle::new(0);
+        unlimited.consume(usize::MAX).await;
+    }
+}
@@ -7,13 +7,41 @@
 //! - Health monitoring
 //! - Consensus via Raft
 
+pub mod anti_entropy;
+pub mod bandwidth;
+pub mod cdc;
+pub mod chunking;
+pub mod commands;
+pub mod consistency;
+pub mod dedup;
+pub mod drain;
+pub mod etcd;
+pub mod events;
+pub mod gc;
 pub mod grpc;
+pub mod health;
+pub mod hotkeys;
 pub mod http;
+pub mod kv_grpc;
+pub mod loopback;
+pub mod memcached;
 pub mod metadata;
+pub mod migration;
 pub mod placement;
+pub mod plugins;
 pub mod raft_node;
+pub mod raft_replicator;
 pub mod raft_rpc_client;
+pub mod redirect;
+pub mod repair;
+pub mod replication;
+pub mod resp;
 pub mod server;
+pub mod tenant;
+pub mod tiering;
+pub mod txn;
+pub mod usage;
 pub mod volume_client;
+pub mod webhooks;
 
 pub use server::Coordinator;
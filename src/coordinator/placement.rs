@@ -2,9 +2,15 @@
 //!
 //! This module implements horizontal scaling via sharding and flexible replica sets.
 //! Keys are assigned to shards using HRW (Highest Random Weight) hashing, and replicas are selected for fault tolerance.
+//! Replicas are spread across failure domains (zones or racks) according to the placement policy.
+//! Volumes are weighted by free space (or a configured weight) so small disks don't fill up first,
+//! and volumes below the free-space floor are excluded from new writes.
 
-use crate::common::{select_replicas, shard_key, ConsistentHashRing, Result};
+use crate::common::{
+    shard_key, weighted_hrw_hash, ConsistentHashRing, FailureDomain, NodeState, Result,
+};
 use crate::coordinator::metadata::VolumeMetadata;
+use std::collections::{HashMap, HashSet};
 
 /// PlacementManager handles sharding and replica selection for distributed writes.
 pub struct PlacementManager {
@@ -14,6 +20,37 @@ pub struct PlacementManager {
     replicas: usize,
     /// Total number of shards in the cluster
     num_shards: u64,
+    /// Failure domain replicas must be spread across
+    failure_domain: FailureDomain,
+    /// Let replicas share a domain instead of refusing the placement
+    allow_violation: bool,
+    /// Volumes with less free space take no new writes
+    min_free_bytes: u64,
+}
+
+/// State a volume should be in given its free space.
+/// Volumes below the floor become read-only; they only return to service with
+/// 10% headroom above it, so a volume hovering at the threshold doesn't flap.
+pub fn capacity_state(current: NodeState, free_bytes: u64, min_free_bytes: u64) -> NodeState {
+    match current {
+        NodeState::Alive if free_bytes < min_free_bytes => NodeState::ReadOnly,
+        NodeState::ReadOnly if free_bytes >= min_free_bytes + min_free_bytes / 10 => {
+            NodeState::Alive
+        }
+        state => state,
+    }
+}
+
+/// Whether the volume has reported its free space (zero means unknown)
+fn has_capacity_stats(volume: &VolumeMetadata) -> bool {
+    volume.free_bytes > 0
+}
+
+/// Configured weight, else free space once reported
+fn known_weight(volume: &VolumeMetadata) -> Option<f64> {
+    volume
+        .weight
+        .or_else(|| has_capacity_stats(volume).then_some(volume.free_bytes as f64))
 }
 
 impl PlacementManager {
@@ -22,40 +59,185 @@ impl PlacementManager {
             ring: ConsistentHashRing::new(num_shards),
             replicas,
             num_shards,
+            failure_domain: FailureDomain::default(),
+            allow_violation: false,
+            min_free_bytes: 0,
+        }
+    }
+
+    /// Set the free-space floor below which volumes take no new writes
+    pub fn with_min_free_bytes(mut self, min_free_bytes: u64) -> Self {
+        self.min_free_bytes = min_free_bytes;
+        self
+    }
+
+    /// Whether a volume can take new writes
+    fn accepts_writes(&self, volume: &VolumeMetadata) -> bool {
+        volume.state.is_healthy()
+            && (!has_capacity_stats(volume) || volume.free_bytes >= self.min_free_bytes)
+    }
+
+    /// Placement weights: the configured weight, else free space.
+    /// Volumes that haven't reported usage yet get the average weight.
+    fn weights(&self, volumes: &[&VolumeMetadata]) -> Vec<(String, f64)> {
+        let known: Vec<f64> = volumes.iter().filter_map(|v| known_weight(v)).collect();
+        let average = if known.is_empty() {
+            1.0
+        } else {
+            known.iter().sum::<f64>() / known.len() as f64
+        };
+
+        volumes
+            .iter()
+            .map(|v| (v.volume_id.clone(), known_weight(v).unwrap_or(average)))
+            .collect()
+    }
+
+    /// Set the failure domain policy.
+    /// With `allow_violation`, replicas may share a domain when there are not
+    /// enough distinct domains; otherwise such placements are refused.
+    pub fn with_policy(mut self, failure_domain: FailureDomain, allow_violation: bool) -> Self {
+        self.failure_domain = failure_domain;
+        self.allow_violation = allow_violation;
+        self
+    }
+
+    /// Failure domain a volume belongs to.
+    /// Unlabeled volumes form a domain of their own.
+    fn domain_of(&self, volume: &VolumeMetadata) -> String {
+        match (self.failure_domain, &volume.zone, &volume.rack) {
+            (FailureDomain::Zone, Some(zone), _) => zone.clone(),
+            (FailureDomain::Rack, zone, Some(rack)) => {
+                format!("{}/{}", zone.as_deref().unwrap_or_default(), rack)
+            }
+            _ => volume.volume_id.clone(),
         }
     }
 
     /// Select volumes for a key.
-    /// Uses HRW hashing to assign the key to a shard and select healthy replicas.
+    /// Uses weighted HRW hashing to rank writable volumes, then picks the highest
+    /// ranked volume of each failure domain until enough replicas are selected.
+    /// The result is stored in the key metadata, so weights changing over time
+    /// only affect where new writes go.
     pub fn select_volumes(&self, key: &str, volumes: &[VolumeMetadata]) -> Result<Vec<String>> {
         if volumes.is_empty() {
             return Err(crate::Error::NoHealthyVolumes);
         }
 
-        // Filter healthy volumes
-        let healthy: Vec<String> = volumes
-            .iter()
-            .filter(|v| v.state.is_healthy())
-            .map(|v| v.volume_id.clone())
-            .collect();
+        // Filter healthy volumes with enough free space
+        let writable: Vec<&VolumeMetadata> =
+            volumes.iter().filter(|v| self.accepts_writes(v)).collect();
 
-        if healthy.is_empty() {
+        if writable.is_empty() {
             return Err(crate::Error::NoHealthyVolumes);
         }
 
-        // Use HRW to select replicas
-        let selected = select_replicas(key, &healthy, self.replicas);
+        // Use weighted HRW to rank replicas
+        let ranked = weighted_hrw_hash(key, &self.weights(&writable));
+        let healthy: HashMap<&str, &VolumeMetadata> = writable
+            .iter()
+            .map(|v| (v.volume_id.as_str(), *v))
+            .collect();
 
-        if selected.len() < self.replicas {
+        if ranked.len() < self.replicas {
             return Err(crate::Error::InsufficientReplicas {
                 needed: self.replicas,
-                available: selected.len(),
+                available: ranked.len(),
             });
         }
 
+        let mut selected = Vec::with_capacity(self.replicas);
+        let mut same_domain = Vec::new();
+        let mut used_domains = HashSet::new();
+        for volume_id in ranked {
+            if selected.len() == self.replicas {
+                break;
+            }
+            if used_domains.insert(self.domain_of(healthy[volume_id.as_str()])) {
+                selected.push(volume_id);
+            } else {
+                same_domain.push(volume_id);
+            }
+        }
+
+        if selected.len() < self.replicas {
+            if !self.allow_violation {
+                return Err(crate::Error::PlacementViolation(format!(
+                    "{} replicas need {} distinct {:?} domains, only {} available",
+                    self.replicas,
+                    self.replicas,
+                    self.failure_domain,
+                    used_domains.len()
+                )));
+            }
+            tracing::warn!(
+                "Placing {} with replicas sharing a {:?} domain",
+                key,
+                self.failure_domain
+            );
+            let missing = self.replicas - selected.len();
+            selected.extend(same_domain.into_iter().take(missing));
+        }
+
         Ok(selected)
     }
 
+    /// Select a volume to take over one replica of `key`.
+    /// `current` holds the replicas that stay; the new volume is outside of
+    /// them and, policy permitting, outside of their failure domains.
+    pub fn select_replacement(
+        &self,
+        key: &str,
+        current: &[String],
+        volumes: &[VolumeMetadata],
+    ) -> Result<String> {
+        let candidates: Vec<&VolumeMetadata> = volumes
+            .iter()
+            .filter(|v| self.accepts_writes(v) && !current.contains(&v.volume_id))
+            .collect();
+        if candidates.is_empty() {
+            return Err(crate::Error::InsufficientReplicas {
+                needed: current.len() + 1,
+                available: current.len(),
+            });
+        }
+
+        let used_domains: HashSet<String> = volumes
+            .iter()
+            .filter(|v| current.contains(&v.volume_id))
+            .map(|v| self.domain_of(v))
+            .collect();
+        let by_id: HashMap<&str, &VolumeMetadata> = candidates
+            .iter()
+            .map(|v| (v.volume_id.as_str(), *v))
+            .collect();
+        let ranked = weighted_hrw_hash(key, &self.weights(&candidates));
+
+        if let Some(volume_id) = ranked
+            .iter()
+            .find(|id| !used_domains.contains(&self.domain_of(by_id[id.as_str()])))
+        {
+            return Ok(volume_id.clone());
+        }
+        if self.allow_violation {
+            return Ok(ranked[0].clone());
+        }
+        Err(crate::Error::PlacementViolation(format!(
+            "no {:?} domain left outside of {:?} for {}",
+            self.failure_domain, current, key
+        )))
+    }
+
+    /// Number of shards keys are spread over
+    pub fn num_shards(&self) -> u64 {
+        self.num_shards
+    }
+
+    /// Number of replicas each key is placed on
+    pub fn replicas(&self) -> usize {
+        self.replicas
+    }
+
     /// Get shard for key
     pub fn get_shard(&self, key: &str) -> u64 {
         shard_key(key, self.num_shards)
@@ -72,6 +254,11 @@ impl PlacementManager {
         self.ring.rebalance(&available, self.replicas);
     }
 
+    /// Snapshot of the shard → volumes map
+    pub fn shard_map(&self) -> HashMap<u64, Vec<String>> {
+        self.ring.assignments().clone()
+    }
+
     /// Get volumes for a specific shard
     pub fn get_shard_volumes(&self, shard: u64) -> Option<Vec<String>> {
         self.ring.get_shard_nodes(shard).map(|nodes| nodes.to_vec())
@@ -84,6 +271,15 @@ mod tests {
     use crate::common::NodeState;
 
     fn mock_volume(id: &str, state: NodeState) -> VolumeMetadata {
+        labeled_volume(id, state, None, None)
+    }
+
+    fn labeled_volume(
+        id: &str,
+        state: NodeState,
+        zone: Option<&str>,
+        rack: Option<&str>,
+    ) -> VolumeMetadata {
         VolumeMetadata {
             volume_id: id.to_string(),
             address: format!("http://localhost:{}", id),
@@ -94,6 +290,10 @@ mod tests {
             total_bytes: 0,
             free_bytes: 0,
             last_heartbeat: 0,
+            zone: zone.map(str::to_string),
+            rack: rack.map(str::to_string),
+            weight: None,
+            wal_error: None,
         }
     }
 
@@ -137,4 +337,113 @@ mod tests {
         let result = manager.select_volumes("test-key", &volumes);
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_zone_spread() {
+        let manager = PlacementManager::new(256, 3);
+
+        let volumes = vec![
+            labeled_volume("vol-1", NodeState::Alive, Some("a"), None),
+            labeled_volume("vol-2", NodeState::Alive, Some("a"), None),
+            labeled_volume("vol-3", NodeState::Alive, Some("b"), None),
+            labeled_volume("vol-4", NodeState::Alive, Some("c"), None),
+        ];
+
+        for i in 0..32 {
+            let selected = manager
+                .select_volumes(&format!("key-{}", i), &volumes)
+                .unwrap();
+            let zones: HashSet<_> = selected
+                .iter()
+                .map(|id| {
+                    volumes
+                        .iter()
+                        .find(|v| &v.volume_id == id)
+                        .unwrap()
+                        .zone
+                        .clone()
+                })
+                .collect();
+            assert_eq!(zones.len(), 3);
+        }
+    }
+
+    #[test]
+    fn test_placement_violation() {
+        let volumes = vec![
+            labeled_volume("vol-1", NodeState::Alive, Some("a"), Some("r1")),
+            labeled_volume("vol-2", NodeState::Alive, Some("a"), Some("r2")),
+            labeled_volume("vol-3", NodeState::Alive, Some("b"), Some("r1")),
+        ];
+
+        let strict = PlacementManager::new(256, 3);
+        assert!(matches!(
+            strict.select_volumes("test-key", &volumes),
+            Err(crate::Error::PlacementViolation(_))
+        ));
+
+        let by_rack = PlacementManager::new(256, 3).with_policy(FailureDomain::Rack, false);
+        assert_eq!(
+            by_rack.select_volumes("test-key", &volumes).unwrap().len(),
+            3
+        );
+
+        let relaxed = PlacementManager::new(256, 3).with_policy(FailureDomain::Zone, true);
+        assert_eq!(
+            relaxed.select_volumes("test-key", &volumes).unwrap().len(),
+            3
+        );
+    }
+
+    #[test]
+    fn test_capacity_floor() {
+        let manager = PlacementManager::new(256, 2).with_min_free_bytes(1000);
+
+        let mut full = mock_volume("vol-1", NodeState::Alive);
+        full.total_bytes = 10_000;
+        full.free_bytes = 10;
+        let mut roomy = mock_volume("vol-2", NodeState::Alive);
+        roomy.free_bytes = 5000;
+        let volumes = vec![full, roomy, mock_volume("vol-3", NodeState::Alive)];
+
+        for i in 0..16 {
+            let selected = manager
+                .select_volumes(&format!("key-{}", i), &volumes)
+                .unwrap();
+            assert!(!selected.contains(&"vol-1".to_string()));
+        }
+
+        assert_eq!(
+            capacity_state(NodeState::Alive, 10, 1000),
+            NodeState::ReadOnly
+        );
+        assert_eq!(
+            capacity_state(NodeState::ReadOnly, 1050, 1000),
+            NodeState::ReadOnly
+        );
+        assert_eq!(
+            capacity_state(NodeState::ReadOnly, 1100, 1000),
+            NodeState::Alive
+        );
+        assert_eq!(capacity_state(NodeState::Dead, 10, 1000), NodeState::Dead);
+    }
+
+    #[test]
+    fn test_select_replacement() {
+        let manager = PlacementManager::new(256, 3);
+
+        let volumes = vec![
+            labeled_volume("vol-1", NodeState::Alive, Some("a"), None),
+            labeled_volume("vol-2", NodeState::Alive, Some("b"), None),
+            labeled_volume("vol-3", NodeState::Draining, Some("c"), None),
+            labeled_volume("vol-4", NodeState::Alive, Some("a"), None),
+            labeled_volume("vol-5", NodeState::Alive, Some("c"), None),
+        ];
+        let current = vec!["vol-1".to_string(), "vol-2".to_string()];
+
+        let replacement = manager
+            .select_replacement("test-key", &current, &volumes)
+            .unwrap();
+        assert_eq!(replacement, "vol-5");
+    }
 }
@@ -0,0 +1,360 @@
+//! WASM plugins run on puts and gets
+//!
+//! Operators load WebAssembly modules (`[[coordinator.plugins]]`) that see
+//! the value of each `PUT /:key` or `GET /:key` (and the gRPC, Redis and
+//! memcached front ends mapped onto them) for the keys under their prefix.
+//! A plugin may refuse the request, replace the value, or tag the key on
+//! puts: validate content, strip PII, derive tags. Plugins run in
+//! configuration order, each on the value the previous one left.
+//!
+//! Modules are sandboxed: they may import nothing but the host API below
+//! (no WASI, clock or I/O), run in a fresh instance for every call, have
+//! their memory capped at `max_memory_bytes` and are interrupted after
+//! `timeout_ms`. A plugin trapping or timing out refuses the request, unless
+//! it is `fail_open`.
+//!
+//! A module exports `memory`, `alloc(len: i32) -> i32`, returning where the
+//! host may write `len` bytes, and `on_put` and/or `on_get`, both taking
+//! `(key_ptr, key_len, value_ptr, value_len: i32)`. From module `minikv` it
+//! may import:
+//! - `set_value(ptr, len: i32)`: replace the value
+//! - `add_tag(name_ptr, name_len, value_ptr, value_len: i32)`: tag the key (puts only)
+//! - `reject(ptr, len: i32)`: refuse the request, with a message
+//! - `log(ptr, len: i32)`: write a debug log line
+
+use crate::common::{PluginConfig, PluginHook, Result, METRICS};
+use anyhow::{anyhow, bail};
+use bytes::Bytes;
+use once_cell::sync::Lazy;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Once, RwLock};
+use std::time::{Duration, Instant};
+use wasmtime::{
+    Caller, Engine, InstancePre, Linker, Memory, Module, Store, StoreLimits, StoreLimitsBuilder,
+    Trap, TypedFunc,
+};
+
+/// Resolution of plugin timeouts
+const EPOCH_TICK: Duration = Duration::from_millis(5);
+
+/// Global plugin host
+pub static PLUGINS: Lazy<Plugins> = Lazy::new(Plugins::new);
+
+/// A compiled plugin
+struct Plugin {
+    config: PluginConfig,
+    pre: InstancePre<Call>,
+}
+
+impl Plugin {
+    fn runs_on(&self, hook: PluginHook, key: &str) -> bool {
+        self.config.hooks.contains(&hook) && key.starts_with(&self.config.prefix)
+    }
+}
+
+/// What a plugin call did, through the host API
+struct Call {
+    hook: PluginHook,
+    plugin: String,
+    value: Option<Vec<u8>>,
+    tags: BTreeMap<String, String>,
+    rejected: Option<String>,
+    limits: StoreLimits,
+}
+
+/// The loaded plugins
+pub struct Plugins {
+    engine: Engine,
+    plugins: RwLock<Arc<Vec<Arc<Plugin>>>>,
+    /// Starts the thread moving the epochs timeouts are counted in
+    ticker: Once,
+}
+
+impl Plugins {
+    fn new() -> Self {
+        let mut config = wasmtime::Config::new();
+        config.epoch_interruption(true);
+        Self {
+            engine: Engine::new(&config).expect("invalid WASM engine configuration"),
+            plugins: RwLock::new(Arc::new(Vec::new())),
+            ticker: Once::new(),
+        }
+    }
+
+    /// Compile the modules of `configs` (from `CoordinatorConfig::plugins`),
+    /// replacing the loaded plugins. Nothing changes if one fails to load.
+    pub fn load(&self, configs: &[PluginConfig]) -> Result<()> {
+        let invalid = |name: &str, e: anyhow::Error| {
+            crate::Error::InvalidConfig(format!("plugin {}: {:#}", name, e))
+        };
+        let linker = host_api(&self.engine).map_err(|e| invalid("host API", e))?;
+        let mut plugins = Vec::new();
+        for config in configs {
+            let module = Module::from_file(&self.engine, &config.path)
+                .map_err(|e| invalid(&config.name, e))?;
+            let exports = ["memory", "alloc"]
+                .into_iter()
+                .chain(config.hooks.iter().map(|hook| export_name(*hook)));
+            for export in exports {
+                if module.get_export(export).is_none() {
+                    return Err(invalid(&config.name, anyhow!("no `{}` export", export)));
+                }
+            }
+            // Fails on imports other than the host API's
+            let pre = linker
+                .instantiate_pre(&module)
+                .map_err(|e| invalid(&config.name, e))?;
+            plugins.push(Arc::new(Plugin {
+                config: config.clone(),
+                pre,
+            }));
+        }
+        if !plugins.is_empty() {
+            self.ticker.call_once(|| {
+                let engine = self.engine.clone();
+                std::thread::spawn(move || loop {
+                    std::thread::sleep(EPOCH_TICK);
+                    engine.increment_epoch();
+                });
+            });
+        }
+        *self.plugins.write().unwrap() = Arc::new(plugins);
+        Ok(())
+    }
+
+    fn matching(&self, hook: PluginHook, key: &str) -> Vec<Arc<Plugin>> {
+        let plugins = self.plugins.read().unwrap().clone();
+        plugins
+            .iter()
+            .filter(|plugin| plugin.runs_on(hook, key))
+            .cloned()
+            .collect()
+    }
+
+    /// Does a plugin run on `hook` for `key`? Values must then go through
+    /// the coordinator, not be redirected to volumes.
+    pub fn applies(&self, hook: PluginHook, key: &str) -> bool {
+        let plugins = self.plugins.read().unwrap();
+        plugins.iter().any(|plugin| plugin.runs_on(hook, key))
+    }
+
+    /// Run the put plugins of `key` on `value` and the `tags` it is written
+    /// with, returning what to write
+    pub async fn on_put(
+        &self,
+        key: &str,
+        mut value: Bytes,
+        mut tags: BTreeMap<String, String>,
+    ) -> Result<(Bytes, BTreeMap<String, String>)> {
+        for plugin in self.matching(PluginHook::Put, key) {
+            if let Some(call) = run(&self.engine, plugin, PluginHook::Put, key, &value).await? {
+                if let Some(replaced) = call.value {
+                    value = Bytes::from(replaced);
+                }
+                tags.extend(call.tags);
+            }
+        }
+        Ok((value, tags))
+    }
+
+    /// Run the get plugins of `key` on `value`, returning what to answer
+    pub async fn on_get(&self, key: &str, mut value: Vec<u8>) -> Result<Vec<u8>> {
+        for plugin in self.matching(PluginHook::Get, key) {
+            if let Some(call) = run(&self.engine, plugin, PluginHook::Get, key, &value).await? {
+                if let Some(replaced) = call.value {
+                    value = replaced;
+                }
+            }
+        }
+        Ok(value)
+    }
+}
+
+fn export_name(hook: PluginHook) -> &'static str {
+    match hook {
+        PluginHook::Put => "on_put",
+        PluginHook::Get => "on_get",
+    }
+}
+
+/// Run one plugin on the blocking pool. `None` if it failed open.
+async fn run(
+    engine: &Engine,
+    plugin: Arc<Plugin>,
+    hook: PluginHook,
+    key: &str,
+    value: &[u8],
+) -> Result<Option<Call>> {
+    let start = Instant::now();
+    let (engine, runner, key, value) = (
+        engine.clone(),
+        plugin.clone(),
+        key.to_string(),
+        value.to_vec(),
+    );
+    let result = tokio::task::spawn_blocking(move || call(&engine, &runner, hook, &key, &value))
+        .await
+        .unwrap_or_else(|e| Err(crate::Error::Internal(e.to_string())));
+    let name = &plugin.config.name;
+    METRICS.record_plugin_call(name, start.elapsed(), result.is_ok());
+    match result {
+        Ok(Call {
+            rejected: Some(reason),
+            ..
+        }) => Err(crate::Error::InvalidRequest(format!(
+            "rejected by plugin {}: {}",
+            name, reason
+        ))),
+        Ok(call) => Ok(Some(call)),
+        Err(e) if plugin.config.fail_open => {
+            tracing::warn!("Plugin {} failed, letting the request through: {}", name, e);
+            Ok(None)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Instantiate `plugin` and call its `hook` export
+fn call(
+    engine: &Engine,
+    plugin: &Plugin,
+    hook: PluginHook,
+    key: &str,
+    value: &[u8],
+) -> Result<Call> {
+    let config = &plugin.config;
+    let mut store = Store::new(
+        engine,
+        Call {
+            hook,
+            plugin: config.name.clone(),
+            value: None,
+            tags: BTreeMap::new(),
+            rejected: None,
+            limits: StoreLimitsBuilder::new()
+                .memory_size(config.max_memory_bytes)
+                .build(),
+        },
+    );
+    store.limiter(|call| &mut call.limits);
+    store.set_epoch_deadline(
+        config
+            .timeout_ms
+            .div_ceil(EPOCH_TICK.as_millis() as u64)
+            .max(1),
+    );
+
+    let mut invoke = || -> anyhow::Result<()> {
+        let instance = plugin.pre.instantiate(&mut store)?;
+        let memory = instance
+            .get_memory(&mut store, "memory")
+            .ok_or_else(|| anyhow!("`memory` is not a memory"))?;
+        let alloc = instance.get_typed_func::<i32, i32>(&mut store, "alloc")?;
+        let entry =
+            instance.get_typed_func::<(i32, i32, i32, i32), ()>(&mut store, export_name(hook))?;
+        let key_ptr = write_guest(&mut store, &alloc, &memory, key.as_bytes())?;
+        let value_ptr = write_guest(&mut store, &alloc, &memory, value)?;
+        entry.call(
+            &mut store,
+            (key_ptr, key.len() as i32, value_ptr, value.len() as i32),
+        )
+    };
+    match invoke() {
+        Ok(()) => Ok(store.into_data()),
+        Err(e) if e.downcast_ref::<Trap>() == Some(&Trap::Interrupt) => Err(crate::Error::Timeout(
+            format!("plugin {} ran past {} ms", config.name, config.timeout_ms),
+        )),
+        Err(e) => Err(crate::Error::Internal(format!(
+            "plugin {} failed: {:#}",
+            config.name, e
+        ))),
+    }
+}
+
+/// Copy `bytes` into memory the guest allocated
+fn write_guest(
+    store: &mut Store<Call>,
+    alloc: &TypedFunc<i32, i32>,
+    memory: &Memory,
+    bytes: &[u8],
+) -> anyhow::Result<i32> {
+    let ptr = alloc.call(&mut *store, i32::try_from(bytes.len())?)?;
+    memory.write(&mut *store, ptr as u32 as usize, bytes)?;
+    Ok(ptr)
+}
+
+/// `len` bytes of guest memory from `ptr`
+fn read_guest(caller: &mut Caller<'_, Call>, ptr: i32, len: i32) -> anyhow::Result<Vec<u8>> {
+    let memory = caller
+        .get_export("memory")
+        .and_then(|export| export.into_memory())
+        .ok_or_else(|| anyhow!("no exported memory"))?;
+    let start = ptr as u32 as usize;
+    let end = start.saturating_add(len as u32 as usize);
+    match memory.data(&caller).get(start..end) {
+        Some(bytes) => Ok(bytes.to_vec()),
+        None => bail!("{} bytes at {} are out of bounds", len, ptr),
+    }
+}
+
+fn read_guest_str(caller: &mut Caller<'_, Call>, ptr: i32, len: i32) -> anyhow::Result<String> {
+    Ok(String::from_utf8(read_guest(caller, ptr, len)?)?)
+}
+
+/// The functions plugins may import
+fn host_api(engine: &Engine) -> anyhow::Result<Linker<Call>> {
+    let mut linker = Linker::new(engine);
+    linker.func_wrap(
+        "minikv",
+        "set_value",
+        |mut caller: Caller<'_, Call>, ptr: i32, len: i32| -> anyhow::Result<()> {
+            let value = read_guest(&mut caller, ptr, len)?;
+            caller.data_mut().value = Some(value);
+            Ok(())
+        },
+    )?;
+    linker.func_wrap(
+        "minikv",
+        "add_tag",
+        |mut caller: Caller<'_, Call>,
+         name_ptr: i32,
+         name_len: i32,
+         value_ptr: i32,
+         value_len: i32|
+         -> anyhow::Result<()> {
+            if caller.data().hook != PluginHook::Put {
+                bail!("keys can only be tagged on puts");
+            }
+            let name = read_guest_str(&mut caller, name_ptr, name_len)?;
+            let value = read_guest_str(&mut caller, value_ptr, value_len)?;
+            // As with `X-Tag` headers
+            if name.trim().is_empty() || name.contains(':') {
+                bail!("invalid tag name {:?}", name);
+            }
+            caller
+                .data_mut()
+                .tags
+                .insert(name.trim().to_string(), value.trim().to_string());
+            Ok(())
+        },
+    )?;
+    linker.func_wrap(
+        "minikv",
+        "reject",
+        |mut caller: Caller<'_, Call>, ptr: i32, len: i32| -> anyhow::Result<()> {
+            let reason = String::from_utf8_lossy(&read_guest(&mut caller, ptr, len)?).into_owned();
+            caller.data_mut().rejected = Some(reason);
+            Ok(())
+        },
+    )?;
+    linker.func_wrap(
+        "minikv",
+        "log",
+        |mut caller: Caller<'_, Call>, ptr: i32, len: i32| -> anyhow::Result<()> {
+            let line = String::from_utf8_lossy(&read_guest(&mut caller, ptr, len)?).into_owned();
+            tracing::debug!("Plugin {}: {}", caller.data().plugin, line);
+            Ok(())
+        },
+    )?;
+    Ok(linker)
+}
@@ -4,10 +4,51 @@
 //! Only basic leader/follower roles are currently supported.
 //! Full multi-node Raft (log replication, elections, etc.) is still in progress.
 //! For production, use a full Raft library like tikv/raft.
+//!
+//! When opened with a metadata store, the term, vote and log are persisted
+//! (with fsync) before the node acts on them, and reloaded on restart.
+//! Once the log grows past the snapshot threshold, the metadata state machine
+//! is snapshotted and the covered entries are dropped; followers too far
+//! behind receive the snapshot through InstallSnapshot.
+//! Committed entries carry a [`MetadataCommand`] and are applied to the
+//! metadata store on every node, leader and followers alike.
+//!
+//! Elections start with a pre-vote round that changes no state, so a node cut
+//! off from the cluster doesn't inflate its term and depose a healthy leader
+//! when it rejoins. Leadership can be handed to a chosen peer with
+//! [`RaftNode::transfer_leadership`].
+//!
+//! The leader ships its log through per-peer replicators (see
+//! [`raft_replicator`](crate::coordinator::raft_replicator)); `replicate`
+//! appends locally and waits for a majority's `match_index` to reach the entry.
 
-use crate::common::Result;
-use crate::coordinator::raft_rpc_client::{send_append_entries_rpc, send_request_vote_rpc};
+use crate::common::raft::{HardState, LogEntry, SnapshotMeta};
+use crate::common::{Result, METRICS};
+use crate::coordinator::events::{self, ClusterEvent};
+use crate::coordinator::metadata::{MetadataCommand, MetadataStore};
+use crate::coordinator::raft_replicator::{
+    start_replicators, Batch, PeerProgress, MAX_BATCH_BYTES, MAX_BATCH_ENTRIES,
+};
+use crate::coordinator::raft_rpc_client::{
+    send_append_entries_rpc, send_install_snapshot_rpc, send_request_vote_rpc, send_timeout_now_rpc,
+};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
+// Tokio's clock, so a paused runtime (`minikv::sim`) controls leader leases
+use tokio::time::Instant;
+
+/// Shortest election timeout. A node that heard from a leader more recently
+/// than this refuses pre-votes.
+const MIN_ELECTION_TIMEOUT: Duration = Duration::from_millis(150);
+
+/// How long a leadership transfer may take when the caller sets no limit
+pub const DEFAULT_TRANSFER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long `replicate` waits for a majority before giving up
+const COMMIT_TIMEOUT: Duration = Duration::from_secs(5);
 
 /// Simplified Raft state
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -34,14 +75,118 @@ pub struct RaftNode {
     term: Arc<Mutex<u64>>,
     voted_for: Arc<Mutex<Option<String>>>,
     leader_id: Arc<Mutex<Option<String>>>,
+    /// HTTP address of the current leader, learned from its AppendEntries
+    leader_addr: Arc<Mutex<Option<String>>>,
+    /// HTTP address clients are redirected to while this node leads
+    advertise_addr: Option<String>,
+    /// Last time an AppendEntries from a current leader arrived
+    last_leader_contact: Arc<Mutex<Option<Instant>>>,
     log: Arc<Mutex<Vec<crate::common::raft::LogEntry>>>,
     peers: Arc<Mutex<Vec<String>>>, // List of peer node IDs
+    /// Per-peer replication progress, reset on each election won
+    progress: Arc<Mutex<HashMap<String, PeerProgress>>>,
+    /// Wakes the replicators when entries are appended
+    replication_notify: Arc<tokio::sync::Notify>,
+    /// Wakes `replicate` callers when the commit index moves
+    commit_notify: Arc<tokio::sync::Notify>,
     commit_index: Arc<Mutex<u64>>,
     last_applied: Arc<Mutex<u64>>,
     snapshot: Arc<Mutex<Option<Vec<u8>>>>, // Optionally store snapshot bytes
+    /// Log position covered by the latest snapshot
+    snapshot_meta: Arc<Mutex<SnapshotMeta>>,
+    /// Log length that triggers a snapshot
+    snapshot_threshold: u64,
+    /// Durable storage for the hard state and log; `None` keeps everything in memory
+    storage: Option<Arc<MetadataStore>>,
+    /// Draws the election timeouts
+    rng: Mutex<StdRng>,
 }
 
 impl RaftNode {
+    /// Open a node whose term, vote and log are persisted in `storage`,
+    /// restoring whatever a previous run left there
+    pub fn open(node_id: String, storage: Arc<MetadataStore>) -> Result<Self> {
+        let hard_state = storage.load_hard_state()?.unwrap_or_default();
+        let log = storage.load_raft_log()?;
+        let snapshot_meta = storage.load_snapshot_meta()?.unwrap_or_default();
+        tracing::info!(
+            "Raft node {} restored term {} with {} log entries after snapshot index {}",
+            node_id,
+            hard_state.term,
+            log.len(),
+            snapshot_meta.last_included_index
+        );
+
+        let mut node = Self::new(node_id);
+        *node.term.lock().unwrap() = hard_state.term;
+        *node.voted_for.lock().unwrap() = hard_state.voted_for;
+        *node.log.lock().unwrap() = log;
+        // The state machine lives in the same store, so it already reflects the snapshot
+        *node.commit_index.lock().unwrap() = snapshot_meta.last_included_index;
+        *node.last_applied.lock().unwrap() = snapshot_meta.last_included_index;
+        *node.snapshot_meta.lock().unwrap() = snapshot_meta;
+        node.storage = Some(storage);
+        Ok(node)
+    }
+
+    /// Snapshot the state machine once the log holds more than `threshold` entries
+    pub fn with_snapshot_threshold(mut self, threshold: u64) -> Self {
+        self.snapshot_threshold = threshold;
+        self
+    }
+
+    /// Draw election timeouts from `seed`, so that runs repeat
+    pub fn with_seed(self, seed: u64) -> Self {
+        *self.rng.lock().unwrap() = StdRng::seed_from_u64(seed);
+        self
+    }
+
+    /// Set the other coordinators of the cluster, by gRPC address. Addresses
+    /// without a scheme are reached over plain http.
+    pub fn with_peers(self, peers: Vec<String>) -> Self {
+        *self.peers.lock().unwrap() = peers
+            .into_iter()
+            .map(|peer| {
+                if peer.contains("://") {
+                    peer
+                } else {
+                    format!("http://{}", peer)
+                }
+            })
+            .collect();
+        self
+    }
+
+    /// Advertise `addr` to followers as the HTTP address clients should use
+    pub fn with_advertise_addr(mut self, addr: String) -> Self {
+        self.advertise_addr = Some(addr);
+        self
+    }
+
+    /// Persist the term and vote. Must succeed before the node acts on them.
+    fn persist_hard_state(&self, term: u64, voted_for: &Option<String>) -> Result<()> {
+        match &self.storage {
+            Some(storage) => storage.save_hard_state(&HardState {
+                term,
+                voted_for: voted_for.clone(),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Persist log entries appended after the first `keep` entries of `log`,
+    /// dropping any persisted suffix they replace
+    fn persist_log_suffix(&self, log: &[LogEntry], keep: usize) -> Result<()> {
+        let Some(storage) = &self.storage else {
+            return Ok(());
+        };
+        if let Some(first) = log.get(keep) {
+            storage.truncate_raft_log(first.index)?;
+            storage.append_raft_log(&log[keep..])?;
+        }
+        Ok(())
+    }
+
     /// Get a copy of the current peer list
     pub fn get_peers(&self) -> Vec<String> {
         self.peers.lock().unwrap().clone()
@@ -57,15 +202,35 @@ impl RaftNode {
 
     /// Recovery: reloads the snapshot and log after a crash or partition
     pub fn recover(&self) {
-        if self.load_snapshot().is_some() {
-            // Apply the snapshot to the real state machine here
-        }
-        let log = self.log.lock().unwrap().clone();
-        if !log.is_empty() {
-            // Apply each entry to the real state machine here
+        // The snapshot is already part of the state machine in storage;
+        // replay whatever was committed after it
+        if let Err(e) = self.apply_committed() {
+            tracing::error!("Raft recovery failed: {}", e);
         }
+    }
+
+    /// Apply entries between the last applied and the commit index to the
+    /// metadata store. Stops at the first entry that fails to apply, so it is
+    /// retried on the next call.
+    pub fn apply_committed(&self) -> Result<()> {
+        let log = self.log.lock().unwrap();
+        let commit = *self.commit_index.lock().unwrap();
         let mut applied = self.last_applied.lock().unwrap();
-        *applied = *self.commit_index.lock().unwrap();
+        for entry in log
+            .iter()
+            .filter(|e| e.index > *applied && e.index <= commit)
+        {
+            // Empty entries are leader no-ops (see `read_index`)
+            if let (Some(storage), false) = (&self.storage, entry.data.is_empty()) {
+                match MetadataCommand::decode(&entry.data) {
+                    Ok(command) => storage.apply_at(entry.index, &command)?,
+                    // Not a metadata command; nothing to apply
+                    Err(e) => tracing::warn!("Skipping Raft entry {}: {}", entry.index, e),
+                }
+            }
+            *applied = entry.index;
+        }
+        Ok(())
     }
     /// Save a snapshot of the current state (log, index, etc.)
     pub fn save_snapshot(&self, data: Vec<u8>) {
@@ -73,22 +238,339 @@ impl RaftNode {
         *snap = Some(data);
     }
 
-    /// Load a snapshot into the state machine
+    /// Load the latest snapshot, from memory or from storage after a restart
     pub fn load_snapshot(&self) -> Option<Vec<u8>> {
-        self.snapshot.lock().unwrap().clone()
+        if let Some(data) = self.snapshot.lock().unwrap().clone() {
+            return Some(data);
+        }
+        let storage = self.storage.as_ref()?;
+        match storage.load_snapshot() {
+            Ok(Some((_, data))) => Some(data),
+            Ok(None) => None,
+            Err(e) => {
+                tracing::error!("Failed to load Raft snapshot: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Log position covered by the latest snapshot
+    pub fn snapshot_meta(&self) -> SnapshotMeta {
+        *self.snapshot_meta.lock().unwrap()
     }
 
-    /// Apply a snapshot received from the leader (InstallSnapshot RPC)
-    pub fn apply_snapshot(&self, data: Vec<u8>, last_included_index: u64, last_included_term: u64) {
+    /// Snapshot the state machine at the last applied entry and drop the log
+    /// entries it covers, once the log exceeds the snapshot threshold.
+    /// Returns whether a snapshot was taken.
+    pub fn maybe_compact(&self) -> Result<bool> {
+        let Some(storage) = &self.storage else {
+            return Ok(false);
+        };
+        let applied = *self.last_applied.lock().unwrap();
+        let mut log = self.log.lock().unwrap();
+        if log.len() as u64 <= self.snapshot_threshold {
+            return Ok(false);
+        }
+        // Nothing applied since the last snapshot
+        let Some(last_term) = log.iter().find(|e| e.index == applied).map(|e| e.term) else {
+            return Ok(false);
+        };
+
+        let meta = SnapshotMeta {
+            last_included_index: applied,
+            last_included_term: last_term,
+        };
+        let data = storage.export_state()?;
+        storage.save_snapshot(&meta, &data)?;
+        storage.compact_raft_log(applied)?;
+        log.retain(|entry| entry.index > applied);
+        tracing::info!(
+            "Raft snapshot at index {} ({} bytes), {} entries left in the log",
+            applied,
+            data.len(),
+            log.len()
+        );
+
+        *self.snapshot_meta.lock().unwrap() = meta;
         self.save_snapshot(data);
+        Ok(true)
+    }
+
+    /// Apply a snapshot received from the leader (InstallSnapshot RPC):
+    /// replace the state machine and drop the log entries the snapshot covers.
+    /// Log entries following the snapshot are kept only if our log agrees with it.
+    pub fn apply_snapshot(
+        &self,
+        data: Vec<u8>,
+        last_included_index: u64,
+        last_included_term: u64,
+    ) -> Result<()> {
+        if last_included_index <= self.snapshot_meta().last_included_index {
+            // Already covered by our own snapshot
+            return Ok(());
+        }
+        let meta = SnapshotMeta {
+            last_included_index,
+            last_included_term,
+        };
+
         let mut log = self.log.lock().unwrap();
-        log.retain(|entry| entry.index > last_included_index);
+        let matches = log
+            .iter()
+            .any(|e| e.index == last_included_index && e.term == last_included_term);
+        if let Some(storage) = &self.storage {
+            storage.restore_state(&data)?;
+            storage.load_api_keys()?;
+            storage.load_quotas()?;
+            storage.load_acl_rules()?;
+            storage.save_snapshot(&meta, &data)?;
+            if matches {
+                storage.compact_raft_log(last_included_index)?;
+            } else {
+                storage.truncate_raft_log(0)?;
+            }
+        }
+        if matches {
+            log.retain(|entry| entry.index > last_included_index);
+        } else {
+            log.clear();
+        }
         let mut commit = self.commit_index.lock().unwrap();
-        *commit = last_included_index;
+        *commit = (*commit).max(last_included_index);
         let mut applied = self.last_applied.lock().unwrap();
         *applied = last_included_index;
-        let mut term = self.term.lock().unwrap();
-        *term = last_included_term;
+
+        *self.snapshot_meta.lock().unwrap() = meta;
+        self.save_snapshot(data);
+        tracing::info!(
+            "Installed Raft snapshot at index {} (term {})",
+            last_included_index,
+            last_included_term
+        );
+        Ok(())
+    }
+
+    /// Index and term of the last log entry, falling back to the snapshot
+    fn last_log_position(&self, log: &[LogEntry]) -> (u64, u64) {
+        match log.last() {
+            Some(entry) => (entry.index, entry.term),
+            None => {
+                let meta = self.snapshot_meta();
+                (meta.last_included_index, meta.last_included_term)
+            }
+        }
+    }
+
+    /// Term of the entry at `index`, if it is still known: in the log, or
+    /// as the last entry covered by the snapshot
+    fn term_at(&self, log: &[LogEntry], index: u64) -> Option<u64> {
+        let snapshot = self.snapshot_meta();
+        if index == snapshot.last_included_index {
+            return Some(snapshot.last_included_term);
+        }
+        let first = log.first()?.index;
+        log.get(index.checked_sub(first)? as usize)
+            .filter(|e| e.index == index)
+            .map(|e| e.term)
+    }
+
+    /// Stream the latest snapshot to a follower that is behind the log start
+    pub(crate) async fn send_snapshot(&self, peer: &str, term: u64) {
+        let Some(data) = self.load_snapshot() else {
+            return;
+        };
+        let meta = self.snapshot_meta();
+        match send_install_snapshot_rpc(peer, term, &self.node_id, meta, data).await {
+            Ok(peer_term) if peer_term > term => self.step_down(peer_term, None),
+            Ok(_) => {
+                tracing::info!(
+                    "Sent snapshot at index {} to {}",
+                    meta.last_included_index,
+                    peer
+                );
+                let mut progress = self.progress.lock().unwrap();
+                let p = progress.entry(peer.to_string()).or_default();
+                p.match_index = p.match_index.max(meta.last_included_index);
+                p.next_index = p.next_index.max(p.match_index + 1);
+            }
+            Err(e) => tracing::warn!("InstallSnapshot to {} failed: {}", peer, e),
+        }
+    }
+
+    /// Peer holding the most of the log, while this node leads: the quickest
+    /// to hand leadership to
+    pub fn most_caught_up_peer(&self) -> Option<String> {
+        let peers = self.get_peers();
+        let progress = self.progress.lock().unwrap();
+        peers
+            .into_iter()
+            .max_by_key(|peer| progress.get(peer).map_or(0, |p| p.match_index))
+    }
+
+    /// Replication progress of `peer`, while this node leads
+    pub fn peer_progress(&self, peer: &str) -> Option<PeerProgress> {
+        self.progress.lock().unwrap().get(peer).copied()
+    }
+
+    /// Current term, if this node leads it
+    pub(crate) fn leader_term(&self) -> Option<u64> {
+        let term = self.get_term();
+        self.is_leader().then_some(term)
+    }
+
+    pub(crate) fn replication_wakeup(&self) -> &tokio::sync::Notify {
+        &self.replication_notify
+    }
+
+    /// Next AppendEntries for `peer`, starting at its `next_index`. The batch
+    /// is counted as sent: `next_index` moves past it so the following batch
+    /// can be pipelined behind it.
+    pub(crate) fn next_batch(&self, peer: &str, term: u64) -> Batch {
+        let log = self.log.lock().unwrap();
+        let (last_index, _) = self.last_log_position(&log);
+        let leader_commit = *self.commit_index.lock().unwrap();
+        let mut progress = self.progress.lock().unwrap();
+        let p = progress.entry(peer.to_string()).or_insert(PeerProgress {
+            next_index: last_index + 1,
+            match_index: 0,
+        });
+        if p.next_index <= self.snapshot_meta().last_included_index {
+            return Batch::Snapshot;
+        }
+        if p.next_index > last_index {
+            return Batch::UpToDate;
+        }
+
+        let prev_log_index = p.next_index - 1;
+        let prev_log_term = self.term_at(&log, prev_log_index).unwrap_or(0);
+        let start = (p.next_index - log[0].index) as usize;
+        let mut bytes = 0;
+        let entries: Vec<LogEntry> = log[start..]
+            .iter()
+            .take(MAX_BATCH_ENTRIES)
+            .take_while(|e| {
+                let fits = bytes == 0 || bytes + e.data.len() <= MAX_BATCH_BYTES;
+                bytes += e.data.len();
+                fits
+            })
+            .cloned()
+            .collect();
+        p.next_index += entries.len() as u64;
+
+        Batch::Append(crate::common::raft::AppendRequest {
+            term,
+            leader_id: self.node_id.clone(),
+            prev_log_index,
+            prev_log_term,
+            entries,
+            leader_commit,
+            leader_addr: self.advertise_addr.clone().unwrap_or_default(),
+        })
+    }
+
+    /// Record a follower's answer to an AppendEntries of `term` whose last
+    /// entry was `last_sent`
+    pub(crate) fn on_append_response(
+        &self,
+        peer: &str,
+        term: u64,
+        last_sent: u64,
+        resp: &crate::common::raft::AppendResponse,
+    ) {
+        if resp.term > term {
+            self.step_down(resp.term, None);
+            return;
+        }
+        if self.leader_term() != Some(term) {
+            return;
+        }
+        {
+            let mut progress = self.progress.lock().unwrap();
+            let p = progress.entry(peer.to_string()).or_default();
+            if resp.success {
+                p.match_index = p.match_index.max(last_sent);
+                p.next_index = p.next_index.max(p.match_index + 1);
+            } else {
+                // Back off to where the follower's log may still agree with ours
+                p.next_index = p
+                    .next_index
+                    .min(resp.conflict_index + 1)
+                    .max(p.match_index + 1);
+            }
+        }
+        if resp.success {
+            self.advance_commit();
+        }
+    }
+
+    /// Resend everything `peer` hasn't acknowledged
+    pub(crate) fn rewind_peer(&self, peer: &str) {
+        if let Some(p) = self.progress.lock().unwrap().get_mut(peer) {
+            p.next_index = p.match_index + 1;
+        }
+    }
+
+    /// Commit the highest entry of the current term that a majority holds,
+    /// then apply it
+    fn advance_commit(&self) {
+        let term = self.get_term();
+        let peers = self.get_peers();
+        let advanced = {
+            let log = self.log.lock().unwrap();
+            let (last_index, _) = self.last_log_position(&log);
+            let mut matched: Vec<u64> = {
+                let progress = self.progress.lock().unwrap();
+                peers
+                    .iter()
+                    .map(|peer| progress.get(peer).map_or(0, |p| p.match_index))
+                    .collect()
+            };
+            matched.push(last_index);
+            matched.sort_unstable_by(|a, b| b.cmp(a));
+            let majority_index = matched[matched.len() / 2];
+
+            // Entries of earlier terms only commit along with one of ours
+            let mut commit = self.commit_index.lock().unwrap();
+            if majority_index > *commit && self.term_at(&log, majority_index) == Some(term) {
+                *commit = majority_index;
+                true
+            } else {
+                false
+            }
+        };
+        if advanced {
+            if let Err(e) = self.apply_committed() {
+                tracing::error!("Failed to apply committed Raft entries: {}", e);
+            }
+            self.commit_notify.notify_waiters();
+        }
+    }
+
+    /// Wait until the entry at `index`, appended in `term`, is committed and applied
+    async fn wait_committed(&self, index: u64, term: u64) -> Result<()> {
+        let deadline = tokio::time::Instant::now() + COMMIT_TIMEOUT;
+        loop {
+            let notified = self.commit_notify.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+
+            if *self.commit_index.lock().unwrap() >= index {
+                let log = self.log.lock().unwrap();
+                if matches!(self.term_at(&log, index), Some(t) if t != term) {
+                    return Err(crate::Error::Raft(format!(
+                        "entry {} was replaced by a new leader",
+                        index
+                    )));
+                }
+                drop(log);
+                return self.apply_committed();
+            }
+            self.ensure_leader()?;
+            if tokio::time::Instant::now() >= deadline {
+                return Err(crate::Error::ConsensusTimeout);
+            }
+            let _ = tokio::time::timeout(Duration::from_millis(50), notified).await;
+        }
     }
     pub fn get_log(&self) -> std::sync::MutexGuard<'_, Vec<crate::common::raft::LogEntry>> {
         self.log.lock().unwrap()
@@ -99,13 +581,20 @@ impl RaftNode {
     /// Send heartbeats (AppendEntries RPC) to all followers.
     /// This maintains leadership and triggers log replication.
     pub async fn send_heartbeats(&self) {
+        self.heartbeat_round().await;
+    }
+
+    /// Send one round of heartbeats. Returns how many peers still accept this
+    /// node as leader of its current term.
+    async fn heartbeat_round(&self) -> usize {
         let peers = self.peers.lock().unwrap().clone();
         let term = self.get_term();
         let leader_id = self.node_id.clone();
         let log_snapshot = self.log.lock().unwrap().clone();
-        let prev_log_index = log_snapshot.last().map(|e| e.index).unwrap_or(0);
-        let prev_log_term = log_snapshot.last().map(|e| e.term).unwrap_or(0);
-        let leader_commit = prev_log_index;
+        let (prev_log_index, prev_log_term) = self.last_log_position(&log_snapshot);
+        // Only advertise what is committed, so followers never apply more
+        let leader_commit = *self.commit_index.lock().unwrap();
+        let mut acks = 0;
         for peer in &peers {
             let req = crate::common::raft::AppendRequest {
                 term,
@@ -114,20 +603,66 @@ impl RaftNode {
                 prev_log_term,
                 entries: vec![], // Heartbeat: no entries
                 leader_commit,
+                leader_addr: self.advertise_addr.clone().unwrap_or_default(),
+            };
+            let sent_at = Instant::now();
+            let Ok(resp) = send_append_entries_rpc(peer, req).await else {
+                continue;
             };
-            let _ = send_append_entries_rpc(peer, req).await;
+            METRICS.record_heartbeat(peer, sent_at.elapsed());
+            if resp.term > term {
+                self.step_down(resp.term, None);
+                return 0;
+            }
+            // A log mismatch still means the follower accepted our term
+            acks += 1;
+            // Let the replicator resend from wherever the follower's log ends
+            self.on_append_response(peer, term, prev_log_index, &resp);
         }
+        acks
+    }
+
+    /// ReadIndex barrier for linearizable reads. Returns once this node has
+    /// confirmed with a majority that it still leads and has applied every
+    /// entry committed before the call. Fails with `NotLeader` on a follower.
+    pub async fn read_index(&self) -> Result<u64> {
+        self.ensure_leader()?;
+        let term = self.get_term();
+
+        // Until it commits an entry of its own term, a new leader may not
+        // know everything its predecessor committed
+        let committed_term = {
+            let log = self.log.lock().unwrap();
+            let commit = *self.commit_index.lock().unwrap();
+            match log.iter().find(|e| e.index == commit) {
+                Some(entry) => entry.term,
+                None => self.snapshot_meta().last_included_term,
+            }
+        };
+        if committed_term != term {
+            self.replicate(Vec::new()).await?;
+        }
+        let read_index = *self.commit_index.lock().unwrap();
+
+        let peers = self.peers.lock().unwrap().len();
+        let acks = self.heartbeat_round().await + 1;
+        if acks < quorum(peers + 1) || self.get_term() != term {
+            self.ensure_leader()?;
+            return Err(crate::Error::ConsensusTimeout);
+        }
+
+        if *self.last_applied.lock().unwrap() < read_index {
+            self.apply_committed()?;
+        }
+        Ok(read_index)
     }
 
     /// Start the election timer and trigger elections if no heartbeat is received.
     /// This is a stub for multi-node Raft; should run in a background task.
     /// Election timer: triggers election if no heartbeat received.
     pub async fn run_election_timer(&self) {
-        use rand::Rng;
-        let mut rng = rand::thread_rng();
         loop {
-            let timeout = rng.gen_range(150..300);
-            tokio::time::sleep(tokio::time::Duration::from_millis(timeout)).await;
+            tokio::time::sleep(self.election_timeout()).await;
             if !self.is_leader() {
                 // If no heartbeat, start election
                 let peers = {
@@ -139,32 +674,83 @@ impl RaftNode {
         }
     }
 
+    /// Random election timeout, between 150 and 300 ms
+    fn election_timeout(&self) -> Duration {
+        MIN_ELECTION_TIMEOUT + Duration::from_millis(self.rng.lock().unwrap().gen_range(0..150))
+    }
+
     /// Handle incoming RequestVote RPC from other nodes.
     pub fn handle_request_vote(
         &self,
         req: crate::common::raft::VoteRequest,
     ) -> crate::common::raft::VoteResponse {
-        let mut term = self.term.lock().unwrap();
+        let log_ok = self.log_up_to_date(req.last_log_index, req.last_log_term);
+        if req.pre_vote {
+            let current_term = self.get_term();
+            let vote_granted =
+                log_ok && req.term > current_term && !self.is_leader() && !self.leader_is_alive();
+            return crate::common::raft::VoteResponse {
+                term: current_term,
+                vote_granted,
+            };
+        }
+
+        let old_term = self.get_term();
+        let (term, vote_granted) = self.update_vote(req.term, req.candidate_id, log_ok);
+        if term > old_term {
+            // A newer term deposes us, whether or not the candidate gets our vote
+            *self.role.lock().unwrap() = RaftRole::Follower;
+            *self.leader_id.lock().unwrap() = None;
+            *self.leader_addr.lock().unwrap() = None;
+        }
+        crate::common::raft::VoteResponse { term, vote_granted }
+    }
+
+    /// Whether a candidate whose log ends at (`last_index`, `last_term`) is at
+    /// least as up to date as ours (the Raft election restriction)
+    fn log_up_to_date(&self, last_index: u64, last_term: u64) -> bool {
+        let log = self.log.lock().unwrap();
+        let (our_index, our_term) = self.last_log_position(&log);
+        (last_term, last_index) >= (our_term, our_index)
+    }
+
+    /// Whether a leader was heard from within the minimum election timeout
+    fn leader_is_alive(&self) -> bool {
+        self.last_leader_contact
+            .lock()
+            .unwrap()
+            .is_some_and(|at| at.elapsed() < MIN_ELECTION_TIMEOUT)
+    }
+
+    /// Apply a vote request to the term and vote, persisting any change before
+    /// it takes effect. Returns the resulting term and whether the vote was granted.
+    /// A new vote also requires `log_ok`; repeating a vote already cast doesn't.
+    fn update_vote(&self, term: u64, candidate_id: String, log_ok: bool) -> (u64, bool) {
+        let mut current_term = self.term.lock().unwrap();
         let mut voted_for = self.voted_for.lock().unwrap();
-        let current_term = *term;
-        let mut vote_granted = false;
+        if term < *current_term {
+            return (*current_term, false);
+        }
 
-        if req.term < current_term {
-            vote_granted = false;
+        let mut new_vote = if term > *current_term {
+            None
         } else {
-            if req.term > current_term {
-                *term = req.term;
-                *voted_for = None;
-            }
-            if voted_for.is_none() || voted_for.as_ref() == Some(&req.candidate_id) {
-                *voted_for = Some(req.candidate_id.clone());
-                vote_granted = true;
-            }
+            voted_for.clone()
+        };
+        let granted = new_vote.as_ref() == Some(&candidate_id) || (new_vote.is_none() && log_ok);
+        if granted {
+            new_vote = Some(candidate_id);
         }
-        crate::common::raft::VoteResponse {
-            term: *term,
-            vote_granted,
+
+        if term != *current_term || new_vote != *voted_for {
+            if let Err(e) = self.persist_hard_state(term, &new_vote) {
+                tracing::error!("Failed to persist Raft hard state: {}", e);
+                return (*current_term, false);
+            }
+            *current_term = term;
+            *voted_for = new_vote;
         }
+        (*current_term, granted)
     }
 
     /// Handle incoming AppendEntries RPC from leader.
@@ -175,42 +761,219 @@ impl RaftNode {
     ) -> crate::common::raft::AppendResponse {
         let mut term = self.term.lock().unwrap();
         let current_term = *term;
+        // A current leader: remember where clients should be sent
+        let leader =
+            (req.term >= current_term).then(|| (req.leader_id.clone(), req.leader_addr.clone()));
         let mut log = self.log.lock().unwrap();
         let mut conflict_index = 0;
         let success = if req.term < current_term {
             false
         } else {
             if req.term > current_term {
+                let mut voted_for = self.voted_for.lock().unwrap();
+                if let Err(e) = self.persist_hard_state(req.term, &None) {
+                    tracing::error!("Failed to persist Raft hard state: {}", e);
+                    return crate::common::raft::AppendResponse {
+                        term: current_term,
+                        success: false,
+                        conflict_index: 0,
+                    };
+                }
                 *term = req.term;
+                *voted_for = None;
             }
-            if req.prev_log_index as usize <= log.len() {
+            let (last_index, _) = self.last_log_position(&log);
+            let snapshot_index = self.snapshot_meta().last_included_index;
+            let prev_matches = if req.prev_log_index > last_index {
+                conflict_index = last_index;
+                false
+            } else if req.prev_log_index <= snapshot_index {
+                true
+            } else {
+                match self.term_at(&log, req.prev_log_index) {
+                    Some(t) if t == req.prev_log_term => true,
+                    other => {
+                        // Skip the leader back over the whole conflicting term
+                        let first = other
+                            .and_then(|t| log.iter().find(|e| e.term == t))
+                            .map_or(req.prev_log_index, |e| e.index);
+                        conflict_index = (first - 1).max(snapshot_index);
+                        false
+                    }
+                }
+            };
+            if prev_matches {
+                // Only what this request covers is known to match the leader
+                let last_new = req.entries.last().map_or(req.prev_log_index, |e| e.index);
+                // Skip entries we already have, drop a conflicting suffix
+                let keep = log.len();
+                let mut first_new = keep;
                 for entry in req.entries {
+                    if entry.index <= snapshot_index {
+                        continue;
+                    }
+                    match log.iter().position(|e| e.index == entry.index) {
+                        Some(pos) if log[pos].term == entry.term => continue,
+                        Some(pos) => {
+                            log.truncate(pos);
+                            first_new = first_new.min(pos);
+                        }
+                        None => {}
+                    }
                     log.push(entry);
                 }
+                if let Err(e) = self.persist_log_suffix(&log, first_new) {
+                    tracing::error!("Failed to persist Raft log: {}", e);
+                    return crate::common::raft::AppendResponse {
+                        term: *term,
+                        success: false,
+                        conflict_index: last_index,
+                    };
+                }
                 // Update the commit index
                 let mut commit = self.commit_index.lock().unwrap();
                 if req.leader_commit > *commit {
-                    *commit =
-                        std::cmp::min(req.leader_commit, log.last().map(|e| e.index).unwrap_or(0));
+                    *commit = (*commit).max(req.leader_commit.min(last_new));
                 }
                 true
             } else {
-                conflict_index = log.len() as u64;
                 false
             }
         };
-        crate::common::raft::AppendResponse {
+        let resp = crate::common::raft::AppendResponse {
             term: *term,
             success,
             conflict_index,
+        };
+        drop(log);
+        drop(term);
+
+        if let Some((leader_id, leader_addr)) = leader {
+            *self.last_leader_contact.lock().unwrap() = Some(Instant::now());
+            *self.role.lock().unwrap() = RaftRole::Follower;
+            self.set_leader(Some(leader_id));
+            *self.leader_addr.lock().unwrap() = Some(leader_addr).filter(|a| !a.is_empty());
         }
+
+        if resp.success {
+            if let Err(e) = self.apply_committed() {
+                tracing::error!("Failed to apply committed Raft entries: {}", e);
+            }
+        }
+        resp
     }
 
     pub async fn start_election_and_collect_votes(&self, peers: Vec<String>) -> bool {
-        let new_term = self.start_election();
+        if !self.pre_vote(&peers).await {
+            tracing::debug!("Node {} lost the pre-vote", self.node_id);
+            return false;
+        }
+        self.run_election(peers).await
+    }
+
+    /// Ask the peers whether they would vote for us in the next term, without
+    /// changing any state. Returns whether a majority would.
+    async fn pre_vote(&self, peers: &[String]) -> bool {
+        let term = self.get_term() + 1;
+        let (last_log_index, last_log_term) = {
+            let log = self.log.lock().unwrap();
+            self.last_log_position(&log)
+        };
+        let mut votes = 1; // Our own
+        for peer in peers {
+            let req = crate::common::raft::VoteRequest {
+                term,
+                candidate_id: self.node_id.clone(),
+                last_log_index,
+                last_log_term,
+                pre_vote: true,
+            };
+            if let Ok(resp) = send_request_vote_rpc(peer, req).await {
+                if resp.term > term {
+                    self.step_down(resp.term, None);
+                    return false;
+                }
+                if resp.vote_granted {
+                    votes += 1;
+                }
+            }
+        }
+        votes >= quorum(peers.len() + 1)
+    }
+
+    /// Campaign right away, skipping the pre-vote. Used when the leader hands
+    /// over leadership (TimeoutNow).
+    pub async fn campaign_now(&self) -> bool {
+        let peers = self.get_peers();
+        self.run_election(peers).await
+    }
+
+    /// Handle TimeoutNow from the leader of `term`: returns whether this node
+    /// should start an election at once
+    pub fn handle_timeout_now(&self, term: u64, leader_id: &str) -> bool {
+        term == self.get_term() && self.get_leader().as_deref() == Some(leader_id)
+    }
+
+    /// Hand leadership to `target` (a peer address): bring its log up to date,
+    /// then tell it to start an election at once. Returns once this node has
+    /// stepped down; fails with `ConsensusTimeout` if that takes over `timeout`.
+    pub async fn transfer_leadership(&self, target: &str, timeout: Duration) -> Result<()> {
+        self.ensure_leader()?;
+        if !self.get_peers().iter().any(|peer| peer == target) {
+            return Err(crate::Error::NotFound(format!("Raft peer {}", target)));
+        }
+        let deadline = tokio::time::Instant::now() + timeout;
+        let term = self.get_term();
+
+        // Wait for the target's replicator to bring its log level with ours
+        loop {
+            self.ensure_leader()?;
+            let last_index = {
+                let log = self.log.lock().unwrap();
+                self.last_log_position(&log).0
+            };
+            if self
+                .peer_progress(target)
+                .is_some_and(|p| p.match_index >= last_index)
+            {
+                break;
+            }
+            if tokio::time::Instant::now() >= deadline {
+                return Err(crate::Error::ConsensusTimeout);
+            }
+            self.replication_notify.notify_waiters();
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+
+        tracing::info!("Transferring leadership of term {} to {}", term, target);
+        if let Err(e) = send_timeout_now_rpc(target, term, &self.node_id).await {
+            return Err(crate::Error::Raft(format!(
+                "TimeoutNow to {}: {}",
+                target, e
+            )));
+        }
+        while self.is_leader() && self.get_term() == term {
+            if tokio::time::Instant::now() >= deadline {
+                return Err(crate::Error::ConsensusTimeout);
+            }
+            // Our heartbeats learn the target's new term and make us step down
+            self.heartbeat_round().await;
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        Ok(())
+    }
+
+    /// Run an election for the next term with the given peers
+    async fn run_election(&self, peers: Vec<String>) -> bool {
+        let new_term = match self.start_election() {
+            Ok(term) => term,
+            Err(e) => {
+                tracing::error!("Cannot start election: {}", e);
+                return false;
+            }
+        };
         let log_snapshot = self.log.lock().unwrap().clone();
-        let last_log_index = log_snapshot.last().map(|e| e.index).unwrap_or(0);
-        let last_log_term = log_snapshot.last().map(|e| e.term).unwrap_or(0);
+        let (last_log_index, last_log_term) = self.last_log_position(&log_snapshot);
         let mut votes = 1; // Vote for self
         for peer in &peers {
             let req = crate::common::raft::VoteRequest {
@@ -218,19 +981,28 @@ impl RaftNode {
                 candidate_id: self.node_id.clone(),
                 last_log_index,
                 last_log_term,
+                pre_vote: false,
             };
             if let Ok(resp) = send_request_vote_rpc(peer, req).await {
+                if resp.term > new_term {
+                    self.step_down(resp.term, None);
+                    return false;
+                }
                 if resp.vote_granted {
                     votes += 1;
                 }
             }
         }
-        let majority = (peers.len() + 1).div_ceil(2);
-        if votes >= majority {
+        // A leader or a newer term may have shown up while votes were out
+        let still_candidate = self.get_role() == RaftRole::Candidate && self.get_term() == new_term;
+        if still_candidate && votes >= quorum(peers.len() + 1) {
             self.become_leader();
             true
         } else {
-            self.step_down(new_term, None);
+            // Back to follower, keeping the vote we cast for ourselves
+            if still_candidate {
+                *self.role.lock().unwrap() = RaftRole::Follower;
+            }
             false
         }
     }
@@ -241,18 +1013,42 @@ impl RaftNode {
             term: Arc::new(Mutex::new(0)),
             voted_for: Arc::new(Mutex::new(None)),
             leader_id: Arc::new(Mutex::new(None)),
+            leader_addr: Arc::new(Mutex::new(None)),
+            advertise_addr: None,
+            last_leader_contact: Arc::new(Mutex::new(None)),
             log: Arc::new(Mutex::new(Vec::new())),
             peers: Arc::new(Mutex::new(Vec::new())),
+            progress: Arc::new(Mutex::new(HashMap::new())),
+            replication_notify: Arc::new(tokio::sync::Notify::new()),
+            commit_notify: Arc::new(tokio::sync::Notify::new()),
             commit_index: Arc::new(Mutex::new(0)),
             last_applied: Arc::new(Mutex::new(0)),
             snapshot: Arc::new(Mutex::new(None)),
+            snapshot_meta: Arc::new(Mutex::new(SnapshotMeta::default())),
+            snapshot_threshold: u64::MAX,
+            storage: None,
+            rng: Mutex::new(StdRng::from_entropy()),
         }
     }
 
+    pub fn node_id(&self) -> &str {
+        &self.node_id
+    }
+
     pub fn is_leader(&self) -> bool {
         matches!(*self.role.lock().unwrap(), RaftRole::Leader)
     }
 
+    /// Fails with `NotLeader` (carrying the known leader) unless this node leads
+    pub fn ensure_leader(&self) -> Result<()> {
+        if self.is_leader() {
+            return Ok(());
+        }
+        Err(crate::Error::NotLeader(
+            self.get_leader().unwrap_or_else(|| "unknown".to_string()),
+        ))
+    }
+
     pub fn get_role(&self) -> RaftRole {
         *self.role.lock().unwrap()
     }
@@ -261,139 +1057,180 @@ impl RaftNode {
         self.leader_id.lock().unwrap().clone()
     }
 
+    /// HTTP address of the current leader, if known
+    pub fn get_leader_addr(&self) -> Option<String> {
+        if self.is_leader() {
+            return self.advertise_addr.clone();
+        }
+        self.leader_addr.lock().unwrap().clone()
+    }
+
     pub fn get_term(&self) -> u64 {
         *self.term.lock().unwrap()
     }
 
-    /// Become leader (for single-node testing)
+    /// Become leader (also used directly for single-node testing).
+    /// Every follower is assumed to be up to date until it says otherwise.
     pub fn become_leader(&self) {
+        let last_index = {
+            let log = self.log.lock().unwrap();
+            self.last_log_position(&log).0
+        };
+        let peers = self.get_peers();
+        *self.progress.lock().unwrap() = peers
+            .into_iter()
+            .map(|peer| {
+                let progress = PeerProgress {
+                    next_index: last_index + 1,
+                    match_index: 0,
+                };
+                (peer, progress)
+            })
+            .collect();
         *self.role.lock().unwrap() = RaftRole::Leader;
-        *self.leader_id.lock().unwrap() = Some(self.node_id.clone());
+        self.set_leader(Some(self.node_id.clone()));
+        self.replication_notify.notify_waiters();
+        events::publish(ClusterEvent::LeaderElected {
+            node_id: self.node_id.clone(),
+            term: self.get_term(),
+        });
     }
 
     /// Step down to follower
     pub fn step_down(&self, new_term: u64, leader_id: Option<String>) {
         *self.role.lock().unwrap() = RaftRole::Follower;
-        *self.term.lock().unwrap() = new_term;
-        *self.leader_id.lock().unwrap() = leader_id;
-        *self.voted_for.lock().unwrap() = None;
+        self.set_leader(leader_id);
+        *self.leader_addr.lock().unwrap() = None;
+        let mut term = self.term.lock().unwrap();
+        let mut voted_for = self.voted_for.lock().unwrap();
+        if new_term != *term || voted_for.is_some() {
+            if let Err(e) = self.persist_hard_state(new_term, &None) {
+                tracing::error!("Failed to persist Raft hard state: {}", e);
+            }
+        }
+        *term = new_term;
+        *voted_for = None;
+    }
+
+    /// Follow the leader of `term`. A newer term is persisted first; the vote
+    /// is only reset when the term actually changes.
+    pub fn follow_leader(&self, term: u64, leader_id: String) -> Result<()> {
+        {
+            let mut current_term = self.term.lock().unwrap();
+            let mut voted_for = self.voted_for.lock().unwrap();
+            if term > *current_term {
+                self.persist_hard_state(term, &None)?;
+                *current_term = term;
+                *voted_for = None;
+            }
+        }
+        *self.role.lock().unwrap() = RaftRole::Follower;
+        if self.get_leader().as_ref() != Some(&leader_id) {
+            *self.leader_addr.lock().unwrap() = None;
+        }
+        self.set_leader(Some(leader_id));
+        Ok(())
+    }
+
+    /// Record the current leader, counting changes to a new one
+    fn set_leader(&self, leader: Option<String>) {
+        let mut current = self.leader_id.lock().unwrap();
+        if leader.is_some() && *current != leader {
+            METRICS.raft_leader_changes.inc();
+        }
+        *current = leader;
     }
 
-    /// Start election
-    pub fn start_election(&self) -> u64 {
+    /// Refresh the Raft gauges of the global metrics registry
+    pub fn export_metrics(&self) {
+        METRICS.raft_term.set(self.get_term());
+        METRICS.raft_is_leader.set(self.is_leader() as u64);
+        METRICS
+            .raft_log_entries
+            .set(self.log.lock().unwrap().len() as u64);
+        METRICS
+            .raft_commit_index
+            .set(*self.commit_index.lock().unwrap());
+        METRICS
+            .raft_last_applied
+            .set(*self.last_applied.lock().unwrap());
+    }
+
+    /// Start election: move to the next term and vote for ourselves.
+    /// Fails if the new term and vote can't be persisted.
+    pub fn start_election(&self) -> Result<u64> {
         let mut term = self.term.lock().unwrap();
-        *term += 1;
-        let new_term = *term;
+        let mut voted_for = self.voted_for.lock().unwrap();
+        let new_term = *term + 1;
+        let vote = Some(self.node_id.clone());
+        self.persist_hard_state(new_term, &vote)?;
+        *term = new_term;
+        *voted_for = vote;
+        METRICS.raft_elections.inc();
 
         *self.role.lock().unwrap() = RaftRole::Candidate;
-        *self.voted_for.lock().unwrap() = Some(self.node_id.clone());
         *self.leader_id.lock().unwrap() = None;
 
-        new_term
+        Ok(new_term)
     }
 
     /// Grant vote
     pub fn grant_vote(&self, term: u64, candidate_id: String) -> bool {
-        let mut current_term = self.term.lock().unwrap();
-        let mut voted = self.voted_for.lock().unwrap();
-
-        if term < *current_term {
-            return false;
-        }
-
-        if term > *current_term {
-            *current_term = term;
-            *voted = None;
-        }
+        self.update_vote(term, candidate_id, true).1
+    }
 
-        if voted.is_none() || voted.as_ref() == Some(&candidate_id) {
-            *voted = Some(candidate_id);
-            true
-        } else {
-            false
-        }
+    /// Replicate a metadata mutation and apply it once committed
+    pub async fn propose(&self, command: &MetadataCommand) -> Result<()> {
+        self.replicate(command.encode()?).await
     }
 
-    /// Replicate entry (simplified)
-    pub async fn replicate(&self, _entry: Vec<u8>) -> Result<()> {
-        if !self.is_leader() {
-            return Err(crate::Error::NotLeader(
-                self.get_leader().unwrap_or_else(|| "unknown".to_string()),
-            ));
-        }
-        // 1. Append entry to local log
-        let index;
-        let term;
-        let entry;
-        {
+    /// Append an entry to the log, hand it to the replicators and wait until
+    /// a majority holds it. Once this returns, the entry is committed and applied.
+    pub async fn replicate(&self, data: Vec<u8>) -> Result<()> {
+        self.ensure_leader()?;
+        let term = self.get_term();
+        let index = {
             let mut log = self.log.lock().unwrap();
-            index = log.last().map(|e| e.index + 1).unwrap_or(1);
-            term = self.get_term();
-            entry = crate::common::raft::LogEntry {
-                term,
-                index,
-                data: _entry,
-            };
-            log.push(entry.clone());
-        }
-        let peers = {
-            let peers_guard = self.peers.lock().unwrap();
-            peers_guard.clone()
-        };
-        let entry_snapshot = entry.clone();
-        let node_id = self.node_id.clone();
-        let mut ack_count = 1; // Leader self-ack
-        for peer in &peers {
-            let req = crate::common::raft::AppendRequest {
-                term,
-                leader_id: node_id.clone(),
-                prev_log_index: index - 1,
-                prev_log_term: term,
-                entries: vec![entry_snapshot.clone()],
-                leader_commit: index,
-            };
-            if let Ok(resp) = send_append_entries_rpc(peer, req).await {
-                if resp.success {
-                    ack_count += 1;
-                }
+            let index = self.last_log_position(&log).0 + 1;
+            log.push(LogEntry { term, index, data });
+            if let Err(e) = self.persist_log_suffix(&log, log.len() - 1) {
+                log.pop();
+                return Err(e);
             }
-        }
-        let majority = (peers.len() + 1).div_ceil(2);
-        if ack_count >= majority {
-            // Effective commit: advance commit_index
-            let mut commit = self.commit_index.lock().unwrap();
-            *commit = index;
-            let mut applied = self.last_applied.lock().unwrap();
-            while *applied < *commit {
-                // Apply log[*applied] to the real state machine here
-                *applied += 1;
-            }
-            Ok(())
-        } else {
-            Err(crate::Error::Internal(
-                "Raft: no majority for commit".to_string(),
-            ))
-        }
+            index
+        };
+        self.replication_notify.notify_waiters();
+        // Commits right away when there are no peers
+        self.advance_commit();
+        self.wait_committed(index, term).await
     }
 }
 
+/// Votes or acks needed for a strict majority of `nodes`
+fn quorum(nodes: usize) -> usize {
+    nodes / 2 + 1
+}
+
 pub fn start_raft_tasks(node: Arc<RaftNode>) -> tokio::task::JoinHandle<()> {
+    start_replicators(node.clone());
     tokio::spawn({
         let node = node.clone();
         async move {
             let mut last_heartbeat = tokio::time::Instant::now();
-            let mut election_timeout =
-                tokio::time::Duration::from_millis(150 + rand::random::<u64>() % 150);
+            let mut election_timeout = node.election_timeout();
             loop {
                 tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
                 // Clone peers each loop to avoid holding MutexGuard
                 let peers = node.peers.lock().unwrap().clone();
+                // A leader's AppendEntries resets the election timeout
+                if let Some(contact) = *node.last_leader_contact.lock().unwrap() {
+                    last_heartbeat = last_heartbeat.max(contact);
+                }
                 // If follower and no heartbeat received, start election
                 if !node.is_leader() && last_heartbeat.elapsed() > election_timeout {
                     tracing::info!("Node {} starting election", node.node_id);
                     node.start_election_and_collect_votes(peers).await;
-                    election_timeout =
-                        tokio::time::Duration::from_millis(150 + rand::random::<u64>() % 150);
+                    election_timeout = node.election_timeout();
                     last_heartbeat = tokio::time::Instant::now();
                 }
                 // If leader, send heartbeats
@@ -401,7 +1238,25 @@ pub fn start_raft_tasks(node: Arc<RaftNode>) -> tokio::task::JoinHandle<()> {
                     node.send_heartbeats().await;
                     last_heartbeat = tokio::time::Instant::now();
                 }
+                if let Err(e) = node.maybe_compact() {
+                    tracing::error!("Raft log compaction failed: {}", e);
+                }
+                node.export_metrics();
             }
         }
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quorum_is_strict_majority() {
+        assert_eq!(quorum(1), 1);
+        assert_eq!(quorum(2), 2);
+        assert_eq!(quorum(3), 2);
+        assert_eq!(quorum(4), 3);
+        assert_eq!(quorum(5), 3);
+    }
+}
@@ -0,0 +1,123 @@
+//! Per-peer log replication for the Raft leader
+//!
+//! Each peer gets a replicator task that ships the log in batches and keeps
+//! several AppendEntries in flight, so one slow round trip doesn't hold up
+//! every write behind it. The leader tracks, per peer, the next entry to send
+//! (`next_index`) and the highest entry known to be replicated there
+//! (`match_index`); a rejected append moves `next_index` back to where the
+//! follower's log may still agree with ours.
+
+use crate::coordinator::raft_node::RaftNode;
+use crate::coordinator::raft_rpc_client::{append_entries_with, connect_peer, PeerClient};
+use futures_util::stream::{FuturesUnordered, StreamExt};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Most entries sent in one AppendEntries
+pub const MAX_BATCH_ENTRIES: usize = 256;
+
+/// Payload size after which a batch is cut short (a single larger entry is still sent)
+pub const MAX_BATCH_BYTES: usize = 1024 * 1024;
+
+/// AppendEntries a peer may have outstanding at once
+pub const MAX_IN_FLIGHT: usize = 4;
+
+/// How long a replicator sleeps when idle or after a failed RPC
+const IDLE_WAIT: Duration = Duration::from_millis(50);
+
+/// Replication progress of one follower, as seen by the leader
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PeerProgress {
+    /// Index of the next entry to send
+    pub next_index: u64,
+    /// Highest entry known to be in the follower's log
+    pub match_index: u64,
+}
+
+/// What a replicator should send next
+pub(crate) enum Batch {
+    Append(crate::common::raft::AppendRequest),
+    /// The follower needs entries already compacted into the snapshot
+    Snapshot,
+    UpToDate,
+}
+
+/// Spawn a replicator for every peer. They idle while this node isn't leader.
+pub fn start_replicators(node: Arc<RaftNode>) -> Vec<tokio::task::JoinHandle<()>> {
+    node.get_peers()
+        .into_iter()
+        .map(|peer| tokio::spawn(run_replicator(node.clone(), peer)))
+        .collect()
+}
+
+async fn run_replicator(node: Arc<RaftNode>, peer: String) {
+    let mut client: Option<PeerClient> = None;
+    let mut in_flight = FuturesUnordered::new();
+    loop {
+        // Register for wake-ups before looking at the log, so none is missed
+        let wakeup = node.replication_wakeup().notified();
+        tokio::pin!(wakeup);
+        wakeup.as_mut().enable();
+
+        let Some(term) = node.leader_term() else {
+            in_flight = FuturesUnordered::new();
+            tokio::select! {
+                _ = wakeup => {}
+                _ = tokio::time::sleep(IDLE_WAIT) => {}
+            }
+            continue;
+        };
+        let conn = match &client {
+            Some(conn) => conn.clone(),
+            None => match connect_peer(&peer).await {
+                Ok(conn) => {
+                    client = Some(conn.clone());
+                    conn
+                }
+                Err(e) => {
+                    tracing::debug!("Raft peer {} unreachable: {}", peer, e);
+                    tokio::time::sleep(IDLE_WAIT).await;
+                    continue;
+                }
+            },
+        };
+
+        while in_flight.len() < MAX_IN_FLIGHT {
+            match node.next_batch(&peer, term) {
+                Batch::Append(req) => {
+                    let last_sent = req.prev_log_index + req.entries.len() as u64;
+                    let mut conn = conn.clone();
+                    in_flight.push(async move {
+                        (last_sent, append_entries_with(&mut conn, req).await)
+                    });
+                }
+                Batch::Snapshot => {
+                    // Let outstanding appends settle first; they may move next_index
+                    if in_flight.is_empty() {
+                        node.send_snapshot(&peer, term).await;
+                    }
+                    break;
+                }
+                Batch::UpToDate => break,
+            }
+        }
+
+        let done = tokio::select! {
+            Some(done) = in_flight.next(), if !in_flight.is_empty() => Some(done),
+            _ = wakeup => None,
+            _ = tokio::time::sleep(IDLE_WAIT) => None,
+        };
+        match done {
+            Some((last_sent, Ok(resp))) => node.on_append_response(&peer, term, last_sent, &resp),
+            Some((_, Err(status))) => {
+                tracing::debug!("AppendEntries to {} failed: {}", peer, status);
+                // Resend everything not acknowledged, over a fresh connection
+                node.rewind_peer(&peer);
+                in_flight = FuturesUnordered::new();
+                client = None;
+                tokio::time::sleep(IDLE_WAIT).await;
+            }
+            None => {}
+        }
+    }
+}
@@ -1,14 +1,31 @@
 //! Raft gRPC client helpers
-use crate::common::raft::{AppendRequest, AppendResponse, VoteRequest, VoteResponse};
+use crate::common::raft::{AppendRequest, AppendResponse, SnapshotMeta, VoteRequest, VoteResponse};
+use crate::common::{connect_internal, InternalChannel};
 use crate::proto::coordinator_internal_client::CoordinatorInternalClient;
 
+/// Connection to a peer coordinator, reusable across RPCs
+pub type PeerClient = CoordinatorInternalClient<InternalChannel>;
+
+pub async fn connect_peer(peer_addr: &str) -> Result<PeerClient, tonic::Status> {
+    let channel = connect_internal(peer_addr)
+        .await
+        .map_err(|e| tonic::Status::internal(e.to_string()))?;
+    Ok(CoordinatorInternalClient::new(channel))
+}
+
 pub async fn send_append_entries_rpc(
     peer_addr: &str,
     req: AppendRequest,
 ) -> Result<AppendResponse, tonic::Status> {
-    let mut client = CoordinatorInternalClient::connect(peer_addr.to_string())
-        .await
-        .map_err(|e| tonic::Status::internal(e.to_string()))?;
+    let mut client = connect_peer(peer_addr).await?;
+    append_entries_with(&mut client, req).await
+}
+
+/// AppendEntries over an existing connection
+pub async fn append_entries_with(
+    client: &mut PeerClient,
+    req: AppendRequest,
+) -> Result<AppendResponse, tonic::Status> {
     let proto_req: crate::proto::AppendRequest = (&req).into();
     let resp = client.append_entries(proto_req).await?.into_inner();
     Ok((&resp).into())
@@ -18,10 +35,63 @@ pub async fn send_request_vote_rpc(
     peer_addr: &str,
     req: VoteRequest,
 ) -> Result<VoteResponse, tonic::Status> {
-    let mut client = CoordinatorInternalClient::connect(peer_addr.to_string())
-        .await
-        .map_err(|e| tonic::Status::internal(e.to_string()))?;
+    let mut client = connect_peer(peer_addr).await?;
     let proto_req: crate::proto::VoteRequest = (&req).into();
     let resp = client.request_vote(proto_req).await?.into_inner();
     Ok((&resp).into())
 }
+
+/// Tell `peer` to start an election immediately (leadership transfer)
+pub async fn send_timeout_now_rpc(
+    peer_addr: &str,
+    term: u64,
+    leader_id: &str,
+) -> Result<u64, tonic::Status> {
+    let mut client = connect_peer(peer_addr).await?;
+    let resp = client
+        .timeout_now(crate::proto::TimeoutNowRequest {
+            term,
+            leader_id: leader_id.to_string(),
+        })
+        .await?
+        .into_inner();
+    Ok(resp.term)
+}
+
+/// Snapshots are streamed in chunks of this size
+const SNAPSHOT_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// Stream a snapshot to a follower. Returns the follower's term.
+pub async fn send_install_snapshot_rpc(
+    peer_addr: &str,
+    term: u64,
+    leader_id: &str,
+    meta: SnapshotMeta,
+    data: Vec<u8>,
+) -> Result<u64, tonic::Status> {
+    let mut client = connect_peer(peer_addr).await?;
+
+    // An empty snapshot still needs one (final) chunk
+    let num_chunks = data.len().div_ceil(SNAPSHOT_CHUNK_SIZE).max(1);
+    let chunks: Vec<crate::proto::SnapshotRequest> = (0..num_chunks)
+        .map(|i| {
+            let start = i * SNAPSHOT_CHUNK_SIZE;
+            let end = (start + SNAPSHOT_CHUNK_SIZE).min(data.len());
+            crate::proto::SnapshotRequest {
+                term,
+                leader_id: leader_id.to_string(),
+                last_included_index: meta.last_included_index,
+                last_included_term: meta.last_included_term,
+                data: data[start..end].to_vec(),
+                offset: start as u64,
+                done: i + 1 == num_chunks,
+            }
+        })
+        .collect();
+
+    let resp = client
+        .install_snapshot(tokio_stream::iter(chunks))
+        .await?
+        .into_inner();
+    Ok(resp.term)
+}
@@ -0,0 +1,509 @@
+//! Cluster repair
+//!
+//! Brings every key back to the replication factor. Replicas on dead or
+//! unregistered volumes no longer count; the key is copied from a replica
+//! matching its checksum to volumes chosen by the placement policy until it
+//! has enough, and its metadata repointed through Raft. Up to `parallelism`
+//! keys are copied at once.
+//!
+//! Keys are walked page by page in key order. After each page the progress
+//! is persisted in the metadata config column family, with the last key of
+//! the page as checkpoint, so the CLI can poll it and a repair interrupted
+//! by a restart or a lost leadership resumes from its checkpoint when this
+//! coordinator is asked to repair again with the same settings.
+
+use crate::common::{timestamp_now, NodeState, Result};
+use crate::coordinator::anti_entropy::copy_key;
+use crate::coordinator::events::{self, ClusterEvent};
+use crate::coordinator::metadata::{
+    KeyMetadata, KeyState, MetadataCommand, MetadataStore, VolumeMetadata, DEFAULT_PAGE_SIZE,
+};
+use crate::coordinator::placement::PlacementManager;
+use crate::coordinator::raft_node::RaftNode;
+use futures_util::stream::{self, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+const PROGRESS_KEY: &str = "repair/progress";
+
+/// Keys copied at once unless asked otherwise
+pub const DEFAULT_PARALLELISM: usize = 8;
+
+/// Set while a repair runs in this process
+static RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// Phase of a repair
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RepairState {
+    Running,
+    /// Every key was walked and none failed
+    Completed,
+    /// Some keys could not be repaired, or the walk stopped early
+    Failed,
+}
+
+/// Progress of a cluster repair
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RepairProgress {
+    pub state: RepairState,
+    /// Replicas each key should have
+    pub replicas: usize,
+    /// Keys copied at once
+    pub parallelism: usize,
+    pub dry_run: bool,
+    /// Under-replicated keys when the repair started, and the bytes to copy
+    pub total_keys: u64,
+    pub total_bytes: u64,
+    pub keys_checked: u64,
+    /// Keys brought back to `replicas` (or, in a dry run, that would have been)
+    pub keys_repaired: u64,
+    pub keys_failed: u64,
+    pub bytes_copied: u64,
+    /// Last key of the last page walked; a resumed repair starts after it
+    pub checkpoint: Option<String>,
+    pub started_at: u64,
+    pub updated_at: u64,
+    /// Estimated seconds left, once some data has been copied
+    pub eta_secs: Option<u64>,
+    pub error: Option<String>,
+}
+
+impl RepairProgress {
+    fn new(replicas: usize, parallelism: usize, dry_run: bool) -> Self {
+        let now = timestamp_now();
+        Self {
+            state: RepairState::Running,
+            replicas,
+            parallelism,
+            dry_run,
+            total_keys: 0,
+            total_bytes: 0,
+            keys_checked: 0,
+            keys_repaired: 0,
+            keys_failed: 0,
+            bytes_copied: 0,
+            checkpoint: None,
+            started_at: now,
+            updated_at: now,
+            eta_secs: None,
+            error: None,
+        }
+    }
+
+    /// Refresh the timestamp and the ETA from the average throughput so far
+    fn touch(&mut self) {
+        self.updated_at = timestamp_now();
+        let elapsed = self.updated_at.saturating_sub(self.started_at).max(1);
+        let (done, total) = if self.total_bytes > 0 {
+            (self.bytes_copied, self.total_bytes)
+        } else {
+            (self.keys_repaired, self.total_keys)
+        };
+        self.eta_secs = (done > 0).then(|| total.saturating_sub(done) * elapsed / done);
+    }
+
+    /// Whether a repair with these settings picks up where this one stopped
+    fn resumes_as(&self, replicas: usize, dry_run: bool) -> bool {
+        self.state != RepairState::Completed
+            && self.checkpoint.is_some()
+            && self.replicas == replicas
+            && self.dry_run == dry_run
+    }
+}
+
+/// Get the progress of the last repair
+pub fn get_progress(metadata: &MetadataStore) -> Result<Option<RepairProgress>> {
+    match metadata.get_config(PROGRESS_KEY)? {
+        Some(bytes) => serde_json::from_slice(&bytes)
+            .map(Some)
+            .map_err(|e| crate::Error::MetadataCorrupted(e.to_string())),
+        None => Ok(None),
+    }
+}
+
+fn progress_command(progress: &RepairProgress) -> Result<MetadataCommand> {
+    let value = serde_json::to_vec(progress)
+        .map_err(|e| crate::Error::Internal(format!("Serialize error: {}", e)))?;
+    Ok(MetadataCommand::PutConfig {
+        key: PROGRESS_KEY.to_string(),
+        value,
+    })
+}
+
+async fn save_progress(raft: &RaftNode, progress: &RepairProgress) -> Result<()> {
+    raft.propose(&progress_command(progress)?).await
+}
+
+/// Start repairing the cluster in the background, resuming the last repair
+/// from its checkpoint if it was interrupted (idempotent while one is running)
+pub async fn start_repair(
+    metadata: Arc<MetadataStore>,
+    placement: Arc<Mutex<PlacementManager>>,
+    raft: Arc<RaftNode>,
+    replicas: usize,
+    parallelism: usize,
+    dry_run: bool,
+) -> Result<RepairProgress> {
+    raft.ensure_leader()?;
+    if RUNNING.swap(true, Ordering::SeqCst) {
+        return get_progress(&metadata)?
+            .ok_or_else(|| crate::Error::InvalidRequest("a repair is already running".into()));
+    }
+
+    let progress = match get_progress(&metadata) {
+        Ok(Some(mut last)) if last.resumes_as(replicas, dry_run) => {
+            tracing::info!("Resuming repair after {:?}", last.checkpoint);
+            last.state = RepairState::Running;
+            last.parallelism = parallelism;
+            last.error = None;
+            last
+        }
+        Ok(_) => RepairProgress::new(replicas, parallelism, dry_run),
+        Err(e) => {
+            RUNNING.store(false, Ordering::SeqCst);
+            return Err(e);
+        }
+    };
+    if let Err(e) = save_progress(&raft, &progress).await {
+        RUNNING.store(false, Ordering::SeqCst);
+        return Err(e);
+    }
+    events::publish(ClusterEvent::RepairStarted {
+        replicas: progress.replicas,
+        dry_run: progress.dry_run,
+        resumed: progress.checkpoint.is_some(),
+    });
+
+    let task_progress = progress.clone();
+    tokio::spawn(async move {
+        if let Err(e) = run_repair(&metadata, &placement, &raft, task_progress).await {
+            tracing::error!("Repair failed: {}", e);
+            if let Ok(Some(mut progress)) = get_progress(&metadata) {
+                progress.state = RepairState::Failed;
+                progress.error = Some(e.to_string());
+                progress.touch();
+                let _ = save_progress(&raft, &progress).await;
+            }
+        }
+        RUNNING.store(false, Ordering::SeqCst);
+    });
+
+    Ok(progress)
+}
+
+async fn run_repair(
+    metadata: &MetadataStore,
+    placement: &Mutex<PlacementManager>,
+    raft: &RaftNode,
+    mut progress: RepairProgress,
+) -> Result<()> {
+    let (replicas, dry_run) = (progress.replicas, progress.dry_run);
+
+    if progress.checkpoint.is_none() {
+        let pending = count_under_replicated(metadata, replicas)?;
+        progress.total_keys = pending.keys;
+        progress.total_bytes = pending.bytes;
+        save_progress(raft, &progress).await?;
+        tracing::info!(
+            "Repairing to {} replicas: {} keys, {} bytes",
+            replicas,
+            progress.total_keys,
+            progress.total_bytes
+        );
+    }
+
+    loop {
+        let page =
+            metadata.list_keys_paginated("", progress.checkpoint.as_deref(), DEFAULT_PAGE_SIZE)?;
+        let Some(last) = page.last().map(|meta| meta.key.clone()) else {
+            break;
+        };
+        let full = page.len() == DEFAULT_PAGE_SIZE;
+
+        let volumes = metadata.list_volumes()?;
+        progress.keys_checked += page.len() as u64;
+        let results: Vec<_> = stream::iter(
+            page.iter()
+                .filter(|meta| missing_replicas(meta, &volumes, replicas) > 0),
+        )
+        .map(|meta| async move {
+            let result = repair_key(metadata, placement, raft, &meta.key, replicas, dry_run).await;
+            (&meta.key, result)
+        })
+        .buffer_unordered(progress.parallelism.max(1))
+        .collect()
+        .await;
+
+        for (key, result) in results {
+            match result {
+                Ok(0) => {}
+                Ok(bytes) => {
+                    progress.keys_repaired += 1;
+                    progress.bytes_copied += bytes;
+                }
+                // Another coordinator leads now: stop, keeping the checkpoint
+                Err(e @ crate::Error::NotLeader(_)) => return Err(e),
+                Err(e) => {
+                    tracing::warn!("Repair of {} failed: {}", key, e);
+                    progress.keys_failed += 1;
+                    progress.error = Some(format!("{}: {}", key, e));
+                }
+            }
+        }
+
+        progress.checkpoint = Some(last);
+        progress.touch();
+        save_progress(raft, &progress).await?;
+        if !full {
+            break;
+        }
+    }
+
+    progress.checkpoint = None;
+    progress.touch();
+    if progress.keys_failed > 0 {
+        progress.state = RepairState::Failed;
+    } else {
+        progress.state = RepairState::Completed;
+        progress.eta_secs = Some(0);
+    }
+    tracing::info!(
+        "Repair finished: {} keys repaired, {} bytes copied, {} failed",
+        progress.keys_repaired,
+        progress.bytes_copied,
+        progress.keys_failed
+    );
+    events::publish(ClusterEvent::RepairFinished {
+        state: progress.state,
+        keys_repaired: progress.keys_repaired,
+        bytes_copied: progress.bytes_copied,
+        keys_failed: progress.keys_failed,
+    });
+    save_progress(raft, &progress).await
+}
+
+/// Keys short of the replication factor
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UnderReplicated {
+    /// Replicas each key should have
+    pub replicas: usize,
+    pub keys: u64,
+    /// Bytes to copy to bring them back to `replicas`
+    pub bytes: u64,
+}
+
+/// Count the keys with fewer than `replicas` live replicas, walking the
+/// whole key metadata
+pub fn count_under_replicated(
+    metadata: &MetadataStore,
+    replicas: usize,
+) -> Result<UnderReplicated> {
+    let volumes = metadata.list_volumes()?;
+    let mut pending = UnderReplicated {
+        replicas,
+        keys: 0,
+        bytes: 0,
+    };
+    let mut after: Option<String> = None;
+    loop {
+        let page = metadata.list_keys_paginated("", after.as_deref(), DEFAULT_PAGE_SIZE)?;
+        for meta in &page {
+            let missing = missing_replicas(meta, &volumes, replicas) as u64;
+            if missing > 0 {
+                pending.keys += 1;
+                pending.bytes += meta.size * missing;
+            }
+        }
+        if page.len() < DEFAULT_PAGE_SIZE {
+            break;
+        }
+        after = page.last().map(|meta| meta.key.clone());
+    }
+    Ok(pending)
+}
+
+/// Replicas of `meta` on volumes that still hold data: registered and not dead
+fn live_replicas(meta: &KeyMetadata, volumes: &[VolumeMetadata]) -> Vec<String> {
+    meta.replicas
+        .iter()
+        .filter(|id| {
+            volumes
+                .iter()
+                .any(|v| v.volume_id == **id && v.state != NodeState::Dead)
+        })
+        .cloned()
+        .collect()
+}
+
+/// Number of copies `meta` lacks to have `replicas` live ones. Deduplicated
+/// and chunked keys have no replicas of their own, tiered keys none at all.
+fn missing_replicas(meta: &KeyMetadata, volumes: &[VolumeMetadata], replicas: usize) -> usize {
+    if meta.state != KeyState::Active || meta.blob.is_some() || meta.chunks.is_some() {
+        return 0;
+    }
+    replicas.saturating_sub(live_replicas(meta, volumes).len())
+}
+
+/// Copy `key` to new volumes until it has `replicas` live ones and repoint
+/// its metadata. Returns the bytes copied (in a dry run, to be copied).
+async fn repair_key(
+    metadata: &MetadataStore,
+    placement: &Mutex<PlacementManager>,
+    raft: &RaftNode,
+    key: &str,
+    replicas: usize,
+    dry_run: bool,
+) -> Result<u64> {
+    let Some(meta) = metadata.get_key(key)? else {
+        return Ok(0);
+    };
+    let volumes = metadata.list_volumes()?;
+    let missing = missing_replicas(&meta, &volumes, replicas);
+    if missing == 0 {
+        return Ok(0);
+    }
+    if dry_run {
+        return Ok(meta.size * missing as u64);
+    }
+
+    let mut current = live_replicas(&meta, &volumes);
+    let mut added = 0;
+    let mut failure = None;
+    for _ in 0..missing {
+        let copied = async {
+            let target_id = placement
+                .lock()
+                .unwrap()
+                .select_replacement(key, &current, &volumes)?;
+            let target = volumes
+                .iter()
+                .find(|v| v.volume_id == target_id)
+                .ok_or_else(|| crate::Error::NotFound(target_id.clone()))?;
+            if !copy_key(metadata, key, target).await? {
+                return Err(crate::Error::Internal(format!(
+                    "no replica of {} matches its checksum",
+                    key
+                )));
+            }
+            Ok(target_id)
+        }
+        .await;
+        match copied {
+            Ok(target_id) => {
+                current.push(target_id);
+                added += 1;
+            }
+            Err(e) => {
+                failure = Some(e);
+                break;
+            }
+        }
+    }
+
+    // Publish the copies made, unless the key changed while they were made
+    if added > 0 {
+        if let Some(mut latest) = metadata.get_key(key)? {
+            if latest.state == KeyState::Active && latest.blake3 == meta.blake3 {
+                latest.replicas = current;
+                latest.updated_at = timestamp_now();
+                raft.propose(&MetadataCommand::PutKey(latest)).await?;
+            }
+        }
+    }
+    match failure {
+        Some(e) => Err(e),
+        None => Ok(meta.size * added as u64),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn volume(id: &str, state: NodeState) -> VolumeMetadata {
+        VolumeMetadata {
+            volume_id: id.to_string(),
+            address: String::new(),
+            grpc_address: String::new(),
+            state,
+            shards: vec![],
+            total_keys: 0,
+            total_bytes: 0,
+            free_bytes: 0,
+            last_heartbeat: 0,
+            zone: None,
+            rack: None,
+            weight: None,
+            wal_error: None,
+        }
+    }
+
+    fn key(replicas: &[&str]) -> KeyMetadata {
+        KeyMetadata {
+            key: "default/k".to_string(),
+            replicas: replicas.iter().map(|id| id.to_string()).collect(),
+            size: 10,
+            blake3: String::new(),
+            created_at: 0,
+            updated_at: 0,
+            state: KeyState::Active,
+            tags: Default::default(),
+            blob: None,
+            chunks: None,
+        }
+    }
+
+    #[test]
+    fn test_missing_replicas() {
+        let volumes = [
+            volume("vol-1", NodeState::Alive),
+            volume("vol-2", NodeState::Dead),
+            volume("vol-3", NodeState::Draining),
+        ];
+
+        assert_eq!(missing_replicas(&key(&["vol-1", "vol-3"]), &volumes, 2), 0);
+        // Dead and unregistered volumes don't count
+        assert_eq!(missing_replicas(&key(&["vol-1", "vol-2"]), &volumes, 2), 1);
+        assert_eq!(missing_replicas(&key(&["vol-9"]), &volumes, 3), 3);
+        assert_eq!(
+            live_replicas(&key(&["vol-2", "vol-3"]), &volumes),
+            vec!["vol-3".to_string()]
+        );
+
+        let mut tiered = key(&[]);
+        tiered.state = KeyState::Tiered;
+        assert_eq!(missing_replicas(&tiered, &volumes, 3), 0);
+    }
+
+    #[test]
+    fn test_progress_roundtrip_and_resume() {
+        let dir = tempdir().unwrap();
+        let store = MetadataStore::open(dir.path().join("test.db")).unwrap();
+
+        assert!(get_progress(&store).unwrap().is_none());
+
+        let mut progress = RepairProgress::new(3, 4, false);
+        progress.total_bytes = 1000;
+        progress.bytes_copied = 250;
+        progress.keys_repaired = 1;
+        progress.touch();
+        store.apply(&progress_command(&progress).unwrap()).unwrap();
+
+        let loaded = get_progress(&store).unwrap().unwrap();
+        assert_eq!(loaded.state, RepairState::Running);
+        assert_eq!(loaded.bytes_copied, 250);
+        assert!(loaded.eta_secs.is_some());
+
+        // Only a repair that stopped part way, with the same settings, resumes
+        assert!(!loaded.resumes_as(3, false));
+        progress.checkpoint = Some("default/k".to_string());
+        assert!(progress.resumes_as(3, false));
+        assert!(!progress.resumes_as(2, false));
+        assert!(!progress.resumes_as(3, true));
+        progress.state = RepairState::Completed;
+        assert!(!progress.resumes_as(3, false));
+    }
+}
@@ -0,0 +1,688 @@
+//! Cross-cluster replication
+//!
+//! The leader tails the CDC log (see `coordinator::cdc`) and pushes the key
+//! changes under each target's tenants and prefixes to that remote cluster
+//! through its HTTP API: `POST /<key>` with the current value and tags, or
+//! `DELETE /<key>`, acting for the key's tenant with `X-Minikv-Tenant`. Each
+//! target's cursor is committed through Raft like a CDC sink's, so a new
+//! leader resumes where the old one stopped. Only the latest change of a key
+//! in a batch is pushed, with the value current when it is pushed.
+//!
+//! Pushed changes carry the source's `updated_at` (for a delete, the time it
+//! was applied) in `X-Minikv-Replicated-At`, and the remote settles conflicts
+//! with last-writer-wins (`resolve`): a change older than its own copy of the
+//! key is refused with `409`, ties going to the larger BLAKE3, and applied
+//! changes keep the source's `updated_at`. A change the remote already holds
+//! is acknowledged without a write, so two clusters replicating to each other
+//! don't echo changes back and forth. Deletes leave no tombstone: a delete
+//! racing an older write on the other cluster may lose to it.
+//!
+//! Targets come from `[[coordinator.replication.targets]]` or are added at
+//! runtime through `POST /admin/replication/targets` (as `minikv migrate`
+//! does for its cutover), starting from the current end of the CDC log.
+//! Targets only get the changes still in the CDC log. A new target should be
+//! seeded with `minikv export` / `minikv import` or `minikv migrate`, and one
+//! unreachable for longer than `cdc.retention_secs` misses changes. Lag is
+//! served at `/admin/replication/status` and as `minikv_replication_*` metrics.
+
+use crate::common::{encode_key, timestamp_now, ReplicationConfig, ReplicationTarget, Result};
+use crate::coordinator::cdc::{CdcEvent, CdcOp};
+use crate::coordinator::consistency::ConsistencyLevel;
+use crate::coordinator::metadata::{KeyMetadata, KeyState, MetadataCommand, MetadataStore};
+use crate::coordinator::raft_node::RaftNode;
+use crate::coordinator::{chunking, dedup, tenant, tiering};
+use futures_util::StreamExt;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+/// Header carrying the source's `updated_at` on replicated writes
+pub const REPLICATED_AT_HEADER: &str = "x-minikv-replicated-at";
+
+/// Events read from the CDC log at once
+const BATCH_SIZE: usize = 500;
+
+/// How often the replication task looks for new events
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Config key holding the last sequence number pushed to a target
+const CURSOR_PREFIX: &str = "replication/cursor/";
+
+/// Config key holding the targets added at runtime, as JSON
+const TARGETS_KEY: &str = "replication/targets";
+
+/// Leader redirects followed for one change
+const MAX_REDIRECTS: usize = 3;
+
+/// Replication targets and their counters, shared with the HTTP handlers
+pub static REPLICATION: Lazy<ReplicationManager> = Lazy::new(|| ReplicationManager {
+    targets: RwLock::new(Vec::new()),
+    stats: Mutex::new(HashMap::new()),
+});
+
+pub struct ReplicationManager {
+    targets: RwLock<Vec<ReplicationTarget>>,
+    stats: Mutex<HashMap<String, TargetStats>>,
+}
+
+/// Changes pushed to a target since this node started
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TargetStats {
+    /// Applied by the remote, or already held by it
+    pub replicated: u64,
+    /// Refused by the remote, whose copy was newer
+    pub conflicts: u64,
+    /// Refused by the remote for another reason, and not retried
+    pub rejected: u64,
+    pub last_error: Option<String>,
+    pub last_push_at: Option<u64>,
+}
+
+/// Replication state of one target, as served at `/admin/replication/status`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TargetStatus {
+    pub name: String,
+    pub url: String,
+    pub tenants: Vec<String>,
+    pub prefixes: Vec<String>,
+    /// Added through `/admin/replication/targets` rather than configured
+    pub runtime: bool,
+    /// Last sequence number pushed
+    pub cursor: u64,
+    /// Raft log entries after the cursor (an upper bound on pending changes)
+    pub pending_entries: u64,
+    /// Age of the oldest change not pushed yet, 0 when caught up
+    pub lag_secs: u64,
+    #[serde(flatten)]
+    pub stats: TargetStats,
+}
+
+/// Replication overview served at `/admin/replication/status`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ReplicationStatus {
+    pub targets: Vec<TargetStatus>,
+}
+
+impl ReplicationManager {
+    /// Apply the replication configuration
+    pub fn configure(&self, config: &ReplicationConfig) {
+        *self.targets.write().unwrap() = config.targets.clone();
+    }
+
+    /// The configured targets, then those added at runtime
+    pub fn targets(&self, metadata: &MetadataStore) -> Result<Vec<ReplicationTarget>> {
+        let mut targets = self.targets.read().unwrap().clone();
+        targets.extend(runtime_targets(metadata)?);
+        Ok(targets)
+    }
+
+    fn is_configured(&self, name: &str) -> bool {
+        self.targets.read().unwrap().iter().any(|t| t.name == name)
+    }
+
+    fn record(&self, target: &str, update: impl FnOnce(&mut TargetStats)) {
+        update(
+            self.stats
+                .lock()
+                .unwrap()
+                .entry(target.to_string())
+                .or_default(),
+        );
+    }
+
+    /// Cursor, lag and counters of every target
+    pub fn status(&self, metadata: &MetadataStore) -> Result<ReplicationStatus> {
+        let head = metadata.last_cdc_seq()?;
+        let mut status = ReplicationStatus::default();
+        for target in self.targets(metadata)? {
+            let runtime = !self.is_configured(&target.name);
+            let cursor = target_cursor(metadata, &target.name)?;
+            let lag_secs = match metadata.cdc_events(cursor + 1, 1)?.first() {
+                Some(oldest) => timestamp_now().saturating_sub(oldest.timestamp),
+                None => 0,
+            };
+            let stats = self
+                .stats
+                .lock()
+                .unwrap()
+                .get(&target.name)
+                .cloned()
+                .unwrap_or_default();
+            status.targets.push(TargetStatus {
+                runtime,
+                pending_entries: head.saturating_sub(cursor),
+                cursor,
+                lag_secs,
+                stats,
+                name: target.name,
+                url: target.url,
+                tenants: target.tenants,
+                prefixes: target.prefixes,
+            });
+        }
+        Ok(status)
+    }
+
+    /// Lag and counters of every target in Prometheus format
+    pub fn to_prometheus(&self, metadata: &MetadataStore) -> String {
+        use std::fmt::Write;
+
+        let status = match self.status(metadata) {
+            Ok(status) if !status.targets.is_empty() => status,
+            _ => return String::new(),
+        };
+        let mut out = String::new();
+        out.push_str(
+            "# HELP minikv_replication_pending_entries Raft log entries not yet pushed to a target\n",
+        );
+        out.push_str("# TYPE minikv_replication_pending_entries gauge\n");
+        for t in &status.targets {
+            writeln!(
+                out,
+                "minikv_replication_pending_entries{{target=\"{}\"}} {}",
+                t.name, t.pending_entries
+            )
+            .unwrap();
+        }
+        out.push_str(
+            "# HELP minikv_replication_lag_seconds Age of the oldest change not yet pushed to a target\n",
+        );
+        out.push_str("# TYPE minikv_replication_lag_seconds gauge\n");
+        for t in &status.targets {
+            writeln!(
+                out,
+                "minikv_replication_lag_seconds{{target=\"{}\"}} {}",
+                t.name, t.lag_secs
+            )
+            .unwrap();
+        }
+        out.push_str(
+            "# HELP minikv_replication_changes_total Changes pushed to a target, by outcome\n",
+        );
+        out.push_str("# TYPE minikv_replication_changes_total counter\n");
+        for t in &status.targets {
+            for (outcome, count) in [
+                ("replicated", t.stats.replicated),
+                ("conflict", t.stats.conflicts),
+                ("rejected", t.stats.rejected),
+            ] {
+                writeln!(
+                    out,
+                    "minikv_replication_changes_total{{target=\"{}\",outcome=\"{}\"}} {}",
+                    t.name, outcome, count
+                )
+                .unwrap();
+            }
+        }
+        out
+    }
+}
+
+impl ReplicationTarget {
+    /// Whether changes of the stored key `key` go to this target
+    pub fn matches(&self, key: &str) -> bool {
+        if self.tenants.is_empty() && self.prefixes.is_empty() {
+            return true;
+        }
+        let in_tenant = key
+            .split_once('/')
+            .is_some_and(|(tenant, _)| self.tenants.iter().any(|t| t == tenant));
+        in_tenant || self.prefixes.iter().any(|p| key.starts_with(p.as_str()))
+    }
+}
+
+/// What a cluster does with a replicated change
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolution {
+    /// The change is newer than the local copy
+    Apply,
+    /// The local copy already matches it
+    Unchanged,
+    /// The local copy is newer and wins
+    Conflict,
+}
+
+/// Last-writer-wins between the local copy of a key and a replicated change
+/// made at `updated_at`: a put of a value hashing to `blake3` with `tags`,
+/// or a delete (`None`)
+pub fn resolve(
+    local: Option<&KeyMetadata>,
+    updated_at: u64,
+    put: Option<(&str, &BTreeMap<String, String>)>,
+) -> Resolution {
+    let Some(local) = local.filter(|meta| meta.state != KeyState::Tombstone) else {
+        return match put {
+            Some(_) => Resolution::Apply,
+            None => Resolution::Unchanged,
+        };
+    };
+    if let Some((blake3, tags)) = put {
+        if local.blake3 == blake3 && &local.tags == tags {
+            return Resolution::Unchanged;
+        }
+    }
+    match local.updated_at.cmp(&updated_at) {
+        Ordering::Less => Resolution::Apply,
+        Ordering::Greater => Resolution::Conflict,
+        // Both clusters must pick the same winner
+        Ordering::Equal => match put {
+            Some((blake3, _)) if blake3 <= local.blake3.as_str() => Resolution::Conflict,
+            _ => Resolution::Apply,
+        },
+    }
+}
+
+/// Give the key written for a replicated change the source's `updated_at`
+pub async fn stamp(
+    metadata: &MetadataStore,
+    raft: &RaftNode,
+    key: &str,
+    blake3: &str,
+    updated_at: u64,
+) -> Result<()> {
+    match metadata.get_key(key)? {
+        Some(mut meta) if meta.blake3 == blake3 && meta.updated_at != updated_at => {
+            meta.updated_at = updated_at;
+            raft.propose(&MetadataCommand::PutKey(meta)).await
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Last sequence number pushed to the target `name`
+pub fn target_cursor(metadata: &MetadataStore, name: &str) -> Result<u64> {
+    let key = format!("{}{}", CURSOR_PREFIX, name);
+    Ok(metadata
+        .get_config(&key)?
+        .and_then(|bytes| String::from_utf8(bytes).ok())
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(0))
+}
+
+/// Targets added at runtime
+pub fn runtime_targets(metadata: &MetadataStore) -> Result<Vec<ReplicationTarget>> {
+    match metadata.get_config(TARGETS_KEY)? {
+        Some(bytes) => serde_json::from_slice(&bytes)
+            .map_err(|e| crate::Error::MetadataCorrupted(e.to_string())),
+        None => Ok(Vec::new()),
+    }
+}
+
+async fn save_runtime_targets(raft: &RaftNode, targets: &[ReplicationTarget]) -> Result<()> {
+    let value = serde_json::to_vec(targets)
+        .map_err(|e| crate::Error::Internal(format!("Serialize error: {}", e)))?;
+    raft.propose(&MetadataCommand::PutConfig {
+        key: TARGETS_KEY.to_string(),
+        value,
+    })
+    .await
+}
+
+/// Add a target at runtime, replicating the changes made from now on.
+/// Adding a target again with the same definition does nothing.
+pub async fn add_target(
+    metadata: &MetadataStore,
+    raft: &RaftNode,
+    target: ReplicationTarget,
+) -> Result<()> {
+    raft.ensure_leader()?;
+    if target.name.is_empty() || target.url.is_empty() {
+        return Err(crate::Error::InvalidRequest(
+            "a replication target needs a name and a url".to_string(),
+        ));
+    }
+    let mut targets = runtime_targets(metadata)?;
+    match targets.iter().find(|t| t.name == target.name) {
+        Some(existing) if *existing == target => return Ok(()),
+        None if !REPLICATION.is_configured(&target.name) => {}
+        _ => {
+            return Err(crate::Error::InvalidRequest(format!(
+                "replication target {} already exists",
+                target.name
+            )))
+        }
+    }
+    // Earlier changes are the copy's job, not replication's
+    raft.propose(&MetadataCommand::PutConfig {
+        key: format!("{}{}", CURSOR_PREFIX, target.name),
+        value: metadata.last_cdc_seq()?.to_string().into_bytes(),
+    })
+    .await?;
+    targets.push(target);
+    save_runtime_targets(raft, &targets).await
+}
+
+/// Remove a target added at runtime. Returns whether it existed.
+pub async fn remove_target(metadata: &MetadataStore, raft: &RaftNode, name: &str) -> Result<bool> {
+    raft.ensure_leader()?;
+    if REPLICATION.is_configured(name) {
+        return Err(crate::Error::InvalidRequest(format!(
+            "replication target {} is configured, not added at runtime",
+            name
+        )));
+    }
+    let mut targets = runtime_targets(metadata)?;
+    let count = targets.len();
+    targets.retain(|t| t.name != name);
+    if targets.len() == count {
+        return Ok(false);
+    }
+    save_runtime_targets(raft, &targets).await?;
+    REPLICATION.stats.lock().unwrap().remove(name);
+    Ok(true)
+}
+
+/// The last event of each key in `events` that `target` replicates, in log order
+fn latest_changes<'a>(events: &'a [CdcEvent], target: &ReplicationTarget) -> Vec<&'a CdcEvent> {
+    let mut latest = HashMap::new();
+    for (i, event) in events.iter().enumerate() {
+        if target.matches(&event.key) {
+            latest.insert(event.key.as_str(), i);
+        }
+    }
+    let mut indices: Vec<usize> = latest.into_values().collect();
+    indices.sort_unstable();
+    indices.into_iter().map(|i| &events[i]).collect()
+}
+
+/// Outcome of pushing one change
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Pushed {
+    Replicated,
+    Conflict,
+    /// The key changed again since; a later event pushes it
+    Superseded,
+    Rejected(String),
+}
+
+/// Current value of `meta`, wherever it is stored
+async fn read_value(metadata: &MetadataStore, meta: &KeyMetadata) -> Result<Vec<u8>> {
+    if meta.state == KeyState::Tiered {
+        return tiering::fetch_tiered(meta).await.map(|(_, data)| data);
+    }
+    let content = dedup::resolve(metadata, meta.clone())?;
+    chunking::read_value(metadata, &content, ConsistencyLevel::One, None, false).await
+}
+
+/// Send `request`, following the redirects of remote followers to their
+/// leader (the client doesn't follow them itself, as it would drop the API key)
+async fn send(client: &reqwest::Client, request: reqwest::Request) -> Result<reqwest::Response> {
+    let mut request = request;
+    let mut redirects = 0;
+    loop {
+        let retry = request.try_clone();
+        let resp = client
+            .execute(request)
+            .await
+            .map_err(|e| crate::Error::Http(e.to_string()))?;
+        let location = resp
+            .headers()
+            .get(reqwest::header::LOCATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| reqwest::Url::parse(v).ok());
+        match (resp.status(), location, retry) {
+            (reqwest::StatusCode::TEMPORARY_REDIRECT, Some(location), Some(mut retry))
+                if redirects < MAX_REDIRECTS =>
+            {
+                *retry.url_mut() = location;
+                request = retry;
+                redirects += 1;
+            }
+            _ => return Ok(resp),
+        }
+    }
+}
+
+/// Push the current state of the key changed by `event` to `target`. Errors
+/// are worth retrying; changes the remote will never accept are `Rejected`.
+async fn push_change(
+    metadata: &MetadataStore,
+    client: &reqwest::Client,
+    target: &ReplicationTarget,
+    event: &CdcEvent,
+) -> Result<Pushed> {
+    let Some((tenant, key)) = event.key.split_once('/') else {
+        return Ok(Pushed::Rejected(format!("{} has no tenant", event.key)));
+    };
+    // The remote must not redirect the upload to one of its volumes
+    let url = format!(
+        "{}/{}?redirect=false",
+        target.url.trim_end_matches('/'),
+        encode_key(key)
+    );
+    let current = metadata
+        .get_key(&event.key)?
+        .filter(|meta| meta.state != KeyState::Tombstone);
+    let request = match (event.op, current) {
+        (CdcOp::Put, Some(meta)) if event.blake3.as_ref() == Some(&meta.blake3) => {
+            let value = read_value(metadata, &meta).await?;
+            let mut request = client
+                .post(&url)
+                .header(REPLICATED_AT_HEADER, meta.updated_at)
+                .body(value);
+            for (name, value) in &meta.tags {
+                request = request.header("X-Tag", format!("{}={}", name, value));
+            }
+            request
+        }
+        (CdcOp::Delete, None) => client
+            .delete(&url)
+            .header(REPLICATED_AT_HEADER, event.timestamp),
+        _ => return Ok(Pushed::Superseded),
+    };
+    let mut request = request.header(tenant::TENANT_HEADER, tenant);
+    if let Some(api_key) = &target.api_key {
+        request = request.header("Authorization", format!("ApiKey {}", api_key));
+    }
+    let request = request
+        .build()
+        .map_err(|e| crate::Error::Http(e.to_string()))?;
+
+    let resp = send(client, request).await?;
+    let status = resp.status();
+    if status.is_success()
+        || (status == reqwest::StatusCode::NOT_FOUND && event.op == CdcOp::Delete)
+    {
+        return Ok(Pushed::Replicated);
+    }
+    if status == reqwest::StatusCode::CONFLICT {
+        return Ok(Pushed::Conflict);
+    }
+    let body = resp.text().await.unwrap_or_default();
+    let permanent = status.is_client_error()
+        && status != reqwest::StatusCode::REQUEST_TIMEOUT
+        && status != reqwest::StatusCode::TOO_MANY_REQUESTS;
+    if permanent {
+        Ok(Pushed::Rejected(format!(
+            "{}: {} {}",
+            event.key, status, body
+        )))
+    } else {
+        Err(crate::Error::Http(format!(
+            "{}: {} {}",
+            event.key, status, body
+        )))
+    }
+}
+
+/// Push the changes `target` hasn't received yet, one batch at a time,
+/// committing its cursor after each. Returns how many changes were pushed.
+/// A batch that fails is pushed again from its start.
+pub async fn replicate_pending(
+    metadata: &MetadataStore,
+    raft: &RaftNode,
+    client: &reqwest::Client,
+    target: &ReplicationTarget,
+) -> Result<usize> {
+    let mut pushed = 0;
+    loop {
+        let cursor = target_cursor(metadata, &target.name)?;
+        let events = metadata.cdc_events(cursor + 1, BATCH_SIZE)?;
+        let Some(last) = events.last().map(|e| e.seq) else {
+            return Ok(pushed);
+        };
+        let changes = latest_changes(&events, target);
+        let results: Vec<Result<Pushed>> = futures_util::stream::iter(&changes)
+            .map(|event| push_change(metadata, client, target, event))
+            .buffer_unordered(target.parallelism.max(1))
+            .collect()
+            .await;
+        let outcomes = results.into_iter().collect::<Result<Vec<_>>>()?;
+
+        REPLICATION.record(&target.name, |stats| {
+            for outcome in outcomes {
+                match outcome {
+                    Pushed::Replicated => stats.replicated += 1,
+                    Pushed::Conflict => stats.conflicts += 1,
+                    Pushed::Superseded => {}
+                    Pushed::Rejected(error) => {
+                        tracing::warn!("Replication to {} rejected {}", target.name, error);
+                        stats.rejected += 1;
+                        stats.last_error = Some(error);
+                    }
+                }
+            }
+            stats.last_push_at = Some(timestamp_now());
+        });
+        raft.propose(&MetadataCommand::PutConfig {
+            key: format!("{}{}", CURSOR_PREFIX, target.name),
+            value: last.to_string().into_bytes(),
+        })
+        .await?;
+        pushed += changes.len();
+        if events.len() < BATCH_SIZE {
+            return Ok(pushed);
+        }
+    }
+}
+
+/// Background task: on the leader, pushes each target's pending changes
+pub fn start_replication_task(
+    metadata: Arc<MetadataStore>,
+    raft: Arc<RaftNode>,
+    config: ReplicationConfig,
+) -> tokio::task::JoinHandle<()> {
+    REPLICATION.configure(&config);
+    tokio::spawn(async move {
+        let client = match reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .connect_timeout(Duration::from_secs(10))
+            .build()
+        {
+            Ok(client) => client,
+            Err(e) => {
+                tracing::error!("Replication disabled: {}", e);
+                return;
+            }
+        };
+        loop {
+            tokio::time::sleep(POLL_INTERVAL).await;
+            if !raft.is_leader() {
+                continue;
+            }
+            let targets = match REPLICATION.targets(&metadata) {
+                Ok(targets) => targets,
+                Err(e) => {
+                    tracing::warn!("Reading the replication targets failed: {}", e);
+                    continue;
+                }
+            };
+            for target in &targets {
+                if let Err(e) = replicate_pending(&metadata, &raft, &client, target).await {
+                    tracing::warn!("Replication to {} failed: {}", target.name, e);
+                    REPLICATION
+                        .record(&target.name, |stats| stats.last_error = Some(e.to_string()));
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target(tenants: &[&str], prefixes: &[&str]) -> ReplicationTarget {
+        ReplicationTarget {
+            name: "eu".to_string(),
+            url: "http://eu:5000".to_string(),
+            api_key: None,
+            tenants: tenants.iter().map(|t| t.to_string()).collect(),
+            prefixes: prefixes.iter().map(|p| p.to_string()).collect(),
+            parallelism: 1,
+        }
+    }
+
+    fn local(blake3: &str, updated_at: u64) -> KeyMetadata {
+        KeyMetadata {
+            key: "acme/a".to_string(),
+            replicas: vec!["vol-1".to_string()],
+            size: 1,
+            blake3: blake3.to_string(),
+            created_at: 0,
+            updated_at,
+            state: KeyState::Active,
+            tags: BTreeMap::new(),
+            blob: None,
+            chunks: None,
+        }
+    }
+
+    fn event(seq: u64, op: CdcOp, key: &str) -> CdcEvent {
+        CdcEvent {
+            seq,
+            op,
+            key: key.to_string(),
+            size: None,
+            blake3: None,
+            timestamp: 0,
+        }
+    }
+
+    #[test]
+    fn test_target_matches() {
+        assert!(target(&[], &[]).matches("acme/a"));
+        let scoped = target(&["acme"], &["globex/photos/"]);
+        assert!(scoped.matches("acme/a/b"));
+        assert!(!scoped.matches("acmeco/a"));
+        assert!(scoped.matches("globex/photos/cat.jpg"));
+        assert!(!scoped.matches("globex/docs/a"));
+    }
+
+    #[test]
+    fn test_resolve_last_writer_wins() {
+        let tags = BTreeMap::new();
+        let stored = local("bbb", 10);
+        let put = |blake3, at| resolve(Some(&stored), at, Some((blake3, &tags)));
+
+        assert_eq!(put("ccc", 11), Resolution::Apply);
+        assert_eq!(put("ccc", 9), Resolution::Conflict);
+        assert_eq!(put("bbb", 9), Resolution::Unchanged);
+        // Ties go to the larger hash, on both clusters
+        assert_eq!(put("ccc", 10), Resolution::Apply);
+        assert_eq!(put("aaa", 10), Resolution::Conflict);
+
+        assert_eq!(resolve(None, 1, Some(("ccc", &tags))), Resolution::Apply);
+        assert_eq!(resolve(Some(&stored), 11, None), Resolution::Apply);
+        assert_eq!(resolve(Some(&stored), 9, None), Resolution::Conflict);
+        assert_eq!(resolve(None, 9, None), Resolution::Unchanged);
+    }
+
+    #[test]
+    fn test_latest_changes() {
+        let events = vec![
+            event(1, CdcOp::Put, "acme/a"),
+            event(2, CdcOp::Put, "other/b"),
+            event(3, CdcOp::Put, "acme/c"),
+            event(5, CdcOp::Delete, "acme/a"),
+        ];
+        let seqs: Vec<u64> = latest_changes(&events, &target(&["acme"], &[]))
+            .iter()
+            .map(|e| e.seq)
+            .collect();
+        assert_eq!(seqs, vec![3, 5]);
+    }
+}
@@ -0,0 +1,643 @@
+//! Redis protocol (RESP2) compatibility layer
+//!
+//! Served on `resp_addr`, so Redis clients and tools can use a cluster for
+//! simple cases. A subset of the commands is mapped onto the HTTP API, each
+//! dispatched to the coordinator's router in process (`loopback`), with
+//! the same authentication, ACLs, quotas and rate limits:
+//!
+//! | Command | Maps to |
+//! |---|---|
+//! | `GET key` | `GET /key` |
+//! | `SET key value [NX\|XX]` | `POST /key` |
+//! | `DEL key [key...]` | `DELETE /key` for each |
+//! | `EXISTS key [key...]`, `TTL key` | `GET /keys?prefix=key` |
+//! | `INCR key` | `GET /key`, then `POST /key` |
+//! | `SCAN cursor [MATCH pattern] [COUNT n]` | `GET /keys?after=...` |
+//!
+//! along with `PING`, `ECHO`, `QUIT` and `AUTH [username] password`: the
+//! password is sent as an API key and a username other than `default` as
+//! the tenant. Keys never expire, so `TTL` answers -1 for existing keys and
+//! `SET` refuses expiration options. `INCR` and `SET NX|XX` read then write:
+//! they are atomic between the RESP clients of one coordinator only.
+//! Followers answer writes and reads with a `NOTLEADER` error naming the
+//! leader.
+
+use crate::common::encode_key;
+use crate::coordinator::chunking;
+use crate::coordinator::loopback::{self, Answer, Caller};
+use crate::coordinator::metadata::KeyState;
+use crate::coordinator::tenant::TENANT_HEADER;
+use axum::body::{Body, Bytes};
+use axum::http::{HeaderMap, HeaderValue, Method, StatusCode};
+use std::collections::BTreeMap;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+
+/// Longest line (inline command or RESP header)
+const MAX_LINE_BYTES: u64 = 64 * 1024;
+
+/// Bytes a command may carry beyond its largest argument (key, options and
+/// RESP headers)
+const MAX_COMMAND_OVERHEAD: usize = 1024 * 1024;
+
+/// Most arguments of a command
+const MAX_ARGS: usize = 1024 * 1024;
+
+/// `SCAN` cursors kept; older ones are forgotten
+const MAX_CURSORS: usize = 4096;
+
+/// Keys returned by a `SCAN` without `COUNT`
+const DEFAULT_SCAN_COUNT: usize = 10;
+
+/// A RESP2 reply
+#[derive(Debug, Clone, PartialEq)]
+pub enum Reply {
+    Simple(String),
+    Error(String),
+    Integer(i64),
+    Bulk(Option<Bytes>),
+    Array(Vec<Reply>),
+}
+
+impl Reply {
+    fn ok() -> Self {
+        Reply::Simple("OK".to_string())
+    }
+
+    fn error(message: impl Into<String>) -> Self {
+        Reply::Error(message.into())
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Reply::Simple(s) => {
+                out.push(b'+');
+                out.extend_from_slice(s.as_bytes());
+            }
+            Reply::Error(e) => {
+                // One line only
+                out.push(b'-');
+                out.extend(
+                    e.bytes()
+                        .map(|b| if b == b'\r' || b == b'\n' { b' ' } else { b }),
+                );
+            }
+            Reply::Integer(n) => out.extend_from_slice(format!(":{}", n).as_bytes()),
+            Reply::Bulk(None) => out.extend_from_slice(b"$-1"),
+            Reply::Bulk(Some(data)) => {
+                out.extend_from_slice(format!("${}\r\n", data.len()).as_bytes());
+                out.extend_from_slice(data);
+            }
+            Reply::Array(items) => {
+                out.extend_from_slice(format!("*{}\r\n", items.len()).as_bytes());
+                for item in items {
+                    item.encode(out);
+                }
+                return;
+            }
+        }
+        out.extend_from_slice(b"\r\n");
+    }
+}
+
+/// Read one command, as a RESP array of bulk strings or an inline line.
+/// `None` at the end of the stream.
+async fn read_command<R: AsyncRead + Unpin>(
+    reader: &mut BufReader<R>,
+) -> io::Result<Option<Vec<Bytes>>> {
+    // An argument is at most a value, as for PUT
+    let max_arg = usize::try_from(chunking::config().max_object_bytes).unwrap_or(usize::MAX);
+    let max_total = max_arg.saturating_add(MAX_COMMAND_OVERHEAD);
+    loop {
+        let line = match read_line(reader).await? {
+            Some(line) => line,
+            None => return Ok(None),
+        };
+        if let Some(count) = line.strip_prefix(b"*") {
+            let count = parse_length(count, MAX_ARGS)?;
+            let mut args = Vec::with_capacity(count.min(64));
+            let mut total = line.len() + 2;
+            for _ in 0..count {
+                let header = read_line(reader)
+                    .await?
+                    .ok_or_else(|| protocol_error("unexpected end of stream"))?;
+                let len = match header.strip_prefix(b"$") {
+                    Some(len) => parse_length(len, max_arg)?,
+                    None => return Err(protocol_error("expected '$'")),
+                };
+                total += header.len() + len + 4;
+                if total > max_total {
+                    return Err(protocol_error("command too large"));
+                }
+                // Grows as the data arrives, not up front from the header
+                let mut data = Vec::new();
+                (&mut *reader)
+                    .take(len as u64 + 2)
+                    .read_to_end(&mut data)
+                    .await?;
+                if data.len() < len + 2 {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+                if !data.ends_with(b"\r\n") {
+                    return Err(protocol_error("bulk string not ended by CRLF"));
+                }
+                data.truncate(len);
+                args.push(Bytes::from(data));
+            }
+            return Ok(Some(args));
+        }
+        let args: Vec<Bytes> = line
+            .split(|b| b.is_ascii_whitespace())
+            .filter(|arg| !arg.is_empty())
+            .map(Bytes::copy_from_slice)
+            .collect();
+        // Blank lines are skipped, as by Redis
+        if !args.is_empty() {
+            return Ok(Some(args));
+        }
+    }
+}
+
+/// A line without its CRLF
+async fn read_line<R: AsyncRead + Unpin>(reader: &mut BufReader<R>) -> io::Result<Option<Vec<u8>>> {
+    let mut line = Vec::new();
+    (&mut *reader)
+        .take(MAX_LINE_BYTES)
+        .read_until(b'\n', &mut line)
+        .await?;
+    if line.is_empty() {
+        return Ok(None);
+    }
+    if line.pop() != Some(b'\n') {
+        return Err(protocol_error("line too long or not ended"));
+    }
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    Ok(Some(line))
+}
+
+fn parse_length(digits: &[u8], max: usize) -> io::Result<usize> {
+    std::str::from_utf8(digits)
+        .ok()
+        .and_then(|digits| digits.parse::<usize>().ok())
+        .filter(|len| *len <= max)
+        .ok_or_else(|| protocol_error("invalid length"))
+}
+
+fn protocol_error(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Whether `key` matches the glob `pattern` (`*`, `?` and `\` escapes).
+/// Iterative: a mismatch only goes back to the last `*`.
+fn glob_match(pattern: &[u8], key: &[u8]) -> bool {
+    let (mut p, mut k) = (0, 0);
+    // Just past the last `*` seen, and the key position it is matched up to
+    let mut star: Option<(usize, usize)> = None;
+    while k < key.len() {
+        let width = match pattern.get(p) {
+            Some(b'*') => {
+                p += 1;
+                star = Some((p, k));
+                continue;
+            }
+            Some(b'?') => Some(1),
+            Some(b'\\') if p + 1 < pattern.len() => (pattern[p + 1] == key[k]).then_some(2),
+            Some(c) => (*c == key[k]).then_some(1),
+            None => None,
+        };
+        if let Some(width) = width {
+            p += width;
+            k += 1;
+        } else if let Some((after_star, matched)) = star {
+            // Let the `*` take one more byte and retry from there
+            p = after_star;
+            k = matched + 1;
+            star = Some((after_star, k));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == b'*')
+}
+
+/// Literal start of a glob pattern, every key it matches begins with
+fn glob_prefix(pattern: &str) -> &str {
+    let end = pattern.find(['*', '?', '\\']).unwrap_or(pattern.len());
+    &pattern[..end]
+}
+
+/// Keys `SCAN` resumes after, by cursor
+#[derive(Default)]
+struct Cursors {
+    next: u64,
+    after: BTreeMap<u64, String>,
+}
+
+impl Cursors {
+    fn insert(&mut self, after: String) -> u64 {
+        self.next += 1;
+        self.after.insert(self.next, after);
+        while self.after.len() > MAX_CURSORS {
+            self.after.pop_first();
+        }
+        self.next
+    }
+}
+
+/// The error reply for a failed request
+fn error_reply(answer: &Answer) -> Reply {
+    if let Some(leader) = answer.leader() {
+        return Reply::error(format!("NOTLEADER leader is at {}", leader));
+    }
+    match answer.status {
+        StatusCode::UNAUTHORIZED => Reply::error("NOAUTH Authentication required."),
+        StatusCode::FORBIDDEN => Reply::error(format!("NOPERM {}", answer.message())),
+        _ => Reply::error(format!("ERR {}", answer.message())),
+    }
+}
+
+/// Redis protocol server of a coordinator
+#[derive(Clone)]
+pub struct RespServer {
+    router: axum::Router,
+    cursors: Arc<Mutex<Cursors>>,
+    /// Held by the commands reading before they write
+    updates: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl RespServer {
+    /// Serve commands through `router`, the coordinator's HTTP router
+    pub fn new(router: axum::Router) -> Self {
+        Self {
+            router,
+            cursors: Arc::new(Mutex::new(Cursors::default())),
+            updates: Arc::new(tokio::sync::Mutex::new(())),
+        }
+    }
+
+    /// Accept connections on `listener` until it fails
+    pub async fn serve(self, listener: TcpListener) -> io::Result<()> {
+        loop {
+            let (stream, peer) = listener.accept().await?;
+            let server = self.clone();
+            tokio::spawn(async move {
+                if let Err(e) = server.serve_connection(stream, Some(peer)).await {
+                    tracing::debug!("RESP connection from {} closed: {}", peer, e);
+                }
+            });
+        }
+    }
+
+    /// Answer the commands of one client, from `peer` if known
+    pub async fn serve_connection<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        stream: S,
+        peer: Option<SocketAddr>,
+    ) -> io::Result<()> {
+        let (reader, mut writer) = tokio::io::split(stream);
+        let mut reader = BufReader::new(reader);
+        let mut caller = Caller {
+            peer,
+            ..Default::default()
+        };
+        let mut out = Vec::new();
+        loop {
+            let args = match read_command(&mut reader).await {
+                Ok(Some(args)) => args,
+                Ok(None) => return Ok(()),
+                Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                    // The stream can't be followed any further
+                    out.clear();
+                    Reply::error(format!("ERR Protocol error: {}", e)).encode(&mut out);
+                    writer.write_all(&out).await?;
+                    return Err(e);
+                }
+                Err(e) => return Err(e),
+            };
+            let quit = args[0].eq_ignore_ascii_case(b"QUIT");
+            let reply = if quit {
+                Reply::ok()
+            } else {
+                self.execute(&mut caller, &args).await
+            };
+            out.clear();
+            reply.encode(&mut out);
+            writer.write_all(&out).await?;
+            if quit {
+                return Ok(());
+            }
+        }
+    }
+
+    async fn execute(&self, caller: &mut Caller, args: &[Bytes]) -> Reply {
+        let name = String::from_utf8_lossy(&args[0]).to_ascii_uppercase();
+        let arity_error = || {
+            Reply::error(format!(
+                "ERR wrong number of arguments for '{}' command",
+                name.to_ascii_lowercase()
+            ))
+        };
+        let keys: Vec<String> = args[1..]
+            .iter()
+            .map(|arg| String::from_utf8_lossy(arg).to_string())
+            .collect();
+        match (name.as_str(), args.len()) {
+            ("PING", 1) => Reply::Simple("PONG".to_string()),
+            ("PING", 2) | ("ECHO", 2) => Reply::Bulk(Some(args[1].clone())),
+            ("AUTH", 2) | ("AUTH", 3) => self.auth(caller, &keys),
+            ("GET", 2) => self.get(caller, &keys[0]).await,
+            ("SET", n) if n >= 3 => self.set(caller, &keys[0], &args[2], &keys[2..]).await,
+            ("DEL", n) if n >= 2 => self.del(caller, &keys).await,
+            ("EXISTS", n) if n >= 2 => self.exists(caller, &keys).await,
+            ("TTL", 2) => match self.key_exists(caller, &keys[0]).await {
+                Ok(true) => Reply::Integer(-1),
+                Ok(false) => Reply::Integer(-2),
+                Err(reply) => reply,
+            },
+            ("INCR", 2) => self.incr(caller, &keys[0]).await,
+            ("SCAN", n) if n >= 2 => self.scan(caller, &keys).await,
+            (
+                "PING" | "ECHO" | "AUTH" | "GET" | "SET" | "DEL" | "EXISTS" | "TTL" | "INCR"
+                | "SCAN",
+                _,
+            ) => arity_error(),
+            _ => Reply::error(format!(
+                "ERR unknown command '{}'",
+                String::from_utf8_lossy(&args[0])
+            )),
+        }
+    }
+
+    async fn http(&self, caller: &Caller, method: Method, uri: String, body: Body) -> Answer {
+        loopback::call(&self.router, caller, method, uri, body).await
+    }
+
+    fn auth(&self, caller: &mut Caller, args: &[String]) -> Reply {
+        let (user, password) = match args {
+            [password] => (None, password),
+            [user, password] => (Some(user.as_str()), password),
+            _ => unreachable!("AUTH takes one or two arguments"),
+        };
+        let Ok(key) = HeaderValue::from_str(password) else {
+            return Reply::error("WRONGPASS invalid password");
+        };
+        let mut headers = HeaderMap::new();
+        headers.insert("x-api-key", key);
+        if let Some(tenant) = user.filter(|user| *user != "default") {
+            match HeaderValue::from_str(tenant) {
+                Ok(tenant) => {
+                    headers.insert(TENANT_HEADER, tenant);
+                }
+                Err(_) => return Reply::error("WRONGPASS invalid username"),
+            }
+        }
+        // Checked by the requests that follow
+        caller.headers = headers;
+        Reply::ok()
+    }
+
+    /// The value of `key`, `None` if it has none
+    async fn read(&self, caller: &Caller, key: &str) -> Result<Option<Bytes>, Reply> {
+        let uri = format!("/{}?redirect=false", encode_key(key));
+        let answer = self.http(caller, Method::GET, uri, Body::empty()).await;
+        match answer.status {
+            status if status.is_success() => Ok(Some(answer.body)),
+            StatusCode::NOT_FOUND => Ok(None),
+            _ => Err(error_reply(&answer)),
+        }
+    }
+
+    async fn write(&self, caller: &Caller, key: &str, value: Bytes) -> Result<(), Reply> {
+        let uri = format!("/{}?redirect=false", encode_key(key));
+        let answer = self
+            .http(caller, Method::POST, uri, Body::from(value))
+            .await;
+        if answer.status.is_success() {
+            Ok(())
+        } else {
+            Err(error_reply(&answer))
+        }
+    }
+
+    async fn key_exists(&self, caller: &Caller, key: &str) -> Result<bool, Reply> {
+        match loopback::key_metadata(&self.router, caller, key).await {
+            Ok(meta) => Ok(meta.is_some()),
+            Err(answer) => Err(error_reply(&answer)),
+        }
+    }
+
+    async fn get(&self, caller: &Caller, key: &str) -> Reply {
+        match self.read(caller, key).await {
+            Ok(value) => Reply::Bulk(value),
+            Err(reply) => reply,
+        }
+    }
+
+    async fn set(&self, caller: &Caller, key: &str, value: &Bytes, options: &[String]) -> Reply {
+        let mut condition = None;
+        for option in options {
+            match option.to_ascii_uppercase().as_str() {
+                "NX" | "XX" if condition.is_none() => condition = Some(option.to_ascii_uppercase()),
+                "EX" | "PX" | "EXAT" | "PXAT" | "KEEPTTL" => {
+                    return Reply::error("ERR keys can't expire, expiration is not supported")
+                }
+                _ => return Reply::error("ERR syntax error"),
+            }
+        }
+        let Some(condition) = condition else {
+            return match self.write(caller, key, value.clone()).await {
+                Ok(()) => Reply::ok(),
+                Err(reply) => reply,
+            };
+        };
+
+        let _updating = self.updates.lock().await;
+        match self.key_exists(caller, key).await {
+            Ok(exists) if exists == (condition == "XX") => {}
+            Ok(_) => return Reply::Bulk(None),
+            Err(reply) => return reply,
+        }
+        match self.write(caller, key, value.clone()).await {
+            Ok(()) => Reply::ok(),
+            Err(reply) => reply,
+        }
+    }
+
+    async fn del(&self, caller: &Caller, keys: &[String]) -> Reply {
+        let mut deleted = 0;
+        for key in keys {
+            let uri = format!("/{}", encode_key(key));
+            let answer = self.http(caller, Method::DELETE, uri, Body::empty()).await;
+            match answer.status {
+                status if status.is_success() => deleted += 1,
+                StatusCode::NOT_FOUND => {}
+                _ => return error_reply(&answer),
+            }
+        }
+        Reply::Integer(deleted)
+    }
+
+    async fn exists(&self, caller: &Caller, keys: &[String]) -> Reply {
+        let mut found = 0;
+        for key in keys {
+            match self.key_exists(caller, key).await {
+                Ok(true) => found += 1,
+                Ok(false) => {}
+                Err(reply) => return reply,
+            }
+        }
+        Reply::Integer(found)
+    }
+
+    async fn incr(&self, caller: &Caller, key: &str) -> Reply {
+        let _updating = self.updates.lock().await;
+        let current = match self.read(caller, key).await {
+            Ok(Some(value)) => match std::str::from_utf8(&value)
+                .ok()
+                .and_then(|value| value.parse::<i64>().ok())
+            {
+                Some(n) => n,
+                None => return Reply::error("ERR value is not an integer or out of range"),
+            },
+            Ok(None) => 0,
+            Err(reply) => return reply,
+        };
+        let Some(next) = current.checked_add(1) else {
+            return Reply::error("ERR increment or decrement would overflow");
+        };
+        match self.write(caller, key, Bytes::from(next.to_string())).await {
+            Ok(()) => Reply::Integer(next),
+            Err(reply) => reply,
+        }
+    }
+
+    async fn scan(&self, caller: &Caller, args: &[String]) -> Reply {
+        let cursor = match args[0].parse::<u64>() {
+            Ok(cursor) => cursor,
+            Err(_) => return Reply::error("ERR invalid cursor"),
+        };
+        let mut pattern = None;
+        let mut count = DEFAULT_SCAN_COUNT;
+        let mut options = args[1..].iter();
+        while let Some(option) = options.next() {
+            match (option.to_ascii_uppercase().as_str(), options.next()) {
+                ("MATCH", Some(glob)) => pattern = Some(glob.clone()),
+                ("COUNT", Some(n)) => match n.parse::<usize>() {
+                    Ok(n) if n > 0 => count = n,
+                    _ => return Reply::error("ERR value is not an integer or out of range"),
+                },
+                _ => return Reply::error("ERR syntax error"),
+            }
+        }
+
+        let after = if cursor == 0 {
+            None
+        } else {
+            match self.cursors.lock().unwrap().after.get(&cursor) {
+                Some(after) => Some(after.clone()),
+                None => return Reply::error("ERR invalid cursor"),
+            }
+        };
+        let prefix = pattern.as_deref().map(glob_prefix).unwrap_or_default();
+        let page = match loopback::list(&self.router, caller, prefix, after.as_deref(), count).await
+        {
+            Ok(page) => page,
+            Err(answer) => return error_reply(&answer),
+        };
+        let keys = page
+            .keys
+            .into_iter()
+            .filter(|meta| meta.state != KeyState::Tombstone)
+            .filter(|meta| {
+                pattern.as_deref().map_or(true, |glob| {
+                    glob_match(glob.as_bytes(), meta.key.as_bytes())
+                })
+            })
+            .map(|meta| Reply::Bulk(Some(Bytes::from(meta.key))))
+            .collect();
+        let next = match page.next_after {
+            Some(after) => self.cursors.lock().unwrap().insert(after),
+            None => 0,
+        };
+        Reply::Array(vec![
+            Reply::Bulk(Some(Bytes::from(next.to_string()))),
+            Reply::Array(keys),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn parse(input: &[u8]) -> io::Result<Option<Vec<Bytes>>> {
+        read_command(&mut BufReader::new(input)).await
+    }
+
+    #[tokio::test]
+    async fn test_read_command() {
+        let args = parse(b"*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$4\r\na\r\nb\r\n")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(args, vec!["SET", "k", "a\r\nb"]);
+        let args = parse(b"\r\nget  key\r\n").await.unwrap().unwrap();
+        assert_eq!(args, vec!["get", "key"]);
+        assert!(parse(b"").await.unwrap().is_none());
+        assert!(parse(b"*1\r\n$5\r\nab\r\n").await.is_err());
+        assert!(parse(b"*1\r\n+PING\r\n").await.is_err());
+        // Longer than a value may be, refused before reading it
+        let len = chunking::config().max_object_bytes + 1;
+        let header = format!("*1\r\n${}\r\n", len);
+        assert!(parse(header.as_bytes()).await.is_err());
+    }
+
+    #[test]
+    fn test_encode() {
+        let mut out = Vec::new();
+        Reply::Array(vec![
+            Reply::Bulk(Some(Bytes::from("0"))),
+            Reply::Array(vec![Reply::Bulk(None), Reply::Integer(-2)]),
+            Reply::error("ERR bad\r\nline"),
+        ])
+        .encode(&mut out);
+        assert_eq!(
+            out,
+            b"*3\r\n$1\r\n0\r\n*2\r\n$-1\r\n:-2\r\n-ERR bad  line\r\n"
+        );
+    }
+
+    #[test]
+    fn test_glob() {
+        assert!(glob_match(b"user:*", b"user:1"));
+        assert!(glob_match(b"*:?", b"user:1"));
+        assert!(!glob_match(b"user:?", b"user:10"));
+        assert!(glob_match(b"a\\*", b"a*"));
+        assert!(!glob_match(b"a\\*", b"ab"));
+        assert_eq!(glob_prefix("user:*"), "user:");
+        assert_eq!(glob_prefix("plain"), "plain");
+        // `[` is no class, matched as is
+        assert!(glob_match(b"a[b]*", b"a[b]c"));
+        assert!(!glob_match(b"a[b]*", b"ab"));
+        assert_eq!(glob_prefix("a[b]*"), "a[b]");
+        // Linear, however many stars
+        let key = vec![b'a'; 10_000];
+        assert!(!glob_match(b"*a*a*a*a*a*a*a*a*b", &key));
+        assert!(glob_match(b"*a*a*a*a*a*a*a*a*", &key));
+    }
+
+    #[test]
+    fn test_cursors_bounded() {
+        let mut cursors = Cursors::default();
+        for n in 0..MAX_CURSORS + 1 {
+            cursors.insert(n.to_string());
+        }
+        assert_eq!(cursors.after.len(), MAX_CURSORS);
+        assert!(!cursors.after.contains_key(&1));
+    }
+}
@@ -2,13 +2,92 @@
 use axum_server::tls_rustls::{bind_rustls, RustlsConfig};
 use std::future::IntoFuture;
 
-use crate::common::{CoordinatorConfig, Result};
+use crate::common::auth::KEY_STORE;
+use crate::common::lifecycle;
+use crate::common::ratelimit::{start_eviction_task, RateLimiter};
+use crate::common::reload::{start_reload_task, touched, RELOADER};
+use crate::common::shutdown::{run_listener, start_signal_task};
+use crate::common::{
+    check_peer, check_token, configure_grpc_auth, configure_grpc_tls, server_tls_config,
+    AuthConfig, Config, CoordinatorConfig, IpFilter, Result, WriteBudget, AUDIT_LOGGER,
+    QUOTA_MANAGER, SHUTDOWN,
+};
+use crate::coordinator::anti_entropy::start_anti_entropy_task;
+use crate::coordinator::cdc::start_cdc_task;
+use crate::coordinator::etcd::start_lease_expiry_task;
+use crate::coordinator::gc::start_gc_task;
 use crate::coordinator::grpc::CoordGrpcService;
+use crate::coordinator::health::{start_health_monitor, HealthConfig};
 use crate::coordinator::http::{create_router, CoordState};
-use crate::coordinator::metadata::MetadataStore;
+use crate::coordinator::kv_grpc::KvGrpcService;
+use crate::coordinator::memcached::MemcachedServer;
+use crate::coordinator::metadata::{init_global_store, MetadataStore};
+use crate::coordinator::migration::MIGRATIONS;
 use crate::coordinator::placement::PlacementManager;
-use crate::coordinator::raft_node::{start_raft_tasks, RaftNode};
-use std::sync::{Arc, Mutex};
+use crate::coordinator::raft_node::{start_raft_tasks, RaftNode, DEFAULT_TRANSFER_TIMEOUT};
+use crate::coordinator::replication::start_replication_task;
+use crate::coordinator::resp::RespServer;
+use crate::coordinator::tiering::start_tiering_task;
+use crate::coordinator::txn::{recover_transactions, start_recovery_task};
+use crate::coordinator::usage::{set_default_quota, start_usage_task};
+use crate::coordinator::webhooks::start_webhook_task;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use tonic::service::interceptor::InterceptedService;
+
+/// Fields a coordinator applies on a configuration reload (see
+/// `common::reload`)
+const RELOADABLE: &[&str] = &[
+    "log_level",
+    "coordinator.rate_limit.enabled",
+    "coordinator.rate_limit.burst_size",
+    "coordinator.rate_limit.requests_per_second",
+    "coordinator.quota.storage_limit",
+    "coordinator.quota.object_limit",
+    "coordinator.quota.rate_limit",
+    "coordinator.quota.ingress_limit",
+    "coordinator.quota.egress_limit",
+    "coordinator.auth",
+    "coordinator.grpc_auth",
+];
+
+/// Apply the `fields` of a reloaded `config` that changed
+#[allow(clippy::result_large_err)]
+fn apply_reload(
+    config: &Config,
+    fields: &[String],
+    rate_limiter: &RateLimiter,
+    auth: &RwLock<AuthConfig>,
+) -> Result<()> {
+    let coordinator = config.coordinator.clone().unwrap_or_default();
+    if touched(fields, "log_level") {
+        crate::common::logging::set_level(&config.logging.log_level)?;
+    }
+    if touched(fields, "coordinator.grpc_auth") {
+        configure_grpc_auth(&coordinator.grpc_auth)?;
+    }
+    if touched(fields, "coordinator.auth") {
+        // A new random secret would void the JWTs handed out so far
+        if touched(fields, "coordinator.auth.enabled")
+            || touched(fields, "coordinator.auth.jwt_secret")
+        {
+            KEY_STORE
+                .configure(&coordinator.auth)
+                .map_err(|e| crate::Error::InvalidConfig(e.to_string()))?;
+        }
+        *auth.write().unwrap() = coordinator.auth;
+    }
+    if touched(fields, "coordinator.rate_limit") {
+        // The eviction task keeps its interval until restarted
+        let mut rate_limit = coordinator.rate_limit;
+        rate_limit.eviction_interval_secs = rate_limiter.stats().config.eviction_interval_secs;
+        rate_limiter.set_config(rate_limit);
+    }
+    if touched(fields, "coordinator.quota") {
+        set_default_quota(&coordinator.quota);
+    }
+    Ok(())
+}
 
 pub struct Coordinator {
     config: CoordinatorConfig,
@@ -24,31 +103,166 @@ impl Coordinator {
         tracing::info!("Starting coordinator: {}", self.node_id);
         tracing::info!("  HTTP API: {}", self.config.bind_addr);
         tracing::info!("  gRPC API: {}", self.config.grpc_addr);
+        if let Some(addr) = self.config.client_grpc_addr {
+            tracing::info!("  Client gRPC API: {}", addr);
+        }
+        if let Some(addr) = self.config.resp_addr {
+            tracing::info!("  RESP: {}", addr);
+        }
+        if let Some(addr) = self.config.memcached_addr {
+            tracing::info!("  memcached: {}", addr);
+        }
         tracing::info!("  DB path: {}", self.config.db_path.display());
         tracing::info!("  Replicas: {}", self.config.replicas);
 
         // Initialize metadata store
         let metadata = Arc::new(MetadataStore::open(&self.config.db_path)?);
+        init_global_store(metadata.clone());
+        // API keys, tenant quotas and ACL rules survive restarts
+        metadata.load_api_keys()?;
+        metadata.load_quotas()?;
+        metadata.load_acl_rules()?;
+        KEY_STORE
+            .configure(&self.config.auth)
+            .map_err(|e| crate::Error::InvalidConfig(e.to_string()))?;
+        tracing::info!("  Authentication: {}", self.config.auth.enabled);
+        AUDIT_LOGGER.configure(&self.config.audit).map_err(|e| {
+            crate::Error::InvalidConfig(format!("audit log {}: {}", self.config.audit.path, e))
+        })?;
+        configure_grpc_tls(&self.config.grpc_tls)?;
+        tracing::info!("  Internal gRPC mTLS: {}", self.config.grpc_tls.enabled);
+        configure_grpc_auth(&self.config.grpc_auth)?;
+        tracing::info!("  Internal gRPC tokens: {}", self.config.grpc_auth.enabled);
 
         // Initialize placement manager
-        let placement = Arc::new(Mutex::new(PlacementManager::new(
-            self.config.num_shards,
-            self.config.replicas,
-        )));
+        let placement = Arc::new(Mutex::new(
+            PlacementManager::new(self.config.num_shards, self.config.replicas)
+                .with_policy(
+                    self.config.failure_domain,
+                    self.config.allow_placement_violation,
+                )
+                .with_min_free_bytes(self.config.min_free_bytes),
+        ));
+
+        // Throttle shard migration traffic
+        MIGRATIONS.set_bandwidth(self.config.migration_bandwidth_bytes_per_sec);
+        MIGRATIONS.set_max_concurrent_transfers(self.config.max_concurrent_transfers);
+
+        // Seed the shard map from the registered volumes
+        placement
+            .lock()
+            .unwrap()
+            .rebalance(&metadata.list_volumes()?);
 
         // Initialize Raft
-        let raft = Arc::new(RaftNode::new(self.node_id.clone()));
+        let raft = Arc::new(
+            RaftNode::open(self.node_id.clone(), metadata.clone())?
+                .with_snapshot_threshold(self.config.snapshot_threshold)
+                .with_advertise_addr(self.config.advertise_url())
+                .with_peers(self.config.peers.clone()),
+        );
         let _raft_handle = start_raft_tasks(raft.clone());
 
+        // Resolve 2PC transactions left over from a previous leader
+        let _recovery_handle = start_recovery_task(metadata.clone(), raft.clone());
+
+        // Track volume liveness and re-replicate keys off dead volumes
+        let _health_handle = start_health_monitor(
+            metadata.clone(),
+            placement.clone(),
+            raft.clone(),
+            HealthConfig {
+                suspect_after_secs: self.config.suspect_after_secs,
+                dead_after_secs: self.config.dead_after_secs,
+            },
+        );
+
+        // Periodically compare replica Merkle trees and sync divergent keys
+        let _anti_entropy_handle = start_anti_entropy_task(
+            metadata.clone(),
+            raft.clone(),
+            self.config.num_shards,
+            self.config.anti_entropy_interval_secs,
+        );
+
+        // Delete blobs no key metadata points at
+        let _gc_handle = start_gc_task(
+            metadata.clone(),
+            raft.clone(),
+            self.config.gc_interval_secs,
+            self.config.gc_grace_secs,
+        );
+
+        // Trim the CDC log and feed its sinks
+        let _cdc_handle = start_cdc_task(metadata.clone(), raft.clone(), self.config.cdc.clone());
+
+        // Delete the etcd gateway keys of leases not kept alive
+        let _etcd_lease_handle = start_lease_expiry_task(metadata.clone(), raft.clone());
+
+        // Push key changes to the remote clusters
+        let _replication_handle = start_replication_task(
+            metadata.clone(),
+            raft.clone(),
+            self.config.replication.clone(),
+        );
+
+        crate::coordinator::health::set_ready_min_volumes(self.config.ready_min_volumes);
+        crate::coordinator::dedup::set_enabled(self.config.dedup);
+        crate::coordinator::chunking::set_config(self.config.chunking);
+        crate::coordinator::consistency::set_verify_on_read(self.config.verify_on_read);
+        crate::coordinator::redirect::set_config(self.config.redirect);
+        crate::coordinator::hotkeys::HOT_KEYS.set_config(self.config.hotkeys);
+        crate::common::FAULTS.set_enabled(self.config.fault_injection);
+        crate::coordinator::plugins::PLUGINS.load(&self.config.plugins)?;
+
+        // Notify webhooks of cluster events, and of key changes on the leader
+        crate::coordinator::webhooks::WEBHOOKS.start(&self.config.webhooks)?;
+        let _webhook_handle = start_webhook_task(metadata.clone(), raft.clone());
+
+        // Move cold values to the external bucket
+        let _tiering_handle =
+            start_tiering_task(metadata.clone(), raft.clone(), self.config.tiering.clone());
+
+        // Tenant quotas, with usage recomputed from the key metadata
+        let _usage_handle = start_usage_task(metadata.clone(), self.config.quota.clone());
+        // Per-IP rate limiting; idle clients and tenants are forgotten
+        let rate_limiter = Arc::new(RateLimiter::new(self.config.rate_limit.clone()));
+        let _eviction_handle = start_eviction_task(
+            vec![
+                Box::new(&*QUOTA_MANAGER),
+                Box::new(rate_limiter.as_ref().clone()),
+            ],
+            self.config.rate_limit.eviction_interval(),
+        );
+
+        // Clients refused by address on each listener
+        let ip_filter = Arc::new(IpFilter::from_config(&self.config.ip_filter)?);
+
+        // Apply configuration changes on SIGHUP or POST /admin/config/reload
+        let auth = Arc::new(RwLock::new(self.config.auth.clone()));
+        match Config::try_load() {
+            Ok(running) => RELOADER.install(&running, RELOADABLE, {
+                let (rate_limiter, auth) = (rate_limiter.clone(), auth.clone());
+                move |config, fields| apply_reload(config, fields, &rate_limiter, &auth)
+            })?,
+            Err(e) => tracing::warn!("Configuration reload unavailable: {}", e),
+        }
+        let _reload_handle = start_reload_task();
+
         // Create HTTP server
         let http_state = CoordState {
             metadata: metadata.clone(),
             placement: placement.clone(),
             raft: raft.clone(),
+            auth,
+            rate_limiter,
+            ip_filter: ip_filter.clone(),
+            writes: Arc::new(WriteBudget::new(self.config.write_budget)),
         };
         let http_router = create_router(http_state);
 
-        // TLS support (axum-server/rustls)
+        // TLS support (axum-server/rustls). Either way, the listener closes
+        // once a shutdown starts and waits for the requests in flight.
         let use_tls = self.config.tls_cert_path.is_some() && self.config.tls_key_path.is_some();
         use std::future::Future;
         use std::pin::Pin;
@@ -60,18 +274,61 @@ impl Coordinator {
             let rustls_config = RustlsConfig::from_pem_file(cert_path, key_path)
                 .await
                 .unwrap();
+            let handle = axum_server::Handle::new();
+            let draining = handle.clone();
+            tokio::spawn(async move {
+                SHUTDOWN.wait().await;
+                draining.graceful_shutdown(None);
+            });
             Box::pin(
                 bind_rustls(self.config.bind_addr, rustls_config)
-                    .serve(http_router.clone().into_make_service()),
+                    .handle(handle)
+                    .serve(
+                        http_router
+                            .clone()
+                            .into_make_service_with_connect_info::<std::net::SocketAddr>(),
+                    ),
             )
         } else {
             let http_listener = tokio::net::TcpListener::bind(self.config.bind_addr).await?;
-            Box::pin(axum::serve(http_listener, http_router.clone()).into_future())
+            Box::pin(
+                axum::serve(
+                    http_listener,
+                    http_router
+                        .clone()
+                        .into_make_service_with_connect_info::<std::net::SocketAddr>(),
+                )
+                .with_graceful_shutdown(SHUTDOWN.wait())
+                .into_future(),
+            )
         };
 
-        // Create gRPC server (TLS enabled if certs are present)
-        let grpc_service = CoordGrpcService::new();
-        let grpc_server = if let (Some(cert_path), Some(key_path)) = (
+        // Create gRPC server (mutual TLS if configured, else TLS if certs are present)
+        let mut grpc_service = CoordGrpcService::new()
+            .with_min_free_bytes(self.config.min_free_bytes)
+            .with_raft(raft.clone());
+        if self.config.auto_rebalance {
+            grpc_service = grpc_service.with_auto_rebalance(metadata.clone(), placement.clone());
+        }
+        // Internal calls need an allowed address, client certificate and token, when configured
+        let grpc_service = InterceptedService::new(grpc_service.into_server(), move |request| {
+            ip_filter
+                .check_grpc(request)
+                .and_then(check_peer)
+                .and_then(check_token)
+        });
+        // Serves Raft and the volumes until the end of a shutdown
+        let (stop_internal, internal_stopped) = tokio::sync::oneshot::channel::<()>();
+        let internal_stopped = async move {
+            let _ = internal_stopped.await;
+        };
+        let grpc_server = if let Some(tls) = server_tls_config() {
+            tonic::transport::Server::builder()
+                .tls_config(tls)
+                .expect("Invalid gRPC mTLS config")
+                .add_service(grpc_service)
+                .serve_with_shutdown(self.config.grpc_addr, internal_stopped)
+        } else if let (Some(cert_path), Some(key_path)) = (
             self.config.tls_cert_path.as_ref(),
             self.config.tls_key_path.as_ref(),
         ) {
@@ -84,31 +341,140 @@ impl Coordinator {
             tonic::transport::Server::builder()
                 .tls_config(ServerTlsConfig::new().identity(identity))
                 .expect("Invalid TLS config")
-                .add_service(grpc_service.into_server())
-                .serve(self.config.grpc_addr)
+                .add_service(grpc_service)
+                .serve_with_shutdown(self.config.grpc_addr, internal_stopped)
         } else {
             tonic::transport::Server::builder()
-                .add_service(grpc_service.into_server())
-                .serve(self.config.grpc_addr)
+                .add_service(grpc_service)
+                .serve_with_shutdown(self.config.grpc_addr, internal_stopped)
+        };
+
+        // Public gRPC API, authenticated by the HTTP router it dispatches to
+        let client_grpc_server: std::pin::Pin<
+            Box<
+                dyn std::future::Future<Output = std::result::Result<(), tonic::transport::Error>>
+                    + Send,
+            >,
+        > = match self.config.client_grpc_addr {
+            Some(addr) => {
+                let service = KvGrpcService::new(http_router.clone()).into_server();
+                let mut builder = tonic::transport::Server::builder();
+                if let (Some(cert_path), Some(key_path)) = (
+                    self.config.tls_cert_path.as_ref(),
+                    self.config.tls_key_path.as_ref(),
+                ) {
+                    use tonic::transport::{Identity, ServerTlsConfig};
+                    let cert = tokio::fs::read(cert_path).await?;
+                    let key = tokio::fs::read(key_path).await?;
+                    builder = builder
+                        .tls_config(ServerTlsConfig::new().identity(Identity::from_pem(cert, key)))
+                        .map_err(|e| crate::Error::InvalidConfig(e.to_string()))?;
+                }
+                Box::pin(
+                    builder
+                        .add_service(service)
+                        .serve_with_shutdown(addr, SHUTDOWN.wait()),
+                )
+            }
+            None => Box::pin(std::future::ready(Ok(()))),
+        };
+
+        // Redis and memcached protocol listeners, dispatching to the HTTP router too
+        let resp_server: std::pin::Pin<
+            Box<dyn std::future::Future<Output = std::io::Result<()>> + Send>,
+        > = match self.config.resp_addr {
+            Some(addr) => {
+                let listener = tokio::net::TcpListener::bind(addr).await?;
+                Box::pin(until_shutdown(
+                    RespServer::new(http_router.clone()).serve(listener),
+                ))
+            }
+            None => Box::pin(std::future::ready(Ok(()))),
+        };
+        let memcached_server: std::pin::Pin<
+            Box<dyn std::future::Future<Output = std::io::Result<()>> + Send>,
+        > = match self.config.memcached_addr {
+            Some(addr) => {
+                let listener = tokio::net::TcpListener::bind(addr).await?;
+                let server =
+                    MemcachedServer::new(http_router.clone()).with_auth(self.config.auth.enabled);
+                Box::pin(until_shutdown(server.serve(listener)))
+            }
+            None => Box::pin(std::future::ready(Ok(()))),
         };
 
         // Start servers
 
         tracing::info!("✓ Coordinator ready ({:?})", raft.get_role());
+        let _signal_handle = start_signal_task();
+        lifecycle::notify_ready();
+        let internal = tokio::spawn(run_listener("gRPC server", grpc_server));
+        let listeners = async {
+            tokio::join!(
+                run_listener("HTTP server", http_server),
+                run_listener("Client gRPC server", client_grpc_server),
+                run_listener("RESP server", resp_server),
+                run_listener("memcached server", memcached_server),
+            )
+        };
+        tokio::pin!(listeners);
 
+        // Until a signal, or a listener failing
+        let mut drained = false;
         tokio::select! {
-            res = http_server => {
-                if let Err(e) = res {
-                    tracing::error!("HTTP server error: {}", e);
-                }
-            }
-            res = grpc_server => {
-                if let Err(e) = res {
-                    tracing::error!("gRPC server error: {}", e);
-                }
-            }
+            _ = &mut listeners => drained = true,
+            _ = SHUTDOWN.wait() => {}
         }
-
+        lifecycle::notify_stopping();
+        let deadline =
+            tokio::time::Instant::now() + Duration::from_secs(self.config.shutdown_timeout_secs);
+        if !drained
+            && tokio::time::timeout_at(deadline, &mut listeners)
+                .await
+                .is_err()
+        {
+            tracing::warn!("Requests still in flight at the shutdown timeout");
+        }
+        if raft.is_leader()
+            && tokio::time::timeout_at(deadline, hand_over(&metadata, &raft))
+                .await
+                .is_err()
+        {
+            tracing::warn!("Shutdown timed out before leadership was handed over");
+        }
+        let _ = stop_internal.send(());
+        let _ = tokio::time::timeout_at(deadline, internal).await;
+        metadata.flush()?;
+        tracing::info!("Coordinator stopped");
         Ok(())
     }
 }
+
+/// Run `server` until a shutdown starts
+async fn until_shutdown(
+    server: impl std::future::Future<Output = std::io::Result<()>>,
+) -> std::io::Result<()> {
+    tokio::select! {
+        res = server => res,
+        _ = SHUTDOWN.wait() => Ok(()),
+    }
+}
+
+/// Before a leader exits: resolve the two-phase commits left unfinished, then
+/// hand leadership to the most caught-up peer rather than have the cluster
+/// wait out an election timeout
+async fn hand_over(metadata: &MetadataStore, raft: &RaftNode) {
+    if let Err(e) = recover_transactions(metadata, raft).await {
+        tracing::warn!("Resolving transactions before shutdown failed: {}", e);
+    }
+    let Some(peer) = raft.most_caught_up_peer() else {
+        return;
+    };
+    match raft
+        .transfer_leadership(&peer, DEFAULT_TRANSFER_TIMEOUT)
+        .await
+    {
+        Ok(()) => tracing::info!("Handed leadership to {}", peer),
+        Err(e) => tracing::warn!("Leadership transfer to {} failed: {}", peer, e),
+    }
+}
@@ -0,0 +1,126 @@
+//! Tenant namespaces
+//!
+//! Every key a client reads or writes lives in its tenant's namespace: key
+//! `photos/cat.jpg` of tenant `acme` is stored as `acme/photos/cat.jpg`, so
+//! the tenant of a stored key is its first path segment (as dedup and tiering
+//! already assume). Clients never see the tenant segment.
+//!
+//! The tenant of a request is the one of its API key, `default` without one.
+//! Admins, and every caller while authentication is disabled, may work in
+//! another tenant's namespace with the `X-Minikv-Tenant` header; anyone else
+//! naming a tenant other than their own is refused.
+
+use crate::common::{AuthContext, Result};
+
+/// Header naming the tenant to act for
+pub const TENANT_HEADER: &str = "x-minikv-tenant";
+
+/// Tenant of unauthenticated requests
+pub const DEFAULT_TENANT: &str = "default";
+
+/// Whether `tenant` can name a namespace: non-empty, without `/`, and not
+/// starting with `.` (reserved for internal keys such as `.cas/`)
+pub fn is_valid(tenant: &str) -> bool {
+    !tenant.is_empty() && !tenant.contains('/') && !tenant.starts_with('.')
+}
+
+/// Prefix of every stored key of `tenant`
+pub fn prefix(tenant: &str) -> String {
+    format!("{}/", tenant)
+}
+
+/// Stored key of the client key `key` of `tenant`
+pub fn internal_key(tenant: &str, key: &str) -> String {
+    format!("{}/{}", tenant, key)
+}
+
+/// Client key of the stored key `internal`, if it belongs to `tenant`
+pub fn user_key<'a>(tenant: &str, internal: &'a str) -> Option<&'a str> {
+    internal
+        .strip_prefix(tenant)
+        .and_then(|rest| rest.strip_prefix('/'))
+}
+
+/// Tenant a request works in, given its authenticated caller (if any),
+/// whether authentication is enabled and the tenant it asks for
+pub fn resolve(
+    caller: Option<&AuthContext>,
+    auth_enabled: bool,
+    requested: Option<&str>,
+) -> Result<String> {
+    let own = caller.map_or(DEFAULT_TENANT, |ctx| ctx.tenant.as_str());
+    let tenant = requested.unwrap_or(own);
+    if !is_valid(tenant) {
+        return Err(crate::Error::InvalidRequest(format!(
+            "invalid tenant {:?}",
+            tenant
+        )));
+    }
+    let may_switch = !auth_enabled || caller.is_some_and(|ctx| ctx.can_admin());
+    if tenant != own && !may_switch {
+        return Err(crate::Error::PermissionDenied(format!(
+            "tenant {} may not access tenant {}",
+            own, tenant
+        )));
+    }
+    Ok(tenant.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::Role;
+
+    fn caller(tenant: &str, role: Role) -> AuthContext {
+        AuthContext {
+            key_id: "key".to_string(),
+            tenant: tenant.to_string(),
+            role,
+        }
+    }
+
+    #[test]
+    fn test_keys_are_namespaced() {
+        let key = internal_key("acme", "photos/cat.jpg");
+        assert_eq!(key, "acme/photos/cat.jpg");
+        assert!(key.starts_with(&prefix("acme")));
+        assert_eq!(user_key("acme", &key), Some("photos/cat.jpg"));
+        assert_eq!(user_key("acm", &key), None);
+        assert_eq!(user_key("other", &key), None);
+
+        assert!(is_valid("acme"));
+        assert!(!is_valid(""));
+        assert!(!is_valid("a/b"));
+        assert!(!is_valid(".cas"));
+    }
+
+    #[test]
+    fn test_resolve() {
+        let reader = caller("acme", Role::ReadWrite);
+        let admin = caller("ops", Role::Admin);
+
+        assert_eq!(resolve(None, true, None).unwrap(), DEFAULT_TENANT);
+        assert_eq!(resolve(Some(&reader), true, None).unwrap(), "acme");
+        assert_eq!(resolve(Some(&reader), true, Some("acme")).unwrap(), "acme");
+
+        // Cross-tenant access is for admins only
+        assert!(matches!(
+            resolve(Some(&reader), true, Some("globex")),
+            Err(crate::Error::PermissionDenied(_))
+        ));
+        assert!(matches!(
+            resolve(None, true, Some("globex")),
+            Err(crate::Error::PermissionDenied(_))
+        ));
+        assert_eq!(
+            resolve(Some(&admin), true, Some("globex")).unwrap(),
+            "globex"
+        );
+        assert_eq!(resolve(None, false, Some("globex")).unwrap(), "globex");
+
+        assert!(matches!(
+            resolve(Some(&admin), true, Some("../etc")),
+            Err(crate::Error::InvalidRequest(_))
+        ));
+    }
+}
@@ -0,0 +1,467 @@
+//! Cold storage tiering
+//!
+//! On the leader, a periodic pass moves cold values off the volumes into an
+//! external S3-compatible bucket. A key is cold once its tenant's policy says
+//! so: written longer than `min_age_secs` ago and, optionally, not read for
+//! `min_idle_secs`. Tiering uploads the value to `<prefix><key>`, publishes the
+//! key metadata through Raft as a `Tiered` stub (same size, BLAKE3 and tags, no
+//! replicas), applied only if the key was not written meanwhile, and then
+//! deletes the volume copies.
+//!
+//! GETs of a tiered key fetch the object and check it against the stub's
+//! BLAKE3. With `on_read = "proxy"` the key stays tiered; with `"rehydrate"`
+//! it is written back to the volumes through 2PC and the object removed.
+//!
+//! Read times are only tracked in the leader's memory. A freshly elected
+//! leader counts every key as read when it took over, so failovers delay
+//! tiering rather than trigger it.
+
+use crate::common::{timestamp_now, Result, S3Client, TierReadMode, TieringConfig};
+use crate::coordinator::consistency::{self, ConsistencyLevel};
+use crate::coordinator::dedup;
+use crate::coordinator::metadata::{
+    KeyMetadata, KeyState, MetadataCommand, MetadataStore, MAX_PAGE_SIZE,
+};
+use crate::coordinator::placement::PlacementManager;
+use crate::coordinator::raft_node::RaftNode;
+use crate::coordinator::volume_client::VolumeClient;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+/// Tiering state shared by the background task and the HTTP handlers
+pub static TIERING: Lazy<TierManager> = Lazy::new(|| TierManager {
+    tier: RwLock::new(None),
+    last_read: Mutex::new(HashMap::new()),
+    tracking_since: AtomicU64::new(timestamp_now()),
+    running: AtomicBool::new(false),
+    last_run: Mutex::new(None),
+});
+
+/// Configured bucket and policies
+pub struct Tier {
+    pub config: TieringConfig,
+    pub s3: S3Client,
+}
+
+impl Tier {
+    /// Name of the object holding `key`
+    pub fn object_key(&self, key: &str) -> String {
+        format!("{}{}", self.config.prefix, key)
+    }
+}
+
+pub struct TierManager {
+    tier: RwLock<Option<Arc<Tier>>>,
+    /// Last read of each key served by this node (Unix seconds)
+    last_read: Mutex<HashMap<String, u64>>,
+    tracking_since: AtomicU64,
+    running: AtomicBool,
+    last_run: Mutex<Option<TierReport>>,
+}
+
+impl TierManager {
+    /// Apply the tiering configuration (disabled configs clear it)
+    pub fn configure(&self, config: TieringConfig) {
+        let tier = config.enabled.then(|| {
+            Arc::new(Tier {
+                s3: S3Client::from_config(&config),
+                config,
+            })
+        });
+        *self.tier.write().unwrap() = tier;
+    }
+
+    /// The bucket and policies, if tiering is configured
+    pub fn tier(&self) -> Option<Arc<Tier>> {
+        self.tier.read().unwrap().clone()
+    }
+
+    /// Note a read of `key`
+    pub fn record_read(&self, key: &str) {
+        self.last_read
+            .lock()
+            .unwrap()
+            .insert(key.to_string(), timestamp_now());
+    }
+
+    /// Forget the reads of a key that was deleted or tiered
+    pub fn forget(&self, key: &str) {
+        self.last_read.lock().unwrap().remove(key);
+    }
+
+    /// Last read of `key`, or when this node started tracking reads
+    pub fn last_read(&self, key: &str) -> u64 {
+        let since = self.tracking_since.load(Ordering::Relaxed);
+        self.last_read
+            .lock()
+            .unwrap()
+            .get(key)
+            .map_or(since, |&t| t.max(since))
+    }
+
+    /// Restart read tracking, e.g. when this node becomes leader
+    pub fn reset_reads(&self) {
+        self.last_read.lock().unwrap().clear();
+        self.tracking_since
+            .store(timestamp_now(), Ordering::Relaxed);
+    }
+}
+
+/// Outcome of a tiering pass
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TierReport {
+    pub started_at: u64,
+    pub finished_at: u64,
+    pub keys_scanned: u64,
+    pub keys_tiered: u64,
+    pub bytes_tiered: u64,
+    pub failed: u64,
+}
+
+/// Tiering overview served at `/admin/tier/status`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TierStatus {
+    pub enabled: bool,
+    pub bucket: Option<String>,
+    pub running: bool,
+    pub tiered_keys: u64,
+    pub tiered_bytes: u64,
+    pub last_run: Option<TierReport>,
+}
+
+/// Whether `meta` is cold under the configured policy
+pub fn is_cold(config: &TieringConfig, meta: &KeyMetadata, last_read: u64, now: u64) -> bool {
+    let policy = config.policy_for(&meta.key);
+    meta.state == KeyState::Active
+        && meta.blob.is_none()
+        && meta.chunks.is_none()
+        && !dedup::is_reserved(&meta.key)
+        && policy.min_age_secs > 0
+        && now.saturating_sub(meta.updated_at) >= policy.min_age_secs
+        && (policy.min_idle_secs == 0 || now.saturating_sub(last_read) >= policy.min_idle_secs)
+}
+
+/// Move one key's value to the bucket and leave a stub in its metadata
+pub async fn tier_key(
+    metadata: &MetadataStore,
+    raft: &RaftNode,
+    tier: &Tier,
+    meta: &KeyMetadata,
+) -> Result<()> {
+    let data = consistency::quorum_read(metadata, meta, ConsistencyLevel::One).await?;
+    let object = tier.object_key(&meta.key);
+    tier.s3.put_object(&object, data).await?;
+
+    // A write may land while the value is uploaded: the stub only replaces
+    // the metadata if it still describes the value uploaded
+    let stub = KeyMetadata {
+        replicas: Vec::new(),
+        state: KeyState::Tiered,
+        ..meta.clone()
+    };
+    raft.propose(&MetadataCommand::TierKey(stub)).await?;
+    let tiered = metadata
+        .get_key(&meta.key)?
+        .is_some_and(|current| current.state == KeyState::Tiered && current.blake3 == meta.blake3);
+    if !tiered {
+        if let Err(e) = tier.s3.delete_object(&object).await {
+            tracing::warn!("Failed to remove tiered object {}: {}", object, e);
+        }
+        return Err(crate::Error::Internal(format!(
+            "{} changed while being tiered",
+            meta.key
+        )));
+    }
+    TIERING.forget(&meta.key);
+
+    delete_replicas(metadata, &meta.key, &meta.replicas).await;
+    Ok(())
+}
+
+/// One tiering pass over every key. Only one pass runs at a time.
+pub async fn run_tier_pass(metadata: &MetadataStore, raft: &RaftNode) -> Result<TierReport> {
+    let Some(tier) = TIERING.tier() else {
+        return Err(crate::Error::InvalidConfig(
+            "cold storage tiering is not configured".into(),
+        ));
+    };
+    raft.ensure_leader()?;
+    if TIERING.running.swap(true, Ordering::SeqCst) {
+        return Err(crate::Error::Internal(
+            "a tiering pass is already running".into(),
+        ));
+    }
+
+    let mut report = TierReport {
+        started_at: timestamp_now(),
+        ..Default::default()
+    };
+    let result = tier_pass(metadata, raft, &tier, &mut report).await;
+    report.finished_at = timestamp_now();
+    TIERING.running.store(false, Ordering::SeqCst);
+    *TIERING.last_run.lock().unwrap() = Some(report.clone());
+    result.map(|_| report)
+}
+
+async fn tier_pass(
+    metadata: &MetadataStore,
+    raft: &RaftNode,
+    tier: &Tier,
+    report: &mut TierReport,
+) -> Result<()> {
+    let mut after: Option<String> = None;
+    loop {
+        let page = metadata.list_keys_paginated("", after.as_deref(), MAX_PAGE_SIZE)?;
+        let Some(last) = page.last() else {
+            return Ok(());
+        };
+        after = Some(last.key.clone());
+
+        for meta in &page {
+            report.keys_scanned += 1;
+            if !is_cold(
+                &tier.config,
+                meta,
+                TIERING.last_read(&meta.key),
+                timestamp_now(),
+            ) {
+                continue;
+            }
+            if !raft.is_leader() {
+                return Err(crate::Error::NotLeader(
+                    "lost leadership during tiering".into(),
+                ));
+            }
+            match tier_key(metadata, raft, tier, meta).await {
+                Ok(()) => {
+                    report.keys_tiered += 1;
+                    report.bytes_tiered += meta.size;
+                }
+                Err(e) => {
+                    tracing::warn!("Tiering {} failed: {}", meta.key, e);
+                    report.failed += 1;
+                }
+            }
+        }
+        if page.len() < MAX_PAGE_SIZE {
+            return Ok(());
+        }
+    }
+}
+
+/// Read a tiered key's value from the bucket, rehydrating it if configured to
+pub async fn read_tiered(
+    metadata: &Arc<MetadataStore>,
+    raft: &Arc<RaftNode>,
+    placement: &Mutex<PlacementManager>,
+    meta: &KeyMetadata,
+) -> Result<Vec<u8>> {
+    let (tier, data) = fetch_tiered(meta).await?;
+    if tier.config.on_read == TierReadMode::Rehydrate && raft.is_leader() {
+        if let Err(e) = rehydrate(metadata, raft, placement, &tier, meta, data.clone()).await {
+            tracing::warn!("Rehydrating {} failed: {}", meta.key, e);
+        }
+    }
+    Ok(data)
+}
+
+/// Read a tiered key's value from the bucket, checked against its BLAKE3
+pub async fn fetch_tiered(meta: &KeyMetadata) -> Result<(Arc<Tier>, Vec<u8>)> {
+    let Some(tier) = TIERING.tier() else {
+        return Err(crate::Error::InvalidConfig(format!(
+            "{} is tiered but cold storage is not configured",
+            meta.key
+        )));
+    };
+    let object = tier.object_key(&meta.key);
+    let data = tier
+        .s3
+        .get_object(&object)
+        .await?
+        .ok_or_else(|| crate::Error::NotFound(format!("{} (tiered object)", meta.key)))?;
+    if crate::common::blake3_hash(&data) != meta.blake3 {
+        return Err(crate::Error::Corrupted(format!(
+            "tiered object for {} does not match its checksum",
+            meta.key
+        )));
+    }
+    Ok((tier, data))
+}
+
+/// Write a tiered value back to the volumes, then drop its object
+async fn rehydrate(
+    metadata: &Arc<MetadataStore>,
+    raft: &Arc<RaftNode>,
+    placement: &Mutex<PlacementManager>,
+    tier: &Tier,
+    meta: &KeyMetadata,
+    data: Vec<u8>,
+) -> Result<()> {
+    let volumes = metadata.get_healthy_volumes()?;
+    let target_ids = placement
+        .lock()
+        .unwrap()
+        .select_volumes(&meta.key, &volumes)?;
+    let targets: Vec<_> = volumes
+        .into_iter()
+        .filter(|v| target_ids.contains(&v.volume_id))
+        .collect();
+    crate::coordinator::txn::execute_2pc(
+        metadata,
+        raft,
+        &meta.key,
+        &targets,
+        data,
+        meta.tags.clone(),
+        ConsistencyLevel::Quorum,
+    )
+    .await?;
+    tier.s3.delete_object(&tier.object_key(&meta.key)).await
+}
+
+/// Remove the object of a tiered key that was deleted
+pub async fn delete_tiered(key: &str) -> Result<()> {
+    TIERING.forget(key);
+    match TIERING.tier() {
+        Some(tier) => tier.s3.delete_object(&tier.object_key(key)).await,
+        None => Ok(()),
+    }
+}
+
+/// Current tiering status, counting the tiered keys
+pub fn tier_status(metadata: &MetadataStore) -> Result<TierStatus> {
+    let tier = TIERING.tier();
+    let mut status = TierStatus {
+        enabled: tier.is_some(),
+        bucket: tier.map(|t| t.s3.bucket().to_string()),
+        running: TIERING.running.load(Ordering::SeqCst),
+        last_run: TIERING.last_run.lock().unwrap().clone(),
+        ..Default::default()
+    };
+
+    let mut after: Option<String> = None;
+    loop {
+        let page = metadata.list_keys_paginated("", after.as_deref(), MAX_PAGE_SIZE)?;
+        for meta in page.iter().filter(|m| m.state == KeyState::Tiered) {
+            status.tiered_keys += 1;
+            status.tiered_bytes += meta.size;
+        }
+        match page.last() {
+            Some(last) if page.len() == MAX_PAGE_SIZE => after = Some(last.key.clone()),
+            _ => return Ok(status),
+        }
+    }
+}
+
+async fn delete_replicas(metadata: &MetadataStore, key: &str, replicas: &[String]) {
+    for volume_id in replicas {
+        let Ok(Some(volume)) = metadata.get_volume(volume_id) else {
+            continue;
+        };
+        let result = match VolumeClient::connect(volume.grpc_address.clone()).await {
+            Ok(mut client) => client.delete(key.to_string()).await.map(|_| ()),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            tracing::warn!("Delete of tiered {} on {} failed: {}", key, volume_id, e);
+        }
+    }
+}
+
+/// Background task: a tiering pass every `interval_secs` while this node leads
+pub fn start_tiering_task(
+    metadata: Arc<MetadataStore>,
+    raft: Arc<RaftNode>,
+    config: TieringConfig,
+) -> tokio::task::JoinHandle<()> {
+    let interval = Duration::from_secs(config.interval_secs.max(1));
+    TIERING.configure(config);
+    tokio::spawn(async move {
+        let mut was_leader = false;
+        loop {
+            tokio::time::sleep(interval).await;
+            let leader = raft.is_leader();
+            if leader && !was_leader {
+                // Reads served before this node led were not tracked here
+                TIERING.reset_reads();
+            }
+            was_leader = leader;
+            if !leader || TIERING.tier().is_none() {
+                continue;
+            }
+            match run_tier_pass(&metadata, &raft).await {
+                Ok(report) if report.keys_tiered > 0 => tracing::info!(
+                    "Tiered {} keys ({} bytes) to cold storage",
+                    report.keys_tiered,
+                    report.bytes_tiered
+                ),
+                Ok(_) => {}
+                Err(e) => tracing::warn!("Tiering pass failed: {}", e),
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::TierPolicy;
+
+    fn meta(key: &str, updated_at: u64) -> KeyMetadata {
+        KeyMetadata {
+            key: key.to_string(),
+            replicas: vec!["vol-1".to_string()],
+            size: 1,
+            blake3: String::new(),
+            created_at: 0,
+            updated_at,
+            state: KeyState::Active,
+            tags: Default::default(),
+            blob: None,
+            chunks: None,
+        }
+    }
+
+    #[test]
+    fn test_cold_keys_follow_tenant_policies() {
+        let mut config = TieringConfig {
+            default_policy: TierPolicy {
+                min_age_secs: 100,
+                min_idle_secs: 0,
+            },
+            ..Default::default()
+        };
+        config.tenants.insert(
+            "acme".to_string(),
+            TierPolicy {
+                min_age_secs: 10,
+                min_idle_secs: 50,
+            },
+        );
+
+        let now = 1_000;
+        assert!(is_cold(&config, &meta("logs/a", 900), now, now));
+        assert!(!is_cold(&config, &meta("logs/a", 901), 0, now));
+
+        // acme keys tier sooner, but only once nobody read them for a while
+        assert!(is_cold(&config, &meta("acme/a", 990), 950, now));
+        assert!(!is_cold(&config, &meta("acme/a", 990), 951, now));
+        assert!(!is_cold(&config, &meta("acme/a", 991), 0, now));
+
+        // Already tiered keys, deduplicated content and a zero age (never)
+        // are left alone
+        let mut tiered = meta("logs/a", 0);
+        tiered.state = KeyState::Tiered;
+        assert!(!is_cold(&config, &tiered, 0, now));
+        let mut reference = meta("logs/a", 0);
+        reference.blob = Some(".cas/logs/ab12".into());
+        assert!(!is_cold(&config, &reference, 0, now));
+        assert!(!is_cold(&config, &meta(".cas/logs/ab12", 0), 0, now));
+        config.default_policy.min_age_secs = 0;
+        assert!(!is_cold(&config, &meta("logs/a", 0), 0, now));
+    }
+}
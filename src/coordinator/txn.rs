@@ -0,0 +1,490 @@
+//! Two-phase commit orchestration and crash recovery
+//!
+//! Every write is recorded in the metadata store's `txns` column family before
+//! the prepare phase starts. Flipping the record to `Committing` is the commit
+//! point: a leader that finds such a record after a restart re-sends Commit to
+//! every replica, while any other record (or a prepared upload the coordinator
+//! has no record of) is aborted. A replica that no longer has the upload
+//! staged either committed it before the crash, if it holds the value, or
+//! lost it, and is then left out of the write; with no replica left the write
+//! is aborted.
+//!
+//! Transaction records and the published key metadata are written through Raft,
+//! so a newly elected leader sees every in-flight write of its predecessor.
+
+use crate::common::utils::generate_upload_id;
+use crate::common::{blake3_hash, timestamp_now, NodeState, Result};
+use crate::coordinator::consistency::ConsistencyLevel;
+use crate::coordinator::metadata::{
+    KeyMetadata, KeyState, MetadataCommand, MetadataStore, TxnRecord, TxnState, VolumeMetadata,
+};
+use crate::coordinator::raft_node::RaftNode;
+use crate::coordinator::volume_client::VolumeClient;
+use futures_util::stream::{FuturesUnordered, StreamExt};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+/// Outcome of a successful 2PC write
+#[derive(Debug, Clone, Serialize)]
+pub struct WriteOutcome {
+    pub upload_id: String,
+    /// Replicas that acknowledged the commit before the coordinator answered
+    pub acked: Vec<String>,
+    /// Replicas the write was committed to (acked or still in flight)
+    pub replicas: Vec<String>,
+}
+
+/// Run a full 2PC write of `key` against `replicas`.
+///
+/// The call returns as soon as `level` is satisfied: enough replicas prepared and
+/// then acknowledged the commit. Commits still in flight keep running in the
+/// background and their acks are recorded on the transaction; the record is only
+/// dropped once every replica acknowledged, so recovery retries the stragglers.
+/// `tags` replace the key's previous tags once the write is published.
+pub async fn execute_2pc(
+    metadata: &Arc<MetadataStore>,
+    raft: &Arc<RaftNode>,
+    key: &str,
+    replicas: &[VolumeMetadata],
+    data: Vec<u8>,
+    tags: BTreeMap<String, String>,
+    level: ConsistencyLevel,
+) -> Result<WriteOutcome> {
+    let required = level.required_acks(replicas.len());
+    let mut txn = TxnRecord {
+        upload_id: generate_upload_id(),
+        key: key.to_string(),
+        replicas: replicas.iter().map(|v| v.volume_id.clone()).collect(),
+        size: data.len() as u64,
+        blake3: blake3_hash(&data),
+        state: TxnState::Preparing,
+        created_at: timestamp_now(),
+        acks: Vec::new(),
+    };
+    save_txn(raft, &txn).await?;
+
+    // Prepare phase: all replicas in parallel
+    let results = futures_util::future::join_all(
+        replicas
+            .iter()
+            .map(|volume| prepare_on(volume, &txn, data.clone())),
+    )
+    .await;
+
+    let mut prepared = Vec::new();
+    let mut unprepared = Vec::new();
+    let mut prepare_error = None;
+    for (volume, result) in replicas.iter().zip(results) {
+        match result {
+            Ok(()) => prepared.push(volume),
+            Err(e) => {
+                tracing::warn!("Prepare of {} on {} failed: {}", key, volume.volume_id, e);
+                if matches!(e, crate::Error::StorageFull(_)) {
+                    exclude_full_volume(metadata, raft, &volume.volume_id).await;
+                }
+                unprepared.push(volume);
+                prepare_error.get_or_insert(e);
+            }
+        }
+    }
+
+    if prepared.is_empty() || prepared.len() < required {
+        txn.state = TxnState::Aborting;
+        save_txn(raft, &txn).await?;
+        for volume in replicas {
+            if let Err(abort_err) = abort_on(&volume.grpc_address, &txn.upload_id).await {
+                tracing::warn!(
+                    "Abort of {} on {} failed: {}",
+                    txn.upload_id,
+                    volume.volume_id,
+                    abort_err
+                );
+            }
+        }
+        forget_txn(raft, &txn.upload_id).await?;
+        return Err(prepare_error.unwrap_or(crate::Error::InsufficientReplicas {
+            needed: required,
+            available: prepared.len(),
+        }));
+    }
+
+    // Replicas that failed to prepare are left out of this write; repair restores them
+    for volume in unprepared {
+        let _ = abort_on(&volume.grpc_address, &txn.upload_id).await;
+    }
+
+    // Commit point: from here on, recovery rolls the write forward
+    txn.replicas = prepared.iter().map(|v| v.volume_id.clone()).collect();
+    txn.state = TxnState::Committing;
+    save_txn(raft, &txn).await?;
+
+    let mut pending: FuturesUnordered<_> = prepared
+        .iter()
+        .map(|volume| {
+            let volume_id = volume.volume_id.clone();
+            let grpc_address = volume.grpc_address.clone();
+            let txn = txn.clone();
+            async move {
+                let result = commit_on(&grpc_address, &txn).await;
+                (volume_id, result)
+            }
+        })
+        .collect();
+
+    while txn.acks.len() < required {
+        match pending.next().await {
+            Some((volume_id, Ok(()))) => txn.acks.push(volume_id),
+            Some((volume_id, Err(e))) => {
+                tracing::warn!("Commit of {} on {} failed: {}", key, volume_id, e);
+            }
+            None => break,
+        }
+    }
+
+    if txn.acks.len() < required {
+        // Stays `Committing`: recovery keeps retrying the missing replicas
+        save_txn(raft, &txn).await?;
+        return Err(crate::Error::CommitFailed {
+            node: txn.key.clone(),
+            reason: format!(
+                "only {} of {} required replicas acknowledged",
+                txn.acks.len(),
+                required
+            ),
+        });
+    }
+
+    publish_key(metadata, raft, &txn, Some(tags)).await?;
+    let outcome = WriteOutcome {
+        upload_id: txn.upload_id.clone(),
+        acked: txn.acks.clone(),
+        replicas: txn.replicas.clone(),
+    };
+
+    if txn.acks.len() == txn.replicas.len() {
+        forget_txn(raft, &txn.upload_id).await?;
+    } else {
+        save_txn(raft, &txn).await?;
+        let raft = raft.clone();
+        tokio::spawn(async move {
+            while let Some((volume_id, result)) = pending.next().await {
+                match result {
+                    Ok(()) => txn.acks.push(volume_id),
+                    Err(e) => {
+                        tracing::warn!("Commit of {} on {} failed: {}", txn.key, volume_id, e)
+                    }
+                }
+            }
+            let result = if txn.acks.len() == txn.replicas.len() {
+                forget_txn(&raft, &txn.upload_id).await
+            } else {
+                save_txn(&raft, &txn).await
+            };
+            if let Err(e) = result {
+                tracing::error!("Failed to record acks for {}: {}", txn.upload_id, e);
+            }
+        });
+    }
+
+    Ok(outcome)
+}
+
+/// Summary of a recovery pass
+#[derive(Debug, Default, Serialize)]
+pub struct RecoveryReport {
+    /// Transactions rolled forward (commit decision was persisted)
+    pub committed: usize,
+    /// Transactions rolled back (no commit decision)
+    pub aborted: usize,
+    /// Transactions left in place because a replica was unreachable
+    pub pending: usize,
+    /// Prepared uploads found on volumes with no matching transaction record
+    pub orphans_aborted: usize,
+}
+
+/// Resolve every in-flight transaction left behind by a previous leader.
+/// Safe to run repeatedly: aborts are idempotent on the volumes, and a
+/// replica that already committed is recognized by the value it holds.
+pub async fn recover_transactions(
+    metadata: &MetadataStore,
+    raft: &RaftNode,
+) -> Result<RecoveryReport> {
+    let mut report = RecoveryReport::default();
+
+    for txn in metadata.list_txns()? {
+        match txn.state {
+            TxnState::Committing => {
+                let mut txn = txn;
+                let mut all_committed = true;
+                let mut lost = Vec::new();
+                for volume_id in txn.replicas.iter().filter(|id| !txn.acks.contains(id)) {
+                    let result = match metadata.get_volume(volume_id)? {
+                        Some(volume) => match commit_on(&volume.grpc_address, &txn).await {
+                            Err(crate::Error::NotFound(_)) => {
+                                holds_value(&volume.grpc_address, &txn).await
+                            }
+                            result => result.map(|()| true),
+                        },
+                        None => Err(crate::Error::NotFound(volume_id.clone())),
+                    };
+                    match result {
+                        Ok(true) => {}
+                        Ok(false) => {
+                            tracing::warn!(
+                                "Recovery: {} lost upload {}, leaving it out",
+                                volume_id,
+                                txn.upload_id
+                            );
+                            lost.push(volume_id.clone());
+                        }
+                        Err(e) => {
+                            tracing::warn!(
+                                "Recovery: commit of {} on {} failed: {}",
+                                txn.upload_id,
+                                volume_id,
+                                e
+                            );
+                            all_committed = false;
+                        }
+                    }
+                }
+                txn.replicas.retain(|id| !lost.contains(id));
+                if !all_committed {
+                    report.pending += 1;
+                } else if txn.replicas.is_empty() {
+                    // No replica holds the value: the write never happened
+                    forget_txn(raft, &txn.upload_id).await?;
+                    report.aborted += 1;
+                } else {
+                    publish_key(metadata, raft, &txn, None).await?;
+                    forget_txn(raft, &txn.upload_id).await?;
+                    report.committed += 1;
+                }
+            }
+            TxnState::Preparing | TxnState::Aborting => {
+                for volume_id in &txn.replicas {
+                    if let Some(volume) = metadata.get_volume(volume_id)? {
+                        // Unreachable replicas are cleaned up by the orphan scan below
+                        let _ = abort_on(&volume.grpc_address, &txn.upload_id).await;
+                    }
+                }
+                forget_txn(raft, &txn.upload_id).await?;
+                report.aborted += 1;
+            }
+        }
+    }
+
+    // Abort anything a volume prepared that no transaction record accounts for
+    for volume in metadata.list_volumes()? {
+        let mut client = match VolumeClient::connect(volume.grpc_address.clone()).await {
+            Ok(client) => client,
+            Err(e) => {
+                tracing::warn!("Recovery: cannot reach {}: {}", volume.volume_id, e);
+                continue;
+            }
+        };
+        let uploads = match client.list_prepared().await {
+            Ok(uploads) => uploads,
+            Err(e) => {
+                tracing::warn!(
+                    "Recovery: list_prepared on {} failed: {}",
+                    volume.volume_id,
+                    e
+                );
+                continue;
+            }
+        };
+        for upload in uploads {
+            if metadata.get_txn(&upload.upload_id)?.is_none()
+                && client.abort(upload.upload_id).await.is_ok()
+            {
+                report.orphans_aborted += 1;
+            }
+        }
+    }
+
+    tracing::info!(
+        "2PC recovery: {} committed, {} aborted, {} pending, {} orphans aborted",
+        report.committed,
+        report.aborted,
+        report.pending,
+        report.orphans_aborted
+    );
+    Ok(report)
+}
+
+/// Run transaction recovery every time this node becomes leader.
+pub fn start_recovery_task(
+    metadata: Arc<MetadataStore>,
+    raft: Arc<RaftNode>,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut was_leader = false;
+        loop {
+            tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+            let is_leader = raft.is_leader();
+            if is_leader && !was_leader {
+                if let Err(e) = recover_transactions(&metadata, &raft).await {
+                    tracing::error!("2PC recovery failed: {}", e);
+                }
+            }
+            was_leader = is_leader;
+        }
+    })
+}
+
+async fn prepare_on(volume: &VolumeMetadata, txn: &TxnRecord, data: Vec<u8>) -> Result<()> {
+    let mut client = VolumeClient::connect(volume.grpc_address.clone()).await?;
+    let resp = client
+        .prepare(
+            txn.key.clone(),
+            txn.upload_id.clone(),
+            txn.size,
+            txn.blake3.clone(),
+            data,
+        )
+        .await
+        .map_err(|e| match e {
+            crate::Error::Grpc(status) if status.code() == tonic::Code::ResourceExhausted => {
+                crate::Error::StorageFull(status.message().to_string())
+            }
+            e => e,
+        })?;
+    if resp.ok {
+        Ok(())
+    } else {
+        Err(crate::Error::PrepareFailed {
+            node: volume.volume_id.clone(),
+            reason: resp.error,
+        })
+    }
+}
+
+/// Take a volume that refused a write for lack of space out of placement
+/// right away; its heartbeats bring it back once it has room again
+async fn exclude_full_volume(metadata: &MetadataStore, raft: &RaftNode, volume_id: &str) {
+    let volume = match metadata.get_volume(volume_id) {
+        Ok(Some(volume)) if volume.state == NodeState::Alive => volume,
+        Ok(_) => return,
+        Err(e) => {
+            tracing::warn!("Cannot mark {} read-only: {}", volume_id, e);
+            return;
+        }
+    };
+    let volume = VolumeMetadata {
+        state: NodeState::ReadOnly,
+        ..volume
+    };
+    match raft
+        .propose(&MetadataCommand::PutVolume(volume.clone()))
+        .await
+    {
+        Ok(()) => tracing::warn!("Volume {} is full, now {}", volume_id, volume.state),
+        Err(e) => tracing::warn!("Cannot mark {} read-only: {}", volume_id, e),
+    }
+}
+
+async fn commit_on(grpc_address: &str, txn: &TxnRecord) -> Result<()> {
+    let mut client = VolumeClient::connect(grpc_address.to_string()).await?;
+    let resp = client
+        .commit(txn.upload_id.clone(), txn.key.clone())
+        .await?;
+    if resp.ok {
+        Ok(())
+    } else {
+        Err(crate::Error::Internal(resp.error))
+    }
+}
+
+/// Whether the volume at `grpc_address` holds the value `txn` wrote, for a
+/// replica that no longer has the upload staged
+async fn holds_value(grpc_address: &str, txn: &TxnRecord) -> Result<bool> {
+    let mut client = VolumeClient::connect(grpc_address.to_string()).await?;
+    let stats = client.stat(vec![txn.key.clone()]).await?;
+    Ok(stats
+        .first()
+        .is_some_and(|stat| stat.exists && stat.blake3 == txn.blake3))
+}
+
+async fn abort_on(grpc_address: &str, upload_id: &str) -> Result<()> {
+    let mut client = VolumeClient::connect(grpc_address.to_string()).await?;
+    client.abort(upload_id.to_string()).await?;
+    Ok(())
+}
+
+/// Write a copy of an already committed key straight to one volume.
+/// Used to (re)create replicas; no transaction record is kept since a crash
+/// just leaves the copy for the next repair pass.
+pub(crate) async fn write_replica(
+    target: &VolumeMetadata,
+    key: &str,
+    blake3: &str,
+    data: Vec<u8>,
+) -> Result<()> {
+    let upload_id = generate_upload_id();
+    let mut client = VolumeClient::connect(target.grpc_address.clone()).await?;
+    let prepared = client
+        .prepare(
+            key.to_string(),
+            upload_id.clone(),
+            data.len() as u64,
+            blake3.to_string(),
+            data,
+        )
+        .await?;
+    if !prepared.ok {
+        return Err(crate::Error::PrepareFailed {
+            node: target.volume_id.clone(),
+            reason: prepared.error,
+        });
+    }
+    let committed = client.commit(upload_id, key.to_string()).await?;
+    if !committed.ok {
+        return Err(crate::Error::CommitFailed {
+            node: target.volume_id.clone(),
+            reason: committed.error,
+        });
+    }
+    Ok(())
+}
+
+/// Publish the committed key metadata.
+/// Without `tags` (recovery, which doesn't know them) the key keeps its current tags.
+async fn publish_key(
+    metadata: &MetadataStore,
+    raft: &RaftNode,
+    txn: &TxnRecord,
+    tags: Option<BTreeMap<String, String>>,
+) -> Result<()> {
+    let now = timestamp_now();
+    let existing = metadata.get_key(&txn.key)?;
+    let created_at = existing.as_ref().map(|m| m.created_at).unwrap_or(now);
+    let tags = tags
+        .or_else(|| existing.map(|m| m.tags))
+        .unwrap_or_default();
+    raft.propose(&MetadataCommand::PutKey(KeyMetadata {
+        key: txn.key.clone(),
+        replicas: txn.replicas.clone(),
+        size: txn.size,
+        blake3: txn.blake3.clone(),
+        created_at,
+        updated_at: now,
+        state: KeyState::Active,
+        tags,
+        blob: None,
+        chunks: None,
+    }))
+    .await
+}
+
+/// Record or update a transaction through Raft
+async fn save_txn(raft: &RaftNode, txn: &TxnRecord) -> Result<()> {
+    raft.propose(&MetadataCommand::PutTxn(txn.clone())).await
+}
+
+/// Forget a finished transaction through Raft
+async fn forget_txn(raft: &RaftNode, upload_id: &str) -> Result<()> {
+    raft.propose(&MetadataCommand::DeleteTxn(upload_id.to_string()))
+        .await
+}
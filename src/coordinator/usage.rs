@@ -0,0 +1,253 @@
+//! Tenant usage accounting
+//!
+//! `QUOTA_MANAGER` counts the bytes and keys of each tenant as writes and
+//! deletes go through this coordinator. Those counters only live in memory,
+//! so they are rebuilt from the key metadata at startup and reconciled with
+//! it periodically, which also corrects any drift (writes served while
+//! another node led, batches, failed requests...).
+
+use crate::common::{QuotaConfig, Result, TenantQuota, QUOTA_MANAGER};
+use crate::coordinator::metadata::{KeyState, MetadataStore, DEFAULT_PAGE_SIZE};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How `minikv du` groups keys
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UsageGrouping {
+    #[default]
+    Tenant,
+    /// By the tenant and the first levels of the key, `/` separating them
+    Prefix,
+}
+
+impl UsageGrouping {
+    /// Levels of the stored key (`tenant/key`) making a group, for `depth`
+    /// levels of the key under the tenant
+    pub fn levels(self, depth: usize) -> usize {
+        match self {
+            UsageGrouping::Tenant => 1,
+            UsageGrouping::Prefix => 1 + depth,
+        }
+    }
+}
+
+impl FromStr for UsageGrouping {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "tenant" => Ok(UsageGrouping::Tenant),
+            "prefix" => Ok(UsageGrouping::Prefix),
+            other => Err(format!("unknown grouping {} (tenant or prefix)", other)),
+        }
+    }
+}
+
+/// Bytes and keys stored under a prefix
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PrefixUsage {
+    pub prefix: String,
+    pub bytes: u64,
+    pub keys: u64,
+}
+
+/// Group of `key`: its first `levels` levels, or all but the last when it
+/// has fewer, with a trailing `/`. `None` outside any tenant namespace.
+fn usage_group(key: &str, levels: usize) -> Option<&str> {
+    let end = key
+        .match_indices('/')
+        .take(levels.max(1))
+        .last()
+        .map(|(i, _)| i + 1)?;
+    Some(&key[..end])
+}
+
+/// Usage of the keys under `prefix`, grouped by their first `levels`
+/// levels (see `usage_group`), from the key metadata, largest first. Keys
+/// outside any tenant namespace (internal or written before namespacing)
+/// aren't counted.
+pub fn prefix_usage(
+    metadata: &MetadataStore,
+    prefix: &str,
+    levels: usize,
+) -> Result<Vec<PrefixUsage>> {
+    let mut usage: BTreeMap<String, (u64, u64)> = BTreeMap::new();
+    let mut after: Option<String> = None;
+    loop {
+        let page = metadata.list_keys_paginated(prefix, after.as_deref(), DEFAULT_PAGE_SIZE)?;
+        for meta in &page {
+            if meta.state == KeyState::Tombstone || meta.key.starts_with('.') {
+                continue;
+            }
+            if let Some(group) = usage_group(&meta.key, levels) {
+                let entry = usage.entry(group.to_string()).or_default();
+                entry.0 += meta.size;
+                entry.1 += 1;
+            }
+        }
+        if page.len() < DEFAULT_PAGE_SIZE {
+            break;
+        }
+        after = page.last().map(|meta| meta.key.clone());
+    }
+    let mut usage: Vec<_> = usage
+        .into_iter()
+        .map(|(prefix, (bytes, keys))| PrefixUsage {
+            prefix,
+            bytes,
+            keys,
+        })
+        .collect();
+    usage.sort_by(|a, b| b.bytes.cmp(&a.bytes));
+    Ok(usage)
+}
+
+/// `(bytes, keys)` stored by each tenant, from the key metadata
+pub fn tenant_usage(metadata: &MetadataStore) -> Result<HashMap<String, (u64, u64)>> {
+    Ok(prefix_usage(metadata, "", 1)?
+        .into_iter()
+        .map(|usage| {
+            let tenant = usage.prefix.trim_end_matches('/').to_string();
+            (tenant, (usage.bytes, usage.keys))
+        })
+        .collect())
+}
+
+/// Replace the usage counters of `QUOTA_MANAGER` with `tenant_usage`
+pub fn reconcile(metadata: &MetadataStore) -> Result<usize> {
+    let usage = tenant_usage(metadata)?;
+    QUOTA_MANAGER.reconcile_usage(&usage);
+    Ok(usage.len())
+}
+
+/// Apply the limits of tenants without a quota of their own
+pub fn set_default_quota(config: &QuotaConfig) {
+    let mut default_quota = TenantQuota::with_limits(
+        "__default__".to_string(),
+        config.storage_limit,
+        config.object_limit,
+        config.rate_limit,
+    );
+    default_quota.ingress_limit = config.ingress_limit;
+    default_quota.egress_limit = config.egress_limit;
+    QUOTA_MANAGER.set_default_quota(default_quota);
+}
+
+/// Apply the default quota of `config`, then reconcile tenant usage now and
+/// every `reconcile_interval_secs` (0 = only now)
+pub fn start_usage_task(
+    metadata: Arc<MetadataStore>,
+    config: QuotaConfig,
+) -> tokio::task::JoinHandle<()> {
+    set_default_quota(&config);
+    tokio::spawn(async move {
+        let interval_secs = config.reconcile_interval_secs;
+        let mut ticker = tokio::time::interval(Duration::from_secs(interval_secs.max(1)));
+        loop {
+            ticker.tick().await;
+            let metadata = metadata.clone();
+            match tokio::task::spawn_blocking(move || reconcile(&metadata)).await {
+                Ok(Ok(tenants)) => tracing::debug!("Reconciled usage of {} tenants", tenants),
+                Ok(Err(e)) => tracing::warn!("Tenant usage reconciliation failed: {}", e),
+                Err(e) => tracing::warn!("Tenant usage reconciliation panicked: {}", e),
+            }
+            if interval_secs == 0 {
+                return;
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coordinator::metadata::KeyMetadata;
+
+    fn key(key: &str, size: u64, state: KeyState) -> KeyMetadata {
+        KeyMetadata {
+            key: key.to_string(),
+            replicas: vec![],
+            size,
+            blake3: String::new(),
+            created_at: 0,
+            updated_at: 0,
+            state,
+            tags: Default::default(),
+            blob: None,
+            chunks: None,
+        }
+    }
+
+    #[test]
+    fn test_tenant_usage() {
+        let dir = tempfile::tempdir().unwrap();
+        let metadata = MetadataStore::open(dir.path().join("test.db")).unwrap();
+        for meta in [
+            key("acme/a", 10, KeyState::Active),
+            key("acme/b/c", 5, KeyState::Tiered),
+            key("acme/gone", 100, KeyState::Tombstone),
+            key("globex/a", 7, KeyState::Active),
+            key(".cas/acme/0123", 10, KeyState::Active),
+            key("legacy", 1, KeyState::Active),
+        ] {
+            metadata.put_key(&meta).unwrap();
+        }
+
+        let usage = tenant_usage(&metadata).unwrap();
+        assert_eq!(usage.len(), 2);
+        assert_eq!(usage["acme"], (15, 2));
+        assert_eq!(usage["globex"], (7, 1));
+    }
+
+    #[test]
+    fn test_usage_group() {
+        assert_eq!(usage_group("acme/a", 1), Some("acme/"));
+        assert_eq!(
+            usage_group("acme/photos/2024/a.jpg", 2),
+            Some("acme/photos/")
+        );
+        assert_eq!(
+            usage_group("acme/photos/2024/a.jpg", 9),
+            Some("acme/photos/2024/")
+        );
+        // Keys right under the tenant stay in its group
+        assert_eq!(usage_group("acme/a", 3), Some("acme/"));
+        assert_eq!(usage_group("legacy", 1), None);
+    }
+
+    #[test]
+    fn test_prefix_usage() {
+        let dir = tempfile::tempdir().unwrap();
+        let metadata = MetadataStore::open(dir.path().join("test.db")).unwrap();
+        for meta in [
+            key("acme/a", 10, KeyState::Active),
+            key("acme/logs/1", 20, KeyState::Active),
+            key("acme/logs/2", 30, KeyState::Active),
+            key("acme/logs/3", 40, KeyState::Tombstone),
+            key("globex/logs/1", 7, KeyState::Active),
+        ] {
+            metadata.put_key(&meta).unwrap();
+        }
+
+        let usage = prefix_usage(&metadata, "acme/", 2).unwrap();
+        assert_eq!(
+            usage,
+            vec![
+                PrefixUsage {
+                    prefix: "acme/logs/".to_string(),
+                    bytes: 50,
+                    keys: 2
+                },
+                PrefixUsage {
+                    prefix: "acme/".to_string(),
+                    bytes: 10,
+                    keys: 1
+                },
+            ]
+        );
+    }
+}
@@ -1,15 +1,23 @@
+use crate::common::{connect_internal, InternalChannel, Result};
 use crate::proto::volume_internal_client::VolumeInternalClient;
 use crate::proto::*;
-use tonic::transport::Channel;
 
+/// Values are streamed to Prepare in chunks of this size
+const PREPARE_CHUNK_SIZE: usize = 1024 * 1024;
+
+#[derive(Clone)]
 pub struct VolumeClient {
-    client: VolumeInternalClient<Channel>,
+    client: VolumeInternalClient<InternalChannel>,
 }
 
 impl VolumeClient {
-    pub async fn connect(addr: String) -> Result<Self, Box<dyn std::error::Error>> {
-        let client = VolumeInternalClient::connect(addr).await?;
-        Ok(Self { client })
+    pub async fn connect(addr: String) -> Result<Self> {
+        let channel = connect_internal(&addr)
+            .await
+            .map_err(|e| crate::Error::ConnectionFailed(format!("{}: {}", addr, e)))?;
+        Ok(Self {
+            client: VolumeInternalClient::new(channel),
+        })
     }
 
     pub async fn prepare(
@@ -18,36 +26,175 @@ impl VolumeClient {
         upload_id: String,
         expected_size: u64,
         expected_blake3: String,
-    ) -> Result<PrepareResponse, Box<dyn std::error::Error>> {
-        let request = tonic::Request::new(PrepareRequest {
+        data: Vec<u8>,
+    ) -> Result<PrepareResponse> {
+        // An empty value still needs the first message
+        let mut chunks = data.chunks(PREPARE_CHUNK_SIZE).map(<[u8]>::to_vec);
+        let first = PrepareRequest {
             key,
             upload_id,
             expected_size,
             expected_blake3,
-        });
+            data: chunks.next().unwrap_or_default(),
+        };
+        let messages: Vec<PrepareRequest> = std::iter::once(first)
+            .chain(chunks.map(|data| PrepareRequest {
+                data,
+                ..Default::default()
+            }))
+            .collect();
 
-        let response = self.client.prepare(request).await?;
+        let response = self.client.prepare(tokio_stream::iter(messages)).await?;
         Ok(response.into_inner())
     }
 
-    pub async fn commit(
-        &mut self,
-        upload_id: String,
-        key: String,
-    ) -> Result<CommitResponse, Box<dyn std::error::Error>> {
+    /// Commit a prepared upload. Fails with `Error::NotFound` if the volume
+    /// has no such upload staged (never prepared, aborted, already committed
+    /// or lost in a restart).
+    pub async fn commit(&mut self, upload_id: String, key: String) -> Result<CommitResponse> {
         let request = tonic::Request::new(CommitRequest { upload_id, key });
 
-        let response = self.client.commit(request).await?;
+        let response = self.client.commit(request).await.map_err(|status| {
+            if status.code() == tonic::Code::NotFound {
+                crate::Error::NotFound(status.message().to_string())
+            } else {
+                status.into()
+            }
+        })?;
         Ok(response.into_inner())
     }
 
-    pub async fn abort(
-        &mut self,
-        upload_id: String,
-    ) -> Result<AbortResponse, Box<dyn std::error::Error>> {
+    pub async fn abort(&mut self, upload_id: String) -> Result<AbortResponse> {
         let request = tonic::Request::new(AbortRequest { upload_id });
 
         let response = self.client.abort(request).await?;
         Ok(response.into_inner())
     }
+
+    /// Open the Pull stream for a blob.
+    /// Returns `None` if the volume does not hold the key.
+    pub async fn pull(&mut self, key: String) -> Result<Option<tonic::Streaming<Chunk>>> {
+        self.pull_range(key, 0, 0).await
+    }
+
+    /// Open the Pull stream for `length` bytes of a blob from `offset`
+    /// (all of it if `length` is 0).
+    /// Returns `None` if the volume does not hold the key.
+    pub async fn pull_range(
+        &mut self,
+        key: String,
+        offset: u64,
+        length: u64,
+    ) -> Result<Option<tonic::Streaming<Chunk>>> {
+        let request = tonic::Request::new(PullRequest {
+            key,
+            source_url: String::new(),
+            offset,
+            length,
+        });
+
+        match self.client.pull(request).await {
+            Ok(response) => Ok(Some(response.into_inner())),
+            Err(status) if status.code() == tonic::Code::NotFound => Ok(None),
+            Err(status) => Err(status.into()),
+        }
+    }
+
+    /// Read a blob from the volume via the Pull stream.
+    /// Returns `None` if the volume does not hold the key.
+    pub async fn read(&mut self, key: String) -> Result<Option<Vec<u8>>> {
+        self.read_range(key, 0, 0).await
+    }
+
+    /// Read `length` bytes of a blob from `offset` via the Pull stream.
+    /// Returns `None` if the volume does not hold the key.
+    pub async fn read_range(
+        &mut self,
+        key: String,
+        offset: u64,
+        length: u64,
+    ) -> Result<Option<Vec<u8>>> {
+        let Some(mut stream) = self.pull_range(key, offset, length).await? else {
+            return Ok(None);
+        };
+
+        let mut data = Vec::new();
+        while let Some(chunk) = stream.message().await? {
+            data.extend_from_slice(&chunk.data);
+        }
+        Ok(Some(data))
+    }
+
+    /// List uploads the volume has prepared but not yet committed or aborted
+    pub async fn list_prepared(&mut self) -> Result<Vec<PreparedUpload>> {
+        let request = tonic::Request::new(ListPreparedRequest {});
+
+        let response = self.client.list_prepared(request).await?;
+        Ok(response.into_inner().uploads)
+    }
+
+    pub async fn delete(&mut self, key: String) -> Result<DeleteResponse> {
+        let request = tonic::Request::new(DeleteRequest { key });
+
+        let response = self.client.delete(request).await?;
+        Ok(response.into_inner())
+    }
+
+    /// Merkle root of every shard, indexed by shard
+    pub async fn merkle_roots(&mut self, num_shards: u64) -> Result<Vec<Vec<u8>>> {
+        let request = tonic::Request::new(MerkleRootsRequest { num_shards });
+
+        let response = self.client.merkle_roots(request).await?;
+        Ok(response.into_inner().roots)
+    }
+
+    /// Leaf digests of one shard's Merkle tree
+    pub async fn merkle_leaves(&mut self, num_shards: u64, shard: u64) -> Result<Vec<Vec<u8>>> {
+        let request = tonic::Request::new(MerkleLeavesRequest { num_shards, shard });
+
+        let response = self.client.merkle_leaves(request).await?;
+        Ok(response.into_inner().leaves)
+    }
+
+    /// `(key, blake3)` pairs stored in one leaf bucket of a shard
+    pub async fn list_bucket(
+        &mut self,
+        num_shards: u64,
+        shard: u64,
+        bucket: u32,
+    ) -> Result<Vec<KeyHash>> {
+        let request = tonic::Request::new(ListBucketRequest {
+            num_shards,
+            shard,
+            bucket,
+        });
+
+        let response = self.client.list_bucket(request).await?;
+        Ok(response.into_inner().entries)
+    }
+
+    /// A page of the blobs stored on the volume, in key order after `start_after`
+    pub async fn list_blobs(&mut self, start_after: String, limit: u32) -> Result<Vec<BlobInfo>> {
+        let request = tonic::Request::new(ListBlobsRequest { start_after, limit });
+
+        let response = self.client.list_blobs(request).await?;
+        Ok(response.into_inner().blobs)
+    }
+
+    /// Delete the blobs that still hold the given hashes
+    pub async fn delete_blobs(&mut self, blobs: Vec<KeyHash>) -> Result<DeleteBlobsResponse> {
+        let request = tonic::Request::new(DeleteBlobsRequest { blobs });
+
+        let response = self.client.delete_blobs(request).await?;
+        Ok(response.into_inner())
+    }
+
+    /// Whether the volume holds each of `keys`, with its size and blake3,
+    /// in the order asked
+    pub async fn stat(&mut self, keys: Vec<String>) -> Result<Vec<BlobStat>> {
+        let request = tonic::Request::new(StatRequest { keys });
+
+        let response = self.client.stat(request).await?;
+        Ok(response.into_inner().blobs)
+    }
 }
@@ -0,0 +1,520 @@
+//! Webhook notifications
+//!
+//! Coordinators POST events to the endpoints of `[coordinator.webhooks]`,
+//! one JSON object per request, for the event types each one selects: the
+//! cluster events of `events`, and
+//! - `key.put`, `key.delete`: key changes, read by the leader from the CDC
+//!   log (see `cdc`)
+//! - `quota.exceeded`: a write refused by a tenant quota, at most once a
+//!   minute per tenant
+//!
+//! Each endpoint has its own queue, delivered in order, and failed attempts
+//! are retried with exponential backoff. Events still undelivered after
+//! `max_retries`, or arriving while the queue is full, go to the dead-letter
+//! log. Events are not replayed when leadership changes: the CDC sinks are
+//! the at-least-once path for key changes.
+
+use crate::common::{Error, Result, WebhookConfig, WebhooksConfig, METRICS};
+use crate::coordinator::cdc::{CdcEvent, CdcOp};
+use crate::coordinator::events::ClusterEvent;
+use crate::coordinator::metadata::MetadataStore;
+use crate::coordinator::raft_node::RaftNode;
+use hmac::{Hmac, Mac};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+
+/// Events held per endpoint while it is slow or down
+const QUEUE_SIZE: usize = 1000;
+
+/// Longest wait between two delivery attempts
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Shortest interval between two `quota.exceeded` events of a tenant
+const QUOTA_EVENT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How often the leader looks for new key changes
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Key changes read from the CDC log at once
+const BATCH_SIZE: usize = 500;
+
+/// Header carrying the HMAC-SHA256 of the body
+pub const SIGNATURE_HEADER: &str = "x-minikv-signature";
+
+/// Global webhook dispatcher
+pub static WEBHOOKS: Lazy<Webhooks> = Lazy::new(Webhooks::default);
+
+/// The event for a change of the CDC log
+fn key_event(event: &CdcEvent) -> ClusterEvent {
+    let (tenant, key) = event.key.split_once('/').unwrap_or(("", &event.key));
+    let (tenant, key) = (tenant.to_string(), key.to_string());
+    match event.op {
+        CdcOp::Put => ClusterEvent::KeyPut {
+            tenant,
+            key,
+            size: event.size.unwrap_or(0),
+            seq: event.seq,
+        },
+        CdcOp::Delete => ClusterEvent::KeyDelete {
+            tenant,
+            key,
+            seq: event.seq,
+        },
+    }
+}
+
+/// Does `filter` (`key.put`, `key.*` or `*`) select events of type `kind`?
+pub fn selects(filter: &str, kind: &str) -> bool {
+    match filter.strip_suffix(".*") {
+        Some(family) => kind.split_once('.').map(|(f, _)| f) == Some(family),
+        None => filter == "*" || filter == kind,
+    }
+}
+
+/// The body POSTed for an event
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Notification {
+    /// Unique per event, for receivers to drop redeliveries
+    pub id: String,
+    /// Unix time (seconds) of the event
+    pub timestamp: i64,
+    #[serde(flatten)]
+    pub event: ClusterEvent,
+}
+
+/// `sha256=<hex>` HMAC of `body` keyed with `secret`
+pub fn signature(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// A configured endpoint and its queue
+struct Endpoint {
+    config: WebhookConfig,
+    tx: mpsc::Sender<Notification>,
+    dead_letters: Arc<DeadLetters>,
+}
+
+impl Endpoint {
+    fn wants(&self, kind: &str) -> bool {
+        self.config
+            .events
+            .iter()
+            .any(|filter| selects(filter, kind))
+    }
+}
+
+/// Webhook endpoints and their delivery tasks
+#[derive(Default)]
+pub struct Webhooks {
+    endpoints: RwLock<Vec<Endpoint>>,
+    /// When each tenant last had a `quota.exceeded` event
+    quota_events: Mutex<HashMap<String, Instant>>,
+}
+
+impl Webhooks {
+    /// Start delivering to the endpoints of `config` on the current Tokio
+    /// runtime. Endpoints started before deliver what they hold and stop.
+    pub fn start(&self, config: &WebhooksConfig) -> Result<()> {
+        let handle = tokio::runtime::Handle::try_current()
+            .map_err(|_| Error::InvalidConfig("webhooks need a Tokio runtime".to_string()))?;
+        let dead_letters = Arc::new(DeadLetters {
+            path: config.dead_letter_path.clone(),
+            lock: Mutex::new(()),
+        });
+        let mut endpoints = Vec::new();
+        for hook in &config.hooks {
+            let client = reqwest::Client::builder()
+                .timeout(Duration::from_millis(hook.timeout_ms))
+                .build()
+                .map_err(|e| Error::InvalidConfig(format!("webhook {}: {}", hook.url, e)))?;
+            let (tx, rx) = mpsc::channel(QUEUE_SIZE);
+            handle.spawn(deliver_all(hook.clone(), client, rx, dead_letters.clone()));
+            endpoints.push(Endpoint {
+                config: hook.clone(),
+                tx,
+                dead_letters: dead_letters.clone(),
+            });
+        }
+        *self.endpoints.write().unwrap() = endpoints;
+        Ok(())
+    }
+
+    /// Does an endpoint take events of type `kind`?
+    pub fn wants(&self, kind: &str) -> bool {
+        let endpoints = self.endpoints.read().unwrap();
+        endpoints.iter().any(|endpoint| endpoint.wants(kind))
+    }
+
+    /// Queue `event` for the endpoints selecting it
+    pub fn notify(&self, event: ClusterEvent) {
+        let kind = event.kind();
+        let endpoints = self.endpoints.read().unwrap();
+        let mut selected = endpoints
+            .iter()
+            .filter(|endpoint| endpoint.wants(kind))
+            .peekable();
+        if selected.peek().is_none() {
+            return;
+        }
+        let notification = Notification {
+            id: uuid::Uuid::new_v4().to_string(),
+            timestamp: chrono::Utc::now().timestamp(),
+            event,
+        };
+        for endpoint in selected {
+            if let Err(e) = endpoint.tx.try_send(notification.clone()) {
+                let error = match e {
+                    mpsc::error::TrySendError::Full(_) => "queue full",
+                    mpsc::error::TrySendError::Closed(_) => "endpoint stopped",
+                };
+                endpoint
+                    .dead_letters
+                    .record(&endpoint.config.url, &notification, 0, error);
+            }
+        }
+    }
+
+    /// `quota.exceeded` for `tenant`, unless it had one in the last minute
+    pub fn notify_quota(&self, tenant: &str, message: String) {
+        if !self.wants("quota.exceeded") {
+            return;
+        }
+        {
+            let mut last = self.quota_events.lock().unwrap();
+            let now = Instant::now();
+            if last
+                .get(tenant)
+                .is_some_and(|at| now.duration_since(*at) < QUOTA_EVENT_INTERVAL)
+            {
+                return;
+            }
+            last.insert(tenant.to_string(), now);
+        }
+        self.notify(ClusterEvent::QuotaExceeded {
+            tenant: tenant.to_string(),
+            message,
+        });
+    }
+}
+
+async fn deliver_all(
+    config: WebhookConfig,
+    client: reqwest::Client,
+    mut rx: mpsc::Receiver<Notification>,
+    dead_letters: Arc<DeadLetters>,
+) {
+    while let Some(notification) = rx.recv().await {
+        let body = serde_json::to_vec(&notification).unwrap_or_default();
+        let mut backoff = Duration::from_millis(100);
+        for attempt in 1.. {
+            match send(&client, &config, &notification, body.clone()).await {
+                Ok(()) => {
+                    METRICS.webhook_deliveries.inc();
+                    break;
+                }
+                Err(e) if attempt > config.max_retries => {
+                    dead_letters.record(&config.url, &notification, attempt, &e.to_string());
+                    break;
+                }
+                Err(e) => {
+                    tracing::warn!(
+                        "Webhook {} delivery of {} failed (attempt {}): {}",
+                        config.url,
+                        notification.id,
+                        attempt,
+                        e
+                    );
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                }
+            }
+        }
+    }
+}
+
+async fn send(
+    client: &reqwest::Client,
+    config: &WebhookConfig,
+    notification: &Notification,
+    body: Vec<u8>,
+) -> Result<()> {
+    let mut request = client
+        .post(&config.url)
+        .header("content-type", "application/json")
+        .header("x-minikv-event", notification.event.kind())
+        .header("x-minikv-delivery", &notification.id);
+    if let Some(secret) = &config.secret {
+        request = request.header(SIGNATURE_HEADER, signature(secret, &body));
+    }
+    let resp = request
+        .body(body)
+        .send()
+        .await
+        .map_err(|e| Error::ConnectionFailed(format!("{}: {}", config.url, e)))?;
+    if !resp.status().is_success() {
+        return Err(Error::Http(format!(
+            "{} answered {}",
+            config.url,
+            resp.status()
+        )));
+    }
+    Ok(())
+}
+
+/// Log of the events given up on
+struct DeadLetters {
+    path: Option<PathBuf>,
+    /// Keeps lines of concurrent endpoints whole
+    lock: Mutex<()>,
+}
+
+/// A line of the dead-letter log
+#[derive(Serialize)]
+struct DeadLetter<'a> {
+    webhook: &'a str,
+    attempts: u32,
+    error: &'a str,
+    notification: &'a Notification,
+}
+
+impl DeadLetters {
+    fn record(&self, webhook: &str, notification: &Notification, attempts: u32, error: &str) {
+        METRICS.webhook_dead_letters.inc();
+        tracing::error!(
+            "Webhook {} gave up on {} event {}: {}",
+            webhook,
+            notification.event.kind(),
+            notification.id,
+            error
+        );
+        let Some(path) = &self.path else {
+            return;
+        };
+        let mut line = serde_json::to_vec(&DeadLetter {
+            webhook,
+            attempts,
+            error,
+            notification,
+        })
+        .unwrap_or_default();
+        line.push(b'\n');
+        let _guard = self.lock.lock().unwrap();
+        let written = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .and_then(|mut file| file.write_all(&line));
+        if let Err(e) = written {
+            tracing::error!(
+                "Writing to dead-letter log {} failed: {}",
+                path.display(),
+                e
+            );
+        }
+    }
+}
+
+/// Background task: on the leader, turns the key changes of the CDC log
+/// into `key.put` and `key.delete` events
+pub fn start_webhook_task(
+    metadata: Arc<MetadataStore>,
+    raft: Arc<RaftNode>,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        // Last change notified; `None` until this node leads
+        let mut cursor = None;
+        loop {
+            tokio::time::sleep(POLL_INTERVAL).await;
+            if !raft.is_leader() || !(WEBHOOKS.wants("key.put") || WEBHOOKS.wants("key.delete")) {
+                cursor = None;
+                continue;
+            }
+            if let Err(e) = notify_key_changes(&metadata, &mut cursor) {
+                tracing::warn!("Reading key changes for webhooks failed: {}", e);
+            }
+        }
+    })
+}
+
+/// Notify the changes after `cursor`, or only start from the last one if
+/// there is no cursor yet
+fn notify_key_changes(metadata: &MetadataStore, cursor: &mut Option<u64>) -> Result<()> {
+    let Some(mut from) = *cursor else {
+        *cursor = Some(metadata.last_cdc_seq()?);
+        return Ok(());
+    };
+    loop {
+        let events = metadata.cdc_events(from + 1, BATCH_SIZE)?;
+        for event in &events {
+            WEBHOOKS.notify(key_event(event));
+            from = event.seq;
+        }
+        *cursor = Some(from);
+        if events.len() < BATCH_SIZE {
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::NodeState;
+    use axum::{extract::State, http::HeaderMap, http::StatusCode, routing::post, Router};
+
+    /// Serve `/hook`, answering `status` to the first `failures` requests
+    /// and recording the others
+    #[derive(Clone, Default)]
+    struct Receiver {
+        failures: Arc<Mutex<u32>>,
+        received: Arc<Mutex<Vec<(HeaderMap, Vec<u8>)>>>,
+    }
+
+    async fn receive(
+        State(r): State<Receiver>,
+        headers: HeaderMap,
+        body: axum::body::Bytes,
+    ) -> StatusCode {
+        {
+            let mut failures = r.failures.lock().unwrap();
+            if *failures > 0 {
+                *failures -= 1;
+                return StatusCode::SERVICE_UNAVAILABLE;
+            }
+        }
+        r.received.lock().unwrap().push((headers, body.to_vec()));
+        StatusCode::OK
+    }
+
+    async fn serve(receiver: Receiver) -> String {
+        let app = Router::new()
+            .route("/hook", post(receive))
+            .with_state(receiver);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        url
+    }
+
+    fn hook(url: &str, events: &[&str], max_retries: u32) -> WebhookConfig {
+        WebhookConfig {
+            url: url.to_string(),
+            events: events.iter().map(|e| e.to_string()).collect(),
+            secret: Some("s3cret".to_string()),
+            max_retries,
+            timeout_ms: 1000,
+        }
+    }
+
+    fn volume_event() -> ClusterEvent {
+        ClusterEvent::VolumeState {
+            volume_id: "vol-1".to_string(),
+            previous: NodeState::Alive,
+            state: NodeState::Suspect,
+        }
+    }
+
+    #[test]
+    fn test_filters() {
+        assert!(selects("*", "key.put"));
+        assert!(selects("key.*", "key.delete"));
+        assert!(selects("volume.state", "volume.state"));
+        assert!(!selects("key.*", "keys.put"));
+        assert!(!selects("key.put", "key.delete"));
+
+        let notification = Notification {
+            id: "1".to_string(),
+            timestamp: 0,
+            event: volume_event(),
+        };
+        let json = serde_json::to_value(&notification).unwrap();
+        assert_eq!(json["type"], "volume.state");
+        assert_eq!(json["state"], "suspect");
+        assert_eq!(
+            serde_json::from_value::<Notification>(json).unwrap(),
+            notification
+        );
+    }
+
+    #[tokio::test]
+    async fn test_delivery_signed_with_retry() {
+        let receiver = Receiver::default();
+        *receiver.failures.lock().unwrap() = 1;
+        let url = serve(receiver.clone()).await;
+        let webhooks = Webhooks::default();
+        webhooks
+            .start(&WebhooksConfig {
+                dead_letter_path: None,
+                hooks: vec![hook(&url, &["volume.*"], 3)],
+            })
+            .unwrap();
+
+        // Not selected
+        webhooks.notify_quota("acme", "Storage quota exceeded".to_string());
+        webhooks.notify(volume_event());
+        for _ in 0..100 {
+            if !receiver.received.lock().unwrap().is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        let received = receiver.received.lock().unwrap().clone();
+        assert_eq!(received.len(), 1);
+        let (headers, body) = &received[0];
+        assert_eq!(headers["x-minikv-event"], "volume.state");
+        assert_eq!(
+            headers[SIGNATURE_HEADER],
+            signature("s3cret", body).as_str()
+        );
+        let notification: Notification = serde_json::from_slice(body).unwrap();
+        assert_eq!(notification.event, volume_event());
+        assert_eq!(headers["x-minikv-delivery"], notification.id.as_str());
+    }
+
+    #[tokio::test]
+    async fn test_dead_letters() {
+        let receiver = Receiver::default();
+        *receiver.failures.lock().unwrap() = u32::MAX;
+        let url = serve(receiver.clone()).await;
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dead-letters.ndjson");
+        let webhooks = Webhooks::default();
+        webhooks
+            .start(&WebhooksConfig {
+                dead_letter_path: Some(path.clone()),
+                hooks: vec![hook(&url, &["*"], 1)],
+            })
+            .unwrap();
+
+        webhooks.notify_quota("acme", "Storage quota exceeded".to_string());
+        // At most one a minute per tenant
+        webhooks.notify_quota("acme", "Storage quota exceeded".to_string());
+        for _ in 0..100 {
+            if std::fs::read_to_string(&path).is_ok_and(|log| log.ends_with('\n')) {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        let log = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<serde_json::Value> = log
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0]["webhook"], url);
+        assert_eq!(lines[0]["attempts"], 2);
+        assert_eq!(lines[0]["notification"]["type"], "quota.exceeded");
+        assert_eq!(lines[0]["notification"]["tenant"], "acme");
+        assert!(receiver.received.lock().unwrap().is_empty());
+    }
+}
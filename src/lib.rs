@@ -75,9 +75,11 @@
 //! minikv compact --shard 0
 //! ```
 
+pub mod client;
 pub mod common;
 pub mod coordinator;
 pub mod ops;
+pub mod sim;
 pub mod volume;
 
 // Re-export commonly used types
@@ -0,0 +1,107 @@
+//! API key administration
+//!
+//! Creates, lists and revokes API keys through the coordinator's
+//! `/admin/keys` endpoints. Once authentication is enabled these require an
+//! admin key, passed as `Authorization: ApiKey <key>`.
+
+use crate::common::Result;
+use serde::{Deserialize, Serialize};
+
+/// An API key as listed by `GET /admin/keys` (never its secret or hash)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiKeyInfo {
+    pub id: String,
+    pub name: String,
+    pub tenant: String,
+    pub role: String,
+    pub active: bool,
+    pub created_at: u64,
+    pub expires_at: Option<u64>,
+    pub last_used_at: Option<u64>,
+}
+
+/// Answer of `POST /admin/keys`: the only time the plaintext key is shown
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreatedApiKey {
+    pub id: String,
+    pub key: String,
+    pub tenant: String,
+    pub role: String,
+}
+
+#[derive(Deserialize)]
+struct KeyList {
+    keys: Vec<ApiKeyInfo>,
+}
+
+/// Creates a key for `tenant` with `role` ("admin", "read_write" or
+/// "read_only"), expiring after `expires_in_secs` if set
+pub async fn create_api_key(
+    coordinator_url: &str,
+    admin_key: Option<&str>,
+    name: &str,
+    tenant: &str,
+    role: &str,
+    expires_in_secs: Option<u64>,
+) -> Result<CreatedApiKey> {
+    let request = reqwest::Client::new()
+        .post(format!("{}/admin/keys", coordinator_url))
+        .json(&serde_json::json!({
+            "name": name,
+            "tenant": tenant,
+            "role": role,
+            "expires_in_secs": expires_in_secs,
+        }));
+    let body = send(request, admin_key).await?;
+    serde_json::from_str(&body).map_err(|e| crate::Error::Http(e.to_string()))
+}
+
+/// Lists every key, or the keys of `tenant`
+pub async fn list_api_keys(
+    coordinator_url: &str,
+    admin_key: Option<&str>,
+    tenant: Option<&str>,
+) -> Result<Vec<ApiKeyInfo>> {
+    let mut request = reqwest::Client::new().get(format!("{}/admin/keys", coordinator_url));
+    if let Some(tenant) = tenant {
+        request = request.query(&[("tenant", tenant)]);
+    }
+    let body = send(request, admin_key).await?;
+    serde_json::from_str::<KeyList>(&body)
+        .map(|list| list.keys)
+        .map_err(|e| crate::Error::Http(e.to_string()))
+}
+
+/// Revokes a key: it stays listed, but no longer authenticates
+pub async fn revoke_api_key(
+    coordinator_url: &str,
+    admin_key: Option<&str>,
+    key_id: &str,
+) -> Result<()> {
+    let request =
+        reqwest::Client::new().post(format!("{}/admin/keys/{}/revoke", coordinator_url, key_id));
+    send(request, admin_key).await.map(|_| ())
+}
+
+/// Sends `request` with the admin key, returning the body of a successful answer
+pub(crate) async fn send(
+    mut request: reqwest::RequestBuilder,
+    admin_key: Option<&str>,
+) -> Result<String> {
+    if let Some(key) = admin_key {
+        request = request.header("Authorization", format!("ApiKey {}", key));
+    }
+    let resp = request
+        .send()
+        .await
+        .map_err(|e| crate::Error::Http(e.to_string()))?;
+    let status = resp.status();
+    let body = resp
+        .text()
+        .await
+        .map_err(|e| crate::Error::Http(e.to_string()))?;
+    if !status.is_success() {
+        return Err(crate::Error::Http(format!("{}: {}", status, body)));
+    }
+    Ok(body)
+}
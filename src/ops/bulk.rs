@@ -0,0 +1,774 @@
+//! Bulk import and export
+//!
+//! `export_keys` streams the keys under a prefix, with their values, out of
+//! the cluster into a file, and `import_keys` streams such a file back in.
+//! Both go through the coordinator's key API or, with `direct`, straight to
+//! the volumes through its redirects. Up to `parallelism` values are in
+//! flight at once while entries keep their order in the file, and a
+//! transfer can be held to a number of keys and bytes per second. Answers
+//! of `503` or `429` (a saturated write budget or rate limit) are retried
+//! after their `Retry-After`.
+//!
+//! Two formats are supported:
+//! - `ndjson`: one `{"key": ..., "value": <base64>}` object per line,
+//! - `tar`: a ustar archive with one regular file per key, named after it
+//!   (in a PAX header when longer than 100 bytes).
+//!
+//! Progress is checkpointed beside the file (`<file>.checkpoint`), so an
+//! interrupted transfer started again on the same file picks up where it
+//! stopped. The checkpoint is removed once the transfer finishes.
+
+use crate::common::quota::ByteRate;
+use crate::common::utils::encode_key;
+use crate::common::Result;
+use crate::coordinator::metadata::{KeyState, DEFAULT_PAGE_SIZE};
+use crate::ops::keys::list_key_page;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use futures_util::stream::{self, FuturesOrdered, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::io::{
+    AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncSeekExt, AsyncWriteExt, BufReader, BufWriter,
+};
+
+/// Imported entries between two checkpoints
+const CHECKPOINT_INTERVAL: u64 = 1000;
+
+/// Tries of a request answered `503` or `429`
+const MAX_ATTEMPTS: u32 = 5;
+
+/// Most errors listed in a report
+const MAX_REPORTED_ERRORS: usize = 1000;
+
+const TAR_BLOCK: usize = 512;
+
+/// Format of an export file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BulkFormat {
+    Ndjson,
+    Tar,
+}
+
+impl BulkFormat {
+    /// Format of `path` going by its extension: `tar` for `.tar`, else `ndjson`
+    pub fn for_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("tar") => BulkFormat::Tar,
+            _ => BulkFormat::Ndjson,
+        }
+    }
+}
+
+impl FromStr for BulkFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "ndjson" => Ok(BulkFormat::Ndjson),
+            "tar" => Ok(BulkFormat::Tar),
+            other => Err(format!("unknown format {} (ndjson or tar)", other)),
+        }
+    }
+}
+
+/// How a bulk transfer moves values
+#[derive(Debug, Clone)]
+pub struct BulkOptions {
+    /// API key to authenticate with; the keys moved are its tenant's
+    pub api_key: Option<String>,
+    /// Values in flight at once
+    pub parallelism: usize,
+    /// Keys moved per second, 0 for no limit
+    pub keys_per_sec: u64,
+    /// Value bytes moved per second, 0 for no limit
+    pub bytes_per_sec: u64,
+    /// Move values straight to and from the volumes (`?redirect=true`)
+    pub direct: bool,
+}
+
+impl Default for BulkOptions {
+    fn default() -> Self {
+        Self {
+            api_key: None,
+            parallelism: 8,
+            keys_per_sec: 0,
+            bytes_per_sec: 0,
+            direct: false,
+        }
+    }
+}
+
+/// Outcome of a bulk transfer
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BulkReport {
+    /// Keys moved
+    pub keys: u64,
+    /// Value bytes moved
+    pub bytes: u64,
+    /// Keys that could not be moved
+    pub failed: u64,
+    /// Whether the transfer resumed from a checkpoint
+    pub resumed: bool,
+    /// Why keys could not be moved, up to 1000
+    pub errors: Vec<String>,
+}
+
+impl BulkReport {
+    fn fail(&mut self, key: &str, error: crate::Error) {
+        tracing::warn!("Transfer of {} failed: {}", key, error);
+        self.failed += 1;
+        if self.errors.len() < MAX_REPORTED_ERRORS {
+            self.errors.push(format!("{}: {}", key, error));
+        }
+    }
+}
+
+/// Progress of a transfer, saved beside its file
+#[derive(Debug, Serialize, Deserialize)]
+struct Checkpoint {
+    format: BulkFormat,
+    /// Prefix exported (empty for imports)
+    prefix: String,
+    /// Bytes of the file written (export) or read (import) so far
+    offset: u64,
+    /// Last key listed before `offset` (export)
+    after: Option<String>,
+    report: BulkReport,
+}
+
+fn checkpoint_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".checkpoint");
+    PathBuf::from(name)
+}
+
+/// The checkpoint of an interrupted transfer of `path`, if any. It must be
+/// one of the same transfer.
+async fn load_checkpoint(
+    path: &Path,
+    format: BulkFormat,
+    prefix: &str,
+) -> Result<Option<Checkpoint>> {
+    let cp_path = checkpoint_path(path);
+    let bytes = match tokio::fs::read(&cp_path).await {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let checkpoint: Checkpoint = serde_json::from_slice(&bytes)
+        .map_err(|e| crate::Error::Corrupted(format!("{}: {}", cp_path.display(), e)))?;
+    if checkpoint.format != format || checkpoint.prefix != prefix {
+        return Err(crate::Error::InvalidRequest(format!(
+            "{} belongs to another transfer of {}; remove it to start over",
+            cp_path.display(),
+            path.display()
+        )));
+    }
+    Ok(Some(checkpoint))
+}
+
+async fn save_checkpoint(path: &Path, checkpoint: &Checkpoint) -> Result<()> {
+    let cp_path = checkpoint_path(path);
+    let mut tmp = cp_path.clone().into_os_string();
+    tmp.push(".tmp");
+    let value = serde_json::to_vec(checkpoint)
+        .map_err(|e| crate::Error::Internal(format!("Serialize error: {}", e)))?;
+    tokio::fs::write(&tmp, value).await?;
+    tokio::fs::rename(&tmp, &cp_path).await?;
+    Ok(())
+}
+
+async fn remove_checkpoint(path: &Path) -> Result<()> {
+    match tokio::fs::remove_file(checkpoint_path(path)).await {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+/// Token buckets holding a transfer to its keys and bytes per second
+pub(crate) struct Limiter {
+    keys_per_sec: u64,
+    bytes_per_sec: u64,
+    keys: Mutex<ByteRate>,
+    bytes: Mutex<ByteRate>,
+}
+
+impl Limiter {
+    pub(crate) fn new(keys_per_sec: u64, bytes_per_sec: u64) -> Self {
+        Self {
+            keys_per_sec,
+            bytes_per_sec,
+            keys: Mutex::default(),
+            bytes: Mutex::default(),
+        }
+    }
+
+    /// Wait until one more key of `bytes` may move
+    pub(crate) async fn wait(&self, bytes: u64) {
+        let now = Instant::now();
+        let keys_wait = self.keys.lock().unwrap().reserve(self.keys_per_sec, 1, now);
+        let bytes_wait = self
+            .bytes
+            .lock()
+            .unwrap()
+            .reserve(self.bytes_per_sec, bytes, now);
+        let wait = keys_wait.max(bytes_wait);
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+fn key_url(coordinator_url: &str, key: &str, options: &BulkOptions) -> String {
+    let mut url = format!("{}/{}", coordinator_url, encode_key(key));
+    if options.direct {
+        url.push_str("?redirect=true");
+    }
+    url
+}
+
+/// Send `request` with `api_key`, trying again after the `Retry-After` of
+/// `503` and `429` answers
+pub(crate) async fn send(
+    request: reqwest::RequestBuilder,
+    api_key: Option<&str>,
+) -> Result<reqwest::Response> {
+    let mut request = match api_key {
+        Some(key) => request.header("Authorization", format!("ApiKey {}", key)),
+        None => request,
+    };
+    let mut attempt = 1;
+    loop {
+        let retry = request.try_clone();
+        let resp = request
+            .send()
+            .await
+            .map_err(|e| crate::Error::Http(e.to_string()))?;
+        let busy = matches!(
+            resp.status(),
+            reqwest::StatusCode::SERVICE_UNAVAILABLE | reqwest::StatusCode::TOO_MANY_REQUESTS
+        );
+        match retry {
+            Some(retry) if busy && attempt < MAX_ATTEMPTS => {
+                let wait = resp
+                    .headers()
+                    .get(reqwest::header::RETRY_AFTER)
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(1);
+                tokio::time::sleep(Duration::from_secs(wait)).await;
+                request = retry;
+                attempt += 1;
+            }
+            _ => return Ok(resp),
+        }
+    }
+}
+
+pub(crate) async fn error_of(resp: reqwest::Response) -> crate::Error {
+    let status = resp.status();
+    let body = resp.text().await.unwrap_or_default();
+    crate::Error::Http(format!("{}: {}", status, body))
+}
+
+/// Value of `key`, `None` if it was deleted since it was listed
+async fn get_value(
+    client: &reqwest::Client,
+    coordinator_url: &str,
+    options: &BulkOptions,
+    key: &str,
+) -> Result<Option<Vec<u8>>> {
+    let request = client.get(key_url(coordinator_url, key, options));
+    let resp = send(request, options.api_key.as_deref()).await?;
+    if resp.status() == reqwest::StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    if !resp.status().is_success() {
+        return Err(error_of(resp).await);
+    }
+    let value = resp
+        .bytes()
+        .await
+        .map_err(|e| crate::Error::Http(e.to_string()))?;
+    Ok(Some(value.to_vec()))
+}
+
+async fn put_value(
+    client: &reqwest::Client,
+    coordinator_url: &str,
+    options: &BulkOptions,
+    key: &str,
+    value: Vec<u8>,
+) -> Result<()> {
+    let request = client
+        .post(key_url(coordinator_url, key, options))
+        .body(value);
+    let resp = send(request, options.api_key.as_deref()).await?;
+    if !resp.status().is_success() {
+        return Err(error_of(resp).await);
+    }
+    Ok(())
+}
+
+/// Exports the keys under `prefix`, with their values, to `path`. A
+/// checkpoint left by an interrupted export of the same file is resumed.
+pub async fn export_keys(
+    coordinator_url: &str,
+    prefix: &str,
+    format: BulkFormat,
+    path: &Path,
+    options: &BulkOptions,
+) -> Result<BulkReport> {
+    let checkpoint = load_checkpoint(path, format, prefix).await?;
+    let (file, mut offset, mut after, mut report) = match checkpoint {
+        Some(checkpoint) => {
+            let mut file = tokio::fs::OpenOptions::new().write(true).open(path).await?;
+            // Drop whatever was written after the checkpoint
+            file.set_len(checkpoint.offset).await?;
+            file.seek(std::io::SeekFrom::Start(checkpoint.offset))
+                .await?;
+            let mut report = checkpoint.report;
+            report.resumed = true;
+            tracing::info!(
+                "Resuming export of {} after {:?}",
+                path.display(),
+                checkpoint.after
+            );
+            (file, checkpoint.offset, checkpoint.after, report)
+        }
+        None => (
+            tokio::fs::File::create(path).await?,
+            0,
+            None,
+            BulkReport::default(),
+        ),
+    };
+    let mut out = BufWriter::new(file);
+    let client = reqwest::Client::new();
+    let limiter = Limiter::new(options.keys_per_sec, options.bytes_per_sec);
+
+    loop {
+        let page = list_key_page(
+            coordinator_url,
+            options.api_key.as_deref(),
+            prefix,
+            after.as_deref(),
+            DEFAULT_PAGE_SIZE,
+        )
+        .await?;
+        let mut values = stream::iter(page.keys.iter().filter(|m| m.state != KeyState::Tombstone))
+            .map(|meta| {
+                let (client, limiter) = (&client, &limiter);
+                async move {
+                    limiter.wait(meta.size).await;
+                    let value = get_value(client, coordinator_url, options, &meta.key).await;
+                    (&meta.key, value)
+                }
+            })
+            .buffered(options.parallelism.max(1));
+        while let Some((key, value)) = values.next().await {
+            match value {
+                Ok(Some(value)) => {
+                    let entry = encode_entry(format, key, &value)?;
+                    out.write_all(&entry).await?;
+                    offset += entry.len() as u64;
+                    report.keys += 1;
+                    report.bytes += value.len() as u64;
+                }
+                Ok(None) => {}
+                Err(e) => report.fail(key, e),
+            }
+        }
+        drop(values);
+
+        match page.next_after {
+            Some(next) => {
+                after = Some(next);
+                out.flush().await?;
+                let checkpoint = Checkpoint {
+                    format,
+                    prefix: prefix.to_string(),
+                    offset,
+                    after: after.clone(),
+                    report: report.clone(),
+                };
+                save_checkpoint(path, &checkpoint).await?;
+            }
+            None => break,
+        }
+    }
+
+    if format == BulkFormat::Tar {
+        // End of archive
+        out.write_all(&[0; 2 * TAR_BLOCK]).await?;
+    }
+    out.flush().await?;
+    remove_checkpoint(path).await?;
+    Ok(report)
+}
+
+/// Imports the keys and values of the export file `path`. A checkpoint left
+/// by an interrupted import of the same file is resumed.
+pub async fn import_keys(
+    coordinator_url: &str,
+    format: BulkFormat,
+    path: &Path,
+    options: &BulkOptions,
+) -> Result<BulkReport> {
+    let checkpoint = load_checkpoint(path, format, "").await?;
+    let mut file = tokio::fs::File::open(path).await?;
+    let (mut offset, mut report) = match checkpoint {
+        Some(checkpoint) => {
+            file.seek(std::io::SeekFrom::Start(checkpoint.offset))
+                .await?;
+            let mut report = checkpoint.report;
+            report.resumed = true;
+            tracing::info!(
+                "Resuming import of {} at byte {}",
+                path.display(),
+                checkpoint.offset
+            );
+            (checkpoint.offset, report)
+        }
+        None => (0, BulkReport::default()),
+    };
+    let mut reader = BufReader::new(file);
+    let client = reqwest::Client::new();
+    let limiter = Limiter::new(options.keys_per_sec, options.bytes_per_sec);
+
+    let mut in_flight = FuturesOrdered::new();
+    let mut done = 0u64;
+    let mut eof = false;
+    loop {
+        while !eof && in_flight.len() < options.parallelism.max(1) {
+            match read_entry(&mut reader, format).await? {
+                Some((entry, len)) => {
+                    offset += len;
+                    let end = offset;
+                    let (client, limiter) = (&client, &limiter);
+                    in_flight.push_back(async move {
+                        let bytes = entry.value.len() as u64;
+                        limiter.wait(bytes).await;
+                        let result =
+                            put_value(client, coordinator_url, options, &entry.key, entry.value)
+                                .await;
+                        (entry.key, bytes, end, result)
+                    });
+                }
+                None => eof = true,
+            }
+        }
+        let Some((key, bytes, end, result)) = in_flight.next().await else {
+            break;
+        };
+        match result {
+            Ok(()) => {
+                report.keys += 1;
+                report.bytes += bytes;
+            }
+            Err(e) => report.fail(&key, e),
+        }
+
+        done += 1;
+        if done % CHECKPOINT_INTERVAL == 0 {
+            let checkpoint = Checkpoint {
+                format,
+                prefix: String::new(),
+                offset: end,
+                after: None,
+                report: report.clone(),
+            };
+            save_checkpoint(path, &checkpoint).await?;
+        }
+    }
+
+    remove_checkpoint(path).await?;
+    Ok(report)
+}
+
+/// A key and its value, as stored in an export file
+#[derive(Debug)]
+struct Entry {
+    key: String,
+    value: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct JsonEntry {
+    key: String,
+    /// Base64 of the value
+    value: String,
+}
+
+/// `key` and `value` as they are written to a file of `format`
+fn encode_entry(format: BulkFormat, key: &str, value: &[u8]) -> Result<Vec<u8>> {
+    match format {
+        BulkFormat::Ndjson => {
+            let entry = JsonEntry {
+                key: key.to_string(),
+                value: BASE64.encode(value),
+            };
+            let mut line = serde_json::to_vec(&entry)
+                .map_err(|e| crate::Error::Internal(format!("Serialize error: {}", e)))?;
+            line.push(b'\n');
+            Ok(line)
+        }
+        BulkFormat::Tar => Ok(tar_entry(key, value)),
+    }
+}
+
+/// Read the next entry of a file of `format`, with the number of bytes it
+/// took up. `None` at the end of the file.
+async fn read_entry<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    format: BulkFormat,
+) -> Result<Option<(Entry, u64)>> {
+    match format {
+        BulkFormat::Ndjson => read_json_entry(reader).await,
+        BulkFormat::Tar => read_tar_entry(reader).await,
+    }
+}
+
+async fn read_json_entry<R: AsyncBufRead + Unpin>(reader: &mut R) -> Result<Option<(Entry, u64)>> {
+    let mut consumed = 0;
+    let mut line = String::new();
+    loop {
+        line.clear();
+        let len = reader.read_line(&mut line).await?;
+        if len == 0 {
+            return Ok(None);
+        }
+        consumed += len as u64;
+        if !line.trim().is_empty() {
+            break;
+        }
+    }
+    let entry: JsonEntry = serde_json::from_str(&line)
+        .map_err(|e| crate::Error::Corrupted(format!("bad ndjson entry: {}", e)))?;
+    let value = BASE64
+        .decode(entry.value)
+        .map_err(|e| crate::Error::Corrupted(format!("bad value of {}: {}", entry.key, e)))?;
+    Ok(Some((
+        Entry {
+            key: entry.key,
+            value,
+        },
+        consumed,
+    )))
+}
+
+/// A ustar header block for a `kind` entry of `size` bytes named `name`
+fn tar_header(name: &str, size: u64, kind: u8) -> [u8; TAR_BLOCK] {
+    let mut header = [0u8; TAR_BLOCK];
+    let name = name.as_bytes();
+    header[..name.len()].copy_from_slice(name);
+    write_octal(&mut header[100..108], 0o644);
+    write_octal(&mut header[108..116], 0);
+    write_octal(&mut header[116..124], 0);
+    write_octal(&mut header[124..136], size);
+    write_octal(&mut header[136..148], crate::common::timestamp_now());
+    header[156] = kind;
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+    header[148..156].copy_from_slice(format!("{:06o}\0 ", tar_checksum(&header)).as_bytes());
+    header
+}
+
+/// Sum of the header bytes, its checksum field counted as spaces
+fn tar_checksum(header: &[u8; TAR_BLOCK]) -> u32 {
+    header
+        .iter()
+        .enumerate()
+        .map(|(i, b)| {
+            if (148..156).contains(&i) {
+                b' ' as u32
+            } else {
+                *b as u32
+            }
+        })
+        .sum()
+}
+
+fn write_octal(field: &mut [u8], value: u64) {
+    let digits = format!("{:0width$o}\0", value, width = field.len() - 1);
+    field.copy_from_slice(digits.as_bytes());
+}
+
+fn parse_octal(field: &[u8]) -> Result<u64> {
+    let digits = std::str::from_utf8(field)
+        .map_err(|_| crate::Error::Corrupted("bad tar number".into()))?
+        .trim_matches(|c: char| c == '\0' || c == ' ');
+    if digits.is_empty() {
+        return Ok(0);
+    }
+    u64::from_str_radix(digits, 8)
+        .map_err(|_| crate::Error::Corrupted(format!("bad tar number {:?}", digits)))
+}
+
+/// NUL-terminated string of a header field
+fn c_string(field: &[u8]) -> Result<&str> {
+    let end = field.iter().position(|b| *b == 0).unwrap_or(field.len());
+    std::str::from_utf8(&field[..end]).map_err(|_| crate::Error::Corrupted("bad tar name".into()))
+}
+
+/// Zero bytes after `len` bytes of data, up to the next block
+fn tar_padding(len: usize) -> usize {
+    (TAR_BLOCK - len % TAR_BLOCK) % TAR_BLOCK
+}
+
+/// PAX extended header record `"<length> <keyword>=<value>\n"`, where the
+/// length counts its own digits
+fn pax_record(keyword: &str, value: &str) -> Vec<u8> {
+    let rest = format!(" {}={}\n", keyword, value);
+    let mut len = rest.len() + 1;
+    while len != rest.len() + len.to_string().len() {
+        len = rest.len() + len.to_string().len();
+    }
+    format!("{}{}", len, rest).into_bytes()
+}
+
+/// `key` and `value` as a tar entry, preceded by a PAX header holding the
+/// key when it does not fit the 100 bytes of a ustar name
+fn tar_entry(key: &str, value: &[u8]) -> Vec<u8> {
+    let mut entry = Vec::with_capacity(3 * TAR_BLOCK + value.len());
+    let mut name = key;
+    if key.len() > 100 {
+        let record = pax_record("path", key);
+        entry.extend_from_slice(&tar_header("././@PaxHeader", record.len() as u64, b'x'));
+        entry.extend_from_slice(&record);
+        entry.resize(entry.len() + tar_padding(record.len()), 0);
+        let mut end = 100;
+        while !key.is_char_boundary(end) {
+            end -= 1;
+        }
+        name = &key[..end];
+    }
+    entry.extend_from_slice(&tar_header(name, value.len() as u64, b'0'));
+    entry.extend_from_slice(value);
+    entry.resize(entry.len() + tar_padding(value.len()), 0);
+    entry
+}
+
+async fn read_tar_entry<R: AsyncBufRead + Unpin>(reader: &mut R) -> Result<Option<(Entry, u64)>> {
+    let mut consumed = 0;
+    let mut pax_path = None;
+    loop {
+        let mut header = [0u8; TAR_BLOCK];
+        match reader.read_exact(&mut header).await {
+            Ok(_) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+        consumed += TAR_BLOCK as u64;
+        if header.iter().all(|b| *b == 0) {
+            // End of archive
+            return Ok(None);
+        }
+        if parse_octal(&header[148..156])? != tar_checksum(&header) as u64 {
+            return Err(crate::Error::Corrupted("bad tar header checksum".into()));
+        }
+
+        let size = parse_octal(&header[124..136])? as usize;
+        let mut data = vec![0u8; size + tar_padding(size)];
+        reader.read_exact(&mut data).await?;
+        consumed += data.len() as u64;
+        data.truncate(size);
+
+        match header[156] {
+            b'x' => {
+                let records = String::from_utf8_lossy(&data);
+                pax_path = records
+                    .lines()
+                    .filter_map(|record| record.split_once(' ')?.1.strip_prefix("path="))
+                    .last()
+                    .map(str::to_string);
+            }
+            b'0' | 0 => {
+                let key = match pax_path {
+                    Some(path) => path,
+                    None => {
+                        let (name, prefix) =
+                            (c_string(&header[..100])?, c_string(&header[345..500])?);
+                        if prefix.is_empty() {
+                            name.to_string()
+                        } else {
+                            format!("{}/{}", prefix, name)
+                        }
+                    }
+                };
+                return Ok(Some((Entry { key, value: data }, consumed)));
+            }
+            // Directories, links and the like hold no values
+            _ => pax_path = None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn roundtrip(format: BulkFormat, entries: &[(&str, &[u8])]) {
+        let mut file = Vec::new();
+        for (key, value) in entries {
+            file.extend(encode_entry(format, key, value).unwrap());
+        }
+        if format == BulkFormat::Tar {
+            file.extend_from_slice(&[0; 2 * TAR_BLOCK]);
+        }
+
+        let mut reader = &file[..];
+        let mut offset = 0;
+        for (key, value) in entries {
+            let (entry, len) = read_entry(&mut reader, format).await.unwrap().unwrap();
+            assert_eq!(entry.key, *key);
+            assert_eq!(entry.value, *value);
+            offset += len as usize;
+            // Resuming at the offset reads the next entries
+            assert_eq!(reader.len(), file.len() - offset);
+        }
+        assert!(read_entry(&mut reader, format).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_entry_roundtrip() {
+        let long_key = "dir/".repeat(40) + "é";
+        let big = vec![7u8; 3 * TAR_BLOCK + 1];
+        let entries: Vec<(&str, &[u8])> = vec![
+            ("a", &b"hello"[..]),
+            ("empty", &b""[..]),
+            (long_key.as_str(), &big[..]),
+            ("bin/\n\"", &[0, 255, 10][..]),
+        ];
+        roundtrip(BulkFormat::Ndjson, &entries).await;
+        roundtrip(BulkFormat::Tar, &entries).await;
+    }
+
+    #[test]
+    fn test_pax_record_length() {
+        assert_eq!(pax_record("path", "k"), b"9 path=k\n");
+        // 102 bytes besides the length, whose 3 digits make it 105
+        let record = pax_record("path", &"k".repeat(95));
+        assert_eq!(record.len(), 105);
+        assert!(record.starts_with(b"105 path="));
+    }
+
+    #[test]
+    fn test_format_for_path() {
+        assert_eq!(BulkFormat::for_path(Path::new("dump.tar")), BulkFormat::Tar);
+        assert_eq!(
+            BulkFormat::for_path(Path::new("dump.ndjson")),
+            BulkFormat::Ndjson
+        );
+        assert_eq!("tar".parse::<BulkFormat>().unwrap(), BulkFormat::Tar);
+        assert!("zip".parse::<BulkFormat>().is_err());
+        assert_eq!(
+            checkpoint_path(Path::new("/tmp/dump.tar")),
+            PathBuf::from("/tmp/dump.tar.checkpoint")
+        );
+    }
+}
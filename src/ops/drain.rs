@@ -0,0 +1,55 @@
+//! Volume drain
+//!
+//! Asks the coordinator to decommission a volume and follows its progress.
+
+use crate::common::Result;
+use crate::coordinator::drain::{DrainProgress, DrainState};
+use std::time::Duration;
+
+/// How often to poll the coordinator while waiting
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Starts draining `volume_id` via `/admin/drain`.
+/// With `wait`, polls until the drain finishes, calling `on_progress` after each poll.
+pub async fn drain_volume(
+    coordinator_url: &str,
+    volume_id: &str,
+    wait: bool,
+    mut on_progress: impl FnMut(&DrainProgress),
+) -> Result<DrainProgress> {
+    let client = reqwest::Client::new();
+    let resp = client
+        .post(format!("{}/admin/drain", coordinator_url))
+        .header("content-type", "application/json")
+        .body(serde_json::json!({ "volume_id": volume_id }).to_string())
+        .send()
+        .await
+        .map_err(|e| crate::Error::Http(e.to_string()))?;
+    let mut progress = parse_progress(resp).await?;
+    on_progress(&progress);
+
+    while wait && progress.state == DrainState::Running {
+        tokio::time::sleep(POLL_INTERVAL).await;
+        let resp = client
+            .get(format!("{}/admin/drain/{}", coordinator_url, volume_id))
+            .send()
+            .await
+            .map_err(|e| crate::Error::Http(e.to_string()))?;
+        progress = parse_progress(resp).await?;
+        on_progress(&progress);
+    }
+
+    Ok(progress)
+}
+
+async fn parse_progress(resp: reqwest::Response) -> Result<DrainProgress> {
+    let status = resp.status();
+    let body = resp
+        .text()
+        .await
+        .map_err(|e| crate::Error::Http(e.to_string()))?;
+    if !status.is_success() {
+        return Err(crate::Error::Http(format!("{}: {}", status, body)));
+    }
+    serde_json::from_str(&body).map_err(|e| crate::Error::Http(e.to_string()))
+}
@@ -0,0 +1,44 @@
+//! Storage usage breakdown
+//!
+//! Sums the sizes and counts the keys per tenant or per key prefix, from the
+//! key metadata, for capacity planning and chargeback.
+
+use crate::common::Result;
+use crate::coordinator::usage::{PrefixUsage, UsageGrouping};
+use crate::ops::api_keys::send;
+use serde::{Deserialize, Serialize};
+
+/// Answer of `GET /admin/metadata/usage`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UsageReport {
+    pub by: UsageGrouping,
+    /// Largest first
+    pub usage: Vec<PrefixUsage>,
+    pub total_bytes: u64,
+    pub total_keys: u64,
+}
+
+/// Fetches the usage of the keys under the internal `prefix` (`tenant/...`),
+/// grouped `by` tenant or by `depth` levels of the key under the tenant
+pub async fn disk_usage(
+    coordinator_url: &str,
+    admin_key: Option<&str>,
+    by: UsageGrouping,
+    depth: usize,
+    prefix: &str,
+) -> Result<UsageReport> {
+    let by = match by {
+        UsageGrouping::Tenant => "tenant",
+        UsageGrouping::Prefix => "prefix",
+    };
+    let query = [
+        ("by", by.to_string()),
+        ("depth", depth.to_string()),
+        ("prefix", prefix.to_string()),
+    ];
+    let request = reqwest::Client::new()
+        .get(format!("{}/admin/metadata/usage", coordinator_url))
+        .query(&query);
+    let body = send(request, admin_key).await?;
+    serde_json::from_str(&body).map_err(|e| crate::Error::Http(e.to_string()))
+}
@@ -0,0 +1,33 @@
+//! Orphaned blob collection
+//!
+//! Asks the leader to cross-check the volumes' blobs against the key metadata
+//! and delete the orphans.
+
+use crate::common::Result;
+use crate::coordinator::gc::GcReport;
+
+/// Runs a GC pass via `/admin/gc`. `grace_secs` overrides the configured grace period.
+pub async fn gc_cluster(
+    coordinator_url: &str,
+    dry_run: bool,
+    grace_secs: Option<u64>,
+) -> Result<GcReport> {
+    let mut url = format!("{}/admin/gc?dry_run={}", coordinator_url, dry_run);
+    if let Some(grace) = grace_secs {
+        url.push_str(&format!("&grace_secs={}", grace));
+    }
+    let resp = reqwest::Client::new()
+        .post(url)
+        .send()
+        .await
+        .map_err(|e| crate::Error::Http(e.to_string()))?;
+    let status = resp.status();
+    let body = resp
+        .text()
+        .await
+        .map_err(|e| crate::Error::Http(e.to_string()))?;
+    if !status.is_success() {
+        return Err(crate::Error::Http(format!("{}: {}", status, body)));
+    }
+    serde_json::from_str(&body).map_err(|e| crate::Error::Http(e.to_string()))
+}
@@ -0,0 +1,26 @@
+//! Hot keys
+//!
+//! Asks a coordinator which keys it served the most reads and writes of, and
+//! which were the largest, over a window.
+
+use crate::common::Result;
+use crate::coordinator::hotkeys::HotKeysReport;
+use crate::ops::api_keys::send;
+
+/// Fetches `/admin/hotkeys`, listing `limit` keys per ranking over the last
+/// `window_secs`
+pub async fn hot_keys(
+    coordinator_url: &str,
+    admin_key: Option<&str>,
+    window_secs: u64,
+    limit: usize,
+) -> Result<HotKeysReport> {
+    let request = reqwest::Client::new()
+        .get(format!("{}/admin/hotkeys", coordinator_url))
+        .query(&[
+            ("window", window_secs.to_string()),
+            ("limit", limit.to_string()),
+        ]);
+    let body = send(request, admin_key).await?;
+    serde_json::from_str(&body).map_err(|e| crate::Error::Http(e.to_string()))
+}
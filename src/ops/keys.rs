@@ -0,0 +1,112 @@
+//! Paginated key listing
+//!
+//! Walks the coordinator's key metadata one page at a time through
+//! `GET /keys`, so ops commands never hold the whole keyspace in memory.
+//! Commands checking the whole store page through `GET /admin/metadata/keys`
+//! instead, which spans every tenant and the internal keys.
+
+use crate::common::Result;
+use crate::coordinator::metadata::{KeyMetadata, VolumeMetadata};
+use serde::{Deserialize, Serialize};
+
+/// Answer of `GET /keys`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeyPage {
+    pub keys: Vec<KeyMetadata>,
+    /// Cursor for the next page; `None` on the last one
+    pub next_after: Option<String>,
+}
+
+/// Fetches one page of at most `limit` keys under `prefix`, after `after`,
+/// of the tenant of `api_key` (the default tenant without one)
+pub async fn list_key_page(
+    coordinator_url: &str,
+    api_key: Option<&str>,
+    prefix: &str,
+    after: Option<&str>,
+    limit: usize,
+) -> Result<KeyPage> {
+    let url = format!("{}/keys", coordinator_url);
+    fetch_key_page(url, api_key, prefix, after, limit).await
+}
+
+/// Fetches one page of at most `limit` internal keys under `prefix`, across
+/// every tenant and including content-addressed and chunk keys, after `after`
+pub async fn list_metadata_page(
+    coordinator_url: &str,
+    admin_key: Option<&str>,
+    prefix: &str,
+    after: Option<&str>,
+    limit: usize,
+) -> Result<KeyPage> {
+    let url = format!("{}/admin/metadata/keys", coordinator_url);
+    fetch_key_page(url, admin_key, prefix, after, limit).await
+}
+
+async fn fetch_key_page(
+    url: String,
+    api_key: Option<&str>,
+    prefix: &str,
+    after: Option<&str>,
+    limit: usize,
+) -> Result<KeyPage> {
+    let mut query = vec![("prefix", prefix.to_string()), ("limit", limit.to_string())];
+    if let Some(after) = after {
+        query.push(("after", after.to_string()));
+    }
+    let mut request = reqwest::Client::new().get(url).query(&query);
+    if let Some(key) = api_key {
+        request = request.header("Authorization", format!("ApiKey {}", key));
+    }
+    get_json(request).await
+}
+
+/// Every volume registered with the coordinator (`GET /admin/volumes`)
+pub async fn list_volumes(coordinator_url: &str) -> Result<Vec<VolumeMetadata>> {
+    #[derive(Deserialize)]
+    struct Volumes {
+        volumes: Vec<VolumeMetadata>,
+    }
+    let request = reqwest::Client::new().get(format!("{}/admin/volumes", coordinator_url));
+    let volumes: Volumes = get_json(request).await?;
+    Ok(volumes.volumes)
+}
+
+async fn get_json<T: serde::de::DeserializeOwned>(request: reqwest::RequestBuilder) -> Result<T> {
+    let resp = request
+        .send()
+        .await
+        .map_err(|e| crate::Error::Http(e.to_string()))?;
+    let status = resp.status();
+    let body = resp
+        .text()
+        .await
+        .map_err(|e| crate::Error::Http(e.to_string()))?;
+    if !status.is_success() {
+        return Err(crate::Error::Http(format!("{}: {}", status, body)));
+    }
+    serde_json::from_str(&body).map_err(|e| crate::Error::Http(e.to_string()))
+}
+
+/// Calls `f` on every page of keys under `prefix`, in key order
+pub async fn for_each_key_page<F>(coordinator_url: &str, prefix: &str, mut f: F) -> Result<()>
+where
+    F: FnMut(Vec<KeyMetadata>),
+{
+    let mut after: Option<String> = None;
+    loop {
+        let page = list_key_page(
+            coordinator_url,
+            None,
+            prefix,
+            after.as_deref(),
+            crate::coordinator::metadata::DEFAULT_PAGE_SIZE,
+        )
+        .await?;
+        f(page.keys);
+        match page.next_after {
+            Some(next) => after = Some(next),
+            None => return Ok(()),
+        }
+    }
+}
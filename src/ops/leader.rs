@@ -0,0 +1,30 @@
+//! Leader discovery
+//!
+//! Asks any coordinator which node currently leads the cluster.
+
+use crate::common::Result;
+use serde::{Deserialize, Serialize};
+
+/// Answer of `GET /leader`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LeaderInfo {
+    pub leader_id: Option<String>,
+    /// HTTP address of the leader, when the coordinator knows it
+    pub leader_addr: Option<String>,
+    pub term: u64,
+    /// Whether the coordinator that answered is the leader
+    pub is_leader: bool,
+}
+
+/// Queries `/leader` on `coordinator_url`. A coordinator that knows of no
+/// leader (e.g. during an election) still answers, with `leader_id` unset.
+pub async fn find_leader(coordinator_url: &str) -> Result<LeaderInfo> {
+    let resp = reqwest::get(format!("{}/leader", coordinator_url))
+        .await
+        .map_err(|e| crate::Error::Http(e.to_string()))?;
+    let body = resp
+        .text()
+        .await
+        .map_err(|e| crate::Error::Http(e.to_string()))?;
+    serde_json::from_str(&body).map_err(|e| crate::Error::Http(e.to_string()))
+}
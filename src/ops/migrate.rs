@@ -0,0 +1,487 @@
+//! Cluster-to-cluster migration
+//!
+//! `migrate_cluster` copies the keys of one cluster, or of some of its
+//! tenants and prefixes, to another through their HTTP APIs. It walks both
+//! clusters' key metadata side by side (`GET /admin/metadata/keys`) and
+//! copies the keys missing on the target or older there, checking each value
+//! against the source's BLAKE3. Copies carry the source's `updated_at` in
+//! `X-Minikv-Replicated-At`, so the target settles them with last-writer-wins
+//! like replicated changes (see `coordinator::replication`): a key written on
+//! the target since is kept, and a migration run again only copies what
+//! changed. A second walk then verifies every key against the target.
+//! Up to `parallelism` keys are copied at once, and a migration can be held
+//! to a number of keys and bytes per second.
+//!
+//! With `cutover`, the source first starts replicating its writes to the
+//! target (a runtime replication target named after the migration), so
+//! clients keep writing to the source during the copy and the verification
+//! waits for the target to catch up. Clients then switch to the target, and
+//! `finish_migration` waits for the last replicated changes before removing
+//! the replication target.
+
+use crate::common::{blake3_hash, encode_key, ReplicationTarget, Result};
+use crate::coordinator::dedup;
+use crate::coordinator::metadata::{KeyMetadata, KeyState, DEFAULT_PAGE_SIZE};
+use crate::coordinator::replication::{self, Resolution, REPLICATED_AT_HEADER};
+use crate::coordinator::tenant;
+use crate::ops::bulk::{error_of, send, Limiter};
+use crate::ops::keys::list_metadata_page;
+use crate::ops::replication::{
+    add_replication_target, remove_replication_target, replication_status,
+};
+use futures_util::stream::{self, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// Most errors listed in a report
+const MAX_REPORTED_ERRORS: usize = 1000;
+
+/// How often the replication status is polled while waiting for the target
+const CATCH_UP_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// What a migration copies and how
+#[derive(Debug, Clone)]
+pub struct MigrateOptions {
+    /// Admin API key of the source cluster
+    pub from_api_key: Option<String>,
+    /// Admin API key of the target cluster
+    pub to_api_key: Option<String>,
+    /// Only the keys of these tenants (or under `prefixes`); every key if
+    /// both are empty
+    pub tenants: Vec<String>,
+    /// Only the stored keys (`<tenant>/<key>`) under these prefixes
+    pub prefixes: Vec<String>,
+    /// Keys copied at once
+    pub parallelism: usize,
+    /// Keys copied per second, 0 for no limit
+    pub keys_per_sec: u64,
+    /// Value bytes copied per second, 0 for no limit
+    pub bytes_per_sec: u64,
+    /// Replicate the source's writes to the target until `finish_migration`
+    pub cutover: bool,
+    /// Name of the replication target added for the cutover
+    pub name: String,
+}
+
+impl Default for MigrateOptions {
+    fn default() -> Self {
+        Self {
+            from_api_key: None,
+            to_api_key: None,
+            tenants: Vec::new(),
+            prefixes: Vec::new(),
+            parallelism: 8,
+            keys_per_sec: 0,
+            bytes_per_sec: 0,
+            cutover: false,
+            name: "migration".to_string(),
+        }
+    }
+}
+
+/// Step of a migration reported to `on_progress`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MigratePhase {
+    Copying,
+    /// Waiting for the target to replicate the writes made during the copy
+    CatchingUp,
+    Verifying,
+}
+
+/// Outcome of a migration
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MigrateReport {
+    /// Keys of the source in scope
+    pub keys_scanned: u64,
+    /// Keys copied to the target
+    pub keys_copied: u64,
+    /// Value bytes copied to the target
+    pub bytes_copied: u64,
+    /// Keys found identical on both clusters by the verification
+    pub keys_verified: u64,
+    /// Keys written on the target after the source, left as they are
+    pub newer_on_target: u64,
+    /// Keys still older or missing on the target after the copy
+    pub mismatched: u64,
+    /// Keys that could not be copied
+    pub failed: u64,
+    /// Replication target kept until `finish_migration` (with `cutover`)
+    pub cutover_target: Option<String>,
+    /// Failed and mismatched keys, up to 1000
+    pub errors: Vec<String>,
+}
+
+impl MigrateReport {
+    fn error(&mut self, key: &str, error: impl std::fmt::Display) {
+        tracing::warn!("Migration of {} failed: {}", key, error);
+        if self.errors.len() < MAX_REPORTED_ERRORS {
+            self.errors.push(format!("{}: {}", key, error));
+        }
+    }
+}
+
+/// Key metadata of one cluster under a prefix, fetched a page at a time
+struct Listing<'a> {
+    url: &'a str,
+    admin_key: Option<&'a str>,
+    prefix: String,
+    keys: VecDeque<KeyMetadata>,
+    after: Option<String>,
+    done: bool,
+}
+
+impl<'a> Listing<'a> {
+    fn new(url: &'a str, admin_key: Option<&'a str>, prefix: String) -> Self {
+        Self {
+            url,
+            admin_key,
+            prefix,
+            keys: VecDeque::new(),
+            after: None,
+            done: false,
+        }
+    }
+
+    /// First key not yet taken, fetching the next page when needed
+    async fn peek(&mut self) -> Result<Option<&KeyMetadata>> {
+        while self.keys.is_empty() && !self.done {
+            let page = list_metadata_page(
+                self.url,
+                self.admin_key,
+                &self.prefix,
+                self.after.as_deref(),
+                DEFAULT_PAGE_SIZE,
+            )
+            .await?;
+            self.done = page.next_after.is_none();
+            self.after = page.next_after;
+            self.keys.extend(page.keys);
+        }
+        Ok(self.keys.front())
+    }
+
+    async fn next(&mut self) -> Result<Option<KeyMetadata>> {
+        self.peek().await?;
+        Ok(self.keys.pop_front())
+    }
+
+    /// Metadata of `key`, skipping the keys listed before it
+    async fn seek(&mut self, key: &str) -> Result<Option<KeyMetadata>> {
+        while let Some(meta) = self.peek().await? {
+            if meta.key.as_str() >= key {
+                break;
+            }
+            self.keys.pop_front();
+        }
+        match self.keys.front() {
+            Some(meta) if meta.key == key => Ok(self.keys.pop_front()),
+            _ => Ok(None),
+        }
+    }
+}
+
+/// Prefixes of the stored keys a migration covers
+fn scopes(options: &MigrateOptions) -> Vec<String> {
+    if options.tenants.is_empty() && options.prefixes.is_empty() {
+        return vec![String::new()];
+    }
+    options
+        .tenants
+        .iter()
+        .map(|t| tenant::prefix(t))
+        .chain(options.prefixes.iter().cloned())
+        .collect()
+}
+
+/// Whether `meta`, listed under `scopes[scope]`, is to be migrated there:
+/// a live client key not already covered by an earlier scope
+fn in_scope(scopes: &[String], scope: usize, meta: &KeyMetadata) -> bool {
+    meta.state != KeyState::Tombstone
+        && meta.key.contains('/')
+        && !dedup::is_reserved(&meta.key)
+        && !scopes[..scope]
+            .iter()
+            .any(|earlier| meta.key.starts_with(earlier.as_str()))
+}
+
+/// Walks the source's keys in scope beside the target's, pairing each with
+/// what the target makes of its copy
+struct Scan<'a> {
+    from: &'a str,
+    to: &'a str,
+    options: &'a MigrateOptions,
+    scopes: Vec<String>,
+    scope: usize,
+    source: Listing<'a>,
+    target: Listing<'a>,
+}
+
+impl<'a> Scan<'a> {
+    fn new(from: &'a str, to: &'a str, options: &'a MigrateOptions) -> Self {
+        let scopes = scopes(options);
+        let source = Listing::new(from, options.from_api_key.as_deref(), scopes[0].clone());
+        let target = Listing::new(to, options.to_api_key.as_deref(), scopes[0].clone());
+        Self {
+            from,
+            to,
+            options,
+            scopes,
+            scope: 0,
+            source,
+            target,
+        }
+    }
+
+    /// Up to a page of keys, empty once every scope is walked
+    async fn next_batch(&mut self) -> Result<Vec<(KeyMetadata, Resolution)>> {
+        let mut batch = Vec::new();
+        while batch.len() < DEFAULT_PAGE_SIZE {
+            let Some(meta) = self.source.next().await? else {
+                if self.scope + 1 == self.scopes.len() {
+                    break;
+                }
+                self.scope += 1;
+                let prefix = &self.scopes[self.scope];
+                self.source = Listing::new(
+                    self.from,
+                    self.options.from_api_key.as_deref(),
+                    prefix.clone(),
+                );
+                self.target =
+                    Listing::new(self.to, self.options.to_api_key.as_deref(), prefix.clone());
+                continue;
+            };
+            if !in_scope(&self.scopes, self.scope, &meta) {
+                continue;
+            }
+            let copy = self.target.seek(&meta.key).await?;
+            let resolution = replication::resolve(
+                copy.as_ref(),
+                meta.updated_at,
+                Some((meta.blake3.as_str(), &meta.tags)),
+            );
+            batch.push((meta, resolution));
+        }
+        Ok(batch)
+    }
+}
+
+/// What became of a key to copy
+enum Copied {
+    /// Copied, with its value's size
+    Value(u64),
+    /// Deleted or written again on the source since it was listed, or
+    /// written on the target in the meantime
+    Skipped,
+}
+
+/// Copy `meta`'s value and tags from the source to the target
+async fn copy_key(
+    client: &reqwest::Client,
+    from: &str,
+    to: &str,
+    options: &MigrateOptions,
+    limiter: &Limiter,
+    meta: &KeyMetadata,
+) -> Result<Copied> {
+    let (tenant, key) = meta
+        .key
+        .split_once('/')
+        .ok_or_else(|| crate::Error::Http(format!("{} has no tenant", meta.key)))?;
+    limiter.wait(meta.size).await;
+
+    let request = client
+        .get(format!("{}/{}", from, encode_key(key)))
+        .header(tenant::TENANT_HEADER, tenant);
+    let resp = send(request, options.from_api_key.as_deref()).await?;
+    if resp.status() == reqwest::StatusCode::NOT_FOUND {
+        return Ok(Copied::Skipped);
+    }
+    if !resp.status().is_success() {
+        return Err(error_of(resp).await);
+    }
+    let value = resp
+        .bytes()
+        .await
+        .map_err(|e| crate::Error::Http(e.to_string()))?;
+    if blake3_hash(&value) != meta.blake3 {
+        return Ok(Copied::Skipped);
+    }
+
+    let size = value.len() as u64;
+    // The target must not redirect the upload to one of its volumes
+    let mut request = client
+        .post(format!("{}/{}?redirect=false", to, encode_key(key)))
+        .header(tenant::TENANT_HEADER, tenant)
+        .header(REPLICATED_AT_HEADER, meta.updated_at)
+        .body(value);
+    for (name, value) in &meta.tags {
+        request = request.header("X-Tag", format!("{}={}", name, value));
+    }
+    let resp = send(request, options.to_api_key.as_deref()).await?;
+    if resp.status() == reqwest::StatusCode::CONFLICT {
+        return Ok(Copied::Skipped);
+    }
+    if !resp.status().is_success() {
+        return Err(error_of(resp).await);
+    }
+    Ok(Copied::Value(size))
+}
+
+/// Copies the keys in scope from the cluster at `from` to the one at `to`,
+/// then verifies them, calling `on_progress` after each page of keys
+pub async fn migrate_cluster(
+    from: &str,
+    to: &str,
+    options: &MigrateOptions,
+    mut on_progress: impl FnMut(MigratePhase, &MigrateReport),
+) -> Result<MigrateReport> {
+    let mut report = MigrateReport::default();
+    if options.cutover {
+        let target = ReplicationTarget {
+            name: options.name.clone(),
+            url: to.to_string(),
+            api_key: options.to_api_key.clone(),
+            tenants: options.tenants.clone(),
+            prefixes: options.prefixes.clone(),
+            parallelism: options.parallelism,
+        };
+        add_replication_target(from, options.from_api_key.as_deref(), &target).await?;
+        report.cutover_target = Some(options.name.clone());
+    }
+
+    let client = reqwest::Client::new();
+    let limiter = Limiter::new(options.keys_per_sec, options.bytes_per_sec);
+    let mut scan = Scan::new(from, to, options);
+    loop {
+        let batch = scan.next_batch().await?;
+        if batch.is_empty() {
+            break;
+        }
+        report.keys_scanned += batch.len() as u64;
+        let copies = batch
+            .into_iter()
+            .filter(|(_, resolution)| *resolution == Resolution::Apply)
+            .map(|(meta, _)| {
+                let (client, limiter) = (&client, &limiter);
+                async move {
+                    let result = copy_key(client, from, to, options, limiter, &meta).await;
+                    (meta.key, result)
+                }
+            });
+        let mut copies = stream::iter(copies).buffer_unordered(options.parallelism.max(1));
+        while let Some((key, result)) = copies.next().await {
+            match result {
+                Ok(Copied::Value(size)) => {
+                    report.keys_copied += 1;
+                    report.bytes_copied += size;
+                }
+                Ok(Copied::Skipped) => {}
+                Err(e) => {
+                    report.failed += 1;
+                    report.error(&key, e);
+                }
+            }
+        }
+        on_progress(MigratePhase::Copying, &report);
+    }
+
+    if options.cutover {
+        on_progress(MigratePhase::CatchingUp, &report);
+        wait_caught_up(from, options.from_api_key.as_deref(), &options.name).await?;
+    }
+
+    let mut scan = Scan::new(from, to, options);
+    loop {
+        let batch = scan.next_batch().await?;
+        if batch.is_empty() {
+            break;
+        }
+        for (meta, resolution) in batch {
+            match resolution {
+                Resolution::Unchanged => report.keys_verified += 1,
+                Resolution::Conflict => report.newer_on_target += 1,
+                Resolution::Apply => {
+                    report.mismatched += 1;
+                    report.error(&meta.key, "older or missing on the target");
+                }
+            }
+        }
+        on_progress(MigratePhase::Verifying, &report);
+    }
+    Ok(report)
+}
+
+/// Wait until the replication target `name` of the cluster at `from` has
+/// pushed every change in the CDC log
+async fn wait_caught_up(from: &str, admin_key: Option<&str>, name: &str) -> Result<()> {
+    loop {
+        let status = replication_status(from, admin_key).await?;
+        let target = status
+            .targets
+            .iter()
+            .find(|target| target.name == name)
+            .ok_or_else(|| crate::Error::Http(format!("replication target {} not found", name)))?;
+        if target.pending_entries == 0 {
+            return Ok(());
+        }
+        tokio::time::sleep(CATCH_UP_POLL_INTERVAL).await;
+    }
+}
+
+/// Ends the cutover of a migration once clients write to the target: waits
+/// for the source to replicate its last changes, then stops replicating
+pub async fn finish_migration(from: &str, admin_key: Option<&str>, name: &str) -> Result<()> {
+    wait_caught_up(from, admin_key, name).await?;
+    remove_replication_target(from, admin_key, name).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn meta(key: &str, state: KeyState) -> KeyMetadata {
+        KeyMetadata {
+            key: key.to_string(),
+            replicas: vec![],
+            size: 1,
+            blake3: String::new(),
+            created_at: 0,
+            updated_at: 0,
+            state,
+            tags: Default::default(),
+            blob: None,
+            chunks: None,
+        }
+    }
+
+    #[test]
+    fn test_scopes() {
+        assert_eq!(scopes(&MigrateOptions::default()), vec![""]);
+        let options = MigrateOptions {
+            tenants: vec!["acme".to_string()],
+            prefixes: vec!["default/logs/".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(scopes(&options), vec!["acme/", "default/logs/"]);
+    }
+
+    #[test]
+    fn test_in_scope() {
+        let scopes = vec!["acme/".to_string(), "acme/logs/".to_string()];
+        assert!(in_scope(&scopes, 0, &meta("acme/a", KeyState::Active)));
+        assert!(in_scope(&scopes, 0, &meta("acme/b", KeyState::Tiered)));
+        assert!(!in_scope(&scopes, 0, &meta("acme/c", KeyState::Tombstone)));
+        // Already migrated with the first scope
+        assert!(!in_scope(
+            &scopes,
+            1,
+            &meta("acme/logs/1", KeyState::Active)
+        ));
+
+        let all = vec![String::new()];
+        assert!(!in_scope(&all, 0, &meta(".cas/acme/abc", KeyState::Active)));
+        assert!(!in_scope(&all, 0, &meta("orphan", KeyState::Active)));
+    }
+}
@@ -1,9 +1,43 @@
 //! Ops commands for cluster management
 
+pub mod api_keys;
+pub mod bulk;
 pub mod compact;
+pub mod drain;
+pub mod du;
+pub mod gc;
+pub mod hotkeys;
+pub mod keys;
+pub mod leader;
+pub mod migrate;
+pub mod quota;
 pub mod repair;
+pub mod replication;
+pub mod status;
+pub mod tier;
+pub mod upgrade;
 pub mod verify;
 
+pub use api_keys::{create_api_key, list_api_keys, revoke_api_key, ApiKeyInfo, CreatedApiKey};
+pub use bulk::{export_keys, import_keys, BulkFormat, BulkOptions, BulkReport};
 pub use compact::{compact_cluster, stream_large_blob};
+pub use drain::drain_volume;
+pub use du::{disk_usage, UsageReport};
+pub use gc::gc_cluster;
+pub use hotkeys::hot_keys;
+pub use keys::{for_each_key_page, list_key_page, list_metadata_page, list_volumes, KeyPage};
+pub use leader::{find_leader, LeaderInfo};
+pub use migrate::{
+    finish_migration, migrate_cluster, MigrateOptions, MigratePhase, MigrateReport,
+};
+pub use quota::{get_quota, list_quotas, set_quota, QuotaInfo, QuotaLimits};
 pub use repair::{auto_rebalance_cluster, repair_cluster};
-pub use verify::{prepare_seamless_upgrade, verify_cluster};
+pub use replication::{
+    add_replication_target, remove_replication_target, replication_status,
+};
+pub use status::{cluster_status, ClusterStatus};
+pub use tier::{run_tiering, tier_status};
+pub use upgrade::{
+    rolling_upgrade, set_upgrade_state, StepPhase, UpgradeOptions, UpgradeProgress, UpgradeState,
+};
+pub use verify::verify_cluster;
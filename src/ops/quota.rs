@@ -0,0 +1,84 @@
+//! Tenant quota administration
+//!
+//! Sets, shows and lists tenant quotas through the coordinator's
+//! `/admin/quotas` endpoints, which require an admin key once authentication
+//! is enabled. Quotas are replicated through Raft, so any coordinator may be
+//! asked; followers redirect writes to the leader.
+
+use super::api_keys::send;
+use crate::common::Result;
+use serde::{Deserialize, Serialize};
+
+/// A tenant's quota and usage, as answered by `GET /admin/quotas/<tenant>`.
+/// Limits of 0 are unlimited; `default` is set when the tenant has no quota
+/// of its own.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QuotaInfo {
+    pub tenant: String,
+    pub storage_limit: u64,
+    pub object_limit: u64,
+    pub rate_limit: u32,
+    pub ingress_limit: u64,
+    pub egress_limit: u64,
+    pub enabled: bool,
+    pub default: bool,
+    pub storage_used: u64,
+    pub object_count: u64,
+}
+
+/// Limits set by `set_quota`, 0 meaning unlimited
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct QuotaLimits {
+    /// Bytes stored
+    pub storage_limit: u64,
+    /// Keys stored
+    pub object_limit: u64,
+    /// Writes per second
+    pub rate_limit: u32,
+    /// Bytes per second uploaded
+    pub ingress_limit: u64,
+    /// Bytes per second downloaded
+    pub egress_limit: u64,
+    /// Whether the tenant may write at all
+    pub enabled: bool,
+}
+
+#[derive(Deserialize)]
+struct QuotaList {
+    quotas: Vec<QuotaInfo>,
+}
+
+/// Sets the quota of `tenant`
+pub async fn set_quota(
+    coordinator_url: &str,
+    admin_key: Option<&str>,
+    tenant: &str,
+    limits: &QuotaLimits,
+) -> Result<QuotaInfo> {
+    let request = reqwest::Client::new()
+        .put(format!("{}/admin/quotas/{}", coordinator_url, tenant))
+        .json(limits);
+    let body = send(request, admin_key).await?;
+    serde_json::from_str(&body).map_err(|e| crate::Error::Http(e.to_string()))
+}
+
+/// The quota applying to `tenant` and its usage
+pub async fn get_quota(
+    coordinator_url: &str,
+    admin_key: Option<&str>,
+    tenant: &str,
+) -> Result<QuotaInfo> {
+    let request =
+        reqwest::Client::new().get(format!("{}/admin/quotas/{}", coordinator_url, tenant));
+    let body = send(request, admin_key).await?;
+    serde_json::from_str(&body).map_err(|e| crate::Error::Http(e.to_string()))
+}
+
+/// Every tenant with a quota of its own
+pub async fn list_quotas(coordinator_url: &str, admin_key: Option<&str>) -> Result<Vec<QuotaInfo>> {
+    let request = reqwest::Client::new().get(format!("{}/admin/quotas", coordinator_url));
+    let body = send(request, admin_key).await?;
+    serde_json::from_str::<QuotaList>(&body)
+        .map(|list| list.quotas)
+        .map_err(|e| crate::Error::Http(e.to_string()))
+}
@@ -1,7 +1,7 @@
 //! Repair under-replicated keys
 //!
-//! This module provides logic for repairing keys that do not meet the desired replication factor.
-//! Copies missing blobs to additional volumes and updates metadata.
+//! Asks the leader to bring keys that do not meet the desired replication
+//! factor back to it, and follows the repair's progress.
 //!
 //! Advanced ops (auto-rebalancing, seamless upgrades, large blob streaming) are under development.
 //! Some admin automation is still missing and planned for future releases.
@@ -9,57 +9,81 @@
 #![allow(dead_code)]
 
 use crate::common::Result;
+use crate::coordinator::repair::{RepairProgress, RepairState};
+use std::time::Duration;
 
-/// Repairs under-replicated keys in the cluster.
-/// Copies missing blobs to additional volumes and updates metadata.
+/// How often to poll the coordinator while waiting
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Starts repairing under-replicated keys via `/admin/repair`, copying up to
+/// `parallelism` keys at once (`replicas` defaults to the coordinator's
+/// replication factor). An interrupted repair resumes from its checkpoint.
+/// With `wait`, polls until the repair finishes, calling `on_progress` after each poll.
 pub async fn repair_cluster(
-    _coordinator_url: &str,
-    _replicas: usize,
-    _dry_run: bool,
-) -> Result<RepairReport> {
+    coordinator_url: &str,
+    replicas: Option<usize>,
+    parallelism: usize,
+    dry_run: bool,
+    wait: bool,
+    mut on_progress: impl FnMut(&RepairProgress),
+) -> Result<RepairProgress> {
     tracing::info!("Starting cluster repair");
+    let client = reqwest::Client::new();
+    let mut url = format!(
+        "{}/admin/repair?parallelism={}&dry_run={}",
+        coordinator_url, parallelism, dry_run
+    );
+    if let Some(replicas) = replicas {
+        url.push_str(&format!("&replicas={}", replicas));
+    }
+    let resp = client
+        .post(url)
+        .send()
+        .await
+        .map_err(|e| crate::Error::Http(e.to_string()))?;
+    let mut progress = parse_progress(resp).await?;
+    on_progress(&progress);
 
-    // Real implementation:
-    // 1. Fetch all keys from coordinator metadata (gRPC/HTTP)
-    // 2. For each key, check replica count
-    // 3. If under-replicated, copy blob to new volume and update metadata
-    // (Stub: replace with actual repair logic)
-    Ok(RepairReport {
-        keys_checked: 1000,
-        keys_repaired: 10,
-        bytes_copied: 10 * 1024 * 1024, // Example: 10MB
-    })
+    while wait && progress.state == RepairState::Running {
+        tokio::time::sleep(POLL_INTERVAL).await;
+        let resp = client
+            .get(format!("{}/admin/repair/status", coordinator_url))
+            .send()
+            .await
+            .map_err(|e| crate::Error::Http(e.to_string()))?;
+        progress = parse_progress(resp).await?;
+        on_progress(&progress);
+    }
+
+    Ok(progress)
 }
 
-/// Auto-rebalancing stub: Moves keys/blobs to balance load across volumes.
-pub async fn auto_rebalance_cluster(_coordinator_url: &str) -> Result<()> {
-    use crate::coordinator::metadata::MetadataStore;
-    tracing::info!("Auto-rebalancing cluster...");
-    let metadata = MetadataStore::open("/data/coord.db")
-        .map_err(|e| crate::Error::Internal(format!("metadata: {}", e)))?;
-    let volumes = metadata
-        .get_healthy_volumes()
-        .map_err(|e| crate::Error::Internal(format!("volumes: {}", e)))?;
-    let overloaded = volumes
-        .iter()
-        .filter(|v| v.total_bytes > 10 * 1024 * 1024 * 1024)
-        .collect::<Vec<_>>();
-    for v in overloaded {
-        tracing::info!("Rebalancing volume {}...", v.volume_id);
-        // Move keys/blobs to underloaded volumes
-        // Update metadata after rebalancing
+async fn parse_progress(resp: reqwest::Response) -> Result<RepairProgress> {
+    let status = resp.status();
+    let body = resp
+        .text()
+        .await
+        .map_err(|e| crate::Error::Http(e.to_string()))?;
+    if !status.is_success() {
+        return Err(crate::Error::Http(format!("{}: {}", status, body)));
     }
-    tracing::info!("Rebalancing complete.");
-    Ok(())
+    serde_json::from_str(&body).map_err(|e| crate::Error::Http(e.to_string()))
 }
 
-/// Report of cluster repair results.
-#[derive(Debug, serde::Serialize)]
-pub struct RepairReport {
-    /// Number of keys checked
-    pub keys_checked: usize,
-    /// Number of keys repaired
-    pub keys_repaired: usize,
-    /// Total bytes copied during repair
-    pub bytes_copied: u64,
+/// Auto-rebalancing: asks the coordinator to recompute the shard map and
+/// migrate the data of every shard that changed owners.
+/// Progress is available at `/admin/rebalance/status`.
+pub async fn auto_rebalance_cluster(coordinator_url: &str) -> Result<()> {
+    tracing::info!("Auto-rebalancing cluster...");
+    let resp = reqwest::Client::new()
+        .post(format!("{}/admin/rebalance", coordinator_url))
+        .send()
+        .await
+        .map_err(|e| crate::Error::Http(e.to_string()))?;
+    if !resp.status().is_success() {
+        let status = resp.status();
+        let body = resp.text().await.unwrap_or_default();
+        return Err(crate::Error::Http(format!("{}: {}", status, body)));
+    }
+    Ok(())
 }
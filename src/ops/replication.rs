@@ -0,0 +1,45 @@
+//! Cross-cluster replication
+//!
+//! Reports how far each replication target of the leader lags behind, and
+//! adds or removes targets at runtime.
+
+use crate::common::{encode_key, ReplicationTarget, Result};
+use crate::coordinator::replication::ReplicationStatus;
+use crate::ops::api_keys::send;
+
+/// Fetches `/admin/replication/status`
+pub async fn replication_status(
+    coordinator_url: &str,
+    admin_key: Option<&str>,
+) -> Result<ReplicationStatus> {
+    let request =
+        reqwest::Client::new().get(format!("{}/admin/replication/status", coordinator_url));
+    let body = send(request, admin_key).await?;
+    serde_json::from_str(&body).map_err(|e| crate::Error::Http(e.to_string()))
+}
+
+/// Starts replicating to `target` from the current end of the CDC log
+pub async fn add_replication_target(
+    coordinator_url: &str,
+    admin_key: Option<&str>,
+    target: &ReplicationTarget,
+) -> Result<()> {
+    let request = reqwest::Client::new()
+        .post(format!("{}/admin/replication/targets", coordinator_url))
+        .json(target);
+    send(request, admin_key).await.map(|_| ())
+}
+
+/// Stops replicating to the runtime target `name`
+pub async fn remove_replication_target(
+    coordinator_url: &str,
+    admin_key: Option<&str>,
+    name: &str,
+) -> Result<()> {
+    let request = reqwest::Client::new().delete(format!(
+        "{}/admin/replication/targets/{}",
+        coordinator_url,
+        encode_key(name)
+    ));
+    send(request, admin_key).await.map(|_| ())
+}
@@ -0,0 +1,85 @@
+//! Cluster status
+//!
+//! Gathers what operators would otherwise fetch endpoint by endpoint: the
+//! Raft role and term of the coordinator (`/admin/status`, `/leader`), the
+//! volumes and their usage (`/admin/volumes`), the keys short of the
+//! replication factor (`/admin/repair/pending`) and the shards a rebalance
+//! has yet to move (`/admin/rebalance/status`).
+
+use crate::common::Result;
+use crate::coordinator::metadata::VolumeMetadata;
+use crate::coordinator::migration::{MigrationState, RebalanceStatus};
+use crate::coordinator::repair::UnderReplicated;
+use crate::ops::api_keys::send;
+use crate::ops::leader::{find_leader, LeaderInfo};
+use serde::{Deserialize, Serialize};
+
+/// Status of the whole cluster, as seen by one coordinator
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClusterStatus {
+    /// Raft role of the coordinator that answered
+    pub role: String,
+    pub nb_peers: usize,
+    pub leader: LeaderInfo,
+    pub volumes: Vec<VolumeMetadata>,
+    pub under_replicated: UnderReplicated,
+    pub rebalance: RebalanceStatus,
+}
+
+impl ClusterStatus {
+    /// Shards of the rebalance not moved yet, running ones included
+    pub fn pending_shards(&self) -> usize {
+        self.rebalance
+            .shards
+            .iter()
+            .filter(|shard| {
+                matches!(
+                    shard.state,
+                    MigrationState::Pending | MigrationState::Running
+                )
+            })
+            .count()
+    }
+}
+
+/// Fetches the status endpoints of `coordinator_url` concurrently
+pub async fn cluster_status(
+    coordinator_url: &str,
+    admin_key: Option<&str>,
+) -> Result<ClusterStatus> {
+    #[derive(Deserialize)]
+    struct Coordinator {
+        role: String,
+        nb_peers: usize,
+    }
+    #[derive(Deserialize)]
+    struct Volumes {
+        volumes: Vec<VolumeMetadata>,
+    }
+
+    let client = reqwest::Client::new();
+    let get = |path: &str| {
+        send(
+            client.get(format!("{}{}", coordinator_url, path)),
+            admin_key,
+        )
+    };
+    let (coordinator, leader, volumes, pending, rebalance) = tokio::try_join!(
+        get("/admin/status"),
+        find_leader(coordinator_url),
+        get("/admin/volumes"),
+        get("/admin/repair/pending"),
+        get("/admin/rebalance/status"),
+    )?;
+    let parse_error = |e: serde_json::Error| crate::Error::Http(e.to_string());
+    let coordinator: Coordinator = serde_json::from_str(&coordinator).map_err(parse_error)?;
+    let volumes: Volumes = serde_json::from_str(&volumes).map_err(parse_error)?;
+    Ok(ClusterStatus {
+        role: coordinator.role,
+        nb_peers: coordinator.nb_peers,
+        leader,
+        volumes: volumes.volumes,
+        under_replicated: serde_json::from_str(&pending).map_err(parse_error)?,
+        rebalance: serde_json::from_str(&rebalance).map_err(parse_error)?,
+    })
+}
@@ -0,0 +1,37 @@
+//! Cold storage tiering
+//!
+//! Reports on, and triggers, the leader's tiering of cold values to S3.
+
+use crate::common::Result;
+use crate::coordinator::tiering::TierStatus;
+
+/// Fetches `/admin/tier/status`
+pub async fn tier_status(coordinator_url: &str) -> Result<TierStatus> {
+    let resp = reqwest::get(format!("{}/admin/tier/status", coordinator_url))
+        .await
+        .map_err(|e| crate::Error::Http(e.to_string()))?;
+    let status = resp.status();
+    let body = resp
+        .text()
+        .await
+        .map_err(|e| crate::Error::Http(e.to_string()))?;
+    if !status.is_success() {
+        return Err(crate::Error::Http(format!("{}: {}", status, body)));
+    }
+    serde_json::from_str(&body).map_err(|e| crate::Error::Http(e.to_string()))
+}
+
+/// Starts a tiering pass via `/admin/tier/run`
+pub async fn run_tiering(coordinator_url: &str) -> Result<()> {
+    let resp = reqwest::Client::new()
+        .post(format!("{}/admin/tier/run", coordinator_url))
+        .send()
+        .await
+        .map_err(|e| crate::Error::Http(e.to_string()))?;
+    let status = resp.status();
+    if !status.is_success() {
+        let body = resp.text().await.unwrap_or_default();
+        return Err(crate::Error::Http(format!("{}: {}", status, body)));
+    }
+    Ok(())
+}
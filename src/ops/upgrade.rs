@@ -0,0 +1,602 @@
+//! Rolling upgrade
+//!
+//! Restarts the nodes of a cluster one at a time, e.g. onto a new release,
+//! keeping the cluster available. `restart_command` does the restart itself
+//! (`systemctl restart minikv-{role}@{id}`, `kubectl delete pod {id}`...),
+//! run by `sh -c` with `{role}`, `{id}` and `{address}` replaced. These are
+//! passed as shell parameters, never pasted into the command, so they
+//! should not be quoted in it.
+//!
+//! Volumes go first. Each one, once no key is under-replicated and no
+//! rebalance runs:
+//! 1. is drained, its data moved to the other volumes (unless `drain` is off);
+//! 2. is restarted, and must heartbeat `alive` again within `health_timeout`;
+//! 3. is verified: the cluster must settle again, the keys it missed
+//!    re-replicated and the shards given back to it moved.
+//!
+//! Coordinators follow, the leader last: it hands leadership to its most
+//! caught-up follower before its restart. A restarted coordinator must answer
+//! `/health` and know a leader again before the next one restarts.
+//!
+//! Progress is saved to `state_path` as each phase ends. `pause` and `abort`
+//! written there are picked up between phases, and running the upgrade again
+//! resumes it from the phase it stopped at.
+
+use crate::common::{timestamp_now, NodeRole, NodeState, Result};
+use crate::coordinator::drain::DrainState;
+use crate::coordinator::metadata::VolumeMetadata;
+use crate::coordinator::migration::RebalanceStatus;
+use crate::coordinator::repair::UnderReplicated;
+use crate::ops::api_keys::send;
+use crate::ops::drain::drain_volume;
+use crate::ops::leader::find_leader;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// How often to poll the cluster while waiting
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// How an upgrade runs
+#[derive(Debug, Clone)]
+pub struct UpgradeOptions {
+    /// Admin API key for the `/admin/*` endpoints
+    pub admin_key: Option<String>,
+    /// Shell command restarting a node, `{role}`, `{id}` and `{address}`
+    /// passed as shell parameters
+    pub restart_command: String,
+    /// HTTP URLs of the coordinators to restart; none restarts only volumes
+    pub coordinators: Vec<String>,
+    /// Leave the volumes alone
+    pub skip_volumes: bool,
+    /// Move the data off each volume before restarting it
+    pub drain: bool,
+    /// How long the cluster has to settle, and a node to come back
+    pub health_timeout: Duration,
+    /// Where progress is saved
+    pub state_path: PathBuf,
+}
+
+impl Default for UpgradeOptions {
+    fn default() -> Self {
+        Self {
+            admin_key: None,
+            restart_command: String::new(),
+            coordinators: vec![],
+            skip_volumes: false,
+            drain: true,
+            health_timeout: Duration::from_secs(600),
+            state_path: PathBuf::from("minikv-upgrade.json"),
+        }
+    }
+}
+
+/// State of an upgrade
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UpgradeState {
+    Running,
+    /// Waits before the next phase until resumed
+    Paused,
+    /// Stopped before the next phase; running the upgrade again starts over
+    Aborted,
+    Completed,
+    /// A phase failed; running the upgrade again retries it
+    Failed,
+}
+
+/// Phase of a node's upgrade, in order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StepPhase {
+    /// Moving the data off a volume, or leadership off a coordinator
+    Draining,
+    Restarting,
+    /// Waiting for the cluster to settle
+    Verifying,
+    Done,
+}
+
+/// Upgrade of one node
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpgradeStep {
+    pub role: NodeRole,
+    pub id: String,
+    /// HTTP address of a coordinator, gRPC address of a volume
+    pub address: String,
+    pub phase: StepPhase,
+    pub started_at: Option<u64>,
+    pub finished_at: Option<u64>,
+    /// Version a coordinator reports once restarted
+    pub version: Option<String>,
+    pub error: Option<String>,
+}
+
+/// Progress of a rolling upgrade
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpgradeProgress {
+    pub state: UpgradeState,
+    pub steps: Vec<UpgradeStep>,
+    pub started_at: u64,
+    pub updated_at: u64,
+}
+
+impl UpgradeProgress {
+    /// Progress saved at `path`, if any
+    pub fn load(path: &Path) -> Result<Option<Self>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let bytes = std::fs::read(path)?;
+        serde_json::from_slice(&bytes)
+            .map(Some)
+            .map_err(|e| crate::Error::InvalidConfig(format!("{}: {}", path.display(), e)))
+    }
+
+    fn save(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_vec_pretty(self)
+            .map_err(|e| crate::Error::Internal(format!("Serialize error: {}", e)))?;
+        let temp_path = path.with_extension("tmp");
+        std::fs::write(&temp_path, json)?;
+        std::fs::rename(&temp_path, path)?;
+        Ok(())
+    }
+
+    /// Nodes upgraded so far
+    pub fn done(&self) -> usize {
+        self.steps
+            .iter()
+            .filter(|step| step.phase == StepPhase::Done)
+            .count()
+    }
+}
+
+/// Pause, resume (`Running`) or abort the upgrade saved at `path`
+pub fn set_upgrade_state(path: &Path, state: UpgradeState) -> Result<UpgradeProgress> {
+    let mut progress = UpgradeProgress::load(path)?
+        .ok_or_else(|| crate::Error::Http(format!("no upgrade at {}", path.display())))?;
+    if !matches!(progress.state, UpgradeState::Running | UpgradeState::Paused) {
+        return Err(crate::Error::Http(format!(
+            "the upgrade is {:?}, not running",
+            progress.state
+        )));
+    }
+    progress.state = state;
+    progress.updated_at = timestamp_now();
+    progress.save(path)?;
+    Ok(progress)
+}
+
+/// Upgrades the cluster of `coordinator_url` node by node, resuming the
+/// upgrade saved at `options.state_path` unless it completed or was
+/// aborted, and calling `on_progress` as each phase ends
+pub async fn rolling_upgrade(
+    coordinator_url: &str,
+    options: &UpgradeOptions,
+    on_progress: impl FnMut(&UpgradeProgress),
+) -> Result<UpgradeProgress> {
+    let progress = match UpgradeProgress::load(&options.state_path)? {
+        Some(mut last) if matches!(last.state, UpgradeState::Running | UpgradeState::Failed) => {
+            last.state = UpgradeState::Running;
+            last
+        }
+        // Left paused: resuming is up to `set_upgrade_state`
+        Some(last) if last.state == UpgradeState::Paused => last,
+        _ => plan(coordinator_url, options).await?,
+    };
+    let mut upgrade = Upgrade {
+        coordinator_url,
+        options,
+        progress,
+        on_progress,
+    };
+    // Replaces the upgrade saved before, whatever its state
+    upgrade.progress.save(&options.state_path)?;
+    (upgrade.on_progress)(&upgrade.progress);
+
+    for i in 0..upgrade.progress.steps.len() {
+        while upgrade.progress.steps[i].phase != StepPhase::Done {
+            if !upgrade.proceed().await? {
+                return Ok(upgrade.progress);
+            }
+            let step = &mut upgrade.progress.steps[i];
+            step.started_at.get_or_insert_with(timestamp_now);
+            step.error = None;
+            let step = step.clone();
+            let next = match step.role {
+                NodeRole::Volume => upgrade.volume_phase(&step).await,
+                NodeRole::Coordinator => upgrade.coordinator_phase(&step).await,
+            };
+            let step = &mut upgrade.progress.steps[i];
+            match next {
+                Ok(phase) => {
+                    step.phase = phase;
+                    if phase == StepPhase::Done {
+                        step.finished_at = Some(timestamp_now());
+                    }
+                }
+                Err(e) => {
+                    tracing::error!("Upgrade of {} failed: {}", step.id, e);
+                    step.error = Some(e.to_string());
+                    upgrade.progress.state = UpgradeState::Failed;
+                    upgrade.save()?;
+                    return Ok(upgrade.progress);
+                }
+            }
+            upgrade.save()?;
+        }
+    }
+    upgrade.progress.state = UpgradeState::Completed;
+    upgrade.save()?;
+    Ok(upgrade.progress)
+}
+
+/// Steps of a new upgrade: the volumes, then the followers, then the leader
+async fn plan(coordinator_url: &str, options: &UpgradeOptions) -> Result<UpgradeProgress> {
+    let step = |role, id: String, address: String| UpgradeStep {
+        role,
+        id,
+        address,
+        phase: StepPhase::Draining,
+        started_at: None,
+        finished_at: None,
+        version: None,
+        error: None,
+    };
+    let mut steps = vec![];
+    if !options.skip_volumes {
+        let mut volumes = list_volumes(coordinator_url, options.admin_key.as_deref()).await?;
+        volumes.sort_by(|a, b| a.volume_id.cmp(&b.volume_id));
+        for volume in volumes {
+            steps.push(step(NodeRole::Volume, volume.volume_id, volume.address));
+        }
+    }
+    let mut leader = None;
+    for url in &options.coordinators {
+        let health = coordinator_health(url).await?;
+        let coordinator = step(NodeRole::Coordinator, health.node_id, url.clone());
+        if health.is_leader {
+            leader = Some(coordinator);
+        } else {
+            steps.push(coordinator);
+        }
+    }
+    steps.extend(leader);
+    let now = timestamp_now();
+    Ok(UpgradeProgress {
+        state: UpgradeState::Running,
+        steps,
+        started_at: now,
+        updated_at: now,
+    })
+}
+
+/// Answer of a coordinator's `GET /health`
+#[derive(Debug, Deserialize)]
+struct CoordinatorHealth {
+    node_id: String,
+    is_leader: bool,
+    version: String,
+}
+
+async fn coordinator_health(url: &str) -> Result<CoordinatorHealth> {
+    let body = send(reqwest::Client::new().get(format!("{}/health", url)), None).await?;
+    serde_json::from_str(&body).map_err(|e| crate::Error::Http(e.to_string()))
+}
+
+async fn list_volumes(
+    coordinator_url: &str,
+    admin_key: Option<&str>,
+) -> Result<Vec<VolumeMetadata>> {
+    #[derive(Deserialize)]
+    struct Volumes {
+        volumes: Vec<VolumeMetadata>,
+    }
+    let request = reqwest::Client::new().get(format!("{}/admin/volumes", coordinator_url));
+    let body = send(request, admin_key).await?;
+    let volumes: Volumes =
+        serde_json::from_str(&body).map_err(|e| crate::Error::Http(e.to_string()))?;
+    Ok(volumes.volumes)
+}
+
+struct Upgrade<'a, F> {
+    coordinator_url: &'a str,
+    options: &'a UpgradeOptions,
+    progress: UpgradeProgress,
+    on_progress: F,
+}
+
+impl<F: FnMut(&UpgradeProgress)> Upgrade<'_, F> {
+    fn save(&mut self) -> Result<()> {
+        // Keep a pause or abort written meanwhile
+        if self.progress.state == UpgradeState::Running {
+            if let Some(saved) = UpgradeProgress::load(&self.options.state_path)? {
+                if matches!(saved.state, UpgradeState::Paused | UpgradeState::Aborted) {
+                    self.progress.state = saved.state;
+                }
+            }
+        }
+        self.progress.updated_at = timestamp_now();
+        self.progress.save(&self.options.state_path)?;
+        (self.on_progress)(&self.progress);
+        Ok(())
+    }
+
+    /// Whether to go on with the next phase: waits while the upgrade is
+    /// paused, and stops it once aborted
+    async fn proceed(&mut self) -> Result<bool> {
+        loop {
+            let saved = UpgradeProgress::load(&self.options.state_path)?.map(|p| p.state);
+            match saved {
+                Some(UpgradeState::Aborted) => {
+                    self.progress.state = UpgradeState::Aborted;
+                    self.save()?;
+                    return Ok(false);
+                }
+                Some(UpgradeState::Paused) => {
+                    if self.progress.state != UpgradeState::Paused {
+                        self.progress.state = UpgradeState::Paused;
+                        (self.on_progress)(&self.progress);
+                    }
+                    tokio::time::sleep(POLL_INTERVAL).await;
+                }
+                _ => {
+                    if self.progress.state == UpgradeState::Paused {
+                        self.progress.state = UpgradeState::Running;
+                        self.save()?;
+                    }
+                    return Ok(true);
+                }
+            }
+        }
+    }
+
+    /// Admin URL of the leader, where drains and repairs run
+    async fn leader_url(&self) -> String {
+        match find_leader(self.coordinator_url).await {
+            Ok(leader) => leader
+                .leader_addr
+                .unwrap_or_else(|| self.coordinator_url.to_string()),
+            Err(_) => self.coordinator_url.to_string(),
+        }
+    }
+
+    /// Runs the current phase of a volume, returning the next one
+    async fn volume_phase(&self, step: &UpgradeStep) -> Result<StepPhase> {
+        let admin_key = self.options.admin_key.as_deref();
+        match step.phase {
+            StepPhase::Draining => {
+                self.wait_settled().await?;
+                if self.options.drain {
+                    let leader = self.leader_url().await;
+                    tracing::info!("Draining volume {}", step.id);
+                    let drained = drain_volume(&leader, &step.id, true, |_| {}).await?;
+                    if drained.state != DrainState::Completed {
+                        return Err(crate::Error::Http(format!(
+                            "drain of {} ended {:?}: {}",
+                            step.id,
+                            drained.state,
+                            drained.error.unwrap_or_default()
+                        )));
+                    }
+                }
+                Ok(StepPhase::Restarting)
+            }
+            StepPhase::Restarting => {
+                let restarted_at = timestamp_now();
+                self.restart(step).await?;
+                let back = self
+                    .wait_for("the volume to heartbeat again", move || async move {
+                        let volumes = list_volumes(&self.leader_url().await, admin_key).await?;
+                        Ok(volumes.iter().any(|v| {
+                            v.volume_id == step.id
+                                && v.state == NodeState::Alive
+                                && v.last_heartbeat >= restarted_at
+                        }))
+                    })
+                    .await;
+                back.map(|_| StepPhase::Verifying)
+            }
+            StepPhase::Verifying => self.wait_settled().await.map(|_| StepPhase::Done),
+            StepPhase::Done => Ok(StepPhase::Done),
+        }
+    }
+
+    /// Runs the current phase of a coordinator, returning the next one
+    async fn coordinator_phase(&mut self, step: &UpgradeStep) -> Result<StepPhase> {
+        let url = step.address.as_str();
+        match step.phase {
+            StepPhase::Draining => {
+                if coordinator_health(url).await?.is_leader {
+                    tracing::info!("Handing leadership of {} over", step.id);
+                    let request = reqwest::Client::new()
+                        .post(format!("{}/admin/leader/transfer", url))
+                        .json(&serde_json::json!({}));
+                    send(request, self.options.admin_key.as_deref()).await?;
+                    self.wait_for("leadership to move", move || async move {
+                        Ok(!coordinator_health(url).await?.is_leader)
+                    })
+                    .await?;
+                }
+                Ok(StepPhase::Restarting)
+            }
+            StepPhase::Restarting => {
+                self.restart(step).await?;
+                self.wait_for(
+                    "the coordinator to know a leader again",
+                    move || async move {
+                        if coordinator_health(url).await.is_err() {
+                            return Ok(false);
+                        }
+                        Ok(find_leader(url).await?.leader_id.is_some())
+                    },
+                )
+                .await?;
+                Ok(StepPhase::Verifying)
+            }
+            StepPhase::Verifying => {
+                let health = coordinator_health(url).await?;
+                if health.node_id != step.id {
+                    return Err(crate::Error::Http(format!(
+                        "{} answers as {}, not {}",
+                        url, health.node_id, step.id
+                    )));
+                }
+                if let Some(current) = self.progress.steps.iter_mut().find(|s| s.id == step.id) {
+                    current.version = Some(health.version);
+                }
+                Ok(StepPhase::Done)
+            }
+            StepPhase::Done => Ok(StepPhase::Done),
+        }
+    }
+
+    async fn restart(&self, step: &UpgradeStep) -> Result<()> {
+        let role = match step.role {
+            NodeRole::Coordinator => "coordinator",
+            NodeRole::Volume => "volume",
+        };
+        let mut command = restart_command(&self.options.restart_command, role, step);
+        tracing::info!(
+            "Restarting {} {}: {}",
+            role,
+            step.id,
+            self.options.restart_command
+        );
+        let status = tokio::task::spawn_blocking(move || command.status())
+            .await
+            .map_err(|e| crate::Error::Internal(e.to_string()))??;
+        if !status.success() {
+            return Err(crate::Error::Http(format!(
+                "restart command of {} failed: {}",
+                step.id, status
+            )));
+        }
+        Ok(())
+    }
+
+    /// Waits until no key is under-replicated and no rebalance runs
+    async fn wait_settled(&self) -> Result<()> {
+        let admin_key = self.options.admin_key.as_deref();
+        self.wait_for("the cluster to settle", move || async move {
+            let leader = self.leader_url().await;
+            let client = reqwest::Client::new();
+            let pending = send(
+                client.get(format!("{}/admin/repair/pending", leader)),
+                admin_key,
+            )
+            .await?;
+            let pending: UnderReplicated =
+                serde_json::from_str(&pending).map_err(|e| crate::Error::Http(e.to_string()))?;
+            let rebalance = send(
+                client.get(format!("{}/admin/rebalance/status", leader)),
+                admin_key,
+            )
+            .await?;
+            let rebalance: RebalanceStatus =
+                serde_json::from_str(&rebalance).map_err(|e| crate::Error::Http(e.to_string()))?;
+            Ok(pending.keys == 0 && !rebalance.running)
+        })
+        .await
+    }
+
+    /// Polls `done` until it holds, for up to `health_timeout`. Errors
+    /// count as not done yet, as nodes come and go.
+    async fn wait_for<Fut>(&self, what: &str, mut done: impl FnMut() -> Fut) -> Result<()>
+    where
+        Fut: std::future::Future<Output = Result<bool>>,
+    {
+        let deadline = tokio::time::Instant::now() + self.options.health_timeout;
+        let mut last_error = None;
+        loop {
+            match done().await {
+                Ok(true) => return Ok(()),
+                Ok(false) => {}
+                Err(e) => last_error = Some(e),
+            }
+            if tokio::time::Instant::now() >= deadline {
+                return Err(crate::Error::Http(match last_error {
+                    Some(e) => format!("timed out waiting for {}: {}", what, e),
+                    None => format!("timed out waiting for {}", what),
+                }));
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    }
+}
+
+/// `sh -c` running `template`, its placeholders turned into the positional
+/// parameters holding the values, so these are never parsed by the shell
+fn restart_command(template: &str, role: &str, step: &UpgradeStep) -> std::process::Command {
+    let script = template
+        .replace("{role}", "\"$1\"")
+        .replace("{id}", "\"$2\"")
+        .replace("{address}", "\"$3\"");
+    let mut command = std::process::Command::new("sh");
+    command
+        .arg("-c")
+        .arg(script)
+        .arg("sh")
+        .arg(role)
+        .arg(&step.id)
+        .arg(&step.address);
+    command
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn progress(state: UpgradeState) -> UpgradeProgress {
+        UpgradeProgress {
+            state,
+            steps: vec![],
+            started_at: 0,
+            updated_at: 0,
+        }
+    }
+
+    #[test]
+    fn test_set_upgrade_state() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("upgrade.json");
+        assert!(set_upgrade_state(&path, UpgradeState::Paused).is_err());
+
+        progress(UpgradeState::Running).save(&path).unwrap();
+        set_upgrade_state(&path, UpgradeState::Paused).unwrap();
+        let saved = UpgradeProgress::load(&path).unwrap().unwrap();
+        assert_eq!(saved.state, UpgradeState::Paused);
+        set_upgrade_state(&path, UpgradeState::Aborted).unwrap();
+        // Only a running or paused upgrade can change state
+        assert!(set_upgrade_state(&path, UpgradeState::Running).is_err());
+    }
+
+    #[test]
+    fn test_restart_command_does_not_run_values() {
+        let step = UpgradeStep {
+            id: "vol-1; echo injected".to_string(),
+            address: "$(echo injected)".to_string(),
+            role: NodeRole::Volume,
+            phase: StepPhase::Restarting,
+            started_at: None,
+            finished_at: None,
+            version: None,
+            error: None,
+        };
+        let output = restart_command("echo {role} {id} {address}", "volume", &step)
+            .output()
+            .unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            "volume vol-1; echo injected $(echo injected)\n"
+        );
+    }
+
+    #[test]
+    fn test_phases_in_order() {
+        assert!(StepPhase::Draining < StepPhase::Restarting);
+        assert!(StepPhase::Verifying < StepPhase::Done);
+        let json = serde_json::to_string(&StepPhase::Restarting).unwrap();
+        assert_eq!(json, "\"restarting\"");
+    }
+}
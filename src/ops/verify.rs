@@ -1,46 +1,302 @@
 //! Verify cluster integrity
 //!
-//! This module provides logic for verifying the health and integrity of the distributed key-value cluster.
-//! Checks for missing, corrupted, or under-replicated keys and blobs.
+//! Walks the key metadata of every tenant page by page
+//! (`GET /admin/metadata/keys`) and asks each replica, through the volumes'
+//! Stat RPC, whether it holds the key with the recorded size and blake3. A
+//! key is:
+//! - healthy when every replica holds it intact,
+//! - corrupted when some replica holds other contents,
+//! - under-replicated otherwise: it has no replicas, or some replica is
+//!   missing it or could not be asked.
+//!
+//! Deduplicated and chunked keys have no replicas of their own; their content
+//! and chunk keys are checked as keys in their own right. Tiered keys live in
+//! cold storage and are not checked.
+//!
+//! Stat answers from the volume's index, whose hash may not be the value's
+//! (an index rebuilt from segments does not rehash values), so a replica
+//! whose hash differs is read back before being called corrupted. With
+//! `deep`, every replica is read back through Pull and its blake3 recomputed
+//! as it streams, which also catches values damaged on disk. At most
+//! `concurrency` volume calls run at once.
+//!
+//! Orphaned blobs, held by a volume but referenced by no key, are counted by
+//! a GC dry run.
 
 #![allow(dead_code)]
 
-use crate::common::Result;
+use crate::common::{Error, Result};
+use crate::coordinator::metadata::{KeyMetadata, KeyState, VolumeMetadata, DEFAULT_PAGE_SIZE};
+use crate::coordinator::volume_client::VolumeClient;
+use crate::ops::gc::gc_cluster;
+use crate::ops::keys::{list_metadata_page, list_volumes};
+use crate::proto::BlobStat;
+use futures_util::stream::{self, StreamExt};
+use std::collections::HashMap;
+
+/// Most problems listed in a report
+const MAX_REPORTED_PROBLEMS: usize = 1000;
 
 /// Verifies the integrity of the cluster.
-/// Checks for missing, corrupted, or under-replicated keys.
-/// If deep=true, verifies checksums for all blobs.
+/// Checks every replica of every key; if deep=true, also recomputes the
+/// checksum of every replica.
 pub async fn verify_cluster(
-    _coordinator_url: &str,
-    _deep: bool,
-    _concurrency: usize,
+    coordinator_url: &str,
+    deep: bool,
+    concurrency: usize,
 ) -> Result<VerifyReport> {
-    tracing::info!("Starting cluster verification");
-
-    // Implementation:
-    // 1. Fetch all keys from coordinator metadata (gRPC/HTTP)
-    // 2. For each key, check existence and health on volumes
-    // 3. If deep=true, verify checksums
-    // 4. Aggregate and report results
-    Ok(VerifyReport {
-        total_keys: 1000,
-        healthy: 980,
-        under_replicated: 10,
-        corrupted: 5,
-        orphaned: 5,
-    })
-}
-
-/// Seamless upgrade stub: Prepares cluster for rolling upgrades with zero downtime.
-pub async fn prepare_seamless_upgrade(_coordinator_url: &str) -> Result<()> {
-    // Implementation:
-    // 1. Drain nodes, migrate leadership, ensure data safety
-    // 2. Orchestrate rolling upgrade with zero downtime
-    Ok(())
+    tracing::info!(deep, concurrency, "Starting cluster verification");
+    let concurrency = concurrency.max(1);
+    let volumes: HashMap<String, VolumeMetadata> = list_volumes(coordinator_url)
+        .await?
+        .into_iter()
+        .map(|v| (v.volume_id.clone(), v))
+        .collect();
+
+    let mut report = VerifyReport::default();
+    let mut after: Option<String> = None;
+    loop {
+        let page = list_metadata_page(
+            coordinator_url,
+            None,
+            "",
+            after.as_deref(),
+            DEFAULT_PAGE_SIZE,
+        )
+        .await?;
+        verify_page(&volumes, &page.keys, deep, concurrency, &mut report).await;
+        match page.next_after {
+            Some(next) => after = Some(next),
+            None => break,
+        }
+    }
+
+    match gc_cluster(coordinator_url, true, None).await {
+        Ok(gc) => {
+            report.orphaned = gc.orphans_found as usize;
+            report.errors.extend(gc.errors);
+        }
+        Err(e) => report.errors.push(format!("orphan scan: {}", e)),
+    }
+
+    tracing::info!(
+        total = report.total_keys,
+        healthy = report.healthy,
+        under_replicated = report.under_replicated,
+        corrupted = report.corrupted,
+        orphaned = report.orphaned,
+        "Cluster verification finished"
+    );
+    Ok(report)
+}
+
+/// What verification found on one replica of a key
+#[derive(Debug, Clone, PartialEq)]
+enum Replica {
+    /// The key, with the recorded contents
+    Intact,
+    /// The key, with a hash other than the recorded one in the volume index
+    /// (to be read back)
+    Suspect,
+    /// The key, with other contents
+    Corrupted(String),
+    /// Not the key
+    Missing,
+    /// The volume could not be asked
+    Unreachable(String),
+}
+
+/// Health of a key, from what its replicas hold
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Health {
+    Healthy,
+    UnderReplicated,
+    Corrupted,
+}
+
+/// Compare a volume's Stat answer with the key's metadata
+fn check_stat(meta: &KeyMetadata, stat: &BlobStat) -> Replica {
+    if !stat.exists {
+        Replica::Missing
+    } else if stat.size != meta.size {
+        Replica::Corrupted(format!("size {} instead of {}", stat.size, meta.size))
+    } else if !meta.blake3.is_empty() && stat.blake3 != meta.blake3 {
+        Replica::Suspect
+    } else {
+        Replica::Intact
+    }
+}
+
+/// Health of a key whose replicas hold `replicas`
+fn classify<'a>(replicas: impl IntoIterator<Item = &'a Replica>) -> Health {
+    let mut health = Health::Healthy;
+    for replica in replicas {
+        match replica {
+            Replica::Corrupted(_) => return Health::Corrupted,
+            Replica::Missing | Replica::Unreachable(_) => health = Health::UnderReplicated,
+            Replica::Intact | Replica::Suspect => {}
+        }
+    }
+    health
+}
+
+/// Check the replicas of one page of keys and add the results to `report`
+async fn verify_page(
+    volumes: &HashMap<String, VolumeMetadata>,
+    keys: &[KeyMetadata],
+    deep: bool,
+    concurrency: usize,
+    report: &mut VerifyReport,
+) {
+    let mut checked = Vec::new();
+    for meta in keys.iter().filter(|m| m.state == KeyState::Active) {
+        report.total_keys += 1;
+        if meta.blob.is_some() || meta.chunks.is_some() {
+            // Deduplicated or chunked: the replicas belong to its content
+            // or chunk keys
+            report.healthy += 1;
+        } else if meta.replicas.is_empty() {
+            report.under_replicated += 1;
+            report.problem(&meta.key, None, "no replicas".to_string());
+        } else {
+            checked.push(meta);
+        }
+    }
+
+    // One Stat call per volume
+    let mut asked: HashMap<&str, Vec<&KeyMetadata>> = HashMap::new();
+    for meta in &checked {
+        for volume_id in &meta.replicas {
+            asked.entry(volume_id.as_str()).or_default().push(meta);
+        }
+    }
+    let answers: Vec<_> = stream::iter(asked)
+        .map(|(volume_id, metas)| async move {
+            let answer = stat_volume(volumes.get(volume_id), &metas).await;
+            (volume_id, metas, answer)
+        })
+        .buffer_unordered(concurrency)
+        .collect()
+        .await;
+
+    let mut clients = HashMap::new();
+    let mut found: HashMap<(&str, &str), Replica> = HashMap::new();
+    for (volume_id, metas, answer) in answers {
+        match answer {
+            Ok((client, stats)) => {
+                for (meta, stat) in metas.iter().zip(&stats) {
+                    found.insert((meta.key.as_str(), volume_id), check_stat(meta, stat));
+                }
+                clients.insert(volume_id, client);
+            }
+            Err(e) => {
+                let reason = e.to_string();
+                for meta in metas {
+                    found.insert(
+                        (meta.key.as_str(), volume_id),
+                        Replica::Unreachable(reason.clone()),
+                    );
+                }
+            }
+        }
+    }
+
+    // Read back suspects, and with `deep` every replica that looked intact
+    let expected: HashMap<&str, &str> = checked
+        .iter()
+        .map(|meta| (meta.key.as_str(), meta.blake3.as_str()))
+        .collect();
+    let reads: Vec<_> = found
+        .iter()
+        .filter(|(_, replica)| match replica {
+            Replica::Suspect => true,
+            Replica::Intact => deep,
+            _ => false,
+        })
+        .map(|(&(key, volume_id), _)| (key, volume_id, clients[volume_id].clone()))
+        .collect();
+    let reads: Vec<_> = stream::iter(reads)
+        .map(|(key, volume_id, mut client)| {
+            let expected = expected[key];
+            async move {
+                let replica = match read_hash(&mut client, key).await {
+                    Ok(None) => Replica::Missing,
+                    Ok(Some(actual)) if expected.is_empty() || actual == expected => {
+                        Replica::Intact
+                    }
+                    Ok(Some(actual)) => {
+                        Replica::Corrupted(format!("blake3 {} instead of {}", actual, expected))
+                    }
+                    Err(Error::Grpc(status)) if status.code() == tonic::Code::DataLoss => {
+                        Replica::Corrupted(status.message().to_string())
+                    }
+                    Err(e) => Replica::Unreachable(e.to_string()),
+                };
+                ((key, volume_id), replica)
+            }
+        })
+        .buffer_unordered(concurrency)
+        .collect()
+        .await;
+    found.extend(reads);
+
+    for meta in checked {
+        let replicas: Vec<_> = meta
+            .replicas
+            .iter()
+            .map(|volume_id| (volume_id, &found[&(meta.key.as_str(), volume_id.as_str())]))
+            .collect();
+        match classify(replicas.iter().map(|(_, replica)| *replica)) {
+            Health::Healthy => report.healthy += 1,
+            Health::UnderReplicated => report.under_replicated += 1,
+            Health::Corrupted => report.corrupted += 1,
+        }
+        for (volume_id, replica) in replicas {
+            let problem = match replica {
+                Replica::Intact | Replica::Suspect => continue,
+                Replica::Corrupted(reason) => format!("corrupted: {}", reason),
+                Replica::Missing => "missing".to_string(),
+                Replica::Unreachable(reason) => format!("unreachable: {}", reason),
+            };
+            report.problem(&meta.key, Some(volume_id), problem);
+        }
+    }
+}
+
+/// Stat `metas` on `volume`, keeping the client for reads
+async fn stat_volume(
+    volume: Option<&VolumeMetadata>,
+    metas: &[&KeyMetadata],
+) -> Result<(VolumeClient, Vec<BlobStat>)> {
+    let volume = volume.ok_or_else(|| Error::NotFound("unknown volume".to_string()))?;
+    let mut client = VolumeClient::connect(volume.grpc_address.clone()).await?;
+    let keys = metas.iter().map(|meta| meta.key.clone()).collect();
+    let stats = client.stat(keys).await?;
+    if stats.len() != metas.len() {
+        return Err(Error::Corrupted(format!(
+            "{} answered {} stats for {} keys",
+            volume.volume_id,
+            stats.len(),
+            metas.len()
+        )));
+    }
+    Ok((client, stats))
+}
+
+/// blake3 of `key` read back from a volume, `None` if it does not hold it
+async fn read_hash(client: &mut VolumeClient, key: &str) -> Result<Option<String>> {
+    let Some(mut stream) = client.pull(key.to_string()).await? else {
+        return Ok(None);
+    };
+    let mut hasher = blake3::Hasher::new();
+    while let Some(chunk) = stream.message().await? {
+        hasher.update(&chunk.data);
+    }
+    Ok(Some(hasher.finalize().to_string()))
 }
 
 /// Report of cluster verification results.
-#[derive(Debug, serde::Serialize)]
+#[derive(Debug, Default, serde::Serialize)]
 pub struct VerifyReport {
     /// Total number of keys checked
     pub total_keys: usize,
@@ -52,4 +308,94 @@ pub struct VerifyReport {
     pub corrupted: usize,
     /// Number of orphaned blobs
     pub orphaned: usize,
+    /// The problems found, up to 1000
+    pub problems: Vec<VerifyProblem>,
+    /// Steps that could not run (the orphan scan, or volumes it could not scan)
+    pub errors: Vec<String>,
+}
+
+impl VerifyReport {
+    fn problem(&mut self, key: &str, volume_id: Option<&String>, problem: String) {
+        if self.problems.len() < MAX_REPORTED_PROBLEMS {
+            self.problems.push(VerifyProblem {
+                key: key.to_string(),
+                volume_id: volume_id.cloned(),
+                problem,
+            });
+        }
+    }
+}
+
+/// A replica (or key, without `volume_id`) that failed verification
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct VerifyProblem {
+    pub key: String,
+    pub volume_id: Option<String>,
+    pub problem: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn meta(size: u64, blake3: &str) -> KeyMetadata {
+        KeyMetadata {
+            key: "default/k".to_string(),
+            replicas: vec!["vol-1".to_string()],
+            size,
+            blake3: blake3.to_string(),
+            created_at: 0,
+            updated_at: 0,
+            state: KeyState::Active,
+            tags: Default::default(),
+            blob: None,
+            chunks: None,
+        }
+    }
+
+    fn stat(exists: bool, size: u64, blake3: &str) -> BlobStat {
+        BlobStat {
+            key: "default/k".to_string(),
+            exists,
+            size,
+            blake3: blake3.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_check_stat() {
+        let recorded = meta(5, "abc");
+        assert_eq!(
+            check_stat(&recorded, &stat(true, 5, "abc")),
+            Replica::Intact
+        );
+        assert_eq!(check_stat(&recorded, &stat(false, 0, "")), Replica::Missing);
+        assert_eq!(
+            check_stat(&recorded, &stat(true, 5, "def")),
+            Replica::Suspect
+        );
+        assert!(matches!(
+            check_stat(&recorded, &stat(true, 4, "abc")),
+            Replica::Corrupted(_)
+        ));
+        // No recorded hash: only the size is compared
+        let unhashed = meta(5, "");
+        assert_eq!(
+            check_stat(&unhashed, &stat(true, 5, "def")),
+            Replica::Intact
+        );
+    }
+
+    #[test]
+    fn test_classify() {
+        let intact = Replica::Intact;
+        let missing = Replica::Missing;
+        let unreachable = Replica::Unreachable("down".to_string());
+        let corrupted = Replica::Corrupted("size".to_string());
+
+        assert_eq!(classify([&intact, &intact]), Health::Healthy);
+        assert_eq!(classify([&intact, &missing]), Health::UnderReplicated);
+        assert_eq!(classify([&unreachable, &intact]), Health::UnderReplicated);
+        assert_eq!(classify([&missing, &corrupted]), Health::Corrupted);
+    }
 }
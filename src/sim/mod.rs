@@ -0,0 +1,316 @@
+//! Deterministic single-process cluster simulator
+//!
+//! `Sim` runs coordinators and volumes as tasks of the calling runtime,
+//! serving their internal gRPC APIs over in-memory pipes (`network`) instead
+//! of TCP, so tests of elections, partitions and 2PC edge cases need no
+//! release binaries and no ports. Under a paused clock
+//! (`#[tokio::test(start_paused = true)]`) timers fire as soon as every task
+//! waits, so seconds of cluster time take milliseconds, and with a
+//! current-thread runtime and a fixed `seed` (election timeouts) runs repeat.
+//!
+//! ```ignore
+//! #[tokio::test(start_paused = true)]
+//! async fn test_failover() {
+//!     let dir = tempfile::TempDir::new().unwrap();
+//!     let sim = Sim::start(dir.path(), SimConfig::default()).unwrap();
+//!     let leader = sim.wait_for_leader(Duration::from_secs(5)).await.unwrap();
+//!     sim.isolate(&leader);
+//!     let next = sim.wait_for_leader(Duration::from_secs(5)).await.unwrap();
+//!     assert_ne!(leader, next);
+//! }
+//! ```
+//!
+//! Nodes are named `c1`, `c2`... and `v1`, `v2`... Coordinators only run
+//! Raft and serve their HTTP API through `request`, without the background
+//! tasks (health monitor, repair, GC...) of `minikv-coord`; volumes are
+//! registered as alive with every coordinator up front. Process-wide state
+//! (`GLOBAL_STORE`, `KEY_STORE`, `FAULTS`...) is shared by all the simulated
+//! nodes.
+
+pub mod network;
+
+pub use network::Network;
+
+use crate::common::{
+    IpFilter, NodeState, RateLimitConfig, RateLimiter, Result, WalSyncPolicy, WriteBudget,
+    WriteBudgetConfig,
+};
+use crate::coordinator::etcd::start_lease_expiry_task;
+use crate::coordinator::grpc::CoordGrpcService;
+use crate::coordinator::http::{create_router, CoordState};
+use crate::coordinator::metadata::{MetadataStore, VolumeMetadata};
+use crate::coordinator::placement::PlacementManager;
+use crate::coordinator::raft_node::{start_raft_tasks, RaftNode};
+use crate::volume::blob::BlobStore;
+use crate::volume::grpc::VolumeGrpcService;
+use axum::body::{Body, Bytes};
+use axum::http::{Request, StatusCode};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tower::ServiceExt;
+
+/// Shards of the simulated clusters
+const NUM_SHARDS: u64 = 64;
+
+/// Free space reported by the simulated volumes
+const VOLUME_FREE_BYTES: u64 = 1 << 40;
+
+/// Shape of a simulated cluster
+#[derive(Debug, Clone, Copy)]
+pub struct SimConfig {
+    pub coordinators: usize,
+    pub volumes: usize,
+    /// Replicas of each key
+    pub replicas: usize,
+    /// Seed of the election timeouts; coordinator `n` draws from `seed + n`
+    pub seed: u64,
+}
+
+impl Default for SimConfig {
+    fn default() -> Self {
+        Self {
+            coordinators: 3,
+            volumes: 3,
+            replicas: 3,
+            seed: 0,
+        }
+    }
+}
+
+/// A simulated coordinator
+pub struct SimCoordinator {
+    pub id: String,
+    pub metadata: Arc<MetadataStore>,
+    pub raft: Arc<RaftNode>,
+    pub router: axum::Router,
+}
+
+/// A simulated volume
+pub struct SimVolume {
+    pub id: String,
+    pub store: Arc<BlobStore>,
+}
+
+/// A cluster running in this process
+pub struct Sim {
+    network: Arc<Network>,
+    coordinators: Vec<SimCoordinator>,
+    volumes: Vec<SimVolume>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl Sim {
+    /// Start a cluster storing its data under `dir`. Must be called within a
+    /// Tokio runtime, which runs the nodes.
+    pub fn start(dir: &Path, config: SimConfig) -> Result<Self> {
+        let network = Network::new();
+        let mut tasks = Vec::new();
+
+        let mut volumes = Vec::new();
+        let mut registered = Vec::new();
+        for n in 1..=config.volumes {
+            let id = format!("v{}", n);
+            let store = BlobStore::open(
+                &dir.join(&id).join("data"),
+                &dir.join(&id).join("wal"),
+                WalSyncPolicy::Always,
+            )?;
+            let service = VolumeGrpcService::new(store).with_volume_id(id.clone());
+            let store = service.store();
+            tasks.push(network.serve(&id, service.into_server()));
+            registered.push(VolumeMetadata {
+                volume_id: id.clone(),
+                address: network.address(&id),
+                grpc_address: network.address(&id),
+                state: NodeState::Alive,
+                shards: vec![],
+                total_keys: 0,
+                total_bytes: 0,
+                free_bytes: VOLUME_FREE_BYTES,
+                last_heartbeat: crate::common::timestamp_now(),
+                zone: None,
+                rack: None,
+                weight: None,
+                wal_error: None,
+            });
+            volumes.push(SimVolume { id, store });
+        }
+
+        let ids: Vec<String> = (1..=config.coordinators)
+            .map(|n| format!("c{}", n))
+            .collect();
+        let mut coordinators = Vec::new();
+        for (n, id) in ids.iter().enumerate() {
+            let metadata = Arc::new(MetadataStore::open(dir.join(id).join("meta.db"))?);
+            for volume in &registered {
+                metadata.put_volume(volume)?;
+            }
+            let peers = ids
+                .iter()
+                .filter(|peer| *peer != id)
+                .map(|peer| network.address_from(id, peer))
+                .collect();
+            let raft = Arc::new(
+                RaftNode::open(id.clone(), metadata.clone())?
+                    .with_seed(config.seed.wrapping_add(n as u64))
+                    .with_advertise_addr(network.address(id))
+                    .with_peers(peers),
+            );
+            let service = CoordGrpcService::new().with_raft(raft.clone());
+            tasks.push(network.serve(id, service.into_server()));
+            tasks.push(start_raft_tasks(raft.clone()));
+            tasks.push(start_lease_expiry_task(metadata.clone(), raft.clone()));
+
+            let mut placement = PlacementManager::new(NUM_SHARDS, config.replicas);
+            placement.rebalance(&registered);
+            let router = create_router(CoordState {
+                metadata: metadata.clone(),
+                placement: Arc::new(Mutex::new(placement)),
+                raft: raft.clone(),
+                auth: Default::default(),
+                rate_limiter: Arc::new(RateLimiter::new(RateLimitConfig::default())),
+                ip_filter: Arc::new(IpFilter::default()),
+                writes: Arc::new(WriteBudget::new(WriteBudgetConfig::default())),
+            });
+            coordinators.push(SimCoordinator {
+                id: id.clone(),
+                metadata,
+                raft,
+                router,
+            });
+        }
+
+        Ok(Self {
+            network,
+            coordinators,
+            volumes,
+            tasks,
+        })
+    }
+
+    /// The transport between the nodes
+    pub fn network(&self) -> &Arc<Network> {
+        &self.network
+    }
+
+    pub fn coordinators(&self) -> &[SimCoordinator] {
+        &self.coordinators
+    }
+
+    pub fn volumes(&self) -> &[SimVolume] {
+        &self.volumes
+    }
+
+    /// # Panics
+    /// If there is no coordinator `id`
+    pub fn coordinator(&self, id: &str) -> &SimCoordinator {
+        self.coordinators
+            .iter()
+            .find(|c| c.id == id)
+            .unwrap_or_else(|| panic!("no simulated coordinator {}", id))
+    }
+
+    /// # Panics
+    /// If there is no volume `id`
+    pub fn volume(&self, id: &str) -> &SimVolume {
+        self.volumes
+            .iter()
+            .find(|v| v.id == id)
+            .unwrap_or_else(|| panic!("no simulated volume {}", id))
+    }
+
+    /// The coordinator leading the latest term. A leader cut off from the
+    /// others may still believe it leads an earlier one.
+    pub fn leader(&self) -> Option<&SimCoordinator> {
+        self.coordinators
+            .iter()
+            .filter(|c| c.raft.is_leader())
+            .max_by_key(|c| c.raft.get_term())
+    }
+
+    /// Let the cluster run until a coordinator leads a term no other
+    /// coordinator went past, returning its id, or `None` after `timeout`
+    pub async fn wait_for_leader(&self, timeout: Duration) -> Option<String> {
+        let deadline = tokio::time::Instant::now() + timeout;
+        while tokio::time::Instant::now() < deadline {
+            let term = self.coordinators.iter().map(|c| c.raft.get_term()).max();
+            if let Some(leader) = self.leader().filter(|l| Some(l.raft.get_term()) == term) {
+                return Some(leader.id.clone());
+            }
+            self.advance(Duration::from_millis(10)).await;
+        }
+        None
+    }
+
+    /// Let the cluster run for `duration`. Under a paused clock, returns as
+    /// soon as the timers up to then fired.
+    pub async fn advance(&self, duration: Duration) {
+        tokio::time::sleep(duration).await;
+    }
+
+    /// Split the nodes into groups that can't reach each other
+    pub fn partition(&self, sides: &[&[&str]]) {
+        self.network.partition(sides);
+    }
+
+    /// Cut `node` off from every other node
+    pub fn isolate(&self, node: &str) {
+        self.network.isolate(node);
+    }
+
+    /// Undo partitions and isolations
+    pub fn heal(&self) {
+        self.network.heal();
+    }
+
+    /// Fail the next `times` calls of the gRPC `method` received by `node`
+    pub fn fail_calls(&self, node: &str, method: &str, times: u32) {
+        self.network.fail_calls(node, method, times);
+    }
+
+    /// Send an HTTP request to coordinator `id`
+    pub async fn request(
+        &self,
+        id: &str,
+        method: &str,
+        uri: &str,
+        body: impl Into<Body>,
+    ) -> (StatusCode, Bytes) {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .body(body.into())
+            .expect("invalid simulated request");
+        let resp = match self.coordinator(id).router.clone().oneshot(request).await {
+            Ok(resp) => resp,
+            Err(never) => match never {},
+        };
+        let status = resp.status();
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+            .await
+            .unwrap_or_default();
+        (status, body)
+    }
+
+    /// Put `key` through coordinator `id`
+    pub async fn put(&self, id: &str, key: &str, value: &[u8]) -> (StatusCode, Bytes) {
+        let uri = format!("/{}", key);
+        self.request(id, "POST", &uri, value.to_vec()).await
+    }
+
+    /// Get `key` through coordinator `id`
+    pub async fn get(&self, id: &str, key: &str) -> (StatusCode, Bytes) {
+        let uri = format!("/{}", key);
+        self.request(id, "GET", &uri, Body::empty()).await
+    }
+}
+
+impl Drop for Sim {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
@@ -0,0 +1,364 @@
+//! In-memory transport of the simulator
+//!
+//! The nodes of a simulation serve gRPC over in-process pipes instead of
+//! TCP. Their addresses read `sim://{network}/{node}`, or
+//! `sim://{network}/{from}/{node}` when the caller is known: each coordinator
+//! reaches its peers through addresses naming itself, so partitions cut the
+//! links between coordinators one by one. Volumes are reached through the
+//! address they registered with, the same for every coordinator, so calls to
+//! them are only cut when either side is isolated.
+//!
+//! A partition is checked on every read and write of a connection, so the
+//! ones open when it starts fail too, and callers reconnect as they would
+//! after a network error.
+
+use once_cell::sync::Lazy;
+use std::collections::{HashMap, HashSet};
+use std::convert::Infallible;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, DuplexStream, ReadBuf};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::UnboundedReceiverStream;
+use tonic::body::BoxBody;
+use tonic::codegen::{http, BoxFuture};
+use tonic::server::NamedService;
+use tonic::transport::{Channel, Endpoint};
+use tower::Service;
+
+const SCHEME: &str = "sim://";
+
+/// Bytes a pipe buffers in each direction
+const PIPE_CAPACITY: usize = 64 * 1024;
+
+/// Networks of the running simulations, by id
+static NETWORKS: Lazy<Mutex<HashMap<u64, Weak<Network>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+static NEXT_NETWORK: AtomicU64 = AtomicU64::new(1);
+
+/// Who may reach whom
+#[derive(Debug, Default)]
+struct Links {
+    /// Side of each node while partitioned
+    sides: HashMap<String, usize>,
+    isolated: HashSet<String>,
+    /// Calls left to fail, per node and gRPC method
+    failures: HashMap<(String, String), u32>,
+}
+
+/// Nodes of one simulation and the links between them
+pub struct Network {
+    id: u64,
+    listeners: Mutex<HashMap<String, mpsc::UnboundedSender<io::Result<DuplexStream>>>>,
+    links: Mutex<Links>,
+}
+
+impl Network {
+    pub fn new() -> Arc<Self> {
+        let network = Arc::new(Self {
+            id: NEXT_NETWORK.fetch_add(1, Ordering::Relaxed),
+            listeners: Mutex::new(HashMap::new()),
+            links: Mutex::new(Links::default()),
+        });
+        NETWORKS
+            .lock()
+            .unwrap()
+            .insert(network.id, Arc::downgrade(&network));
+        network
+    }
+
+    /// Address of `node`, for any caller
+    pub fn address(&self, node: &str) -> String {
+        format!("{}{}/{}", SCHEME, self.id, node)
+    }
+
+    /// Address of `node` for calls made by `from`
+    pub fn address_from(&self, from: &str, node: &str) -> String {
+        format!("{}{}/{}/{}", SCHEME, self.id, from, node)
+    }
+
+    /// Split the nodes named in `sides` into groups that can't reach each
+    /// other. Nodes left out still reach everyone.
+    pub fn partition(&self, sides: &[&[&str]]) {
+        let mut links = self.links.lock().unwrap();
+        links.sides = sides
+            .iter()
+            .enumerate()
+            .flat_map(|(side, nodes)| nodes.iter().map(move |node| (node.to_string(), side)))
+            .collect();
+    }
+
+    /// Cut `node` off from every other node
+    pub fn isolate(&self, node: &str) {
+        self.links.lock().unwrap().isolated.insert(node.to_string());
+    }
+
+    /// Undo partitions and isolations
+    pub fn heal(&self) {
+        let mut links = self.links.lock().unwrap();
+        links.sides.clear();
+        links.isolated.clear();
+    }
+
+    /// Fail the next `times` calls of the gRPC `method` (`Prepare`,
+    /// `AppendEntries`...) that `node` receives, as `unavailable`
+    pub fn fail_calls(&self, node: &str, method: &str, times: u32) {
+        self.links
+            .lock()
+            .unwrap()
+            .failures
+            .insert((node.to_string(), method.to_string()), times);
+    }
+
+    /// Whether `to` can be reached, by `from` if known
+    pub fn reachable(&self, from: Option<&str>, to: &str) -> bool {
+        let links = self.links.lock().unwrap();
+        if links.isolated.contains(to) || from.is_some_and(|from| links.isolated.contains(from)) {
+            return false;
+        }
+        match (
+            from.and_then(|from| links.sides.get(from)),
+            links.sides.get(to),
+        ) {
+            (Some(from), Some(to)) => from == to,
+            _ => true,
+        }
+    }
+
+    /// Whether to fail a call of `method` received by `node`, counting it
+    fn take_failure(&self, node: &str, method: &str) -> bool {
+        let mut links = self.links.lock().unwrap();
+        match links
+            .failures
+            .get_mut(&(node.to_string(), method.to_string()))
+        {
+            Some(left) if *left > 0 => {
+                *left -= 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Serve `service` as `node`, until the network is dropped
+    pub(crate) fn serve<S>(self: &Arc<Self>, node: &str, service: S) -> tokio::task::JoinHandle<()>
+    where
+        S: Service<http::Request<BoxBody>, Response = http::Response<BoxBody>, Error = Infallible>
+            + NamedService
+            + Clone
+            + Send
+            + 'static,
+        S::Future: Send + 'static,
+    {
+        let (sender, incoming) = mpsc::unbounded_channel();
+        self.listeners
+            .lock()
+            .unwrap()
+            .insert(node.to_string(), sender);
+        let service = Gate {
+            inner: service,
+            node: node.to_string(),
+            network: Arc::downgrade(self),
+        };
+        let node = node.to_string();
+        tokio::spawn(async move {
+            if let Err(e) = tonic::transport::Server::builder()
+                .add_service(service)
+                .serve_with_incoming(UnboundedReceiverStream::new(incoming))
+                .await
+            {
+                tracing::warn!("Simulated node {} stopped serving: {}", node, e);
+            }
+        })
+    }
+
+    /// Open a pipe from `from` to `to`
+    fn connect(self: &Arc<Self>, from: Option<String>, to: String) -> io::Result<Pipe> {
+        if !self.reachable(from.as_deref(), &to) {
+            return Err(io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                format!("{} is unreachable", to),
+            ));
+        }
+        let (client, server) = tokio::io::duplex(PIPE_CAPACITY);
+        let listeners = self.listeners.lock().unwrap();
+        let accepted = listeners
+            .get(&to)
+            .is_some_and(|listener| listener.send(Ok(server)).is_ok());
+        if !accepted {
+            return Err(io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                format!("{} is not serving", to),
+            ));
+        }
+        Ok(Pipe {
+            inner: client,
+            network: self.clone(),
+            from,
+            to,
+        })
+    }
+}
+
+impl Drop for Network {
+    fn drop(&mut self) {
+        NETWORKS.lock().unwrap().remove(&self.id);
+    }
+}
+
+/// Open a channel to `addr` if it is the address of a simulated node,
+/// `None` for any other address
+pub(crate) async fn connect(
+    addr: &str,
+) -> Option<std::result::Result<Channel, tonic::transport::Error>> {
+    let rest = addr.strip_prefix(SCHEME)?;
+    let mut parts = rest.split('/');
+    let id: u64 = parts.next()?.parse().ok()?;
+    let (from, to) = match (parts.next(), parts.next()) {
+        (Some(to), None) => (None, to.to_string()),
+        (Some(from), Some(to)) => (Some(from.to_string()), to.to_string()),
+        _ => return None,
+    };
+    let network = NETWORKS.lock().unwrap().get(&id)?.upgrade()?;
+    let connector = tower::service_fn(move |_: http::Uri| {
+        let pipe = network.connect(from.clone(), to.clone());
+        async move { pipe.map(hyper_util::rt::TokioIo::new) }
+    });
+    Some(
+        Endpoint::from_static("http://sim.invalid")
+            .connect_with_connector(connector)
+            .await,
+    )
+}
+
+/// Client end of a connection, failing once its nodes are partitioned
+struct Pipe {
+    inner: DuplexStream,
+    network: Arc<Network>,
+    from: Option<String>,
+    to: String,
+}
+
+impl Pipe {
+    fn check(&self) -> io::Result<()> {
+        if self.network.reachable(self.from.as_deref(), &self.to) {
+            Ok(())
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::ConnectionReset,
+                format!("{} is unreachable", self.to),
+            ))
+        }
+    }
+}
+
+impl AsyncRead for Pipe {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        self.check()?;
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for Pipe {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.check()?;
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// gRPC service of a simulated node, failing the calls `fail_calls` asked for
+#[derive(Clone)]
+struct Gate<S> {
+    inner: S,
+    node: String,
+    network: Weak<Network>,
+}
+
+impl<S: NamedService> NamedService for Gate<S> {
+    const NAME: &'static str = S::NAME;
+}
+
+impl<S> Service<http::Request<BoxBody>> for Gate<S>
+where
+    S: Service<http::Request<BoxBody>, Response = http::Response<BoxBody>, Error = Infallible>,
+    S::Future: Send + 'static,
+{
+    type Response = http::Response<BoxBody>;
+    type Error = Infallible;
+    type Future = BoxFuture<Self::Response, Infallible>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<BoxBody>) -> Self::Future {
+        let method = request.uri().path().rsplit('/').next().unwrap_or_default();
+        let fail = self
+            .network
+            .upgrade()
+            .is_some_and(|network| network.take_failure(&self.node, method));
+        if fail {
+            let status = tonic::Status::unavailable(format!(
+                "{} on {} failed by the simulation",
+                method, self.node
+            ));
+            let response = status.into_http();
+            return Box::pin(async move { Ok(response) });
+        }
+        Box::pin(self.inner.call(request))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reachable() {
+        let network = Network::new();
+        assert!(network.reachable(Some("c1"), "c2"));
+
+        network.partition(&[&["c1"], &["c2", "c3"]]);
+        assert!(!network.reachable(Some("c1"), "c2"));
+        assert!(network.reachable(Some("c2"), "c3"));
+        // Callers that don't say who they are get through
+        assert!(network.reachable(None, "c2"));
+
+        network.heal();
+        network.isolate("v1");
+        assert!(!network.reachable(None, "v1"));
+        assert!(!network.reachable(Some("v1"), "c1"));
+        network.heal();
+        assert!(network.reachable(None, "v1"));
+    }
+
+    #[test]
+    fn test_fail_calls() {
+        let network = Network::new();
+        network.fail_calls("v1", "Commit", 2);
+        assert!(network.take_failure("v1", "Commit"));
+        assert!(!network.take_failure("v1", "Prepare"));
+        assert!(network.take_failure("v1", "Commit"));
+        assert!(!network.take_failure("v1", "Commit"));
+    }
+}
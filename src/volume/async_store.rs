@@ -0,0 +1,242 @@
+//! Async facade over the blob store
+//!
+//! `BlobStore` does blocking file I/O, so async handlers must not call it
+//! directly. `AsyncBlobStore` runs each operation on tokio's blocking pool,
+//! with at most `max_in_flight` of them running at once so that a burst of
+//! requests queues up instead of exhausting the pool, and fails operations
+//! after `timeout_ms`. Writes also wait for their WAL entry to be durable
+//! within that deadline.
+//!
+//! A timeout only stops the wait: an operation that already started runs to
+//! completion and keeps its slot until then, so a timed out write may still
+//! land.
+
+use crate::common::{Error, Result, StoreIoConfig, METRICS};
+use crate::volume::blob::{BlobStore, StoreStats};
+use crate::volume::wal::WalCommit;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::Instant;
+
+/// Shared handle running blob store operations off the async runtime
+#[derive(Clone)]
+pub struct AsyncBlobStore {
+    store: Arc<BlobStore>,
+    slots: Arc<Semaphore>,
+    timeout: Duration,
+}
+
+/// Slot of a running operation, given back when it finishes
+struct InFlight {
+    _permit: OwnedSemaphorePermit,
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        METRICS.store_io_in_flight.dec();
+    }
+}
+
+impl AsyncBlobStore {
+    pub fn new(store: Arc<BlobStore>, config: StoreIoConfig) -> Self {
+        Self {
+            store,
+            slots: Arc::new(Semaphore::new(config.max_in_flight.max(1))),
+            timeout: Duration::from_millis(config.timeout_ms),
+        }
+    }
+
+    /// The underlying store, for callers already off the runtime
+    pub fn store(&self) -> &Arc<BlobStore> {
+        &self.store
+    }
+
+    /// Run `op` on the blocking pool once a slot is free, failing with
+    /// `Error::Timeout` if it isn't done by the deadline
+    pub async fn run<T, F>(&self, name: &'static str, op: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&BlobStore) -> Result<T> + Send + 'static,
+    {
+        self.run_until(Instant::now() + self.timeout, name, op)
+            .await
+    }
+
+    async fn run_until<T, F>(&self, deadline: Instant, name: &'static str, op: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&BlobStore) -> Result<T> + Send + 'static,
+    {
+        let permit =
+            match tokio::time::timeout_at(deadline, self.slots.clone().acquire_owned()).await {
+                Ok(permit) => permit.map_err(|_| Error::Internal("blob store closed".into()))?,
+                Err(_) => return Err(self.timed_out(name)),
+            };
+        METRICS.store_io_in_flight.inc();
+        let slot = InFlight { _permit: permit };
+        let store = self.store.clone();
+        let task = tokio::task::spawn_blocking(move || {
+            let _slot = slot;
+            op(&store)
+        });
+        match tokio::time::timeout_at(deadline, task).await {
+            Ok(joined) => joined.map_err(|e| Error::Internal(e.to_string()))?,
+            Err(_) => Err(self.timed_out(name)),
+        }
+    }
+
+    /// Run a write, then wait for its WAL entry to be durable
+    async fn write<F>(&self, name: &'static str, op: F) -> Result<()>
+    where
+        F: FnOnce(&BlobStore) -> Result<()> + Send + 'static,
+    {
+        let deadline = Instant::now() + self.timeout;
+        let commit: WalCommit = self
+            .run_until(deadline, name, move |store| {
+                op(store)?;
+                Ok(store.wal_commit())
+            })
+            .await?;
+        tokio::time::timeout_at(deadline, commit.wait())
+            .await
+            .map_err(|_| self.timed_out(name))?
+    }
+
+    fn timed_out(&self, name: &str) -> Error {
+        METRICS.store_io_timeouts.inc();
+        Error::Timeout(format!("blob store {} after {:?}", name, self.timeout))
+    }
+
+    pub async fn get(&self, key: impl Into<String>) -> Result<Option<Vec<u8>>> {
+        let key = key.into();
+        self.run("get", move |store| store.get(&key)).await
+    }
+
+    pub async fn get_range(
+        &self,
+        key: impl Into<String>,
+        offset: u64,
+        length: u64,
+    ) -> Result<Option<Vec<u8>>> {
+        let key = key.into();
+        self.run("get_range", move |store| {
+            store.get_range(&key, offset, length)
+        })
+        .await
+    }
+
+    pub async fn put(&self, key: impl Into<String>, value: Vec<u8>) -> Result<()> {
+        self.put_with_ttl(key, value, None).await
+    }
+
+    pub async fn put_with_ttl(
+        &self,
+        key: impl Into<String>,
+        value: Vec<u8>,
+        ttl_ms: Option<u64>,
+    ) -> Result<()> {
+        let key = key.into();
+        self.write("put", move |store| store.put_with_ttl(&key, &value, ttl_ms))
+            .await
+    }
+
+    pub async fn delete(&self, key: impl Into<String>) -> Result<()> {
+        let key = key.into();
+        self.write("delete", move |store| store.delete(&key)).await
+    }
+
+    pub async fn key_hashes(&self) -> Result<Vec<(String, String)>> {
+        self.run("key_hashes", |store| Ok(store.key_hashes())).await
+    }
+
+    pub async fn blobs_after(
+        &self,
+        after: impl Into<String>,
+        limit: usize,
+    ) -> Result<Vec<(String, String, u64)>> {
+        let after = after.into();
+        self.run("blobs_after", move |store| {
+            Ok(store.blobs_after(&after, limit))
+        })
+        .await
+    }
+
+    /// Store stats and free disk space
+    pub async fn stats(&self) -> Result<(StoreStats, Option<u64>)> {
+        self.run("stats", |store| Ok((store.stats(), store.free_bytes())))
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::WalSyncPolicy;
+    use tempfile::tempdir;
+
+    fn open(dir: &tempfile::TempDir, config: StoreIoConfig) -> AsyncBlobStore {
+        let store = BlobStore::open(
+            &dir.path().join("data"),
+            &dir.path().join("wal"),
+            WalSyncPolicy::Group,
+        )
+        .unwrap();
+        AsyncBlobStore::new(Arc::new(store), config)
+    }
+
+    #[tokio::test]
+    async fn test_async_roundtrip() {
+        let dir = tempdir().unwrap();
+        let store = open(&dir, StoreIoConfig::default());
+
+        store.put("a", b"one".to_vec()).await.unwrap();
+        store
+            .put_with_ttl("b", b"two".to_vec(), Some(60_000))
+            .await
+            .unwrap();
+        assert_eq!(store.get("a").await.unwrap().unwrap(), b"one");
+        assert_eq!(store.blobs_after("a", 10).await.unwrap().len(), 1);
+
+        store.delete("a").await.unwrap();
+        assert!(store.get("a").await.unwrap().is_none());
+        let (stats, _) = store.stats().await.unwrap();
+        assert_eq!(stats.total_keys, 1);
+    }
+
+    #[tokio::test]
+    async fn test_bounded_in_flight_and_timeout() {
+        let dir = tempdir().unwrap();
+        let store = open(
+            &dir,
+            StoreIoConfig {
+                max_in_flight: 1,
+                timeout_ms: 100,
+            },
+        );
+
+        let slow = store.clone();
+        let held = tokio::spawn(async move {
+            slow.run("slow", |_| {
+                std::thread::sleep(Duration::from_millis(300));
+                Ok(())
+            })
+            .await
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        // The only slot is taken: the next operation times out waiting
+        let timeouts = METRICS.store_io_timeouts.get();
+        let err = store.get("a").await.unwrap_err();
+        assert!(matches!(err, Error::Timeout(_)));
+        assert!(METRICS.store_io_timeouts.get() > timeouts);
+
+        // The slow one missed its deadline too
+        assert!(matches!(held.await.unwrap(), Err(Error::Timeout(_))));
+
+        // Its slot comes back once it actually finishes
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        store.put("a", b"value".to_vec()).await.unwrap();
+        assert_eq!(store.get("a").await.unwrap().unwrap(), b"value");
+    }
+}
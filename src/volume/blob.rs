@@ -3,27 +3,128 @@
 //! It uses a log-structured, append-only design for durability and performance.
 //! The in-memory HashMap index enables fast lookups, while a Bloom filter accelerates negative lookups.
 //! All operations are logged to a Write-Ahead Log (WAL) for crash recovery.
+//! On open, the WAL is re-applied on top of the index snapshot (or of the
+//! index rebuilt from the segments): each logged put is located in the
+//! segments, or rewritten from the logged value if its record never made it
+//! to disk, and each logged delete is removed again.
+//! The bloom filter is saved with the snapshot and loaded with it, unless it
+//! was saved along with another snapshot. It is sized for twice the keys it
+//! holds (at least `BLOOM_MIN_KEYS`), and rebuilt from the index once the
+//! keys outgrow it or shrink to an eighth of its size, which also drops the
+//! bits of deleted keys.
+//!
+//! Deletes also append a tombstone record (flagged, with an empty value) to
+//! the segments, so an index rebuilt from the segments alone doesn't bring
+//! deleted keys back. Compaction only copies live keys, dropping tombstones
+//! along with the records they shadow.
 //!
 //! Features in v0.5.0:
 //! - TTL (Time-To-Live) support for automatic key expiration
-//! - LZ4 compression for efficient storage
+//! - LZ4 or zstd compression for efficient storage
 //! - Background cleanup task for expired keys
-
-use crate::common::{blake3_hash, crc32, Result, WalSyncPolicy};
-use crate::volume::index::{BlobLocation, Index};
-use crate::volume::wal::{Wal, WalEntry, WalOp};
+//!
+//! With encryption at rest enabled (`ENCRYPTION_MANAGER`), blob values are
+//! sealed after compression and flagged as such in their record, so segments
+//! may mix encrypted and plaintext records. Keys stay in clear in the record
+//! headers, which the index is rebuilt from. After a master key rotation,
+//! `rekey_batch` rewrites the records sealed with an older key.
+//!
+//! The store is shared between request handlers without an outer lock.
+//! Writes are logged and appended to the active segment under the writer
+//! lock, which keeps the WAL and the segments in the same order; values are
+//! compressed and sealed before taking it. Reads only take the lock of their
+//! index shard, for the time of a lookup, and then read the segment file
+//! directly, so they neither wait for writes nor for each other.
+//!
+//! Segments are numbered files in the flat `segments/` directory of the data
+//! directory (`segments/seg_000042.blob`), and locations only hold the
+//! segment number. Data directories of the older layout, which spread
+//! segments over `NN/NN/` directories derived from their number, are moved
+//! over when opened. A full compaction writes new segments next to it and
+//! swaps the directory as a whole; `compact_segment` instead moves the live
+//! records of one segment to the active segment and removes it. Either way
+//! records are copied as stored, `COPY_CHUNK_SIZE` bytes of the value at a
+//! time with their checksum verified along the way, so compacting a volume
+//! of multi-GB values doesn't need memory for a whole one.
+//!
+//! Segments may be spread over several data directories, on different disks
+//! (see `DiskSet`). The first one holds everything else, and the segments
+//! written by a full compaction.
+//!
+//! Opening scans segments, to rebuild the index without a snapshot or to
+//! locate the puts logged since one, on up to `MAX_SCAN_WORKERS` threads,
+//! applying their records in segment order. Progress is logged every
+//! `SCAN_PROGRESS_SECS`, and the time the open took is exported as a metric.
+//!
+//! Below `min_free_bytes` of free space the store turns read-only: puts
+//! fail with `Error::StorageFull` before touching the WAL, until the disks
+//! have 10% more than that again. A write that runs out of space anyway
+//! fails the same way instead of with the raw I/O error.
+//!
+//! Values read by `get` go through a `BlobCache`, which serves hot keys
+//! without touching the segments. With `mmap_reads`, segments are read
+//! through memory maps instead of opening the file for every read.
+
+use crate::common::range::slice_of;
+use crate::common::{
+    blake3_hash, crc32, timestamp_now_millis, BlobCacheConfig, CompressionConfig, EncryptedData,
+    GroupCommitConfig, Result, WalSyncPolicy, ENCRYPTION_MANAGER, FAULTS, METRICS,
+};
+use crate::volume::cache::BlobCache;
+use crate::volume::disks::{DiskSet, DiskStats};
+use crate::volume::index::{BlobLocation, Index, ShardedIndex};
+use crate::volume::wal::{Wal, WalCommit, WalEntry, WalOp};
 use bloomfilter::Bloom;
+use memmap2::Mmap;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::fs::{self, File, OpenOptions};
 use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+pub use crate::common::CompressionMode;
 
 const BLOB_MAGIC: [u8; 4] = [0x42, 0x4C, 0x4F, 0x42];
 /// Magic bytes for compressed blobs (v0.5.0)
 const BLOB_MAGIC_COMPRESSED: [u8; 4] = [0x42, 0x4C, 0x4F, 0x43]; // BLOC
-const SEGMENT_SIZE: u64 = 64 * 1024 * 1024;
-const MAX_SEGMENTS: u64 = 1000;
-/// Minimum size for compression (smaller blobs are stored uncompressed)
-const COMPRESSION_THRESHOLD: usize = 128;
+/// Magic bytes for blobs followed by a compression flag byte; written since
+/// zstd support, the two magics above are only read
+const BLOB_MAGIC_FLAGGED: [u8; 4] = [0x42, 0x4C, 0x4F, 0x46]; // BLOF
+/// Magic bytes of a bloom filter file, followed by the blake3 hash of the
+/// index snapshot it was saved with and the keys it is sized for
+const BLOOM_MAGIC: [u8; 4] = [0x42, 0x4C, 0x4D, 0x32]; // BLM2
+/// Fewest keys a bloom filter is sized for
+const BLOOM_MIN_KEYS: usize = 100_000;
+/// False positive rate of a bloom filter holding as many keys as it is sized for
+const BLOOM_FP_RATE: f64 = 0.01;
+/// Directory of the segment files, under the data directory
+pub(crate) const SEGMENTS_DIR: &str = "segments";
+/// Log of the records quarantined by scrubbing, under the data directory
+const QUARANTINE_LOG: &str = "quarantine.log";
+/// How often writes measure the free space again, in milliseconds
+const FREE_SPACE_CHECK_MS: u64 = 1000;
+/// Segment size until `set_segment_limits` is called
+const DEFAULT_SEGMENT_SIZE: u64 = 64 * 1024 * 1024;
+/// Most threads scanning segments at once on open
+const MAX_SCAN_WORKERS: usize = 8;
+/// How often scanning segments on open logs its progress
+const SCAN_PROGRESS_SECS: u64 = 5;
+/// Piece of a value compaction holds in memory while copying it
+const COPY_CHUNK_SIZE: usize = 1024 * 1024;
+/// Bytes of a flagged record besides its key and value:
+/// MAGIC(4) + FLAG(1) + KEY_LEN(4) + VAL_LEN(8) + ORIG_LEN(8) + CHECKSUM(4)
+const RECORD_OVERHEAD: u64 = 4 + 1 + 4 + 8 + 8 + 4;
+
+/// Compression flag of a record: how its value is stored
+const FLAG_NONE: u8 = 0;
+const FLAG_LZ4: u8 = 1;
+const FLAG_ZSTD: u8 = 2;
+/// Set on top of the compression flag when the stored value is encrypted
+const FLAG_ENCRYPTED: u8 = 0x80;
+/// Set instead of a compression flag on a record deleting its key
+const FLAG_TOMBSTONE: u8 = 0x40;
 
 #[derive(Debug, Clone)]
 pub struct StoreStats {
@@ -31,108 +132,377 @@ pub struct StoreStats {
     pub total_bytes: u64,
     pub active_segments: usize,
     pub index_size: usize,
+    /// Lookups the bloom filter let through for keys not in the index
     pub bloom_false_positives: u64,
+    /// Keys the bloom filter is sized for
+    pub bloom_capacity: usize,
     /// Number of keys with TTL set
     pub keys_with_ttl: usize,
     /// Number of compressed blobs
     pub compressed_blobs: u64,
+    /// Original size of the compressed blobs
+    pub compressed_bytes: u64,
+    /// Size of the compressed blobs on disk
+    pub compressed_stored_bytes: u64,
+    /// Size of the write-ahead log
+    pub wal_bytes: u64,
+}
+
+impl StoreStats {
+    /// Original over stored size of the compressed blobs (1.0 when there are none)
+    pub fn compression_ratio(&self) -> f64 {
+        if self.compressed_stored_bytes == 0 {
+            1.0
+        } else {
+            self.compressed_bytes as f64 / self.compressed_stored_bytes as f64
+        }
+    }
+}
+
+/// Bytes of the segments, and how many of them hold live records
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SpaceUsage {
+    /// Size of the segment files
+    pub segment_bytes: u64,
+    /// Size of the records of the indexed keys. Estimated from their stored
+    /// size, leaving out the encryption overhead of sealed records.
+    pub live_bytes: u64,
+}
+
+impl SpaceUsage {
+    /// Bytes of overwritten, deleted or expired records, and tombstones
+    pub fn dead_bytes(&self) -> u64 {
+        self.segment_bytes.saturating_sub(self.live_bytes)
+    }
+
+    /// Share of the segment bytes that is dead (0.0 with no segments)
+    pub fn dead_ratio(&self) -> f64 {
+        if self.segment_bytes == 0 {
+            0.0
+        } else {
+            self.dead_bytes() as f64 / self.segment_bytes as f64
+        }
+    }
 }
 
-/// Compression configuration
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
-pub enum CompressionMode {
-    /// No compression
-    #[default]
-    None,
-    /// LZ4 compression (fast)
-    Lz4,
+/// Outcome of scrubbing one segment (`BlobStore::scrub_segment_with`)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ScrubReport {
+    /// Live records checked, and their size
+    pub records: u64,
+    pub bytes: u64,
+    /// Keys whose record failed its checks, now quarantined
+    pub corrupted: Vec<String>,
+}
+
+/// Outcome of one `BlobStore::rekey_batch` call
+#[derive(Debug, Clone, Default)]
+pub struct RekeyBatch {
+    /// Keys looked at
+    pub scanned: usize,
+    /// Keys re-encrypted with the current master key
+    pub rewritten: usize,
+    /// Last key looked at, to resume from; `None` once all keys were seen
+    pub next_after: Option<String>,
 }
 
 /// BlobStore manages the log-structured storage for a volume.
 /// It maintains an in-memory index and a Bloom filter for fast lookups.
 /// All changes are recorded in a WAL for durability and recovery.
 pub struct BlobStore {
+    /// Primary data directory
     data_path: PathBuf,
+    /// Data directories holding the segments
+    disks: DiskSet,
 
     /// In-memory index for key lookups
-    index: Index,
+    index: ShardedIndex,
     /// Bloom filter for fast negative lookups
+    bloom: RwLock<KeyFilter>,
+    bloom_false_positives: AtomicU64,
+    /// WAL and active segment, locked for each write
+    writer: Mutex<Writer>,
+    /// Held by reads, and exclusively while compaction swaps the segments
+    /// directory, so a read never follows a location into the wrong layout
+    layout: RwLock<()>,
+    /// WAL sync policy
+    sync_policy: WalSyncPolicy,
+    /// Compression of new blobs
+    compression: CompressionConfig,
+    /// Recently read values
+    cache: BlobCache,
+    /// Read segments through `segment_maps` rather than with file reads
+    mmap_reads: bool,
+    /// Memory maps of the segments read so far, with `mmap_reads`
+    segment_maps: RwLock<HashMap<u64, Arc<Mmap>>>,
+    /// Writes and deletes so far, and as of the last snapshot
+    changes: AtomicU64,
+    snapshot_changes: AtomicU64,
+    /// Quarantined keys not yet reported to the coordinator
+    corrupted: Mutex<BTreeSet<String>>,
+    /// Free space below which writes are refused (0 = never)
+    min_free_bytes: u64,
+    /// Set while writes are refused for lack of space, and when the free
+    /// space was last measured (Unix milliseconds)
+    read_only: AtomicBool,
+    free_space_checked_at: AtomicU64,
+}
+
+/// Bloom filter of the stored keys
+struct KeyFilter {
     bloom: Bloom<[u8; 32]>,
+    /// Keys it was sized for
+    capacity: usize,
+}
+
+impl KeyFilter {
+    /// An empty filter with room for `keys` to double
+    fn for_keys(keys: usize) -> Self {
+        let capacity = keys.saturating_mul(2).max(BLOOM_MIN_KEYS);
+        Self {
+            bloom: Bloom::new_for_fp_rate(capacity, BLOOM_FP_RATE).unwrap(),
+            capacity,
+        }
+    }
+
+    fn set(&mut self, key: &str) {
+        self.bloom.set(&bloom_key(key));
+    }
+
+    fn check(&self, key: &str) -> bool {
+        self.bloom.check(&bloom_key(key))
+    }
+
+    /// Whether a filter holding `keys` should be rebuilt at another size
+    fn needs_resize(&self, keys: usize) -> bool {
+        keys > self.capacity || (self.capacity > BLOOM_MIN_KEYS && keys * 8 < self.capacity)
+    }
+}
+
+/// Appending side of the store
+struct Writer {
     /// Write-Ahead Log for durability
     wal: Wal,
     /// Current segment number in log-structured storage
     current_segment: u64,
     /// Current offset in the active segment
     current_offset: u64,
-    /// WAL sync policy
-    sync_policy: WalSyncPolicy,
-    /// Compression mode (v0.5.0)
-    compression: CompressionMode,
+    /// Size past which the next record starts a new segment
+    segment_size: u64,
+    /// Most segments the store may have, 0 for no limit
+    max_segments: u64,
+}
+
+impl Writer {
+    /// Move on to the next segment once the current one is full. Fails with
+    /// `Error::StorageFull`, leaving the active segment as is, if that would
+    /// go past `max_segments` or no disk has room for a whole segment.
+    fn roll_segment(&mut self, disks: &DiskSet) -> Result<()> {
+        if self.current_offset <= self.segment_size {
+            return Ok(());
+        }
+        if self.max_segments > 0 && self.current_segment + 1 >= self.max_segments {
+            return Err(crate::Error::StorageFull(format!(
+                "all {} segments are in use",
+                self.max_segments
+            )));
+        }
+        disks.place(self.current_segment + 1, self.segment_size)?;
+        self.current_segment += 1;
+        self.current_offset = 0;
+        Ok(())
+    }
+}
+
+/// A value as stored in its record: compressed and sealed as `flag` says
+struct Encoded {
+    flag: u8,
+    stored: Vec<u8>,
+    size: u64,
+    blake3: String,
 }
 
 impl BlobStore {
     pub fn open(data_path: &Path, wal_path: &Path, sync_policy: WalSyncPolicy) -> Result<Self> {
+        Self::open_dirs(&[data_path.to_path_buf()], wal_path, sync_policy)
+    }
+
+    /// Open a store keeping its segments in `data_paths`, the first being
+    /// the primary data directory
+    pub fn open_dirs(
+        data_paths: &[PathBuf],
+        wal_path: &Path,
+        sync_policy: WalSyncPolicy,
+    ) -> Result<Self> {
+        let started = Instant::now();
+        let data_path = data_paths.first().ok_or_else(|| {
+            crate::Error::InvalidConfig("a volume needs at least one data directory".into())
+        })?;
         fs::create_dir_all(data_path)?;
         fs::create_dir_all(wal_path)?;
+        Self::recover_layout(data_paths)?;
+        let disks = DiskSet::open(data_paths)?;
 
         let snapshot_path = data_path.join("index.snap");
-        let mut index = if snapshot_path.exists() {
-            Index::load_snapshot(&snapshot_path)?
-        } else {
-            Index::new()
-        };
-
-        let bloom_path = data_path.join("bloom.filter");
-        let mut bloom = if bloom_path.exists() {
-            let bytes = fs::read(&bloom_path)?;
-            Bloom::from_bytes(bytes)
-                .unwrap_or_else(|_: &str| Bloom::new_for_fp_rate(100_000, 0.01).unwrap())
+        let (mut index, snapshot_hash) = if snapshot_path.exists() {
+            let hash = *blake3::hash(&fs::read(&snapshot_path)?).as_bytes();
+            (Index::load_snapshot(&snapshot_path)?, Some(hash))
         } else {
-            Bloom::new_for_fp_rate(100_000, 0.01).unwrap()
+            (Index::new(), None)
         };
 
         let wal_file = wal_path.join("wal.log");
         let wal = Wal::open(&wal_file, sync_policy)?;
 
-        Wal::replay(&wal_file, &mut |entry: WalEntry| {
-            match entry.op {
-                WalOp::Put { ref key, .. } => {
-                    let hash = blake3_hash(key.as_bytes());
-                    let hash_vec: Vec<u8> = hex::decode(&hash).unwrap_or_else(|_| vec![0u8; 32]);
-                    let hash_bytes: [u8; 32] = hash_vec.try_into().unwrap_or([0u8; 32]);
-                    bloom.set(&hash_bytes);
-                }
-                WalOp::Delete { ref key } => {
-                    index.remove(key);
-                }
+        let mut scanned = 0;
+        if snapshot_hash.is_none() {
+            scanned += Self::rebuild_index_from_segments(&mut index, &disks)?;
+        }
+        let bloom_path = data_path.join("bloom.filter");
+        let saved_bloom = snapshot_hash.and_then(|hash| load_bloom(&bloom_path, &hash));
+        // Missing, or saved along with another snapshot
+        let bloom = saved_bloom.unwrap_or_else(|| {
+            let mut bloom = KeyFilter::for_keys(index.len());
+            for key in index.keys() {
+                bloom.set(key);
+            }
+            bloom
+        });
+
+        // Keys logged since the snapshot, to find their records; scanning
+        // also cuts off records torn by a crash, before writing resumes
+        let mut logged = HashSet::new();
+        Wal::replay(&wal_file, |entry: WalEntry| {
+            if let WalOp::Put { key, .. } = entry.op {
+                logged.insert(key);
             }
             Ok(())
         })?;
+        let mut candidates: HashMap<String, Vec<BlobLocation>> = HashMap::new();
+        if !logged.is_empty() {
+            let segments = disks.segment_files();
+            Self::scan_segments(&segments, |key, location| {
+                if let Some(location) = location.filter(|_| logged.contains(&key)) {
+                    candidates.entry(key).or_default().push(location);
+                }
+            })?;
+            scanned += segments.len();
+        }
 
-        if !snapshot_path.exists() {
-            Self::rebuild_index_from_segments(&mut index, &mut bloom, data_path)?;
-        } else {
-            for key in index.keys() {
-                let hash = blake3_hash(key.as_bytes());
-                let hash_vec: Vec<u8> = hex::decode(&hash).unwrap_or_else(|_| vec![0u8; 32]);
-                let hash_bytes: [u8; 32] = hash_vec.try_into().unwrap_or([0u8; 32]);
-                bloom.set(&hash_bytes);
+        let (mut current_segment, mut current_offset) = Self::find_current_position(&disks)?;
+        if disks.has_failed() {
+            // The active segment may have been on the failed disk: start
+            // one past every segment known to the index
+            let last = index.iter().map(|(_, location)| location.shard).max();
+            if let Some(last) = last.filter(|&last| last >= current_segment) {
+                current_segment = last + 1;
+                current_offset = 0;
             }
         }
 
-        let (current_segment, current_offset) = Self::find_current_position(data_path)?;
-
-        Ok(Self {
-            data_path: data_path.to_path_buf(),
-
-            index,
-            bloom,
-            wal,
-            current_segment,
-            current_offset,
+        let store = Self {
+            data_path: data_path.clone(),
+            disks,
+
+            index: ShardedIndex::from_index(index),
+            bloom: RwLock::new(bloom),
+            bloom_false_positives: AtomicU64::new(0),
+            writer: Mutex::new(Writer {
+                wal,
+                current_segment,
+                current_offset,
+                segment_size: DEFAULT_SEGMENT_SIZE,
+                max_segments: 0,
+            }),
+            layout: RwLock::new(()),
             sync_policy,
-            compression: CompressionMode::None,
-        })
+            compression: CompressionConfig::default(),
+            cache: BlobCache::new(BlobCacheConfig::default()),
+            mmap_reads: false,
+            segment_maps: RwLock::new(HashMap::new()),
+            changes: AtomicU64::new(0),
+            snapshot_changes: AtomicU64::new(0),
+            corrupted: Mutex::new(BTreeSet::new()),
+            min_free_bytes: 0,
+            read_only: AtomicBool::new(false),
+            free_space_checked_at: AtomicU64::new(0),
+        };
+        store.replay_wal(&wal_file, candidates)?;
+        store.resize_bloom_if_needed();
+        METRICS
+            .bloom_capacity_keys
+            .set(store.bloom.read().unwrap().capacity as u64);
+        let elapsed = started.elapsed();
+        METRICS.volume_startup_ms.set(elapsed.as_millis() as u64);
+        METRICS.volume_startup_segments_scanned.set(scanned as u64);
+        tracing::info!(
+            "Opened volume {} with {} keys in {:.2?}, {} segments scanned",
+            data_path.display(),
+            store.index.len(),
+            elapsed,
+            scanned
+        );
+        Ok(store)
+    }
+
+    /// Re-apply the WAL on top of the index. `candidates` holds the records
+    /// of each key put in the WAL, in write order; a put takes the latest one
+    /// holding its value, or is written again if none does.
+    fn replay_wal(
+        &self,
+        wal_file: &Path,
+        mut candidates: HashMap<String, Vec<BlobLocation>>,
+    ) -> Result<()> {
+        let mut writer = self.writer.lock().unwrap();
+        let (mut located, mut rewritten, mut deleted) = (0, 0, 0);
+        Wal::replay(wal_file, |entry: WalEntry| {
+            match entry.op {
+                WalOp::Put { key, value } => {
+                    let records = candidates.entry(key.clone()).or_default();
+                    let found = records
+                        .iter()
+                        .rev()
+                        .find(|location| {
+                            matches!(self.read_blob(location), Ok(Some(stored)) if stored == value)
+                        })
+                        .cloned();
+                    let location = match found {
+                        Some(location) => {
+                            located += 1;
+                            location
+                        }
+                        None => {
+                            rewritten += 1;
+                            let encoded = self.encode(&value)?;
+                            let location = self.append_encoded(&mut writer, &key, &encoded)?;
+                            records.push(location.clone());
+                            location
+                        }
+                    };
+                    self.bloom.write().unwrap().set(&key);
+                    // Already indexed there: keep its TTL
+                    let current = self.index.get(&key).map(|c| (c.shard, c.offset));
+                    if current != Some((location.shard, location.offset)) {
+                        let blake3 = blake3_hash(&value);
+                        self.index.insert(key, BlobLocation { blake3, ..location });
+                    }
+                }
+                WalOp::Delete { key } => {
+                    deleted += 1;
+                    self.index.remove(&key);
+                }
+            }
+            Ok(())
+        })?;
+        if located + rewritten + deleted > 0 {
+            tracing::info!(
+                "WAL replay: {} puts located, {} rewritten, {} deletes",
+                located,
+                rewritten,
+                deleted
+            );
+        }
+        Ok(())
     }
 
     /// Open BlobStore with compression enabled (v0.5.0)
@@ -143,30 +513,96 @@ impl BlobStore {
         compression: CompressionMode,
     ) -> Result<Self> {
         let mut store = Self::open(data_path, wal_path, sync_policy)?;
-        store.compression = compression;
+        store.compression.algorithm = compression;
         Ok(store)
     }
 
     /// Set compression mode at runtime
     pub fn set_compression(&mut self, mode: CompressionMode) {
-        self.compression = mode;
+        self.compression.algorithm = mode;
     }
 
     /// Get current compression mode
     pub fn compression_mode(&self) -> CompressionMode {
-        self.compression
+        self.compression.algorithm
+    }
+
+    /// Set the compression algorithm, threshold and level (from `VolumeConfig::compression`)
+    pub fn set_compression_config(&mut self, config: CompressionConfig) {
+        self.compression = config;
+    }
+
+    /// Set the batching of WAL group commit (from `VolumeConfig::wal_group_commit`)
+    pub fn set_group_commit(&mut self, config: GroupCommitConfig) {
+        self.writer.get_mut().unwrap().wal.set_group_commit(config);
+    }
+
+    /// Set the size of a segment and the most segments the store may have,
+    /// 0 for no limit (from `VolumeConfig::segment_size_bytes` and
+    /// `VolumeConfig::max_segments`). Only later rolls are affected.
+    pub fn set_segment_limits(&mut self, segment_size: u64, max_segments: u64) {
+        let writer = self.writer.get_mut().unwrap();
+        writer.segment_size = segment_size.max(1);
+        writer.max_segments = max_segments;
+    }
+
+    /// Refuse writes once the disks have less than `min_free_bytes` free
+    /// (from `VolumeConfig::min_free_bytes`, 0 = never)
+    pub fn set_min_free_bytes(&mut self, min_free_bytes: u64) {
+        self.min_free_bytes = min_free_bytes;
+        self.refresh_read_only();
+    }
+
+    /// Size the cache of recently read blobs (from `VolumeConfig::cache`)
+    pub fn set_cache_config(&mut self, config: BlobCacheConfig) {
+        self.cache = BlobCache::new(config);
+    }
+
+    /// Read segments through memory maps (from `VolumeConfig::mmap_reads`).
+    /// Falls back to file reads for a segment that can't be mapped.
+    pub fn set_mmap_reads(&mut self, enabled: bool) {
+        self.mmap_reads = enabled;
+        self.segment_maps.get_mut().unwrap().clear();
+    }
+
+    /// Cache of recently read blobs
+    pub fn cache(&self) -> &BlobCache {
+        &self.cache
+    }
+
+    /// Durability of the writes made so far. With `WalSyncPolicy::Group`,
+    /// concurrent writes waiting on it share an fsync.
+    pub fn wal_commit(&self) -> WalCommit {
+        self.writer.lock().unwrap().wal.commit()
+    }
+
+    /// Flush and fsync the WAL, whatever the sync policy
+    pub fn sync_wal(&self) -> Result<()> {
+        self.writer.lock().unwrap().wal.sync()
+    }
+
+    /// Whether the WAL can still be written to (see `Wal::check_writable`)
+    pub fn check_wal(&self) -> Result<()> {
+        self.writer.lock().unwrap().wal.check_writable()
     }
 
     /// Put a key-value pair with optional TTL (v0.5.0)
     /// If ttl_ms is Some, the key will expire after the specified milliseconds.
-    pub fn put_with_ttl(&mut self, key: &str, value: &[u8], ttl_ms: Option<u64>) -> Result<()> {
-        self.wal.append_put(key, value)?;
-        let hash = blake3_hash(key.as_bytes());
-        let hash_vec: Vec<u8> = hex::decode(&hash).unwrap_or_else(|_| vec![0u8; 32]);
-        let hash_bytes: [u8; 32] = hash_vec.try_into().unwrap_or([0u8; 32]);
-        self.bloom.set(&hash_bytes);
-
-        let mut location = self.write_blob(key, value)?;
+    pub fn put_with_ttl(&self, key: &str, value: &[u8], ttl_ms: Option<u64>) -> Result<()> {
+        self.check_writable()?;
+        let encoded = self.encode(value)?;
+        let mut writer = self.writer.lock().unwrap();
+        // Before logging the put, so a full store rejects it outright
+        writer.roll_segment(&self.disks)?;
+        writer
+            .wal
+            .append_put(key, value)
+            .map_err(|e| self.write_error(e))?;
+        self.bloom.write().unwrap().set(key);
+
+        let mut location = self
+            .append_encoded(&mut writer, key, &encoded)
+            .map_err(|e| self.write_error(e))?;
 
         // Set expiration if TTL is provided
         if let Some(ttl) = ttl_ms {
@@ -178,106 +614,536 @@ impl BlobStore {
         }
 
         self.index.insert(key.to_string(), location);
+        self.cache.remove(key);
+        self.changes.fetch_add(1, Ordering::SeqCst);
+        self.resize_bloom_if_needed();
         Ok(())
     }
 
-    pub fn put(&mut self, key: &str, value: &[u8]) -> Result<()> {
+    pub fn put(&self, key: &str, value: &[u8]) -> Result<()> {
         self.put_with_ttl(key, value, None)
     }
 
     pub fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
-        let hash = blake3_hash(key.as_bytes());
-        let hash_vec: Vec<u8> = hex::decode(&hash).unwrap_or_else(|_| vec![0u8; 32]);
-        let hash_bytes: [u8; 32] = hash_vec.try_into().unwrap_or([0u8; 32]);
-
-        if !self.bloom.check(&hash_bytes) {
+        if !self.bloom.read().unwrap().check(key) {
             return Ok(None);
         }
 
+        let _layout = self.layout.read().unwrap();
         // Use get_if_valid to respect TTL (v0.5.0)
-        match self.index.get_if_valid(key) {
-            Some(loc) => self.read_blob(loc),
-            None => Ok(None),
+        let Some(loc) = self.lookup(key) else {
+            return Ok(None);
+        };
+        if let Some(value) = self.cache.get(key, &loc) {
+            return Ok(Some(value));
+        }
+        let value = self.read_blob(&loc)?;
+        if let Some(value) = &value {
+            self.cache.insert(key, &loc, value);
+        }
+        Ok(value)
+    }
+
+    /// Read `length` bytes of the value of `key` from `offset`, cut short at
+    /// the end of the value. Only that slice is read from plain records,
+    /// which leaves their checksum unverified; compressed or encrypted
+    /// records are decoded whole.
+    pub fn get_range(&self, key: &str, offset: u64, length: u64) -> Result<Option<Vec<u8>>> {
+        if !self.bloom.read().unwrap().check(key) {
+            return Ok(None);
+        }
+
+        let _layout = self.layout.read().unwrap();
+        let Some(loc) = self.lookup(key) else {
+            return Ok(None);
+        };
+        if let Some(value) = self.cache.get(key, &loc) {
+            return Ok(Some(slice_of(&value, offset, length).to_vec()));
+        }
+        self.read_range(&loc, offset, length)
+    }
+
+    /// Unexpired location of `key`, past the bloom filter. Counts a false
+    /// positive if the key isn't indexed at all.
+    fn lookup(&self, key: &str) -> Option<BlobLocation> {
+        let location = self.index.get(key);
+        if location.is_none() {
+            self.bloom_false_positives.fetch_add(1, Ordering::Relaxed);
+            METRICS.bloom_false_positives.inc();
+        }
+        location.filter(|location| !location.is_expired())
+    }
+
+    /// Rebuild the bloom filter from the index if the keys outgrew it or
+    /// shrank well below it. Called with the writer locked, so that no key
+    /// is added meanwhile.
+    fn resize_bloom_if_needed(&self) {
+        let keys = self.index.len();
+        if !self.bloom.read().unwrap().needs_resize(keys) {
+            return;
+        }
+        let mut bloom = KeyFilter::for_keys(keys);
+        self.index.for_each(|key, _| bloom.set(key));
+        tracing::debug!(
+            "Resized the bloom filter for {} keys ({} before)",
+            bloom.capacity,
+            self.bloom.read().unwrap().capacity
+        );
+        METRICS.bloom_capacity_keys.set(bloom.capacity as u64);
+        METRICS.bloom_resizes.inc();
+        *self.bloom.write().unwrap() = bloom;
+    }
+
+    pub fn delete(&self, key: &str) -> Result<()> {
+        let mut writer = self.writer.lock().unwrap();
+        self.delete_locked(&mut writer, key)
+    }
+
+    fn delete_locked(&self, writer: &mut Writer, key: &str) -> Result<()> {
+        writer
+            .wal
+            .append_delete(key)
+            .map_err(|e| self.write_error(e))?;
+        if self.index.remove(key).is_some() {
+            self.cache.remove(key);
+            self.changes.fetch_add(1, Ordering::SeqCst);
+            self.append_tombstone(writer, key)
+                .map_err(|e| self.write_error(e))?;
+            self.resize_bloom_if_needed();
         }
+        Ok(())
     }
 
-    pub fn delete(&mut self, key: &str) -> Result<()> {
-        self.wal.append_delete(key)?;
-        self.index.remove(key);
+    /// Rewrite the live keys into new segments, all in the primary data
+    /// directory. Records are copied as stored, keeping their compression,
+    /// encryption and TTL; corrupted ones are dropped. Writes wait
+    /// for the whole compaction, reads go on from the old segments until the
+    /// swap. Refused while a disk is failed, as its keys couldn't be copied.
+    pub fn compact(&self) -> Result<()> {
+        self.compact_with(|_| true)?;
         Ok(())
     }
 
-    pub fn compact(&mut self) -> Result<()> {
+    /// `compact`, calling `on_copy` with the size of each record copied.
+    /// Once it returns false, the copy stops and the new segments are
+    /// dropped, leaving the store as it was; returns whether it went through.
+    pub fn compact_with(&self, mut on_copy: impl FnMut(u64) -> bool) -> Result<bool> {
+        let mut writer = self.writer.lock().unwrap();
+        if self.disks.has_failed() {
+            return Err(crate::Error::DiskUnavailable(
+                "a data directory failed, compacting segment by segment only".into(),
+            ));
+        }
         let temp_path = self.data_path.join("compact_temp");
-        fs::create_dir_all(&temp_path)?;
+        if temp_path.exists() {
+            fs::remove_dir_all(&temp_path)?;
+        }
+        fs::create_dir_all(temp_path.join(SEGMENTS_DIR))?;
 
         let mut new_index = Index::new();
         let mut new_segment = 0u64;
         let mut new_offset = 0u64;
 
-        for (key, old_location) in self.index.iter() {
-            if let Ok(Some(value)) = self.read_blob(old_location) {
-                let (location, bytes_written) =
-                    self.write_blob_to_segment(&temp_path, new_segment, new_offset, key, &value)?;
-                new_index.insert(key.clone(), location);
-                new_offset += bytes_written;
-                if new_offset > SEGMENT_SIZE {
-                    new_segment += 1;
-                    new_offset = 0;
-                }
+        for (key, old_location) in self.index.snapshot().iter() {
+            let bytes_written =
+                match self.copy_record(old_location, &temp_path, new_segment, new_offset) {
+                    Ok(Some(bytes_written)) => bytes_written,
+                    Ok(None) => continue,
+                    Err(e) if is_corruption(&e) => {
+                        tracing::warn!("Compaction dropped corrupted record of {}: {}", key, e);
+                        continue;
+                    }
+                    Err(e) => return Err(e),
+                };
+            let location = BlobLocation {
+                shard: new_segment,
+                offset: new_offset,
+                ..old_location.clone()
+            };
+            new_index.insert(key.clone(), location);
+            new_offset += bytes_written;
+            if new_offset > writer.segment_size {
+                new_segment += 1;
+                new_offset = 0;
+            }
+            if !on_copy(bytes_written) {
+                fs::remove_dir_all(&temp_path)?;
+                return Ok(false);
+            }
+            if FAULTS.kill_compaction() {
+                return Err(killed());
             }
         }
 
+        let segments_dir = self.data_path.join(SEGMENTS_DIR);
         let backup_path = self.data_path.join("compact_backup");
-        fs::rename(&self.data_path, &backup_path)?;
-        fs::rename(&temp_path, &self.data_path)?;
+        let segments: BTreeSet<u64> = new_index.iter().map(|(_, l)| l.shard).collect();
+        {
+            let _layout = self.layout.write().unwrap();
+            // The snapshot locates keys in the old segments: until the new
+            // one is saved, a restart rebuilds the index from the segments
+            let snapshot_path = self.data_path.join("index.snap");
+            if snapshot_path.exists() {
+                fs::remove_file(&snapshot_path)?;
+            }
+            // The other directories first: a crash before the primary's
+            // swap puts them all back (see `recover_layout`)
+            for dir in self.disks.dirs().iter().skip(1) {
+                if dir.join(SEGMENTS_DIR).exists() {
+                    fs::rename(dir.join(SEGMENTS_DIR), dir.join("compact_backup"))?;
+                }
+            }
+            if segments_dir.exists() {
+                fs::rename(&segments_dir, &backup_path)?;
+            }
+            fs::rename(temp_path.join(SEGMENTS_DIR), &segments_dir)?;
+            self.disks.reset_to_primary(segments);
+            self.index.replace(new_index);
+            self.cache.clear();
+            self.segment_maps.write().unwrap().clear();
+        }
 
-        self.index = new_index;
-        self.current_segment = new_segment;
-        self.current_offset = new_offset;
+        writer.current_segment = new_segment;
+        writer.current_offset = new_offset;
 
         self.save_snapshot()?;
-        self.wal.truncate()?;
-        fs::remove_dir_all(&backup_path)?;
+        writer.wal.truncate()?;
+        for dir in self.disks.dirs().iter().skip(1) {
+            if dir.join("compact_backup").exists() {
+                fs::remove_dir_all(dir.join("compact_backup"))?;
+            }
+        }
+        if backup_path.exists() {
+            fs::remove_dir_all(&backup_path)?;
+        }
+        fs::remove_dir_all(&temp_path)?;
 
-        Ok(())
+        Ok(true)
+    }
+
+    /// Bytes of the segments and of the live records in them
+    pub fn space_usage(&self) -> Result<SpaceUsage> {
+        let usage = self.segment_usage()?;
+        Ok(SpaceUsage {
+            segment_bytes: usage.values().map(|u| u.segment_bytes).sum(),
+            live_bytes: usage.values().map(|u| u.live_bytes).sum(),
+        })
+    }
+
+    /// `space_usage` of each segment, leaving out those on failed disks
+    pub fn segment_usage(&self) -> Result<BTreeMap<u64, SpaceUsage>> {
+        let _layout = self.layout.read().unwrap();
+        let mut usage: BTreeMap<u64, SpaceUsage> = BTreeMap::new();
+        for (segment, path) in self.disks.segment_files() {
+            usage.entry(segment).or_default().segment_bytes = fs::metadata(&path)?.len();
+        }
+        self.index.for_each(|key, loc| {
+            if let Some(usage) = usage.get_mut(&loc.shard) {
+                let stored = loc.compressed_size.unwrap_or(loc.size);
+                usage.live_bytes += RECORD_OVERHEAD + key.len() as u64 + stored;
+            }
+        });
+        Ok(usage)
+    }
+
+    /// Segment new records are appended to
+    pub fn active_segment(&self) -> u64 {
+        self.writer.lock().unwrap().current_segment
+    }
+
+    /// Move the live records of `segment` to the active segment, then remove
+    /// it. Writes wait for this one segment only; reads go on throughout.
+    /// Returns the bytes reclaimed.
+    pub fn compact_segment(&self, segment: u64) -> Result<u64> {
+        Ok(self.compact_segment_with(segment, |_| true)?.unwrap_or(0))
+    }
+
+    /// `compact_segment`, calling `on_copy` with the size of each record
+    /// copied. Once it returns false, the copy stops and returns `None`,
+    /// the segment stays in use and the records copied so far are garbage.
+    ///
+    /// Records are copied as stored, so they keep their compression,
+    /// encryption and TTL. Tombstones are carried over while older segments
+    /// may still hold a record they shadow. The copies are synced and the
+    /// index snapshot saved before the segment is removed, so a crash leaves
+    /// either the segment or its copies to the next open.
+    pub fn compact_segment_with(
+        &self,
+        segment: u64,
+        mut on_copy: impl FnMut(u64) -> bool,
+    ) -> Result<Option<u64>> {
+        let mut writer = self.writer.lock().unwrap();
+        if segment == writer.current_segment {
+            return Err(crate::Error::InvalidRequest(format!(
+                "segment {} is the active segment",
+                segment
+            )));
+        }
+        let Some(path) = self.disks.locate(segment)?.filter(|path| path.exists()) else {
+            return Err(crate::Error::NotFound(format!("segment {}", segment)));
+        };
+        // Tombstones may shadow records on a failed disk
+        let oldest = !self.disks.has_failed()
+            && self.disks.segment_files().first().map(|(s, _)| *s) == Some(segment);
+
+        let mut records = Vec::new();
+        Self::scan_segment(&path, segment, |key, location| {
+            records.push((key, location))
+        })?;
+
+        let mut moved = Vec::new();
+        let mut tombstones = HashSet::new();
+        let mut written = HashSet::new();
+        let mut copied = 0;
+        for (key, location) in records {
+            let bytes = match location {
+                Some(location) => {
+                    let Some(current) = self.index.get(&key) else {
+                        continue;
+                    };
+                    if current.shard != segment || current.offset != location.offset {
+                        continue;
+                    }
+                    writer.roll_segment(&self.disks)?;
+                    let offset = writer.current_offset;
+                    let Some(bytes) = self.copy_record(
+                        &current,
+                        &self.disks.dir_for_write(writer.current_segment)?,
+                        writer.current_segment,
+                        offset,
+                    )?
+                    else {
+                        continue;
+                    };
+                    writer.current_offset += bytes;
+                    let location = BlobLocation {
+                        shard: writer.current_segment,
+                        offset,
+                        ..current
+                    };
+                    moved.push((key, location));
+                    bytes
+                }
+                None if !oldest
+                    && self.index.get(&key).is_none()
+                    && tombstones.insert(key.clone()) =>
+                {
+                    self.append_tombstone(&mut writer, &key)?;
+                    RECORD_OVERHEAD + key.len() as u64
+                }
+                None => continue,
+            };
+            written.insert(writer.current_segment);
+            copied += bytes;
+            if !on_copy(bytes) {
+                return Ok(None);
+            }
+            if FAULTS.kill_compaction() {
+                return Err(killed());
+            }
+        }
+
+        for written in written {
+            if let Some(file) = self.disks.open_segment(written)? {
+                file.sync_all()?;
+            }
+        }
+        {
+            let _layout = self.layout.write().unwrap();
+            for (key, location) in moved {
+                self.index.insert(key, location);
+            }
+            self.segment_maps.write().unwrap().remove(&segment);
+        }
+        self.save_snapshot()?;
+        let size = fs::metadata(&path)?.len();
+        fs::remove_file(&path)?;
+        self.disks.forget(segment);
+        Ok(Some(size.saturating_sub(copied)))
+    }
+
+    /// Check the live records of `segment`: their checksum, and the blake3
+    /// of their value against the index. A corrupted record is quarantined:
+    /// its key is logged to `quarantine.log` and deleted, so reads miss
+    /// instead of serving it, and is kept for `corrupted_keys` until the
+    /// coordinator has been told to copy it back from another replica.
+    ///
+    /// Records are read one at a time, leaving writes and compaction free
+    /// in between. `on_record` is called with the size of each; once it
+    /// returns false the scrub stops and returns `None`.
+    pub fn scrub_segment_with(
+        &self,
+        segment: u64,
+        mut on_record: impl FnMut(u64) -> bool,
+    ) -> Result<Option<ScrubReport>> {
+        let mut records = Vec::new();
+        self.index.for_each(|key, location| {
+            if location.shard == segment {
+                records.push((key.clone(), location.clone()));
+            }
+        });
+        records.sort_by_key(|(_, location)| location.offset);
+
+        let mut report = ScrubReport::default();
+        for (key, location) in records {
+            let problem = {
+                let _layout = self.layout.read().unwrap();
+                if !self.indexed_at(&key, &location) {
+                    // Overwritten, deleted or moved since
+                    continue;
+                }
+                self.verify_record(&location)?
+            };
+            let size = RECORD_OVERHEAD
+                + key.len() as u64
+                + location.compressed_size.unwrap_or(location.size);
+            report.records += 1;
+            report.bytes += size;
+            if let Some(problem) = problem {
+                if self.quarantine(&key, &location, &problem)? {
+                    report.corrupted.push(key);
+                }
+            }
+            if !on_record(size) {
+                return Ok(None);
+            }
+        }
+        Ok(Some(report))
+    }
+
+    /// Whether the index still points `key` to `location`
+    fn indexed_at(&self, key: &str, location: &BlobLocation) -> bool {
+        matches!(
+            self.index.get(key),
+            Some(current) if current.shard == location.shard && current.offset == location.offset
+        )
+    }
+
+    /// Read the record at `location` and check it, returning what is wrong
+    /// with it. Errors other than corruption, such as a failed disk read,
+    /// are returned as such.
+    fn verify_record(&self, location: &BlobLocation) -> Result<Option<String>> {
+        match self.read_blob(location) {
+            Ok(Some(value)) => {
+                let actual = blake3_hash(&value);
+                if location.blake3.is_empty() || actual == location.blake3 {
+                    Ok(None)
+                } else {
+                    Ok(Some(format!(
+                        "blake3 {} instead of {}",
+                        actual, location.blake3
+                    )))
+                }
+            }
+            // Segment compacted away since
+            Ok(None) => Ok(None),
+            Err(e @ (crate::Error::Corrupted(_) | crate::Error::ChecksumMismatch { .. })) => {
+                Ok(Some(e.to_string()))
+            }
+            Err(crate::Error::Io(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                Ok(Some("record cut short".to_string()))
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Quarantine the record of `key` at `location`, unless it was replaced
+    /// meanwhile. Returns whether it was.
+    fn quarantine(&self, key: &str, location: &BlobLocation, problem: &str) -> Result<bool> {
+        let mut writer = self.writer.lock().unwrap();
+        if !self.indexed_at(key, location) {
+            return Ok(false);
+        }
+        tracing::error!(
+            "Quarantining {} (segment {}, offset {}): {}",
+            key,
+            location.shard,
+            location.offset,
+            problem
+        );
+        let mut log = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.data_path.join(QUARANTINE_LOG))?;
+        writeln!(
+            log,
+            "{}\t{:?}\t{}\t{}\t{}",
+            crate::common::timestamp_now(),
+            key,
+            location.shard,
+            location.offset,
+            problem
+        )?;
+        log.sync_all()?;
+        self.delete_locked(&mut writer, key)?;
+        self.corrupted.lock().unwrap().insert(key.to_string());
+        Ok(true)
     }
 
+    /// Keys quarantined since they were last reported
+    pub fn corrupted_keys(&self) -> Vec<String> {
+        self.corrupted.lock().unwrap().iter().cloned().collect()
+    }
+
+    /// Forget `keys`, reported to the coordinator
+    pub fn clear_corrupted_keys(&self, keys: &[String]) {
+        let mut corrupted = self.corrupted.lock().unwrap();
+        for key in keys {
+            corrupted.remove(key);
+        }
+    }
+
+    /// Save the index snapshot, then the bloom filter along with the hash
+    /// of that snapshot, so that `open` only loads a filter holding all of
+    /// its keys. Both are written to a temporary file first, so a crash
+    /// leaves the previous ones whole.
     pub fn save_snapshot(&self) -> Result<()> {
+        let changes = self.changes.load(Ordering::SeqCst);
         let snapshot_path = self.data_path.join("index.snap");
-        self.index.save_snapshot(&snapshot_path)?;
+        let temp_path = self.data_path.join("index.snap.tmp");
+        self.index.snapshot().save_snapshot(&temp_path)?;
+        let snapshot = fs::read(&temp_path)?;
+        File::open(&temp_path)?.sync_all()?;
+        fs::rename(&temp_path, &snapshot_path)?;
+
+        // Taken after the index: puts set the filter before indexing the key
+        let mut bytes = BLOOM_MAGIC.to_vec();
+        bytes.extend_from_slice(blake3::hash(&snapshot).as_bytes());
+        {
+            let bloom = self.bloom.read().unwrap();
+            bytes.extend_from_slice(&(bloom.capacity as u64).to_le_bytes());
+            bytes.extend_from_slice(&bloom.bloom.to_bytes());
+        }
         let bloom_path = self.data_path.join("bloom.filter");
-        let mut f = OpenOptions::new()
-            .create(true)
-            .write(true)
-            .truncate(true)
-            .open(&bloom_path)?;
-        f.write_all(&self.bloom.to_bytes())?;
+        let temp_path = self.data_path.join("bloom.filter.tmp");
+        let mut f = File::create(&temp_path)?;
+        f.write_all(&bytes)?;
         f.sync_all()?;
+        fs::rename(&temp_path, &bloom_path)?;
+        self.snapshot_changes.store(changes, Ordering::SeqCst);
         Ok(())
     }
 
+    /// Whether keys were written or deleted since the last `save_snapshot`
+    pub fn changed_since_snapshot(&self) -> bool {
+        self.changes.load(Ordering::SeqCst) != self.snapshot_changes.load(Ordering::SeqCst)
+    }
+
     /// Clean up expired keys (v0.5.0)
     /// Returns the number of keys removed.
-    pub fn cleanup_expired(&mut self) -> usize {
+    pub fn cleanup_expired(&self) -> usize {
         self.index.cleanup_expired()
     }
 
     /// Get TTL remaining for a key in milliseconds (v0.5.0)
     /// Returns None if key doesn't exist or has no TTL.
     pub fn get_ttl(&self, key: &str) -> Option<u64> {
-        if let Some(loc) = self.index.get(key) {
-            if let Some(expires_at) = loc.expires_at {
-                let now = std::time::SystemTime::now()
-                    .duration_since(std::time::UNIX_EPOCH)
-                    .unwrap()
-                    .as_millis() as u64;
-                if now < expires_at {
-                    return Some(expires_at - now);
-                }
-            }
-        }
-        None
+        let expires_at = self.index.get(key)?.expires_at?;
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+        (now < expires_at).then(|| expires_at - now)
     }
 
     /// Check if a key exists (respecting TTL) (v0.5.0)
@@ -285,55 +1151,326 @@ impl BlobStore {
         self.index.get_if_valid(key).is_some()
     }
 
+    /// Location of the live value of `key` (respecting TTL)
+    pub fn location(&self, key: &str) -> Option<BlobLocation> {
+        self.index.get_if_valid(key)
+    }
+
+    /// `(key, blake3)` of every live key, used to build anti-entropy Merkle trees
+    pub fn key_hashes(&self) -> Vec<(String, String)> {
+        let mut hashes = Vec::new();
+        self.index.for_each(|key, loc| {
+            if !loc.is_expired() {
+                hashes.push((key.clone(), loc.blake3.clone()));
+            }
+        });
+        hashes
+    }
+
+    /// Up to `limit` live `(key, blake3, size)` entries after `after`, in key order
+    pub fn blobs_after(&self, after: &str, limit: usize) -> Vec<(String, String, u64)> {
+        let mut blobs = Vec::new();
+        self.index.for_each(|key, loc| {
+            if key.as_str() > after && !loc.is_expired() {
+                blobs.push((key.clone(), loc.blake3.clone(), loc.size));
+            }
+        });
+        if blobs.len() > limit {
+            blobs.select_nth_unstable_by(limit, |a, b| a.0.cmp(&b.0));
+            blobs.truncate(limit);
+        }
+        blobs.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+        blobs
+    }
+
+    /// Delete `key` if it still holds the value hashing to `blake3`.
+    /// Returns the size of the deleted blob.
+    pub fn delete_if_hash(&self, key: &str, blake3: &str) -> Result<Option<u64>> {
+        let mut writer = self.writer.lock().unwrap();
+        let size = match self.index.get(key) {
+            Some(loc) if loc.blake3 == blake3 => loc.size,
+            _ => return Ok(None),
+        };
+        self.delete_locked(&mut writer, key)?;
+        Ok(Some(size))
+    }
+
     pub fn stats(&self) -> StoreStats {
-        let total_bytes: u64 = self.index.iter().map(|(_, loc)| loc.size).sum();
-        let keys_with_ttl = self.index.keys_with_ttl().len();
+        let (mut total_bytes, mut keys_with_ttl) = (0, 0);
+        let (mut compressed_blobs, mut compressed_bytes, mut compressed_stored_bytes) = (0, 0, 0);
+        self.index.for_each(|_, loc| {
+            total_bytes += loc.size;
+            if loc.expires_at.is_some() {
+                keys_with_ttl += 1;
+            }
+            if let Some(stored) = loc.compressed_size {
+                compressed_blobs += 1;
+                compressed_bytes += loc.size;
+                compressed_stored_bytes += stored;
+            }
+        });
+        let (current_segment, wal_bytes) = {
+            let writer = self.writer.lock().unwrap();
+            (writer.current_segment, writer.wal.size_bytes())
+        };
+        let total_keys = self.index.len();
         StoreStats {
-            total_keys: self.index.len(),
+            total_keys,
             total_bytes,
-            active_segments: (self.current_segment + 1) as usize,
-            index_size: self.index.len(),
-            bloom_false_positives: 0,
+            active_segments: (current_segment + 1) as usize,
+            index_size: total_keys,
+            bloom_false_positives: self.bloom_false_positives.load(Ordering::Relaxed),
+            bloom_capacity: self.bloom.read().unwrap().capacity,
             keys_with_ttl,
-            compressed_blobs: 0, // TODO: track number of compressed blobs
+            compressed_blobs,
+            compressed_bytes,
+            compressed_stored_bytes,
+            wal_bytes,
         }
     }
 
-    fn write_blob(&mut self, key: &str, value: &[u8]) -> Result<BlobLocation> {
-        if self.current_offset > SEGMENT_SIZE {
-            self.current_segment += 1;
-            self.current_offset = 0;
-            if self.current_segment >= MAX_SEGMENTS {
-                return Err(crate::Error::Internal("Max segments reached".into()));
+    /// Re-encrypt up to `limit` keys after `after`, in key order, whose
+    /// records aren't sealed with the current master key (including
+    /// plaintext ones). The rewritten records are appended like any put; the
+    /// superseded ones stay in their segments until the next compaction.
+    pub fn rekey_batch(&self, after: &str, limit: usize) -> Result<RekeyBatch> {
+        let mut keys = Vec::new();
+        self.index.for_each(|key, _| {
+            if key.as_str() > after {
+                keys.push(key.clone());
             }
+        });
+        if keys.len() > limit {
+            keys.select_nth_unstable(limit);
+            keys.truncate(limit);
         }
+        keys.sort_unstable();
 
-        let (location, bytes_written) = self.write_blob_to_segment(
-            &self.data_path,
-            self.current_segment,
-            self.current_offset,
-            key,
-            value,
-        )?;
-        self.current_offset = location.offset + bytes_written;
-        Ok(location)
-    }
-
-    /// Write a blob to a segment file.
-    /// Returns (BlobLocation, total_bytes_written)
-    fn write_blob_to_segment(
-        &self,
-        base_path: &Path,
-        segment: u64,
-        offset: u64,
-        key: &str,
-        value: &[u8],
-    ) -> Result<(BlobLocation, u64)> {
-        let segment_dir = base_path
-            .join(format!("{:02}", segment % 100))
-            .join(format!("{:02}", segment / 100));
-        fs::create_dir_all(&segment_dir)?;
-        let segment_file = segment_dir.join(format!("seg_{:04}.blob", segment));
+        let mut batch = RekeyBatch {
+            scanned: keys.len(),
+            rewritten: 0,
+            next_after: keys.last().cloned(),
+        };
+        let mut writer = self.writer.lock().unwrap();
+        for key in keys {
+            let Some(location) = self.index.get_if_valid(&key) else {
+                continue;
+            };
+            let Some((flag, stored, _)) = self.read_record(&location)? else {
+                continue;
+            };
+            let current = flag & FLAG_ENCRYPTED != 0 && {
+                let encrypted = EncryptedData::from_bytes(&stored)?;
+                ENCRYPTION_MANAGER.read().unwrap().is_current(&encrypted)
+            };
+            if current {
+                continue;
+            }
+            let Some(value) = self.read_blob(&location)? else {
+                continue;
+            };
+            writer.wal.append_put(&key, &value)?;
+            let encoded = self.encode(&value)?;
+            let mut rewritten = self.append_encoded(&mut writer, &key, &encoded)?;
+            rewritten.expires_at = location.expires_at;
+            self.index.insert(key, rewritten);
+            batch.rewritten += 1;
+        }
+        Ok(batch)
+    }
+
+    /// Free space on the disks holding the data directories, if it can be
+    /// measured
+    pub fn free_bytes(&self) -> Option<u64> {
+        self.disks.free_bytes()
+    }
+
+    /// State of each data directory
+    pub fn disk_stats(&self) -> Vec<DiskStats> {
+        self.disks.stats()
+    }
+
+    /// Whether writes are refused for lack of free space
+    pub fn is_read_only(&self) -> bool {
+        self.read_only.load(Ordering::SeqCst)
+    }
+
+    /// Measure the free space, turning read-only below `min_free_bytes` or
+    /// back to writable with 10% more than that. Returns whether the store
+    /// is now read-only.
+    pub fn refresh_read_only(&self) -> bool {
+        self.free_space_checked_at
+            .store(timestamp_now_millis(), Ordering::SeqCst);
+        let Some(free) = self.free_bytes() else {
+            // Can't tell: writes fail on their own if the disk is full
+            self.set_read_only(false, "free space can't be measured");
+            return false;
+        };
+        let floor = if self.is_read_only() {
+            self.min_free_bytes.saturating_add(self.min_free_bytes / 10)
+        } else {
+            self.min_free_bytes
+        };
+        let read_only = free < floor;
+        self.set_read_only(
+            read_only,
+            &format!("{} bytes free, watermark {}", free, self.min_free_bytes),
+        );
+        read_only
+    }
+
+    /// Fail with `Error::StorageFull` while the store is read-only,
+    /// measuring the free space again at most every `FREE_SPACE_CHECK_MS`
+    pub fn check_writable(&self) -> Result<()> {
+        let checked_at = self.free_space_checked_at.load(Ordering::SeqCst);
+        let read_only = if timestamp_now_millis().saturating_sub(checked_at) >= FREE_SPACE_CHECK_MS
+        {
+            self.refresh_read_only()
+        } else {
+            self.is_read_only()
+        };
+        if read_only {
+            return Err(crate::Error::StorageFull(format!(
+                "volume is read-only below {} bytes free",
+                self.min_free_bytes
+            )));
+        }
+        Ok(())
+    }
+
+    fn set_read_only(&self, read_only: bool, why: &str) {
+        if self.read_only.swap(read_only, Ordering::SeqCst) == read_only {
+            return;
+        }
+        METRICS.volume_read_only.set(read_only as u64);
+        if read_only {
+            tracing::warn!("Volume is now read-only: {}", why);
+        } else {
+            tracing::info!("Volume takes writes again: {}", why);
+        }
+    }
+
+    /// `error` from a write, as `Error::StorageFull` if the disk ran out of
+    /// space, which also turns the store read-only
+    fn write_error(&self, error: crate::Error) -> crate::Error {
+        match error {
+            crate::Error::Io(e) if e.raw_os_error() == Some(libc::ENOSPC) => {
+                self.set_read_only(true, &e.to_string());
+                crate::Error::StorageFull(e.to_string())
+            }
+            error => error,
+        }
+    }
+
+    /// Compress and seal `value` for its record
+    fn encode(&self, value: &[u8]) -> Result<Encoded> {
+        let (mut flag, mut stored) = compress(&self.compression, value);
+        {
+            let encryption = ENCRYPTION_MANAGER.read().unwrap();
+            if encryption.is_enabled() {
+                stored = encryption.encrypt(&stored)?.to_bytes();
+                flag |= FLAG_ENCRYPTED;
+            }
+        }
+        Ok(Encoded {
+            flag,
+            stored,
+            size: value.len() as u64,
+            blake3: blake3_hash(value),
+        })
+    }
+
+    /// Append the record of an encoded value to the active segment
+    fn append_encoded(
+        &self,
+        writer: &mut Writer,
+        key: &str,
+        encoded: &Encoded,
+    ) -> Result<BlobLocation> {
+        writer.roll_segment(&self.disks)?;
+        let (location, bytes_written) = self.write_encoded(
+            &self.disks.dir_for_write(writer.current_segment)?,
+            writer.current_segment,
+            writer.current_offset,
+            key,
+            encoded,
+        )?;
+        writer.current_offset = location.offset + bytes_written;
+        Ok(location)
+    }
+
+    /// Append a tombstone for `key` to the active segment
+    fn append_tombstone(&self, writer: &mut Writer, key: &str) -> Result<()> {
+        // Deletes go on in a full store: the tombstone is small enough to
+        // overrun the active segment
+        if let Err(e) = writer.roll_segment(&self.disks) {
+            tracing::debug!(
+                "Writing the tombstone of {} in the active segment: {}",
+                key,
+                e
+            );
+        }
+        writer.current_offset += self.write_record(
+            &self.disks.dir_for_write(writer.current_segment)?,
+            writer.current_segment,
+            writer.current_offset,
+            FLAG_TOMBSTONE,
+            key,
+            &[],
+            0,
+        )?;
+        Ok(())
+    }
+
+    /// Write the record of an encoded value to a segment file.
+    /// Returns (BlobLocation, total_bytes_written)
+    fn write_encoded(
+        &self,
+        base_path: &Path,
+        segment: u64,
+        offset: u64,
+        key: &str,
+        encoded: &Encoded,
+    ) -> Result<(BlobLocation, u64)> {
+        let bytes_written = self.write_record(
+            base_path,
+            segment,
+            offset,
+            encoded.flag,
+            key,
+            &encoded.stored,
+            encoded.size,
+        )?;
+        Ok((
+            BlobLocation {
+                shard: segment,
+                offset,
+                size: encoded.size,
+                blake3: encoded.blake3.clone(),
+                expires_at: None, // TTL is set by put_with_ttl, not here
+                compressed_size: (encoded.flag & !FLAG_ENCRYPTED != FLAG_NONE)
+                    .then_some(encoded.stored.len() as u64),
+            },
+            bytes_written,
+        ))
+    }
+
+    /// Write a record holding `write_value` as stored (compressed and
+    /// encrypted as `flag` says) at `offset` of a segment file. Returns the
+    /// number of bytes written.
+    #[allow(clippy::too_many_arguments)]
+    fn write_record(
+        &self,
+        base_path: &Path,
+        segment: u64,
+        offset: u64,
+        flag: u8,
+        key: &str,
+        write_value: &[u8],
+        orig_len: u64,
+    ) -> Result<u64> {
+        fs::create_dir_all(base_path.join(SEGMENTS_DIR))?;
+        let segment_file = segment_path(base_path, segment);
         let mut file = OpenOptions::new()
             .create(true)
             .write(true)
@@ -343,40 +1480,27 @@ impl BlobStore {
         file.seek(SeekFrom::Start(offset))?;
         let mut writer = BufWriter::new(&file);
 
-        // Compress value if compression is enabled and size is above threshold (v0.5.0)
-        let (write_value, is_compressed) =
-            if self.compression == CompressionMode::Lz4 && value.len() >= COMPRESSION_THRESHOLD {
-                match lz4::block::compress(value, None, true) {
-                    Ok(compressed) if compressed.len() < value.len() => (compressed, true),
-                    _ => (value.to_vec(), false), // Fallback to uncompressed if compression doesn't help
-                }
-            } else {
-                (value.to_vec(), false)
-            };
-
-        // Use different magic for compressed blobs
-        let magic = if is_compressed {
-            BLOB_MAGIC_COMPRESSED
-        } else {
-            BLOB_MAGIC
-        };
-        writer.write_all(&magic)?;
+        writer.write_all(&BLOB_MAGIC_FLAGGED)?;
+        writer.write_all(&[flag])?;
 
         // Store original size for decompression
         writer.write_all(&(key.len() as u32).to_le_bytes())?;
         writer.write_all(&(write_value.len() as u64).to_le_bytes())?;
         // Store original size for compressed blobs
-        writer.write_all(&(value.len() as u64).to_le_bytes())?;
+        writer.write_all(&orig_len.to_le_bytes())?;
         writer.write_all(key.as_bytes())?;
-        writer.write_all(&write_value)?;
+        writer.write_all(write_value)?;
 
-        let mut checksum_data = Vec::new();
+        let mut checksum_data = vec![flag];
         checksum_data.extend_from_slice(&(key.len() as u32).to_le_bytes());
         checksum_data.extend_from_slice(&(write_value.len() as u64).to_le_bytes());
-        checksum_data.extend_from_slice(&(value.len() as u64).to_le_bytes());
+        checksum_data.extend_from_slice(&orig_len.to_le_bytes());
         checksum_data.extend_from_slice(key.as_bytes());
-        checksum_data.extend_from_slice(&write_value);
-        let checksum = crc32(&checksum_data);
+        checksum_data.extend_from_slice(write_value);
+        let mut checksum = crc32(&checksum_data);
+        if FAULTS.corrupt_record() {
+            checksum = !checksum;
+        }
         writer.write_all(&checksum.to_le_bytes())?;
         writer.flush()?;
 
@@ -384,235 +1508,743 @@ impl BlobStore {
             file.sync_all()?;
         }
 
-        // Calculate total bytes written:
-        // MAGIC(4) + KEY_LEN(4) + VAL_LEN(8) + ORIG_LEN(8) + KEY + VALUE + CHECKSUM(4)
-        let bytes_written = 4 + 4 + 8 + 8 + key.len() as u64 + write_value.len() as u64 + 4;
+        Ok(RECORD_OVERHEAD + key.len() as u64 + write_value.len() as u64)
+    }
 
-        let blake3 = blake3_hash(value);
-        Ok((
-            BlobLocation {
-                shard: segment,
-                offset,
-                size: value.len() as u64,
-                blake3,
-                expires_at: None, // TTL is set by put_with_ttl, not here
-            },
-            bytes_written,
-        ))
+    /// Copy the record at `location` as stored to `offset` of `segment` in
+    /// the data directory `base_path`, reading and writing its value
+    /// `COPY_CHUNK_SIZE` bytes at a time. The checksum of the source is
+    /// checked as it goes; if it doesn't match, or the copy fails, the
+    /// segment is cut back to `offset`. Returns the number of bytes written,
+    /// `None` if the source segment is gone.
+    fn copy_record(
+        &self,
+        location: &BlobLocation,
+        base_path: &Path,
+        segment: u64,
+        offset: u64,
+    ) -> Result<Option<u64>> {
+        let Some(source) = self.disks.open_segment(location.shard)? else {
+            return Ok(None);
+        };
+        let mut reader = BufReader::new(source);
+        reader.seek(SeekFrom::Start(location.offset))?;
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        let Some(flag) = read_flag(&magic, &mut reader)? else {
+            return Err(crate::Error::Corrupted("Invalid blob magic".into()));
+        };
+        // KEY_LEN(4) + VAL_LEN(8) + ORIG_LEN(8), copied as they are
+        let mut lengths = [0u8; 20];
+        reader.read_exact(&mut lengths)?;
+        let key_len = u32::from_le_bytes(lengths[..4].try_into().unwrap()) as usize;
+        let val_len = u64::from_le_bytes(lengths[4..12].try_into().unwrap());
+        let mut key = vec![0u8; key_len];
+        reader.read_exact(&mut key)?;
+
+        fs::create_dir_all(base_path.join(SEGMENTS_DIR))?;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .read(true)
+            .truncate(false)
+            .open(segment_path(base_path, segment))?;
+        file.seek(SeekFrom::Start(offset))?;
+
+        let copied = (|| -> Result<()> {
+            // The copy is always flagged, the source may predate flags
+            let mut source_checksum = crc32fast::Hasher::new();
+            if magic == BLOB_MAGIC_FLAGGED {
+                source_checksum.update(&[flag]);
+            }
+            let mut checksum = crc32fast::Hasher::new();
+            checksum.update(&[flag]);
+            for header in [&lengths[..], &key] {
+                source_checksum.update(header);
+                checksum.update(header);
+            }
+
+            let mut writer = BufWriter::new(&file);
+            writer.write_all(&BLOB_MAGIC_FLAGGED)?;
+            writer.write_all(&[flag])?;
+            writer.write_all(&lengths)?;
+            writer.write_all(&key)?;
+            let mut chunk = vec![0u8; (val_len as usize).min(COPY_CHUNK_SIZE)];
+            let mut left = val_len;
+            while left > 0 {
+                let piece = &mut chunk[..left.min(COPY_CHUNK_SIZE as u64) as usize];
+                reader.read_exact(piece)?;
+                source_checksum.update(piece);
+                checksum.update(piece);
+                writer.write_all(piece)?;
+                left -= piece.len() as u64;
+            }
+
+            let mut stored = [0u8; 4];
+            reader.read_exact(&mut stored)?;
+            let (expected, actual) = (u32::from_le_bytes(stored), source_checksum.finalize());
+            if expected != actual {
+                return Err(crate::Error::ChecksumMismatch {
+                    expected: format!("{:08x}", expected),
+                    actual: format!("{:08x}", actual),
+                });
+            }
+            writer.write_all(&checksum.finalize().to_le_bytes())?;
+            writer.flush()?;
+            Ok(())
+        })();
+        if let Err(e) = copied {
+            file.set_len(offset)?;
+            return Err(e);
+        }
+
+        if self.sync_policy == WalSyncPolicy::Always {
+            file.sync_all()?;
+        }
+        Ok(Some(RECORD_OVERHEAD + key_len as u64 + val_len))
     }
 
     fn read_blob(&self, location: &BlobLocation) -> Result<Option<Vec<u8>>> {
-        let segment_file = self.data_path.join(format!(
-            "{:02}/{:02}/seg_{:04}.blob",
-            location.shard % 100,
-            location.shard / 100,
-            location.shard
-        ));
-        if !segment_file.exists() {
+        let Some((flag, value, orig_len)) = self.read_record(location)? else {
             return Ok(None);
-        }
+        };
+        let value = if flag & FLAG_ENCRYPTED != 0 {
+            let encrypted = EncryptedData::from_bytes(&value)?;
+            ENCRYPTION_MANAGER.read().unwrap().decrypt(&encrypted)?
+        } else {
+            value
+        };
+        decompress(flag & !FLAG_ENCRYPTED, value, orig_len).map(Some)
+    }
 
-        let file = File::open(&segment_file)?;
+    /// `get_range` from the segment
+    fn read_range(
+        &self,
+        location: &BlobLocation,
+        offset: u64,
+        length: u64,
+    ) -> Result<Option<Vec<u8>>> {
+        let Some(file) = self.disks.open_segment(location.shard)? else {
+            return Ok(None);
+        };
         let mut reader = BufReader::new(file);
         reader.seek(SeekFrom::Start(location.offset))?;
-
-        let mut magic = [0u8; 4];
-        reader.read_exact(&mut magic)?;
-
-        // Check for both compressed and uncompressed magic (v0.5.0)
-        let is_compressed = magic == BLOB_MAGIC_COMPRESSED;
-        if magic != BLOB_MAGIC && !is_compressed {
+        let Some((flag, _, record_len, val_len, _)) = Self::read_record_header(&mut reader)? else {
             return Err(crate::Error::Corrupted("Invalid blob magic".into()));
+        };
+        if flag != FLAG_NONE {
+            let value = self.read_blob(location)?;
+            return Ok(value.map(|value| slice_of(&value, offset, length).to_vec()));
         }
 
-        let mut key_len_bytes = [0u8; 4];
-        reader.read_exact(&mut key_len_bytes)?;
-        let key_len = u32::from_le_bytes(key_len_bytes) as usize;
+        // The value sits right before the checksum
+        let value_start = location.offset + record_len - val_len - 4;
+        let start = offset.min(val_len);
+        let end = start.saturating_add(length).min(val_len);
+        reader.seek(SeekFrom::Start(value_start + start))?;
+        let mut slice = vec![0u8; (end - start) as usize];
+        reader.read_exact(&mut slice)?;
+        Ok(Some(slice))
+    }
 
-        let mut val_len_bytes = [0u8; 8];
-        reader.read_exact(&mut val_len_bytes)?;
-        let val_len = u64::from_le_bytes(val_len_bytes) as usize;
+    /// Read and verify the record at `location`: its flag, its value as
+    /// stored (possibly compressed and encrypted) and its original size
+    fn read_record(&self, location: &BlobLocation) -> Result<Option<(u8, Vec<u8>, usize)>> {
+        if self.mmap_reads {
+            match self.segment_map(location.shard, false) {
+                Ok(Some(map)) => return self.read_record_mapped(location, map),
+                Ok(None) => return Ok(None),
+                Err(e) => tracing::debug!(
+                    "Mapping segment {} failed, reading it instead: {}",
+                    location.shard,
+                    e
+                ),
+            }
+        }
 
-        // Read original size (v0.5.0)
-        let mut orig_len_bytes = [0u8; 8];
-        reader.read_exact(&mut orig_len_bytes)?;
-        let orig_len = u64::from_le_bytes(orig_len_bytes) as usize;
+        let Some(file) = self.disks.open_segment(location.shard)? else {
+            return Ok(None);
+        };
+        let mut reader = BufReader::new(file);
+        reader.seek(SeekFrom::Start(location.offset))?;
+        decode_record(&mut reader).map(Some)
+    }
 
-        let mut key_bytes = vec![0u8; key_len];
-        reader.read_exact(&mut key_bytes)?;
-        let mut value = vec![0u8; val_len];
-        reader.read_exact(&mut value)?;
-
-        let mut checksum_bytes = [0u8; 4];
-        reader.read_exact(&mut checksum_bytes)?;
-        let stored_checksum = u32::from_le_bytes(checksum_bytes);
-
-        let mut checksum_data = Vec::new();
-        checksum_data.extend_from_slice(&key_len_bytes);
-        checksum_data.extend_from_slice(&val_len_bytes);
-        checksum_data.extend_from_slice(&orig_len_bytes);
-        checksum_data.extend_from_slice(&key_bytes);
-        checksum_data.extend_from_slice(&value);
-        let computed_checksum = crc32(&checksum_data);
-
-        if computed_checksum != stored_checksum {
-            return Err(crate::Error::ChecksumMismatch {
-                expected: format!("{:08x}", stored_checksum),
-                actual: format!("{:08x}", computed_checksum),
-            });
+    /// `read_record` from the memory map of the segment. A map only covers
+    /// the segment as it was when taken, so a record appended since is read
+    /// from a fresh one.
+    fn read_record_mapped(
+        &self,
+        location: &BlobLocation,
+        map: Arc<Mmap>,
+    ) -> Result<Option<(u8, Vec<u8>, usize)>> {
+        let start = location.offset as usize;
+        let mut bytes = map.get(start..).unwrap_or_default();
+        match decode_record(&mut bytes) {
+            Err(crate::Error::Io(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                let Some(map) = self.segment_map(location.shard, true)? else {
+                    return Ok(None);
+                };
+                let mut bytes = map.get(start..).unwrap_or_default();
+                decode_record(&mut bytes).map(Some)
+            }
+            record => record.map(Some),
         }
+    }
 
-        // Decompress if needed (v0.5.0)
-        if is_compressed {
-            match lz4::block::decompress(&value, Some(orig_len as i32)) {
-                Ok(decompressed) => Ok(Some(decompressed)),
-                Err(_) => Err(crate::Error::Corrupted("LZ4 decompression failed".into())),
+    /// Memory map of `segment`, taken again if `refresh`
+    fn segment_map(&self, segment: u64, refresh: bool) -> Result<Option<Arc<Mmap>>> {
+        if !refresh {
+            if let Some(map) = self.segment_maps.read().unwrap().get(&segment) {
+                return Ok(Some(map.clone()));
             }
-        } else {
-            Ok(Some(value))
         }
+        let Some(file) = self.disks.open_segment(segment)? else {
+            return Ok(None);
+        };
+        // SAFETY: segment files are only appended to while the store is
+        // open (torn tails are cut by `open`, before any read), and
+        // compaction drops the maps when it swaps the segments directory, so a
+        // mapped range is never truncated under a reader.
+        let map = Arc::new(unsafe { Mmap::map(&file)? });
+        self.segment_maps
+            .write()
+            .unwrap()
+            .insert(segment, map.clone());
+        Ok(Some(map))
     }
 
-    fn rebuild_index_from_segments(
-        index: &mut Index,
-        bloom: &mut Bloom<[u8; 32]>,
-        data_path: &Path,
-    ) -> Result<()> {
-        for entry in fs::read_dir(data_path)? {
-            let entry = entry?;
-            if !entry.path().is_dir() {
-                continue;
+    /// Rebuild the index from every segment. Returns the number of segments
+    /// scanned.
+    fn rebuild_index_from_segments(index: &mut Index, disks: &DiskSet) -> Result<usize> {
+        let segments = disks.segment_files();
+        tracing::info!("Rebuilding the index from {} segments", segments.len());
+        Self::scan_segments(&segments, |key, location| match location {
+            Some(location) => {
+                index.insert(key, location);
             }
+            None => {
+                index.remove(&key);
+            }
+        })?;
+        Ok(segments.len())
+    }
 
-            for subentry in fs::read_dir(entry.path())? {
-                let subentry = subentry?;
-                if !subentry.path().is_dir() {
-                    continue;
+    /// Scan `segments` (`(segment, path)` in write order) as `scan_segment`
+    /// does, on up to `MAX_SCAN_WORKERS` threads, visiting their records on
+    /// this thread in segment order. A segment scanned ahead of an earlier
+    /// one waits in memory until that one is visited.
+    fn scan_segments(
+        segments: &[(u64, PathBuf)],
+        mut visit: impl FnMut(String, Option<BlobLocation>),
+    ) -> Result<()> {
+        let workers = std::thread::available_parallelism()
+            .map_or(1, |n| n.get())
+            .min(MAX_SCAN_WORKERS)
+            .min(segments.len());
+        let next = AtomicUsize::new(0);
+        let (tx, rx) = mpsc::channel();
+        std::thread::scope(|scope| {
+            for _ in 0..workers {
+                let (tx, next) = (tx.clone(), &next);
+                scope.spawn(move || loop {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    let Some((segment, path)) = segments.get(i) else {
+                        break;
+                    };
+                    let mut records = Vec::new();
+                    let scanned = Self::scan_segment(path, *segment, |key, location| {
+                        records.push((key, location))
+                    });
+                    // The scan was given up on
+                    if tx.send((i, scanned.map(|()| records))).is_err() {
+                        break;
+                    }
+                });
+            }
+            drop(tx);
+
+            let mut pending = BTreeMap::new();
+            let mut visited = 0;
+            let mut reported = Instant::now();
+            for (i, scanned) in rx {
+                pending.insert(i, scanned);
+                while let Some(scanned) = pending.remove(&visited) {
+                    for (key, location) in scanned? {
+                        visit(key, location);
+                    }
+                    visited += 1;
+                }
+                if reported.elapsed() >= Duration::from_secs(SCAN_PROGRESS_SECS) {
+                    tracing::info!("Scanned {} of {} segments", visited, segments.len());
+                    reported = Instant::now();
                 }
+            }
+            Ok(())
+        })
+    }
+
+    /// Finish or roll back a compaction interrupted by a crash, then move
+    /// the segments of the legacy layout into `segments/` of the primary
+    /// data directory, the first of `data_paths`
+    fn recover_layout(data_paths: &[PathBuf]) -> Result<()> {
+        let data_path = &data_paths[0];
+        let segments_dir = data_path.join(SEGMENTS_DIR);
+        let backup_path = data_path.join("compact_backup");
+        // The other directories are set aside before the primary is swapped,
+        // and cleaned up before it
+        let swapped = backup_path.exists() && segments_dir.exists();
+        for dir in &data_paths[1..] {
+            let backup = dir.join("compact_backup");
+            if !backup.exists() {
+                continue;
+            }
+            if swapped {
+                fs::remove_dir_all(&backup)?;
+            } else {
+                fs::rename(&backup, dir.join(SEGMENTS_DIR))?;
+            }
+        }
+        if backup_path.exists() {
+            if segments_dir.exists() {
+                // Swapped in, the old segments just weren't removed yet
+                fs::remove_dir_all(&backup_path)?;
+            } else {
+                fs::rename(&backup_path, &segments_dir)?;
+            }
+        }
+        let temp_path = data_path.join("compact_temp");
+        if temp_path.exists() {
+            fs::remove_dir_all(&temp_path)?;
+        }
 
-                for file_entry in fs::read_dir(subentry.path())? {
-                    let file_entry = file_entry?;
-                    let path = file_entry.path();
-                    if path.extension().and_then(|s| s.to_str()) == Some("blob") {
-                        Self::scan_segment(index, bloom, &path)?;
+        let mut moved = 0;
+        for outer in legacy_dirs(data_path)? {
+            for inner in legacy_dirs(&outer)? {
+                for entry in fs::read_dir(&inner)? {
+                    let path = entry?.path();
+                    let Some(segment) = segment_number(&path) else {
+                        continue;
+                    };
+                    let target = segment_path(data_path, segment);
+                    if target.exists() {
+                        return Err(crate::Error::Corrupted(format!(
+                            "segment {} is in both {} and {}",
+                            segment,
+                            path.display(),
+                            target.display()
+                        )));
                     }
+                    fs::create_dir_all(&segments_dir)?;
+                    fs::rename(&path, &target)?;
+                    moved += 1;
                 }
+                // Left alone if anything else was in there
+                let _ = fs::remove_dir(&inner);
             }
+            let _ = fs::remove_dir(&outer);
+        }
+        if moved > 0 {
+            tracing::info!(
+                "Moved {} segments of the legacy layout to {}",
+                moved,
+                segments_dir.display()
+            );
         }
         Ok(())
     }
 
-    fn scan_segment(index: &mut Index, bloom: &mut Bloom<[u8; 32]>, path: &Path) -> Result<()> {
+    /// Visit the records of a segment in order as `(key, location)`, with no
+    /// location for tombstones. A record
+    /// torn by a crash at the end of the file is cut off, so that writes
+    /// resume right after the last complete one.
+    fn scan_segment(
+        path: &Path,
+        segment: u64,
+        mut visit: impl FnMut(String, Option<BlobLocation>),
+    ) -> Result<()> {
         let file = File::open(path)?;
+        let file_len = file.metadata()?.len();
         let mut reader = BufReader::new(file);
         let mut offset = 0u64;
-        let segment = path
-            .file_stem()
-            .and_then(|s| s.to_str())
-            .and_then(|s| s.strip_prefix("seg_"))
-            .and_then(|s| s.parse::<u64>().ok())
-            .unwrap_or(0);
-
-        loop {
-            let mut magic = [0u8; 4];
-            match reader.read_exact(&mut magic) {
-                Ok(_) => {}
-                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
-                Err(e) => return Err(e.into()),
-            }
 
-            // Support both compressed and uncompressed magic (v0.5.0)
-            let is_compressed = magic == BLOB_MAGIC_COMPRESSED;
-            if magic != BLOB_MAGIC && !is_compressed {
+        let mut torn = false;
+        while offset < file_len {
+            let record = match Self::read_record_header(&mut reader) {
+                Ok(Some(record)) => record,
+                Ok(None) => break,
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                    torn = true;
+                    break;
+                }
+                Err(e) => return Err(e.into()),
+            };
+            let (flag, key, record_len, val_len, orig_len) = record;
+            if offset + record_len > file_len {
+                torn = true;
                 break;
             }
 
-            let mut key_len_bytes = [0u8; 4];
-            reader.read_exact(&mut key_len_bytes)?;
-            let key_len = u32::from_le_bytes(key_len_bytes) as usize;
+            let location = (flag != FLAG_TOMBSTONE).then(|| BlobLocation {
+                shard: segment,
+                offset,
+                size: orig_len, // Use original size, not compressed size
+                blake3: blake3_hash(key.as_bytes()),
+                expires_at: None, // Legacy entries don't have TTL
+                compressed_size: (flag & !FLAG_ENCRYPTED != FLAG_NONE).then_some(val_len),
+            });
+            visit(key, location);
+            offset += record_len;
+        }
+
+        if torn {
+            tracing::warn!(
+                "Cutting off {} bytes of torn record at the end of {}",
+                file_len - offset,
+                path.display()
+            );
+            OpenOptions::new().write(true).open(path)?.set_len(offset)?;
+        }
+        Ok(())
+    }
 
-            let mut val_len_bytes = [0u8; 8];
-            reader.read_exact(&mut val_len_bytes)?;
-            let val_len = u64::from_le_bytes(val_len_bytes) as usize;
+    /// Read the header of the record at the reader's position and skip past
+    /// its value and checksum. Returns `(flag, key, record length, stored
+    /// value length, original length)`, or `None` if no record starts there.
+    fn read_record_header(
+        reader: &mut BufReader<File>,
+    ) -> std::io::Result<Option<(u8, String, u64, u64, u64)>> {
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        let flag = match read_flag(&magic, reader) {
+            Ok(Some(flag)) => flag,
+            Ok(None) => return Ok(None),
+            Err(crate::Error::Io(e)) => return Err(e),
+            Err(e) => return Err(std::io::Error::other(e.to_string())),
+        };
+        let header_len = if magic == BLOB_MAGIC_FLAGGED { 5 } else { 4 };
 
-            // Read original size (v0.5.0)
-            let mut orig_len_bytes = [0u8; 8];
-            reader.read_exact(&mut orig_len_bytes)?;
-            let orig_len = u64::from_le_bytes(orig_len_bytes);
+        let mut key_len_bytes = [0u8; 4];
+        reader.read_exact(&mut key_len_bytes)?;
+        let key_len = u32::from_le_bytes(key_len_bytes) as u64;
 
-            let mut key_bytes = vec![0u8; key_len];
-            reader.read_exact(&mut key_bytes)?;
-            let key = String::from_utf8_lossy(&key_bytes).to_string();
+        let mut val_len_bytes = [0u8; 8];
+        reader.read_exact(&mut val_len_bytes)?;
+        let val_len = u64::from_le_bytes(val_len_bytes);
 
-            reader.seek(SeekFrom::Current(val_len as i64))?;
-            let mut checksum_bytes = [0u8; 4];
-            reader.read_exact(&mut checksum_bytes)?;
+        // Read original size (v0.5.0)
+        let mut orig_len_bytes = [0u8; 8];
+        reader.read_exact(&mut orig_len_bytes)?;
+        let orig_len = u64::from_le_bytes(orig_len_bytes);
 
-            let hash = blake3_hash(key.as_bytes());
-            let hash_vec: Vec<u8> = hex::decode(&hash).unwrap_or_else(|_| vec![0u8; 32]);
-            let hash_bytes: [u8; 32] = hash_vec.try_into().unwrap_or([0u8; 32]);
-            bloom.set(&hash_bytes);
+        let mut key_bytes = vec![0u8; key_len as usize];
+        reader.read_exact(&mut key_bytes)?;
+        let key = String::from_utf8_lossy(&key_bytes).to_string();
 
-            index.insert(
-                key,
-                BlobLocation {
-                    shard: segment,
-                    offset,
-                    size: orig_len, // Use original size, not compressed size
-                    blake3: hash,
-                    expires_at: None, // Legacy entries don't have TTL
-                },
-            );
+        reader.seek_relative(val_len as i64 + 4)?;
 
-            // Updated offset calculation: MAGIC(4) + KEY_LEN(4) + VAL_LEN(8) + ORIG_LEN(8) + KEY + VALUE + CHECKSUM(4)
-            offset += 4 + 4 + 8 + 8 + key_len as u64 + val_len as u64 + 4;
-        }
-        Ok(())
+        // MAGIC(4) [+ FLAG(1)] + KEY_LEN(4) + VAL_LEN(8) + ORIG_LEN(8) + KEY + VALUE + CHECKSUM(4)
+        let record_len = header_len + 4 + 8 + 8 + key_len + val_len + 4;
+        Ok(Some((flag, key, record_len, val_len, orig_len)))
     }
 
-    fn find_current_position(data_path: &Path) -> Result<(u64, u64)> {
+    fn find_current_position(disks: &DiskSet) -> Result<(u64, u64)> {
         let mut max_segment = 0u64;
         let mut max_offset = 0u64;
 
-        if !data_path.exists() {
-            return Ok((0, 0));
+        for (segment, path) in disks.segment_files() {
+            let size = fs::metadata(&path)?.len();
+            if segment > max_segment || (segment == max_segment && size > max_offset) {
+                max_segment = segment;
+                max_offset = size;
+            }
         }
 
-        for entry in fs::read_dir(data_path)? {
-            let entry = entry?;
-            if !entry.path().is_dir() {
-                continue;
-            }
+        Ok((max_segment, max_offset))
+    }
+}
 
-            for subentry in fs::read_dir(entry.path())? {
-                let subentry = subentry?;
-                if !subentry.path().is_dir() {
-                    continue;
-                }
+/// Bloom filter bits of `key`
+/// Error of a compaction killed by fault injection, which leaves what it
+/// copied behind as a crash would
+fn killed() -> crate::Error {
+    crate::Error::CompactFailed("killed by fault injection".into())
+}
 
-                for file_entry in fs::read_dir(subentry.path())? {
-                    let file_entry = file_entry?;
-                    let path = file_entry.path();
-
-                    if path.extension().and_then(|s| s.to_str()) == Some("blob") {
-                        let segment = path
-                            .file_stem()
-                            .and_then(|s| s.to_str())
-                            .and_then(|s| s.strip_prefix("seg_"))
-                            .and_then(|s| s.parse::<u64>().ok())
-                            .unwrap_or(0);
-                        let metadata = fs::metadata(&path)?;
-                        let size = metadata.len();
-
-                        if segment > max_segment || (segment == max_segment && size > max_offset) {
-                            max_segment = segment;
-                            max_offset = size;
-                        }
-                    }
-                }
+fn bloom_key(key: &str) -> [u8; 32] {
+    *blake3::hash(key.as_bytes()).as_bytes()
+}
+
+/// The bloom filter saved at `path`, if it was saved with the snapshot
+/// hashing to `snapshot_hash`
+fn load_bloom(path: &Path, snapshot_hash: &[u8; 32]) -> Option<KeyFilter> {
+    let bytes = fs::read(path).ok()?;
+    let rest = bytes.strip_prefix(&BLOOM_MAGIC)?;
+    let (hash, rest) = rest.split_at_checked(32)?;
+    if hash != snapshot_hash {
+        tracing::debug!("Bloom filter was saved with another snapshot, rebuilding it");
+        return None;
+    }
+    let (capacity, filter) = rest.split_at_checked(8)?;
+    Some(KeyFilter {
+        bloom: Bloom::from_bytes(filter.to_vec()).ok()?,
+        capacity: u64::from_le_bytes(capacity.try_into().ok()?) as usize,
+    })
+}
+
+/// Path of `segment` under the data directory `base_path`
+pub(crate) fn segment_path(base_path: &Path, segment: u64) -> PathBuf {
+    base_path
+        .join(SEGMENTS_DIR)
+        .join(format!("seg_{:06}.blob", segment))
+}
+
+/// Number of the segment file at `path`, if it is one
+pub(crate) fn segment_number(path: &Path) -> Option<u64> {
+    if path.extension().and_then(|s| s.to_str()) != Some("blob") {
+        return None;
+    }
+    path.file_stem()
+        .and_then(|s| s.to_str())
+        .and_then(|s| s.strip_prefix("seg_"))
+        .and_then(|s| s.parse().ok())
+}
+
+/// Two-digit directories under `path`, where the legacy layout kept
+/// segments (`NN/NN/seg_NNNN.blob`)
+fn legacy_dirs(path: &Path) -> Result<Vec<PathBuf>> {
+    let mut dirs = Vec::new();
+    for entry in fs::read_dir(path)? {
+        let path = entry?.path();
+        let legacy = path
+            .file_name()
+            .and_then(|s| s.to_str())
+            .is_some_and(|name| name.len() == 2 && name.bytes().all(|b| b.is_ascii_digit()));
+        if legacy && path.is_dir() {
+            dirs.push(path);
+        }
+    }
+    Ok(dirs)
+}
+
+/// A record of a segment file as read by `check_segment`
+#[derive(Debug, Clone)]
+pub(crate) struct CheckedRecord {
+    pub offset: u64,
+    /// Bytes the record takes in the segment
+    pub len: u64,
+    /// Key, with invalid UTF-8 replaced
+    pub key: String,
+    pub tombstone: bool,
+    /// Whether the record matches its CRC32
+    pub crc_ok: bool,
+}
+
+/// The records of a segment file, in order
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SegmentCheck {
+    pub records: Vec<CheckedRecord>,
+    /// Where the bytes that don't make a whole record start, and why, if
+    /// the file doesn't end with its last record
+    pub tail: Option<(u64, String)>,
+}
+
+/// Read every record of the segment file at `path`, checking each against
+/// its CRC32 `COPY_CHUNK_SIZE` bytes of the value at a time. Unlike
+/// `scan_segment`, a torn record is reported rather than cut off.
+pub(crate) fn check_segment(path: &Path) -> Result<SegmentCheck> {
+    let file = File::open(path)?;
+    let file_len = file.metadata()?.len();
+    let mut reader = BufReader::new(file);
+    let mut check = SegmentCheck::default();
+    let mut offset = 0u64;
+    while offset < file_len {
+        match check_record(&mut reader, offset, file_len - offset) {
+            Ok(Some(record)) => {
+                offset += record.len;
+                check.records.push(record);
+            }
+            Ok(None) => {
+                check.tail = Some((offset, "no record starts here".to_string()));
+                break;
+            }
+            Err(e) => {
+                check.tail = Some((offset, e.to_string()));
+                break;
             }
         }
+    }
+    Ok(check)
+}
 
-        Ok((max_segment, max_offset))
+/// Read and check the record at `offset`, where the reader is positioned,
+/// with `available` bytes left in the file. `None` if no record starts there.
+fn check_record(
+    reader: &mut impl Read,
+    offset: u64,
+    available: u64,
+) -> Result<Option<CheckedRecord>> {
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+    let Some(flag) = read_flag(&magic, reader)? else {
+        return Ok(None);
+    };
+    // KEY_LEN(4) + VAL_LEN(8) + ORIG_LEN(8)
+    let mut lengths = [0u8; 20];
+    reader.read_exact(&mut lengths)?;
+    let key_len = u32::from_le_bytes(lengths[..4].try_into().unwrap()) as u64;
+    let val_len = u64::from_le_bytes(lengths[4..12].try_into().unwrap());
+    let header_len = if magic == BLOB_MAGIC_FLAGGED { 5 } else { 4 };
+    let len = header_len + lengths.len() as u64 + key_len + val_len + 4;
+    if len > available {
+        return Err(crate::Error::Corrupted(format!(
+            "record of {} bytes runs past the end of the file",
+            len
+        )));
+    }
+
+    let mut key = vec![0u8; key_len as usize];
+    reader.read_exact(&mut key)?;
+    let mut checksum = crc32fast::Hasher::new();
+    if magic == BLOB_MAGIC_FLAGGED {
+        checksum.update(&[flag]);
+    }
+    checksum.update(&lengths);
+    checksum.update(&key);
+    let mut chunk = vec![0u8; (val_len as usize).min(COPY_CHUNK_SIZE)];
+    let mut left = val_len;
+    while left > 0 {
+        let piece = &mut chunk[..left.min(COPY_CHUNK_SIZE as u64) as usize];
+        reader.read_exact(piece)?;
+        checksum.update(piece);
+        left -= piece.len() as u64;
+    }
+    let mut stored = [0u8; 4];
+    reader.read_exact(&mut stored)?;
+
+    Ok(Some(CheckedRecord {
+        offset,
+        len,
+        key: String::from_utf8_lossy(&key).into_owned(),
+        tombstone: flag == FLAG_TOMBSTONE,
+        crc_ok: u32::from_le_bytes(stored) == checksum.finalize(),
+    }))
+}
+
+/// Decode and verify the record `reader` is positioned at
+fn decode_record(reader: &mut impl Read) -> Result<(u8, Vec<u8>, usize)> {
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+    let Some(flag) = read_flag(&magic, reader)? else {
+        return Err(crate::Error::Corrupted("Invalid blob magic".into()));
+    };
+
+    let mut key_len_bytes = [0u8; 4];
+    reader.read_exact(&mut key_len_bytes)?;
+    let key_len = u32::from_le_bytes(key_len_bytes) as usize;
+
+    let mut val_len_bytes = [0u8; 8];
+    reader.read_exact(&mut val_len_bytes)?;
+    let val_len = u64::from_le_bytes(val_len_bytes) as usize;
+
+    // Read original size (v0.5.0)
+    let mut orig_len_bytes = [0u8; 8];
+    reader.read_exact(&mut orig_len_bytes)?;
+    let orig_len = u64::from_le_bytes(orig_len_bytes) as usize;
+
+    let mut key_bytes = vec![0u8; key_len];
+    reader.read_exact(&mut key_bytes)?;
+    let mut value = vec![0u8; val_len];
+    reader.read_exact(&mut value)?;
+
+    let mut checksum_bytes = [0u8; 4];
+    reader.read_exact(&mut checksum_bytes)?;
+    let stored_checksum = u32::from_le_bytes(checksum_bytes);
+
+    let mut checksum_data = Vec::new();
+    if magic == BLOB_MAGIC_FLAGGED {
+        checksum_data.push(flag);
+    }
+    checksum_data.extend_from_slice(&key_len_bytes);
+    checksum_data.extend_from_slice(&val_len_bytes);
+    checksum_data.extend_from_slice(&orig_len_bytes);
+    checksum_data.extend_from_slice(&key_bytes);
+    checksum_data.extend_from_slice(&value);
+    let computed_checksum = crc32(&checksum_data);
+
+    if computed_checksum != stored_checksum {
+        return Err(crate::Error::ChecksumMismatch {
+            expected: format!("{:08x}", stored_checksum),
+            actual: format!("{:08x}", computed_checksum),
+        });
+    }
+
+    Ok((flag, value, orig_len))
+}
+
+/// Whether `error` reading a record means the record itself is damaged
+fn is_corruption(error: &crate::Error) -> bool {
+    match error {
+        crate::Error::Corrupted(_) | crate::Error::ChecksumMismatch { .. } => true,
+        crate::Error::Io(e) => e.kind() == std::io::ErrorKind::UnexpectedEof,
+        _ => false,
+    }
+}
+
+/// Compression flag of the record starting with `magic`, reading the flag byte
+/// of flagged records. `None` if `magic` doesn't start a record.
+fn read_flag(magic: &[u8; 4], reader: &mut impl Read) -> Result<Option<u8>> {
+    match *magic {
+        BLOB_MAGIC_FLAGGED => {
+            let mut flag = [0u8; 1];
+            reader.read_exact(&mut flag)?;
+            Ok(Some(flag[0]))
+        }
+        BLOB_MAGIC => Ok(Some(FLAG_NONE)),
+        BLOB_MAGIC_COMPRESSED => Ok(Some(FLAG_LZ4)),
+        _ => Ok(None),
+    }
+}
+
+/// `(flag, stored bytes)` for `value`. Values under the threshold, or that
+/// compression doesn't shrink, are stored as is.
+fn compress(config: &CompressionConfig, value: &[u8]) -> (u8, Vec<u8>) {
+    if value.len() < config.min_size {
+        return (FLAG_NONE, value.to_vec());
+    }
+    let compressed = match config.algorithm {
+        CompressionMode::None => None,
+        CompressionMode::Lz4 => lz4::block::compress(value, None, true)
+            .ok()
+            .map(|c| (FLAG_LZ4, c)),
+        CompressionMode::Zstd => zstd::bulk::compress(value, config.zstd_level)
+            .ok()
+            .map(|c| (FLAG_ZSTD, c)),
+    };
+    match compressed {
+        Some((flag, compressed)) if compressed.len() < value.len() => (flag, compressed),
+        _ => (FLAG_NONE, value.to_vec()),
+    }
+}
+
+/// Original bytes of a value stored with `flag`
+fn decompress(flag: u8, value: Vec<u8>, orig_len: usize) -> Result<Vec<u8>> {
+    let decompressed = match flag {
+        FLAG_NONE => return Ok(value),
+        // The original size is prepended to LZ4 blocks
+        FLAG_LZ4 => lz4::block::decompress(&value, None)
+            .map_err(|_| crate::Error::Corrupted("LZ4 decompression failed".into()))?,
+        FLAG_ZSTD => zstd::bulk::decompress(&value, orig_len)
+            .map_err(|_| crate::Error::Corrupted("zstd decompression failed".into()))?,
+        other => {
+            return Err(crate::Error::Corrupted(format!(
+                "Unknown compression flag {}",
+                other
+            )))
+        }
+    };
+    if decompressed.len() != orig_len {
+        return Err(crate::Error::Corrupted("Decompressed size mismatch".into()));
     }
+    Ok(decompressed)
 }
@@ -0,0 +1,352 @@
+//! LRU cache of recently read blobs
+//!
+//! Hot keys are served from memory instead of their segment. The cache is
+//! bounded by the total size of the values it holds (`capacity_bytes`), and
+//! split into independently locked shards like the index, each evicting its
+//! least recently read entries.
+//!
+//! An entry remembers the record it was read from and only answers lookups
+//! for that same location, so an overwritten or deleted key can't be served
+//! from the cache even if the entry is still there. Compaction moves records,
+//! so the store clears the cache when it swaps the segments directory.
+//!
+//! Cached bytes are accounted per tenant, the first segment of the key
+//! (`.cas/<tenant>/…` for deduplicated content, `.chunks/<tenant>/…` for
+//! chunks of large values).
+
+use crate::common::{BlobCacheConfig, METRICS};
+use crate::volume::index::BlobLocation;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{Hash, Hasher};
+use std::sync::Mutex;
+
+/// Number of shards of a `BlobCache`
+const CACHE_SHARDS: usize = 16;
+
+/// Byte-bounded LRU cache of blob values, keyed by blob key
+pub struct BlobCache {
+    shards: Vec<Mutex<CacheShard>>,
+    max_entry_bytes: u64,
+    /// Cached bytes per tenant
+    tenants: Mutex<HashMap<String, u64>>,
+}
+
+struct CacheEntry {
+    shard: u64,
+    offset: u64,
+    value: Vec<u8>,
+    /// Last read, ordering `CacheShard::lru`
+    tick: u64,
+}
+
+impl CacheEntry {
+    fn matches(&self, location: &BlobLocation) -> bool {
+        self.shard == location.shard && self.offset == location.offset
+    }
+}
+
+#[derive(Default)]
+struct CacheShard {
+    entries: HashMap<String, CacheEntry>,
+    /// Keys from least to most recently read
+    lru: BTreeMap<u64, String>,
+    tick: u64,
+    bytes: u64,
+    capacity: u64,
+}
+
+impl CacheShard {
+    fn touch(&mut self, key: &str) {
+        self.tick += 1;
+        let tick = self.tick;
+        if let Some(entry) = self.entries.get_mut(key) {
+            let key = self
+                .lru
+                .remove(&entry.tick)
+                .unwrap_or_else(|| key.to_string());
+            entry.tick = tick;
+            self.lru.insert(tick, key);
+        }
+    }
+
+    /// Remove `key`, returning the bytes it held
+    fn remove(&mut self, key: &str) -> u64 {
+        match self.entries.remove(key) {
+            Some(entry) => {
+                self.lru.remove(&entry.tick);
+                let size = entry_size(key, &entry.value);
+                self.bytes -= size;
+                size
+            }
+            None => 0,
+        }
+    }
+}
+
+fn entry_size(key: &str, value: &[u8]) -> u64 {
+    (key.len() + value.len()) as u64
+}
+
+/// Tenant owning `key`
+fn tenant_of(key: &str) -> &str {
+    let key = key
+        .strip_prefix(".cas/")
+        .or_else(|| key.strip_prefix(".chunks/"))
+        .unwrap_or(key);
+    key.split('/').next().unwrap_or(key)
+}
+
+impl BlobCache {
+    pub fn new(config: BlobCacheConfig) -> Self {
+        let capacity = config.capacity_bytes / CACHE_SHARDS as u64;
+        Self {
+            shards: (0..CACHE_SHARDS)
+                .map(|_| {
+                    Mutex::new(CacheShard {
+                        capacity,
+                        ..Default::default()
+                    })
+                })
+                .collect(),
+            max_entry_bytes: config.max_entry_bytes.min(capacity),
+            tenants: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Whether the cache can hold anything
+    pub fn is_enabled(&self) -> bool {
+        self.max_entry_bytes > 0
+    }
+
+    fn shard_of(&self, key: &str) -> &Mutex<CacheShard> {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        &self.shards[hasher.finish() as usize % CACHE_SHARDS]
+    }
+
+    /// Cached value of `key`, if it was read from `location`
+    pub fn get(&self, key: &str, location: &BlobLocation) -> Option<Vec<u8>> {
+        if !self.is_enabled() {
+            return None;
+        }
+        let value = {
+            let mut shard = self.shard_of(key).lock().unwrap();
+            let value = match shard.entries.get(key) {
+                Some(entry) if entry.matches(location) => Some(entry.value.clone()),
+                _ => None,
+            };
+            if value.is_some() {
+                shard.touch(key);
+            }
+            value
+        };
+        match value {
+            Some(_) => METRICS.blob_cache_hits.inc(),
+            None => METRICS.blob_cache_misses.inc(),
+        }
+        value
+    }
+
+    /// Cache `value`, just read from `location`, evicting the least recently
+    /// read entries of its shard to make room
+    pub fn insert(&self, key: &str, location: &BlobLocation, value: &[u8]) {
+        let size = entry_size(key, value);
+        if !self.is_enabled() || size > self.max_entry_bytes {
+            return;
+        }
+        let mut freed: Vec<(String, u64)> = Vec::new();
+        {
+            let mut shard = self.shard_of(key).lock().unwrap();
+            let replaced = shard.remove(key);
+            if replaced > 0 {
+                freed.push((key.to_string(), replaced));
+            }
+            while shard.bytes + size > shard.capacity {
+                let Some((_, oldest)) = shard.lru.pop_first() else {
+                    break;
+                };
+                if let Some(entry) = shard.entries.remove(&oldest) {
+                    let evicted = entry_size(&oldest, &entry.value);
+                    shard.bytes -= evicted;
+                    freed.push((oldest, evicted));
+                }
+            }
+            shard.tick += 1;
+            let tick = shard.tick;
+            shard.entries.insert(
+                key.to_string(),
+                CacheEntry {
+                    shard: location.shard,
+                    offset: location.offset,
+                    value: value.to_vec(),
+                    tick,
+                },
+            );
+            shard.lru.insert(tick, key.to_string());
+            shard.bytes += size;
+        }
+        self.account(&freed, Some((key, size)));
+    }
+
+    /// Drop the entry of `key`
+    pub fn remove(&self, key: &str) {
+        let freed = self.shard_of(key).lock().unwrap().remove(key);
+        if freed > 0 {
+            self.account(&[(key.to_string(), freed)], None);
+        }
+    }
+
+    /// Drop every entry
+    pub fn clear(&self) {
+        for shard in &self.shards {
+            let mut shard = shard.lock().unwrap();
+            shard.entries.clear();
+            shard.lru.clear();
+            shard.bytes = 0;
+        }
+        let mut tenants = self.tenants.lock().unwrap();
+        for (tenant, _) in tenants.drain() {
+            METRICS.set_blob_cache_tenant_bytes(&tenant, 0);
+        }
+        METRICS.blob_cache_bytes.set(0);
+    }
+
+    /// Total bytes cached
+    pub fn bytes(&self) -> u64 {
+        self.shards.iter().map(|s| s.lock().unwrap().bytes).sum()
+    }
+
+    /// Bytes cached for `tenant`
+    pub fn tenant_bytes(&self, tenant: &str) -> u64 {
+        self.tenants
+            .lock()
+            .unwrap()
+            .get(tenant)
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// Update the tenant totals and metrics after freeing and adding entries
+    fn account(&self, freed: &[(String, u64)], added: Option<(&str, u64)>) {
+        let mut tenants = self.tenants.lock().unwrap();
+        let mut changed = Vec::new();
+        for (key, size) in freed {
+            let tenant = tenant_of(key);
+            if let Some(bytes) = tenants.get_mut(tenant) {
+                *bytes = bytes.saturating_sub(*size);
+                changed.push(tenant);
+            }
+        }
+        if let Some((key, size)) = added {
+            let tenant = tenant_of(key);
+            *tenants.entry(tenant.to_string()).or_insert(0) += size;
+            changed.push(tenant);
+        }
+        for tenant in changed {
+            let bytes = tenants.get(tenant).copied().unwrap_or(0);
+            if bytes == 0 {
+                tenants.remove(tenant);
+            }
+            METRICS.set_blob_cache_tenant_bytes(tenant, bytes);
+        }
+        METRICS.blob_cache_bytes.set(tenants.values().sum::<u64>());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn location(shard: u64, offset: u64) -> BlobLocation {
+        BlobLocation {
+            shard,
+            offset,
+            size: 0,
+            blake3: String::new(),
+            expires_at: None,
+            compressed_size: None,
+        }
+    }
+
+    fn cache(capacity_bytes: u64) -> BlobCache {
+        BlobCache::new(BlobCacheConfig {
+            capacity_bytes,
+            max_entry_bytes: capacity_bytes,
+        })
+    }
+
+    #[test]
+    fn test_cache_hits_only_same_location() {
+        let cache = cache(16 * 1024);
+        cache.insert("acme/a", &location(0, 10), b"one");
+
+        assert_eq!(cache.get("acme/a", &location(0, 10)).unwrap(), b"one");
+        // Overwritten since: the entry no longer answers
+        assert!(cache.get("acme/a", &location(1, 0)).is_none());
+        assert!(cache.get("acme/b", &location(0, 10)).is_none());
+
+        cache.remove("acme/a");
+        assert!(cache.get("acme/a", &location(0, 10)).is_none());
+        assert_eq!(cache.bytes(), 0);
+    }
+
+    #[test]
+    fn test_cache_evicts_least_recently_read() {
+        // 64 bytes per shard
+        let cache = cache(64 * CACHE_SHARDS as u64);
+        let keys: Vec<String> = (0..200).map(|i| format!("t/{:03}", i)).collect();
+        for (i, key) in keys.iter().enumerate() {
+            cache.insert(key, &location(0, i as u64), &[0u8; 11]);
+            // Keep the first key hot
+            cache.get(&keys[0], &location(0, 0));
+        }
+
+        assert!(cache.bytes() <= 64 * CACHE_SHARDS as u64);
+        assert!(cache.get(&keys[0], &location(0, 0)).is_some());
+        let cached = (1..200)
+            .filter(|&i| cache.get(&keys[i], &location(0, i as u64)).is_some())
+            .count();
+        assert!(cached < 199);
+        // The latest insert is always there
+        assert!(cache.get(&keys[199], &location(0, 199)).is_some());
+    }
+
+    #[test]
+    fn test_cache_tenant_accounting() {
+        let cache = cache(16 * 1024);
+        cache.insert("acme/a", &location(0, 0), &[1u8; 100]);
+        cache.insert("acme/b", &location(0, 1), &[1u8; 50]);
+        cache.insert(".cas/acme/abcd", &location(0, 2), &[1u8; 10]);
+        cache.insert("other/a", &location(0, 3), &[1u8; 20]);
+        cache.insert(".chunks/other/u1/000000", &location(0, 4), &[1u8; 7]);
+
+        assert_eq!(cache.tenant_bytes("acme"), 106 + 56 + 24);
+        assert_eq!(cache.tenant_bytes("other"), 27 + 30);
+        assert_eq!(cache.bytes(), 106 + 56 + 24 + 27 + 30);
+
+        // Replacing an entry doesn't count it twice
+        cache.insert("acme/a", &location(1, 0), &[1u8; 10]);
+        assert_eq!(cache.tenant_bytes("acme"), 16 + 56 + 24);
+
+        cache.clear();
+        assert_eq!(cache.tenant_bytes("acme"), 0);
+        assert_eq!(cache.bytes(), 0);
+    }
+
+    #[test]
+    fn test_cache_disabled_and_oversized() {
+        let disabled = cache(0);
+        disabled.insert("a", &location(0, 0), b"value");
+        assert!(disabled.get("a", &location(0, 0)).is_none());
+
+        let small = BlobCache::new(BlobCacheConfig {
+            capacity_bytes: 16 * 1024,
+            max_entry_bytes: 8,
+        });
+        small.insert("a", &location(0, 0), b"way too large");
+        assert!(small.get("a", &location(0, 0)).is_none());
+        small.insert("a", &location(0, 0), b"ok");
+        assert!(small.get("a", &location(0, 0)).is_some());
+    }
+}
@@ -0,0 +1,179 @@
+//! Execution of coordinator commands on a volume
+//!
+//! Commands arrive in heartbeat responses (see [`crate::common::command`]).
+//! Compaction and snapshots run on the blocking pool since they do their
+//! disk I/O in one go. A rekey is only started: it runs in
+//! the background, one batch at a time, and logs its progress.
+
+use crate::common::utils::generate_upload_id;
+use crate::common::{blake3_hash, shard_key, Result, VolumeCommand};
+use crate::coordinator::volume_client::VolumeClient;
+use crate::volume::blob::BlobStore;
+use crate::volume::rekey;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Runs commands against a volume's store
+#[derive(Clone)]
+pub struct CommandExecutor {
+    store: Arc<BlobStore>,
+    /// Set by `drain`; the gRPC service refuses new writes while it is set
+    draining: Arc<AtomicBool>,
+}
+
+impl CommandExecutor {
+    pub fn new(store: Arc<BlobStore>, draining: Arc<AtomicBool>) -> Self {
+        Self { store, draining }
+    }
+
+    /// Whether the volume was told to drain
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::SeqCst)
+    }
+
+    pub async fn execute(&self, command: &VolumeCommand) -> Result<()> {
+        match command {
+            VolumeCommand::Compact => {
+                let store = self.store.clone();
+                tokio::task::spawn_blocking(move || store.compact())
+                    .await
+                    .map_err(|e| crate::Error::Internal(e.to_string()))?
+            }
+            VolumeCommand::CompactSegment(segment) => {
+                let (store, segment) = (self.store.clone(), *segment);
+                let reclaimed = tokio::task::spawn_blocking(move || store.compact_segment(segment))
+                    .await
+                    .map_err(|e| crate::Error::Internal(e.to_string()))??;
+                tracing::info!(
+                    "Compacted segment {}, reclaiming {} bytes",
+                    segment,
+                    reclaimed
+                );
+                Ok(())
+            }
+            VolumeCommand::Snapshot => {
+                let store = self.store.clone();
+                tokio::task::spawn_blocking(move || store.save_snapshot())
+                    .await
+                    .map_err(|e| crate::Error::Internal(e.to_string()))?
+            }
+            VolumeCommand::MigrateShard {
+                shard,
+                num_shards,
+                target,
+            } => {
+                let copied = self.migrate_shard(*shard, *num_shards, target).await?;
+                tracing::info!("Copied {} keys of shard {} to {}", copied, shard, target);
+                Ok(())
+            }
+            VolumeCommand::Drain => {
+                self.draining.store(true, Ordering::SeqCst);
+                Ok(())
+            }
+            VolumeCommand::Rekey => {
+                if rekey::is_running() {
+                    return Err(crate::Error::Internal("a rekey is already running".into()));
+                }
+                let store = self.store.clone();
+                tokio::task::spawn_blocking(move || {
+                    let result = rekey::rekey_store(&store, rekey::DEFAULT_BATCH_SIZE, |p| {
+                        tracing::info!(
+                            "Rekey to key version {}: {} keys scanned, {} re-encrypted",
+                            p.key_version,
+                            p.scanned,
+                            p.rewritten
+                        )
+                    });
+                    if let Err(e) = result {
+                        tracing::error!("Rekey failed: {}", e);
+                    }
+                });
+                Ok(())
+            }
+        }
+    }
+
+    /// Copy every local key of a shard to `target`. Returns the number of keys copied.
+    async fn migrate_shard(&self, shard: u64, num_shards: u64, target: &str) -> Result<usize> {
+        let keys: Vec<String> = self
+            .store
+            .key_hashes()
+            .into_iter()
+            .map(|(key, _)| key)
+            .filter(|key| shard_key(key, num_shards) == shard)
+            .collect();
+
+        let mut client = VolumeClient::connect(target.to_string()).await?;
+        let mut copied = 0;
+        for key in keys {
+            // Deleted since the listing
+            let Some(data) = self.store.get(&key)? else {
+                continue;
+            };
+            let upload_id = generate_upload_id();
+            let size = data.len() as u64;
+            let resp = client
+                .prepare(
+                    key.clone(),
+                    upload_id.clone(),
+                    size,
+                    blake3_hash(&data),
+                    data,
+                )
+                .await?;
+            if !resp.ok {
+                return Err(crate::Error::PrepareFailed {
+                    node: target.to_string(),
+                    reason: resp.error,
+                });
+            }
+            let resp = client.commit(upload_id, key).await?;
+            if !resp.ok {
+                return Err(crate::Error::CommitFailed {
+                    node: target.to_string(),
+                    reason: resp.error,
+                });
+            }
+            copied += 1;
+        }
+        Ok(copied)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::WalSyncPolicy;
+    use tempfile::tempdir;
+
+    #[tokio::test]
+    async fn test_execute_local_commands() {
+        let dir = tempdir().unwrap();
+        let mut store = BlobStore::open(
+            &dir.path().join("data"),
+            &dir.path().join("wal"),
+            WalSyncPolicy::Never,
+        )
+        .unwrap();
+        store.set_segment_limits(64, 0);
+        let store = Arc::new(store);
+        store.put("a", b"one").unwrap();
+        store.put("a", b"two").unwrap();
+        // Moves on to segment 1
+        store.put("b", b"three").unwrap();
+
+        let executor = CommandExecutor::new(store.clone(), Arc::new(AtomicBool::new(false)));
+        executor.execute(&VolumeCommand::Snapshot).await.unwrap();
+        executor
+            .execute(&VolumeCommand::CompactSegment(0))
+            .await
+            .unwrap();
+        assert_eq!(store.get("a").unwrap().unwrap(), b"two");
+        executor.execute(&VolumeCommand::Compact).await.unwrap();
+        assert_eq!(store.get("b").unwrap().unwrap(), b"three");
+
+        assert!(!executor.is_draining());
+        executor.execute(&VolumeCommand::Drain).await.unwrap();
+        assert!(executor.is_draining());
+    }
+}
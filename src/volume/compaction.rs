@@ -1,7 +1,482 @@
-use crate::common::Result;
-use crate::volume::blob::BlobStore;
-use std::sync::MutexGuard;
+//! Background compaction of a volume
+//!
+//! `Compactor` checks the store every `compaction_interval_secs` and compacts
+//! the segments that are at least `compaction_dead_ratio` garbage, most
+//! garbage first and at most `compaction_threshold` per pass, one at a time
+//! (see `BlobStore::compact_segment`). Writes only wait for the segment being
+//! compacted, and the disk only needs room for its live records. The copy is
+//! paced to `compaction_max_bytes_per_sec`.
+//!
+//! Pausing keeps new compactions from starting and cuts a running one short,
+//! leaving the segment it was copying in use. The policy can be changed
+//! while running (`set_policy`, on a configuration reload). Progress and
+//! reclaimed bytes are served at `/admin/compaction` on the volume and
+//! exported as metrics.
 
-pub fn compact_store(store: &mut MutexGuard<'_, BlobStore>) -> Result<()> {
+use crate::common::{timestamp_now, Result, VolumeConfig, METRICS};
+use crate::volume::blob::{BlobStore, SpaceUsage};
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+
+pub fn compact_store(store: &BlobStore) -> Result<()> {
     store.compact()
 }
+
+/// When and how fast to compact (from `VolumeConfig`)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CompactionPolicy {
+    /// Between checks of the store (0 = never compact in the background)
+    pub interval_secs: u64,
+    /// Share of dead bytes that gets a segment compacted
+    pub dead_ratio: f64,
+    /// Most segments compacted per pass (0 = no limit)
+    pub max_segments: usize,
+    /// Pace of the copy (0 = unthrottled)
+    pub max_bytes_per_sec: u64,
+}
+
+impl CompactionPolicy {
+    pub fn from_config(config: &VolumeConfig) -> Self {
+        Self {
+            interval_secs: config.compaction_interval_secs,
+            dead_ratio: config.compaction_dead_ratio,
+            max_segments: config.compaction_threshold,
+            max_bytes_per_sec: config.compaction_max_bytes_per_sec,
+        }
+    }
+
+    /// Whether a segment using `usage` is due for compaction
+    pub fn is_due(&self, usage: &SpaceUsage) -> bool {
+        usage.dead_bytes() > 0 && usage.dead_ratio() >= self.dead_ratio
+    }
+}
+
+/// State of background compaction
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CompactionProgress {
+    pub paused: bool,
+    pub running: bool,
+    /// Segments the running (or last) pass compacts, and has compacted
+    pub segments_total: usize,
+    pub segments_compacted: usize,
+    /// Live bytes of those segments, and bytes copied so far
+    pub bytes_total: u64,
+    pub bytes_copied: u64,
+    /// Dead share of all segments at the last check
+    pub dead_ratio: f64,
+    /// Segments compacted in all
+    pub runs: u64,
+    /// Bytes reclaimed by the last pass, and by all of them
+    pub last_reclaimed_bytes: u64,
+    pub reclaimed_bytes: u64,
+    /// When the last pass ended (Unix milliseconds)
+    pub last_finished_at: Option<u64>,
+    /// Why the last pass failed, if it did
+    pub last_error: Option<String>,
+}
+
+/// Compacts a store in the background when it is due
+pub struct Compactor {
+    store: Arc<BlobStore>,
+    policy: watch::Sender<CompactionPolicy>,
+    paused: AtomicBool,
+    progress: Mutex<CompactionProgress>,
+}
+
+impl Compactor {
+    pub fn new(store: Arc<BlobStore>, policy: CompactionPolicy) -> Self {
+        Self {
+            store,
+            policy: watch::channel(policy).0,
+            paused: AtomicBool::new(false),
+            progress: Mutex::new(CompactionProgress::default()),
+        }
+    }
+
+    /// Stop compacting, cutting a running compaction short
+    pub fn pause(&self) {
+        self.paused.store(true, Ordering::SeqCst);
+    }
+
+    pub fn resume(&self) {
+        self.paused.store(false, Ordering::SeqCst);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    pub fn policy(&self) -> CompactionPolicy {
+        *self.policy.borrow()
+    }
+
+    /// Compact by `policy` from the next pass on. A new interval starts
+    /// from now.
+    pub fn set_policy(&self, policy: CompactionPolicy) {
+        self.policy.send_replace(policy);
+    }
+
+    pub fn progress(&self) -> CompactionProgress {
+        CompactionProgress {
+            paused: self.is_paused(),
+            ..self.progress.lock().unwrap().clone()
+        }
+    }
+
+    /// Compact the segments that are due, unless compaction is paused.
+    /// Returns the number of segments compacted. Blocks for the whole pass.
+    pub fn run_once(&self) -> Result<usize> {
+        if self.is_paused() {
+            return Ok(0);
+        }
+        let policy = self.policy();
+        let usage = self.store.segment_usage()?;
+        let dead_ratio = usage
+            .values()
+            .fold(SpaceUsage::default(), |total, u| SpaceUsage {
+                segment_bytes: total.segment_bytes + u.segment_bytes,
+                live_bytes: total.live_bytes + u.live_bytes,
+            })
+            .dead_ratio();
+        let active = self.store.active_segment();
+        let mut due: Vec<(u64, SpaceUsage)> = usage
+            .into_iter()
+            .filter(|(segment, usage)| *segment != active && policy.is_due(usage))
+            .collect();
+        due.sort_by_key(|(_, usage)| std::cmp::Reverse(usage.dead_bytes()));
+        if policy.max_segments > 0 {
+            due.truncate(policy.max_segments);
+        }
+        {
+            let mut progress = self.progress.lock().unwrap();
+            progress.dead_ratio = dead_ratio;
+            if due.is_empty() {
+                return Ok(0);
+            }
+            progress.running = true;
+            progress.segments_total = due.len();
+            progress.segments_compacted = 0;
+            progress.bytes_total = due.iter().map(|(_, usage)| usage.live_bytes).sum();
+            progress.bytes_copied = 0;
+        }
+
+        let started = Instant::now();
+        let mut copied = 0u64;
+        let mut reclaimed = 0u64;
+        let mut result = Ok(0);
+        for (segment, _) in due {
+            match self.compact_segment(segment, started, &mut copied) {
+                Ok(Some(bytes)) => {
+                    reclaimed += bytes;
+                    let mut progress = self.progress.lock().unwrap();
+                    progress.segments_compacted += 1;
+                    progress.runs += 1;
+                    result = Ok(progress.segments_compacted);
+                }
+                Ok(None) => {
+                    tracing::info!("Compaction paused during segment {}", segment);
+                    break;
+                }
+                Err(e) => {
+                    tracing::error!("Compaction of segment {} failed: {}", segment, e);
+                    result = Err(e);
+                    break;
+                }
+            }
+        }
+
+        let mut progress = self.progress.lock().unwrap();
+        progress.running = false;
+        progress.last_finished_at = Some(timestamp_now());
+        progress.last_reclaimed_bytes = reclaimed;
+        progress.reclaimed_bytes += reclaimed;
+        progress.last_error = result.as_ref().err().map(|e| e.to_string());
+        METRICS.compactions.add(progress.segments_compacted as u64);
+        METRICS.compaction_reclaimed_bytes.add(reclaimed);
+        if progress.segments_compacted > 0 {
+            tracing::info!(
+                "Compacted {} segments, copying {} bytes and reclaiming {}",
+                progress.segments_compacted,
+                copied,
+                reclaimed
+            );
+        }
+        result
+    }
+
+    /// Compact `segment` at the policy's pace since `started`, stopping if
+    /// paused. `copied` counts the bytes copied over the pass.
+    fn compact_segment(
+        &self,
+        segment: u64,
+        started: Instant,
+        copied: &mut u64,
+    ) -> Result<Option<u64>> {
+        self.store.compact_segment_with(segment, |bytes| {
+            *copied += bytes;
+            self.progress.lock().unwrap().bytes_copied = *copied;
+            if self.is_paused() {
+                return false;
+            }
+            let max_bytes_per_sec = self.policy().max_bytes_per_sec;
+            if max_bytes_per_sec > 0 {
+                let due = Duration::from_secs_f64(*copied as f64 / max_bytes_per_sec as f64);
+                if let Some(ahead) = due.checked_sub(started.elapsed()) {
+                    std::thread::sleep(ahead);
+                }
+            }
+            true
+        })
+    }
+}
+
+/// Check the store every `interval_secs` of the policy, compacting it when
+/// due. Waits for a policy change while the interval is 0.
+pub fn start_compaction_task(compactor: Arc<Compactor>) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut policy = compactor.policy.subscribe();
+        loop {
+            let interval_secs = policy.borrow_and_update().interval_secs;
+            // The first check waits too: leave the volume time to start
+            let wait = async {
+                match interval_secs {
+                    0 => std::future::pending().await,
+                    secs => tokio::time::sleep(Duration::from_secs(secs)).await,
+                }
+            };
+            tokio::select! {
+                _ = wait => {}
+                changed = policy.changed() => {
+                    if changed.is_err() {
+                        return;
+                    }
+                    continue;
+                }
+            }
+            let compactor = compactor.clone();
+            if let Err(e) = tokio::task::spawn_blocking(move || compactor.run_once()).await {
+                tracing::error!("Compaction task panicked: {}", e);
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::WalSyncPolicy;
+    use tempfile::tempdir;
+
+    fn policy(dead_ratio: f64) -> CompactionPolicy {
+        CompactionPolicy {
+            interval_secs: 0,
+            dead_ratio,
+            max_segments: 0,
+            max_bytes_per_sec: 0,
+        }
+    }
+
+    /// A store moving on to a new segment past 100 bytes
+    fn store(dir: &tempfile::TempDir) -> Arc<BlobStore> {
+        let mut store = BlobStore::open(
+            &dir.path().join("data"),
+            &dir.path().join("wal"),
+            WalSyncPolicy::Never,
+        )
+        .unwrap();
+        store.set_segment_limits(100, 0);
+        Arc::new(store)
+    }
+
+    #[test]
+    fn test_policy_is_due() {
+        let usage = |segment_bytes, live_bytes| SpaceUsage {
+            segment_bytes,
+            live_bytes,
+        };
+        let policy = policy(0.5);
+        assert!(!policy.is_due(&usage(0, 0)));
+        assert!(!policy.is_due(&usage(100, 100)));
+        assert!(!policy.is_due(&usage(100, 60)));
+        assert!(policy.is_due(&usage(100, 50)));
+        assert!(policy.is_due(&usage(100, 0)));
+    }
+
+    #[test]
+    fn test_compacts_due_segments() {
+        let dir = tempdir().unwrap();
+        let store = store(&dir);
+        store.put("kept", &[1u8; 97]).unwrap();
+        store.put("overwritten", &[2u8; 90]).unwrap();
+        store.put("deleted", &[3u8; 94]).unwrap();
+
+        let compactor = Compactor::new(store.clone(), policy(0.3));
+        assert_eq!(compactor.run_once().unwrap(), 0);
+
+        store.put("overwritten", &[4u8; 90]).unwrap();
+        store.delete("deleted").unwrap();
+        let before = store.space_usage().unwrap();
+        // Segments 1 and 2 are dead; the active one, holding the tombstone,
+        // is left alone
+        assert_eq!(compactor.run_once().unwrap(), 2);
+
+        let progress = compactor.progress();
+        assert_eq!(progress.segments_compacted, 2);
+        assert!(!progress.running);
+        assert_eq!(progress.bytes_copied, progress.bytes_total);
+        let after = store.space_usage().unwrap();
+        assert_eq!(
+            progress.reclaimed_bytes,
+            before.segment_bytes - after.segment_bytes
+        );
+        assert!(store.segment_usage().unwrap().get(&1).is_none());
+        assert_eq!(store.get("kept").unwrap().unwrap(), [1u8; 97]);
+        assert_eq!(store.get("overwritten").unwrap().unwrap(), [4u8; 90]);
+        assert!(store.get("deleted").unwrap().is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_task_follows_policy() {
+        let dir = tempdir().unwrap();
+        let store = store(&dir);
+        store.put("overwritten", &[1u8; 97]).unwrap();
+        store.put("kept", &[2u8; 97]).unwrap();
+        store.put("overwritten", &[3u8; 97]).unwrap();
+
+        let compactor = Arc::new(Compactor::new(store.clone(), policy(0.3)));
+        let _task = start_compaction_task(compactor.clone());
+        tokio::time::sleep(Duration::from_secs(3600)).await;
+        assert_eq!(compactor.progress().runs, 0);
+
+        compactor.set_policy(CompactionPolicy {
+            interval_secs: 10,
+            ..policy(0.3)
+        });
+        tokio::time::sleep(Duration::from_secs(11)).await;
+        let started = std::time::Instant::now();
+        while compactor.progress().runs == 0 && started.elapsed() < Duration::from_secs(5) {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(compactor.progress().runs, 1);
+        assert_eq!(store.get("overwritten").unwrap().unwrap(), [3u8; 97]);
+    }
+
+    #[test]
+    fn test_segment_compaction_survives_restart() {
+        let dir = tempdir().unwrap();
+        {
+            let store = store(&dir);
+            store.put("a", &[1u8; 100]).unwrap();
+            store.put("b", &[2u8; 100]).unwrap();
+            store.put("a", &[3u8; 100]).unwrap();
+            store.delete("b").unwrap();
+            store.put("c", &[4u8; 100]).unwrap();
+            store
+                .put_with_ttl("ttl", &[5u8; 100], Some(3_600_000))
+                .unwrap();
+            store.put("d", &[6u8; 100]).unwrap();
+            // Segment 3 holds "c" and the tombstone of "b", whose record is
+            // still in segment 1: both are moved over
+            assert_eq!(store.compact_segment(3).unwrap(), 0);
+            // The first "a" is dead
+            assert_eq!(store.compact_segment(0).unwrap(), 130);
+            assert_eq!(store.compact_segment(4).unwrap(), 0);
+            assert!(store.get_ttl("ttl").is_some());
+            assert!(store.compact_segment(store.active_segment()).is_err());
+            assert!(store.compact_segment(0).is_err());
+        }
+
+        // Without the WAL, the segments alone must still hide "b"
+        std::fs::remove_dir_all(dir.path().join("wal")).unwrap();
+        std::fs::remove_file(dir.path().join("data/index.snap")).unwrap();
+        let store = store(&dir);
+        assert_eq!(store.get("a").unwrap().unwrap(), [3u8; 100]);
+        assert!(store.get("b").unwrap().is_none());
+        assert_eq!(store.get("c").unwrap().unwrap(), [4u8; 100]);
+    }
+
+    #[test]
+    fn test_paused_compaction_keeps_segment() {
+        let dir = tempdir().unwrap();
+        let store = store(&dir);
+        for i in 0..4 {
+            store.put(&format!("k{}", i), &[i as u8; 100]).unwrap();
+        }
+        store.put("k0", &[9u8; 100]).unwrap();
+        store.put("last", &[9u8; 100]).unwrap();
+
+        let compactor = Compactor::new(store.clone(), policy(0.0));
+        compactor.pause();
+        assert_eq!(compactor.run_once().unwrap(), 0);
+        assert!(compactor.progress().paused);
+
+        // Stopped mid-copy: the segment stays
+        assert!(store.compact_segment_with(1, |_| false).unwrap().is_none());
+        assert!(store.segment_usage().unwrap().contains_key(&1));
+        assert_eq!(store.get("k1").unwrap().unwrap(), [1u8; 100]);
+
+        compactor.resume();
+        assert!(compactor.run_once().unwrap() > 0);
+        assert_eq!(store.get("k1").unwrap().unwrap(), [1u8; 100]);
+        assert_eq!(store.get("k0").unwrap().unwrap(), [9u8; 100]);
+    }
+
+    #[test]
+    fn test_compaction_copies_large_values() {
+        let dir = tempdir().unwrap();
+        {
+            let store = store(&dir);
+            // Several copy chunks long
+            let big: Vec<u8> = (0..2_500_000u32).map(|i| (i % 251) as u8).collect();
+            store.put_with_ttl("big", &big, Some(3_600_000)).unwrap();
+            store.put("small", b"value").unwrap();
+
+            assert_eq!(store.compact_segment(0).unwrap(), 0);
+            assert_eq!(store.get("big").unwrap().unwrap(), big);
+            store.compact().unwrap();
+            assert!(store.get_ttl("big").is_some());
+        }
+
+        let store = store(&dir);
+        let big = store.get("big").unwrap().unwrap();
+        assert_eq!(big.len(), 2_500_000);
+        assert!(big.iter().enumerate().all(|(i, &b)| b == (i % 251) as u8));
+        assert!(store.get_ttl("big").is_some());
+        assert_eq!(store.get("small").unwrap().unwrap(), b"value");
+    }
+
+    #[test]
+    fn test_compaction_checks_records() {
+        let dir = tempdir().unwrap();
+        let store = store(&dir);
+        store.put("a", &[1u8; 200]).unwrap();
+        store.put("b", &[2u8; 10]).unwrap();
+
+        let segments = dir.path().join("data/segments");
+        let mut bytes = std::fs::read(segments.join("seg_000000.blob")).unwrap();
+        bytes[50] ^= 0xff;
+        std::fs::write(segments.join("seg_000000.blob"), bytes).unwrap();
+        let active = std::fs::metadata(segments.join("seg_000001.blob"))
+            .unwrap()
+            .len();
+
+        assert!(matches!(
+            store.compact_segment(0),
+            Err(crate::Error::ChecksumMismatch { .. })
+        ));
+        // The partial copy is cut off the active segment
+        let after = std::fs::metadata(segments.join("seg_000001.blob"))
+            .unwrap()
+            .len();
+        assert_eq!(after, active);
+        assert_eq!(store.get("b").unwrap().unwrap(), [2u8; 10]);
+
+        // A full compaction drops the record instead
+        store.compact().unwrap();
+        assert!(store.get("a").unwrap().is_none());
+        assert_eq!(store.get("b").unwrap().unwrap(), [2u8; 10]);
+    }
+}
@@ -0,0 +1,358 @@
+//! Data directories of a volume
+//!
+//! A volume may keep its segments on several disks (JBOD), one data
+//! directory each, listed in `VolumeConfig::data_path`. The first directory
+//! is the primary: it also holds the index snapshot, the bloom filter and
+//! the quarantine log. Each segment lives whole on one disk, found by
+//! listing every directory on open; a new segment goes to the disk with
+//! the most free space, the one holding the fewest segments among disks
+//! about as free.
+//!
+//! A directory that can't be listed on open, or a segment that fails to
+//! open with an I/O error or whose directory is gone, marks its disk failed. New segments avoid it, and reads of its
+//! segments fail with `Error::DiskUnavailable` while the rest of the volume
+//! carries on. The segments of a disk that failed before open aren't known,
+//! so then any segment found on no other disk is taken to be on it. A
+//! failed disk is used again once the volume restarts.
+
+use crate::common::{disk_free_bytes, Result};
+use crate::volume::blob::{segment_number, segment_path, SEGMENTS_DIR};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::RwLock;
+
+/// Free space below which two disks count as equally free when placing a
+/// segment
+const FREE_SPACE_GRANULARITY: u64 = 1024 * 1024 * 1024;
+
+/// State of one data directory
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DiskStats {
+    pub path: PathBuf,
+    pub failed: bool,
+    /// Segments on the disk, and their size
+    pub segments: usize,
+    pub segment_bytes: u64,
+    /// Free space on the disk, if it can be measured
+    pub free_bytes: Option<u64>,
+}
+
+/// The data directories of a volume, and the disk of each segment
+pub struct DiskSet {
+    dirs: Vec<PathBuf>,
+    failed: Vec<AtomicBool>,
+    /// Disk holding each segment, by index in `dirs`
+    segments: RwLock<BTreeMap<u64, usize>>,
+}
+
+impl DiskSet {
+    /// Open the data directories `dirs`, the first being the primary. Fails
+    /// if the primary can't be listed; another directory that can't is
+    /// marked failed.
+    pub fn open(dirs: &[PathBuf]) -> Result<Self> {
+        if dirs.is_empty() {
+            return Err(crate::Error::InvalidConfig(
+                "a volume needs at least one data directory".into(),
+            ));
+        }
+        let failed: Vec<AtomicBool> = dirs.iter().map(|_| AtomicBool::new(false)).collect();
+        let mut segments = BTreeMap::new();
+        for (disk, dir) in dirs.iter().enumerate() {
+            let found = match list_segments(dir) {
+                Ok(found) => found,
+                Err(e) if disk == 0 => return Err(e),
+                Err(e) => {
+                    tracing::error!("Data directory {} failed: {}", dir.display(), e);
+                    failed[disk].store(true, Ordering::SeqCst);
+                    continue;
+                }
+            };
+            for segment in found {
+                if let Some(other) = segments.insert(segment, disk) {
+                    return Err(crate::Error::Corrupted(format!(
+                        "segment {} is in both {} and {}",
+                        segment,
+                        dirs[other].display(),
+                        dir.display()
+                    )));
+                }
+            }
+        }
+        Ok(Self {
+            dirs: dirs.to_vec(),
+            failed,
+            segments: RwLock::new(segments),
+        })
+    }
+
+    /// Directory holding the index and the other files of the volume
+    pub fn primary(&self) -> &Path {
+        &self.dirs[0]
+    }
+
+    pub fn dirs(&self) -> &[PathBuf] {
+        &self.dirs
+    }
+
+    fn is_failed(&self, disk: usize) -> bool {
+        self.failed[disk].load(Ordering::SeqCst)
+    }
+
+    /// Whether any disk failed
+    pub fn has_failed(&self) -> bool {
+        (0..self.dirs.len()).any(|disk| self.is_failed(disk))
+    }
+
+    /// Path of `segment`, `None` if there is no such segment. Fails if it
+    /// is, or may be, on a failed disk.
+    pub fn locate(&self, segment: u64) -> Result<Option<PathBuf>> {
+        match self.segments.read().unwrap().get(&segment) {
+            Some(&disk) if self.is_failed(disk) => Err(crate::Error::DiskUnavailable(format!(
+                "segment {} is on failed disk {}",
+                segment,
+                self.dirs[disk].display()
+            ))),
+            Some(&disk) => Ok(Some(segment_path(&self.dirs[disk], segment))),
+            None if self.has_failed() => Err(crate::Error::DiskUnavailable(format!(
+                "segment {} may be on a failed disk",
+                segment
+            ))),
+            None => Ok(None),
+        }
+    }
+
+    /// Open `segment` for reading, `None` if there is no such segment
+    pub fn open_segment(&self, segment: u64) -> Result<Option<File>> {
+        let Some(path) = self.locate(segment)? else {
+            return Ok(None);
+        };
+        match File::open(&path) {
+            Ok(file) => Ok(Some(file)),
+            // Removed by compaction since it was located
+            Err(e)
+                if e.kind() == io::ErrorKind::NotFound
+                    && path.parent().is_some_and(Path::exists) =>
+            {
+                Ok(None)
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound || is_disk_failure(&e) => {
+                self.fail_segment(segment, &e);
+                Err(crate::Error::DiskUnavailable(format!(
+                    "segment {}: {}",
+                    segment, e
+                )))
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Data directory to write `segment` to, placing it if it is new
+    pub fn dir_for_write(&self, segment: u64) -> Result<PathBuf> {
+        if let Some(&disk) = self.segments.read().unwrap().get(&segment) {
+            if self.is_failed(disk) {
+                return Err(crate::Error::DiskUnavailable(format!(
+                    "segment {} is on failed disk {}",
+                    segment,
+                    self.dirs[disk].display()
+                )));
+            }
+            return Ok(self.dirs[disk].clone());
+        }
+        self.place(segment, 0)?;
+        self.dir_for_write(segment)
+    }
+
+    /// Put new `segment` on the available disk with the most free space,
+    /// with room for at least `min_free` bytes. Fails with
+    /// `Error::StorageFull` if no disk has.
+    pub fn place(&self, segment: u64, min_free: u64) -> Result<()> {
+        let mut segments = self.segments.write().unwrap();
+        let mut counts = vec![0usize; self.dirs.len()];
+        for &disk in segments.values() {
+            counts[disk] += 1;
+        }
+        let mut best: Option<(usize, u64)> = None;
+        let mut most_free = 0;
+        for (disk, dir) in self.dirs.iter().enumerate() {
+            if self.is_failed(disk) {
+                continue;
+            }
+            // Unmeasurable disks are taken to have room
+            let free = disk_free_bytes(dir).unwrap_or(u64::MAX);
+            most_free = most_free.max(free);
+            if free < min_free {
+                continue;
+            }
+            let better = match best {
+                None => true,
+                Some((other, other_free)) => {
+                    let (free, other_free) = (
+                        free / FREE_SPACE_GRANULARITY,
+                        other_free / FREE_SPACE_GRANULARITY,
+                    );
+                    free > other_free || (free == other_free && counts[disk] < counts[other])
+                }
+            };
+            if better {
+                best = Some((disk, free));
+            }
+        }
+        match best {
+            Some((disk, _)) => {
+                segments.insert(segment, disk);
+                Ok(())
+            }
+            None if (0..self.dirs.len()).all(|disk| self.is_failed(disk)) => Err(
+                crate::Error::DiskUnavailable("every data directory failed".into()),
+            ),
+            None => Err(crate::Error::StorageFull(format!(
+                "{} bytes free on the freest disk, a segment takes {}",
+                most_free, min_free
+            ))),
+        }
+    }
+
+    /// Forget `segment`, removed
+    pub fn forget(&self, segment: u64) {
+        self.segments.write().unwrap().remove(&segment);
+    }
+
+    /// Put every segment back on the primary, after a full compaction
+    /// rewrote them all there
+    pub fn reset_to_primary(&self, segments: impl IntoIterator<Item = u64>) {
+        *self.segments.write().unwrap() = segments.into_iter().map(|s| (s, 0)).collect();
+    }
+
+    /// Mark the disk of `segment` failed after `error` opening it
+    fn fail_segment(&self, segment: u64, error: &io::Error) {
+        let Some(&disk) = self.segments.read().unwrap().get(&segment) else {
+            return;
+        };
+        if !self.failed[disk].swap(true, Ordering::SeqCst) {
+            tracing::error!(
+                "Data directory {} failed reading segment {}: {}",
+                self.dirs[disk].display(),
+                segment,
+                error
+            );
+        }
+    }
+
+    /// Segments on the available disks as `(segment, path)`, in write order
+    pub fn segment_files(&self) -> Vec<(u64, PathBuf)> {
+        self.segments
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(_, &disk)| !self.is_failed(disk))
+            .map(|(&segment, &disk)| (segment, segment_path(&self.dirs[disk], segment)))
+            .filter(|(_, path)| path.exists())
+            .collect()
+    }
+
+    /// Free space of the available disks, if it can be measured on any
+    pub fn free_bytes(&self) -> Option<u64> {
+        self.dirs
+            .iter()
+            .enumerate()
+            .filter(|(disk, _)| !self.is_failed(*disk))
+            .filter_map(|(_, dir)| disk_free_bytes(dir))
+            .reduce(|total, free| total + free)
+    }
+
+    pub fn stats(&self) -> Vec<DiskStats> {
+        let segments = self.segments.read().unwrap();
+        self.dirs
+            .iter()
+            .enumerate()
+            .map(|(disk, dir)| {
+                let failed = self.is_failed(disk);
+                let on_disk: Vec<u64> = segments
+                    .iter()
+                    .filter(|(_, &d)| d == disk)
+                    .map(|(&segment, _)| segment)
+                    .collect();
+                let segment_bytes = if failed {
+                    0
+                } else {
+                    on_disk
+                        .iter()
+                        .filter_map(|&s| fs::metadata(segment_path(dir, s)).ok())
+                        .map(|m| m.len())
+                        .sum()
+                };
+                DiskStats {
+                    path: dir.clone(),
+                    failed,
+                    segments: on_disk.len(),
+                    segment_bytes,
+                    free_bytes: if failed { None } else { disk_free_bytes(dir) },
+                }
+            })
+            .collect()
+    }
+}
+
+/// Whether `error` means the disk itself is failing, rather than the file
+fn is_disk_failure(error: &io::Error) -> bool {
+    matches!(
+        error.raw_os_error(),
+        Some(libc::EIO | libc::ENXIO | libc::ENODEV)
+    )
+}
+
+/// Segments in the data directory `dir`, creating its segments directory
+fn list_segments(dir: &Path) -> Result<Vec<u64>> {
+    let segments_dir = dir.join(SEGMENTS_DIR);
+    fs::create_dir_all(&segments_dir)?;
+    let mut segments = Vec::new();
+    for entry in fs::read_dir(&segments_dir)? {
+        if let Some(segment) = segment_number(&entry?.path()) {
+            segments.push(segment);
+        }
+    }
+    Ok(segments)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_place_spreads_segments() {
+        let dir = tempdir().unwrap();
+        let dirs = vec![dir.path().join("a"), dir.path().join("b")];
+        let disks = DiskSet::open(&dirs).unwrap();
+
+        for segment in 0..4 {
+            disks.place(segment, 0).unwrap();
+        }
+        let stats = disks.stats();
+        // Both on the same filesystem: as free as each other
+        assert_eq!(stats[0].segments, 2);
+        assert_eq!(stats[1].segments, 2);
+        assert!(disks.place(4, u64::MAX - 1).is_err());
+    }
+
+    #[test]
+    fn test_failed_disk() {
+        let dir = tempdir().unwrap();
+        let dirs = vec![dir.path().join("a"), dir.path().join("b")];
+        std::fs::write(&dirs[1], b"not a directory").unwrap();
+        let disks = DiskSet::open(&dirs).unwrap();
+
+        assert!(disks.has_failed());
+        assert!(disks.stats()[1].failed);
+        assert!(matches!(
+            disks.locate(7),
+            Err(crate::Error::DiskUnavailable(_))
+        ));
+        // New segments go to the disk left
+        assert_eq!(disks.dir_for_write(0).unwrap(), dirs[0]);
+    }
+}
@@ -0,0 +1,304 @@
+//! Offline consistency check of a volume (`minikv-volume fsck`)
+//!
+//! `fsck` reads every record of a stopped volume's segments, checking its
+//! framing and CRC32, and cross-checks the records with the index snapshot:
+//! - a *dangling* index entry points where no intact record of its key
+//!   starts: into a missing segment, between records or past their end, at
+//!   another key's record or a tombstone, or at a record failing its CRC32;
+//! - an *unreferenced* record is the latest record of a key, a put, while
+//!   the key isn't indexed. Compaction drops it, but it may also be the
+//!   value of a key the index lost;
+//! - a *torn* segment ends with bytes that don't make a whole record.
+//!
+//! Keys logged in the WAL are left out of the cross-check, as opening the
+//! volume applies them to the index again. Without an index snapshot the
+//! index is rebuilt from the segments on open, so only the records are
+//! checked.
+//!
+//! The report comes with a repair plan, which `fix` carries out: torn tails
+//! are cut off, and dangling entries removed from the index snapshot, so the
+//! volume misses those keys and the coordinator copies them back from other
+//! replicas (`minikv repair`). The bloom filter no longer matches the new
+//! snapshot, so the next open rebuilds it.
+
+use crate::common::Result;
+use crate::volume::blob::{check_segment, CheckedRecord};
+use crate::volume::disks::DiskSet;
+use crate::volume::index::Index;
+use crate::volume::wal::Wal;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::path::{Path, PathBuf};
+
+/// A record of a segment
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RecordRef {
+    pub segment: u64,
+    pub offset: u64,
+    pub key: String,
+}
+
+/// A segment ending with bytes that don't make a whole record
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TornSegment {
+    pub segment: u64,
+    /// End of the last whole record
+    pub offset: u64,
+    /// Bytes past it
+    pub bytes: u64,
+    pub reason: String,
+}
+
+/// An index entry pointing where no intact record of its key starts
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DanglingEntry {
+    pub key: String,
+    pub segment: u64,
+    pub offset: u64,
+    pub problem: String,
+}
+
+/// A step of the repair plan
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum FsckAction {
+    /// Cut `segment` off at `offset`
+    Truncate { segment: u64, offset: u64 },
+    /// Remove `key` from the index snapshot
+    Unindex { key: String },
+}
+
+/// Outcome of `fsck`
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct FsckReport {
+    /// Segments read, and their records and bytes
+    pub segments: u64,
+    pub records: u64,
+    pub bytes: u64,
+    /// Records failing their CRC32
+    pub corrupted_records: Vec<RecordRef>,
+    pub torn_segments: Vec<TornSegment>,
+    /// Keys of the index snapshot, `None` without one
+    pub indexed_keys: Option<u64>,
+    /// Keys logged in the WAL, left out of the cross-check
+    pub keys_in_wal: u64,
+    pub dangling: Vec<DanglingEntry>,
+    pub unreferenced: Vec<RecordRef>,
+    /// Bytes of the unreferenced records
+    pub unreferenced_bytes: u64,
+    pub plan: Vec<FsckAction>,
+    /// Whether the plan was carried out
+    pub fixed: bool,
+}
+
+impl FsckReport {
+    /// Whether nothing is wrong with the volume
+    pub fn is_clean(&self) -> bool {
+        self.corrupted_records.is_empty()
+            && self.torn_segments.is_empty()
+            && self.dangling.is_empty()
+            && self.unreferenced.is_empty()
+    }
+}
+
+/// Check the stopped volume keeping its segments in `data_paths` (the first
+/// being the primary, holding the index snapshot) and its WAL in `wal_path`,
+/// if given. With `fix`, the repair plan is carried out.
+pub fn fsck(data_paths: &[PathBuf], wal_path: Option<&Path>, fix: bool) -> Result<FsckReport> {
+    let disks = DiskSet::open(data_paths)?;
+    let mut report = FsckReport::default();
+
+    let segment_files: BTreeMap<u64, PathBuf> = disks.segment_files().into_iter().collect();
+    let mut records: HashMap<(u64, u64), CheckedRecord> = HashMap::new();
+    // Latest intact record of each key, in segment order
+    let mut latest: HashMap<String, (u64, u64)> = HashMap::new();
+    for (&segment, path) in &segment_files {
+        let check = check_segment(path)?;
+        report.segments += 1;
+        for record in check.records {
+            report.records += 1;
+            report.bytes += record.len;
+            if record.crc_ok {
+                latest.insert(record.key.clone(), (segment, record.offset));
+            } else {
+                report.corrupted_records.push(RecordRef {
+                    segment,
+                    offset: record.offset,
+                    key: record.key.clone(),
+                });
+            }
+            records.insert((segment, record.offset), record);
+        }
+        if let Some((offset, reason)) = check.tail {
+            report.torn_segments.push(TornSegment {
+                segment,
+                offset,
+                bytes: fs::metadata(path)?.len() - offset,
+                reason,
+            });
+            report.plan.push(FsckAction::Truncate { segment, offset });
+        }
+    }
+
+    let mut logged = HashSet::new();
+    if let Some(wal_path) = wal_path {
+        let wal_file = if wal_path.is_dir() {
+            wal_path.join("wal.log")
+        } else {
+            wal_path.to_path_buf()
+        };
+        if wal_file.exists() {
+            Wal::inspect(&wal_file, |record| {
+                logged.insert(record.key);
+            })?;
+        }
+    }
+    report.keys_in_wal = logged.len() as u64;
+
+    let snapshot_path = disks.primary().join("index.snap");
+    let mut index = if snapshot_path.exists() {
+        Some(Index::load_snapshot(&snapshot_path)?)
+    } else {
+        None
+    };
+    if let Some(index) = &index {
+        report.indexed_keys = Some(index.len() as u64);
+        let mut entries: Vec<_> = index
+            .iter()
+            .filter(|(key, _)| !logged.contains(*key))
+            .collect();
+        entries.sort_by(|a, b| a.0.cmp(b.0));
+        for (key, location) in entries {
+            let record = records.get(&(location.shard, location.offset));
+            let problem = match record {
+                None if !segment_files.contains_key(&location.shard) => "segment missing",
+                None => "no record starts there",
+                Some(record) if &record.key != key => "record of another key",
+                Some(record) if record.tombstone => "tombstone",
+                Some(record) if !record.crc_ok => "record fails its CRC32",
+                Some(_) => continue,
+            };
+            report.dangling.push(DanglingEntry {
+                key: key.clone(),
+                segment: location.shard,
+                offset: location.offset,
+                problem: problem.to_string(),
+            });
+            report.plan.push(FsckAction::Unindex { key: key.clone() });
+        }
+
+        let mut unreferenced: Vec<_> = latest
+            .iter()
+            .filter(|(key, _)| !index.contains(key) && !logged.contains(*key))
+            .filter_map(|(key, position)| {
+                let record = &records[position];
+                (!record.tombstone).then_some((key, position, record.len))
+            })
+            .collect();
+        unreferenced.sort();
+        for (key, &(segment, offset), len) in unreferenced {
+            report.unreferenced_bytes += len;
+            report.unreferenced.push(RecordRef {
+                segment,
+                offset,
+                key: key.clone(),
+            });
+        }
+    }
+
+    if fix && !report.plan.is_empty() {
+        for action in &report.plan {
+            match action {
+                FsckAction::Truncate { segment, offset } => {
+                    let file = OpenOptions::new()
+                        .write(true)
+                        .open(&segment_files[segment])?;
+                    file.set_len(*offset)?;
+                    file.sync_all()?;
+                }
+                FsckAction::Unindex { key } => {
+                    if let Some(index) = &mut index {
+                        index.remove(key);
+                    }
+                }
+            }
+        }
+        if let Some(index) = index.filter(|_| !report.dangling.is_empty()) {
+            let temp_path = disks.primary().join("index.snap.tmp");
+            index.save_snapshot(&temp_path)?;
+            File::open(&temp_path)?.sync_all()?;
+            fs::rename(&temp_path, &snapshot_path)?;
+        }
+        report.fixed = true;
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::WalSyncPolicy;
+    use crate::volume::blob::{segment_path, BlobStore};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_fsck() {
+        let dir = tempdir().unwrap();
+        let (data, wal) = (dir.path().join("data"), dir.path().join("wal"));
+        {
+            let store = BlobStore::open(&data, &wal, WalSyncPolicy::Always).unwrap();
+            store.put("a", b"value a").unwrap();
+            store.put("b", b"value b").unwrap();
+            store.put("c", b"value c").unwrap();
+            // Saves the snapshot and empties the WAL
+            store.compact().unwrap();
+        }
+        let report = fsck(&[data.clone()], Some(&wal), false).unwrap();
+        assert!(report.is_clean(), "{:?}", report);
+        assert_eq!(report.indexed_keys, Some(3));
+        assert_eq!(report.records, 3);
+
+        // Garble the value of `b`, then tear a record at the end
+        let segment = segment_path(&data, 0);
+        let mut bytes = fs::read(&segment).unwrap();
+        let b = bytes.windows(7).position(|w| w == b"value b").unwrap();
+        bytes[b] ^= 0xff;
+        let whole = bytes.len() as u64;
+        let head = bytes[..10].to_vec();
+        bytes.extend_from_slice(&head);
+        fs::write(&segment, &bytes).unwrap();
+
+        let report = fsck(&[data.clone()], Some(&wal), true).unwrap();
+        assert_eq!(report.corrupted_records.len(), 1);
+        assert_eq!(report.corrupted_records[0].key, "b");
+        assert_eq!(report.dangling.len(), 1);
+        assert_eq!(report.dangling[0].key, "b");
+        assert_eq!(report.torn_segments[0].offset, whole);
+        assert_eq!(
+            report.plan,
+            vec![
+                FsckAction::Truncate {
+                    segment: 0,
+                    offset: whole
+                },
+                FsckAction::Unindex {
+                    key: "b".to_string()
+                },
+            ]
+        );
+        assert!(report.fixed);
+        assert_eq!(fs::metadata(&segment).unwrap().len(), whole);
+
+        // Only the garbled record is left, which compaction drops
+        let report = fsck(&[data.clone()], Some(&wal), false).unwrap();
+        assert_eq!(report.indexed_keys, Some(2));
+        assert!(report.dangling.is_empty() && report.torn_segments.is_empty());
+        assert_eq!(report.corrupted_records.len(), 1);
+        assert!(report.plan.is_empty());
+
+        let store = BlobStore::open(&data, &wal, WalSyncPolicy::Always).unwrap();
+        assert_eq!(store.get("a").unwrap().unwrap(), b"value a");
+        assert!(store.get("b").unwrap().is_none());
+    }
+}
@@ -3,35 +3,139 @@
 //! This module exposes the internal gRPC API for volume operations.
 //! Security features (TLS, authentication) and cross-datacenter replication are planned for future releases.
 
+use crate::common::merkle::{bucket_for, MerkleTree};
+use crate::common::{shard_key, StoreIoConfig, WriteBudget, WriteBudgetConfig, WritePermit};
 use crate::proto::volume_internal_server::{VolumeInternal, VolumeInternalServer};
 use crate::proto::*;
+use crate::volume::async_store::AsyncBlobStore;
 use crate::volume::blob::BlobStore;
+use crate::volume::commands::CommandExecutor;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
+use std::time::Instant;
 use tonic::{Request, Response, Status};
 
+/// Chunk size used when streaming blobs over Pull
+const PULL_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// Largest page returned by ListBlobs, and most keys asked of Stat
+const LIST_BLOBS_MAX: usize = 10_000;
+
+/// A write staged by the prepare phase, applied on commit
+struct PreparedWrite {
+    key: String,
+    data: Vec<u8>,
+    /// Share of the write budget, held until the write is applied or dropped
+    _permit: WritePermit,
+}
+
 pub struct VolumeGrpcService {
-    store: Arc<Mutex<BlobStore>>,
+    store: Arc<BlobStore>,
+    /// Store operations of the handlers, off the async runtime
+    io: AsyncBlobStore,
+    /// Uploads that passed the prepare phase, keyed by upload_id
+    prepared: Arc<Mutex<HashMap<String, PreparedWrite>>>,
+    /// Bound on the writes staged at once
+    writes: Arc<WriteBudget>,
+    /// Set once the coordinator tells the volume to drain
+    draining: Arc<AtomicBool>,
+    /// Id reported by Ping
+    volume_id: String,
+    started_at: Instant,
 }
 
 impl VolumeGrpcService {
     pub fn new(store: BlobStore) -> Self {
+        Self::shared(Arc::new(store))
+    }
+
+    /// Serve `store`, which other parts of the volume use too
+    pub fn shared(store: Arc<BlobStore>) -> Self {
         VolumeGrpcService {
-            store: Arc::new(Mutex::new(store)),
+            io: AsyncBlobStore::new(store.clone(), StoreIoConfig::default()),
+            store,
+            prepared: Arc::new(Mutex::new(HashMap::new())),
+            writes: Arc::new(WriteBudget::new(WriteBudgetConfig::default())),
+            draining: Arc::new(AtomicBool::new(false)),
+            volume_id: String::new(),
+            started_at: Instant::now(),
         }
     }
 
+    /// Set the id this volume registered with
+    pub fn with_volume_id(mut self, volume_id: impl Into<String>) -> Self {
+        self.volume_id = volume_id.into();
+        self
+    }
+
+    /// Bound the in-flight store operations and set their deadline
+    pub fn with_io_config(mut self, config: StoreIoConfig) -> Self {
+        self.io = AsyncBlobStore::new(self.store.clone(), config);
+        self
+    }
+
+    /// Bound the writes staged at once, sharing `writes` with the HTTP API
+    pub fn with_write_budget(mut self, writes: Arc<WriteBudget>) -> Self {
+        self.writes = writes;
+        self
+    }
+
+    /// The store served by this service
+    pub fn store(&self) -> Arc<BlobStore> {
+        self.store.clone()
+    }
+
+    /// Executor for coordinator commands, sharing this service's store and drain flag
+    pub fn command_executor(&self) -> CommandExecutor {
+        CommandExecutor::new(self.store.clone(), self.draining.clone())
+    }
+
     pub fn into_server(self) -> VolumeInternalServer<Self> {
         VolumeInternalServer::new(self)
     }
+
+    /// Live `(key, blake3)` pairs grouped by shard
+    async fn entries_by_shard(
+        &self,
+        num_shards: u64,
+    ) -> Result<HashMap<u64, Vec<(String, String)>>, Status> {
+        if num_shards == 0 {
+            return Err(Status::invalid_argument("num_shards must be positive"));
+        }
+        let mut by_shard: HashMap<u64, Vec<(String, String)>> = HashMap::new();
+        let key_hashes = self.io.key_hashes().await.map_err(|e| e.to_grpc_status())?;
+        for (key, hash) in key_hashes {
+            by_shard
+                .entry(shard_key(&key, num_shards))
+                .or_default()
+                .push((key, hash));
+        }
+        Ok(by_shard)
+    }
+}
+
+fn shard_tree(entries: Option<&Vec<(String, String)>>) -> MerkleTree {
+    MerkleTree::build(
+        entries
+            .into_iter()
+            .flatten()
+            .map(|(key, hash)| (key.as_str(), hash.as_str())),
+    )
 }
 
 #[tonic::async_trait]
 impl VolumeInternal for VolumeGrpcService {
+    /// Stage a value streamed in chunks, the first of which names the upload
     async fn prepare(
         &self,
-        req: Request<PrepareRequest>,
+        req: Request<tonic::Streaming<PrepareRequest>>,
     ) -> Result<Response<PrepareResponse>, Status> {
-        let inner = req.into_inner();
+        let mut stream = req.into_inner();
+        let mut inner = stream
+            .message()
+            .await?
+            .ok_or_else(|| Status::invalid_argument("no message"))?;
 
         // Validate request
         if inner.key.is_empty() {
@@ -41,8 +145,64 @@ impl VolumeInternal for VolumeGrpcService {
             }));
         }
 
-        // Check if we have space (simplified check)
-        // In production: check disk space, quotas, etc.
+        if self.draining.load(Ordering::SeqCst) {
+            return Ok(Response::new(PrepareResponse {
+                ok: false,
+                error: "volume is draining".to_string(),
+            }));
+        }
+
+        // A full volume refuses the write as storage full, which the
+        // coordinator takes as a cue to stop placing writes on it
+        self.store
+            .check_writable()
+            .map_err(|e| e.to_grpc_status())?;
+
+        // Refused rather than staged while the budget is spent, so that
+        // prepared writes can't pile up in memory
+        let permit = self
+            .writes
+            .try_acquire(Some(inner.expected_size))
+            .ok_or_else(|| Status::unavailable("too many writes in flight"))?;
+
+        let mut received = inner.data.len() as u64;
+        while let Some(chunk) = stream.message().await? {
+            received += chunk.data.len() as u64;
+            // Past the expected size the write fails anyway: stop buffering
+            if received <= inner.expected_size {
+                inner.data.extend_from_slice(&chunk.data);
+            }
+        }
+
+        if received != inner.expected_size {
+            return Ok(Response::new(PrepareResponse {
+                ok: false,
+                error: format!(
+                    "size mismatch: expected {}, got {}",
+                    inner.expected_size, received
+                ),
+            }));
+        }
+
+        let actual_blake3 = crate::common::blake3_hash(&inner.data);
+        if !inner.expected_blake3.is_empty() && actual_blake3 != inner.expected_blake3 {
+            return Ok(Response::new(PrepareResponse {
+                ok: false,
+                error: format!(
+                    "checksum mismatch: expected {}, got {}",
+                    inner.expected_blake3, actual_blake3
+                ),
+            }));
+        }
+
+        self.prepared.lock().unwrap().insert(
+            inner.upload_id,
+            PreparedWrite {
+                key: inner.key,
+                data: inner.data,
+                _permit: permit,
+            },
+        );
 
         Ok(Response::new(PrepareResponse {
             ok: true,
@@ -54,30 +214,89 @@ impl VolumeInternal for VolumeGrpcService {
         &self,
         req: Request<CommitRequest>,
     ) -> Result<Response<CommitResponse>, Status> {
-        let _inner = req.into_inner();
-
-        // CI trigger: commit for relaunch
+        let inner = req.into_inner();
 
-        // For now, we just acknowledge
-        // In production: finalize the transaction, make data durable
+        // Not staged: never prepared, aborted, already committed, or lost
+        // in a restart. Only the coordinator can tell which (see
+        // `coordinator::txn`), so it isn't reported as a success.
+        let staged = self.prepared.lock().unwrap().remove(&inner.upload_id);
+        let Some(write) = staged else {
+            return Err(Status::not_found(format!(
+                "upload {} is not prepared",
+                inner.upload_id
+            )));
+        };
 
-        Ok(Response::new(CommitResponse {
-            ok: true,
-            error: String::new(),
-        }))
+        match self.io.put(write.key, write.data).await {
+            Ok(_) => Ok(Response::new(CommitResponse {
+                ok: true,
+                error: String::new(),
+            })),
+            Err(e) => Ok(Response::new(CommitResponse {
+                ok: false,
+                error: e.to_string(),
+            })),
+        }
     }
 
     async fn abort(&self, req: Request<AbortRequest>) -> Result<Response<AbortResponse>, Status> {
-        let _inner = req.into_inner();
+        let inner = req.into_inner();
 
         // Clean up any prepared state
         // In production: delete temp files, release locks
+        self.prepared.lock().unwrap().remove(&inner.upload_id);
 
         Ok(Response::new(AbortResponse { ok: true }))
     }
 
-    async fn pull(&self, _req: Request<PullRequest>) -> Result<Response<Self::PullStream>, Status> {
-        Err(Status::unimplemented("Pull not implemented"))
+    async fn list_prepared(
+        &self,
+        _req: Request<ListPreparedRequest>,
+    ) -> Result<Response<ListPreparedResponse>, Status> {
+        let uploads = self
+            .prepared
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(upload_id, write)| PreparedUpload {
+                upload_id: upload_id.clone(),
+                key: write.key.clone(),
+            })
+            .collect();
+
+        Ok(Response::new(ListPreparedResponse { uploads }))
+    }
+
+    /// Stream a locally stored blob back to the caller in fixed-size chunks
+    async fn pull(&self, req: Request<PullRequest>) -> Result<Response<Self::PullStream>, Status> {
+        let inner = req.into_inner();
+
+        let value = if inner.length > 0 {
+            self.io
+                .get_range(inner.key.clone(), inner.offset, inner.length)
+                .await
+        } else {
+            self.io.get(inner.key.clone()).await
+        };
+        let value = value
+            .map_err(|e| e.to_grpc_status())?
+            .ok_or_else(|| Status::not_found(inner.key.clone()))?;
+
+        let (tx, rx) = tokio::sync::mpsc::channel(4);
+        tokio::spawn(async move {
+            for chunk in value.chunks(PULL_CHUNK_SIZE) {
+                let chunk = Chunk {
+                    data: chunk.to_vec(),
+                };
+                if tx.send(Ok(chunk)).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(Response::new(tokio_stream::wrappers::ReceiverStream::new(
+            rx,
+        )))
     }
 
     async fn delete(
@@ -86,7 +305,7 @@ impl VolumeInternal for VolumeGrpcService {
     ) -> Result<Response<DeleteResponse>, Status> {
         let inner = req.into_inner();
 
-        match self.store.lock().unwrap().delete(&inner.key) {
+        match self.io.delete(inner.key).await {
             Ok(_) => Ok(Response::new(DeleteResponse {
                 ok: true,
                 error: String::new(),
@@ -98,21 +317,151 @@ impl VolumeInternal for VolumeGrpcService {
         }
     }
 
+    async fn merkle_roots(
+        &self,
+        req: Request<MerkleRootsRequest>,
+    ) -> Result<Response<MerkleRootsResponse>, Status> {
+        let num_shards = req.into_inner().num_shards;
+        let by_shard = self.entries_by_shard(num_shards).await?;
+
+        let roots = (0..num_shards)
+            .map(|shard| shard_tree(by_shard.get(&shard)).root().to_vec())
+            .collect();
+
+        Ok(Response::new(MerkleRootsResponse { roots }))
+    }
+
+    async fn merkle_leaves(
+        &self,
+        req: Request<MerkleLeavesRequest>,
+    ) -> Result<Response<MerkleLeavesResponse>, Status> {
+        let inner = req.into_inner();
+        let by_shard = self.entries_by_shard(inner.num_shards).await?;
+
+        let leaves = shard_tree(by_shard.get(&inner.shard))
+            .leaves()
+            .iter()
+            .map(|leaf| leaf.to_vec())
+            .collect();
+
+        Ok(Response::new(MerkleLeavesResponse { leaves }))
+    }
+
+    async fn list_bucket(
+        &self,
+        req: Request<ListBucketRequest>,
+    ) -> Result<Response<ListBucketResponse>, Status> {
+        let inner = req.into_inner();
+        let mut by_shard = self.entries_by_shard(inner.num_shards).await?;
+
+        let entries = by_shard
+            .remove(&inner.shard)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|(key, _)| bucket_for(key) == inner.bucket as usize)
+            .map(|(key, blake3)| KeyHash { key, blake3 })
+            .collect();
+
+        Ok(Response::new(ListBucketResponse { entries }))
+    }
+
+    async fn list_blobs(
+        &self,
+        req: Request<ListBlobsRequest>,
+    ) -> Result<Response<ListBlobsResponse>, Status> {
+        let inner = req.into_inner();
+        let limit = match inner.limit {
+            0 => LIST_BLOBS_MAX,
+            n => (n as usize).min(LIST_BLOBS_MAX),
+        };
+        let blobs = self
+            .io
+            .blobs_after(inner.start_after, limit)
+            .await
+            .map_err(|e| e.to_grpc_status())?
+            .into_iter()
+            .map(|(key, blake3, size)| BlobInfo { key, blake3, size })
+            .collect();
+
+        Ok(Response::new(ListBlobsResponse { blobs }))
+    }
+
+    async fn delete_blobs(
+        &self,
+        req: Request<DeleteBlobsRequest>,
+    ) -> Result<Response<DeleteBlobsResponse>, Status> {
+        let blobs = req.into_inner().blobs;
+        let (deleted, bytes_freed) = self
+            .io
+            .run("delete_blobs", move |store| {
+                let mut deleted = Vec::new();
+                let mut bytes_freed = 0;
+                for blob in blobs {
+                    if let Some(size) = store.delete_if_hash(&blob.key, &blob.blake3)? {
+                        deleted.push(blob.key);
+                        bytes_freed += size;
+                    }
+                }
+                Ok((deleted, bytes_freed))
+            })
+            .await
+            .map_err(|e| e.to_grpc_status())?;
+
+        Ok(Response::new(DeleteBlobsResponse {
+            deleted,
+            bytes_freed,
+        }))
+    }
+
     async fn ping(&self, _req: Request<PingRequest>) -> Result<Response<PingResponse>, Status> {
+        let (stats, _) = self.io.stats().await.map_err(|e| e.to_grpc_status())?;
         Ok(Response::new(PingResponse {
-            volume_id: "vol-1".to_string(),
-            uptime_secs: 0,
-            total_keys: 0,
-            total_bytes: 0,
+            volume_id: self.volume_id.clone(),
+            uptime_secs: self.started_at.elapsed().as_secs(),
+            total_keys: stats.total_keys as u64,
+            total_bytes: stats.total_bytes,
         }))
     }
 
+    async fn stat(&self, req: Request<StatRequest>) -> Result<Response<StatResponse>, Status> {
+        let keys = req.into_inner().keys;
+        if keys.len() > LIST_BLOBS_MAX {
+            return Err(Status::invalid_argument(format!(
+                "at most {} keys per call",
+                LIST_BLOBS_MAX
+            )));
+        }
+        let blobs = keys
+            .into_iter()
+            .map(|key| match self.store.location(&key) {
+                Some(location) => BlobStat {
+                    key,
+                    exists: true,
+                    size: location.size,
+                    blake3: location.blake3,
+                },
+                None => BlobStat {
+                    key,
+                    exists: false,
+                    size: 0,
+                    blake3: String::new(),
+                },
+            })
+            .collect();
+
+        Ok(Response::new(StatResponse { blobs }))
+    }
+
     async fn stats(&self, _req: Request<StatsRequest>) -> Result<Response<StatsResponse>, Status> {
+        let (stats, free_bytes) = self.io.stats().await.map_err(|e| e.to_grpc_status())?;
         Ok(Response::new(StatsResponse {
-            total_keys: 0,
-            total_bytes: 0,
-            free_bytes: 0,
+            total_keys: stats.total_keys as u64,
+            total_bytes: stats.total_bytes,
+            free_bytes: free_bytes.unwrap_or(0),
             shards: vec![],
+            wal_bytes: stats.wal_bytes,
+            uptime_secs: self.started_at.elapsed().as_secs(),
+            segments: stats.active_segments as u64,
         }))
     }
 
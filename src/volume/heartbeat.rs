@@ -0,0 +1,194 @@
+//! Heartbeats from a volume to the coordinators
+//!
+//! A volume joins the cluster before its first heartbeat, and again when the
+//! leader no longer knows it. Only the Raft leader takes joins and
+//! heartbeats, followers refusing them, so the volume ends up talking to the
+//! coordinator that holds its command queue. Each heartbeat reports the volume's usage and placement labels, whether it went read-only for
+//! lack of free space, and the keys scrubbing quarantined since the last one, and receives the commands the
+//! coordinator queued for it. Commands run one at a time on a separate task,
+//! so a long compaction doesn't hold up heartbeats and get the volume marked
+//! suspect; the next heartbeat reports how they went. A last heartbeat tells
+//! the coordinators when the volume shuts down, so they stop placing writes
+//! on it (see `common::shutdown`).
+
+use crate::common::{connect_internal, VolumeCommand, VolumeConfig, METRICS, SHUTDOWN};
+use crate::proto::coordinator_internal_client::CoordinatorInternalClient;
+use crate::proto::{CommandResult, HeartbeatRequest, JoinRequest};
+use crate::volume::blob::BlobStore;
+use crate::volume::commands::CommandExecutor;
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc;
+use tonic::Code;
+
+/// Join request of the volume `volume_id` configured by `config`, with its
+/// placement labels
+pub fn join_request(volume_id: &str, config: &VolumeConfig) -> JoinRequest {
+    JoinRequest {
+        volume_id: volume_id.to_string(),
+        address: config.advertise_url(),
+        shards: vec![],
+        zone: config.zone.clone().unwrap_or_default(),
+        rack: config.rack.clone().unwrap_or_default(),
+        grpc_address: config.grpc_advertise_url(),
+        weight: config.weight.unwrap_or(0.0),
+    }
+}
+
+/// Join with `join`, then send heartbeats every `interval_secs` and execute
+/// the returned commands. Coordinators are tried in order until one accepts
+/// the call.
+pub fn start_heartbeat(
+    join: JoinRequest,
+    coordinators: Vec<String>,
+    interval_secs: u64,
+    store: Arc<BlobStore>,
+    executor: CommandExecutor,
+) -> tokio::task::JoinHandle<()> {
+    let (tx, mut rx) = mpsc::unbounded_channel::<VolumeCommand>();
+    // Commands run and not yet reported
+    let finished = Arc::new(Mutex::new(Vec::new()));
+    let results = finished.clone();
+    tokio::spawn(async move {
+        while let Some(command) = rx.recv().await {
+            tracing::info!("Executing command {}", command);
+            let error = match executor.execute(&command).await {
+                Ok(()) => String::new(),
+                Err(e) => {
+                    tracing::error!("Command {} failed: {}", command, e);
+                    e.to_string()
+                }
+            };
+            results.lock().unwrap().push(CommandResult {
+                command: command.to_string(),
+                error,
+            });
+        }
+    });
+
+    tokio::spawn(async move {
+        let volume_id = join.volume_id.clone();
+        let mut joined = false;
+        let mut interval =
+            tokio::time::interval(tokio::time::Duration::from_secs(interval_secs.max(1)));
+        loop {
+            let stopping = tokio::select! {
+                _ = interval.tick() => false,
+                _ = SHUTDOWN.wait() => true,
+            };
+            if !joined && !stopping {
+                joined = send_join(&coordinators, &join).await;
+                if !joined {
+                    tracing::warn!("{} could not join: no leader reachable", volume_id);
+                    continue;
+                }
+                tracing::info!("{} joined the cluster", volume_id);
+            }
+            let (stats, free_bytes) = (store.stats(), store.free_bytes());
+            let read_only = store.refresh_read_only();
+            let wal_error = match store.check_wal() {
+                Ok(()) => String::new(),
+                Err(e) => {
+                    tracing::error!("WAL of {} can't be written to: {}", volume_id, e);
+                    e.to_string()
+                }
+            };
+            METRICS.compressed_blobs.set(stats.compressed_blobs);
+            let corrupted_keys = store.corrupted_keys();
+            let finished_commands = finished.lock().unwrap().clone();
+            let reported = finished_commands.len();
+            let req = HeartbeatRequest {
+                volume_id: volume_id.clone(),
+                total_keys: stats.total_keys as u64,
+                total_bytes: stats.total_bytes,
+                free_bytes: free_bytes.unwrap_or(0),
+                corrupted_keys: corrupted_keys.clone(),
+                read_only,
+                finished_commands,
+                wal_error,
+                stopping,
+                zone: join.zone.clone(),
+                rack: join.rack.clone(),
+                weight: join.weight,
+            };
+
+            let commands = match send_heartbeat(&coordinators, req).await {
+                Heartbeat::Accepted(commands) => commands,
+                Heartbeat::Unknown => {
+                    // The leader lost the registration: join again
+                    joined = false;
+                    if stopping {
+                        return;
+                    }
+                    continue;
+                }
+                Heartbeat::Unreachable => {
+                    tracing::warn!("Heartbeat from {} reached no coordinator", volume_id);
+                    if stopping {
+                        return;
+                    }
+                    continue;
+                }
+            };
+            if stopping {
+                tracing::info!("Told the coordinators {} is stopping", volume_id);
+                return;
+            }
+            store.clear_corrupted_keys(&corrupted_keys);
+            finished.lock().unwrap().drain(..reported);
+            for command in commands {
+                match command.parse::<VolumeCommand>() {
+                    Ok(command) => {
+                        let _ = tx.send(command);
+                    }
+                    Err(e) => tracing::warn!("Ignoring command: {}", e),
+                }
+            }
+        }
+    })
+}
+
+/// Outcome of a heartbeat
+enum Heartbeat {
+    /// Taken by the leader, with the commands it queued
+    Accepted(Vec<String>),
+    /// The leader doesn't know the volume
+    Unknown,
+    Unreachable,
+}
+
+/// Register with the first coordinator that accepts the join
+async fn send_join(coordinators: &[String], req: &JoinRequest) -> bool {
+    for addr in coordinators {
+        let mut client = match connect_internal(addr).await {
+            Ok(channel) => CoordinatorInternalClient::new(channel),
+            Err(e) => {
+                tracing::debug!("Coordinator {} unreachable: {}", addr, e);
+                continue;
+            }
+        };
+        match client.join(req.clone()).await {
+            Ok(_) => return true,
+            Err(status) => tracing::debug!("Join through {} failed: {}", addr, status),
+        }
+    }
+    false
+}
+
+/// Send `req` to the first coordinator that takes it
+async fn send_heartbeat(coordinators: &[String], req: HeartbeatRequest) -> Heartbeat {
+    for addr in coordinators {
+        let mut client = match connect_internal(addr).await {
+            Ok(channel) => CoordinatorInternalClient::new(channel),
+            Err(e) => {
+                tracing::debug!("Coordinator {} unreachable: {}", addr, e);
+                continue;
+            }
+        };
+        match client.heartbeat(req.clone()).await {
+            Ok(resp) => return Heartbeat::Accepted(resp.into_inner().commands),
+            Err(status) if status.code() == Code::NotFound => return Heartbeat::Unknown,
+            Err(status) => tracing::debug!("Heartbeat to {} failed: {}", addr, status),
+        }
+    }
+    Heartbeat::Unreachable
+}
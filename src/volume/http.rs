@@ -2,17 +2,539 @@
 //!
 //! This module exposes the external HTTP API for volume operations.
 //! Security features (TLS, authentication) and cross-datacenter replication are planned for future releases.
+//!
+//! Data routes, for the coordinator to proxy or redirect clients to the
+//! volume holding a key, so large values don't go through it. Keys are the
+//! ones the coordinator stores (tenant prefix included) and may hold `/`:
+//! - `GET /blob/*key`: the value, or the slice asked for by a `Range`
+//!   header; `X-Minikv-Blake3` carries the hash of the whole value
+//! - `PUT /blob/*key` (or `POST`): store the body as the value, up to
+//!   `max_blob_size`; `507` while the volume is read-only for lack of space,
+//!   `503` with `Retry-After` while the write budget is spent
+//! - `DELETE /blob/*key`
+//! - `GET /stats`: keys, bytes, free space and whether writes are refused
+//! - `GET /health`: `200` as long as the volume answers, with its state
+//! - `GET /health/live`: `200` as long as the volume answers
+//! - `GET /health/ready`: `200` if its WAL can be written to and a data
+//!   directory is working, else `503`, with the result of each check
+//! - `POST /admin/config/reload`: re-read the configuration and apply what
+//!   can change at runtime (see `common::reload`)
+//!
+//! Every route is rate limited per client IP with `[volume.rate_limit]`
+//! (`429` with `Retry-After` past the limit), as on the coordinators.
+//!
+//! Values written here bypass the coordinator's two-phase commit: it is the
+//! caller's job to record them in the key metadata. With internal tokens
+//! enabled (`[volume.grpc_auth]`), data routes and the admin routes that
+//! change state need one as `Authorization: Bearer`.
+//!
+//! Clients come with a URL a coordinator redirected them to instead (see
+//! `coordinator::redirect`), which is checked for expiry and signature. An
+//! upload through one is copied to the other replicas it names and published
+//! through the coordinators before it is answered.
+//!
+//! Admin routes:
+//! - `GET /admin/compaction`: background compaction progress
+//! - `POST /admin/compaction/pause`, `POST /admin/compaction/resume`
+//! - `GET /admin/scrub`: background scrubbing progress and corrupted records
+//! - `GET /admin/disks`: segments, free space and state of each data directory
+//! - `GET /admin/chaos`, `PUT /admin/chaos`, `DELETE /admin/chaos`: faults
+//!   injected, with `fault_injection` (see `common::chaos`)
 
+use crate::common::range::{self, ByteRange};
+use crate::common::ratelimit::rate_limit_middleware;
+use crate::common::reload::reload_now;
+use crate::common::utils::{generate_upload_id, validate_key};
+use crate::common::{
+    blake3_hash, check_http_token, check_transfer, connect_internal, write_budget_middleware,
+    Faults, RateLimiter, WriteBudget, FAULTS,
+};
+use crate::coordinator::redirect::{self, parse_replicas};
+use crate::coordinator::volume_client::VolumeClient;
+use crate::proto::coordinator_internal_client::CoordinatorInternalClient;
+use crate::proto::CommitUploadRequest;
+use crate::volume::async_store::AsyncBlobStore;
 use crate::volume::blob::BlobStore;
+use crate::volume::compaction::Compactor;
+use crate::volume::scrub::Scrubber;
+use axum::body::Bytes;
+use axum::extract::{DefaultBodyLimit, Path, Query, State};
+use axum::http::{header, HeaderMap, HeaderName, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post, put};
+use axum::{Json, Router};
+use futures_util::future::join_all;
+use serde::Deserialize;
+use serde_json::json;
+use std::sync::Arc;
+
+/// Header carrying the blake3 hash of a value read from `/blob`
+pub const BLAKE3_HEADER: &str = "x-minikv-blake3";
 
+/// Size and hash of a stored value
 pub struct Location {
-    pub size: usize,
-    pub blake3: [u8; 32],
+    pub size: u64,
+    pub blake3: String,
+}
+
+/// Where the live value of `key` is, if there is one
+pub fn get_location(store: &BlobStore, key: &str) -> Option<Location> {
+    store.location(key).map(|location| Location {
+        size: location.size,
+        blake3: location.blake3,
+    })
+}
+
+/// State shared by the volume's HTTP handlers
+#[derive(Clone)]
+pub struct VolumeHttpState {
+    /// Store operations of the data routes, off the async runtime
+    pub io: AsyncBlobStore,
+    /// Largest value `PUT /blob` takes
+    pub max_blob_size: u64,
+    /// Coordinators publishing redirected uploads, tried in order
+    pub coordinators: Vec<String>,
+    /// Bound on the uploads in flight
+    pub writes: Arc<WriteBudget>,
+    pub compactor: Arc<Compactor>,
+    pub scrubber: Arc<Scrubber>,
+    /// Per-client-IP limits, applied to every route
+    pub rate_limiter: Arc<RateLimiter>,
+}
+
+/// Router of the volume's HTTP API
+pub fn create_router(state: VolumeHttpState) -> Router {
+    let body_limit = usize::try_from(state.max_blob_size).unwrap_or(usize::MAX);
+    Router::new()
+        .route(
+            "/blob/*key",
+            put(put_blob)
+                .post(put_blob)
+                .route_layer(axum::middleware::from_fn_with_state(
+                    state.writes.clone(),
+                    write_budget_middleware,
+                ))
+                .get(get_blob)
+                .delete(delete_blob)
+                .layer(DefaultBodyLimit::max(body_limit)),
+        )
+        .route("/stats", get(stats))
+        .route("/health", get(health))
+        .route("/health/live", get(health_live))
+        .route("/health/ready", get(health_ready))
+        .route("/admin/compaction", get(compaction_status))
+        .route("/admin/compaction/pause", post(pause_compaction))
+        .route("/admin/compaction/resume", post(resume_compaction))
+        .route("/admin/config/reload", post(reload_config))
+        .route("/admin/scrub", get(scrub_status))
+        .route("/admin/disks", get(disk_status))
+        .route(
+            "/admin/chaos",
+            get(chaos_status).put(set_chaos).delete(clear_chaos),
+        )
+        .layer(axum::middleware::from_fn_with_state(
+            state.rate_limiter.clone(),
+            rate_limit_middleware,
+        ))
+        .with_state(state)
+}
+
+/// Parameters of a URL a coordinator redirected a client to
+#[derive(Debug, Default, Deserialize)]
+struct TransferQuery {
+    expires: Option<u64>,
+    signature: Option<String>,
+    /// Upload id and targets, see `redirect::upload_params`
+    #[serde(default)]
+    upload: String,
+    #[serde(default)]
+    replicas: String,
+    #[serde(default)]
+    acks: usize,
+}
+
+impl TransferQuery {
+    /// Whether the request came through a redirect
+    fn is_transfer(&self) -> bool {
+        self.expires.is_some()
+    }
+}
+
+/// `401` unless `headers` carry an accepted internal token
+fn authorize(headers: &HeaderMap) -> Result<(), Response> {
+    let authorization = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok());
+    if check_http_token(authorization) {
+        Ok(())
+    } else {
+        Err((StatusCode::UNAUTHORIZED, "internal token required").into_response())
+    }
+}
+
+/// `403` unless the redirect URL `query` is unexpired and signed for
+/// `method` on `key` with `params`
+fn authorize_transfer(
+    query: &TransferQuery,
+    method: &str,
+    key: &str,
+    params: &str,
+) -> Result<(), Response> {
+    let expires = query.expires.unwrap_or(0);
+    if check_transfer(method, key, expires, params, query.signature.as_deref()) {
+        Ok(())
+    } else {
+        Err((StatusCode::FORBIDDEN, "invalid or expired transfer URL").into_response())
+    }
+}
+
+fn error_response(e: crate::Error) -> Response {
+    (e.to_http_status(), e.to_string()).into_response()
+}
+
+fn not_found(key: &str) -> Response {
+    (StatusCode::NOT_FOUND, format!("Key {} not found", key)).into_response()
+}
+
+/// Serve the value of `key`, or the slice of it a `Range` header asks for
+async fn get_blob(
+    State(state): State<VolumeHttpState>,
+    Path(key): Path<String>,
+    Query(query): Query<TransferQuery>,
+    headers: HeaderMap,
+) -> Response {
+    let authorized = if query.is_transfer() {
+        authorize_transfer(&query, redirect::READ, &key, "")
+    } else {
+        authorize(&headers)
+    };
+    if let Err(resp) = authorized {
+        return resp;
+    }
+    let Some(location) = get_location(state.io.store(), &key) else {
+        return not_found(&key);
+    };
+    let blake3 = (
+        HeaderName::from_static(BLAKE3_HEADER),
+        HeaderValue::from_str(&location.blake3).unwrap_or(HeaderValue::from_static("")),
+    );
+    let accept_ranges = (header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+    match ByteRange::from_headers(&headers).map(|range| range.resolve(location.size)) {
+        Some(Some((offset, length))) => match state.io.get_range(key.clone(), offset, length).await
+        {
+            Ok(Some(value)) if !value.is_empty() => {
+                let content_range = (
+                    header::CONTENT_RANGE,
+                    range::content_range(offset, value.len() as u64, location.size),
+                );
+                (
+                    StatusCode::PARTIAL_CONTENT,
+                    [content_range, accept_ranges, blake3],
+                    value,
+                )
+                    .into_response()
+            }
+            Ok(Some(_)) => range_not_satisfiable(location.size),
+            Ok(None) => not_found(&key),
+            Err(e) => error_response(e),
+        },
+        Some(None) => range_not_satisfiable(location.size),
+        None => match state.io.get(key.clone()).await {
+            Ok(Some(value)) => (StatusCode::OK, [accept_ranges, blake3], value).into_response(),
+            Ok(None) => not_found(&key),
+            Err(e) => error_response(e),
+        },
+    }
+}
+
+/// 416 response to a range outside a value of `size` bytes
+fn range_not_satisfiable(size: u64) -> Response {
+    (
+        StatusCode::RANGE_NOT_SATISFIABLE,
+        [(header::CONTENT_RANGE, range::unsatisfiable_range(size))],
+        "Requested range not satisfiable",
+    )
+        .into_response()
+}
+
+/// Store the body as the value of `key`. Through a redirect, also copy it
+/// to the other replicas and publish it.
+async fn put_blob(
+    State(state): State<VolumeHttpState>,
+    Path(key): Path<String>,
+    Query(query): Query<TransferQuery>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let authorized = if query.is_transfer() {
+        let params = redirect::upload_params(&query.upload, &query.replicas, query.acks);
+        authorize_transfer(&query, redirect::UPLOAD, &key, &params)
+    } else {
+        authorize(&headers)
+    };
+    if let Err(resp) = authorized {
+        return resp;
+    }
+    if let Err(e) = validate_key(&key) {
+        return error_response(e);
+    }
+    let (size, blake3) = (body.len() as u64, blake3_hash(&body));
+    if let Err(e) = state.io.put(key.clone(), body.to_vec()).await {
+        return error_response(e);
+    }
+    if !query.is_transfer() {
+        return Json(json!({ "key": key, "size": size, "blake3": blake3 })).into_response();
+    }
+    match publish_upload(&state, &key, &blake3, body.to_vec(), &query).await {
+        Ok(replicas) => Json(json!({
+            "key": key,
+            "size": size,
+            "blake3": blake3,
+            "replicas": replicas,
+        }))
+        .into_response(),
+        Err(e) => error_response(e),
+    }
 }
 
-pub fn get_location(_store: &BlobStore) -> Result<Location, String> {
-    Ok(Location {
-        size: 0,
-        blake3: [0; 32],
+/// Copy an upload stored here, the first of its replicas, to the others,
+/// then have a coordinator publish it on the replicas holding it. Returns
+/// those replicas.
+async fn publish_upload(
+    state: &VolumeHttpState,
+    key: &str,
+    blake3: &str,
+    data: Vec<u8>,
+    query: &TransferQuery,
+) -> crate::Result<Vec<String>> {
+    let replicas = parse_replicas(&query.replicas);
+    let Some(((volume_id, _), others)) = replicas.split_first() else {
+        return Err(crate::Error::InvalidRequest(
+            "upload names no replica".into(),
+        ));
+    };
+    let copies = join_all(
+        others
+            .iter()
+            .map(|(_, address)| copy_to(address, key, blake3, data.clone())),
+    )
+    .await;
+    let mut holding = vec![volume_id.to_string()];
+    for ((other, _), copied) in others.iter().zip(copies) {
+        match copied {
+            Ok(()) => holding.push(other.to_string()),
+            Err(e) => tracing::warn!("Copy of upload {} to {} failed: {}", key, other, e),
+        }
+    }
+    if holding.len() < query.acks {
+        return Err(crate::Error::InsufficientReplicas {
+            needed: query.acks,
+            available: holding.len(),
+        });
+    }
+
+    let req = CommitUploadRequest {
+        key: key.to_string(),
+        size: data.len() as u64,
+        blake3: blake3.to_string(),
+        replicas: holding.clone(),
+        upload_id: query.upload.clone(),
+    };
+    let mut last_error = None;
+    for addr in &state.coordinators {
+        let mut client = match connect_internal(addr).await {
+            Ok(channel) => CoordinatorInternalClient::new(channel),
+            Err(e) => {
+                tracing::debug!("Coordinator {} unreachable: {}", addr, e);
+                continue;
+            }
+        };
+        // Followers refuse: go on to the leader
+        match client.commit_upload(req.clone()).await {
+            Ok(_) => return Ok(holding),
+            Err(status) => last_error = Some(status),
+        }
+    }
+    Err(match last_error {
+        Some(status) => status.into(),
+        None => crate::Error::ConnectionFailed("no coordinator reachable".into()),
     })
 }
+
+/// Write a value to the volume at gRPC `address` through 2PC
+async fn copy_to(address: &str, key: &str, blake3: &str, data: Vec<u8>) -> crate::Result<()> {
+    let upload_id = generate_upload_id();
+    let mut client = VolumeClient::connect(address.to_string()).await?;
+    let prepared = client
+        .prepare(
+            key.to_string(),
+            upload_id.clone(),
+            data.len() as u64,
+            blake3.to_string(),
+            data,
+        )
+        .await?;
+    if !prepared.ok {
+        return Err(crate::Error::PrepareFailed {
+            node: address.to_string(),
+            reason: prepared.error,
+        });
+    }
+    let committed = client.commit(upload_id, key.to_string()).await?;
+    if !committed.ok {
+        return Err(crate::Error::CommitFailed {
+            node: address.to_string(),
+            reason: committed.error,
+        });
+    }
+    Ok(())
+}
+
+async fn delete_blob(
+    State(state): State<VolumeHttpState>,
+    Path(key): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    if let Err(resp) = authorize(&headers) {
+        return resp;
+    }
+    if get_location(state.io.store(), &key).is_none() {
+        return not_found(&key);
+    }
+    match state.io.delete(key).await {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => error_response(e),
+    }
+}
+
+async fn stats(State(state): State<VolumeHttpState>) -> Response {
+    match state.io.stats().await {
+        Ok((stats, free_bytes)) => Json(json!({
+            "total_keys": stats.total_keys,
+            "total_bytes": stats.total_bytes,
+            "active_segments": stats.active_segments,
+            "keys_with_ttl": stats.keys_with_ttl,
+            "compressed_blobs": stats.compressed_blobs,
+            "compression_ratio": stats.compression_ratio(),
+            "wal_bytes": stats.wal_bytes,
+            "free_bytes": free_bytes,
+            "read_only": state.io.store().is_read_only(),
+        }))
+        .into_response(),
+        Err(e) => error_response(e),
+    }
+}
+
+/// Answers as long as the volume does; `status` says whether it takes
+/// writes and whether a data directory failed
+async fn health(State(state): State<VolumeHttpState>) -> impl IntoResponse {
+    let store = state.io.store();
+    let failed_disks = store.disk_stats().iter().filter(|d| d.failed).count();
+    let status = if store.is_read_only() {
+        "read_only"
+    } else if failed_disks > 0 {
+        "degraded"
+    } else {
+        "ok"
+    };
+    Json(json!({
+        "status": status,
+        "read_only": store.is_read_only(),
+        "failed_disks": failed_disks,
+        "version": env!("CARGO_PKG_VERSION"),
+    }))
+}
+
+async fn health_live() -> impl IntoResponse {
+    Json(json!({
+        "alive": true,
+        "version": env!("CARGO_PKG_VERSION"),
+    }))
+}
+
+async fn health_ready(State(state): State<VolumeHttpState>) -> impl IntoResponse {
+    let wal = match state.io.run("check_wal", |store| store.check_wal()).await {
+        Ok(()) => json!({ "ok": true }),
+        Err(e) => json!({ "ok": false, "error": e.to_string() }),
+    };
+    let disks = state.io.store().disk_stats();
+    let working = disks.iter().filter(|d| !d.failed).count();
+    let disks = json!({ "ok": working > 0, "working": working, "failed": disks.len() - working });
+    let ready = wal["ok"] == true && disks["ok"] == true;
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (
+        status,
+        Json(json!({
+            "ready": ready,
+            "checks": { "wal": wal, "disks": disks },
+        })),
+    )
+}
+
+async fn compaction_status(
+    State(state): State<VolumeHttpState>,
+) -> impl axum::response::IntoResponse {
+    Json(state.compactor.progress())
+}
+
+/// Stop background compaction, cutting a running one short
+async fn pause_compaction(State(state): State<VolumeHttpState>, headers: HeaderMap) -> Response {
+    if let Err(resp) = authorize(&headers) {
+        return resp;
+    }
+    state.compactor.pause();
+    Json(state.compactor.progress()).into_response()
+}
+
+async fn resume_compaction(State(state): State<VolumeHttpState>, headers: HeaderMap) -> Response {
+    if let Err(resp) = authorize(&headers) {
+        return resp;
+    }
+    state.compactor.resume();
+    Json(state.compactor.progress()).into_response()
+}
+
+async fn reload_config(headers: HeaderMap) -> Response {
+    if let Err(resp) = authorize(&headers) {
+        return resp;
+    }
+    match reload_now().await {
+        Ok(report) => Json(report).into_response(),
+        Err(e) => error_response(e),
+    }
+}
+
+async fn scrub_status(State(state): State<VolumeHttpState>) -> impl axum::response::IntoResponse {
+    Json(state.scrubber.progress())
+}
+
+async fn disk_status(State(state): State<VolumeHttpState>) -> impl axum::response::IntoResponse {
+    Json(state.io.store().disk_stats())
+}
+
+async fn chaos_status() -> impl axum::response::IntoResponse {
+    Json(json!({ "enabled": FAULTS.is_enabled(), "faults": FAULTS.get() }))
+}
+
+/// Replace the faults this volume injects
+async fn set_chaos(headers: HeaderMap, Json(faults): Json<Faults>) -> Response {
+    if let Err(resp) = authorize(&headers) {
+        return resp;
+    }
+    match FAULTS.set(faults) {
+        Ok(faults) => Json(json!({ "enabled": true, "faults": faults })).into_response(),
+        Err(e) => error_response(e),
+    }
+}
+
+async fn clear_chaos(headers: HeaderMap) -> Response {
+    if let Err(resp) = authorize(&headers) {
+        return resp;
+    }
+    FAULTS.clear();
+    Json(json!({ "enabled": FAULTS.is_enabled(), "faults": FAULTS.get() })).into_response()
+}
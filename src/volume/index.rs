@@ -4,15 +4,25 @@
 //! Each key maps to a BlobLocation, which describes where the value is stored on disk.
 //! The index supports snapshotting for fast recovery after a crash.
 //! TTL (Time-To-Live) support enables automatic key expiration.
+//! Snapshots are sealed with the index key when encryption at rest is enabled;
+//! plaintext snapshots still load.
+//! `ShardedIndex` splits an index into independently locked shards, so that
+//! the blob store can serve lookups while other keys are being written.
 
-use crate::common::Result;
+use crate::common::{EncryptedData, Result, ENCRYPTION_MANAGER};
 use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
 use std::collections::HashMap;
 use std::fs::File;
-use std::io::{BufReader, BufWriter, Read, Write};
+use std::hash::{Hash, Hasher};
+use std::io::{BufWriter, Read, Write};
 use std::path::Path;
+use std::sync::RwLock;
 
-const SNAPSHOT_MAGIC: &[u8; 8] = b"KVINDEX3"; // Bumped version for TTL support
+const SNAPSHOT_MAGIC: &[u8; 8] = b"KVINDEX4"; // Bumped version for compressed sizes
+
+/// Number of shards of a `ShardedIndex`
+const INDEX_SHARDS: usize = 16;
 
 /// Blob location metadata
 /// Describes the physical location of a value in the log-structured storage engine.
@@ -26,6 +36,22 @@ pub struct BlobLocation {
     /// If None, the key never expires.
     #[serde(default)]
     pub expires_at: Option<u64>,
+    /// Size of the value on disk when it is stored compressed
+    #[serde(default)]
+    pub compressed_size: Option<u64>,
+}
+
+impl BlobLocation {
+    /// Whether the key has a TTL that has passed
+    pub fn is_expired(&self) -> bool {
+        self.expires_at.is_some_and(|expires_at| {
+            let now = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_millis() as u64;
+            now > expires_at
+        })
+    }
 }
 
 /// In-memory index
@@ -143,8 +169,7 @@ impl Index {
     /// Save the current index as a snapshot file.
     /// Used for fast recovery after restart.
     pub fn save_snapshot(&self, path: impl AsRef<Path>) -> Result<()> {
-        let file = File::create(path)?;
-        let mut writer = BufWriter::new(file);
+        let mut writer = Vec::new();
 
         // Write magic
         writer.write_all(SNAPSHOT_MAGIC)?;
@@ -172,23 +197,45 @@ impl Index {
             // TTL: expires_at (0 = no expiration, >0 = timestamp)
             let expires_at = loc.expires_at.unwrap_or(0);
             writer.write_all(&expires_at.to_le_bytes())?;
+
+            // Compressed size (0 = stored uncompressed)
+            let compressed_size = loc.compressed_size.unwrap_or(0);
+            writer.write_all(&compressed_size.to_le_bytes())?;
         }
 
-        writer.flush()?;
+        let bytes = {
+            let encryption = ENCRYPTION_MANAGER.read().unwrap();
+            if encryption.is_enabled() {
+                encryption.encrypt_index(&writer)?.to_bytes()
+            } else {
+                writer
+            }
+        };
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(&bytes)?;
+        file.flush()?;
         Ok(())
     }
 
     /// Load an index snapshot from file.
     /// Returns a new Index instance populated from the snapshot.
     pub fn load_snapshot(path: impl AsRef<Path>) -> Result<Self> {
-        let file = File::open(path)?;
-        let mut reader = BufReader::new(file);
+        let mut bytes = std::fs::read(path)?;
+        if EncryptedData::is_encrypted(&bytes) {
+            let encrypted = EncryptedData::from_bytes(&bytes)?;
+            bytes = ENCRYPTION_MANAGER
+                .read()
+                .unwrap()
+                .decrypt_index(&encrypted)?;
+        }
+        let mut reader = bytes.as_slice();
 
         // Read and verify magic
         let mut magic = [0u8; 8];
         reader.read_exact(&mut magic)?;
-        // Support both v2 (KVINDEX2) and v3 (KVINDEX3) formats
-        let has_ttl = &magic == b"KVINDEX3";
+        // Support v2 (KVINDEX2), v3 (KVINDEX3, TTL) and v4 (KVINDEX4, compressed sizes)
+        let has_compressed_size = &magic == b"KVINDEX4";
+        let has_ttl = &magic == b"KVINDEX3" || has_compressed_size;
         if &magic != b"KVINDEX2" && !has_ttl {
             return Err(crate::Error::Corrupted("Invalid snapshot magic".into()));
         }
@@ -249,6 +296,15 @@ impl Index {
                 None
             };
 
+            // Read compressed size if v4 format
+            let compressed_size = if has_compressed_size {
+                let mut compressed_bytes = [0u8; 8];
+                reader.read_exact(&mut compressed_bytes)?;
+                Some(u64::from_le_bytes(compressed_bytes)).filter(|&size| size > 0)
+            } else {
+                None
+            };
+
             index.insert(
                 key,
                 BlobLocation {
@@ -257,6 +313,7 @@ impl Index {
                     size,
                     blake3,
                     expires_at,
+                    compressed_size,
                 },
             );
         }
@@ -265,6 +322,111 @@ impl Index {
     }
 }
 
+/// Index split into shards by key hash, each behind its own lock.
+/// Lookups return copies of the locations, so no lock outlives the call.
+#[derive(Debug)]
+pub struct ShardedIndex {
+    shards: Vec<RwLock<Index>>,
+}
+
+impl Default for ShardedIndex {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ShardedIndex {
+    pub fn new() -> Self {
+        Self::from_index(Index::new())
+    }
+
+    /// Distribute the entries of `index` over the shards
+    pub fn from_index(index: Index) -> Self {
+        let sharded = Self {
+            shards: (0..INDEX_SHARDS).map(|_| RwLock::default()).collect(),
+        };
+        sharded.replace(index);
+        sharded
+    }
+
+    fn shard(&self, key: &str) -> &RwLock<Index> {
+        &self.shards[shard_of(key)]
+    }
+
+    /// Insert or update a key in the index.
+    pub fn insert(&self, key: String, location: BlobLocation) {
+        self.shard(&key).write().unwrap().insert(key, location);
+    }
+
+    /// Get location for key
+    pub fn get(&self, key: &str) -> Option<BlobLocation> {
+        self.shard(key).read().unwrap().get(key).cloned()
+    }
+
+    /// Get location for key, returning None if expired.
+    pub fn get_if_valid(&self, key: &str) -> Option<BlobLocation> {
+        self.get(key).filter(|location| !location.is_expired())
+    }
+
+    /// Remove key
+    pub fn remove(&self, key: &str) -> Option<BlobLocation> {
+        self.shard(key).write().unwrap().remove(key)
+    }
+
+    /// Number of keys
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|s| s.read().unwrap().len()).sum()
+    }
+
+    /// Is empty
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Call `f` on every entry, one shard at a time: writes to the other
+    /// shards go on meanwhile.
+    pub fn for_each(&self, mut f: impl FnMut(&String, &BlobLocation)) {
+        for shard in &self.shards {
+            for (key, location) in shard.read().unwrap().iter() {
+                f(key, location);
+            }
+        }
+    }
+
+    /// Remove all expired keys and return the number of keys removed.
+    pub fn cleanup_expired(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|s| s.write().unwrap().cleanup_expired())
+            .sum()
+    }
+
+    /// Copy of the whole index, e.g. to save a snapshot
+    pub fn snapshot(&self) -> Index {
+        let mut index = Index::new();
+        self.for_each(|key, location| index.insert(key.clone(), location.clone()));
+        index
+    }
+
+    /// Replace every entry with those of `index`
+    pub fn replace(&self, index: Index) {
+        let mut shards: Vec<_> = self.shards.iter().map(|s| s.write().unwrap()).collect();
+        for shard in shards.iter_mut() {
+            shard.clear();
+        }
+        for (key, location) in index.map {
+            shards[shard_of(&key)].insert(key, location);
+        }
+    }
+}
+
+/// Shard of a `ShardedIndex` holding `key`
+fn shard_of(key: &str) -> usize {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish() as usize % INDEX_SHARDS
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -283,6 +445,7 @@ mod tests {
                 size: 1024,
                 blake3: "abc123".to_string(),
                 expires_at: None,
+                compressed_size: None,
             },
         );
 
@@ -312,6 +475,7 @@ mod tests {
                 size: 1024,
                 blake3: blake3_hash(b"data1"),
                 expires_at: None,
+                compressed_size: None,
             },
         );
         index.insert(
@@ -322,6 +486,7 @@ mod tests {
                 size: 2048,
                 blake3: blake3_hash(b"data2"),
                 expires_at: Some(9999999999999), // Far future expiration
+                compressed_size: Some(512),
             },
         );
 
@@ -338,9 +503,11 @@ mod tests {
         let loc1 = loaded.get("key1").unwrap();
         assert_eq!(loc1.offset, 100);
         assert_eq!(loc1.expires_at, None);
+        assert_eq!(loc1.compressed_size, None);
 
         let loc2 = loaded.get("key2").unwrap();
         assert_eq!(loc2.expires_at, Some(9999999999999));
+        assert_eq!(loc2.compressed_size, Some(512));
     }
 
     #[test]
@@ -362,6 +529,7 @@ mod tests {
                 size: 100,
                 blake3: "test".to_string(),
                 expires_at: Some(past_time),
+                compressed_size: None,
             },
         );
 
@@ -380,6 +548,7 @@ mod tests {
                 size: 100,
                 blake3: "test".to_string(),
                 expires_at: Some(future_time),
+                compressed_size: None,
             },
         );
 
@@ -392,6 +561,7 @@ mod tests {
                 size: 100,
                 blake3: "test".to_string(),
                 expires_at: None,
+                compressed_size: None,
             },
         );
 
@@ -426,6 +596,7 @@ mod tests {
                 size: 100,
                 blake3: "test".to_string(),
                 expires_at: Some(12345),
+                compressed_size: None,
             },
         );
 
@@ -437,6 +608,7 @@ mod tests {
                 size: 100,
                 blake3: "test".to_string(),
                 expires_at: None,
+                compressed_size: None,
             },
         );
 
@@ -445,4 +617,38 @@ mod tests {
         assert_eq!(keys_with_ttl[0].0, "key_with_ttl");
         assert_eq!(keys_with_ttl[0].1, 12345);
     }
+
+    #[test]
+    fn test_sharded_index() {
+        let location = |offset, expires_at| BlobLocation {
+            shard: 0,
+            offset,
+            size: 10,
+            blake3: "test".to_string(),
+            expires_at,
+            compressed_size: None,
+        };
+        let mut index = Index::new();
+        index.insert("loaded".to_string(), location(0, None));
+        let sharded = ShardedIndex::from_index(index);
+
+        for i in 0..100 {
+            sharded.insert(format!("key_{}", i), location(i, None));
+        }
+        sharded.insert("expired".to_string(), location(0, Some(1)));
+        assert_eq!(sharded.len(), 102);
+        assert_eq!(sharded.get("key_42").unwrap().offset, 42);
+        assert!(sharded.get("expired").is_some());
+        assert!(sharded.get_if_valid("expired").is_none());
+
+        assert!(sharded.remove("key_42").is_some());
+        assert!(sharded.get("key_42").is_none());
+        assert_eq!(sharded.cleanup_expired(), 1);
+
+        let snapshot = sharded.snapshot();
+        assert_eq!(snapshot.len(), 100);
+        assert!(snapshot.contains("loaded"));
+        sharded.replace(Index::new());
+        assert!(sharded.is_empty());
+    }
 }
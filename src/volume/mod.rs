@@ -6,13 +6,24 @@
 //! - Automatic compaction
 //! - Bloom filters for fast negative lookups
 //! - Index snapshots for fast restarts
+//! - LRU cache of recently read blobs
+//! - Heartbeats carrying commands from the coordinator
 
+pub mod async_store;
 pub mod blob;
+pub mod cache;
+pub mod commands;
 pub mod compaction;
+pub mod disks;
+pub mod fsck;
 pub mod grpc;
+pub mod heartbeat;
 pub mod http;
 pub mod index;
+pub mod rekey;
+pub mod scrub;
 pub mod server;
+pub mod snapshot;
 pub mod wal;
 
 pub use server::VolumeServer;
@@ -0,0 +1,85 @@
+//! Re-encryption of a volume under the current master key
+//!
+//! After a master key rotation, records sealed with a previous key (or
+//! written before encryption was enabled) are rewritten batch by batch, so
+//! writes are only held up for one batch at a time; reads keep being served
+//! throughout. A final compaction drops the superseded records and
+//! the WAL, and re-seals the index snapshot, after which the previous key is
+//! no longer needed.
+
+use crate::common::{Result, ENCRYPTION_MANAGER};
+use crate::volume::blob::BlobStore;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Keys looked at per batch
+pub const DEFAULT_BATCH_SIZE: usize = 1000;
+
+/// Set while a rekey runs in this process
+static RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// Progress of a rekey
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RekeyProgress {
+    /// Version of the master key records are rewritten with
+    pub key_version: u8,
+    /// Keys looked at so far
+    pub scanned: usize,
+    /// Keys re-encrypted so far
+    pub rewritten: usize,
+    /// Whether every key was looked at and the store compacted
+    pub done: bool,
+}
+
+/// Whether a rekey is running in this process
+pub fn is_running() -> bool {
+    RUNNING.load(Ordering::SeqCst)
+}
+
+/// Re-encrypt every record of `store` that isn't sealed with the current
+/// master key, then compact. `on_progress` is called after each batch and
+/// once more when done.
+pub fn rekey_store(
+    store: &BlobStore,
+    batch_size: usize,
+    mut on_progress: impl FnMut(&RekeyProgress),
+) -> Result<RekeyProgress> {
+    let key_version = {
+        let encryption = ENCRYPTION_MANAGER.read().unwrap();
+        if !encryption.is_enabled() {
+            return Err(crate::Error::InvalidConfig(
+                "rekey needs encryption at rest to be enabled".into(),
+            ));
+        }
+        encryption.key_version()
+    };
+    if RUNNING.swap(true, Ordering::SeqCst) {
+        return Err(crate::Error::Internal("a rekey is already running".into()));
+    }
+
+    let result = (|| -> Result<RekeyProgress> {
+        let mut progress = RekeyProgress {
+            key_version,
+            ..Default::default()
+        };
+        let mut after = String::new();
+        loop {
+            let batch = store.rekey_batch(&after, batch_size.max(1))?;
+            progress.scanned += batch.scanned;
+            progress.rewritten += batch.rewritten;
+            match batch.next_after {
+                Some(next) => {
+                    after = next;
+                    on_progress(&progress);
+                }
+                None => break,
+            }
+        }
+        store.compact()?;
+        progress.done = true;
+        on_progress(&progress);
+        Ok(progress)
+    })();
+    RUNNING.store(false, Ordering::SeqCst);
+    result
+}
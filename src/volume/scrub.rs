@@ -0,0 +1,260 @@
+//! Background scrubbing of a volume
+//!
+//! `Scrubber` reads every live record of every segment, one segment after
+//! the other, every `scrub_interval_secs`, checking its checksum and the
+//! blake3 of its value against the index (see `BlobStore::scrub_segment_with`).
+//! Reads are paced to `scrub_max_bytes_per_sec` so they stay out of the way
+//! of client traffic.
+//!
+//! Corrupted records are quarantined: deleted from the volume, logged to
+//! `quarantine.log` and reported with the next heartbeat, on which the
+//! coordinator copies the key back from a healthy replica. Progress and
+//! error counts are served at `/admin/scrub` on the volume and exported as
+//! metrics.
+
+use crate::common::{timestamp_now, Result, VolumeConfig, METRICS};
+use crate::volume::blob::BlobStore;
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// When and how fast to scrub (from `VolumeConfig`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScrubPolicy {
+    /// Between passes (0 = never scrub in the background)
+    pub interval_secs: u64,
+    /// Pace of the reads (0 = unthrottled)
+    pub max_bytes_per_sec: u64,
+}
+
+impl ScrubPolicy {
+    pub fn from_config(config: &VolumeConfig) -> Self {
+        Self {
+            interval_secs: config.scrub_interval_secs,
+            max_bytes_per_sec: config.scrub_max_bytes_per_sec,
+        }
+    }
+}
+
+/// State of background scrubbing
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ScrubProgress {
+    pub running: bool,
+    /// Segments of the running (or last) pass, and those scrubbed so far
+    pub segments_total: usize,
+    pub segments_scrubbed: usize,
+    /// Live bytes of those segments, and bytes read so far
+    pub bytes_total: u64,
+    pub bytes_scrubbed: u64,
+    /// Passes completed
+    pub passes: u64,
+    /// Records checked, and found corrupted, over all passes
+    pub records_checked: u64,
+    pub corrupted_records: u64,
+    /// Segments that could not be read over all passes
+    pub errors: u64,
+    /// Keys quarantined by the last pass
+    pub last_corrupted_keys: Vec<String>,
+    /// When the last pass ended (Unix milliseconds)
+    pub last_finished_at: Option<u64>,
+    /// Why the last segment that failed did, if one did
+    pub last_error: Option<String>,
+}
+
+/// Scrubs a store in the background
+pub struct Scrubber {
+    store: Arc<BlobStore>,
+    policy: ScrubPolicy,
+    progress: Mutex<ScrubProgress>,
+}
+
+impl Scrubber {
+    pub fn new(store: Arc<BlobStore>, policy: ScrubPolicy) -> Self {
+        Self {
+            store,
+            policy,
+            progress: Mutex::new(ScrubProgress::default()),
+        }
+    }
+
+    pub fn progress(&self) -> ScrubProgress {
+        self.progress.lock().unwrap().clone()
+    }
+
+    /// Scrub every segment once. A segment that can't be read is counted
+    /// as an error and skipped. Returns the keys quarantined. Blocks for
+    /// the whole pass.
+    pub fn run_once(&self) -> Result<Vec<String>> {
+        let usage = self.store.segment_usage()?;
+        {
+            let mut progress = self.progress.lock().unwrap();
+            progress.running = true;
+            progress.segments_total = usage.len();
+            progress.segments_scrubbed = 0;
+            progress.bytes_total = usage.values().map(|usage| usage.live_bytes).sum();
+            progress.bytes_scrubbed = 0;
+        }
+
+        let started = Instant::now();
+        let mut read = 0u64;
+        let mut corrupted = Vec::new();
+        for segment in usage.into_keys() {
+            let scrubbed = self.store.scrub_segment_with(segment, |bytes| {
+                read += bytes;
+                self.progress.lock().unwrap().bytes_scrubbed = read;
+                self.pace(read, started);
+                true
+            });
+            let mut progress = self.progress.lock().unwrap();
+            match scrubbed {
+                Ok(report) => {
+                    let report = report.unwrap_or_default();
+                    progress.segments_scrubbed += 1;
+                    progress.records_checked += report.records;
+                    progress.corrupted_records += report.corrupted.len() as u64;
+                    METRICS.scrubbed_records.add(report.records);
+                    METRICS
+                        .scrub_corrupted_records
+                        .add(report.corrupted.len() as u64);
+                    corrupted.extend(report.corrupted);
+                }
+                Err(e) => {
+                    tracing::error!("Scrub of segment {} failed: {}", segment, e);
+                    progress.errors += 1;
+                    progress.last_error = Some(format!("segment {}: {}", segment, e));
+                }
+            }
+        }
+
+        let mut progress = self.progress.lock().unwrap();
+        progress.running = false;
+        progress.passes += 1;
+        progress.last_finished_at = Some(timestamp_now());
+        progress.last_corrupted_keys = corrupted.clone();
+        if corrupted.is_empty() {
+            tracing::info!(
+                "Scrubbed {} segments, {} bytes",
+                progress.segments_scrubbed,
+                read
+            );
+        } else {
+            tracing::warn!(
+                "Scrubbed {} segments, quarantining {} corrupted records",
+                progress.segments_scrubbed,
+                corrupted.len()
+            );
+        }
+        Ok(corrupted)
+    }
+
+    /// Sleep until `read` bytes since `started` are within the policy's pace
+    fn pace(&self, read: u64, started: Instant) {
+        if self.policy.max_bytes_per_sec == 0 {
+            return;
+        }
+        let due = Duration::from_secs_f64(read as f64 / self.policy.max_bytes_per_sec as f64);
+        if let Some(ahead) = due.checked_sub(started.elapsed()) {
+            std::thread::sleep(ahead);
+        }
+    }
+}
+
+/// Scrub the store every `interval_secs` of the policy. Does nothing with
+/// an interval of 0.
+pub fn start_scrub_task(scrubber: Arc<Scrubber>) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let interval_secs = scrubber.policy.interval_secs;
+        if interval_secs == 0 {
+            return;
+        }
+        let mut ticker = tokio::time::interval(Duration::from_secs(interval_secs));
+        // The first tick is immediate: leave the volume time to start
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            let scrubber = scrubber.clone();
+            match tokio::task::spawn_blocking(move || scrubber.run_once()).await {
+                Ok(Ok(_)) => {}
+                Ok(Err(e)) => tracing::error!("Scrub failed: {}", e),
+                Err(e) => tracing::error!("Scrub task panicked: {}", e),
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::WalSyncPolicy;
+    use std::io::{Seek, SeekFrom, Write};
+    use tempfile::tempdir;
+
+    fn policy() -> ScrubPolicy {
+        ScrubPolicy {
+            interval_secs: 0,
+            max_bytes_per_sec: 0,
+        }
+    }
+
+    fn store(dir: &tempfile::TempDir) -> Arc<BlobStore> {
+        Arc::new(
+            BlobStore::open(
+                &dir.path().join("data"),
+                &dir.path().join("wal"),
+                WalSyncPolicy::Never,
+            )
+            .unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_scrub_clean_store() {
+        let dir = tempdir().unwrap();
+        let store = store(&dir);
+        store.put("a", b"one").unwrap();
+        store.put("b", b"two").unwrap();
+        store.put("a", b"three").unwrap();
+
+        let scrubber = Scrubber::new(store.clone(), policy());
+        assert!(scrubber.run_once().unwrap().is_empty());
+        let progress = scrubber.progress();
+        assert_eq!(progress.passes, 1);
+        assert_eq!(progress.segments_scrubbed, 1);
+        // Only live records are checked
+        assert_eq!(progress.records_checked, 2);
+        assert_eq!(progress.bytes_scrubbed, progress.bytes_total);
+        assert_eq!(progress.corrupted_records, 0);
+        assert!(store.corrupted_keys().is_empty());
+    }
+
+    #[test]
+    fn test_scrub_quarantines_corrupted_record() {
+        let dir = tempdir().unwrap();
+        let store = store(&dir);
+        store.put("a", b"one").unwrap();
+        store.put("b", b"two").unwrap();
+
+        // Flip the first value byte of "a", the first record
+        let mut segment = std::fs::OpenOptions::new()
+            .write(true)
+            .open(dir.path().join("data/segments/seg_000000.blob"))
+            .unwrap();
+        segment.seek(SeekFrom::Start(25 + 1)).unwrap();
+        segment.write_all(b"X").unwrap();
+        drop(segment);
+
+        let scrubber = Scrubber::new(store.clone(), policy());
+        assert_eq!(scrubber.run_once().unwrap(), vec!["a".to_string()]);
+        assert_eq!(scrubber.progress().corrupted_records, 1);
+        assert!(store.get("a").unwrap().is_none());
+        assert_eq!(store.get("b").unwrap().unwrap(), b"two");
+        assert_eq!(store.corrupted_keys(), vec!["a".to_string()]);
+        let log = std::fs::read_to_string(dir.path().join("data/quarantine.log")).unwrap();
+        assert!(log.contains("\"a\""));
+
+        // Reported once
+        store.clear_corrupted_keys(&["a".to_string()]);
+        assert!(store.corrupted_keys().is_empty());
+        assert!(scrubber.run_once().unwrap().is_empty());
+    }
+}
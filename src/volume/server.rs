@@ -2,17 +2,90 @@
 //!
 //! This module provides the log-structured, append-only storage engine for data volumes.
 //! Each volume uses a BlobStore backed by a Write-Ahead Log (WAL) for durability and fast recovery.
+//!
+//! A serving volume answers the coordinators on its internal gRPC API
+//! (`grpc_addr`, over mTLS and with tokens when configured), joins the
+//! cluster through them and heartbeats (see `volume::heartbeat`), and serves
+//! clients on its HTTP API (`bind_addr`).
 
-use crate::common::{Result, WalSyncPolicy};
+use crate::common::lifecycle;
+use crate::common::reload::{start_reload_task, touched, RELOADER};
+use crate::common::shutdown::{run_listener, start_signal_task};
+use crate::common::{
+    check_peer, check_token, configure_grpc_auth, configure_grpc_tls, server_tls_config,
+    start_eviction_task, Config, RateLimiter, Result, VolumeConfig, WalSyncPolicy, WriteBudget,
+    ENCRYPTION_MANAGER, FAULTS, SHUTDOWN,
+};
+use crate::volume::async_store::AsyncBlobStore;
 use crate::volume::blob::BlobStore;
+use crate::volume::compaction::{start_compaction_task, CompactionPolicy, Compactor};
+use crate::volume::grpc::VolumeGrpcService;
+use crate::volume::heartbeat::{join_request, start_heartbeat};
+use crate::volume::http::{create_router, VolumeHttpState};
+use crate::volume::scrub::{start_scrub_task, ScrubPolicy, Scrubber};
+use crate::volume::snapshot::{snapshot_if_changed, start_snapshot_task};
+use std::future::IntoFuture;
 use std::path::PathBuf;
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
+use std::time::Duration;
+use tonic::service::interceptor::InterceptedService;
+
+/// Fields a volume applies on a configuration reload (see `common::reload`)
+const RELOADABLE: &[&str] = &[
+    "log_level",
+    "volume.compaction_interval_secs",
+    "volume.compaction_threshold",
+    "volume.compaction_dead_ratio",
+    "volume.compaction_max_bytes_per_sec",
+    "volume.grpc_auth",
+    "volume.rate_limit.enabled",
+    "volume.rate_limit.burst_size",
+    "volume.rate_limit.requests_per_second",
+];
+
+/// Apply the `fields` of a reloaded `config` that changed
+#[allow(clippy::result_large_err)]
+fn apply_reload(
+    config: &Config,
+    fields: &[String],
+    compactor: &Compactor,
+    rate_limiter: &RateLimiter,
+) -> Result<()> {
+    let volume = config.volume.clone().unwrap_or_default();
+    if touched(fields, "log_level") {
+        crate::common::logging::set_level(&config.logging.log_level)?;
+    }
+    if touched(fields, "volume.grpc_auth") {
+        configure_grpc_auth(&volume.grpc_auth)?;
+    }
+    if touched(fields, "volume.rate_limit") {
+        // The eviction task keeps its interval until restarted
+        let mut rate_limit = volume.rate_limit.clone();
+        rate_limit.eviction_interval_secs = rate_limiter.stats().config.eviction_interval_secs;
+        rate_limiter.set_config(rate_limit);
+    }
+    if fields
+        .iter()
+        .any(|field| field.starts_with("volume.compaction_"))
+    {
+        compactor.set_policy(CompactionPolicy::from_config(&volume));
+    }
+    Ok(())
+}
+
+fn default_volume_id() -> String {
+    "volume".to_string()
+}
 
 /// VolumeServer manages a single data volume.
 /// It wraps a BlobStore, which provides log-structured, append-only storage.
 pub struct VolumeServer {
-    #[allow(dead_code)]
-    store: Arc<Mutex<BlobStore>>,
+    /// Id the volume joins the cluster with
+    volume_id: String,
+    store: Arc<BlobStore>,
+    config: VolumeConfig,
+    compactor: Arc<Compactor>,
+    scrubber: Arc<Scrubber>,
 }
 
 impl VolumeServer {
@@ -20,16 +93,198 @@ impl VolumeServer {
     /// Initializes the BlobStore and WAL for this volume.
     pub fn new(data_path: PathBuf) -> Result<Self> {
         let wal_path = data_path.with_file_name("wal");
-        let store = BlobStore::open(&data_path, &wal_path, WalSyncPolicy::Always)?;
+        let store = Arc::new(BlobStore::open(
+            &data_path,
+            &wal_path,
+            WalSyncPolicy::Always,
+        )?);
+        let config = VolumeConfig {
+            data_path: data_path.into(),
+            wal_path,
+            wal_sync: WalSyncPolicy::Always,
+            ..Default::default()
+        };
+        let compactor = Arc::new(Compactor::new(
+            store.clone(),
+            CompactionPolicy::from_config(&config),
+        ));
+        let scrubber = Arc::new(Scrubber::new(
+            store.clone(),
+            ScrubPolicy::from_config(&config),
+        ));
+        Ok(Self {
+            volume_id: default_volume_id(),
+            store,
+            config,
+            compactor,
+            scrubber,
+        })
+    }
+
+    /// Create a VolumeServer with the paths, WAL sync policy, compression,
+    /// encryption and internal gRPC TLS and tokens of `config`, fetching
+    /// master keys from their providers
+    pub async fn from_config(config: &VolumeConfig) -> Result<Self> {
+        configure_grpc_tls(&config.grpc_tls)?;
+        configure_grpc_auth(&config.grpc_auth)?;
+        FAULTS.set_enabled(config.fault_injection);
+        let encryption = config.encryption.resolve_keys().await?;
+        ENCRYPTION_MANAGER
+            .write()
+            .unwrap()
+            .configure(&encryption)
+            .map_err(|e| crate::Error::InvalidConfig(e.to_string()))?;
+        let mut store =
+            BlobStore::open_dirs(&config.data_path.dirs(), &config.wal_path, config.wal_sync)?;
+        store.set_compression_config(config.compression);
+        store.set_group_commit(config.wal_group_commit);
+        store.set_cache_config(config.cache);
+        store.set_mmap_reads(config.mmap_reads);
+        store.set_segment_limits(config.segment_size_bytes, config.max_segments);
+        store.set_min_free_bytes(config.min_free_bytes);
+        let store = Arc::new(store);
+        let compactor = Arc::new(Compactor::new(
+            store.clone(),
+            CompactionPolicy::from_config(config),
+        ));
+        let scrubber = Arc::new(Scrubber::new(
+            store.clone(),
+            ScrubPolicy::from_config(config),
+        ));
         Ok(Self {
-            store: Arc::new(Mutex::new(store)),
+            volume_id: default_volume_id(),
+            store,
+            config: config.clone(),
+            compactor,
+            scrubber,
         })
     }
 
-    /// Start serving requests for this volume.
-    /// In a real deployment, this would start the gRPC/HTTP server for client requests.
+    /// Join the cluster as `volume_id` (the node id)
+    pub fn with_volume_id(mut self, volume_id: impl Into<String>) -> Self {
+        self.volume_id = volume_id.into();
+        self
+    }
+
+    /// Background compaction of this volume
+    pub fn compactor(&self) -> &Arc<Compactor> {
+        &self.compactor
+    }
+
+    /// Background scrubbing of this volume
+    pub fn scrubber(&self) -> &Arc<Scrubber> {
+        &self.scrubber
+    }
+
+    /// Start serving requests for this volume: background compaction,
+    /// snapshots and scrubbing, the internal gRPC API on `grpc_addr`,
+    /// heartbeats to the coordinators and the HTTP API on `bind_addr`.
+    /// Returns once shut down (see `common::shutdown`).
     pub async fn serve(&self) -> Result<()> {
-        println!("Volume server running...");
+        // Per-IP rate limiting of the HTTP API; idle clients are forgotten
+        let rate_limiter = Arc::new(RateLimiter::new(self.config.rate_limit.clone()));
+        let _eviction_handle = start_eviction_task(
+            vec![Box::new(rate_limiter.as_ref().clone())],
+            self.config.rate_limit.eviction_interval(),
+        );
+
+        // Apply configuration changes on SIGHUP or POST /admin/config/reload
+        match Config::try_load() {
+            Ok(running) => RELOADER.install(&running, RELOADABLE, {
+                let (compactor, rate_limiter) = (self.compactor.clone(), rate_limiter.clone());
+                move |config, fields| apply_reload(config, fields, &compactor, &rate_limiter)
+            })?,
+            Err(e) => tracing::warn!("Configuration reload unavailable: {}", e),
+        }
+        let _reload_handle = start_reload_task();
+        start_compaction_task(self.compactor.clone());
+        start_snapshot_task(self.store.clone(), &self.config);
+        start_scrub_task(self.scrubber.clone());
+        let writes = Arc::new(WriteBudget::new(self.config.write_budget));
+
+        // Internal gRPC API, with callers checked like the coordinators check theirs
+        let grpc_service = VolumeGrpcService::shared(self.store.clone())
+            .with_volume_id(self.volume_id.clone())
+            .with_io_config(self.config.io)
+            .with_write_budget(writes.clone());
+        let executor = grpc_service.command_executor();
+        let grpc_service = InterceptedService::new(grpc_service.into_server(), |request| {
+            check_peer(request).and_then(check_token)
+        });
+        let mut builder = tonic::transport::Server::builder();
+        if let Some(tls) = server_tls_config() {
+            builder = builder
+                .tls_config(tls)
+                .map_err(|e| crate::Error::InvalidConfig(e.to_string()))?;
+        }
+        // Serves 2PC commits until the end of a shutdown
+        let (stop_internal, internal_stopped) = tokio::sync::oneshot::channel::<()>();
+        let grpc_server = builder.add_service(grpc_service).serve_with_shutdown(
+            self.config.grpc_addr,
+            async move {
+                let _ = internal_stopped.await;
+            },
+        );
+        let internal = tokio::spawn(run_listener("gRPC server", grpc_server));
+        tracing::info!("Volume gRPC API: {}", self.config.grpc_addr);
+
+        let heartbeat = start_heartbeat(
+            join_request(&self.volume_id, &self.config),
+            self.config.coordinators.clone(),
+            self.config.heartbeat_interval_secs,
+            self.store.clone(),
+            executor,
+        );
+
+        let app = create_router(VolumeHttpState {
+            io: AsyncBlobStore::new(self.store.clone(), self.config.io),
+            max_blob_size: self.config.max_blob_size,
+            coordinators: self.config.coordinators.clone(),
+            writes,
+            compactor: self.compactor.clone(),
+            scrubber: self.scrubber.clone(),
+            rate_limiter,
+        });
+        let listener = tokio::net::TcpListener::bind(self.config.bind_addr).await?;
+        tracing::info!("Volume HTTP API: {}", self.config.bind_addr);
+        let _signal_handle = start_signal_task();
+        lifecycle::notify_ready();
+        let server = axum::serve(
+            listener,
+            app.into_make_service_with_connect_info::<std::net::SocketAddr>(),
+        )
+        .with_graceful_shutdown(SHUTDOWN.wait())
+        .into_future();
+        tokio::pin!(server);
+        tokio::select! {
+            res = &mut server => res?,
+            _ = SHUTDOWN.wait() => {
+                lifecycle::notify_stopping();
+                let timeout = Duration::from_secs(self.config.shutdown_timeout_secs);
+                match tokio::time::timeout(timeout, &mut server).await {
+                    Ok(res) => res?,
+                    Err(_) => tracing::warn!("Requests still in flight at the shutdown timeout"),
+                }
+            }
+        }
+        // The last heartbeat tells the coordinators the volume is going away
+        let deadline =
+            tokio::time::Instant::now() + Duration::from_secs(self.config.shutdown_timeout_secs);
+        let _ = tokio::time::timeout_at(deadline, heartbeat).await;
+        let _ = stop_internal.send(());
+        let _ = tokio::time::timeout_at(deadline, internal).await;
+        self.close()
+    }
+
+    /// Stop compacting, sync the WAL and save an index snapshot, so the next
+    /// start has little to replay
+    fn close(&self) -> Result<()> {
+        self.compactor.pause();
+        self.store.sync_wal()?;
+        if self.config.enable_snapshots {
+            snapshot_if_changed(&self.store)?;
+        }
+        tracing::info!("Volume stopped");
         Ok(())
     }
 }
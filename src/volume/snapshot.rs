@@ -0,0 +1,77 @@
+//! Periodic index snapshots
+//!
+//! Every `snapshot_interval_secs`, the index and bloom filter are saved if
+//! any key was written or deleted since the last snapshot (see
+//! `BlobStore::save_snapshot`). On restart the store loads both and only
+//! replays the WAL, instead of rebuilding the filter from every key.
+
+use crate::common::{Result, VolumeConfig};
+use crate::volume::blob::BlobStore;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Save a snapshot of `store` if keys changed since the last one. Returns
+/// whether one was saved.
+pub fn snapshot_if_changed(store: &BlobStore) -> Result<bool> {
+    if !store.changed_since_snapshot() {
+        return Ok(false);
+    }
+    store.save_snapshot()?;
+    Ok(true)
+}
+
+/// Spawn the task snapshotting `store` as configured
+pub fn start_snapshot_task(
+    store: Arc<BlobStore>,
+    config: &VolumeConfig,
+) -> tokio::task::JoinHandle<()> {
+    let interval_secs = if config.enable_snapshots {
+        config.snapshot_interval_secs
+    } else {
+        0
+    };
+    tokio::spawn(async move {
+        if interval_secs == 0 {
+            return;
+        }
+        let mut ticker = tokio::time::interval(Duration::from_secs(interval_secs));
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            let store = store.clone();
+            match tokio::task::spawn_blocking(move || snapshot_if_changed(&store)).await {
+                Ok(Ok(true)) => tracing::debug!("Saved index snapshot"),
+                Ok(Ok(false)) => {}
+                Ok(Err(e)) => tracing::warn!("Index snapshot failed: {}", e),
+                Err(e) => tracing::error!("Snapshot task panicked: {}", e),
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::WalSyncPolicy;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_snapshot_if_changed() {
+        let dir = tempdir().unwrap();
+        let store = BlobStore::open(
+            &dir.path().join("data"),
+            &dir.path().join("wal"),
+            WalSyncPolicy::Never,
+        )
+        .unwrap();
+
+        assert!(!snapshot_if_changed(&store).unwrap());
+        store.put("a", b"one").unwrap();
+        assert!(snapshot_if_changed(&store).unwrap());
+        assert!(dir.path().join("data/index.snap").exists());
+        assert!(!snapshot_if_changed(&store).unwrap());
+
+        store.delete("a").unwrap();
+        assert!(snapshot_if_changed(&store).unwrap());
+    }
+}
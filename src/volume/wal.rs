@@ -3,17 +3,45 @@
 //! Ensures durability by writing operations to a log before applying them.
 //! WAL format: [MAGIC][SEQUENCE][OP][KEY_LEN][VALUE_LEN][KEY][VALUE][CRC32]
 //!
+//! With encryption at rest enabled, PUT values are sealed with the WAL key and
+//! logged under their own op code, so a log may mix encrypted and plaintext
+//! entries.
+//!
 //! This module provides append-only logging for all write and delete operations.
 //! On recovery, the log is replayed to restore the latest state.
-
-use crate::common::{crc32, Error, Result, WalSyncPolicy};
+//!
+//! With `WalSyncPolicy::Group`, appends only reach the OS; a background
+//! thread fsyncs them in batches (group commit) and writers wait for their
+//! entries to be durable with the `WalCommit` of their append, after letting
+//! go of the store so others can join the batch.
+//!
+//! `Wal::inspect` walks a log as stored, without decrypting it, and reports
+//! every entry with its checksum status (`minikv-volume wal dump`).
+
+use crate::common::{
+    crc32, EncryptedData, Error, GroupCommitConfig, Result, WalSyncPolicy, ENCRYPTION_MANAGER,
+    FAULTS, METRICS,
+};
+use serde::Serialize;
 use std::fs::{File, OpenOptions};
 use std::io::{BufReader, BufWriter, Read, Write};
 use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
 
 const WAL_MAGIC: [u8; 4] = [0x57, 0x41, 0x4C, 0x31]; // "WAL1"
 const OP_PUT: u8 = 1;
 const OP_DELETE: u8 = 2;
+/// PUT whose value is encrypted
+const OP_PUT_ENCRYPTED: u8 = 3;
+
+/// Slow the fsync about to run down, when fault injection asks for it
+fn fsync_fault() {
+    if let Some(delay) = FAULTS.wal_fsync_delay() {
+        std::thread::sleep(delay);
+    }
+}
 
 /// WAL entry
 /// Represents a single operation in the log, either a write (Put) or a delete.
@@ -29,6 +57,190 @@ pub enum WalOp {
     Delete { key: String },
 }
 
+/// A WAL entry as stored, reported by `Wal::inspect`
+#[derive(Debug, Clone, Serialize)]
+pub struct WalRecord {
+    /// Byte offset of the entry in the log
+    pub offset: u64,
+    pub sequence: u64,
+    /// `put`, `put_encrypted`, `delete`, or `unknown(<code>)`
+    pub op: String,
+    /// Key, with invalid UTF-8 replaced
+    pub key: String,
+    /// Size of the value as logged (sealed, for encrypted entries)
+    pub value_size: u32,
+    /// Whether the entry matches its CRC32
+    pub crc_ok: bool,
+}
+
+/// Outcome of `Wal::inspect`
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct WalScan {
+    /// Entries read
+    pub entries: u64,
+    /// Entries failing their CRC32
+    pub corrupted: u64,
+    /// Bytes of the log made of whole entries
+    pub valid_bytes: u64,
+    /// Why reading stopped before the end of the file (a torn or garbled entry)
+    pub stopped: Option<String>,
+}
+
+/// An entry as read from the log, before its checksum is enforced
+struct RawEntry {
+    sequence: u64,
+    op: u8,
+    key: Vec<u8>,
+    value_len: u32,
+    /// `None` for deletes, whose value is not logged
+    value: Option<Vec<u8>>,
+    crc_ok: bool,
+}
+
+impl RawEntry {
+    /// Bytes the entry takes in the log
+    fn len(&self) -> u64 {
+        let value = self.value.as_ref().map_or(0, Vec::len);
+        (WAL_MAGIC.len() + 8 + 1 + 4 + 4 + self.key.len() + value + 4) as u64
+    }
+}
+
+/// Durable progress of a group-committed WAL
+#[derive(Debug, Clone, Default)]
+struct SyncProgress {
+    /// Appends made durable so far
+    synced: u64,
+    /// Set once an fsync failed; nothing appended since can be trusted
+    error: Option<String>,
+}
+
+#[derive(Debug)]
+struct GroupState {
+    config: GroupCommitConfig,
+    /// Appends written to the file so far
+    appended: u64,
+    /// Appends covered by the last fsync
+    synced: u64,
+    closed: bool,
+}
+
+/// Shared by a group-committed `Wal`, its syncer thread and the writers
+/// waiting on a `WalCommit`
+#[derive(Debug)]
+struct GroupCommit {
+    file: File,
+    state: Mutex<GroupState>,
+    /// Wakes the syncer on appends and on close
+    pending: Condvar,
+    progress: watch::Sender<SyncProgress>,
+}
+
+impl GroupCommit {
+    fn start(file: File, config: GroupCommitConfig) -> Arc<Self> {
+        let group = Arc::new(Self {
+            file,
+            state: Mutex::new(GroupState {
+                config,
+                appended: 0,
+                synced: 0,
+                closed: false,
+            }),
+            pending: Condvar::new(),
+            progress: watch::Sender::new(SyncProgress::default()),
+        });
+        let syncer = group.clone();
+        std::thread::Builder::new()
+            .name("wal-group-commit".into())
+            .spawn(move || syncer.run())
+            .expect("cannot spawn the WAL group commit thread");
+        group
+    }
+
+    /// Count an append already written to the file; returns its ticket
+    fn appended(&self) -> u64 {
+        let mut state = self.state.lock().unwrap();
+        state.appended += 1;
+        self.pending.notify_one();
+        state.appended
+    }
+
+    /// Mark every append so far durable, after an explicit sync
+    fn mark_synced(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.synced = state.appended;
+        let synced = state.synced;
+        self.progress
+            .send_modify(|p| p.synced = p.synced.max(synced));
+    }
+
+    fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.pending.notify_one();
+    }
+
+    /// Syncer loop: wait for appends, let a batch build up, fsync it
+    fn run(&self) {
+        loop {
+            let mut state = self.state.lock().unwrap();
+            while state.appended == state.synced && !state.closed {
+                state = self.pending.wait(state).unwrap();
+            }
+            if state.appended == state.synced {
+                return;
+            }
+            let deadline = Instant::now() + Duration::from_millis(state.config.interval_ms);
+            while state.appended - state.synced < state.config.max_batch as u64 && !state.closed {
+                let now = Instant::now();
+                if now >= deadline {
+                    break;
+                }
+                state = self.pending.wait_timeout(state, deadline - now).unwrap().0;
+            }
+            let target = state.appended;
+            drop(state);
+
+            let started = Instant::now();
+            fsync_fault();
+            let result = self.file.sync_data();
+            METRICS.record_wal_sync("group", started.elapsed());
+
+            self.state.lock().unwrap().synced = target;
+            self.progress.send_modify(|progress| match result {
+                Ok(()) => progress.synced = target,
+                Err(e) => {
+                    tracing::error!("WAL group commit fsync failed: {}", e);
+                    progress.error.get_or_insert(e.to_string());
+                }
+            });
+        }
+    }
+}
+
+/// Handle on the durability of the entries appended so far
+#[derive(Debug, Clone)]
+pub struct WalCommit {
+    /// `None` when appends are synced (or not) as they are written
+    group: Option<(watch::Receiver<SyncProgress>, u64)>,
+}
+
+impl WalCommit {
+    /// Wait until the entries are on disk. Immediate unless the WAL is
+    /// group-committed.
+    pub async fn wait(self) -> Result<()> {
+        let Some((mut progress, ticket)) = self.group else {
+            return Ok(());
+        };
+        let progress = progress
+            .wait_for(|p| p.synced >= ticket || p.error.is_some())
+            .await
+            .map_err(|_| Error::Wal("WAL closed before its entries were synced".into()))?;
+        match &progress.error {
+            Some(e) => Err(Error::Wal(format!("WAL fsync failed: {}", e))),
+            None => Ok(()),
+        }
+    }
+}
+
 /// Write-Ahead Log
 /// Main WAL structure. Handles appending operations and syncing to disk.
 pub struct Wal {
@@ -36,6 +248,10 @@ pub struct Wal {
     writer: BufWriter<File>,
     next_sequence: u64,
     sync_policy: WalSyncPolicy,
+    /// Background fsyncs of `WalSyncPolicy::Group`
+    group: Option<Arc<GroupCommit>>,
+    /// Ticket of the last append, for `commit`
+    last_ticket: u64,
 }
 
 impl Wal {
@@ -58,14 +274,41 @@ impl Wal {
         // Find last sequence number by reading entire log
         let next_sequence = Self::find_last_sequence(&path)?;
 
+        let group = match sync_policy {
+            WalSyncPolicy::Group => Some(GroupCommit::start(
+                file.try_clone()?,
+                GroupCommitConfig::default(),
+            )),
+            _ => None,
+        };
+
         Ok(Self {
             path,
             writer: BufWriter::new(file),
             next_sequence,
             sync_policy,
+            group,
+            last_ticket: 0,
         })
     }
 
+    /// Set the batching of group commit (no effect under other policies)
+    pub fn set_group_commit(&mut self, config: GroupCommitConfig) {
+        if let Some(group) = &self.group {
+            group.state.lock().unwrap().config = config;
+        }
+    }
+
+    /// Durability of the entries appended so far
+    pub fn commit(&self) -> WalCommit {
+        WalCommit {
+            group: self
+                .group
+                .as_ref()
+                .map(|group| (group.progress.subscribe(), self.last_ticket)),
+        }
+    }
+
     /// Find the last sequence number in the WAL.
     /// Used during WAL open to determine where to resume.
     fn find_last_sequence(path: &Path) -> Result<u64> {
@@ -97,7 +340,18 @@ impl Wal {
         let sequence = self.next_sequence;
         self.next_sequence += 1;
 
-        self.write_entry(sequence, OP_PUT, key, Some(value))?;
+        let encrypted = {
+            let encryption = ENCRYPTION_MANAGER.read().unwrap();
+            if encryption.is_enabled() {
+                Some(encryption.encrypt_wal(value)?.to_bytes())
+            } else {
+                None
+            }
+        };
+        match encrypted {
+            Some(value) => self.write_entry(sequence, OP_PUT_ENCRYPTED, key, Some(&value))?,
+            None => self.write_entry(sequence, OP_PUT, key, Some(value))?,
+        }
         self.maybe_sync()?;
 
         Ok(sequence)
@@ -138,7 +392,7 @@ impl Wal {
 
         // Write payload
         self.writer.write_all(key_bytes)?;
-        if op == OP_PUT {
+        if op != OP_DELETE {
             self.writer.write_all(val_bytes)?;
         }
 
@@ -149,7 +403,7 @@ impl Wal {
         checksum_data.extend_from_slice(&(key_bytes.len() as u32).to_le_bytes());
         checksum_data.extend_from_slice(&(val_bytes.len() as u32).to_le_bytes());
         checksum_data.extend_from_slice(key_bytes);
-        if op == OP_PUT {
+        if op != OP_DELETE {
             checksum_data.extend_from_slice(val_bytes);
         }
 
@@ -164,12 +418,22 @@ impl Wal {
         match self.sync_policy {
             WalSyncPolicy::Always => {
                 self.writer.flush()?;
+                let started = Instant::now();
+                fsync_fault();
                 self.writer.get_ref().sync_all()?;
+                METRICS.record_wal_sync("always", started.elapsed());
             }
             WalSyncPolicy::Interval => {
                 self.writer.flush()?;
             }
             WalSyncPolicy::Never => {}
+            WalSyncPolicy::Group => {
+                // The syncer fsyncs what the file holds
+                self.writer.flush()?;
+                if let Some(group) = &self.group {
+                    self.last_ticket = group.appended();
+                }
+            }
         }
         Ok(())
     }
@@ -201,8 +465,83 @@ impl Wal {
         Ok(())
     }
 
+    /// Read every entry of the log at `path` as stored, without decrypting
+    /// values, passing each to `callback`. Entries failing their checksum are
+    /// reported and skipped over; reading stops at the first entry that
+    /// can't be framed.
+    pub fn inspect<F>(path: impl AsRef<Path>, mut callback: F) -> Result<WalScan>
+    where
+        F: FnMut(WalRecord),
+    {
+        let mut reader = BufReader::new(File::open(path.as_ref())?);
+        let mut scan = WalScan::default();
+        loop {
+            let raw = match Self::read_raw_entry(&mut reader) {
+                Ok(Some(raw)) => raw,
+                Ok(None) => break,
+                Err(e) => {
+                    scan.stopped = Some(format!("at offset {}: {}", scan.valid_bytes, e));
+                    break;
+                }
+            };
+            let op = match raw.op {
+                OP_PUT => "put".to_string(),
+                OP_PUT_ENCRYPTED => "put_encrypted".to_string(),
+                OP_DELETE => "delete".to_string(),
+                other => format!("unknown({})", other),
+            };
+            scan.entries += 1;
+            if !raw.crc_ok {
+                scan.corrupted += 1;
+            }
+            callback(WalRecord {
+                offset: scan.valid_bytes,
+                sequence: raw.sequence,
+                op,
+                key: String::from_utf8_lossy(&raw.key).into_owned(),
+                value_size: raw.value_len,
+                crc_ok: raw.crc_ok,
+            });
+            scan.valid_bytes += raw.len();
+        }
+        Ok(scan)
+    }
+
     /// Read a single entry from the WAL
     fn read_entry_internal<R: Read>(reader: &mut R) -> Result<Option<WalEntry>> {
+        let Some(raw) = Self::read_raw_entry(reader)? else {
+            return Ok(None);
+        };
+        if !raw.crc_ok {
+            return Err(Error::Wal("Checksum mismatch".into()));
+        }
+        let key =
+            String::from_utf8(raw.key).map_err(|_| Error::Wal("Invalid UTF-8 in key".into()))?;
+
+        let wal_op = match raw.op {
+            OP_PUT => WalOp::Put {
+                key,
+                value: raw.value.unwrap(),
+            },
+            OP_PUT_ENCRYPTED => {
+                let encrypted = EncryptedData::from_bytes(&raw.value.unwrap())?;
+                WalOp::Put {
+                    key,
+                    value: ENCRYPTION_MANAGER.read().unwrap().decrypt_wal(&encrypted)?,
+                }
+            }
+            OP_DELETE => WalOp::Delete { key },
+            other => return Err(Error::Wal(format!("Unknown op code: {}", other))),
+        };
+
+        Ok(Some(WalEntry {
+            sequence: raw.sequence,
+            op: wal_op,
+        }))
+    }
+
+    /// Read the framing, payload and checksum of a single entry
+    fn read_raw_entry<R: Read>(reader: &mut R) -> Result<Option<RawEntry>> {
         // Read magic
         let mut magic = [0u8; 4];
         match reader.read_exact(&mut magic) {
@@ -235,13 +574,11 @@ impl Wal {
         let val_len = u32::from_le_bytes(val_len_bytes) as usize;
 
         // Read key
-        let mut key_bytes = vec![0u8; key_len];
-        reader.read_exact(&mut key_bytes)?;
-        let key =
-            String::from_utf8(key_bytes).map_err(|_| Error::Wal("Invalid UTF-8 in key".into()))?;
+        let mut key = vec![0u8; key_len];
+        reader.read_exact(&mut key)?;
 
         // Read value
-        let value = if op[0] == OP_PUT {
+        let value = if op[0] != OP_DELETE {
             let mut val = vec![0u8; val_len];
             reader.read_exact(&mut val)?;
             Some(val)
@@ -260,28 +597,18 @@ impl Wal {
         checksum_data.push(op[0]);
         checksum_data.extend_from_slice(&key_len_bytes);
         checksum_data.extend_from_slice(&val_len_bytes);
-        checksum_data.extend_from_slice(key.as_bytes());
+        checksum_data.extend_from_slice(&key);
         if let Some(ref v) = value {
             checksum_data.extend_from_slice(v);
         }
 
-        let computed_checksum = crc32(&checksum_data);
-        if computed_checksum != stored_checksum {
-            return Err(Error::Wal("Checksum mismatch".into()));
-        }
-
-        let wal_op = match op[0] {
-            OP_PUT => WalOp::Put {
-                key,
-                value: value.unwrap(),
-            },
-            OP_DELETE => WalOp::Delete { key },
-            _ => return Err(Error::Wal(format!("Unknown op code: {}", op[0]))),
-        };
-
-        Ok(Some(WalEntry {
+        Ok(Some(RawEntry {
             sequence,
-            op: wal_op,
+            op: op[0],
+            key,
+            value_len: val_len as u32,
+            value,
+            crc_ok: crc32(&checksum_data) == stored_checksum,
         }))
     }
 
@@ -305,14 +632,48 @@ impl Wal {
         Ok(())
     }
 
+    /// Size of the log in bytes, including writes still buffered
+    pub fn size_bytes(&self) -> u64 {
+        let on_disk = self
+            .writer
+            .get_ref()
+            .metadata()
+            .map(|m| m.len())
+            .unwrap_or(0);
+        on_disk + self.writer.buffer().len() as u64
+    }
+
+    /// Fails if the log can't be written to: buffered writes fail to flush,
+    /// or the file can no longer be opened for appending (removed, disk
+    /// remounted read-only, ...)
+    pub fn check_writable(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        OpenOptions::new().append(true).open(&self.path)?;
+        Ok(())
+    }
+
     /// Sync to disk
     pub fn sync(&mut self) -> Result<()> {
         self.writer.flush()?;
+        fsync_fault();
         self.writer.get_ref().sync_all()?;
+        if let Some(group) = &self.group {
+            group.mark_synced();
+        }
         Ok(())
     }
 }
 
+impl Drop for Wal {
+    fn drop(&mut self) {
+        // The syncer flushes what is pending, then exits
+        let _ = self.writer.flush();
+        if let Some(group) = &self.group {
+            group.close();
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -391,4 +752,109 @@ mod tests {
 
         assert_eq!(count, 3);
     }
+
+    #[tokio::test]
+    async fn test_group_commit() {
+        let dir = tempdir().unwrap();
+        let wal_path = dir.path().join("group.wal");
+        let mut wal = Wal::open(&wal_path, WalSyncPolicy::Group).unwrap();
+        wal.set_group_commit(GroupCommitConfig {
+            max_batch: 3,
+            interval_ms: 10_000,
+        });
+
+        // A full batch is synced without waiting for the interval
+        let mut commits = Vec::new();
+        for i in 0..3 {
+            wal.append_put(&format!("key{}", i), b"value").unwrap();
+            commits.push(wal.commit());
+        }
+        let synced = tokio::time::timeout(Duration::from_secs(5), async {
+            for commit in commits {
+                commit.wait().await.unwrap();
+            }
+        });
+        assert!(synced.await.is_ok());
+
+        // A lone entry waits for the interval, unless synced explicitly
+        wal.set_group_commit(GroupCommitConfig {
+            max_batch: 64,
+            interval_ms: 1,
+        });
+        wal.append_delete("key0").unwrap();
+        wal.commit().wait().await.unwrap();
+        wal.append_delete("key1").unwrap();
+        wal.sync().unwrap();
+        wal.commit().wait().await.unwrap();
+
+        let mut count = 0;
+        Wal::replay(&wal_path, |_| {
+            count += 1;
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(count, 5);
+
+        // Commits of other policies are immediate
+        let always = Wal::open(dir.path().join("always.wal"), WalSyncPolicy::Always).unwrap();
+        always.commit().wait().await.unwrap();
+    }
+
+    #[test]
+    fn test_wal_inspect() {
+        let dir = tempdir().unwrap();
+        let wal_path = dir.path().join("inspect.wal");
+        {
+            let mut wal = Wal::open(&wal_path, WalSyncPolicy::Always).unwrap();
+            wal.append_put("key1", b"value1").unwrap();
+            wal.append_put("key2", b"value2").unwrap();
+            wal.append_delete("key1").unwrap();
+        }
+
+        // Garble the value of the second entry, then tear a fourth one
+        let mut bytes = std::fs::read(&wal_path).unwrap();
+        let second = bytes.windows(6).position(|w| w == b"value2").unwrap();
+        bytes[second] ^= 0xff;
+        bytes.extend_from_slice(&WAL_MAGIC);
+        bytes.extend_from_slice(&[0, 1]);
+        std::fs::write(&wal_path, &bytes).unwrap();
+
+        let mut records = Vec::new();
+        let scan = Wal::inspect(&wal_path, |record| records.push(record)).unwrap();
+        assert_eq!(scan.entries, 3);
+        assert_eq!(scan.corrupted, 1);
+        assert_eq!(scan.valid_bytes, bytes.len() as u64 - 6);
+        assert!(scan.stopped.is_some());
+
+        let ops: Vec<_> = records.iter().map(|r| r.op.as_str()).collect();
+        assert_eq!(ops, vec!["put", "put", "delete"]);
+        assert!(records[0].crc_ok && !records[1].crc_ok && records[2].crc_ok);
+        assert_eq!(records[1].key, "key2");
+        assert_eq!(records[1].value_size, 6);
+        assert_eq!(records[0].offset, 0);
+        assert_eq!(records[1].offset, 4 + 8 + 1 + 4 + 4 + 4 + 6 + 4);
+
+        // Replay still stops at the garbled entry
+        let mut count = 0;
+        Wal::replay(&wal_path, |_| {
+            count += 1;
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    fn test_wal_size() {
+        let dir = tempdir().unwrap();
+        let mut wal = Wal::open(dir.path().join("size.wal"), WalSyncPolicy::Never).unwrap();
+        assert_eq!(wal.size_bytes(), 0);
+
+        wal.append_put("key1", b"value1").unwrap();
+        let size = wal.size_bytes();
+        assert!(size > 0);
+
+        wal.truncate().unwrap();
+        assert_eq!(wal.size_bytes(), 0);
+    }
 }
@@ -0,0 +1,171 @@
+//! Admin listings of volumes and of the key metadata of every tenant, which
+//! `ops verify` walks, the count of under-replicated keys `minikv status`
+//! shows and the usage breakdown of `minikv du`
+
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use minikv::common::{
+    IpFilter, NodeState, RateLimitConfig, RateLimiter, WriteBudget, WriteBudgetConfig,
+};
+use minikv::coordinator::http::{create_router, CoordState};
+use minikv::coordinator::metadata::{KeyMetadata, KeyState, MetadataStore, VolumeMetadata};
+use minikv::coordinator::placement::PlacementManager;
+use minikv::coordinator::raft_node::RaftNode;
+use std::sync::{Arc, Mutex};
+use tempfile::TempDir;
+use tower::ServiceExt;
+
+fn setup(dir: &TempDir) -> (axum::Router, Arc<MetadataStore>) {
+    let metadata = Arc::new(MetadataStore::open(dir.path().join("meta.db")).unwrap());
+    let raft = Arc::new(RaftNode::open("coord-1".to_string(), metadata.clone()).unwrap());
+    raft.become_leader();
+    let router = create_router(CoordState {
+        metadata: metadata.clone(),
+        placement: Arc::new(Mutex::new(PlacementManager::new(16, 1))),
+        raft,
+        auth: Default::default(),
+        rate_limiter: Arc::new(RateLimiter::new(RateLimitConfig::default())),
+        ip_filter: Arc::new(IpFilter::default()),
+        writes: Arc::new(WriteBudget::new(WriteBudgetConfig::default())),
+    });
+    (router, metadata)
+}
+
+fn key(key: &str) -> KeyMetadata {
+    KeyMetadata {
+        key: key.to_string(),
+        replicas: vec!["vol-1".to_string()],
+        size: 1,
+        blake3: String::new(),
+        created_at: 0,
+        updated_at: 0,
+        state: KeyState::Active,
+        tags: Default::default(),
+        blob: None,
+        chunks: None,
+    }
+}
+
+async fn get(router: &axum::Router, uri: &str) -> (StatusCode, serde_json::Value) {
+    let request = Request::get(uri).body(Body::empty()).unwrap();
+    let resp = router.clone().oneshot(request).await.unwrap();
+    let status = resp.status();
+    let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    (status, serde_json::from_slice(&body).unwrap())
+}
+
+fn volume(state: NodeState) -> VolumeMetadata {
+    VolumeMetadata {
+        volume_id: "vol-1".to_string(),
+        address: "http://127.0.0.1:1".to_string(),
+        grpc_address: "http://127.0.0.1:2".to_string(),
+        state,
+        shards: vec![],
+        total_keys: 0,
+        total_bytes: 0,
+        free_bytes: 0,
+        last_heartbeat: 0,
+        zone: None,
+        rack: None,
+        weight: None,
+        wal_error: None,
+    }
+}
+
+#[tokio::test]
+async fn test_list_volumes() {
+    let dir = TempDir::new().unwrap();
+    let (router, metadata) = setup(&dir);
+    metadata.put_volume(&volume(NodeState::Dead)).unwrap();
+
+    let (status, body) = get(&router, "/admin/volumes").await;
+    assert_eq!(status, StatusCode::OK);
+    let volumes = body["volumes"].as_array().unwrap();
+    assert_eq!(volumes.len(), 1);
+    assert_eq!(volumes[0]["grpc_address"], "http://127.0.0.1:2");
+}
+
+#[tokio::test]
+async fn test_list_metadata_spans_tenants() {
+    let dir = TempDir::new().unwrap();
+    let (router, metadata) = setup(&dir);
+    for name in [".cas/acme/abc", "acme/a", "default/b"] {
+        metadata.put_key(&key(name)).unwrap();
+    }
+
+    let (status, body) = get(&router, "/admin/metadata/keys?limit=2&stale=true").await;
+    assert_eq!(status, StatusCode::OK);
+    let names: Vec<_> = body["keys"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|k| k["key"].as_str().unwrap().to_string())
+        .collect();
+    assert_eq!(names, vec![".cas/acme/abc", "acme/a"]);
+    assert_eq!(body["next_after"], "acme/a");
+
+    let (_, body) = get(
+        &router,
+        "/admin/metadata/keys?limit=2&after=acme/a&stale=true",
+    )
+    .await;
+    assert_eq!(body["keys"][0]["key"], "default/b");
+    assert!(body["next_after"].is_null());
+}
+
+#[tokio::test]
+async fn test_pending_repair() {
+    let dir = TempDir::new().unwrap();
+    let (router, metadata) = setup(&dir);
+    metadata.put_volume(&volume(NodeState::Alive)).unwrap();
+    metadata.put_key(&key("acme/a")).unwrap();
+    // Its only replica is on a volume that isn't registered
+    let mut lost = key("acme/b");
+    lost.replicas = vec!["vol-9".to_string()];
+    metadata.put_key(&lost).unwrap();
+
+    let (status, body) = get(&router, "/admin/repair/pending").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["replicas"], 1);
+    assert_eq!(body["keys"], 1);
+    assert_eq!(body["bytes"], 1);
+
+    let (_, body) = get(&router, "/admin/repair/pending?replicas=2").await;
+    assert_eq!(body["keys"], 2);
+    assert_eq!(body["bytes"], 3);
+}
+
+#[tokio::test]
+async fn test_metadata_usage() {
+    let dir = TempDir::new().unwrap();
+    let (router, metadata) = setup(&dir);
+    for name in [
+        ".cas/acme/abc",
+        "acme/a",
+        "acme/logs/1",
+        "acme/logs/2",
+        "globex/b",
+    ] {
+        metadata.put_key(&key(name)).unwrap();
+    }
+
+    let (status, body) = get(&router, "/admin/metadata/usage").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["by"], "tenant");
+    assert_eq!(body["usage"][0]["prefix"], "acme/");
+    assert_eq!(body["usage"][0]["keys"], 3);
+    assert_eq!(body["usage"][1]["prefix"], "globex/");
+    assert_eq!(body["total_keys"], 4);
+
+    let (_, body) = get(
+        &router,
+        "/admin/metadata/usage?by=prefix&depth=1&prefix=acme/",
+    )
+    .await;
+    assert_eq!(body["usage"][0]["prefix"], "acme/logs/");
+    assert_eq!(body["usage"][0]["bytes"], 2);
+    assert_eq!(body["usage"][1]["prefix"], "acme/");
+    assert_eq!(body["total_keys"], 3);
+}
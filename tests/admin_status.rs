@@ -17,41 +17,58 @@ fn get_free_port() -> u16 {
         .port()
 }
 
-/// Launch the minikv-coord server in the background, returns (Child, http_port, grpc_port)
-fn start_server() -> (Child, u16, u16) {
+/// A minikv-coord server, killed when dropped so a failing test leaves
+/// no process behind
+struct Server(Child);
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+/// Launch a minikv-coord server named `name` in the background, with its
+/// own config, data directory and log; returns (Server, http_port, grpc_port)
+fn start_server(name: &str) -> (Server, u16, u16) {
     // Free ports
     let http_port = get_free_port();
     let grpc_port = get_free_port();
+    let id = format!("coord-{}", name);
+    let data_dir = format!("{}-data", id);
+    let config = format!("{}.toml", id);
     // Remove data directory to avoid RocksDB locks
-    let _ = std::fs::remove_dir_all("coord-test-data");
-    let _ = std::fs::create_dir_all("coord-test-data");
-    // Write a minimal config.toml file
+    let _ = std::fs::remove_dir_all(&data_dir);
+    let _ = std::fs::create_dir_all(&data_dir);
+    // Write a minimal config file
     std::fs::write(
-        "config.toml",
-        "node_id = 'coord-test'\nrole = 'coordinator'\n",
+        &config,
+        format!("node_id = '{}'\nrole = 'coordinator'\n", id),
     )
-    .expect("Failed to write config.toml");
+    .expect("Failed to write the config file");
     let mut cmd = Command::new(
         env::var("CARGO_BIN_EXE_minikv-coord")
             .expect("CARGO_BIN_EXE_minikv-coord not set by cargo test"),
     );
     cmd.args([
         "serve",
+        "--config",
+        &config,
         "--id",
-        "coord-test",
+        &id,
         "--bind",
         &format!("127.0.0.1:{}", http_port),
         "--grpc",
         &format!("127.0.0.1:{}", grpc_port),
         "--db",
-        "./coord-test-data",
+        &data_dir,
     ]);
-    let log = std::fs::File::create("coord-test.log").expect("Failed to create log file");
+    let log = std::fs::File::create(format!("{}.log", id)).expect("Failed to create log file");
     let log_err = log.try_clone().expect("Failed to clone log file");
     cmd.stdout(Stdio::from(log));
     cmd.stderr(Stdio::from(log_err));
     let child = cmd.spawn().expect("Failed to launch minikv-coord server");
-    (child, http_port, grpc_port)
+    (Server(child), http_port, grpc_port)
 }
 
 /// Wait until the HTTP endpoint is ready (timeout 15s)
@@ -83,16 +100,24 @@ async fn wait_for_server(child: &mut Child, http_port: u16) {
     }
 }
 
+/// Whether the server binary is available, logging why `test` is skipped
+fn server_built(test: &str) -> bool {
+    let built = std::env::var("CARGO_BIN_EXE_minikv-coord").is_ok();
+    if !built {
+        eprintln!("Skipping {}: CARGO_BIN_EXE_minikv-coord not set", test);
+    }
+    built
+}
+
 #[tokio::test]
 async fn test_admin_status() {
-    if std::env::var("CARGO_BIN_EXE_minikv-coord").is_err() {
-        eprintln!("Skipping test_admin_status: CARGO_BIN_EXE_minikv-coord not set");
+    if !server_built("test_admin_status") {
         return;
     }
     // Start the server
-    let (mut server, http_port, _grpc_port) = start_server();
+    let (mut server, http_port, _grpc_port) = start_server("status");
     // Wait until it is ready
-    wait_for_server(&mut server, http_port).await;
+    wait_for_server(&mut server.0, http_port).await;
 
     let client = Client::new();
     let url = format!("http://localhost:{}/admin/status", http_port);
@@ -109,8 +134,52 @@ async fn test_admin_status() {
     assert!(json.get("nb_peers").is_some());
     assert!(json.get("nb_volumes").is_some());
     assert!(json.get("nb_s3_objects").is_some());
+}
 
-    // Stop the server
-    let _ = server.kill();
-    let _ = server.wait();
+#[tokio::test]
+async fn test_metrics() {
+    if !server_built("test_metrics") {
+        return;
+    }
+    let (mut server, http_port, _grpc_port) = start_server("metrics");
+    // Readiness is polled on /admin/status, which /metrics then counts
+    wait_for_server(&mut server.0, http_port).await;
+
+    // Requests show up in /metrics under their route, next to the cluster gauges
+    let metrics = Client::new()
+        .get(format!("http://localhost:{}/metrics", http_port))
+        .send()
+        .await
+        .expect("Metrics request failed")
+        .text()
+        .await
+        .expect("Failed to read metrics body");
+    assert!(metrics.contains("minikv_endpoint_requests_total{path=\"/admin/status\"}"));
+    assert!(metrics.contains("minikv_raft_term "));
+    assert!(metrics.contains("minikv_healthy_volumes "));
+}
+
+#[tokio::test]
+async fn test_sigterm_exits_cleanly() {
+    if !server_built("test_sigterm_exits_cleanly") {
+        return;
+    }
+    let (mut server, http_port, _grpc_port) = start_server("sigterm");
+    wait_for_server(&mut server.0, http_port).await;
+
+    // SIGTERM shuts the server down cleanly, well within its timeout
+    unsafe {
+        libc::kill(server.0.id() as libc::pid_t, libc::SIGTERM);
+    }
+    let start = Instant::now();
+    let status = loop {
+        if let Some(status) = server.0.try_wait().expect("Error waiting for server") {
+            break status;
+        }
+        if start.elapsed() > Duration::from_secs(15) {
+            panic!("minikv-coord did not stop on SIGTERM");
+        }
+        sleep(Duration::from_millis(100));
+    };
+    assert!(status.success(), "minikv-coord exited with {}", status);
 }
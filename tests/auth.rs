@@ -0,0 +1,386 @@
+//! Authentication and role checks on the coordinator HTTP API.
+//! API keys live in the process-wide `KEY_STORE`, and key changes go through
+//! the process-wide `WATCH_CHANNEL`, hence a test binary of its own.
+
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use minikv::common::auth::{Role, KEY_STORE};
+use minikv::common::{
+    AuditConfig, AuditEntry, AuthConfig, IpFilter, RateLimitConfig, RateLimiter, WriteBudget,
+    WriteBudgetConfig, AUDIT_LOGGER,
+};
+use minikv::coordinator::http::{create_router, CoordState, KeyChangeEvent, WATCH_CHANNEL};
+use minikv::coordinator::metadata::MetadataStore;
+use minikv::coordinator::placement::PlacementManager;
+use minikv::coordinator::raft_node::RaftNode;
+use std::sync::{Arc, Mutex, RwLock};
+use tempfile::TempDir;
+use tower::ServiceExt;
+
+/// A single-node coordinator router, leading so admin commands commit
+fn router(dir: &TempDir, auth: AuthConfig) -> axum::Router {
+    let metadata = Arc::new(MetadataStore::open(dir.path().join("meta.db")).unwrap());
+    let raft = Arc::new(RaftNode::open("coord-1".to_string(), metadata.clone()).unwrap());
+    raft.become_leader();
+    create_router(CoordState {
+        metadata,
+        placement: Arc::new(Mutex::new(PlacementManager::new(16, 1))),
+        raft,
+        auth: Arc::new(RwLock::new(auth)),
+        rate_limiter: Arc::new(RateLimiter::new(RateLimitConfig::default())),
+        ip_filter: Arc::new(IpFilter::default()),
+        writes: Arc::new(WriteBudget::new(WriteBudgetConfig::default())),
+    })
+}
+
+async fn send(
+    router: &axum::Router,
+    method: &str,
+    uri: &str,
+    key: Option<&str>,
+    body: &str,
+) -> (StatusCode, String) {
+    let mut request = Request::builder().method(method).uri(uri);
+    if let Some(key) = key {
+        request = request.header("Authorization", format!("ApiKey {}", key));
+    }
+    if !body.is_empty() {
+        request = request.header("Content-Type", "application/json");
+    }
+    let resp = router
+        .clone()
+        .oneshot(request.body(Body::from(body.to_string())).unwrap())
+        .await
+        .unwrap();
+    let status = resp.status();
+    let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    (status, String::from_utf8_lossy(&body).to_string())
+}
+
+fn is_denied(status: StatusCode) -> bool {
+    status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN
+}
+
+#[tokio::test]
+async fn test_roles_enforced() {
+    let dir = TempDir::new().unwrap();
+    let router = router(
+        &dir,
+        AuthConfig {
+            enabled: true,
+            ..Default::default()
+        },
+    );
+    let (_, admin) = KEY_STORE
+        .generate_key("bootstrap", "default", Role::Admin, None)
+        .unwrap();
+    let (_, reader) = KEY_STORE
+        .generate_key("reader", "default", Role::ReadOnly, None)
+        .unwrap();
+
+    // Public paths and anonymous reads
+    assert_eq!(
+        send(&router, "GET", "/health", None, "").await.0,
+        StatusCode::OK
+    );
+    assert!(!is_denied(
+        send(&router, "GET", "/some-key", None, "").await.0
+    ));
+
+    // Anonymous or invalid credentials
+    let create = r#"{"name":"ci","tenant":"acme","role":"read_write"}"#;
+    assert_eq!(
+        send(&router, "POST", "/admin/keys", None, create).await.0,
+        StatusCode::UNAUTHORIZED
+    );
+    assert_eq!(
+        send(&router, "GET", "/admin/status", None, "").await.0,
+        StatusCode::FORBIDDEN
+    );
+    assert_eq!(
+        send(&router, "POST", "/some-key", Some("mkv_bogus_key"), "x")
+            .await
+            .0,
+        StatusCode::UNAUTHORIZED
+    );
+
+    // A read-only key can read, but neither write nor administer
+    assert!(!is_denied(
+        send(&router, "GET", "/some-key", Some(&reader), "").await.0
+    ));
+    for (method, uri) in [
+        ("POST", "/some-key"),
+        ("DELETE", "/some-key"),
+        ("PUT", "/s3/bucket/object"),
+        ("POST", "/batch"),
+        ("GET", "/admin/keys"),
+        ("POST", "/admin/compact"),
+    ] {
+        assert_eq!(
+            send(&router, method, uri, Some(&reader), "").await.0,
+            StatusCode::FORBIDDEN,
+            "{} {}",
+            method,
+            uri
+        );
+    }
+
+    // An admin key manages keys through the API
+    let (status, body) = send(&router, "POST", "/admin/keys", Some(&admin), create).await;
+    assert_eq!(status, StatusCode::CREATED, "{}", body);
+    let created: serde_json::Value = serde_json::from_str(&body).unwrap();
+    let writer = created["key"].as_str().unwrap().to_string();
+    let writer_id = created["id"].as_str().unwrap().to_string();
+    let (status, body) = send(&router, "GET", "/admin/keys?tenant=acme", Some(&admin), "").await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.contains(&writer_id));
+
+    // The new key may write (whether the write succeeds is another matter
+    // without volumes), but not administer
+    assert!(!is_denied(
+        send(&router, "DELETE", "/some-key", Some(&writer), "")
+            .await
+            .0
+    ));
+    assert_eq!(
+        send(&router, "GET", "/admin/keys", Some(&writer), "")
+            .await
+            .0,
+        StatusCode::FORBIDDEN
+    );
+
+    // Once revoked it is refused
+    let uri = format!("/admin/keys/{}/revoke", writer_id);
+    assert_eq!(
+        send(&router, "POST", &uri, Some(&admin), "").await.0,
+        StatusCode::OK
+    );
+    assert_eq!(
+        send(&router, "DELETE", "/some-key", Some(&writer), "")
+            .await
+            .0,
+        StatusCode::UNAUTHORIZED
+    );
+}
+
+#[tokio::test]
+async fn test_auth_disabled() {
+    let dir = TempDir::new().unwrap();
+    let router = router(&dir, AuthConfig::default());
+
+    let (status, _) = send(&router, "GET", "/admin/keys", None, "").await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(!is_denied(
+        send(&router, "POST", "/some-key", None, "x").await.0
+    ));
+}
+
+#[tokio::test]
+async fn test_tenant_isolation() {
+    let dir = TempDir::new().unwrap();
+    let router = router(
+        &dir,
+        AuthConfig {
+            enabled: true,
+            ..Default::default()
+        },
+    );
+    let (_, acme) = KEY_STORE
+        .generate_key("acme", "acme", Role::ReadWrite, None)
+        .unwrap();
+    let (_, globex) = KEY_STORE
+        .generate_key("globex", "globex", Role::ReadWrite, None)
+        .unwrap();
+    let (_, admin) = KEY_STORE
+        .generate_key("ops", "ops", Role::Admin, None)
+        .unwrap();
+
+    let put = r#"{"ops":[{"op":"put","key":"docs/a","value":"x"}]}"#;
+    let (status, body) = send(&router, "POST", "/batch", Some(&acme), put).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.contains(r#""ok":true"#), "{}", body);
+
+    // Stored under the tenant, listed without it
+    let (_, body) = send(&router, "GET", "/keys?prefix=docs/", Some(&acme), "").await;
+    let page: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(page["keys"][0]["key"], "docs/a");
+    let (_, body) = send(&router, "GET", "/range?start=a&end=z", Some(&acme), "").await;
+    assert_eq!(body, r#"{"keys":["docs/a"]}"#);
+    // Digests come from the metadata, though no volume holds the value
+    let uri = "/range?start=a&end=z&values=digest";
+    let (_, body) = send(&router, "GET", uri, Some(&acme), "").await;
+    let line: serde_json::Value = serde_json::from_str(body.trim()).unwrap();
+    assert_eq!(
+        line,
+        serde_json::json!({ "key": "docs/a", "size": 1, "blake3": "" })
+    );
+
+    // Invisible to another tenant
+    let (_, body) = send(&router, "GET", "/keys", Some(&globex), "").await;
+    let page: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(page["keys"], serde_json::json!([]));
+    let get = r#"{"ops":[{"op":"get","key":"docs/a"}]}"#;
+    let (_, body) = send(&router, "POST", "/batch", Some(&globex), get).await;
+    assert!(body.contains("Not found"), "{}", body);
+
+    // Which can't name it, unlike an admin
+    let request = Request::get("/keys")
+        .header("Authorization", format!("ApiKey {}", globex))
+        .header("X-Minikv-Tenant", "acme")
+        .body(Body::empty())
+        .unwrap();
+    let resp = router.clone().oneshot(request).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    let request = Request::get("/keys")
+        .header("Authorization", format!("ApiKey {}", admin))
+        .header("X-Minikv-Tenant", "acme")
+        .body(Body::empty())
+        .unwrap();
+    let resp = router.clone().oneshot(request).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    assert!(String::from_utf8_lossy(&body).contains("docs/a"));
+}
+
+#[tokio::test]
+async fn test_audit_log() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("audit.log");
+    AUDIT_LOGGER
+        .configure(&AuditConfig {
+            path: path.to_string_lossy().into_owned(),
+            ..Default::default()
+        })
+        .unwrap();
+    let router = router(
+        &dir,
+        AuthConfig {
+            enabled: true,
+            ..Default::default()
+        },
+    );
+    let (admin_id, admin) = KEY_STORE
+        .generate_key("auditor", "audited", Role::Admin, None)
+        .unwrap();
+    let (reader_id, reader) = KEY_STORE
+        .generate_key("audit-reader", "audited", Role::ReadOnly, None)
+        .unwrap();
+
+    send(&router, "POST", "/admin/compact", Some(&reader), "").await;
+    send(
+        &router,
+        "POST",
+        "/audited-key",
+        Some("mkv_audit_bogus"),
+        "x",
+    )
+    .await;
+    let create = r#"{"name":"audit-ci","tenant":"audited","role":"read_only"}"#;
+    let (_, body) = send(&router, "POST", "/admin/keys", Some(&admin), create).await;
+    let created: serde_json::Value = serde_json::from_str(&body).unwrap();
+    let created_id = created["id"].as_str().unwrap().to_string();
+    let uri = format!("/admin/keys/{}/revoke", created_id);
+    send(&router, "POST", &uri, Some(&admin), "").await;
+    let delete = r#"{"ops":[{"op":"delete","key":"gone"}]}"#;
+    send(&router, "POST", "/batch", Some(&admin), delete).await;
+
+    // Other tests of this binary may log too: look for ours
+    let entries: Vec<AuditEntry> = std::fs::read_to_string(&path)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let logged = |event: &str, actor: &str, target: &str| {
+        entries.iter().any(|e| {
+            format!("{:?}", e.event) == event
+                && e.actor == actor
+                && e.target.as_deref() == Some(target)
+        })
+    };
+    assert!(logged("AuthFailure", &reader_id, "/admin/compact"));
+    assert!(logged("AuthFailure", "anonymous", "/audited-key"));
+    assert!(logged("ApiKeyCreated", &admin_id, &created_id));
+    assert!(logged("ApiKeyRevoked", &admin_id, &created_id));
+    assert!(logged("DataDelete", &admin_id, "audited/gone"));
+}
+
+/// Keys of the changes a watch stream shows `key`'s caller, read until one
+/// on `last` arrives
+async fn watched(
+    router: &axum::Router,
+    key: &str,
+    send_events: impl FnOnce(),
+    last: &str,
+) -> Vec<String> {
+    use futures_util::StreamExt;
+
+    let request = Request::get("/watch/sse")
+        .header("Authorization", format!("ApiKey {}", key))
+        .body(Body::empty())
+        .unwrap();
+    let resp = router.clone().oneshot(request).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    send_events();
+
+    let mut body = resp.into_body().into_data_stream();
+    let mut keys = Vec::new();
+    while !keys.iter().any(|k| k == last) {
+        let frame = tokio::time::timeout(std::time::Duration::from_secs(5), body.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        for line in String::from_utf8_lossy(&frame).lines() {
+            if let Some(data) = line.strip_prefix("data:") {
+                let event: KeyChangeEvent = serde_json::from_str(data.trim()).unwrap();
+                keys.push(event.key);
+            }
+        }
+    }
+    keys
+}
+
+#[tokio::test]
+async fn test_watch_scoped_to_tenant() {
+    let dir = TempDir::new().unwrap();
+    let router = router(
+        &dir,
+        AuthConfig {
+            enabled: true,
+            ..Default::default()
+        },
+    );
+    let (_, initech) = KEY_STORE
+        .generate_key("initech", "initech", Role::ReadOnly, None)
+        .unwrap();
+    let (_, admin) = KEY_STORE
+        .generate_key("ops-watch", "ops", Role::Admin, None)
+        .unwrap();
+    let change = |key: &str, tenant: Option<&str>| {
+        let _ = WATCH_CHANNEL.send(KeyChangeEvent {
+            event: "put".to_string(),
+            key: key.to_string(),
+            tenant: tenant.map(str::to_string),
+            timestamp: 0,
+        });
+    };
+    let send_events = || {
+        change("globex-secret", Some("globex"));
+        change("watched-api-key", None);
+        change("initech-doc", Some("initech"));
+    };
+
+    // A tenant sees its own keys, not others' nor API key changes
+    let keys = watched(&router, &initech, send_events, "initech-doc").await;
+    assert_eq!(keys, vec!["initech-doc"]);
+
+    // An admin naming no tenant sees them all
+    let keys = watched(&router, &admin, send_events, "initech-doc").await;
+    for key in ["globex-secret", "watched-api-key", "initech-doc"] {
+        assert!(keys.iter().any(|k| k == key), "{:?}", keys);
+    }
+}
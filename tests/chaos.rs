@@ -0,0 +1,137 @@
+//! Fault injection through the volume HTTP API. Faults and internal tokens
+//! are process-wide (`FAULTS`), hence a test binary of its own.
+
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use minikv::common::{
+    configure_grpc_auth, AttachToken, GrpcAuthConfig, RateLimiter, StoreIoConfig, VolumeConfig,
+    WalSyncPolicy, WriteBudget, FAULTS,
+};
+use minikv::volume::async_store::AsyncBlobStore;
+use minikv::volume::blob::BlobStore;
+use minikv::volume::compaction::{CompactionPolicy, Compactor};
+use minikv::volume::http::{create_router, VolumeHttpState};
+use minikv::volume::scrub::{ScrubPolicy, Scrubber};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+use tonic::service::Interceptor;
+use tower::ServiceExt;
+
+fn router(store: Arc<BlobStore>) -> axum::Router {
+    let config = VolumeConfig::default();
+    create_router(VolumeHttpState {
+        io: AsyncBlobStore::new(store.clone(), StoreIoConfig::default()),
+        max_blob_size: config.max_blob_size,
+        coordinators: vec![],
+        writes: Arc::new(WriteBudget::new(config.write_budget)),
+        compactor: Arc::new(Compactor::new(
+            store.clone(),
+            CompactionPolicy::from_config(&config),
+        )),
+        scrubber: Arc::new(Scrubber::new(store, ScrubPolicy::from_config(&config))),
+        rate_limiter: Arc::new(RateLimiter::new(config.rate_limit.clone())),
+    })
+}
+
+async fn send(router: &axum::Router, method: &str, uri: &str, body: &str) -> (StatusCode, String) {
+    let request = Request::builder()
+        .method(method)
+        .uri(uri)
+        .header("Content-Type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap();
+    let resp = router.clone().oneshot(request).await.unwrap();
+    let status = resp.status();
+    let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    (status, String::from_utf8_lossy(&body).to_string())
+}
+
+#[tokio::test]
+async fn test_fault_injection() {
+    let dir = TempDir::new().unwrap();
+    let store = Arc::new(
+        BlobStore::open(
+            &dir.path().join("data"),
+            &dir.path().join("wal"),
+            WalSyncPolicy::Always,
+        )
+        .unwrap(),
+    );
+    let router = router(store.clone());
+    let faults = r#"{"corrupt_records": 1, "kill_compactions": 1, "wal_fsync_delay_ms": 50}"#;
+
+    // Refused unless the node allows it
+    let (status, _) = send(&router, "PUT", "/admin/chaos", faults).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    FAULTS.set_enabled(true);
+    let (status, body) = send(&router, "PUT", "/admin/chaos", faults).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+
+    let started = Instant::now();
+    let (status, _) = send(&router, "PUT", "/blob/acme/corrupt", "first").await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(started.elapsed() >= Duration::from_millis(50));
+    let (status, _) = send(&router, "PUT", "/blob/acme/intact", "second").await;
+    assert_eq!(status, StatusCode::OK);
+
+    // Only the first record written fails its checksum
+    let (status, _) = send(&router, "GET", "/blob/acme/corrupt", "").await;
+    assert_ne!(status, StatusCode::OK);
+    let (status, body) = send(&router, "GET", "/blob/acme/intact", "").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, "second");
+
+    // The first compaction dies mid-run, leaving the store as it was
+    assert!(store.compact().is_err());
+    assert_eq!(store.get("acme/intact").unwrap().unwrap(), b"second");
+    store.compact().unwrap();
+    assert_eq!(store.get("acme/intact").unwrap().unwrap(), b"second");
+
+    let (_, body) = send(&router, "GET", "/admin/chaos", "").await;
+    let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(body["faults"]["corrupt_records"], 0);
+    assert_eq!(body["faults"]["kill_compactions"], 0);
+    assert_eq!(body["faults"]["wal_fsync_delay_ms"], 50);
+
+    let (status, _) = send(&router, "PUT", "/admin/chaos", r#"{"grpc_drop_rate": 1.0}"#).await;
+    assert_eq!(status, StatusCode::OK);
+    let dropped = AttachToken.call(tonic::Request::new(())).unwrap_err();
+    assert_eq!(dropped.code(), tonic::Code::Unavailable);
+
+    let (status, body) = send(&router, "DELETE", "/admin/chaos", "").await;
+    assert_eq!(status, StatusCode::OK);
+    let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(body["faults"]["grpc_drop_rate"], 0.0);
+    assert!(AttachToken.call(tonic::Request::new(())).is_ok());
+
+    // With internal tokens, admin routes that change state need one
+    configure_grpc_auth(&GrpcAuthConfig {
+        enabled: true,
+        tokens: vec!["secret".to_string()],
+        token: None,
+    })
+    .unwrap();
+    for (method, uri) in [
+        ("PUT", "/admin/chaos"),
+        ("DELETE", "/admin/chaos"),
+        ("POST", "/admin/compaction/pause"),
+        ("POST", "/admin/compaction/resume"),
+        ("POST", "/admin/config/reload"),
+    ] {
+        let (status, _) = send(&router, method, uri, "{}").await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED, "{} {}", method, uri);
+    }
+    let request = Request::builder()
+        .method("PUT")
+        .uri("/admin/chaos")
+        .header("Content-Type", "application/json")
+        .header("Authorization", "Bearer secret")
+        .body(Body::from("{}"))
+        .unwrap();
+    let resp = router.clone().oneshot(request).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+}
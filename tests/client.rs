@@ -0,0 +1,169 @@
+//! `minikv::client` against a leader, with a volume, and a follower serving
+//! HTTP on local ports. Key changes go through the process-wide
+//! `WATCH_CHANNEL`, hence a test binary of its own.
+
+use futures_util::StreamExt;
+use minikv::client::{BatchOp, MiniKvClient};
+use minikv::common::raft::AppendRequest;
+use minikv::common::{
+    IpFilter, NodeState, RateLimitConfig, RateLimiter, WalSyncPolicy, WriteBudget,
+    WriteBudgetConfig,
+};
+use minikv::coordinator::http::{create_router, CoordState};
+use minikv::coordinator::metadata::{MetadataStore, VolumeMetadata};
+use minikv::coordinator::placement::PlacementManager;
+use minikv::coordinator::raft_node::RaftNode;
+use minikv::volume::blob::BlobStore;
+use minikv::volume::grpc::VolumeGrpcService;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tempfile::TempDir;
+use tonic::transport::server::TcpIncoming;
+
+/// Serve a coordinator HTTP API on `listener`
+fn serve(listener: tokio::net::TcpListener, raft: Arc<RaftNode>, metadata: Arc<MetadataStore>) {
+    let mut placement = PlacementManager::new(16, 1);
+    placement.rebalance(&metadata.get_healthy_volumes().unwrap());
+    let router = create_router(CoordState {
+        metadata,
+        placement: Arc::new(Mutex::new(placement)),
+        raft,
+        auth: Default::default(),
+        rate_limiter: Arc::new(RateLimiter::new(RateLimitConfig::default())),
+        ip_filter: Arc::new(IpFilter::default()),
+        writes: Arc::new(WriteBudget::new(WriteBudgetConfig::default())),
+    });
+    tokio::spawn(async move {
+        axum::serve(
+            listener,
+            router.into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .await
+        .unwrap();
+    });
+}
+
+/// Serve a volume's internal gRPC API on a local port and register it with `metadata`
+async fn start_volume(dir: &TempDir, metadata: &MetadataStore) {
+    let store = BlobStore::open(
+        &dir.path().join("vol-1").join("data"),
+        &dir.path().join("vol-1").join("wal"),
+        WalSyncPolicy::Always,
+    )
+    .unwrap();
+    let service = VolumeGrpcService::new(store).with_volume_id("vol-1");
+    let (listener, addr) = listen().await;
+    tokio::spawn(async move {
+        tonic::transport::Server::builder()
+            .add_service(service.into_server())
+            .serve_with_incoming(TcpIncoming::from_listener(listener, true, None).unwrap())
+            .await
+            .unwrap();
+    });
+    metadata
+        .put_volume(&VolumeMetadata {
+            volume_id: "vol-1".to_string(),
+            address: addr.clone(),
+            grpc_address: addr,
+            state: NodeState::Alive,
+            shards: vec![],
+            total_keys: 0,
+            total_bytes: 0,
+            free_bytes: 0,
+            last_heartbeat: minikv::common::timestamp_now(),
+            zone: None,
+            rack: None,
+            weight: None,
+            wal_error: None,
+        })
+        .unwrap();
+}
+
+async fn listen() -> (tokio::net::TcpListener, String) {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    (listener, url)
+}
+
+#[tokio::test]
+async fn test_client_follows_leader() {
+    let dir = TempDir::new().unwrap();
+    let (listener, leader_url) = listen().await;
+    let metadata = Arc::new(MetadataStore::open(dir.path().join("leader.db")).unwrap());
+    let leader = Arc::new(
+        RaftNode::open("coord-1".to_string(), metadata.clone())
+            .unwrap()
+            .with_advertise_addr(leader_url.clone()),
+    );
+    leader.become_leader();
+    start_volume(&dir, &metadata).await;
+    serve(listener, leader, metadata);
+
+    // The follower heard from the leader, so it redirects writes there
+    let metadata = Arc::new(MetadataStore::open(dir.path().join("follower.db")).unwrap());
+    let follower = Arc::new(RaftNode::open("coord-2".to_string(), metadata.clone()).unwrap());
+    follower.handle_append_entries(AppendRequest {
+        term: 0,
+        leader_id: "coord-1".to_string(),
+        prev_log_index: 0,
+        prev_log_term: 0,
+        entries: vec![],
+        leader_commit: 0,
+        leader_addr: leader_url.clone(),
+    });
+    let (listener, follower_url) = listen().await;
+    serve(listener, follower, metadata);
+
+    // Nothing listens on the first endpoint
+    let client = MiniKvClient::new(["http://127.0.0.1:1", follower_url.as_str()])
+        .with_retries(5, Duration::from_millis(10));
+
+    // Reads needing the leader's ReadIndex are redirected too
+    let page = client.list("", None, 10).await.unwrap();
+    assert!(page.keys.is_empty());
+    assert_eq!(client.endpoint(), leader_url);
+
+    let results = client
+        .batch(&[
+            BatchOp::Put {
+                key: "a".into(),
+                value: "1".into(),
+            },
+            BatchOp::Put {
+                key: "b".into(),
+                value: "2".into(),
+            },
+            BatchOp::Get { key: "a".into() },
+        ])
+        .await
+        .unwrap();
+    assert!(results.iter().all(|r| r.ok), "{:?}", results);
+    assert!(results[2].value.is_some());
+
+    let page = client.list("", None, 10).await.unwrap();
+    let keys: Vec<_> = page.keys.iter().map(|k| k.key.as_str()).collect();
+    assert_eq!(keys, vec!["a", "b"]);
+
+    let page = client.list("", Some("a"), 10).await.unwrap();
+    assert_eq!(page.keys.len(), 1);
+    assert_eq!(page.keys[0].key, "b");
+
+    client.delete("a").await.unwrap();
+    let err = client.delete("missing").await.unwrap_err();
+    assert!(matches!(err, minikv::Error::NotFound(_)), "{}", err);
+    assert!(client.get("nothing").await.unwrap().is_none());
+
+    let mut events = client.watch().await.unwrap();
+    client.put("watched", "value").await.unwrap();
+    client.delete("watched").await.unwrap();
+    for expected in ["put", "delete"] {
+        let event = tokio::time::timeout(Duration::from_secs(5), events.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert_eq!(event.key, "watched");
+        assert_eq!(event.event, expected);
+    }
+}
@@ -0,0 +1,212 @@
+//! etcd v3 JSON gateway requests against the leader of a simulated cluster
+//! (`minikv::sim`)
+
+use axum::body::{Body, BodyDataStream};
+use axum::http::{Method, Request, StatusCode};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use futures_util::StreamExt;
+use minikv::coordinator::loopback::{self, Caller};
+use minikv::sim::{Sim, SimConfig};
+use serde_json::{json, Value};
+use std::time::Duration;
+use tempfile::TempDir;
+use tower::ServiceExt;
+
+const ELECTION_WAIT: Duration = Duration::from_secs(10);
+
+fn b64(s: &str) -> String {
+    BASE64.encode(s)
+}
+
+async fn post(router: &axum::Router, uri: &str, body: Value) -> (StatusCode, Value) {
+    let answer = loopback::call(
+        router,
+        &Caller::default(),
+        Method::POST,
+        uri.to_string(),
+        Body::from(body.to_string()),
+    )
+    .await;
+    (answer.status, serde_json::from_slice(&answer.body).unwrap())
+}
+
+/// Keys of a range over `[key, range_end)`, decoded
+async fn range(router: &axum::Router, key: &str, range_end: &str) -> Vec<String> {
+    let (status, resp) = post(
+        router,
+        "/v3/kv/range",
+        json!({ "key": b64(key), "range_end": b64(range_end) }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", resp);
+    resp["kvs"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|kv| {
+            let key = BASE64.decode(kv["key"].as_str().unwrap()).unwrap();
+            String::from_utf8(key).unwrap()
+        })
+        .collect()
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_etcd_kv() {
+    let dir = TempDir::new().unwrap();
+    let sim = Sim::start(dir.path(), SimConfig::default()).unwrap();
+    let leader = sim.wait_for_leader(ELECTION_WAIT).await.unwrap();
+    let router = &sim.coordinator(&leader).router;
+
+    for key in ["config/a", "config/b", "other"] {
+        let (status, _) = post(
+            router,
+            "/v3/kv/put",
+            json!({ "key": b64(key), "value": b64("1") }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+    }
+    let (_, resp) = post(
+        router,
+        "/v3/kv/put",
+        json!({ "key": b64("config/a"), "value": b64("2"), "prev_kv": true }),
+    )
+    .await;
+    assert_eq!(resp["prev_kv"]["value"], b64("1"));
+    assert_eq!(resp["prev_kv"]["version"], "1");
+
+    let (_, resp) = post(router, "/v3/kv/range", json!({ "key": b64("config/a") })).await;
+    let kv = &resp["kvs"][0];
+    assert_eq!(kv["value"], b64("2"));
+    assert_eq!(kv["version"], "2");
+    assert_eq!(resp["header"]["revision"], kv["mod_revision"]);
+    assert_eq!(
+        range(router, "config/", "config0").await,
+        ["config/a", "config/b"]
+    );
+    assert_eq!(
+        range(router, "\0", "\0").await,
+        ["config/a", "config/b", "other"]
+    );
+
+    let (_, resp) = post(
+        router,
+        "/v3/kv/range",
+        json!({ "key": b64("\0"), "range_end": b64("\0"), "limit": "1", "count_only": true }),
+    )
+    .await;
+    assert_eq!((&resp["count"], &resp["more"]), (&json!("3"), &json!(true)));
+    assert!(resp.get("kvs").is_none());
+
+    let (_, resp) = post(
+        router,
+        "/v3/kv/deleterange",
+        json!({ "key": b64("config/"), "range_end": b64("config0") }),
+    )
+    .await;
+    assert_eq!(resp["deleted"], "2");
+    assert_eq!(range(router, "\0", "\0").await, ["other"]);
+
+    let (status, resp) = post(router, "/v3/kv/put", json!({ "value": b64("1") })).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(resp["code"], 3);
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_etcd_lease() {
+    let dir = TempDir::new().unwrap();
+    let sim = Sim::start(dir.path(), SimConfig::default()).unwrap();
+    let leader = sim.wait_for_leader(ELECTION_WAIT).await.unwrap();
+    let router = &sim.coordinator(&leader).router;
+
+    let (status, resp) = post(router, "/v3/lease/grant", json!({ "TTL": "60" })).await;
+    assert_eq!(status, StatusCode::OK, "{}", resp);
+    let lease = resp["ID"].as_str().unwrap().to_string();
+    let (status, _) = post(
+        router,
+        "/v3/kv/put",
+        json!({ "key": b64("node/1"), "value": b64("up"), "lease": lease }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, resp) = post(
+        router,
+        "/v3/kv/put",
+        json!({ "key": b64("node/2"), "value": b64("up"), "lease": "12345" }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(resp["code"], 5);
+
+    // Kept alive past its first deadline
+    sim.advance(Duration::from_secs(40)).await;
+    let (_, resp) = post(router, "/v3/lease/keepalive", json!({ "ID": lease })).await;
+    assert_eq!(resp["result"]["TTL"], "60");
+    sim.advance(Duration::from_secs(40)).await;
+    assert_eq!(range(router, "node/1", "").await, ["node/1"]);
+    let (_, resp) = post(
+        router,
+        "/v3/lease/timetolive",
+        json!({ "ID": lease, "keys": true }),
+    )
+    .await;
+    assert_eq!(resp["grantedTTL"], "60");
+    assert_eq!(resp["keys"], json!([b64("node/1")]));
+
+    // Then left to expire, with its keys
+    sim.advance(Duration::from_secs(30)).await;
+    assert!(range(router, "node/1", "").await.is_empty());
+    let (_, resp) = post(router, "/v3/lease/timetolive", json!({ "ID": lease })).await;
+    assert_eq!(resp["TTL"], "-1");
+}
+
+/// The `result` of the next message of a watch
+async fn next(lines: &mut BodyDataStream) -> Value {
+    let line = lines.next().await.unwrap().unwrap();
+    serde_json::from_slice::<Value>(&line).unwrap()["result"].clone()
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_etcd_watch() {
+    let dir = TempDir::new().unwrap();
+    let sim = Sim::start(dir.path(), SimConfig::default()).unwrap();
+    let leader = sim.wait_for_leader(ELECTION_WAIT).await.unwrap();
+    let router = &sim.coordinator(&leader).router;
+
+    let request = Request::post("/v3/watch")
+        .body(Body::from(
+            json!({ "create_request": { "key": b64("config/"), "range_end": b64("config0") } })
+                .to_string(),
+        ))
+        .unwrap();
+    let resp = router.clone().oneshot(request).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let mut lines = resp.into_body().into_data_stream();
+    assert_eq!(next(&mut lines).await["created"], true);
+
+    post(
+        router,
+        "/v3/kv/put",
+        json!({ "key": b64("other"), "value": b64("1") }),
+    )
+    .await;
+    post(
+        router,
+        "/v3/kv/put",
+        json!({ "key": b64("config/a"), "value": b64("1") }),
+    )
+    .await;
+    post(
+        router,
+        "/v3/kv/deleterange",
+        json!({ "key": b64("config/a") }),
+    )
+    .await;
+
+    let put = next(&mut lines).await;
+    assert_eq!(put["events"][0]["kv"]["key"], b64("config/a"));
+    assert!(put["events"][0].get("type").is_none());
+    let delete = next(&mut lines).await;
+    assert_eq!(delete["events"][0]["type"], "DELETE");
+    assert_eq!(delete["events"][0]["kv"]["key"], b64("config/a"));
+}
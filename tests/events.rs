@@ -0,0 +1,119 @@
+//! `GET /admin/events` on the leader of a simulated cluster (`minikv::sim`)
+
+use axum::body::{Body, BodyDataStream};
+use axum::http::{Request, StatusCode};
+use futures_util::StreamExt;
+use minikv::coordinator::events::{self, ClusterEvent};
+use minikv::sim::{Sim, SimConfig};
+use serde_json::Value;
+use std::time::Duration;
+use tempfile::TempDir;
+use tower::ServiceExt;
+
+const ELECTION_WAIT: Duration = Duration::from_secs(10);
+
+/// An SSE event: its id, type and data
+struct Frame {
+    id: String,
+    event: String,
+    data: Value,
+}
+
+/// Open `GET /admin/events` with `last_event_id`, if any
+async fn subscribe(router: &axum::Router, uri: &str, last_event_id: Option<&str>) -> Frames {
+    let mut request = Request::get(uri);
+    if let Some(id) = last_event_id {
+        request = request.header("last-event-id", id);
+    }
+    let resp = router
+        .clone()
+        .oneshot(request.body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers()["content-type"], "text/event-stream");
+    Frames {
+        body: resp.into_body().into_data_stream(),
+        buf: String::new(),
+    }
+}
+
+struct Frames {
+    body: BodyDataStream,
+    buf: String,
+}
+
+impl Frames {
+    /// The next event, skipping keep-alive comments
+    async fn next(&mut self) -> Frame {
+        loop {
+            if let Some(end) = self.buf.find("\n\n") {
+                let frame: String = self.buf.drain(..end + 2).collect();
+                let field = |name: &str| {
+                    frame
+                        .lines()
+                        .find_map(|line| line.strip_prefix(&format!("{}: ", name)))
+                        .map(str::to_string)
+                };
+                let Some(data) = field("data") else {
+                    continue;
+                };
+                return Frame {
+                    id: field("id").unwrap(),
+                    event: field("event").unwrap(),
+                    data: serde_json::from_str(&data).unwrap(),
+                };
+            }
+            let chunk = self.body.next().await.unwrap().unwrap();
+            self.buf.push_str(std::str::from_utf8(&chunk).unwrap());
+        }
+    }
+
+    /// The next event of type `event`
+    async fn next_of(&mut self, event: &str) -> Frame {
+        loop {
+            let frame = self.next().await;
+            if frame.event == event {
+                return frame;
+            }
+        }
+    }
+}
+
+fn joined(volume_id: &str) -> ClusterEvent {
+    ClusterEvent::VolumeJoined {
+        volume_id: volume_id.to_string(),
+        address: format!("http://{}:5000", volume_id),
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_admin_events() {
+    let dir = TempDir::new().unwrap();
+    let sim = Sim::start(dir.path(), SimConfig::default()).unwrap();
+    let leader = sim.wait_for_leader(ELECTION_WAIT).await.unwrap();
+    let router = &sim.coordinator(&leader).router;
+
+    // From the start of the buffer
+    let mut frames = subscribe(router, "/admin/events?after=0", None).await;
+    let elected = frames.next_of("leader.elected").await;
+    assert_eq!(elected.data["type"], "leader.elected");
+    assert!(elected.data["term"].as_u64().unwrap() > 0);
+
+    events::publish(joined("vol-a"));
+    let first = frames.next_of("volume.joined").await;
+    assert_eq!(first.data["volume_id"], "vol-a");
+    assert_eq!(first.id, first.data["seq"].to_string());
+
+    // A client reconnecting gets what it missed, then new events
+    events::publish(joined("vol-b"));
+    let mut frames = subscribe(router, "/admin/events", Some(&first.id)).await;
+    assert_eq!(frames.next().await.data["volume_id"], "vol-b");
+    events::publish(joined("vol-c"));
+    assert_eq!(frames.next().await.data["volume_id"], "vol-c");
+
+    // Without a position, only new events
+    let mut frames = subscribe(router, "/admin/events", None).await;
+    events::publish(joined("vol-d"));
+    assert_eq!(frames.next().await.data["volume_id"], "vol-d");
+}
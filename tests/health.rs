@@ -0,0 +1,60 @@
+//! Readiness and liveness probes of the coordinators of a simulated cluster
+//! (`minikv::sim`)
+
+use axum::body::Body;
+use axum::http::{Method, StatusCode};
+use minikv::coordinator::loopback::{self, Caller};
+use minikv::sim::{Sim, SimConfig};
+use serde_json::Value;
+use std::time::Duration;
+use tempfile::TempDir;
+
+const ELECTION_WAIT: Duration = Duration::from_secs(10);
+
+async fn get(router: &axum::Router, uri: &str) -> (StatusCode, Value) {
+    let answer = loopback::call(
+        router,
+        &Caller::default(),
+        Method::GET,
+        uri.to_string(),
+        Body::empty(),
+    )
+    .await;
+    (answer.status, serde_json::from_slice(&answer.body).unwrap())
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_ready_and_live() {
+    let dir = TempDir::new().unwrap();
+    let sim = Sim::start(dir.path(), SimConfig::default()).unwrap();
+    let leader = sim.wait_for_leader(ELECTION_WAIT).await.unwrap();
+    let coordinator = sim.coordinator(&leader);
+
+    let (status, ready) = get(&coordinator.router, "/health/ready").await;
+    assert_eq!(status, StatusCode::OK, "{}", ready);
+    let checks = &ready["checks"];
+    assert_eq!(checks["metadata"]["ok"], true);
+    assert_eq!(checks["raft_leader"]["leader_id"], leader.as_str());
+    assert_eq!(checks["volumes"]["healthy"], 3);
+    assert_eq!(checks["volume_wal"]["writable"], 3);
+
+    // No volume left that can write its WAL
+    for mut volume in coordinator.metadata.list_volumes().unwrap() {
+        volume.wal_error = Some("Read-only file system".to_string());
+        coordinator.metadata.put_volume(&volume).unwrap();
+    }
+    let (status, ready) = get(&coordinator.router, "/health/ready").await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(ready["ready"], false);
+    let checks = &ready["checks"];
+    assert_eq!(checks["volumes"]["ok"], true);
+    assert_eq!(checks["volume_wal"]["ok"], false);
+    assert_eq!(
+        checks["volume_wal"]["errors"]["v1"],
+        "Read-only file system"
+    );
+
+    let (status, live) = get(&coordinator.router, "/health/live").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(live["alive"], true);
+}
@@ -0,0 +1,83 @@
+//! Hot key reporting on the coordinator HTTP API. Accesses are counted in
+//! the process-wide `HOT_KEYS`, hence a test binary of its own.
+
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use minikv::common::{
+    HotKeysConfig, IpFilter, RateLimitConfig, RateLimiter, WriteBudget, WriteBudgetConfig,
+};
+use minikv::coordinator::hotkeys::HOT_KEYS;
+use minikv::coordinator::http::{create_router, CoordState};
+use minikv::coordinator::metadata::MetadataStore;
+use minikv::coordinator::placement::PlacementManager;
+use minikv::coordinator::raft_node::RaftNode;
+use std::sync::{Arc, Mutex};
+use tempfile::TempDir;
+use tower::ServiceExt;
+
+fn router(dir: &TempDir) -> axum::Router {
+    let metadata = Arc::new(MetadataStore::open(dir.path().join("meta.db")).unwrap());
+    let raft = Arc::new(RaftNode::open("coord-1".to_string(), metadata.clone()).unwrap());
+    raft.become_leader();
+    create_router(CoordState {
+        metadata,
+        placement: Arc::new(Mutex::new(PlacementManager::new(16, 1))),
+        raft,
+        auth: Default::default(),
+        rate_limiter: Arc::new(RateLimiter::new(RateLimitConfig::default())),
+        ip_filter: Arc::new(IpFilter::default()),
+        writes: Arc::new(WriteBudget::new(WriteBudgetConfig::default())),
+    })
+}
+
+async fn send(router: &axum::Router, method: &str, uri: &str, body: &str) -> (StatusCode, String) {
+    let request = Request::builder()
+        .method(method)
+        .uri(uri)
+        .header("Content-Type", "application/json")
+        .header("X-Minikv-Tenant", "acme")
+        .body(Body::from(body.to_string()))
+        .unwrap();
+    let resp = router.clone().oneshot(request).await.unwrap();
+    let status = resp.status();
+    let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    (status, String::from_utf8_lossy(&body).to_string())
+}
+
+#[tokio::test]
+async fn test_hotkeys() {
+    HOT_KEYS.set_config(HotKeysConfig {
+        sample_rate: 1.0,
+        ..Default::default()
+    });
+    let dir = TempDir::new().unwrap();
+    let router = router(&dir);
+
+    let put = |key: &str, value: &str| {
+        format!(
+            r#"{{"ops":[{{"op":"put","key":"{}","value":"{}"}}]}}"#,
+            key, value
+        )
+    };
+    for _ in 0..3 {
+        let (status, body) = send(&router, "POST", "/batch", &put("busy", "x")).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+    }
+    send(&router, "POST", "/batch", &put("big", &"x".repeat(100))).await;
+    let (status, _) = send(&router, "DELETE", "/busy", "").await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, body) = send(&router, "GET", "/admin/hotkeys?window=60&limit=1", "").await;
+    assert_eq!(status, StatusCode::OK);
+    let report: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(report["window_secs"], 60);
+    assert_eq!(report["tracked_keys"], 2);
+    assert_eq!(report["most_written"][0]["key"], "acme/busy");
+    assert_eq!(report["most_written"][0]["writes"], 4);
+    // Deleted, so only the other key has a size
+    assert_eq!(report["largest"][0]["key"], "acme/big");
+    assert_eq!(report["largest"][0]["size"], 100);
+    assert_eq!(report["most_read"].as_array().unwrap().len(), 0);
+}
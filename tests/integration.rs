@@ -1,6 +1,9 @@
 //! Integration tests for minikv
 
-use minikv::{common::WalSyncPolicy, volume::blob::BlobStore};
+use minikv::{
+    common::{BlobCacheConfig, CompressionConfig, CompressionMode, WalSyncPolicy},
+    volume::blob::BlobStore,
+};
 use tempfile::TempDir;
 
 #[test]
@@ -11,7 +14,7 @@ fn test_volume_persistence() {
 
     // Write data
     {
-        let mut store = BlobStore::open(&data_path, &wal_path, WalSyncPolicy::Always).unwrap();
+        let store = BlobStore::open(&data_path, &wal_path, WalSyncPolicy::Always).unwrap();
         store.put("key1", b"value1").unwrap();
         store.put("key2", b"value2").unwrap();
         store.save_snapshot().unwrap();
@@ -33,7 +36,7 @@ fn test_wal_replay() {
 
     // Write to WAL
     {
-        let mut store = BlobStore::open(&data_path, &wal_path, WalSyncPolicy::Always).unwrap();
+        let store = BlobStore::open(&data_path, &wal_path, WalSyncPolicy::Always).unwrap();
         store.put("key1", b"value1").unwrap();
     }
 
@@ -50,7 +53,7 @@ fn test_bloom_filter() {
     let data_path = dir.path().join("data");
     let wal_path = dir.path().join("wal");
 
-    let mut store = BlobStore::open(&data_path, &wal_path, WalSyncPolicy::Always).unwrap();
+    let store = BlobStore::open(&data_path, &wal_path, WalSyncPolicy::Always).unwrap();
 
     // Write keys
     for i in 0..100 {
@@ -70,7 +73,7 @@ fn test_delete() {
     let data_path = dir.path().join("data");
     let wal_path = dir.path().join("wal");
 
-    let mut store = BlobStore::open(&data_path, &wal_path, WalSyncPolicy::Always).unwrap();
+    let store = BlobStore::open(&data_path, &wal_path, WalSyncPolicy::Always).unwrap();
 
     store.put("key1", b"value1").unwrap();
     assert!(store.get("key1").unwrap().is_some());
@@ -78,3 +81,317 @@ fn test_delete() {
     store.delete("key1").unwrap();
     assert!(store.get("key1").unwrap().is_none());
 }
+
+#[test]
+fn test_blob_cache() {
+    let dir = TempDir::new().unwrap();
+    let data_path = dir.path().join("data");
+    let wal_path = dir.path().join("wal");
+
+    let mut store = BlobStore::open(&data_path, &wal_path, WalSyncPolicy::Always).unwrap();
+    store.set_cache_config(BlobCacheConfig {
+        capacity_bytes: 1024 * 1024,
+        max_entry_bytes: 1024,
+    });
+
+    store.put("acme/key1", b"value1").unwrap();
+    store.put("acme/large", &[7u8; 4096]).unwrap();
+    assert_eq!(store.get("acme/key1").unwrap().unwrap(), b"value1");
+    assert_eq!(store.get("acme/large").unwrap().unwrap().len(), 4096);
+    // Blobs above max_entry_bytes aren't cached
+    assert_eq!(store.cache().tenant_bytes("acme"), 15);
+
+    // Overwrites, deletes and compaction never serve a stale value
+    store.put("acme/key1", b"value2").unwrap();
+    assert_eq!(store.get("acme/key1").unwrap().unwrap(), b"value2");
+    store.put("acme/key2", b"other").unwrap();
+    store.get("acme/key2").unwrap();
+    store.delete("acme/key2").unwrap();
+    assert!(store.get("acme/key2").unwrap().is_none());
+    store.compact().unwrap();
+    assert_eq!(store.cache().bytes(), 0);
+    assert_eq!(store.get("acme/key1").unwrap().unwrap(), b"value2");
+}
+
+#[test]
+fn test_mmap_reads() {
+    use std::io::{Seek, SeekFrom, Write};
+
+    let dir = TempDir::new().unwrap();
+    let data_path = dir.path().join("data");
+    let wal_path = dir.path().join("wal");
+
+    let mut store = BlobStore::open(&data_path, &wal_path, WalSyncPolicy::Always).unwrap();
+    store.set_mmap_reads(true);
+    store.set_cache_config(BlobCacheConfig {
+        capacity_bytes: 0,
+        max_entry_bytes: 0,
+    });
+
+    store.put("key1", b"value1").unwrap();
+    assert_eq!(store.get("key1").unwrap().unwrap(), b"value1");
+    // Appended after the segment was mapped
+    store.put("key2", b"value2").unwrap();
+    assert_eq!(store.get("key2").unwrap().unwrap(), b"value2");
+
+    // Records read through the map are still checksummed
+    let mut segment = std::fs::OpenOptions::new()
+        .write(true)
+        .open(data_path.join("segments/seg_000000.blob"))
+        .unwrap();
+    // MAGIC(4) + FLAG(1) + KEY_LEN(4) + VAL_LEN(8) + ORIG_LEN(8) + "key1"
+    segment.seek(SeekFrom::Start(29)).unwrap();
+    segment.write_all(b"V").unwrap();
+    assert!(store.get("key1").is_err());
+    assert_eq!(store.get("key2").unwrap().unwrap(), b"value2");
+}
+
+#[test]
+fn test_range_reads() {
+    let dir = TempDir::new().unwrap();
+    let data_path = dir.path().join("data");
+    let wal_path = dir.path().join("wal");
+
+    let mut store = BlobStore::open(&data_path, &wal_path, WalSyncPolicy::Always).unwrap();
+    store.set_cache_config(BlobCacheConfig {
+        capacity_bytes: 0,
+        max_entry_bytes: 0,
+    });
+    store.put("plain", b"0123456789").unwrap();
+    assert_eq!(store.get_range("plain", 2, 4).unwrap().unwrap(), b"2345");
+    assert_eq!(store.get_range("plain", 8, 100).unwrap().unwrap(), b"89");
+    assert!(store.get_range("plain", 20, 5).unwrap().unwrap().is_empty());
+    assert!(store.get_range("missing", 0, 5).unwrap().is_none());
+
+    // Compressed records are decoded whole, then sliced
+    store.set_compression_config(CompressionConfig {
+        algorithm: CompressionMode::Zstd,
+        min_size: 0,
+        zstd_level: 3,
+    });
+    let value: Vec<u8> = (0..4096u32).map(|i| (i % 7) as u8).collect();
+    store.put("packed", &value).unwrap();
+    assert_eq!(
+        store.get_range("packed", 1000, 10).unwrap().unwrap(),
+        &value[1000..1010]
+    );
+}
+
+#[test]
+fn test_segment_limits() {
+    let dir = TempDir::new().unwrap();
+    let data_path = dir.path().join("data");
+    let wal_path = dir.path().join("wal");
+
+    {
+        let mut store = BlobStore::open(&data_path, &wal_path, WalSyncPolicy::Always).unwrap();
+        // Two 81-byte records per segment, three segments
+        store.set_segment_limits(100, 3);
+        for i in 0..6 {
+            store.put(&format!("k{}", i), &[i as u8; 50]).unwrap();
+        }
+        assert_eq!(store.stats().active_segments, 3);
+
+        let err = store.put("k6", &[6u8; 50]).unwrap_err();
+        assert!(matches!(err, minikv::Error::StorageFull(_)));
+        // Deletes still go through
+        store.delete("k0").unwrap();
+        assert!(store.get("k0").unwrap().is_none());
+    }
+
+    // The rejected put was never logged
+    let store = BlobStore::open(&data_path, &wal_path, WalSyncPolicy::Always).unwrap();
+    assert!(store.get("k6").unwrap().is_none());
+    assert!(store.get("k0").unwrap().is_none());
+    assert_eq!(store.get("k5").unwrap().unwrap(), [5u8; 50]);
+}
+
+#[test]
+fn test_bloom_false_positives() {
+    let dir = TempDir::new().unwrap();
+    let store = BlobStore::open(
+        &dir.path().join("data"),
+        &dir.path().join("wal"),
+        WalSyncPolicy::Never,
+    )
+    .unwrap();
+    assert_eq!(store.stats().bloom_capacity, 100_000);
+
+    store.put("kept", b"v").unwrap();
+    store.put("gone", b"v").unwrap();
+    store.delete("gone").unwrap();
+    assert!(store.get("kept").unwrap().is_some());
+    assert_eq!(store.stats().bloom_false_positives, 0);
+
+    // Still set in the filter, no longer in the index
+    assert!(store.get("gone").unwrap().is_none());
+    assert!(store.get_range("gone", 0, 1).unwrap().is_none());
+    assert_eq!(store.stats().bloom_false_positives, 2);
+}
+
+#[test]
+fn test_blob_listing_and_conditional_delete() {
+    let dir = TempDir::new().unwrap();
+    let data_path = dir.path().join("data");
+    let wal_path = dir.path().join("wal");
+
+    let store = BlobStore::open(&data_path, &wal_path, WalSyncPolicy::Always).unwrap();
+    for key in ["c", "a", "d", "b"] {
+        store.put(key, key.as_bytes()).unwrap();
+    }
+
+    let keys = |page: Vec<(String, String, u64)>| page.into_iter().map(|b| b.0).collect::<Vec<_>>();
+    assert_eq!(keys(store.blobs_after("", 2)), vec!["a", "b"]);
+    assert_eq!(keys(store.blobs_after("b", 2)), vec!["c", "d"]);
+    assert!(store.blobs_after("d", 2).is_empty());
+
+    // Only a blob still holding the listed value is deleted
+    let (_, hash_a, _) = store.blobs_after("", 1).remove(0);
+    store.put("a", b"rewritten").unwrap();
+    assert_eq!(store.delete_if_hash("a", &hash_a).unwrap(), None);
+    assert!(store.get("a").unwrap().is_some());
+
+    let (_, hash_b, _) = store.blobs_after("a", 1).remove(0);
+    assert!(store.delete_if_hash("b", &hash_b).unwrap().is_some());
+    assert!(store.get("b").unwrap().is_none());
+}
+
+#[test]
+fn test_compression() {
+    let dir = TempDir::new().unwrap();
+    let data_path = dir.path().join("data");
+    let wal_path = dir.path().join("wal");
+    let text = "the quick brown fox jumps over the lazy dog ".repeat(100);
+
+    {
+        let mut store = BlobStore::open(&data_path, &wal_path, WalSyncPolicy::Always).unwrap();
+        store.set_compression_config(CompressionConfig {
+            algorithm: CompressionMode::Zstd,
+            ..Default::default()
+        });
+        store.put("zstd", text.as_bytes()).unwrap();
+        store.put("small", b"tiny").unwrap();
+        store.set_compression(CompressionMode::Lz4);
+        store.put("lz4", text.as_bytes()).unwrap();
+        store.set_compression(CompressionMode::None);
+        store.put("plain", text.as_bytes()).unwrap();
+
+        let stats = store.stats();
+        assert_eq!(stats.compressed_blobs, 2);
+        assert_eq!(stats.compressed_bytes, 2 * text.len() as u64);
+        assert!(stats.compression_ratio() > 5.0);
+    }
+
+    // Every record says how it is stored, whatever the current setting
+    let store = BlobStore::open(&data_path, &wal_path, WalSyncPolicy::Always).unwrap();
+    for key in ["zstd", "lz4", "plain"] {
+        assert_eq!(store.get(key).unwrap().unwrap(), text.as_bytes());
+    }
+    assert_eq!(store.get("small").unwrap().unwrap(), b"tiny");
+    assert_eq!(store.stats().compressed_blobs, 2);
+}
+
+#[test]
+fn test_segments_spread_over_data_dirs() {
+    let dir = TempDir::new().unwrap();
+    let dirs = vec![dir.path().join("disk0"), dir.path().join("disk1")];
+    let wal_path = dir.path().join("wal");
+    let value = vec![7u8; 80];
+
+    {
+        let mut store = BlobStore::open_dirs(&dirs, &wal_path, WalSyncPolicy::Always).unwrap();
+        store.set_segment_limits(100, 0);
+        for i in 0..10 {
+            store.put(&format!("key{}", i), &value).unwrap();
+        }
+        let disks = store.disk_stats();
+        assert_eq!(disks.len(), 2);
+        assert!(disks.iter().all(|disk| !disk.failed && disk.segments >= 4));
+    }
+
+    let store = BlobStore::open_dirs(&dirs, &wal_path, WalSyncPolicy::Always).unwrap();
+    for i in 0..10 {
+        assert_eq!(store.get(&format!("key{}", i)).unwrap().unwrap(), value);
+    }
+
+    // A full compaction rewrites everything to the primary directory
+    store.compact().unwrap();
+    assert_eq!(store.disk_stats()[1].segments, 0);
+    drop(store);
+    let store = BlobStore::open_dirs(&dirs, &wal_path, WalSyncPolicy::Always).unwrap();
+    for i in 0..10 {
+        assert_eq!(store.get(&format!("key{}", i)).unwrap().unwrap(), value);
+    }
+}
+
+#[test]
+fn test_failed_data_dir() {
+    let dir = TempDir::new().unwrap();
+    let dirs = vec![dir.path().join("disk0"), dir.path().join("disk1")];
+    let wal_path = dir.path().join("wal");
+    let value = vec![7u8; 80];
+
+    {
+        let mut store = BlobStore::open_dirs(&dirs, &wal_path, WalSyncPolicy::Always).unwrap();
+        store.set_segment_limits(100, 0);
+        for i in 0..10 {
+            store.put(&format!("key{}", i), &value).unwrap();
+        }
+        store.save_snapshot().unwrap();
+    }
+    // Only the segments hold the values from here, and the second disk is gone
+    std::fs::remove_file(wal_path.join("wal.log")).unwrap();
+    std::fs::remove_dir_all(&dirs[1]).unwrap();
+    std::fs::write(&dirs[1], b"not a directory").unwrap();
+
+    let store = BlobStore::open_dirs(&dirs, &wal_path, WalSyncPolicy::Always).unwrap();
+    assert!(store.disk_stats()[1].failed);
+    let (mut found, mut unavailable) = (0, 0);
+    for i in 0..10 {
+        match store.get(&format!("key{}", i)) {
+            Ok(Some(read)) => {
+                assert_eq!(read, value);
+                found += 1;
+            }
+            Err(minikv::Error::DiskUnavailable(_)) => unavailable += 1,
+            other => panic!("unexpected read: {:?}", other),
+        }
+    }
+    assert!(found > 0 && unavailable > 0);
+
+    // Writes go on to the disk left
+    store.put("new", b"value").unwrap();
+    assert_eq!(store.get("new").unwrap().unwrap(), b"value");
+    assert!(matches!(
+        store.compact(),
+        Err(minikv::Error::DiskUnavailable(_))
+    ));
+}
+
+#[test]
+fn test_read_only_below_free_space_watermark() {
+    let dir = TempDir::new().unwrap();
+    let data_path = dir.path().join("data");
+    let wal_path = dir.path().join("wal");
+
+    let mut store = BlobStore::open(&data_path, &wal_path, WalSyncPolicy::Always).unwrap();
+    store.put("a", b"one").unwrap();
+    if store.free_bytes().is_none() {
+        return;
+    }
+
+    store.set_min_free_bytes(u64::MAX);
+    assert!(store.is_read_only());
+    assert!(matches!(
+        store.put("b", b"two"),
+        Err(minikv::Error::StorageFull(_))
+    ));
+    // Reads and deletes go on
+    assert_eq!(store.get("a").unwrap().unwrap(), b"one");
+    store.delete("a").unwrap();
+
+    store.set_min_free_bytes(0);
+    assert!(!store.is_read_only());
+    store.put("b", b"two").unwrap();
+    assert_eq!(store.get("b").unwrap().unwrap(), b"two");
+}
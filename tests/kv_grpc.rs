@@ -0,0 +1,243 @@
+//! The public `KvService` served on a local port by a leader, with a volume,
+//! and a follower. Key changes go through the process-wide `WATCH_CHANNEL`,
+//! hence a test binary of its own.
+
+use futures_util::StreamExt;
+use minikv::common::raft::AppendRequest;
+use minikv::common::{
+    IpFilter, NodeState, RateLimitConfig, RateLimiter, WalSyncPolicy, WriteBudget,
+    WriteBudgetConfig,
+};
+use minikv::coordinator::http::{create_router, CoordState};
+use minikv::coordinator::kv_grpc::KvGrpcService;
+use minikv::coordinator::metadata::{MetadataStore, VolumeMetadata};
+use minikv::coordinator::placement::PlacementManager;
+use minikv::coordinator::raft_node::RaftNode;
+use minikv::proto::batch_op::Type;
+use minikv::proto::kv_service_client::KvServiceClient;
+use minikv::proto::*;
+use minikv::volume::blob::BlobStore;
+use minikv::volume::grpc::VolumeGrpcService;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tempfile::TempDir;
+use tonic::transport::server::TcpIncoming;
+use tonic::transport::Channel;
+
+/// Serve `KvService` for a coordinator on a local port
+async fn serve(raft: Arc<RaftNode>, metadata: Arc<MetadataStore>) -> KvServiceClient<Channel> {
+    let mut placement = PlacementManager::new(16, 1);
+    placement.rebalance(&metadata.get_healthy_volumes().unwrap());
+    let router = create_router(CoordState {
+        metadata,
+        placement: Arc::new(Mutex::new(placement)),
+        raft,
+        auth: Default::default(),
+        rate_limiter: Arc::new(RateLimiter::new(RateLimitConfig::default())),
+        ip_filter: Arc::new(IpFilter::default()),
+        writes: Arc::new(WriteBudget::new(WriteBudgetConfig::default())),
+    });
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        tonic::transport::Server::builder()
+            .add_service(KvGrpcService::new(router).into_server())
+            .serve_with_incoming(TcpIncoming::from_listener(listener, true, None).unwrap())
+            .await
+            .unwrap();
+    });
+    KvServiceClient::connect(format!("http://{}", addr))
+        .await
+        .unwrap()
+}
+
+/// Serve a volume's internal gRPC API on a local port and register it with `metadata`
+async fn start_volume(dir: &TempDir, metadata: &MetadataStore) {
+    let store = BlobStore::open(
+        &dir.path().join("vol-1").join("data"),
+        &dir.path().join("vol-1").join("wal"),
+        WalSyncPolicy::Always,
+    )
+    .unwrap();
+    let service = VolumeGrpcService::new(store).with_volume_id("vol-1");
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        tonic::transport::Server::builder()
+            .add_service(service.into_server())
+            .serve_with_incoming(TcpIncoming::from_listener(listener, true, None).unwrap())
+            .await
+            .unwrap();
+    });
+    metadata
+        .put_volume(&VolumeMetadata {
+            volume_id: "vol-1".to_string(),
+            address: addr.clone(),
+            grpc_address: addr,
+            state: NodeState::Alive,
+            shards: vec![],
+            total_keys: 0,
+            total_bytes: 0,
+            free_bytes: 0,
+            last_heartbeat: minikv::common::timestamp_now(),
+            zone: None,
+            rack: None,
+            weight: None,
+            wal_error: None,
+        })
+        .unwrap();
+}
+
+fn op(kind: Type, key: &str, value: &str) -> BatchOp {
+    BatchOp {
+        r#type: kind as i32,
+        key: key.to_string(),
+        value: value.as_bytes().to_vec(),
+    }
+}
+
+#[tokio::test]
+async fn test_kv_service() {
+    let dir = TempDir::new().unwrap();
+    let metadata = Arc::new(MetadataStore::open(dir.path().join("leader.db")).unwrap());
+    let leader = Arc::new(
+        RaftNode::open("coord-1".to_string(), metadata.clone())
+            .unwrap()
+            .with_advertise_addr("http://leader:5000".to_string()),
+    );
+    leader.become_leader();
+    start_volume(&dir, &metadata).await;
+    let mut client = serve(leader, metadata).await;
+
+    let results = client
+        .batch(BatchRequest {
+            ops: vec![
+                op(Type::Put, "a", "1"),
+                op(Type::Put, "b", "2"),
+                op(Type::Get, "a", ""),
+            ],
+        })
+        .await
+        .unwrap()
+        .into_inner()
+        .results;
+    assert!(results.iter().all(|r| r.ok), "{:?}", results);
+    assert!(!results[2].value.is_empty());
+
+    let page = client
+        .list(ListKeysRequest {
+            prefix: String::new(),
+            start_after: "a".to_string(),
+            limit: 10,
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    let keys: Vec<_> = page.keys.iter().map(|k| k.key.as_str()).collect();
+    assert_eq!(keys, vec!["b"]);
+    assert!(page.next_after.is_empty());
+
+    client
+        .delete(KvDeleteRequest {
+            key: "a".to_string(),
+        })
+        .await
+        .unwrap();
+    let status = client
+        .delete(KvDeleteRequest {
+            key: "missing".to_string(),
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::NotFound);
+    let status = client
+        .get(KvGetRequest {
+            key: "missing".to_string(),
+            ..Default::default()
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::NotFound);
+
+    // Only changes under the prefix, as writes are applied
+    let mut events = client
+        .watch(WatchRequest {
+            prefix: "watched/".to_string(),
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    for key in ["other", "watched/key"] {
+        let messages = vec![
+            KvPutRequest {
+                key: key.to_string(),
+                data: b"va".to_vec(),
+                consistency: String::new(),
+            },
+            KvPutRequest {
+                data: b"lue".to_vec(),
+                ..Default::default()
+            },
+        ];
+        let resp = client
+            .put(tokio_stream::iter(messages))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(resp.size, 5);
+    }
+    client
+        .delete(KvDeleteRequest {
+            key: "watched/key".to_string(),
+        })
+        .await
+        .unwrap();
+    for expected in ["put", "delete"] {
+        let event = tokio::time::timeout(Duration::from_secs(5), events.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert_eq!(event.key, "watched/key");
+        assert_eq!(event.tenant, "default");
+        assert_eq!(event.event, expected);
+    }
+}
+
+#[tokio::test]
+async fn test_follower_names_leader() {
+    let dir = TempDir::new().unwrap();
+    let metadata = Arc::new(MetadataStore::open(dir.path().join("follower.db")).unwrap());
+    let follower = Arc::new(RaftNode::open("coord-2".to_string(), metadata.clone()).unwrap());
+    follower.handle_append_entries(AppendRequest {
+        term: 0,
+        leader_id: "coord-1".to_string(),
+        prev_log_index: 0,
+        prev_log_term: 0,
+        entries: vec![],
+        leader_commit: 0,
+        leader_addr: "http://leader:5000".to_string(),
+    });
+    let mut client = serve(follower, metadata).await;
+
+    let messages = vec![
+        KvPutRequest {
+            key: "key".to_string(),
+            data: b"va".to_vec(),
+            consistency: String::new(),
+        },
+        KvPutRequest {
+            data: b"lue".to_vec(),
+            ..Default::default()
+        },
+    ];
+    let status = client.put(tokio_stream::iter(messages)).await.unwrap_err();
+    assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+    assert_eq!(
+        status.metadata().get("leader").unwrap(),
+        "http://leader:5000"
+    );
+
+    let status = client.list(ListKeysRequest::default()).await.unwrap_err();
+    assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+}
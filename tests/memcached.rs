@@ -0,0 +1,124 @@
+//! Memcached text protocol commands against the leader of a simulated
+//! cluster (`minikv::sim`), over an in-memory connection. API keys live in
+//! the process-wide `KEY_STORE`, hence a test binary of its own.
+
+use minikv::common::auth::{Role, KEY_STORE};
+use minikv::coordinator::memcached::MemcachedServer;
+use minikv::sim::{Sim, SimConfig};
+use std::time::Duration;
+use tempfile::TempDir;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream};
+
+const ELECTION_WAIT: Duration = Duration::from_secs(10);
+
+/// Send `request` at once and check the answers are `reply`
+async fn call(conn: &mut DuplexStream, request: &str, reply: &str) {
+    conn.write_all(request.as_bytes()).await.unwrap();
+    let mut got = vec![0; reply.len()];
+    conn.read_exact(&mut got).await.unwrap();
+    assert_eq!(String::from_utf8_lossy(&got), reply, "{:?}", request);
+}
+
+async fn connect(server: MemcachedServer) -> DuplexStream {
+    let (conn, stream) = tokio::io::duplex(64 * 1024);
+    tokio::spawn(async move { server.serve_connection(stream, None).await });
+    conn
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_memcached_commands() {
+    let dir = TempDir::new().unwrap();
+    let sim = Sim::start(dir.path(), SimConfig::default()).unwrap();
+    let leader = sim.wait_for_leader(ELECTION_WAIT).await.unwrap();
+    let server = MemcachedServer::new(sim.coordinator(&leader).router.clone());
+    let mut conn = connect(server).await;
+
+    call(&mut conn, "get a\r\n", "END\r\n").await;
+    call(&mut conn, "set a 0 0 5\r\nhello\r\n", "STORED\r\n").await;
+    call(&mut conn, "set b 42 0 2\r\nhi\r\n", "STORED\r\n").await;
+    call(
+        &mut conn,
+        "get a b c\r\n",
+        "VALUE a 0 5\r\nhello\r\nVALUE b 42 2\r\nhi\r\nEND\r\n",
+    )
+    .await;
+
+    // Pipelined, noreply commands answering nothing
+    call(
+        &mut conn,
+        "set c 0 0 1 noreply\r\nx\r\ndelete a\r\ndelete a\r\nget c\r\n",
+        "DELETED\r\nNOT_FOUND\r\nVALUE c 0 1\r\nx\r\nEND\r\n",
+    )
+    .await;
+
+    call(
+        &mut conn,
+        "set d 0 60 1\r\nx\r\n",
+        "CLIENT_ERROR keys can't expire, exptime must be 0\r\n",
+    )
+    .await;
+    call(&mut conn, "incr c 1\r\n", "ERROR\r\n").await;
+
+    // Larger than an item may be: the data is skipped, not read as commands
+    let big = format!("set e 0 0 {}\r\n{}\r\n", 2 << 20, "x".repeat(2 << 20));
+    call(
+        &mut conn,
+        &big,
+        "SERVER_ERROR object too large for cache\r\n",
+    )
+    .await;
+    call(&mut conn, "get e\r\n", "END\r\n").await;
+
+    conn.write_all(b"stats\r\n").await.unwrap();
+    let mut stats = String::new();
+    while !stats.ends_with("END\r\n") {
+        stats.push(conn.read_u8().await.unwrap() as char);
+    }
+    assert!(stats.contains("STAT get_hits 3\r\n"), "{}", stats);
+    assert!(stats.contains("STAT delete_misses 1\r\n"), "{}", stats);
+
+    conn.write_all(b"quit\r\n").await.unwrap();
+    assert_eq!(
+        conn.read_u8().await.unwrap_err().kind(),
+        std::io::ErrorKind::UnexpectedEof
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_memcached_auth() {
+    let dir = TempDir::new().unwrap();
+    let sim = Sim::start(dir.path(), SimConfig::default()).unwrap();
+    let leader = sim.wait_for_leader(ELECTION_WAIT).await.unwrap();
+    let (key, secret) = KEY_STORE
+        .create_key("cache", "default", Role::ReadWrite, None)
+        .unwrap();
+    KEY_STORE.insert_key(key);
+    let server = MemcachedServer::new(sim.coordinator(&leader).router.clone()).with_auth(true);
+
+    let mut conn = connect(server.clone()).await;
+    call(&mut conn, "get a\r\n", "CLIENT_ERROR unauthenticated\r\n").await;
+    call(
+        &mut conn,
+        "set auth 0 0 13\r\ndefault wrong\r\n",
+        "CLIENT_ERROR authentication failure\r\n",
+    )
+    .await;
+    let credentials = format!("default {}", secret);
+    call(
+        &mut conn,
+        &format!("set auth 0 0 {}\r\n{}\r\n", credentials.len(), credentials),
+        "STORED\r\n",
+    )
+    .await;
+    call(&mut conn, "get a\r\n", "END\r\n").await;
+
+    // SASL PLAIN credentials
+    let mut conn = connect(server).await;
+    let credentials = format!("\0default\0{}", secret);
+    call(
+        &mut conn,
+        &format!("set auth 0 0 {}\r\n{}\r\n", credentials.len(), credentials),
+        "STORED\r\n",
+    )
+    .await;
+}
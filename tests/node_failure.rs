@@ -34,6 +34,7 @@ async fn test_node_failure_and_recovery() {
         prev_log_term: node1.get_term(),
         entries: vec![entry.clone()],
         leader_commit: 1,
+        leader_addr: String::new(),
     };
     let resp3 = node3.handle_append_entries(append_req.clone());
     assert!(resp3.success);
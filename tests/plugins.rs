@@ -0,0 +1,160 @@
+//! WASM plugins on the puts and gets of a simulated cluster (`minikv::sim`).
+//! Plugins are loaded process-wide, hence this test binary of its own.
+
+use axum::body::Body;
+use axum::http::{Method, StatusCode};
+use minikv::common::{PluginConfig, PluginHook, METRICS};
+use minikv::coordinator::loopback::{self, Answer, Caller};
+use minikv::coordinator::plugins::PLUGINS;
+use minikv::sim::{Sim, SimConfig};
+use std::path::Path;
+use std::time::Duration;
+use tempfile::TempDir;
+
+const ELECTION_WAIT: Duration = Duration::from_secs(10);
+
+/// Tags values `checked=yes`, rejects those starting with `!`
+const CHECK: &str = r#"(module
+  (import "minikv" "add_tag" (func $add_tag (param i32 i32 i32 i32)))
+  (import "minikv" "reject" (func $reject (param i32 i32)))
+  (memory (export "memory") 1)
+  (data (i32.const 0) "checkedyesvalue starts with !")
+  (global $next (mut i32) (i32.const 1024))
+  (func (export "alloc") (param $len i32) (result i32)
+    (local $ptr i32)
+    (local.set $ptr (global.get $next))
+    (global.set $next (i32.add (global.get $next) (local.get $len)))
+    (local.get $ptr))
+  (func (export "on_put") (param $key i32) (param $key_len i32) (param $value i32) (param $value_len i32)
+    (if (i32.and (i32.gt_u (local.get $value_len) (i32.const 0))
+                 (i32.eq (i32.load8_u (local.get $value)) (i32.const 33)))
+      (then (call $reject (i32.const 10) (i32.const 19)) (return)))
+    (call $add_tag (i32.const 0) (i32.const 7) (i32.const 7) (i32.const 3))))"#;
+
+/// Answers `redacted` whatever the value
+const REDACT: &str = r#"(module
+  (import "minikv" "set_value" (func $set_value (param i32 i32)))
+  (memory (export "memory") 1)
+  (data (i32.const 0) "redacted")
+  (func (export "alloc") (param $len i32) (result i32) (i32.const 1024))
+  (func (export "on_get") (param i32 i32 i32 i32)
+    (call $set_value (i32.const 0) (i32.const 8))))"#;
+
+/// Never returns
+const SPIN: &str = r#"(module
+  (memory (export "memory") 1)
+  (func (export "alloc") (param $len i32) (result i32) (i32.const 1024))
+  (func (export "on_put") (param i32 i32 i32 i32)
+    (loop $spin (br $spin))))"#;
+
+/// Imports from outside the host API
+const WASI: &str = r#"(module
+  (import "wasi_snapshot_preview1" "fd_write" (func (param i32 i32 i32 i32) (result i32)))
+  (memory (export "memory") 1)
+  (func (export "alloc") (param $len i32) (result i32) (i32.const 1024))
+  (func (export "on_put") (param i32 i32 i32 i32)))"#;
+
+fn plugin(dir: &Path, name: &str, wat: &str, hooks: &[PluginHook], prefix: &str) -> PluginConfig {
+    let path = dir.join(format!("{}.wat", name));
+    std::fs::write(&path, wat).unwrap();
+    PluginConfig {
+        name: name.to_string(),
+        path,
+        hooks: hooks.to_vec(),
+        prefix: prefix.to_string(),
+        timeout_ms: 50,
+        max_memory_bytes: 1024 * 1024,
+        fail_open: false,
+    }
+}
+
+async fn put(router: &axum::Router, key: &str, value: &'static str) -> Answer {
+    loopback::call(
+        router,
+        &Caller::default(),
+        Method::PUT,
+        format!("/{}", key),
+        Body::from(value),
+    )
+    .await
+}
+
+async fn get(router: &axum::Router, key: &str) -> Answer {
+    loopback::call(
+        router,
+        &Caller::default(),
+        Method::GET,
+        format!("/{}", key),
+        Body::empty(),
+    )
+    .await
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_plugins() {
+    let dir = TempDir::new().unwrap();
+    let plugins = TempDir::new().unwrap();
+    let lenient = PluginConfig {
+        name: "lenient".to_string(),
+        prefix: "lenient/".to_string(),
+        fail_open: true,
+        ..plugin(plugins.path(), "spin", SPIN, &[PluginHook::Put], "")
+    };
+    PLUGINS
+        .load(&[
+            plugin(plugins.path(), "check", CHECK, &[PluginHook::Put], ""),
+            plugin(
+                plugins.path(),
+                "redact",
+                REDACT,
+                &[PluginHook::Get],
+                "secret/",
+            ),
+            plugin(plugins.path(), "slow", SPIN, &[PluginHook::Put], "slow/"),
+            lenient,
+        ])
+        .unwrap();
+
+    let sim = Sim::start(dir.path(), SimConfig::default()).unwrap();
+    let leader = sim.wait_for_leader(ELECTION_WAIT).await.unwrap();
+    let coordinator = sim.coordinator(&leader);
+    let router = &coordinator.router;
+
+    assert_eq!(put(router, "a", "hello").await.status, StatusCode::OK);
+    let meta = coordinator.metadata.get_key("default/a").unwrap().unwrap();
+    assert_eq!(meta.tags.get("checked").map(String::as_str), Some("yes"));
+    assert_eq!(get(router, "a").await.body, "hello");
+
+    let answer = put(router, "b", "!nope").await;
+    assert_eq!(answer.status, StatusCode::BAD_REQUEST);
+    let message = String::from_utf8_lossy(&answer.body);
+    assert!(message.contains("rejected by plugin check: value starts with !"));
+    assert!(coordinator.metadata.get_key("default/b").unwrap().is_none());
+
+    assert_eq!(
+        put(router, "secret/a", "hunter2").await.status,
+        StatusCode::OK
+    );
+    assert_eq!(get(router, "secret/a").await.body, "redacted");
+    // Range scans streaming values answer what the plugins do
+    let scan = get(router, "range?start=secret/&end=secret/~&values=blob").await;
+    assert_eq!(scan.status, StatusCode::OK);
+    let scan = String::from_utf8_lossy(&scan.body);
+    assert!(scan.contains("\"value\":\"cmVkYWN0ZWQ=\""), "{}", scan);
+    assert!(!scan.contains("aHVudGVyMg=="), "{}", scan);
+
+    assert_eq!(
+        put(router, "slow/a", "x").await.status,
+        StatusCode::REQUEST_TIMEOUT
+    );
+    assert_eq!(put(router, "lenient/a", "x").await.status, StatusCode::OK);
+    let metrics = METRICS.to_prometheus();
+    assert!(metrics.contains("minikv_plugin_failures_total{plugin=\"slow\"} 1"));
+    assert!(metrics.contains("minikv_plugin_failures_total{plugin=\"lenient\"} 1"));
+
+    // A module importing more than the host API is refused, the loaded
+    // plugins kept
+    let wasi = plugin(plugins.path(), "wasi", WASI, &[PluginHook::Put], "");
+    assert!(PLUGINS.load(&[wasi]).is_err());
+    assert_eq!(get(router, "secret/a").await.body, "redacted");
+}
@@ -0,0 +1,159 @@
+//! Tenant quota administration and enforcement on the coordinator HTTP API.
+//! Quotas and usage live in the process-wide `QUOTA_MANAGER`, hence a test
+//! binary of its own.
+
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use minikv::common::{IpFilter, RateLimitConfig, RateLimiter, WriteBudget, WriteBudgetConfig};
+use minikv::coordinator::http::{create_router, CoordState};
+use minikv::coordinator::metadata::MetadataStore;
+use minikv::coordinator::placement::PlacementManager;
+use minikv::coordinator::raft_node::RaftNode;
+use std::sync::{Arc, Mutex};
+use tempfile::TempDir;
+use tower::ServiceExt;
+
+/// A single-node coordinator router without authentication, leading so
+/// admin commands commit
+fn router(dir: &TempDir) -> axum::Router {
+    let metadata = Arc::new(MetadataStore::open(dir.path().join("meta.db")).unwrap());
+    let raft = Arc::new(RaftNode::open("coord-1".to_string(), metadata.clone()).unwrap());
+    raft.become_leader();
+    create_router(CoordState {
+        metadata,
+        placement: Arc::new(Mutex::new(PlacementManager::new(16, 1))),
+        raft,
+        auth: Default::default(),
+        rate_limiter: Arc::new(RateLimiter::new(RateLimitConfig::default())),
+        ip_filter: Arc::new(IpFilter::default()),
+        writes: Arc::new(WriteBudget::new(WriteBudgetConfig::default())),
+    })
+}
+
+async fn send(router: &axum::Router, method: &str, uri: &str, body: &str) -> (StatusCode, String) {
+    send_as(router, "acme", method, uri, body).await
+}
+
+async fn send_as(
+    router: &axum::Router,
+    tenant: &str,
+    method: &str,
+    uri: &str,
+    body: &str,
+) -> (StatusCode, String) {
+    let request = Request::builder()
+        .method(method)
+        .uri(uri)
+        .header("Content-Type", "application/json")
+        .header("X-Minikv-Tenant", tenant)
+        .body(Body::from(body.to_string()))
+        .unwrap();
+    let resp = router.clone().oneshot(request).await.unwrap();
+    let status = resp.status();
+    let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    (status, String::from_utf8_lossy(&body).to_string())
+}
+
+fn json(body: &str) -> serde_json::Value {
+    serde_json::from_str(body).unwrap()
+}
+
+#[tokio::test]
+async fn test_quota_admin() {
+    let dir = TempDir::new().unwrap();
+    let router = router(&dir);
+
+    let (status, body) = send(&router, "GET", "/admin/quotas/acme", "").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(json(&body)["default"], true);
+
+    let (status, body) = send(
+        &router,
+        "PUT",
+        "/admin/quotas/acme",
+        r#"{"object_limit":1}"#,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let quota = json(&body);
+    assert_eq!(quota["object_limit"], 1);
+    assert_eq!(quota["storage_limit"], 0);
+    assert_eq!(quota["default"], false);
+    let (_, body) = send(&router, "GET", "/admin/quotas", "").await;
+    assert_eq!(json(&body)["quotas"][0]["tenant"], "acme");
+
+    // The second key is over the limit; replacing the first one isn't
+    let put = |key: &str| format!(r#"{{"ops":[{{"op":"put","key":"{}","value":"x"}}]}}"#, key);
+    let (_, body) = send(&router, "POST", "/batch", &put("a")).await;
+    assert_eq!(json(&body)["results"][0]["ok"], true, "{}", body);
+    let (_, body) = send(&router, "POST", "/batch", &put("b")).await;
+    assert_eq!(json(&body)["results"][0]["ok"], false, "{}", body);
+    let (_, body) = send(&router, "POST", "/batch", &put("a")).await;
+    assert_eq!(json(&body)["results"][0]["ok"], true, "{}", body);
+    let (_, body) = send(&router, "GET", "/admin/quotas/acme", "").await;
+    assert_eq!(json(&body)["object_count"], 1);
+
+    // Without its quota the tenant is back to the (unlimited) default
+    let (status, _) = send(&router, "DELETE", "/admin/quotas/acme", "").await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = send(&router, "DELETE", "/admin/quotas/acme", "").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (_, body) = send(&router, "POST", "/batch", &put("b")).await;
+    assert_eq!(json(&body)["results"][0]["ok"], true, "{}", body);
+
+    // Bandwidth limits and current rates
+    let limits = r#"{"ingress_limit":1048576,"egress_limit":2097152}"#;
+    let (status, _) = send(&router, "PUT", "/admin/quotas/acme", limits).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, body) = send(&router, "GET", "/admin/quotas/acme/usage", "").await;
+    assert_eq!(status, StatusCode::OK);
+    let usage = json(&body);
+    assert_eq!(usage["ingress_limit"], 1048576);
+    assert_eq!(usage["egress_limit"], 2097152);
+    assert_eq!(usage["object_count"], 2);
+    assert!(usage["ingress_bytes_per_sec"].is_u64());
+
+    let (status, _) = send(&router, "PUT", "/admin/quotas/.cas", "{}").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_s3_put_quota() {
+    let dir = TempDir::new().unwrap();
+    let router = router(&dir);
+
+    let limits = r#"{"object_limit":1,"storage_limit":4}"#;
+    let (status, _) = send_as(
+        &router,
+        "s3-tenant",
+        "PUT",
+        "/admin/quotas/s3-tenant",
+        limits,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    // Replacing an object counts its new size only; a second one is refused
+    let (status, body) = send_as(&router, "s3-tenant", "PUT", "/s3/bucket/a", "abc").await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let (status, body) = send_as(&router, "s3-tenant", "PUT", "/s3/bucket/a", "abcd").await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let (status, _) = send_as(&router, "s3-tenant", "PUT", "/s3/bucket/a", "abcde").await;
+    assert_ne!(status, StatusCode::OK);
+    let (status, _) = send_as(&router, "s3-tenant", "PUT", "/s3/bucket/b", "x").await;
+    assert_ne!(status, StatusCode::OK);
+    let (_, body) = send_as(
+        &router,
+        "s3-tenant",
+        "GET",
+        "/admin/quotas/s3-tenant/usage",
+        "",
+    )
+    .await;
+    assert_eq!(json(&body)["object_count"], 1);
+
+    let (status, _) = send_as(&router, "s3-tenant", "PUT", "/s3/.cas/a", "x").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
@@ -32,6 +32,7 @@ async fn raft_election_and_replication() {
         prev_log_term: node1.get_term(),
         entries: vec![entry.clone()],
         leader_commit: 1,
+        leader_addr: String::new(),
     };
     let resp2 = node2.handle_append_entries(append_req.clone());
     let resp3 = node3.handle_append_entries(append_req);
@@ -49,3 +50,383 @@ async fn raft_election_and_replication() {
     assert!(node2.is_leader());
     assert_eq!(node2.get_role(), RaftRole::Leader);
 }
+
+#[tokio::test]
+async fn raft_state_survives_restart() {
+    use minikv::common::raft::{AppendRequest, VoteRequest};
+    use minikv::coordinator::metadata::MetadataStore;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("meta.db");
+    let vote = |term: u64, candidate: &str| VoteRequest {
+        term,
+        candidate_id: candidate.to_string(),
+        last_log_index: 0,
+        last_log_term: 0,
+        pre_vote: false,
+    };
+
+    {
+        let store = Arc::new(MetadataStore::open(&path).unwrap());
+        let node = RaftNode::open("node2".to_string(), store).unwrap();
+        assert!(node.handle_request_vote(vote(5, "node1")).vote_granted);
+        let resp = node.handle_append_entries(AppendRequest {
+            term: 5,
+            leader_id: "node1".to_string(),
+            prev_log_index: 0,
+            prev_log_term: 0,
+            entries: vec![LogEntry {
+                term: 5,
+                index: 1,
+                data: b"set x=42".to_vec(),
+            }],
+            leader_commit: 1,
+            leader_addr: String::new(),
+        });
+        assert!(resp.success);
+    }
+
+    // After a restart the node remembers its vote for term 5
+    let store = Arc::new(MetadataStore::open(&path).unwrap());
+    let node = RaftNode::open("node2".to_string(), store).unwrap();
+    assert_eq!(node.get_term(), 5);
+    assert!(!node.handle_request_vote(vote(5, "node3")).vote_granted);
+    assert!(node.handle_request_vote(vote(5, "node1")).vote_granted);
+    assert_eq!(node.get_log().len(), 1);
+    assert_eq!(node.get_log()[0].data, b"set x=42".to_vec());
+}
+
+#[tokio::test]
+async fn raft_log_compaction_and_snapshot_install() {
+    use minikv::common::raft::AppendRequest;
+    use minikv::coordinator::metadata::MetadataStore;
+
+    let dir = tempfile::tempdir().unwrap();
+    let leader_store = Arc::new(MetadataStore::open(dir.path().join("leader.db")).unwrap());
+    let follower_store = Arc::new(MetadataStore::open(dir.path().join("follower.db")).unwrap());
+
+    let leader = RaftNode::open("node1".to_string(), leader_store.clone())
+        .unwrap()
+        .with_snapshot_threshold(2);
+    leader.become_leader();
+    leader_store.put_config("x", b"42").unwrap();
+    for i in 0..3u8 {
+        leader.replicate(vec![i]).await.unwrap();
+    }
+    assert!(leader.maybe_compact().unwrap());
+    assert!(leader.get_log().is_empty());
+    let meta = leader.snapshot_meta();
+    assert_eq!(meta.last_included_index, 3);
+
+    // A follower behind the snapshot installs it and continues from there
+    let follower = RaftNode::open("node2".to_string(), follower_store.clone()).unwrap();
+    follower
+        .apply_snapshot(
+            leader.load_snapshot().unwrap(),
+            meta.last_included_index,
+            meta.last_included_term,
+        )
+        .unwrap();
+    assert_eq!(follower_store.get_config("x").unwrap().unwrap(), b"42");
+
+    let resp = follower.handle_append_entries(AppendRequest {
+        term: leader.get_term(),
+        leader_id: "node1".to_string(),
+        prev_log_index: 3,
+        prev_log_term: meta.last_included_term,
+        entries: vec![LogEntry {
+            term: leader.get_term(),
+            index: 4,
+            data: vec![4],
+        }],
+        leader_commit: 4,
+        leader_addr: String::new(),
+    });
+    assert!(resp.success);
+    assert_eq!(follower.get_log().len(), 1);
+}
+
+#[tokio::test]
+async fn raft_applies_committed_commands() {
+    use minikv::common::raft::AppendRequest;
+    use minikv::coordinator::metadata::{MetadataCommand, MetadataStore};
+
+    let dir = tempfile::tempdir().unwrap();
+    let leader_store = Arc::new(MetadataStore::open(dir.path().join("leader.db")).unwrap());
+    let follower_store = Arc::new(MetadataStore::open(dir.path().join("follower.db")).unwrap());
+    let leader = RaftNode::open("node1".to_string(), leader_store.clone()).unwrap();
+    let follower = RaftNode::open("node2".to_string(), follower_store.clone()).unwrap();
+
+    leader.become_leader();
+    let command = MetadataCommand::PutConfig {
+        key: "x".to_string(),
+        value: b"42".to_vec(),
+    };
+    leader.propose(&command).await.unwrap();
+    assert_eq!(leader_store.get_config("x").unwrap().unwrap(), b"42");
+
+    // Replicated but not yet committed: the follower must not apply it
+    let entry = leader.get_log()[0].clone();
+    let mut req = AppendRequest {
+        term: leader.get_term(),
+        leader_id: "node1".to_string(),
+        prev_log_index: 0,
+        prev_log_term: 0,
+        entries: vec![entry],
+        leader_commit: 0,
+        leader_addr: String::new(),
+    };
+    assert!(follower.handle_append_entries(req.clone()).success);
+    assert!(follower_store.get_config("x").unwrap().is_none());
+
+    // The next heartbeat carries the commit index
+    req.entries.clear();
+    req.prev_log_index = 1;
+    req.prev_log_term = leader.get_term();
+    req.leader_commit = 1;
+    assert!(follower.handle_append_entries(req).success);
+    assert_eq!(follower_store.get_config("x").unwrap().unwrap(), b"42");
+}
+
+#[tokio::test]
+async fn raft_follower_rejects_writes() {
+    use minikv::coordinator::metadata::{MetadataCommand, MetadataStore};
+
+    let dir = tempfile::tempdir().unwrap();
+    let store = Arc::new(MetadataStore::open(dir.path().join("follower.db")).unwrap());
+    let follower = RaftNode::open("node2".to_string(), store.clone()).unwrap();
+    follower.follow_leader(1, "node1".to_string()).unwrap();
+
+    let command = MetadataCommand::DeleteKey("a".to_string());
+    match follower.propose(&command).await {
+        Err(minikv::Error::NotLeader(leader)) => assert_eq!(leader, "node1"),
+        other => panic!("expected NotLeader, got {:?}", other),
+    }
+    assert!(follower.get_log().is_empty());
+}
+
+#[tokio::test]
+async fn raft_follower_learns_leader_addr() {
+    use minikv::common::raft::AppendRequest;
+
+    let leader = RaftNode::new("node1".to_string()).with_advertise_addr("http://n1:5000".into());
+    let follower = RaftNode::new("node2".to_string());
+    leader.become_leader();
+    assert_eq!(leader.get_leader_addr().as_deref(), Some("http://n1:5000"));
+    assert_eq!(follower.get_leader_addr(), None);
+
+    let resp = follower.handle_append_entries(AppendRequest {
+        term: 1,
+        leader_id: "node1".to_string(),
+        prev_log_index: 0,
+        prev_log_term: 0,
+        entries: vec![],
+        leader_commit: 0,
+        leader_addr: "http://n1:5000".to_string(),
+    });
+    assert!(resp.success);
+    assert_eq!(follower.get_leader().as_deref(), Some("node1"));
+    assert_eq!(
+        follower.get_leader_addr().as_deref(),
+        Some("http://n1:5000")
+    );
+
+    // A new term with no known leader forgets the address
+    follower.step_down(2, None);
+    assert_eq!(follower.get_leader_addr(), None);
+}
+
+#[tokio::test]
+async fn raft_read_index_barrier() {
+    let leader = RaftNode::new("node1".to_string());
+    leader.start_election().unwrap();
+    leader.become_leader();
+
+    // The first read commits a no-op of the new term
+    assert_eq!(leader.read_index().await.unwrap(), 1);
+    assert!(leader.get_log()[0].data.is_empty());
+    assert_eq!(leader.read_index().await.unwrap(), 1);
+
+    let follower = RaftNode::new("node2".to_string());
+    assert!(matches!(
+        follower.read_index().await,
+        Err(minikv::Error::NotLeader(_))
+    ));
+}
+
+#[tokio::test]
+async fn raft_pre_vote_and_election_restriction() {
+    use minikv::common::raft::{AppendRequest, VoteRequest};
+
+    let node = RaftNode::new("node2".to_string());
+    let vote = |term: u64, last_log_index: u64, last_log_term: u64, pre_vote: bool| VoteRequest {
+        term,
+        candidate_id: "node3".to_string(),
+        last_log_index,
+        last_log_term,
+        pre_vote,
+    };
+
+    // A pre-vote changes neither the term nor the vote
+    assert!(node.handle_request_vote(vote(1, 0, 0, true)).vote_granted);
+    assert_eq!(node.get_term(), 0);
+
+    // Once a leader is heard from, pre-votes are refused
+    let resp = node.handle_append_entries(AppendRequest {
+        term: 1,
+        leader_id: "node1".to_string(),
+        prev_log_index: 0,
+        prev_log_term: 0,
+        entries: vec![LogEntry {
+            term: 1,
+            index: 1,
+            data: vec![],
+        }],
+        leader_commit: 0,
+        leader_addr: String::new(),
+    });
+    assert!(resp.success);
+    assert!(!node.handle_request_vote(vote(2, 1, 1, true)).vote_granted);
+    assert_eq!(node.get_term(), 1);
+
+    // A real vote for a candidate with a shorter log is refused, but its term is adopted
+    let resp = node.handle_request_vote(vote(2, 0, 0, false));
+    assert!(!resp.vote_granted);
+    assert_eq!(resp.term, 2);
+    assert!(node.handle_request_vote(vote(2, 1, 1, false)).vote_granted);
+}
+
+#[tokio::test]
+async fn raft_transfer_leadership_checks() {
+    use std::time::Duration;
+
+    let node = RaftNode::new("node1".to_string());
+    assert!(matches!(
+        node.transfer_leadership("http://node2:5001", Duration::from_millis(100))
+            .await,
+        Err(minikv::Error::NotLeader(_))
+    ));
+
+    node.become_leader();
+    assert!(matches!(
+        node.transfer_leadership("http://node2:5001", Duration::from_millis(100))
+            .await,
+        Err(minikv::Error::NotFound(_))
+    ));
+    assert!(node.is_leader());
+}
+
+#[tokio::test]
+async fn raft_follower_backs_off_conflicting_suffix() {
+    use minikv::common::raft::AppendRequest;
+
+    let follower = RaftNode::new("node2".to_string());
+    let entry = |term: u64, index: u64| LogEntry {
+        term,
+        index,
+        data: vec![index as u8],
+    };
+    let append = |prev_log_index: u64, prev_log_term: u64, entries: Vec<LogEntry>| AppendRequest {
+        term: 3,
+        leader_id: "node1".to_string(),
+        prev_log_index,
+        prev_log_term,
+        entries,
+        leader_commit: 0,
+        leader_addr: String::new(),
+    };
+
+    // The follower holds entries 2..=3 from a term-2 leader that never committed them
+    let resp =
+        follower.handle_append_entries(append(0, 0, vec![entry(1, 1), entry(2, 2), entry(2, 3)]));
+    assert!(resp.success);
+
+    // A gap is rejected with the follower's last index
+    let resp = follower.handle_append_entries(append(5, 3, vec![entry(3, 6)]));
+    assert!(!resp.success);
+    assert_eq!(resp.conflict_index, 3);
+
+    // A term mismatch skips the leader back over the whole conflicting term
+    let resp = follower.handle_append_entries(append(3, 3, vec![entry(3, 4)]));
+    assert!(!resp.success);
+    assert_eq!(resp.conflict_index, 1);
+    assert_eq!(follower.get_log().len(), 3);
+
+    // Resending from the agreed prefix replaces the stale suffix
+    let resp = follower.handle_append_entries(append(1, 1, vec![entry(3, 2), entry(3, 3)]));
+    assert!(resp.success);
+    let terms: Vec<u64> = follower.get_log().iter().map(|e| e.term).collect();
+    assert_eq!(terms, vec![1, 3, 3]);
+}
+
+#[tokio::test]
+async fn raft_grpc_handlers_reach_the_node() {
+    use minikv::coordinator::grpc::CoordGrpcService;
+    use minikv::proto::coordinator_internal_server::CoordinatorInternal;
+
+    let node = Arc::new(RaftNode::new("node2".to_string()));
+    let service = CoordGrpcService::new().with_raft(node.clone());
+
+    let vote = service
+        .request_vote(tonic::Request::new(minikv::proto::VoteRequest {
+            term: 4,
+            candidate_id: "node1".to_string(),
+            last_log_index: 0,
+            last_log_term: 0,
+            pre_vote: false,
+        }))
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(vote.vote_granted);
+    assert_eq!(vote.term, 4);
+    assert_eq!(node.get_term(), 4);
+
+    let append = service
+        .append_entries(tonic::Request::new(minikv::proto::AppendRequest {
+            term: 4,
+            leader_id: "node1".to_string(),
+            prev_log_index: 0,
+            prev_log_term: 0,
+            entries: vec![minikv::proto::LogEntry {
+                term: 4,
+                index: 1,
+                data: b"set x=42".to_vec(),
+            }],
+            leader_commit: 0,
+            leader_addr: "http://10.0.0.1:5000".to_string(),
+        }))
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(append.success);
+    assert_eq!(node.get_log().len(), 1);
+    assert_eq!(node.get_leader().as_deref(), Some("node1"));
+    assert_eq!(
+        node.get_leader_addr().as_deref(),
+        Some("http://10.0.0.1:5000")
+    );
+
+    // A stale leader is turned away with the current term
+    let stale = service
+        .append_entries(tonic::Request::new(minikv::proto::AppendRequest {
+            term: 3,
+            leader_id: "node3".to_string(),
+            ..Default::default()
+        }))
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(!stale.success);
+    assert_eq!(stale.term, 4);
+
+    // Peers given as host:port are reached over http
+    let node = RaftNode::new("node1".to_string()).with_peers(vec![
+        "10.0.0.2:5001".to_string(),
+        "https://10.0.0.3:5001".to_string(),
+    ]);
+    assert_eq!(
+        node.get_peers(),
+        vec!["http://10.0.0.2:5001", "https://10.0.0.3:5001"]
+    );
+}
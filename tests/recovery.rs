@@ -11,7 +11,7 @@ fn test_recovery_after_crash() {
 
     // Write data
     {
-        let mut store =
+        let store =
             BlobStore::open(&data_path, &wal_path, minikv::common::WalSyncPolicy::Always).unwrap();
         store.put("key_crash", b"value_crash").unwrap();
         store.save_snapshot().unwrap();
@@ -25,3 +25,254 @@ fn test_recovery_after_crash() {
         assert_eq!(store.get("key_crash").unwrap().unwrap(), b"value_crash");
     }
 }
+
+fn open(dir: &TempDir) -> BlobStore {
+    BlobStore::open(
+        &dir.path().join("data"),
+        &dir.path().join("wal"),
+        minikv::common::WalSyncPolicy::Always,
+    )
+    .unwrap()
+}
+
+fn segment_path(dir: &TempDir) -> std::path::PathBuf {
+    dir.path().join("data/segments/seg_000000.blob")
+}
+
+#[test]
+fn test_recovery_replays_wal_after_snapshot() {
+    let dir = TempDir::new().unwrap();
+
+    {
+        let store = open(&dir);
+        store.put("before", b"v1").unwrap();
+        store.put("deleted", b"gone").unwrap();
+        store.save_snapshot().unwrap();
+
+        // Crash before the next snapshot
+        store.put("after", b"new").unwrap();
+        store.put("before", b"v2").unwrap();
+        store.delete("deleted").unwrap();
+    }
+
+    let size = std::fs::metadata(segment_path(&dir)).unwrap().len();
+    for _ in 0..2 {
+        let store = open(&dir);
+        assert_eq!(store.get("before").unwrap().unwrap(), b"v2");
+        assert_eq!(store.get("after").unwrap().unwrap(), b"new");
+        assert!(store.get("deleted").unwrap().is_none());
+        assert_eq!(store.stats().total_keys, 2);
+    }
+    // Records were all found in place, none written again
+    assert_eq!(std::fs::metadata(segment_path(&dir)).unwrap().len(), size);
+}
+
+#[test]
+fn test_recovery_rewrites_puts_missing_from_segments() {
+    let dir = TempDir::new().unwrap();
+
+    let size = {
+        let store = open(&dir);
+        store.put("kept", b"on disk").unwrap();
+        store.save_snapshot().unwrap();
+        let size = std::fs::metadata(segment_path(&dir)).unwrap().len();
+        store.put("lost", b"only in the WAL").unwrap();
+        size
+    };
+
+    // Crash between the WAL append and the segment write
+    let segment = std::fs::OpenOptions::new()
+        .write(true)
+        .open(segment_path(&dir))
+        .unwrap();
+    segment.set_len(size).unwrap();
+    drop(segment);
+
+    {
+        let store = open(&dir);
+        assert_eq!(store.get("lost").unwrap().unwrap(), b"only in the WAL");
+        assert_eq!(store.get("kept").unwrap().unwrap(), b"on disk");
+        store.put("next", b"appended").unwrap();
+    }
+
+    // The rewritten record is found on the next open
+    let store = open(&dir);
+    assert_eq!(store.get("lost").unwrap().unwrap(), b"only in the WAL");
+    assert_eq!(store.get("next").unwrap().unwrap(), b"appended");
+    assert_eq!(store.stats().total_keys, 3);
+}
+
+#[test]
+fn test_recovery_cuts_torn_record() {
+    let dir = TempDir::new().unwrap();
+
+    let size = {
+        let store = open(&dir);
+        store.put("whole", b"complete record").unwrap();
+        store.put("torn", b"half written").unwrap();
+        std::fs::metadata(segment_path(&dir)).unwrap().len()
+    };
+
+    // Crash in the middle of the last record
+    let segment = std::fs::OpenOptions::new()
+        .write(true)
+        .open(segment_path(&dir))
+        .unwrap();
+    segment.set_len(size - 5).unwrap();
+    drop(segment);
+
+    {
+        let store = open(&dir);
+        assert_eq!(store.get("whole").unwrap().unwrap(), b"complete record");
+        assert_eq!(store.get("torn").unwrap().unwrap(), b"half written");
+        store.put("after", b"written past the tear").unwrap();
+    }
+
+    let store = open(&dir);
+    assert_eq!(store.get("torn").unwrap().unwrap(), b"half written");
+    assert_eq!(
+        store.get("after").unwrap().unwrap(),
+        b"written past the tear"
+    );
+}
+
+#[test]
+fn test_rebuild_honours_tombstones() {
+    let dir = TempDir::new().unwrap();
+
+    {
+        let store = open(&dir);
+        store.put("deleted", b"old").unwrap();
+        store.put("kept", b"value").unwrap();
+        store.delete("deleted").unwrap();
+        store.put("recreated", b"first").unwrap();
+        store.delete("recreated").unwrap();
+        store.put("recreated", b"second").unwrap();
+    }
+
+    // Without snapshot nor WAL, the index comes from the segments alone
+    std::fs::remove_dir_all(dir.path().join("wal")).unwrap();
+
+    let store = open(&dir);
+    assert!(store.get("deleted").unwrap().is_none());
+    assert_eq!(store.get("kept").unwrap().unwrap(), b"value");
+    assert_eq!(store.get("recreated").unwrap().unwrap(), b"second");
+    assert_eq!(store.stats().total_keys, 2);
+}
+
+#[test]
+fn test_rebuild_from_many_segments_keeps_write_order() {
+    let dir = TempDir::new().unwrap();
+
+    {
+        let mut store = open(&dir);
+        // A record or two per segment
+        store.set_segment_limits(64, 0);
+        for round in 0..5 {
+            for i in 0..20 {
+                let value = format!("{}-{}", i, round);
+                store.put(&format!("key{}", i), value.as_bytes()).unwrap();
+            }
+        }
+        for i in (0..20).step_by(3) {
+            store.delete(&format!("key{}", i)).unwrap();
+        }
+    }
+    let segments = std::fs::read_dir(dir.path().join("data/segments"))
+        .unwrap()
+        .count();
+    assert!(segments > 50, "{} segments", segments);
+    std::fs::remove_dir_all(dir.path().join("wal")).unwrap();
+
+    let store = open(&dir);
+    for i in 0..20 {
+        let value = store.get(&format!("key{}", i)).unwrap();
+        if i % 3 == 0 {
+            assert!(value.is_none(), "key{} was deleted", i);
+        } else {
+            assert_eq!(value.unwrap(), format!("{}-4", i).as_bytes());
+        }
+    }
+    assert_eq!(store.stats().total_keys, 13);
+}
+
+#[test]
+fn test_open_migrates_legacy_layout() {
+    let dir = TempDir::new().unwrap();
+
+    {
+        let store = open(&dir);
+        store.put("key1", b"value1").unwrap();
+        store.put("key2", b"value2").unwrap();
+        store.save_snapshot().unwrap();
+    }
+
+    // Segments used to live under NN/NN/ directories
+    let legacy = dir.path().join("data/00/00");
+    std::fs::create_dir_all(&legacy).unwrap();
+    std::fs::rename(segment_path(&dir), legacy.join("seg_0000.blob")).unwrap();
+
+    {
+        let store = open(&dir);
+        assert_eq!(store.get("key1").unwrap().unwrap(), b"value1");
+        store.put("key3", b"value3").unwrap();
+    }
+    assert!(segment_path(&dir).exists());
+    assert!(!dir.path().join("data/00").exists());
+
+    let store = open(&dir);
+    assert_eq!(store.get("key2").unwrap().unwrap(), b"value2");
+    assert_eq!(store.get("key3").unwrap().unwrap(), b"value3");
+}
+
+#[test]
+fn test_recovery_after_interrupted_compaction() {
+    let dir = TempDir::new().unwrap();
+
+    {
+        let store = open(&dir);
+        store.put("key1", b"value1").unwrap();
+        store.put("key2", b"value2").unwrap();
+        store.delete("key2").unwrap();
+        store.compact().unwrap();
+        store.put("key3", b"value3").unwrap();
+    }
+    assert!(segment_path(&dir).exists());
+    assert!(!dir.path().join("data/compact_temp").exists());
+
+    // Crash after moving the old segments aside, before the new ones
+    // were swapped in
+    let data = dir.path().join("data");
+    std::fs::rename(data.join("segments"), data.join("compact_backup")).unwrap();
+    std::fs::create_dir_all(data.join("compact_temp/segments")).unwrap();
+
+    let store = open(&dir);
+    assert!(!data.join("compact_backup").exists());
+    assert!(!data.join("compact_temp").exists());
+    assert_eq!(store.get("key1").unwrap().unwrap(), b"value1");
+    assert!(store.get("key2").unwrap().is_none());
+    assert_eq!(store.get("key3").unwrap().unwrap(), b"value3");
+}
+
+#[test]
+fn test_bloom_filter_saved_with_another_snapshot_is_rebuilt() {
+    let dir = TempDir::new().unwrap();
+    let bloom_path = dir.path().join("data/bloom.filter");
+
+    {
+        let store = open(&dir);
+        store.put("old", b"v1").unwrap();
+        store.save_snapshot().unwrap();
+        std::fs::copy(&bloom_path, dir.path().join("bloom.old")).unwrap();
+
+        store.put("new", b"v2").unwrap();
+        store.save_snapshot().unwrap();
+    }
+    // Left with the filter of the first snapshot, and no WAL to replay
+    std::fs::copy(dir.path().join("bloom.old"), &bloom_path).unwrap();
+    std::fs::remove_dir_all(dir.path().join("wal")).unwrap();
+
+    let store = open(&dir);
+    assert_eq!(store.get("old").unwrap().unwrap(), b"v1");
+    assert_eq!(store.get("new").unwrap().unwrap(), b"v2");
+}
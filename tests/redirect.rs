@@ -0,0 +1,166 @@
+//! Redirects of key reads and writes to the volume HTTP API
+
+use axum::body::Body;
+use axum::http::{header, Request, StatusCode};
+use minikv::common::{
+    configure_grpc_auth, GrpcAuthConfig, IpFilter, NodeState, RateLimitConfig, RateLimiter,
+    WriteBudget, WriteBudgetConfig,
+};
+use minikv::coordinator::http::{create_router, CoordState};
+use minikv::coordinator::metadata::{KeyMetadata, KeyState, MetadataStore, VolumeMetadata};
+use minikv::coordinator::placement::PlacementManager;
+use minikv::coordinator::raft_node::RaftNode;
+use minikv::coordinator::redirect;
+use std::sync::{Arc, Mutex};
+use tempfile::TempDir;
+use tower::ServiceExt;
+
+/// A leading single-node coordinator with one volume, which nothing listens
+/// for, and internal tokens to sign redirects with
+fn setup(dir: &TempDir) -> (axum::Router, Arc<MetadataStore>, Arc<RaftNode>) {
+    configure_grpc_auth(&GrpcAuthConfig {
+        enabled: true,
+        tokens: vec!["secret".to_string()],
+        token: None,
+    })
+    .unwrap();
+    let metadata = Arc::new(MetadataStore::open(dir.path().join("meta.db")).unwrap());
+    metadata
+        .put_volume(&VolumeMetadata {
+            volume_id: "vol-1".to_string(),
+            address: "http://127.0.0.1:1".to_string(),
+            grpc_address: "http://127.0.0.1:2".to_string(),
+            state: NodeState::Alive,
+            shards: vec![],
+            total_keys: 0,
+            total_bytes: 0,
+            free_bytes: 0,
+            last_heartbeat: 0,
+            zone: None,
+            rack: None,
+            weight: None,
+            wal_error: None,
+        })
+        .unwrap();
+    let raft = Arc::new(RaftNode::open("coord-1".to_string(), metadata.clone()).unwrap());
+    raft.become_leader();
+    let router = create_router(CoordState {
+        metadata: metadata.clone(),
+        placement: Arc::new(Mutex::new(PlacementManager::new(16, 1))),
+        raft: raft.clone(),
+        auth: Default::default(),
+        rate_limiter: Arc::new(RateLimiter::new(RateLimitConfig::default())),
+        ip_filter: Arc::new(IpFilter::default()),
+        writes: Arc::new(WriteBudget::new(WriteBudgetConfig::default())),
+    });
+    (router, metadata, raft)
+}
+
+async fn send(
+    router: &axum::Router,
+    method: &str,
+    uri: &str,
+    body: &'static str,
+) -> (StatusCode, Option<String>) {
+    let request = Request::builder()
+        .method(method)
+        .uri(uri)
+        .header(header::CONTENT_LENGTH, body.len())
+        .body(Body::from(body))
+        .unwrap();
+    let resp = router.clone().oneshot(request).await.unwrap();
+    let location = resp
+        .headers()
+        .get(header::LOCATION)
+        .map(|v| v.to_str().unwrap().to_string());
+    (resp.status(), location)
+}
+
+#[tokio::test]
+async fn test_get_redirects_to_replica() {
+    let dir = TempDir::new().unwrap();
+    let (router, metadata, _raft) = setup(&dir);
+    metadata
+        .put_key(&KeyMetadata {
+            key: "default/a/b".to_string(),
+            replicas: vec!["vol-1".to_string()],
+            size: 5,
+            blake3: minikv::common::blake3_hash(b"hello"),
+            created_at: 0,
+            updated_at: 0,
+            state: KeyState::Active,
+            tags: Default::default(),
+            blob: None,
+            chunks: None,
+        })
+        .unwrap();
+
+    let (status, location) = send(&router, "GET", "/a%2Fb?redirect=true&stale=true", "").await;
+    assert_eq!(status, StatusCode::TEMPORARY_REDIRECT);
+    assert!(location
+        .unwrap()
+        .starts_with("http://127.0.0.1:1/blob/default%2Fa%2Fb?expires="));
+
+    let (status, _) = send(&router, "GET", "/missing?redirect=true&stale=true", "").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_put_redirects_to_placement_target() {
+    let dir = TempDir::new().unwrap();
+    let (router, metadata, raft) = setup(&dir);
+
+    let (status, location) = send(&router, "POST", "/k?redirect=true", "hello").await;
+    assert_eq!(status, StatusCode::TEMPORARY_REDIRECT);
+    let location = location.unwrap();
+    assert!(location.starts_with("http://127.0.0.1:1/blob/default%2Fk?expires="));
+    assert!(location.contains("&replicas=vol-1@http:%2F%2F127.0.0.1:2&acks=1"));
+    assert!(location.contains("&signature="));
+    // Nothing is published until the volume commits the upload
+    assert!(metadata.get_key("default/k").unwrap().is_none());
+    let upload = location
+        .split("&upload=")
+        .nth(1)
+        .and_then(|rest| rest.split('&').next())
+        .unwrap();
+
+    // Only an upload the coordinator issued is published
+    let forged = redirect::commit_upload(
+        &metadata,
+        &raft,
+        "forged",
+        "default/k",
+        5,
+        &minikv::common::blake3_hash(b"hello"),
+        vec!["vol-1".to_string()],
+    )
+    .await;
+    assert!(matches!(forged, Err(minikv::Error::PermissionDenied(_))));
+
+    redirect::commit_upload(
+        &metadata,
+        &raft,
+        upload,
+        "default/k",
+        5,
+        &minikv::common::blake3_hash(b"hello"),
+        vec!["vol-1".to_string()],
+    )
+    .await
+    .unwrap();
+    let meta = metadata.get_key("default/k").unwrap().unwrap();
+    assert_eq!(meta.replicas, vec!["vol-1".to_string()]);
+    assert_eq!(meta.size, 5);
+
+    let unknown = redirect::commit_upload(
+        &metadata,
+        &raft,
+        upload,
+        "default/k",
+        5,
+        "",
+        vec!["vol-9".to_string()],
+    )
+    .await;
+    assert!(unknown.is_err());
+}
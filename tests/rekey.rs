@@ -0,0 +1,93 @@
+//! Master key rotation and re-encryption of a volume.
+//! Kept in its own test binary: the encryption manager is process-wide.
+
+use minikv::common::{
+    EncryptionConfig, EncryptionManager, VersionedKey, WalSyncPolicy, ENCRYPTION_MANAGER,
+};
+use minikv::volume::blob::BlobStore;
+use minikv::volume::rekey::rekey_store;
+use tempfile::TempDir;
+
+fn set_encryption(key: Option<(u8, &str)>, previous: Option<(u8, &str)>) {
+    ENCRYPTION_MANAGER
+        .write()
+        .unwrap()
+        .configure(&EncryptionConfig {
+            enabled: key.is_some(),
+            master_key: key.map(|(_, key)| key.to_string()),
+            key_version: key.map_or(1, |(version, _)| version),
+            previous_keys: previous
+                .map(|(version, key)| VersionedKey {
+                    version,
+                    key: key.to_string(),
+                    provider: None,
+                })
+                .into_iter()
+                .collect(),
+            ..Default::default()
+        })
+        .unwrap();
+}
+
+#[test]
+fn test_rotate_and_rekey() {
+    let dir = TempDir::new().unwrap();
+    let data_path = dir.path().join("data");
+    let wal_path = dir.path().join("wal");
+    let (old_key, new_key) = (
+        EncryptionManager::generate_master_key(),
+        EncryptionManager::generate_master_key(),
+    );
+    let open = || BlobStore::open(&data_path, &wal_path, WalSyncPolicy::Always).unwrap();
+
+    set_encryption(None, None);
+    open().put("plain", b"plain-value").unwrap();
+    set_encryption(Some((1, &old_key)), None);
+    {
+        let store = open();
+        store.put("a", b"value-a").unwrap();
+        store
+            .put_with_ttl("b", b"value-b", Some(3_600_000))
+            .unwrap();
+        store.save_snapshot().unwrap();
+    }
+
+    // Rotated: the previous key still reads the old records and snapshot
+    set_encryption(Some((2, &new_key)), Some((1, &old_key)));
+    let store = open();
+    assert_eq!(store.get("a").unwrap().unwrap(), b"value-a");
+    store.put("c", b"value-c").unwrap();
+
+    let mut reports = Vec::new();
+    let progress = rekey_store(&store, 2, |p| reports.push(p.clone())).unwrap();
+    assert!(progress.done);
+    assert_eq!(progress.key_version, 2);
+    assert_eq!(progress.scanned, 4);
+    // "c" was already sealed with the new key
+    assert_eq!(progress.rewritten, 3);
+    assert_eq!(reports.len(), 3);
+    assert_eq!(reports[0].scanned, 2);
+    assert!(reports.last().unwrap().done);
+    assert!(store.get_ttl("b").is_some());
+
+    // Nothing left to rewrite
+    assert_eq!(rekey_store(&store, 2, |_| {}).unwrap().rewritten, 0);
+    drop(store);
+
+    // The previous key is no longer needed, from the snapshot or the segments
+    set_encryption(Some((2, &new_key)), None);
+    for rebuild in [false, true] {
+        if rebuild {
+            std::fs::remove_file(data_path.join("index.snap")).unwrap();
+        }
+        let store = open();
+        assert_eq!(store.get("plain").unwrap().unwrap(), b"plain-value");
+        assert_eq!(store.get("a").unwrap().unwrap(), b"value-a");
+        assert_eq!(store.get("b").unwrap().unwrap(), b"value-b");
+        assert_eq!(store.get("c").unwrap().unwrap(), b"value-c");
+    }
+
+    // Without any key, rekeying is refused
+    set_encryption(None, None);
+    assert!(rekey_store(&Mutex::new(open()), 2, |_| {}).is_err());
+}
@@ -0,0 +1,172 @@
+//! Changes pushed by another cluster: last-writer-wins on `updated_at`, and
+//! the replication targets and status of the leader
+
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use minikv::common::{
+    blake3_hash, IpFilter, RateLimitConfig, RateLimiter, WriteBudget, WriteBudgetConfig,
+};
+use minikv::coordinator::http::{create_router, CoordState};
+use minikv::coordinator::metadata::{KeyMetadata, KeyState, MetadataStore};
+use minikv::coordinator::placement::PlacementManager;
+use minikv::coordinator::raft_node::RaftNode;
+use minikv::coordinator::replication::REPLICATED_AT_HEADER;
+use std::sync::{Arc, Mutex};
+use tempfile::TempDir;
+use tower::ServiceExt;
+
+fn setup(dir: &TempDir) -> (axum::Router, Arc<MetadataStore>) {
+    let metadata = Arc::new(MetadataStore::open(dir.path().join("meta.db")).unwrap());
+    let raft = Arc::new(RaftNode::open("coord-1".to_string(), metadata.clone()).unwrap());
+    raft.become_leader();
+    let router = create_router(CoordState {
+        metadata: metadata.clone(),
+        placement: Arc::new(Mutex::new(PlacementManager::new(16, 1))),
+        raft,
+        auth: Default::default(),
+        rate_limiter: Arc::new(RateLimiter::new(RateLimitConfig::default())),
+        ip_filter: Arc::new(IpFilter::default()),
+        writes: Arc::new(WriteBudget::new(WriteBudgetConfig::default())),
+    });
+    (router, metadata)
+}
+
+fn stored(value: &[u8], updated_at: u64) -> KeyMetadata {
+    KeyMetadata {
+        key: "default/a".to_string(),
+        replicas: vec!["vol-1".to_string()],
+        size: value.len() as u64,
+        blake3: blake3_hash(value),
+        created_at: 0,
+        updated_at,
+        state: KeyState::Active,
+        tags: Default::default(),
+        blob: None,
+        chunks: None,
+    }
+}
+
+async fn push(router: &axum::Router, method: &str, at: &str, body: &[u8]) -> StatusCode {
+    let request = Request::builder()
+        .method(method)
+        .uri("/a")
+        .header(REPLICATED_AT_HEADER, at)
+        .body(Body::from(body.to_vec()))
+        .unwrap();
+    router.clone().oneshot(request).await.unwrap().status()
+}
+
+#[tokio::test]
+async fn test_replicated_writes_last_writer_wins() {
+    let dir = TempDir::new().unwrap();
+    let (router, metadata) = setup(&dir);
+    metadata.put_key(&stored(b"local", 100)).unwrap();
+
+    // Older changes lose to the stored value
+    assert_eq!(
+        push(&router, "POST", "99", b"remote").await,
+        StatusCode::CONFLICT
+    );
+    assert_eq!(
+        push(&router, "DELETE", "99", b"").await,
+        StatusCode::CONFLICT
+    );
+    assert_eq!(
+        metadata.get_key("default/a").unwrap().unwrap().blake3,
+        blake3_hash(b"local")
+    );
+
+    // The value already stored is acknowledged without a write
+    assert_eq!(push(&router, "POST", "200", b"local").await, StatusCode::OK);
+    assert_eq!(
+        metadata.get_key("default/a").unwrap().unwrap().updated_at,
+        100
+    );
+
+    assert_eq!(
+        push(&router, "POST", "soon", b"remote").await,
+        StatusCode::BAD_REQUEST
+    );
+
+    // Newer deletes apply
+    assert_eq!(push(&router, "DELETE", "101", b"").await, StatusCode::OK);
+    assert!(metadata.get_key("default/a").unwrap().is_none());
+}
+
+#[tokio::test]
+async fn test_replication_status_without_targets() {
+    let dir = TempDir::new().unwrap();
+    let (router, _) = setup(&dir);
+    let request = Request::get("/admin/replication/status")
+        .body(Body::empty())
+        .unwrap();
+    let resp = router.oneshot(request).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let status: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(status["targets"], serde_json::json!([]));
+}
+
+async fn send_json(
+    router: &axum::Router,
+    method: &str,
+    uri: &str,
+    body: serde_json::Value,
+) -> (StatusCode, serde_json::Value) {
+    let request = Request::builder()
+        .method(method)
+        .uri(uri)
+        .header("content-type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap();
+    let resp = router.clone().oneshot(request).await.unwrap();
+    let status = resp.status();
+    let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    (status, serde_json::from_slice(&body).unwrap())
+}
+
+#[tokio::test]
+async fn test_runtime_replication_targets() {
+    let dir = TempDir::new().unwrap();
+    let (router, _) = setup(&dir);
+    let target = serde_json::json!({
+        "name": "runtime-test",
+        "url": "http://127.0.0.1:1",
+        "tenants": ["acme"],
+    });
+
+    let (status, _) = send_json(
+        &router,
+        "POST",
+        "/admin/replication/targets",
+        target.clone(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    // Adding the same target again is a no-op, another one under its name isn't
+    let (status, _) = send_json(&router, "POST", "/admin/replication/targets", target).await;
+    assert_eq!(status, StatusCode::OK);
+    let other = serde_json::json!({ "name": "runtime-test", "url": "http://127.0.0.1:2" });
+    let (status, _) = send_json(&router, "POST", "/admin/replication/targets", other).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let (_, status) = send_json(
+        &router,
+        "GET",
+        "/admin/replication/status",
+        serde_json::Value::Null,
+    )
+    .await;
+    assert_eq!(status["targets"][0]["name"], "runtime-test");
+    assert_eq!(status["targets"][0]["runtime"], true);
+
+    let uri = "/admin/replication/targets/runtime-test";
+    let (status, _) = send_json(&router, "DELETE", uri, serde_json::Value::Null).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = send_json(&router, "DELETE", uri, serde_json::Value::Null).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
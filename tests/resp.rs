@@ -0,0 +1,121 @@
+//! Redis protocol commands against the leader of a simulated cluster
+//! (`minikv::sim`), over an in-memory connection
+
+use minikv::coordinator::resp::RespServer;
+use minikv::sim::{Sim, SimConfig};
+use std::time::Duration;
+use tempfile::TempDir;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream};
+
+const ELECTION_WAIT: Duration = Duration::from_secs(10);
+
+async fn send(conn: &mut DuplexStream, command: &[&str]) {
+    let mut request = format!("*{}\r\n", command.len());
+    for arg in command {
+        request.push_str(&format!("${}\r\n{}\r\n", arg.len(), arg));
+    }
+    conn.write_all(request.as_bytes()).await.unwrap();
+}
+
+/// Send `command` as a RESP array and check its reply is `reply`
+async fn call(conn: &mut DuplexStream, command: &[&str], reply: &str) {
+    send(conn, command).await;
+    let mut got = vec![0; reply.len()];
+    conn.read_exact(&mut got).await.unwrap();
+    assert_eq!(String::from_utf8_lossy(&got), reply, "{:?}", command);
+}
+
+/// Send `command` and check it fails with an error starting with `prefix`
+async fn call_error(conn: &mut DuplexStream, command: &[&str], prefix: &str) {
+    send(conn, command).await;
+    let mut line = Vec::new();
+    while !line.ends_with(b"\r\n") {
+        line.push(conn.read_u8().await.unwrap());
+    }
+    let line = String::from_utf8_lossy(&line);
+    assert!(
+        line.starts_with(&format!("-{}", prefix)),
+        "{:?}: {}",
+        command,
+        line
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_resp_commands() {
+    let dir = TempDir::new().unwrap();
+    let sim = Sim::start(dir.path(), SimConfig::default()).unwrap();
+    let leader = sim.wait_for_leader(ELECTION_WAIT).await.unwrap();
+    let server = RespServer::new(sim.coordinator(&leader).router.clone());
+    let (mut conn, stream) = tokio::io::duplex(64 * 1024);
+    tokio::spawn(async move { server.serve_connection(stream, None).await });
+
+    call(&mut conn, &["PING"], "+PONG\r\n").await;
+    call(&mut conn, &["GET", "user:1"], "$-1\r\n").await;
+    call(&mut conn, &["SET", "user:1", "alice"], "+OK\r\n").await;
+    call(&mut conn, &["GET", "user:1"], "$5\r\nalice\r\n").await;
+    call(&mut conn, &["SET", "user:1", "bob", "NX"], "$-1\r\n").await;
+    call(&mut conn, &["SET", "user:2", "bob", "XX"], "$-1\r\n").await;
+    call_error(&mut conn, &["SET", "user:1", "bob", "EX", "10"], "ERR").await;
+
+    call(&mut conn, &["INCR", "counter"], ":1\r\n").await;
+    call(&mut conn, &["INCR", "counter"], ":2\r\n").await;
+    call_error(
+        &mut conn,
+        &["INCR", "user:1"],
+        "ERR value is not an integer",
+    )
+    .await;
+    call(&mut conn, &["GET", "counter"], "$1\r\n2\r\n").await;
+    call_error(&mut conn, &["FLUSHALL"], "ERR unknown command").await;
+
+    call(&mut conn, &["SET", "user:2", "carol"], "+OK\r\n").await;
+    call(
+        &mut conn,
+        &["EXISTS", "user:1", "user:2", "user:3"],
+        ":2\r\n",
+    )
+    .await;
+    call(&mut conn, &["TTL", "user:1"], ":-1\r\n").await;
+    call(&mut conn, &["TTL", "user:3"], ":-2\r\n").await;
+
+    // Pages of one key, then the empty last page
+    call(
+        &mut conn,
+        &["SCAN", "0", "MATCH", "user:*", "COUNT", "1"],
+        "*2\r\n$1\r\n1\r\n*1\r\n$6\r\nuser:1\r\n",
+    )
+    .await;
+    call(
+        &mut conn,
+        &["SCAN", "1", "MATCH", "user:*", "COUNT", "1"],
+        "*2\r\n$1\r\n2\r\n*1\r\n$6\r\nuser:2\r\n",
+    )
+    .await;
+    call(
+        &mut conn,
+        &["SCAN", "2", "MATCH", "user:*", "COUNT", "1"],
+        "*2\r\n$1\r\n0\r\n*0\r\n",
+    )
+    .await;
+
+    call(&mut conn, &["DEL", "user:1", "user:3"], ":1\r\n").await;
+    call(&mut conn, &["EXISTS", "user:1"], ":0\r\n").await;
+    call(&mut conn, &["QUIT"], "+OK\r\n").await;
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_resp_follower_names_leader() {
+    let dir = TempDir::new().unwrap();
+    let sim = Sim::start(dir.path(), SimConfig::default()).unwrap();
+    let leader = sim.wait_for_leader(ELECTION_WAIT).await.unwrap();
+    // Followers hear of the leader with its first heartbeats
+    sim.advance(Duration::from_secs(1)).await;
+    let follower = sim.coordinators().iter().find(|c| c.id != leader).unwrap();
+    let server = RespServer::new(follower.router.clone());
+    let (mut conn, stream) = tokio::io::duplex(64 * 1024);
+    tokio::spawn(async move { server.serve_connection(stream, None).await });
+
+    call_error(&mut conn, &["SET", "key", "value"], "NOTLEADER").await;
+    call_error(&mut conn, &["EXISTS", "key"], "NOTLEADER").await;
+}
@@ -212,6 +212,64 @@ async fn test_s3_overwrite() {
     let _ = fs::remove_dir_all(&vol_wal);
 }
 
+#[tokio::test]
+async fn test_s3_range_get() {
+    if !binaries_available() {
+        eprintln!("Skipping test_s3_range_get: required binaries not available");
+        return;
+    }
+    let test_id = Uuid::new_v4().to_string();
+    let coord_http = get_free_port();
+    let coord_grpc = get_free_port();
+    let vol_http = get_free_port();
+    let vol_grpc = get_free_port();
+    let (mut coord, coord_data) = start_coord(coord_http, coord_grpc, &test_id);
+    let (mut volume, vol_data, vol_wal) = start_volume(vol_http, vol_grpc, coord_http, &test_id);
+    let url = format!("http://127.0.0.1:{}/s3/testbucket/range.txt", coord_http);
+    wait_for_endpoint(&mut [&mut coord, &mut volume], &url).await;
+    let client = Client::new();
+    let put = client.put(&url).body("0123456789").send().await.unwrap();
+    assert!(put.status().is_success());
+
+    let get = client
+        .get(&url)
+        .header("Range", "bytes=2-5")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(get.status(), reqwest::StatusCode::PARTIAL_CONTENT);
+    assert_eq!(get.headers()["content-range"], "bytes 2-5/10");
+    assert_eq!(get.bytes().await.unwrap().as_ref(), b"2345");
+
+    let get = client
+        .get(&url)
+        .header("Range", "bytes=-3")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(get.bytes().await.unwrap().as_ref(), b"789");
+
+    let get = client
+        .get(&url)
+        .header("Range", "bytes=10-")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(get.status(), reqwest::StatusCode::RANGE_NOT_SATISFIABLE);
+    assert_eq!(get.headers()["content-range"], "bytes */10");
+
+    let _ = coord.kill();
+    let _ = coord.wait();
+    let _ = volume.kill();
+    let _ = volume.wait();
+    let _ = fs::remove_file(format!("/tmp/minikv-config-{}.toml", test_id));
+    let _ = fs::remove_file(format!("coord-s3extra-{}.log", test_id));
+    let _ = fs::remove_file(format!("vol-s3extra-{}.log", test_id));
+    let _ = fs::remove_dir_all(&coord_data);
+    let _ = fs::remove_dir_all(&vol_data);
+    let _ = fs::remove_dir_all(&vol_wal);
+}
+
 #[tokio::test]
 async fn test_s3_multiple_objects() {
     if !binaries_available() {
@@ -0,0 +1,245 @@
+//! Elections, partitions and 2PC failures on simulated clusters (`minikv::sim`),
+//! under Tokio's paused clock
+
+use axum::http::StatusCode;
+use minikv::common::{blake3_hash, connect_internal, FailureDomain, VolumeConfig};
+use minikv::coordinator::metadata::init_global_store;
+use minikv::coordinator::placement::PlacementManager;
+use minikv::coordinator::volume_client::VolumeClient;
+use minikv::proto::coordinator_internal_client::CoordinatorInternalClient;
+use minikv::proto::HeartbeatRequest;
+use minikv::sim::{Sim, SimConfig};
+use minikv::volume::heartbeat::join_request;
+use std::time::Duration;
+use tempfile::TempDir;
+
+const ELECTION_WAIT: Duration = Duration::from_secs(10);
+
+#[tokio::test(start_paused = true)]
+async fn test_single_leader_elected() {
+    let dir = TempDir::new().unwrap();
+    let sim = Sim::start(dir.path(), SimConfig::default()).unwrap();
+
+    let leader = sim.wait_for_leader(ELECTION_WAIT).await.unwrap();
+    sim.advance(Duration::from_secs(2)).await;
+
+    // Leadership holds while the network does
+    assert_eq!(sim.leader().unwrap().id, leader);
+    let leaders = sim.coordinators().iter().filter(|c| c.raft.is_leader());
+    assert_eq!(leaders.count(), 1);
+    let term = sim.coordinator(&leader).raft.get_term();
+    for coordinator in sim.coordinators() {
+        assert_eq!(coordinator.raft.get_term(), term);
+        assert_eq!(
+            coordinator.raft.get_leader().as_deref(),
+            Some(leader.as_str())
+        );
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_followers_stay_with_live_leader() {
+    let dir = TempDir::new().unwrap();
+    let sim = Sim::start(dir.path(), SimConfig::default()).unwrap();
+    let leader = sim.wait_for_leader(ELECTION_WAIT).await.unwrap();
+    let term = sim.coordinator(&leader).raft.get_term();
+
+    // Many election timeouts pass, each with heartbeats from the leader
+    sim.advance(Duration::from_secs(30)).await;
+    assert_eq!(sim.leader().unwrap().id, leader);
+    for coordinator in sim.coordinators() {
+        assert_eq!(coordinator.raft.get_term(), term, "{}", coordinator.id);
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_isolated_leader_replaced() {
+    let dir = TempDir::new().unwrap();
+    let sim = Sim::start(dir.path(), SimConfig::default()).unwrap();
+    let old = sim.wait_for_leader(ELECTION_WAIT).await.unwrap();
+    let old_term = sim.coordinator(&old).raft.get_term();
+
+    sim.isolate(&old);
+    let new = sim.wait_for_leader(ELECTION_WAIT).await.unwrap();
+    assert_ne!(new, old);
+    assert!(sim.coordinator(&new).raft.get_term() > old_term);
+    // Cut off, the old leader doesn't know yet
+    assert!(sim.coordinator(&old).raft.is_leader());
+
+    sim.heal();
+    sim.advance(Duration::from_secs(1)).await;
+    assert!(!sim.coordinator(&old).raft.is_leader());
+    assert_eq!(
+        sim.coordinator(&old).raft.get_term(),
+        sim.coordinator(&new).raft.get_term()
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_minority_partition_keeps_leader() {
+    let dir = TempDir::new().unwrap();
+    let sim = Sim::start(dir.path(), SimConfig::default()).unwrap();
+    let leader = sim.wait_for_leader(ELECTION_WAIT).await.unwrap();
+    let term = sim.coordinator(&leader).raft.get_term();
+    let others: Vec<&str> = sim
+        .coordinators()
+        .iter()
+        .map(|c| c.id.as_str())
+        .filter(|id| *id != leader)
+        .collect();
+
+    // The follower cut off can't win the pre-vote, so never disrupts the term
+    sim.partition(&[&[leader.as_str(), others[0]], &[others[1]]]);
+    sim.advance(Duration::from_secs(2)).await;
+    sim.heal();
+    sim.advance(Duration::from_secs(1)).await;
+    assert_eq!(sim.leader().unwrap().id, leader);
+    assert_eq!(sim.coordinator(&leader).raft.get_term(), term);
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_put_survives_failed_commit() {
+    let dir = TempDir::new().unwrap();
+    let sim = Sim::start(dir.path(), SimConfig::default()).unwrap();
+    let leader = sim.wait_for_leader(ELECTION_WAIT).await.unwrap();
+
+    // A quorum of commits is enough
+    sim.fail_calls("v1", "Commit", 1);
+    let (status, body) = sim.put(&leader, "survivor", b"value").await;
+    assert_eq!(status, StatusCode::OK, "{:?}", body);
+
+    let (status, body) = sim.get(&leader, "survivor").await;
+    assert_eq!(status, StatusCode::OK, "{:?}", body);
+    assert_eq!(&body[..], b"value");
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_put_fails_without_prepare() {
+    let dir = TempDir::new().unwrap();
+    let sim = Sim::start(dir.path(), SimConfig::default()).unwrap();
+    let leader = sim.wait_for_leader(ELECTION_WAIT).await.unwrap();
+
+    for volume in sim.volumes() {
+        sim.fail_calls(&volume.id, "Prepare", 1);
+    }
+    let (status, _) = sim.put(&leader, "doomed", b"value").await;
+    assert_ne!(status, StatusCode::OK);
+
+    // Aborted everywhere, so nothing was published
+    let (status, _) = sim.get(&leader, "doomed").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    for volume in sim.volumes() {
+        assert!(volume.store.key_hashes().is_empty());
+    }
+
+    // The next write goes through
+    let (status, body) = sim.put(&leader, "doomed", b"value").await;
+    assert_eq!(status, StatusCode::OK, "{:?}", body);
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_prepare_streams_large_values() {
+    let dir = TempDir::new().unwrap();
+    let sim = Sim::start(dir.path(), SimConfig::default()).unwrap();
+    let mut client = VolumeClient::connect(sim.network().address("v1"))
+        .await
+        .unwrap();
+
+    // Past the 4 MB limit of a single gRPC message
+    let value = vec![7u8; 6 << 20];
+    let prepared = client
+        .prepare(
+            "big".to_string(),
+            "upload-1".to_string(),
+            value.len() as u64,
+            blake3_hash(&value),
+            value.clone(),
+        )
+        .await
+        .unwrap();
+    assert!(prepared.ok, "{}", prepared.error);
+    let committed = client
+        .commit("upload-1".to_string(), "big".to_string())
+        .await
+        .unwrap();
+    assert!(committed.ok, "{}", committed.error);
+    assert_eq!(sim.volume("v1").store.get("big").unwrap().unwrap(), value);
+
+    // No longer staged, so a second commit can't pass for a success
+    let again = client
+        .commit("upload-1".to_string(), "big".to_string())
+        .await;
+    assert!(matches!(again, Err(minikv::Error::NotFound(_))));
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_seeded_elections_repeat() {
+    let first = TempDir::new().unwrap();
+    let second = TempDir::new().unwrap();
+    let config = SimConfig {
+        seed: 42,
+        ..Default::default()
+    };
+    let a = Sim::start(first.path(), config).unwrap();
+    let leader_a = a.wait_for_leader(ELECTION_WAIT).await.unwrap();
+    drop(a);
+    let b = Sim::start(second.path(), config).unwrap();
+    let leader_b = b.wait_for_leader(ELECTION_WAIT).await.unwrap();
+    assert_eq!(leader_a, leader_b);
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_volume_labels_reach_placement() {
+    let dir = TempDir::new().unwrap();
+    let sim = Sim::start(dir.path(), SimConfig::default()).unwrap();
+    let leader = sim.wait_for_leader(ELECTION_WAIT).await.unwrap();
+    let metadata = sim.coordinator(&leader).metadata.clone();
+    init_global_store(metadata.clone());
+    let channel = connect_internal(&sim.network().address(&leader))
+        .await
+        .unwrap();
+    let mut client = CoordinatorInternalClient::new(channel);
+
+    let config = VolumeConfig {
+        zone: Some("zone-a".to_string()),
+        rack: Some("rack-1".to_string()),
+        weight: Some(2.0),
+        ..Default::default()
+    };
+    client.join(join_request("v4", &config)).await.unwrap();
+    let volume = metadata.get_volume("v4").unwrap().unwrap();
+    assert_eq!(volume.zone.as_deref(), Some("zone-a"));
+    assert_eq!(volume.rack.as_deref(), Some("rack-1"));
+    assert_eq!(volume.weight, Some(2.0));
+
+    // Relabeled through the config, as the next heartbeat reports
+    client
+        .heartbeat(HeartbeatRequest {
+            volume_id: "v4".to_string(),
+            zone: "zone-b".to_string(),
+            rack: "rack-1".to_string(),
+            weight: 2.0,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    let volume = metadata.get_volume("v4").unwrap().unwrap();
+    assert_eq!(volume.zone.as_deref(), Some("zone-b"));
+
+    // Zone-aware placement never puts two replicas in zone-b
+    let mut same_zone = volume.clone();
+    same_zone.volume_id = "v5".to_string();
+    let mut other_zone = volume.clone();
+    other_zone.volume_id = "v6".to_string();
+    other_zone.zone = Some("zone-c".to_string());
+    let volumes = vec![volume, same_zone, other_zone];
+    let placement = PlacementManager::new(64, 2).with_policy(FailureDomain::Zone, false);
+    for i in 0..16 {
+        let mut selected = placement
+            .select_volumes(&format!("key-{}", i), &volumes)
+            .unwrap();
+        selected.sort();
+        assert_eq!(selected.len(), 2);
+        assert_eq!(selected[1], "v6");
+    }
+}
@@ -1,6 +1,7 @@
 //! Stress test for minikv cluster: high load, latency, throughput
 
 use minikv::volume::blob::BlobStore;
+use std::sync::Arc;
 use std::time::Instant;
 use tempfile::TempDir;
 
@@ -9,7 +10,7 @@ fn stress_write_read() {
     let dir = TempDir::new().unwrap();
     let data_path = dir.path().join("data");
     let wal_path = dir.path().join("wal");
-    let mut store =
+    let store =
         BlobStore::open(&data_path, &wal_path, minikv::common::WalSyncPolicy::Never).unwrap();
 
     let n = 1_000;
@@ -33,3 +34,49 @@ fn stress_write_read() {
     assert!(write_time.as_secs_f64() < 30.0, "Write too slow");
     assert!(read_time.as_secs_f64() < 30.0, "Read too slow");
 }
+
+#[test]
+fn stress_concurrent_read_write() {
+    let dir = TempDir::new().unwrap();
+    let store = Arc::new(
+        BlobStore::open(
+            &dir.path().join("data"),
+            &dir.path().join("wal"),
+            minikv::common::WalSyncPolicy::Never,
+        )
+        .unwrap(),
+    );
+    for i in 0..100 {
+        store.put(&format!("shared_{}", i), b"initial").unwrap();
+    }
+
+    let threads: Vec<_> = (0..8)
+        .map(|t| {
+            let store = store.clone();
+            std::thread::spawn(move || {
+                for i in 0..200 {
+                    if t % 2 == 0 {
+                        store.put(&format!("own_{}_{}", t, i), b"mine").unwrap();
+                        store
+                            .put(&format!("shared_{}", i % 100), format!("t{}", t).as_bytes())
+                            .unwrap();
+                    } else {
+                        let value = store.get(&format!("shared_{}", i % 100)).unwrap();
+                        assert!(value.is_some(), "shared keys are never missing");
+                    }
+                }
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    assert_eq!(store.stats().total_keys, 100 + 4 * 200);
+    for t in (0..8).step_by(2) {
+        assert_eq!(
+            store.get(&format!("own_{}_199", t)).unwrap().unwrap(),
+            b"mine"
+        );
+    }
+}
@@ -0,0 +1,217 @@
+//! The volume HTTP API: direct blob access, stats and health
+
+use axum::body::Body;
+use axum::extract::ConnectInfo;
+use axum::http::{Request, StatusCode};
+use minikv::common::{
+    RateLimitConfig, RateLimiter, StoreIoConfig, VolumeConfig, WalSyncPolicy, WriteBudget,
+};
+use minikv::volume::async_store::AsyncBlobStore;
+use minikv::volume::blob::BlobStore;
+use minikv::volume::compaction::{CompactionPolicy, Compactor};
+use minikv::volume::http::{create_router, VolumeHttpState};
+use minikv::volume::scrub::{ScrubPolicy, Scrubber};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tempfile::TempDir;
+use tower::ServiceExt;
+
+fn router(dir: &TempDir) -> axum::Router {
+    router_with(
+        dir,
+        VolumeConfig {
+            max_blob_size: 1024,
+            ..Default::default()
+        },
+    )
+}
+
+fn router_with(dir: &TempDir, config: VolumeConfig) -> axum::Router {
+    let store = Arc::new(
+        BlobStore::open(
+            &dir.path().join("data"),
+            &dir.path().join("wal"),
+            WalSyncPolicy::Never,
+        )
+        .unwrap(),
+    );
+    create_router(VolumeHttpState {
+        io: AsyncBlobStore::new(store.clone(), StoreIoConfig::default()),
+        max_blob_size: config.max_blob_size,
+        coordinators: vec![],
+        writes: Arc::new(WriteBudget::new(config.write_budget)),
+        compactor: Arc::new(Compactor::new(
+            store.clone(),
+            CompactionPolicy::from_config(&config),
+        )),
+        scrubber: Arc::new(Scrubber::new(store, ScrubPolicy::from_config(&config))),
+        rate_limiter: Arc::new(RateLimiter::new(config.rate_limit.clone())),
+    })
+}
+
+async fn send(
+    router: &axum::Router,
+    method: &str,
+    uri: &str,
+    range: Option<&str>,
+    body: Vec<u8>,
+) -> (StatusCode, axum::http::HeaderMap, Vec<u8>) {
+    let mut request = Request::builder().method(method).uri(uri);
+    if let Some(range) = range {
+        request = request.header("Range", range);
+    }
+    let resp = router
+        .clone()
+        .oneshot(request.body(Body::from(body)).unwrap())
+        .await
+        .unwrap();
+    let (status, headers) = (resp.status(), resp.headers().clone());
+    let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    (status, headers, body.to_vec())
+}
+
+#[tokio::test]
+async fn test_blob_roundtrip() {
+    let dir = TempDir::new().unwrap();
+    let router = router(&dir);
+
+    let (status, _, body) = send(&router, "PUT", "/blob/acme/a/b", None, b"hello".to_vec()).await;
+    assert_eq!(status, StatusCode::OK);
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["size"], 5);
+
+    let (status, headers, body) = send(&router, "GET", "/blob/acme/a/b", None, vec![]).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, b"hello");
+    assert_eq!(
+        headers["x-minikv-blake3"],
+        minikv::common::blake3_hash(b"hello").as_str()
+    );
+
+    let (status, headers, body) =
+        send(&router, "GET", "/blob/acme/a/b", Some("bytes=1-3"), vec![]).await;
+    assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+    assert_eq!(body, b"ell");
+    assert_eq!(headers["content-range"], "bytes 1-3/5");
+    let (status, _, _) = send(&router, "GET", "/blob/acme/a/b", Some("bytes=9-"), vec![]).await;
+    assert_eq!(status, StatusCode::RANGE_NOT_SATISFIABLE);
+
+    let (status, _, _) = send(&router, "DELETE", "/blob/acme/a/b", None, vec![]).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let (status, _, _) = send(&router, "GET", "/blob/acme/a/b", None, vec![]).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _, _) = send(&router, "DELETE", "/blob/acme/a/b", None, vec![]).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_blob_size_limit() {
+    let dir = TempDir::new().unwrap();
+    let router = router(&dir);
+
+    let (status, _, _) = send(&router, "PUT", "/blob/big", None, vec![0; 2048]).await;
+    assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+    let (status, _, _) = send(&router, "PUT", "/blob/small", None, vec![0; 1024]).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_stats_and_health() {
+    let dir = TempDir::new().unwrap();
+    let router = router(&dir);
+    send(&router, "PUT", "/blob/a", None, b"one".to_vec()).await;
+
+    let (status, _, body) = send(&router, "GET", "/stats", None, vec![]).await;
+    assert_eq!(status, StatusCode::OK);
+    let stats: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(stats["total_keys"], 1);
+    assert_eq!(stats["read_only"], false);
+
+    let (status, _, body) = send(&router, "GET", "/health", None, vec![]).await;
+    assert_eq!(status, StatusCode::OK);
+    let health: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(health["status"], "ok");
+
+    let (status, _, _) = send(&router, "GET", "/health/live", None, vec![]).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _, body) = send(&router, "GET", "/health/ready", None, vec![]).await;
+    assert_eq!(status, StatusCode::OK);
+    let ready: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(ready["checks"]["wal"]["ok"], true);
+    assert_eq!(ready["checks"]["disks"]["working"], 1);
+
+    // Not ready once the WAL is gone, though still alive
+    std::fs::remove_file(dir.path().join("wal").join("wal.log")).unwrap();
+    let (status, _, body) = send(&router, "GET", "/health/ready", None, vec![]).await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    let ready: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(ready["ready"], false);
+    assert_eq!(ready["checks"]["wal"]["ok"], false);
+    let (status, _, _) = send(&router, "GET", "/health/live", None, vec![]).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_transfer_url_expiry() {
+    let dir = TempDir::new().unwrap();
+    let router = router(&dir);
+    send(&router, "PUT", "/blob/a", None, b"one".to_vec()).await;
+
+    // Internal tokens are disabled: nothing can sign a URL, so none is honored
+    let expires = minikv::common::timestamp_now() + 60;
+    let uri = format!("/blob/a?expires={}", expires);
+    let (status, _, _) = send(&router, "GET", &uri, None, vec![]).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let uri = format!(
+        "/blob/a?expires={}&upload=1-0&replicas=vol-1@http://127.0.0.1:2&acks=1",
+        expires
+    );
+    let (status, _, _) = send(&router, "PUT", &uri, None, b"two".to_vec()).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let (status, _, _) = send(&router, "GET", "/blob/a?expires=1", None, vec![]).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let uri = "/blob/a?expires=1&replicas=vol-1@http://127.0.0.1:2&acks=1";
+    let (status, _, _) = send(&router, "PUT", uri, None, b"two".to_vec()).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn test_rate_limited_per_client_ip() {
+    let dir = TempDir::new().unwrap();
+    let router = router_with(
+        &dir,
+        VolumeConfig {
+            rate_limit: RateLimitConfig {
+                burst_size: 2,
+                requests_per_second: 0.001,
+                enabled: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+    );
+    let from = |ip: &str| {
+        let mut request = Request::builder()
+            .uri("/stats")
+            .body(Body::empty())
+            .unwrap();
+        let addr: SocketAddr = format!("{}:4000", ip).parse().unwrap();
+        request.extensions_mut().insert(ConnectInfo(addr));
+        request
+    };
+
+    for _ in 0..2 {
+        let resp = router.clone().oneshot(from("10.0.0.1")).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+    let resp = router.clone().oneshot(from("10.0.0.1")).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert!(resp.headers().contains_key("Retry-After"));
+
+    // Another client has its own budget
+    let resp = router.clone().oneshot(from("10.0.0.2")).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+}